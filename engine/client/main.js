@@ -10,19 +10,144 @@ const OP_LOAD_SOUND = 0x06;
 const OP_PLAY_SOUND = 0x07;
 const OP_STOP_SOUND = 0x08;
 const OP_SET_VOLUME = 0x09;
+const OP_LOAD_IMAGE = 0x0A;
+const OP_DRAW_SPRITE = 0x0B;
+const OP_FILL_CIRCLE = 0x0C;
+const OP_DRAW_CIRCLE = 0x0D;
+const OP_FILL_ELLIPSE = 0x0E;
+const OP_DRAW_ELLIPSE = 0x0F;
+const OP_SET_CONTROL_SCHEME = 0x10;
+const OP_DRAW_POLY = 0x11;
+const OP_SET_TRANSFORM = 0x12;
+const OP_REQUEST_TEXT_INPUT = 0x13;
+const OP_SET_TEXT_SCALE = 0x14;
+const OP_PUSH_TRANSFORM = 0x15;
+const OP_POP_TRANSFORM = 0x16;
+const OP_SET_LAYER = 0x17;
+const OP_DEV_STATUS = 0x18;
+const OP_SET_GRADIENT = 0x19;
+const OP_SET_BLEND_MODE = 0x1A;
+const OP_DRAW_PARTICLES = 0x1B;
+const OP_FILL_ARC = 0x1C;
+const OP_DRAW_ARC = 0x1D;
+const OP_TILEMAP_CHUNK = 0x1E;
+const OP_DRAW_TILEMAP = 0x1F;
+const OP_DRAW_CURVE = 0x20;
+const OP_NODE_CREATE = 0x21;
+const OP_NODE_UPDATE = 0x22;
+const OP_NODE_DESTROY = 0x23;
+const OP_NODE_SYNC = 0x24;
+const OP_DRAW_SCENE = 0x25;
+const OP_DRAW_NINEPATCH = 0x26;
+const OP_CANVAS_CONTENT = 0x27;
+const OP_DRAW_CANVAS = 0x28;
+const OP_SCENE_UPDATE = 0x29;
+const OP_SCREEN_SHAKE = 0x2A;
+const OP_FLASH = 0x2B;
+const OP_VIGNETTE = 0x2C;
+const OP_LOAD_SPRITESHEET = 0x2D;
+const OP_DRAW_FRAME = 0x2E;
+const OP_DEFINE_PALETTE = 0x2F;
+const OP_PALETTE_COLOR = 0x30;
+const OP_FILL_TRIANGLES = 0x31;
+const OP_DRAW_POLYLINE = 0x32;
+const OP_PLAY_SOUND_PANNED = 0x33;
+const OP_LOAD_MUSIC = 0x34;
+const OP_PLAY_MUSIC = 0x35;
+const OP_STOP_MUSIC = 0x36;
+const OP_SET_POST = 0x37;
+const OP_SET_PITCH = 0x38;
+const OP_SET_BUS_VOLUME = 0x39;
+const OP_SET_SOUND_BUS = 0x3A;
+const OP_LIGHTS = 0x3B;
+const OP_DRAW_TRAILS = 0x3C;
+const OP_SETTINGS = 0x3D;
+const OP_ACTION_KEYS = 0x3E;
+const OP_INPUT_CONFIG = 0x3F;
+const OP_PLUGIN_FIRST = 0xC0; // 0xC0..0xFF: plugin opcodes (opcode, u16 len, payload)
+// Opcode groups this client understands (engine compat::CAP_*), announced when connecting:
+// the server rewrites frames with fallbacks for anything missing
+const CAPABILITIES = 0xFFFFFFFF;
+// Frame codecs this client decodes, preferred first; frames then start with the codec used
+const CODECS = 'zstd,lz4,none';
+const CODEC_NONE = 0, CODEC_ZSTD = 1, CODEC_LZ4 = 2;
+
+const BLEND_MODES = ['source-over', 'lighter', 'multiply']; // api.set_blend_mode: alpha, add, multiply
+
+// Plugin opcode handlers: window.cleoselene.registerOpcode(0xC0, (ctx, payloadView) => { ... })
+const pluginHandlers = {};
+// Script messages (api.send_message / on_message): JSON over the WebSocket, reliable and in
+// order. Sent while disconnected, they wait for the next connection.
+const messageHandlers = [];
+const pendingMessages = [];
+function deliverMessage(data) {
+    for (const handler of messageHandlers) {
+        try { handler(data); } catch (e) { console.error("Message handler error:", e); }
+    }
+}
+window.cleoselene = {
+    registerOpcode(opcode, handler) { pluginHandlers[opcode] = handler; },
+    onMessage(handler) { messageHandlers.push(handler); },
+    sendMessage(data) {
+        const text = JSON.stringify({ type: 'MESSAGE', data });
+        if (ws && ws.readyState === WebSocket.OPEN) { ws.send(text); } else { pendingMessages.push(text); }
+    }
+};
 
 // Global State
 let ctx = null;
+let baseTransform = null; // Screen transform (device pixel ratio), restored every frame
 let ws = null;
 let pc = null;
-let dc = null;
+let dc = null;       // Frames: unordered, unreliable
+let eventsDc = null; // Inputs and script messages: ordered, reliable
 let audioCtx = null;
 const sounds = {};
+// api.load_music: name -> { url, el, gain, playing, stopTimer }, streamed through an <audio> element
+const music = {};
+// Audio buses (api.set_bus_volume): bus name -> GainNode; sounds play on "sfx" and music
+// on "music" unless loaded onto another bus
+const buses = {};
+const soundBuses = {};
+const images = {};
+// Sprite sheets (api.load_spritesheet): id -> { img, url, fw, fh }
+const sheets = new Map();
+// api.define_palette: CSS colors by index
+let palette = [];
+// api.new_tilemap: id -> { w, h, tiles, chunks (pre-rendered chunk canvases), key }
+const tilemaps = new Map();
+const TILEMAP_CHUNK = 16;
+// Screen effects: shake and flash fade out over their duration, the vignette stays
+let shake = null; // { intensity, start, duration } (ms)
+let flash = null; // { color, start, duration } (ms)
+let vignette = 0;
+// api.set_post_effect, applied over every frame until changed
+let post = { tint: null, vignette: 0, brightness: 1, contrast: 1, scanlines: false };
+// api.draw_lights: the light map, drawn at the canvas resolution and multiplied over the frame
+let lightCanvas = null;
+// Render targets (api.new_canvas): name -> offscreen canvas, redrawn when its recording arrives
+const canvases = new Map();
+// Retained scene (api.new_node): id -> node. Transforms are eased from `from` to `to`
+// over one frame interval, so nodes move smoothly between server frames.
+const nodes = new Map();
+const NODE_TRANSFORM = 0x01, NODE_SHAPE = 0x02, NODE_COLOR = 0x04, NODE_Z = 0x08, NODE_VISIBLE = 0x10, NODE_PARENT = 0x20, NODE_SNAP = 0x40;
+let frameInterval = 1000 / 30; // Smoothed time between frames
+let lastFrameAt = 0;
+let lastFrame = null; // Replayed while nodes are still easing
 const activeSources = {};
 let sessionId = null;
 let gameStarted = false;
+let preloading = false; // The loading overlay stays up while assets.json loads
 let reconnectAttempts = 0;
+// The server holds a dropped session for a while ([reconnect] grace): reconnecting with its
+// id, even from a reloaded tab, resumes it
+const SESSION_STORAGE_KEY = 'cleoselene-session:' + window.location.pathname;
 let reconnectTimer = null;
+// ?spectate=1 on the page: watch the game without joining it (read before the URL is cleaned up)
+const SPECTATING = new URLSearchParams(window.location.search).get('spectate') === '1';
+// ?token= on the page: the player's login, for servers started with --auth-jwt-secret/--auth-url
+const AUTH_TOKEN = new URLSearchParams(window.location.search).get('token');
+let serverRestarting = false; // SHUTDOWN received: the server is going away on purpose
 let initialServerInstanceId = null;
 
 // Stats
@@ -50,31 +175,158 @@ function hideLoading() {
     if (overlay) overlay.classList.add('hidden');
 }
 
+// Fix Path for Subdirectory Deployment
+function assetUrl(url) {
+    if (url.startsWith('/') && !url.startsWith('//')) {
+        const bp = getBasePath();
+        if (bp && !url.startsWith(bp)) {
+            url = bp + url;
+        }
+    }
+    return url;
+}
+
+// Loads resolve once the asset is usable (or failed); loading a name again is a no-op
+function loadSound(name, url) {
+    if (sounds[name]) return Promise.resolve();
+    sounds[name] = "loading";
+    if (!audioCtx) return Promise.resolve();
+    return fetch(assetUrl(url))
+        .then(r => r.arrayBuffer())
+        .then(ab => audioCtx.decodeAudioData(ab))
+        .then(buf => { sounds[name] = buf; })
+        .catch(e => console.error("Sound load failed:", name, e));
+}
+
+function loadImage(name, url) {
+    if (images[name]) return Promise.resolve();
+    const img = new Image();
+    const loaded = new Promise(resolve => { img.onload = resolve; img.onerror = e => { console.error("Image load failed:", name, e); resolve(); }; });
+    img.src = assetUrl(url);
+    images[name] = img;
+    return loaded;
+}
+
+// assets.json, pushed by the server on connect: everything is loaded behind the loading
+// overlay, then the server runs on_assets_ready(session_id)
+function preloadAssets(manifest) {
+    const loads = [
+        ...Object.entries(manifest.sounds || {}).map(([name, url]) => () => loadSound(name, url)),
+        ...Object.entries(manifest.images || {}).map(([name, url]) => () => loadImage(name, url)),
+    ];
+    let done = 0;
+    preloading = true;
+    showLoading(`LOADING ASSETS 0/${loads.length}`);
+    Promise.all(loads.map(load => load().then(() => updateLoadingStatus(`LOADING ASSETS ${++done}/${loads.length}`))))
+        .then(() => {
+            preloading = false;
+            if (gameStarted) hideLoading();
+            if (ws && ws.readyState === WebSocket.OPEN) ws.send(JSON.stringify({ type: 'ASSETS_READY' }));
+        });
+}
+
+// Buttons with data-action press the first key the server bound to that action (OP_ACTION_KEYS)
+const actionKeys = new Map();
+
+// api.set_input_config (OP_INPUT_CONFIG): key repeat, stick dead zone, touch joystick size
+const inputConfig = { keyRepeat: false, deadZone: 0.15, joystickRadius: 50 };
+
+function applyInputConfig(config) {
+    Object.assign(inputConfig, config);
+    document.documentElement.style.setProperty('--joystick-radius', `${inputConfig.joystickRadius}px`);
+}
+
+// The touch joystick reports as the left stick of a gamepad of its own
+const JOYSTICK_PAD = 255;
+
+function setupJoystick(stick) {
+    const knob = stick.querySelector('.touch-knob');
+    let pointer = null, center = null;
+    const move = (e) => {
+        const r = inputConfig.joystickRadius;
+        let dx = e.clientX - center.x, dy = e.clientY - center.y;
+        const dist = Math.hypot(dx, dy);
+        if (dist > r) { dx *= r / dist; dy *= r / dist; }
+        knob.style.transform = `translate(${dx}px, ${dy}px)`;
+        const dead = Math.min(dist / r, 1) < inputConfig.deadZone;
+        reportGamepad(JOYSTICK_PAD, 1, 0, dead ? 0 : dx / r);
+        reportGamepad(JOYSTICK_PAD, 1, 1, dead ? 0 : dy / r);
+    };
+    const release = (e) => {
+        if (e.pointerId !== pointer) return;
+        pointer = null;
+        knob.style.transform = '';
+        reportGamepad(JOYSTICK_PAD, 1, 0, 0);
+        reportGamepad(JOYSTICK_PAD, 1, 1, 0);
+    };
+    stick.addEventListener('pointerdown', (e) => {
+        if (pointer !== null) return;
+        e.preventDefault();
+        pointer = e.pointerId;
+        stick.setPointerCapture(pointer);
+        const rect = stick.getBoundingClientRect();
+        center = { x: rect.left + rect.width / 2, y: rect.top + rect.height / 2 };
+        move(e);
+    });
+    stick.addEventListener('pointermove', (e) => { if (e.pointerId === pointer) move(e); });
+    stick.addEventListener('pointerup', release);
+    stick.addEventListener('pointercancel', release);
+}
+
 function setupTouchListeners(container) {
     container.querySelectorAll('.touch-btn').forEach(btn => {
-        const key = parseInt(btn.dataset.key);
-        const handleStart = (e) => { e.preventDefault(); sendInput(key, true); };
-        const handleEnd = (e) => { e.preventDefault(); sendInput(key, false); };
+        let key = null;
+        const handleStart = (e) => {
+            e.preventDefault();
+            key = btn.dataset.action ? actionKeys.get(btn.dataset.action)?.[0] ?? null : parseInt(btn.dataset.key);
+            if (key !== null) sendInput(key, true);
+        };
+        const handleEnd = (e) => {
+            e.preventDefault();
+            if (key !== null) sendInput(key, false);
+            key = null;
+        };
         btn.addEventListener('touchstart', handleStart, {passive: false});
         btn.addEventListener('touchend', handleEnd, {passive: false});
         btn.addEventListener('mousedown', handleStart);
         btn.addEventListener('mouseup', handleEnd);
         btn.addEventListener('mouseleave', handleEnd);
     });
+    container.querySelectorAll('.touch-joystick').forEach(setupJoystick);
 }
 
-function buildMobileControls(layout) {
-    if (!layout || !layout.length) return;
+// keys.json strings end up in the markup, as text and inside single-quoted attributes
+function escapeHtml(s) {
+    return String(s).replace(/[&<>"']/g, c => ({ '&': '&amp;', '<': '&lt;', '>': '&gt;', '"': '&quot;', "'": '&#39;' })[c]);
+}
+
+// keys.json: a single layout (rows of buttons) or { default, schemes: { name: layout } }
+function buildMobileControls(keys) {
+    if (!keys) return;
+    const schemes = Array.isArray(keys) ? { default: keys } : (keys.schemes || {});
+    const names = Object.keys(schemes);
+    if (!names.length) return;
+    const defaultScheme = Array.isArray(keys) ? 'default' : (keys.default || names[0]);
     const div = document.createElement('div');
     div.id = 'mobile-controls';
     div.className = 'touch-controls';
     // Display handled by CSS Media Queries
     
     let html = '';
-    layout.forEach(row => {
-        html += `<div class='control-row' style='display: grid; grid-template-columns: repeat(${row.length}, 1fr); gap: 10px;'>`;
-        row.forEach(btn => {
-            html += `<div class='touch-btn' data-key='${btn.key}'>${btn.label}</div>`;
+    names.forEach(name => {
+        const hidden = name === defaultScheme ? '' : ` style='display: none;'`;
+        html += `<div class='control-scheme' data-scheme='${escapeHtml(name)}'${hidden}>`;
+        schemes[name].forEach(row => {
+            html += `<div class='control-row' style='display: grid; grid-template-columns: repeat(${row.length}, 1fr); gap: 10px;'>`;
+            row.forEach(btn => {
+                if (btn.joystick) {
+                    html += `<div class='touch-joystick'><div class='touch-knob'></div></div>`;
+                    return;
+                }
+                const target = btn.action ? `data-action='${escapeHtml(btn.action)}'` : `data-key='${escapeHtml(btn.key)}'`;
+                html += `<div class='touch-btn' ${target}>${escapeHtml(btn.label ?? '')}</div>`;
+            });
+            html += `</div>`;
         });
         html += `</div>`;
     });
@@ -83,6 +335,12 @@ function buildMobileControls(layout) {
     setupTouchListeners(div);
 }
 
+function setControlScheme(name) {
+    document.querySelectorAll('#mobile-controls .control-scheme').forEach(el => {
+        el.style.display = el.dataset.scheme === name ? '' : 'none';
+    });
+}
+
 function init() {
     console.log("App Mounted. Initializing Engine with WebRTC...");
     showLoading("INITIALIZING...");
@@ -135,6 +393,7 @@ function init() {
     canvas.height = 600 * dpr;
     ctx = canvas.getContext('2d');
     ctx.scale(dpr, dpr);
+    baseTransform = ctx.getTransform();
     
     // Setup Audio
     try {
@@ -148,19 +407,50 @@ function init() {
     window.addEventListener('keydown', resumeAudio);
 
     // Input Handling setup
-    window.addEventListener('keydown', (e) => { if(!e.repeat) sendInput(e.keyCode, true); });
+    window.addEventListener('keydown', (e) => { if(!e.repeat || inputConfig.keyRepeat) sendInput(e.keyCode, true); });
     window.addEventListener('keyup', (e) => { sendInput(e.keyCode, false); });
+    window.addEventListener('paste', (e) => {
+        if (e.target instanceof HTMLInputElement || e.target instanceof HTMLTextAreaElement) return;
+        const text = e.clipboardData ? e.clipboardData.getData('text/plain') : '';
+        if (text) { e.preventDefault(); sendPaste(text); }
+    });
+    // Mouse: on_mouse(session_id, event, x, y, button) in the 800x600 screen space
+    const MOUSE_MOVE = 0, MOUSE_DOWN = 1, MOUSE_UP = 2, MOUSE_WHEEL = 3;
+    const toScreen = (e) => {
+        const r = canvas.getBoundingClientRect();
+        return [(e.clientX - r.left) * 800 / r.width, (e.clientY - r.top) * 600 / r.height];
+    };
+    canvas.addEventListener('mousemove', (e) => sendMouse(MOUSE_MOVE, ...toScreen(e), 0));
+    canvas.addEventListener('mousedown', (e) => { e.preventDefault(); sendMouse(MOUSE_DOWN, ...toScreen(e), e.button); });
+    canvas.addEventListener('mouseup', (e) => sendMouse(MOUSE_UP, ...toScreen(e), e.button));
+    canvas.addEventListener('wheel', (e) => { e.preventDefault(); sendMouse(MOUSE_WHEEL, ...toScreen(e), Math.sign(e.deltaY)); }, {passive: false});
+    canvas.addEventListener('contextmenu', (e) => e.preventDefault());
+    // Touch: on_touch(session_id, id, phase, x, y), one message per changed touch
+    const TOUCH_PHASES = { touchstart: 0, touchmove: 1, touchend: 2, touchcancel: 3 };
+    for (const type in TOUCH_PHASES) {
+        canvas.addEventListener(type, (e) => {
+            e.preventDefault(); // No scrolling, zooming or synthesized mouse events
+            for (const t of e.changedTouches) sendTouch(TOUCH_PHASES[type], t.identifier, ...toScreen(t));
+        }, {passive: false});
+    }
 
     // Connect
     connect();
 }
 
+// The server stops (restart, deploy): keep retrying until it is back
+function serverShutdown() {
+    console.log("Server shutting down");
+    serverRestarting = true;
+    showLoading("SERVER RESTARTING...");
+}
+
 function scheduleReconnect() {
     if (reconnectTimer) clearTimeout(reconnectTimer);
     const delay = Math.min(1000 * Math.pow(1.5, reconnectAttempts), 10000); 
     reconnectAttempts++;
     console.log(`Reconnecting in ${delay}ms (Attempt ${reconnectAttempts})...`);
-    showLoading(`RECONNECTING (${reconnectAttempts})...`);
+    showLoading(serverRestarting ? "SERVER RESTARTING..." : `RECONNECTING (${reconnectAttempts})...`);
     reconnectTimer = setTimeout(() => { connect(); }, delay);
 }
 
@@ -173,18 +463,27 @@ async function connect() {
     const protocol = window.location.protocol === 'https:' ? 'wss://' : 'ws://';
     let wsUrl = protocol + window.location.host + getBasePath() + "/ws";
     if (urlSessionId) { wsUrl += "?session=" + urlSessionId; sessionId = urlSessionId; }
-    if (sessionId && reconnectAttempts > 0 && !wsUrl.includes("session=")) {
+    // A reloaded tab (mobile browsers discard background ones) resumes its session too
+    if (!sessionId) { try { sessionId = sessionStorage.getItem(SESSION_STORAGE_KEY); } catch (e) {} }
+    if (sessionId && !wsUrl.includes("session=")) {
          wsUrl += (wsUrl.includes("?") ? "&" : "?") + "session=" + sessionId;
     }
+    // ?room=name on the page: play in that room (a world of its own on the server)
+    const room = urlParams.get('room');
+    if (room) wsUrl += (wsUrl.includes("?") ? "&" : "?") + "room=" + encodeURIComponent(room);
+    wsUrl += (wsUrl.includes("?") ? "&" : "?") + "caps=" + CAPABILITIES;
+    wsUrl += "&codecs=" + CODECS;
+    if (SPECTATING) wsUrl += "&spectate=1";
+    if (AUTH_TOKEN) wsUrl += "&token=" + encodeURIComponent(AUTH_TOKEN);
 
     ws = new WebSocket(wsUrl);
     ws.binaryType = 'arraybuffer';
 
     ws.onopen = () => {
-        console.log("WebSocket Open. Negotiating WebRTC...");
-        updateLoadingStatus("NEGOTIATING...");
+        console.log("WebSocket Open. Waiting for WELCOME...");
         reconnectAttempts = 0; 
-        setupWebRTC();
+        serverRestarting = false;
+        while (pendingMessages.length) ws.send(pendingMessages.shift());
     };
 
     ws.onclose = () => {
@@ -212,9 +511,20 @@ async function connect() {
                     }
                 }
                 updateLoadingStatus("ENTERING GAME...");
+                setupWebRTC(msg.ice_servers); // Plays over the WebSocket until the data channels open
                 sessionId = msg.session_id;
+                if (!SPECTATING) { try { sessionStorage.setItem(SESSION_STORAGE_KEY, sessionId); } catch (e) {} }
                 const cleanUrl = window.location.protocol + "//" + window.location.host + window.location.pathname;
                 window.history.replaceState({path: cleanUrl}, '', cleanUrl);
+            } else if (msg.type === 'MESSAGE') {
+                deliverMessage(msg.data);
+            } else if (msg.type === 'SHUTDOWN') {
+                serverShutdown();
+            } else if (msg.type === 'PING') {
+                // Heartbeat: the server measures the round trip and drops clients that stop answering
+                ws.send(JSON.stringify({ type: 'PONG', id: msg.id }));
+            } else if (msg.type === 'ASSET_MANIFEST') {
+                preloadAssets(msg);
             } else if (msg.type === 'ANSWER') {
                 await pc.setRemoteDescription(new RTCSessionDescription({ type: 'answer', sdp: msg.sdp }));
             } else if (msg.type === 'CANDIDATE') {
@@ -230,8 +540,11 @@ async function connect() {
     };
 }
 
-async function setupWebRTC() {
-    const config = { iceServers: [{ urls: "stun:stun.l.google.com:19302" }] };
+// ICE servers come from the server (--stun/--turn); a public STUN server otherwise
+const DEFAULT_ICE_SERVERS = [{ urls: "stun:stun.l.google.com:19302" }];
+
+async function setupWebRTC(iceServers) {
+    const config = { iceServers: iceServers || DEFAULT_ICE_SERVERS };
     pc = new RTCPeerConnection(config);
     dc = pc.createDataChannel("game_data", { ordered: false, maxRetransmits: 0 });
     dc.binaryType = "arraybuffer";
@@ -240,6 +553,16 @@ async function setupWebRTC() {
         updateLoadingStatus("ENTERING GAME (UDP)...");
     };
     dc.onmessage = (e) => processCompressedFrame(e.data);
+    // A lost input or message would be gone for good, so they get a channel of their own
+    // rather than queueing behind frames
+    eventsDc = pc.createDataChannel("game_events", { ordered: true });
+    eventsDc.binaryType = "arraybuffer";
+    eventsDc.onmessage = (e) => {
+        const msg = JSON.parse(e.data);
+        if (msg.type === 'MESSAGE') deliverMessage(msg.data);
+        else if (msg.type === 'SHUTDOWN') serverShutdown();
+        else if (msg.type === 'PING') eventsDc.send('\x06' + msg.id); // PONG, marked like pastes
+    };
     pc.onicecandidate = (event) => {
         if (event.candidate) {
             ws.send(JSON.stringify({
@@ -253,6 +576,39 @@ async function setupWebRTC() {
     ws.send(JSON.stringify({ type: 'OFFER', sdp: offer.sdp }));
 }
 
+// The codec byte, then the frame compressed with it
+function decodeFrame(data) {
+    const body = data.subarray(1);
+    switch (data[0]) {
+        case CODEC_NONE: return body;
+        case CODEC_ZSTD: return decompress(body);
+        case CODEC_LZ4: return lz4Decompress(body);
+        default: throw new Error("Unknown frame codec " + data[0]);
+    }
+}
+
+// An LZ4 block after its uncompressed size (u32 little-endian)
+function lz4Decompress(src) {
+    const out = new Uint8Array((src[0] | src[1] << 8 | src[2] << 16 | src[3] << 24) >>> 0);
+    let si = 4, di = 0;
+    const length = (n) => {
+        if (n === 15) { let b; do { b = src[si++]; n += b; } while (b === 255); }
+        return n;
+    };
+    while (si < src.length) {
+        const token = src[si++];
+        const literals = length(token >> 4);
+        out.set(src.subarray(si, si + literals), di);
+        si += literals; di += literals;
+        if (si >= src.length) break; // The last sequence is literals only
+        const offset = src[si] | src[si + 1] << 8;
+        si += 2;
+        const matched = length(token & 15) + 4;
+        for (let end = di + matched; di < end; di++) out[di] = out[di - offset];
+    }
+    return out;
+}
+
 async function processCompressedFrame(data) {
     try {
         frameCount++;
@@ -264,38 +620,590 @@ async function processCompressedFrame(data) {
         let streamData = data;
         if (data instanceof Blob) { streamData = await data.arrayBuffer(); }
         
-        // Zstd Decompression (Standard)
-        const decompressed = decompress(new Uint8Array(streamData));
-        renderFrame(new DataView(decompressed.buffer));
+        const frame = decodeFrame(new Uint8Array(streamData));
+        renderFrame(new DataView(frame.buffer, frame.byteOffset, frame.byteLength));
     } catch (e) {
         console.error("Frame Error:", e);
     }
 }
 
-function sendInput(code, isDown) {
-    const buf = new Uint8Array(2);
-    buf[0] = code; buf[1] = isDown ? 1 : 0;
-    if (dc && dc.readyState === 'open') { dc.send(buf); } 
+// Every input message goes out wrapped in INPUT_SEQUENCED (0x10): seq u32, client time f64
+// (ms since the epoch), then the message. They go over the reliable DataChannel (the WebSocket
+// until it opens); the sequence number lets the server notice inputs reordered when switching.
+let inputSeq = 0;
+function sendInputMessage(msg) {
+    const buf = new Uint8Array(13 + msg.length);
+    const view = new DataView(buf.buffer);
+    view.setUint8(0, 0x10);
+    view.setUint32(1, inputSeq, true);
+    view.setFloat64(5, performance.timeOrigin + performance.now(), true);
+    buf.set(msg, 13);
+    inputSeq = (inputSeq + 1) >>> 0;
+    if (eventsDc && eventsDc.readyState === 'open') { eventsDc.send(buf); }
     else if (ws && ws.readyState === WebSocket.OPEN) { ws.send(buf); }
 }
 
-function renderFrame(view) {
+function sendInput(code, isDown) {
+    sendInputMessage(new Uint8Array([code, isDown ? 1 : 0]));
+}
+
+// INPUT_MOUSE (0x01): event u8, x f32, y f32 (little-endian), button i8
+function sendMouse(event, x, y, button) {
+    const view = new DataView(new ArrayBuffer(11));
+    view.setUint8(0, 0x01); view.setUint8(1, event);
+    view.setFloat32(2, x, true); view.setFloat32(6, y, true);
+    view.setInt8(10, button);
+    sendInputMessage(new Uint8Array(view.buffer));
+}
+
+// INPUT_TOUCH (0x02): phase u8, id u16, x f32, y f32 (little-endian)
+function sendTouch(phase, id, x, y) {
+    const view = new DataView(new ArrayBuffer(12));
+    view.setUint8(0, 0x02); view.setUint8(1, phase);
+    view.setUint16(2, id & 0xFFFF, true);
+    view.setFloat32(4, x, true); view.setFloat32(8, y, true);
+    sendInputMessage(new Uint8Array(view.buffer));
+}
+
+// INPUT_GAMEPAD (0x03): pad u8, kind u8 (0 button, 1 axis), index u8, value f32 (little-endian)
+function sendGamepad(pad, kind, index, value) {
+    const view = new DataView(new ArrayBuffer(8));
+    view.setUint8(0, 0x03); view.setUint8(1, pad); view.setUint8(2, kind); view.setUint8(3, index);
+    view.setFloat32(4, value, true);
+    sendInputMessage(new Uint8Array(view.buffer));
+}
+
+// Gamepads are polled every animation frame; only changes are sent. Axes inside the
+// dead zone (inputConfig.deadZone) read 0 and small jitters are not reported.
+const GAMEPAD_AXIS_STEP = 0.02;
+const gamepadState = new Map(); // "pad:kind:index" -> last value sent
+
+function reportGamepad(pad, kind, index, value) {
+    const id = `${pad}:${kind}:${index}`;
+    const last = gamepadState.get(id) ?? 0;
+    if (value === last || (value !== 0 && Math.abs(value - last) < GAMEPAD_AXIS_STEP)) return;
+    gamepadState.set(id, value);
+    sendGamepad(pad, kind, index, value);
+}
+
+function pollGamepads() {
+    for (const gp of (navigator.getGamepads ? navigator.getGamepads() : [])) {
+        if (!gp || gp.index >= JOYSTICK_PAD) continue;
+        gp.buttons.forEach((b, i) => { if (i < 256) reportGamepad(gp.index, 0, i, b.value); });
+        gp.axes.forEach((a, i) => { if (i < 256) reportGamepad(gp.index, 1, i, Math.abs(a) < inputConfig.deadZone ? 0 : a); });
+    }
+    requestAnimationFrame(pollGamepads);
+}
+requestAnimationFrame(pollGamepads);
+
+// Text answers travel as string messages (DataChannel) or TEXT_INPUT signals (WebSocket)
+function sendText(text) {
+    if (eventsDc && eventsDc.readyState === 'open') { eventsDc.send(text); }
+    else if (ws && ws.readyState === WebSocket.OPEN) { ws.send(JSON.stringify({ type: 'TEXT_INPUT', text })); }
+}
+
+// on_paste: text pasted anywhere but a text field (those take it themselves), on the same
+// channel as text answers, marked with \x16 (DataChannel) or `paste` (WebSocket)
+function sendPaste(text) {
+    if (eventsDc && eventsDc.readyState === 'open') { eventsDc.send('\x16' + text); }
+    else if (ws && ws.readyState === WebSocket.OPEN) { ws.send(JSON.stringify({ type: 'TEXT_INPUT', text, paste: true })); }
+}
+
+// on_sound_finished: sounds and tracks that ran to their end, over the WebSocket (reliable)
+function sendSoundFinished(name) {
+    if (ws && ws.readyState === WebSocket.OPEN) { ws.send(JSON.stringify({ type: 'SOUND_FINISHED', name })); }
+}
+
+// api.register_setting: the menu behind the gear button. Values the player picked are kept
+// per game (localStorage) and sent again every time the server sends the menu.
+const SETTINGS_STORAGE_KEY = 'cleoselene-settings:' + window.location.pathname;
+
+function storedSettings() {
+    try { return JSON.parse(localStorage.getItem(SETTINGS_STORAGE_KEY)) || {}; }
+    catch (e) { return {}; }
+}
+
+function sendSetting(key, value) {
+    const stored = storedSettings();
+    stored[key] = value;
+    try { localStorage.setItem(SETTINGS_STORAGE_KEY, JSON.stringify(stored)); } catch (e) {}
+    if (ws && ws.readyState === WebSocket.OPEN) { ws.send(JSON.stringify({ type: 'SETTING', key, value })); }
+}
+
+function showSettings(settings) {
+    document.getElementById('settings-button')?.remove();
+    document.getElementById('settings-panel')?.remove();
+    const stored = storedSettings();
+    const panel = document.createElement('form');
+    panel.id = 'settings-panel';
+    panel.className = 'settings-panel';
+    for (const s of settings) {
+        const value = s.key in stored ? stored[s.key] : s.default;
+        const row = document.createElement('label');
+        row.textContent = s.label;
+        let input;
+        if (s.kind === 'choice') {
+            input = document.createElement('select');
+            for (const option of s.options) input.add(new Option(option, option));
+            input.value = value;
+            input.addEventListener('change', () => sendSetting(s.key, input.value));
+        } else {
+            input = document.createElement('input');
+            if (s.kind === 'toggle') {
+                input.type = 'checkbox'; input.checked = !!value;
+                input.addEventListener('change', () => sendSetting(s.key, input.checked));
+            } else {
+                input.type = 'range'; input.min = s.min; input.max = s.max;
+                input.step = s.step > 0 ? s.step : 'any'; input.value = value;
+                input.addEventListener('input', () => sendSetting(s.key, parseFloat(input.value)));
+            }
+        }
+        // Keys pressed in the menu must not reach the game
+        input.addEventListener('keydown', e => e.stopPropagation());
+        input.addEventListener('keyup', e => e.stopPropagation());
+        row.appendChild(input);
+        panel.appendChild(row);
+        if (s.key in stored && stored[s.key] !== s.default) sendSetting(s.key, stored[s.key]);
+    }
+    const button = document.createElement('button');
+    button.id = 'settings-button';
+    button.className = 'settings-button';
+    button.textContent = '\u2699';
+    button.addEventListener('click', () => panel.classList.toggle('open'));
+    document.body.append(button, panel);
+}
+
+// Prompt opened by api.request_text_input: a focused <input> brings up the mobile keyboard
+function showTextPrompt(placeholder, maxLen) {
+    let form = document.getElementById('text-prompt');
+    if (form) form.remove();
+    form = document.createElement('form');
+    form.id = 'text-prompt';
+    form.className = 'text-prompt';
+    const input = document.createElement('input');
+    input.type = 'text'; input.placeholder = placeholder; input.maxLength = maxLen;
+    input.autocomplete = 'off'; input.enterKeyHint = 'send';
+    // Typing must not reach the game as key presses
+    input.addEventListener('keydown', e => e.stopPropagation());
+    input.addEventListener('keyup', e => e.stopPropagation());
+    form.appendChild(input);
+    form.addEventListener('submit', e => {
+        e.preventDefault();
+        sendText(input.value);
+        form.remove();
+    });
+    document.body.appendChild(form);
+    input.focus();
+}
+
+// Dev overlay (server --dev): reload results fade out, errors stay until replaced
+let devStatusTimer = null;
+function showDevStatus(ok, message) {
+    let banner = document.querySelector('.dev-status');
+    if (!banner) {
+        banner = document.createElement('div');
+        banner.className = 'dev-status';
+        document.body.appendChild(banner);
+    }
+    banner.textContent = message;
+    banner.classList.toggle('error', !ok);
+    banner.style.display = 'block';
+    clearTimeout(devStatusTimer);
+    if (ok) devStatusTimer = setTimeout(() => { banner.style.display = 'none'; }, 3000);
+}
+
+// Tiles are drawn once per chunk into a canvas at the tileset's own resolution;
+// a chunk is rendered again only when its tiles or the tileset change.
+function renderTileChunk(map, img, src, cx, cy) {
+    const columns = Math.max(1, Math.floor(img.naturalWidth / src));
+    const x0 = cx * TILEMAP_CHUNK, y0 = cy * TILEMAP_CHUNK;
+    const w = Math.min(TILEMAP_CHUNK, map.w - x0), h = Math.min(TILEMAP_CHUNK, map.h - y0);
+    const canvas = document.createElement('canvas');
+    canvas.width = w * src; canvas.height = h * src;
+    const c = canvas.getContext('2d');
+    for (let j = 0; j < h; j++) {
+        for (let i = 0; i < w; i++) {
+            const tile = map.tiles[(y0 + j) * map.w + x0 + i];
+            if (tile === 0) continue;
+            const n = tile - 1;
+            c.drawImage(img, (n % columns) * src, Math.floor(n / columns) * src, src, src, i * src, j * src, src, src);
+        }
+    }
+    return canvas;
+}
+
+function drawTilemap(map, img, name, src, x, y, size) {
+    const key = name + ':' + src;
+    if (map.key !== key) { map.chunks.clear(); map.key = key; }
+    const cols = Math.ceil(map.w / TILEMAP_CHUNK), rows = Math.ceil(map.h / TILEMAP_CHUNK);
+    const scale = size / src;
+    for (let cy = 0; cy < rows; cy++) {
+        for (let cx = 0; cx < cols; cx++) {
+            const index = cy * cols + cx;
+            let canvas = map.chunks.get(index);
+            if (!canvas) { canvas = renderTileChunk(map, img, src, cx, cy); map.chunks.set(index, canvas); }
+            ctx.drawImage(canvas, x + cx * TILEMAP_CHUNK * size, y + cy * TILEMAP_CHUNK * size, canvas.width * scale, canvas.height * scale);
+        }
+    }
+}
+
+// Plays a canvas recording into its offscreen canvas, layers included
+function renderCanvas(name, w, h, view) {
+    let target = canvases.get(name);
+    if (!target || target.width !== w || target.height !== h) {
+        target = document.createElement('canvas');
+        target.width = w; target.height = h;
+        canvases.set(name, target);
+    }
+    const screenCtx = ctx, screenTransform = baseTransform;
+    ctx = target.getContext('2d');
+    baseTransform = new DOMMatrix();
+    try {
+        ctx.setTransform(baseTransform);
+        ctx.clearRect(0, 0, w, h);
+        ctx.fillStyle = 'white'; ctx.strokeStyle = 'white';
+        const layers = runCommands(view, null);
+        for (const layer of [...layers].sort((a, b) => a - b)) {
+            ctx.fillStyle = 'white'; ctx.strokeStyle = 'white';
+            runCommands(view, layer);
+        }
+    } finally {
+        ctx = screenCtx; baseTransform = screenTransform;
+    }
+}
+
+// Margins in source pixels, drawn at 1:1 (shrunk when the panel is smaller than them)
+function drawNinePatch(img, x, y, w, h, left, top, right, bottom) {
+    const iw = img.naturalWidth, ih = img.naturalHeight;
+    const sx = [0, left, iw - right, iw], sy = [0, top, ih - bottom, ih];
+    const fx = Math.min(1, w / Math.max(left + right, 1e-6)), fy = Math.min(1, h / Math.max(top + bottom, 1e-6));
+    const dx = [x, x + left * fx, x + w - right * fx, x + w], dy = [y, y + top * fy, y + h - bottom * fy, y + h];
+    for (let j = 0; j < 3; j++) {
+        for (let i = 0; i < 3; i++) {
+            const sw = sx[i + 1] - sx[i], sh = sy[j + 1] - sy[j], dw = dx[i + 1] - dx[i], dh = dy[j + 1] - dy[j];
+            if (sw > 0 && sh > 0 && dw > 0 && dh > 0) ctx.drawImage(img, sx[i], sy[j], sw, sh, dx[i], dy[j], dw, dh);
+        }
+    }
+}
+
+function readString(view, offset) {
+    const len = view.getUint16(offset, true);
+    return [new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset + 2, len)), offset + 2 + len];
+}
+
+// Reads the fields of OP_NODE_CREATE/OP_NODE_UPDATE into `node` (when given); returns the new offset
+function readNodeFields(view, offset, mask, node, now) {
+    if (mask & NODE_TRANSFORM) {
+        const to = {
+            x: view.getFloat32(offset, true), y: view.getFloat32(offset + 4, true),
+            rotation: view.getFloat32(offset + 8, true), scale: view.getFloat32(offset + 12, true),
+        };
+        offset += 16;
+        if (node) {
+            node.from = (mask & NODE_SNAP) || !node.to ? to : nodeTransform(node, now);
+            node.to = to;
+            node.start = now;
+        }
+    }
+    if (mask & NODE_SHAPE) {
+        const kind = view.getUint8(offset); offset += 1;
+        let shape = { kind };
+        if (kind === 1) { shape.w = view.getFloat32(offset, true); shape.h = view.getFloat32(offset + 4, true); offset += 8; }
+        else if (kind === 2) { shape.r = view.getFloat32(offset, true); offset += 4; }
+        else if (kind === 3) {
+            [shape.image, offset] = readString(view, offset);
+            shape.w = view.getFloat32(offset, true); shape.h = view.getFloat32(offset + 4, true); offset += 8;
+        }
+        else if (kind === 4) { [shape.text, offset] = readString(view, offset); }
+        if (node) node.shape = shape;
+    }
+    if (mask & NODE_COLOR) {
+        if (node) node.color = `rgba(${view.getUint8(offset)}, ${view.getUint8(offset + 1)}, ${view.getUint8(offset + 2)}, ${view.getUint8(offset + 3) / 255})`;
+        offset += 4;
+    }
+    if (mask & NODE_Z) { if (node) node.z = view.getInt8(offset); offset += 1; }
+    if (mask & NODE_VISIBLE) { if (node) node.visible = view.getUint8(offset) === 1; offset += 1; }
+    if (mask & NODE_PARENT) { if (node) node.parent = view.getUint32(offset, true); offset += 4; }
+    return offset;
+}
+
+function nodeTransform(node, now) {
+    const t = Math.min(1, Math.max(0, (now - node.start) / frameInterval));
+    const a = node.from, b = node.to;
+    return {
+        x: a.x + (b.x - a.x) * t, y: a.y + (b.y - a.y) * t,
+        rotation: a.rotation + (b.rotation - a.rotation) * t, scale: a.scale + (b.scale - a.scale) * t,
+    };
+}
+
+function drawScene(now) {
+    const order = [...nodes.entries()].sort((a, b) => a[1].z - b[1].z || a[0] - b[0]);
+    for (const [, node] of order) {
+        // Parents first: a node is drawn in its ancestors' coordinate space
+        const chain = [];
+        let visible = true;
+        for (let n = node; n && chain.length < 64; n = n.parent ? nodes.get(n.parent) : null) {
+            chain.unshift(n);
+            visible = visible && n.visible;
+        }
+        if (!visible || node.shape.kind === 0) continue;
+        ctx.save();
+        for (const n of chain) {
+            const t = nodeTransform(n, now);
+            ctx.translate(t.x, t.y); ctx.rotate(t.rotation); ctx.scale(t.scale, t.scale);
+        }
+        const s = node.shape;
+        ctx.fillStyle = node.color;
+        if (s.kind === 1) ctx.fillRect(-s.w / 2, -s.h / 2, s.w, s.h);
+        else if (s.kind === 2) { ctx.beginPath(); ctx.arc(0, 0, Math.max(s.r, 0), 0, Math.PI * 2); ctx.fill(); }
+        else if (s.kind === 3) {
+            const img = images[s.image];
+            if (img && img.complete && img.naturalWidth > 0) ctx.drawImage(img, -s.w / 2, -s.h / 2, s.w, s.h);
+        }
+        else if (s.kind === 4) { ctx.font = '14px monospace'; ctx.textAlign = 'center'; ctx.textBaseline = 'middle'; ctx.fillText(s.text, 0, 0); }
+        ctx.restore();
+    }
+}
+
+function sceneEasing(now) {
+    for (const node of nodes.values()) {
+        if (now - node.start < frameInterval) return true;
+    }
+    return false;
+}
+
+// Between server frames, the last frame is redrawn (without its sounds or other effects)
+// while scene nodes are still easing towards their latest transform
+function animateScene() {
+    const now = performance.now();
+    if (lastFrame && nodes.size > 0 && now - lastFrameAt > 4 && sceneEasing(now - 16)) {
+        renderFrame(lastFrame, true);
+    }
+    requestAnimationFrame(animateScene);
+}
+requestAnimationFrame(animateScene);
+
+function renderFrame(view, replay = false) {
     if (!gameStarted) {
         console.log("First Frame Received! Hiding Overlay.");
         gameStarted = true;
-        hideLoading();
+        if (!preloading) hideLoading();
+    }
+    if (!ctx) return;
+    if (!replay) {
+        const now = performance.now();
+        if (lastFrameAt) frameInterval = frameInterval * 0.9 + Math.min(now - lastFrameAt, 250) * 0.1;
+        lastFrameAt = now;
+        lastFrame = view;
     }
+    // Layers (api.set_layer): a first pass runs the non-drawing commands (sounds, images,
+    // prompts) and finds the layers in use, then the frame is replayed once per layer in
+    // ascending order. State commands (color, transforms, text scale) apply in every pass.
+    const fill = ctx.fillStyle, stroke = ctx.strokeStyle;
+    const layers = runCommands(view, null, !replay);
+    const screen = baseTransform;
+    const offset = shakeOffset(performance.now());
+    if (offset) {
+        // The frame moves: what it uncovers is cleared rather than left from the last one
+        ctx.setTransform(screen); ctx.clearRect(0, 0, 800, 600);
+        baseTransform = screen.translate(offset.x, offset.y);
+    }
+    try {
+        for (const layer of [...layers].sort((a, b) => a - b)) {
+            ctx.fillStyle = fill; ctx.strokeStyle = stroke;
+            runCommands(view, layer);
+        }
+    } finally {
+        baseTransform = screen;
+    }
+    drawScreenEffects(performance.now());
+}
+
+function audioBus(name) {
+    if (!buses[name]) {
+        buses[name] = audioCtx.createGain();
+        buses[name].connect(audioCtx.destination);
+    }
+    return buses[name];
+}
+
+// pan: -1 (left) .. 1 (right), for api.play_sound_at; pitch: playback rate
+function playSound(name, shouldLoop, volume, pan, pitch) {
+    if (!sounds[name] || typeof sounds[name] === "string" || !audioCtx) return;
+    try {
+        if (activeSources[name] && shouldLoop) { try { activeSources[name].source.stopped = true; activeSources[name].source.stop(); } catch(e){} }
+        const source = audioCtx.createBufferSource(); source.buffer = sounds[name]; source.loop = shouldLoop;
+        source.playbackRate.value = pitch;
+        const gainNode = audioCtx.createGain(); gainNode.gain.value = volume;
+        source.connect(gainNode);
+        const bus = audioBus(soundBuses[name] || "sfx");
+        if (pan !== 0 && audioCtx.createStereoPanner) {
+            const panner = audioCtx.createStereoPanner(); panner.pan.value = pan;
+            gainNode.connect(panner); panner.connect(bus);
+        } else {
+            gainNode.connect(bus);
+        }
+        source.start(0);
+        source.onended = () => {
+            if (activeSources[name] && activeSources[name].source === source) { delete activeSources[name]; }
+            if (!source.stopped) sendSoundFinished(name);
+        };
+        activeSources[name] = { source, gain: gainNode };
+    } catch (e) { console.error(e); }
+}
+
+// Playing the track that is already playing only ramps its volume (no restart on hot reload)
+function playMusic(name, shouldLoop, volume, fadeIn) {
+    const track = music[name];
+    if (!track || !audioCtx) return;
+    try {
+        if (!track.el) {
+            track.el = new Audio(track.url);
+            track.el.crossOrigin = "anonymous";
+            track.el.onended = () => { track.playing = false; sendSoundFinished(name); };
+            track.gain = audioCtx.createGain(); track.gain.gain.value = 0;
+            audioCtx.createMediaElementSource(track.el).connect(track.gain);
+            track.gain.connect(audioBus(soundBuses[name] || "music"));
+        }
+        const now = audioCtx.currentTime;
+        const gain = track.gain.gain;
+        track.el.loop = shouldLoop;
+        gain.cancelScheduledValues(now);
+        if (!track.playing) {
+            track.el.currentTime = 0;
+            gain.setValueAtTime(0, now);
+            track.el.play().catch(e => console.error("Music playback failed:", name, e));
+            track.playing = true;
+        } else {
+            gain.setValueAtTime(gain.value, now);
+        }
+        clearTimeout(track.stopTimer); track.stopTimer = null;
+        gain.linearRampToValueAtTime(volume, now + Math.max(fadeIn, 0.05));
+    } catch (e) { console.error(e); }
+}
+
+function stopMusic(name, fadeOut) {
+    const track = music[name];
+    if (!track || !track.playing || track.stopTimer || !audioCtx) return;
+    try {
+        const now = audioCtx.currentTime;
+        const gain = track.gain.gain;
+        gain.cancelScheduledValues(now);
+        gain.setValueAtTime(gain.value, now);
+        gain.linearRampToValueAtTime(0, now + Math.max(fadeOut, 0.05));
+        track.stopTimer = setTimeout(() => {
+            track.el.pause(); track.playing = false; track.stopTimer = null;
+        }, Math.max(fadeOut, 0.05) * 1000);
+    } catch (e) { console.error(e); }
+}
+
+function shakeOffset(now) {
+    if (!shake) return null;
+    const left = 1 - (now - shake.start) / shake.duration;
+    if (!(left > 0)) { shake = null; return null; }
+    const d = shake.intensity * left;
+    return { x: (Math.random() * 2 - 1) * d, y: (Math.random() * 2 - 1) * d };
+}
+
+// Everything drawn so far darkens to `ambient`, brightened by each light's glow (clipped to
+// its visibility polygon when it has one). Lights are in the coordinates of the current transform.
+function drawLights(ambient, lights) {
+    const w = ctx.canvas.width, h = ctx.canvas.height;
+    if (!lightCanvas) lightCanvas = document.createElement('canvas');
+    if (lightCanvas.width !== w || lightCanvas.height !== h) { lightCanvas.width = w; lightCanvas.height = h; }
+    const lc = lightCanvas.getContext('2d');
+    lc.setTransform(1, 0, 0, 1, 0, 0);
+    lc.globalCompositeOperation = 'source-over';
+    lc.fillStyle = ambient;
+    lc.fillRect(0, 0, w, h);
+    lc.setTransform(ctx.getTransform());
+    lc.globalCompositeOperation = 'lighter';
+    for (const l of lights) {
+        const gradient = lc.createRadialGradient(l.x, l.y, 0, l.x, l.y, l.radius);
+        gradient.addColorStop(0, `rgba(${l.r},${l.g},${l.b},${l.a/255})`);
+        gradient.addColorStop(1, `rgba(${l.r},${l.g},${l.b},0)`);
+        lc.fillStyle = gradient;
+        lc.beginPath();
+        if (l.shadow.length) {
+            lc.moveTo(l.shadow[0], l.shadow[1]);
+            for (let i = 2; i < l.shadow.length; i += 2) lc.lineTo(l.shadow[i], l.shadow[i + 1]);
+            lc.closePath();
+        } else {
+            lc.arc(l.x, l.y, l.radius, 0, Math.PI * 2);
+        }
+        lc.fill();
+    }
+    ctx.save();
+    ctx.setTransform(1, 0, 0, 1, 0, 0);
+    ctx.globalCompositeOperation = 'multiply';
+    ctx.drawImage(lightCanvas, 0, 0);
+    ctx.restore();
+}
+
+// Drawn over the whole frame, in screen coordinates
+function drawScreenEffects(now) {
+    ctx.save();
+    if (post.brightness !== 1 || post.contrast !== 1) {
+        // The frame redrawn onto itself through the filter, in device pixels
+        ctx.setTransform(1, 0, 0, 1, 0, 0);
+        ctx.filter = `brightness(${post.brightness}) contrast(${post.contrast})`;
+        ctx.globalCompositeOperation = 'copy';
+        ctx.drawImage(ctx.canvas, 0, 0);
+        ctx.filter = 'none';
+    }
+    ctx.setTransform(baseTransform);
+    ctx.globalCompositeOperation = 'source-over';
+    if (post.tint) {
+        ctx.fillStyle = post.tint;
+        ctx.fillRect(0, 0, 800, 600);
+    }
+    const vignetteStrength = Math.max(vignette, post.vignette);
+    if (vignetteStrength > 0) {
+        const gradient = ctx.createRadialGradient(400, 300, 150, 400, 300, 500);
+        gradient.addColorStop(0, 'rgba(0,0,0,0)');
+        gradient.addColorStop(1, `rgba(0,0,0,${vignetteStrength})`);
+        ctx.fillStyle = gradient;
+        ctx.fillRect(0, 0, 800, 600);
+    }
+    if (post.scanlines) {
+        ctx.fillStyle = 'rgba(0,0,0,0.25)';
+        for (let y = 0; y < 600; y += 3) ctx.fillRect(0, y, 800, 1);
+    }
+    if (flash) {
+        const left = 1 - (now - flash.start) / flash.duration;
+        if (left > 0) {
+            ctx.globalAlpha = left;
+            ctx.fillStyle = flash.color;
+            ctx.fillRect(0, 0, 800, 600);
+        } else {
+            flash = null;
+        }
+    }
+    ctx.restore();
+}
+
+// onlyLayer === null: side effects only (unless applyEffects is false, for replays),
+// returns the set of layers used by the frame. Otherwise rasterizes the draw commands of that layer.
+function runCommands(view, onlyLayer, applyEffects = true) {
+    const effects = onlyLayer === null && applyEffects;
+    const layers = new Set([0]);
+    let draw = onlyLayer === 0;
     let offset = 0;
     const len = view.byteLength;
-    if (!ctx) return;
+    ctx.setTransform(baseTransform); // Each frame starts in screen coordinates
+    ctx.globalCompositeOperation = 'source-over';
+    let textScale = 1;
+    const transformStack = []; // Matrices only: colors/line widths are not affected by pop
     while (offset < len) {
         const opcode = view.getUint8(offset);
         offset += 1;
         if (opcode === OP_CLEAR) {
             const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2);
             offset += 3;
-            ctx.fillStyle = `rgb(${r},${g},${b})`;
-            ctx.fillRect(0, 0, 800, 600);
+            if (draw) {
+                ctx.fillStyle = `rgb(${r},${g},${b})`;
+                ctx.save(); ctx.setTransform(baseTransform); ctx.globalCompositeOperation = 'source-over'; ctx.fillRect(0, 0, 800, 600); ctx.restore();
+            }
         } 
         else if (opcode === OP_SET_COLOR) {
             const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
@@ -303,12 +1211,47 @@ function renderFrame(view) {
             const color = `rgba(${r},${g},${b},${a/255})`;
             ctx.fillStyle = color; ctx.strokeStyle = color;
         }
+        else if (opcode === OP_DEFINE_PALETTE) {
+            const count = view.getUint16(offset, true); offset += 2;
+            const colors = [];
+            for (let i = 0; i < count; i++) {
+                const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
+                offset += 4;
+                colors.push(`rgba(${r},${g},${b},${a/255})`);
+            }
+            if (effects) palette = colors;
+        }
+        else if (opcode === OP_PALETTE_COLOR) {
+            const color = palette[view.getUint8(offset)];
+            offset += 1;
+            if (color !== undefined) { ctx.fillStyle = color; ctx.strokeStyle = color; }
+        }
+        else if (opcode === OP_SET_GRADIENT) {
+            const kind = view.getUint8(offset); offset += 1;
+            const c = [];
+            for (let i = 0; i < 6; i++) { c.push(view.getFloat32(offset, true)); offset += 4; }
+            const count = view.getUint8(offset); offset += 1;
+            const gradient = kind === 0
+                ? ctx.createLinearGradient(c[0], c[1], c[3], c[4])
+                : ctx.createRadialGradient(c[0], c[1], c[2], c[3], c[4], c[5]);
+            for (let i = 0; i < count; i++) {
+                const stop = view.getFloat32(offset, true); offset += 4;
+                const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
+                offset += 4;
+                gradient.addColorStop(stop, `rgba(${r},${g},${b},${a/255})`);
+            }
+            ctx.fillStyle = gradient;
+        }
+        else if (opcode === OP_SET_BLEND_MODE) {
+            const mode = view.getUint8(offset); offset += 1;
+            ctx.globalCompositeOperation = BLEND_MODES[mode] || 'source-over';
+        }
         else if (opcode === OP_FILL_RECT) {
             const x = view.getFloat32(offset, true); offset += 4;
             const y = view.getFloat32(offset, true); offset += 4;
             const w = view.getFloat32(offset, true); offset += 4;
             const h = view.getFloat32(offset, true); offset += 4;
-            ctx.fillRect(x, y, w, h);
+            if (draw) ctx.fillRect(x, y, w, h);
         }
         else if (opcode === OP_DRAW_LINE) {
             const x1 = view.getFloat32(offset, true); offset += 4;
@@ -316,7 +1259,302 @@ function renderFrame(view) {
             const x2 = view.getFloat32(offset, true); offset += 4;
             const y2 = view.getFloat32(offset, true); offset += 4;
             const w = view.getFloat32(offset, true); offset += 4;
-            ctx.lineWidth = w; ctx.beginPath(); ctx.moveTo(x1, y1); ctx.lineTo(x2, y2); ctx.stroke(); ctx.lineWidth = 1;
+            if (draw) { ctx.lineWidth = w; ctx.beginPath(); ctx.moveTo(x1, y1); ctx.lineTo(x2, y2); ctx.stroke(); ctx.lineWidth = 1; }
+        }
+        else if (opcode === OP_FILL_CIRCLE) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const r = view.getFloat32(offset, true); offset += 4;
+            if (draw) { ctx.beginPath(); ctx.arc(x, y, Math.max(r, 0), 0, Math.PI * 2); ctx.fill(); }
+        }
+        else if (opcode === OP_DRAW_CIRCLE) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const r = view.getFloat32(offset, true); offset += 4;
+            const w = view.getFloat32(offset, true); offset += 4;
+            if (draw) { ctx.lineWidth = w; ctx.beginPath(); ctx.arc(x, y, Math.max(r, 0), 0, Math.PI * 2); ctx.stroke(); ctx.lineWidth = 1; }
+        }
+        else if (opcode === OP_FILL_ARC) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const r = view.getFloat32(offset, true); offset += 4;
+            const start = view.getFloat32(offset, true); offset += 4;
+            const end = view.getFloat32(offset, true); offset += 4;
+            const inner = view.getFloat32(offset, true); offset += 4;
+            if (draw) {
+                ctx.beginPath();
+                if (inner > 0) {
+                    // Ring segment: outer arc forward, inner arc back
+                    ctx.arc(x, y, Math.max(r, 0), start, end, end < start);
+                    ctx.arc(x, y, inner, end, start, end >= start);
+                } else {
+                    ctx.moveTo(x, y);
+                    ctx.arc(x, y, Math.max(r, 0), start, end, end < start);
+                }
+                ctx.closePath();
+                ctx.fill();
+            }
+        }
+        else if (opcode === OP_DRAW_ARC) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const r = view.getFloat32(offset, true); offset += 4;
+            const start = view.getFloat32(offset, true); offset += 4;
+            const end = view.getFloat32(offset, true); offset += 4;
+            const w = view.getFloat32(offset, true); offset += 4;
+            if (draw) { ctx.lineWidth = w; ctx.beginPath(); ctx.arc(x, y, Math.max(r, 0), start, end, end < start); ctx.stroke(); ctx.lineWidth = 1; }
+        }
+        else if (opcode === OP_DRAW_CURVE) {
+            const cubic = view.getUint8(offset) === 1; offset += 1;
+            const closed = view.getUint8(offset) === 1; offset += 1;
+            const w = view.getFloat32(offset, true); offset += 4;
+            const x0 = view.getFloat32(offset, true); offset += 4;
+            const y0 = view.getFloat32(offset, true); offset += 4;
+            const count = view.getUint16(offset, true); offset += 2;
+            const floats = count * (cubic ? 6 : 4);
+            if (!draw) { offset += floats * 4; continue; }
+            const p = [];
+            for (let i = 0; i < floats; i++) { p.push(view.getFloat32(offset, true)); offset += 4; }
+            ctx.beginPath();
+            ctx.moveTo(x0, y0);
+            for (let i = 0; i < floats; i += cubic ? 6 : 4) {
+                if (cubic) ctx.bezierCurveTo(p[i], p[i + 1], p[i + 2], p[i + 3], p[i + 4], p[i + 5]);
+                else ctx.quadraticCurveTo(p[i], p[i + 1], p[i + 2], p[i + 3]);
+            }
+            if (closed) ctx.closePath();
+            ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1;
+        }
+        else if (opcode === OP_NODE_CREATE || opcode === OP_NODE_UPDATE) {
+            const id = view.getUint32(offset, true); offset += 4;
+            const mask = view.getUint8(offset); offset += 1;
+            let node = null;
+            if (effects) {
+                node = nodes.get(id);
+                // Create on a known id is a full refresh; updates to unknown ids wait for one
+                if (!node && opcode === OP_NODE_CREATE) {
+                    node = { from: null, to: null, start: 0, shape: { kind: 0 }, color: 'white', z: 0, visible: true, parent: 0 };
+                    nodes.set(id, node);
+                }
+            }
+            offset = readNodeFields(view, offset, mask, node, performance.now());
+        }
+        else if (opcode === OP_NODE_DESTROY) {
+            const id = view.getUint32(offset, true); offset += 4;
+            if (effects) nodes.delete(id);
+        }
+        else if (opcode === OP_NODE_SYNC) {
+            const count = view.getUint32(offset, true); offset += 4;
+            if (effects) {
+                const live = new Set();
+                for (let i = 0; i < count; i++) live.add(view.getUint32(offset + i * 4, true));
+                for (const id of [...nodes.keys()]) if (!live.has(id)) nodes.delete(id);
+            }
+            offset += count * 4;
+        }
+        else if (opcode === OP_SCENE_UPDATE) {
+            // Position and rotation of known nodes, scale unchanged
+            const count = view.getUint16(offset, true); offset += 2;
+            const now = performance.now();
+            for (let i = 0; i < count; i++, offset += 16) {
+                const node = effects ? nodes.get(view.getUint32(offset, true)) : null;
+                if (!node || !node.to) continue;
+                const to = {
+                    x: view.getFloat32(offset + 4, true), y: view.getFloat32(offset + 8, true),
+                    rotation: view.getFloat32(offset + 12, true), scale: node.to.scale,
+                };
+                node.from = nodeTransform(node, now);
+                node.to = to;
+                node.start = now;
+            }
+        }
+        else if (opcode === OP_SCREEN_SHAKE) {
+            const intensity = view.getFloat32(offset, true); const duration = view.getFloat32(offset + 4, true);
+            offset += 8;
+            if (effects && duration > 0) shake = { intensity, start: performance.now(), duration: duration * 1000 };
+        }
+        else if (opcode === OP_FLASH) {
+            const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2);
+            const duration = view.getFloat32(offset + 3, true);
+            offset += 7;
+            if (effects && duration > 0) flash = { color: `rgb(${r},${g},${b})`, start: performance.now(), duration: duration * 1000 };
+        }
+        else if (opcode === OP_VIGNETTE) {
+            const strength = view.getFloat32(offset, true); offset += 4;
+            if (effects) vignette = strength;
+        }
+        else if (opcode === OP_SET_POST) {
+            const r = view.getUint8(offset), g = view.getUint8(offset + 1), b = view.getUint8(offset + 2), a = view.getUint8(offset + 3); offset += 4;
+            const strength = view.getFloat32(offset, true); offset += 4;
+            const brightness = view.getFloat32(offset, true); offset += 4;
+            const contrast = view.getFloat32(offset, true); offset += 4;
+            const scanlines = view.getUint8(offset) === 1; offset += 1;
+            if (effects) post = { tint: a > 0 ? `rgba(${r},${g},${b},${a / 255})` : null, vignette: strength, brightness, contrast, scanlines };
+        }
+        else if (opcode === OP_DRAW_SCENE) {
+            if (draw) drawScene(performance.now());
+        }
+        else if (opcode === OP_TILEMAP_CHUNK) {
+            const id = view.getUint16(offset, true); offset += 2;
+            const mw = view.getUint16(offset, true); offset += 2;
+            const mh = view.getUint16(offset, true); offset += 2;
+            const x0 = view.getUint16(offset, true); offset += 2;
+            const y0 = view.getUint16(offset, true); offset += 2;
+            const cw = view.getUint8(offset); offset += 1;
+            const ch = view.getUint8(offset); offset += 1;
+            if (effects) {
+                let map = tilemaps.get(id);
+                if (!map || map.w !== mw || map.h !== mh) {
+                    map = { w: mw, h: mh, tiles: new Uint16Array(mw * mh), chunks: new Map(), key: '' };
+                    tilemaps.set(id, map);
+                }
+                for (let j = 0; j < ch; j++) {
+                    for (let i = 0; i < cw; i++) {
+                        map.tiles[(y0 + j) * mw + x0 + i] = view.getUint16(offset + (j * cw + i) * 2, true);
+                    }
+                }
+                map.chunks.delete(Math.floor(y0 / TILEMAP_CHUNK) * Math.ceil(mw / TILEMAP_CHUNK) + Math.floor(x0 / TILEMAP_CHUNK));
+            }
+            offset += cw * ch * 2;
+        }
+        else if (opcode === OP_DRAW_TILEMAP) {
+            const id = view.getUint16(offset, true); offset += 2;
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const src = view.getUint16(offset, true); offset += 2;
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const size = view.getFloat32(offset, true); offset += 4;
+            const map = tilemaps.get(id);
+            const img = images[name];
+            if (draw && map && img && img.complete && img.naturalWidth > 0) drawTilemap(map, img, name, src, x, y, size);
+        }
+        else if (opcode === OP_FILL_ELLIPSE) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const rx = view.getFloat32(offset, true); offset += 4;
+            const ry = view.getFloat32(offset, true); offset += 4;
+            if (draw) { ctx.beginPath(); ctx.ellipse(x, y, Math.max(rx, 0), Math.max(ry, 0), 0, 0, Math.PI * 2); ctx.fill(); }
+        }
+        else if (opcode === OP_DRAW_ELLIPSE) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const rx = view.getFloat32(offset, true); offset += 4;
+            const ry = view.getFloat32(offset, true); offset += 4;
+            const w = view.getFloat32(offset, true); offset += 4;
+            if (draw) { ctx.lineWidth = w; ctx.beginPath(); ctx.ellipse(x, y, Math.max(rx, 0), Math.max(ry, 0), 0, 0, Math.PI * 2); ctx.stroke(); ctx.lineWidth = 1; }
+        }
+        else if (opcode === OP_DRAW_POLY) {
+            const fill = view.getUint8(offset) === 0; offset += 1;
+            const w = view.getFloat32(offset, true); offset += 4;
+            const count = view.getUint16(offset, true); offset += 2;
+            if (!draw) { offset += count * 8; continue; }
+            ctx.beginPath();
+            for (let i = 0; i < count; i++) {
+                const x = view.getFloat32(offset, true); offset += 4;
+                const y = view.getFloat32(offset, true); offset += 4;
+                if (i === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y);
+            }
+            ctx.closePath();
+            if (count >= 2) {
+                if (fill) { ctx.fill(); }
+                else { ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1; }
+            }
+        }
+        else if (opcode === OP_DRAW_POLYLINE) {
+            const w = view.getFloat32(offset, true); offset += 4;
+            const count = view.getUint16(offset, true); offset += 2;
+            if (!draw) { offset += count * 8; continue; }
+            ctx.beginPath();
+            for (let i = 0; i < count; i++) {
+                const x = view.getFloat32(offset, true); offset += 4;
+                const y = view.getFloat32(offset, true); offset += 4;
+                if (i === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y);
+            }
+            ctx.lineWidth = w; ctx.stroke(); ctx.lineWidth = 1;
+        }
+        else if (opcode === OP_FILL_TRIANGLES) {
+            const count = view.getUint16(offset, true); offset += 2;
+            if (!draw) { offset += count * 24; continue; }
+            // One path, filled once: no seams between triangles
+            ctx.beginPath();
+            for (let i = 0; i < count; i++) {
+                for (let v = 0; v < 3; v++) {
+                    const x = view.getFloat32(offset, true); const y = view.getFloat32(offset + 4, true);
+                    offset += 8;
+                    if (v === 0) ctx.moveTo(x, y); else ctx.lineTo(x, y);
+                }
+                ctx.closePath();
+            }
+            ctx.fill();
+        }
+        else if (opcode === OP_DRAW_PARTICLES) {
+            const circles = view.getUint8(offset) === 1; offset += 1;
+            const count = view.getUint16(offset, true); offset += 2;
+            if (!draw) { offset += count * 14; continue; }
+            const fill = ctx.fillStyle;
+            for (let i = 0; i < count; i++) {
+                const x = view.getFloat32(offset, true); offset += 4;
+                const y = view.getFloat32(offset, true); offset += 4;
+                const size = view.getUint16(offset, true) / 16; offset += 2;
+                const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
+                offset += 4;
+                ctx.fillStyle = `rgba(${r},${g},${b},${a/255})`;
+                if (circles) { ctx.beginPath(); ctx.arc(x, y, size / 2, 0, Math.PI * 2); ctx.fill(); }
+                else { ctx.fillRect(x - size / 2, y - size / 2, size, size); }
+            }
+            ctx.fillStyle = fill; // Particles don't change the current color
+        }
+        else if (opcode === OP_LIGHTS) {
+            const ambient = `rgb(${view.getUint8(offset)},${view.getUint8(offset + 1)},${view.getUint8(offset + 2)})`;
+            offset += 3;
+            const count = view.getUint16(offset, true); offset += 2;
+            const lights = [];
+            for (let i = 0; i < count; i++) {
+                const x = view.getFloat32(offset, true); offset += 4;
+                const y = view.getFloat32(offset, true); offset += 4;
+                const radius = view.getFloat32(offset, true); offset += 4;
+                const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
+                offset += 4;
+                const points = view.getUint16(offset, true); offset += 2;
+                const shadow = [];
+                for (let j = 0; j < points * 2; j++) { shadow.push(view.getFloat32(offset, true)); offset += 4; }
+                lights.push({ x, y, radius, r, g, b, a, shadow });
+            }
+            if (draw) drawLights(ambient, lights);
+        }
+        else if (opcode === OP_DRAW_TRAILS) {
+            const count = view.getUint16(offset, true); offset += 2;
+            const stroke = ctx.strokeStyle, lineWidth = ctx.lineWidth, lineCap = ctx.lineCap;
+            ctx.lineCap = 'round';
+            for (let i = 0; i < count; i++) {
+                const r = view.getUint8(offset); const g = view.getUint8(offset + 1); const b = view.getUint8(offset + 2); const a = view.getUint8(offset + 3);
+                offset += 4;
+                const width = view.getFloat32(offset, true); offset += 4;
+                const points = view.getUint16(offset, true); offset += 2;
+                if (!draw || points < 2) { offset += points * 8; continue; }
+                // Head first: each segment narrower and fainter than the one before
+                let x = view.getFloat32(offset, true), y = view.getFloat32(offset + 4, true);
+                offset += 8;
+                for (let j = 1; j < points; j++) {
+                    const nx = view.getFloat32(offset, true), ny = view.getFloat32(offset + 4, true);
+                    offset += 8;
+                    const left = 1 - (j - 1) / (points - 1);
+                    ctx.strokeStyle = `rgba(${r},${g},${b},${a / 255 * left})`;
+                    ctx.lineWidth = width * left;
+                    ctx.beginPath(); ctx.moveTo(x, y); ctx.lineTo(nx, ny); ctx.stroke();
+                    x = nx; y = ny;
+                }
+            }
+            ctx.strokeStyle = stroke; ctx.lineWidth = lineWidth; ctx.lineCap = lineCap; // Trails don't change the current style
+        }
+        else if (opcode === OP_SET_TRANSFORM) {
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const zoom = view.getFloat32(offset, true); offset += 4;
+            const rotation = view.getFloat32(offset, true); offset += 4;
+            // World (x, y) at the screen center
+            ctx.setTransform(baseTransform);
+            ctx.translate(400, 300); ctx.rotate(-rotation); ctx.scale(zoom, zoom); ctx.translate(-x, -y);
         }
         else if (opcode === OP_DRAW_TEXT) {
             const x = view.getFloat32(offset, true); offset += 4;
@@ -325,58 +1563,41 @@ function renderFrame(view) {
             const textBuffer = new Uint8Array(view.buffer, view.byteOffset + offset, textLen);
             offset += textLen;
             const text = new TextDecoder().decode(textBuffer);
-            ctx.font = "14px monospace"; ctx.textBaseline = "middle"; ctx.fillText(text, x, y);
+            if (draw) { ctx.font = `${14 * textScale}px monospace`; ctx.textBaseline = "middle"; ctx.fillText(text, x, y); }
         }
         else if (opcode === OP_LOAD_SOUND) {
             const nameLen = view.getUint16(offset, true); offset += 2;
             const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
             const urlLen = view.getUint16(offset, true); offset += 2;
-            let url = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, urlLen)); offset += urlLen;
-            
-            // Fix Path for Subdirectory Deployment
-            if (url.startsWith('/') && !url.startsWith('//')) {
-                const bp = getBasePath();
-                if (bp && !url.startsWith(bp)) {
-                    url = bp + url;
-                }
-            }
-
-            if (!sounds[name]) {
-                sounds[name] = "loading"; 
-                fetch(url)
-                    .then(r => r.arrayBuffer())
-                    .then(ab => audioCtx.decodeAudioData(ab))
-                    .then(buf => { sounds[name] = buf; })
-                    .catch(e => console.error("Sound load failed:", name, e));
-            }
+            const url = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, urlLen)); offset += urlLen;
+            if (effects) loadSound(name, url);
         }
         else if (opcode === OP_PLAY_SOUND) {
             const nameLen = view.getUint16(offset, true); offset += 2;
             const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
             const shouldLoop = view.getUint8(offset) === 1; offset += 1;
             const volume = view.getFloat32(offset, true); offset += 4;
-            if (sounds[name] && typeof sounds[name] !== "string" && audioCtx) {
-                try {
-                    if (activeSources[name] && shouldLoop) { try { activeSources[name].source.stop(); } catch(e){} }
-                    const source = audioCtx.createBufferSource(); source.buffer = sounds[name]; source.loop = shouldLoop;
-                    const gainNode = audioCtx.createGain(); gainNode.gain.value = volume; 
-                    source.connect(gainNode); gainNode.connect(audioCtx.destination);
-                    source.start(0);
-                    source.onended = () => { if (activeSources[name] && activeSources[name].source === source) { delete activeSources[name]; } };
-                    activeSources[name] = { source, gain: gainNode };
-                } catch (e) { console.error(e); }
-            }
+            const pitch = view.getFloat32(offset, true); offset += 4;
+            if (effects) playSound(name, shouldLoop, volume, 0, pitch);
+        }
+        else if (opcode === OP_PLAY_SOUND_PANNED) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const shouldLoop = view.getUint8(offset) === 1; offset += 1;
+            const volume = view.getFloat32(offset, true); offset += 4;
+            const pan = view.getFloat32(offset, true); offset += 4;
+            if (effects) playSound(name, shouldLoop, volume, pan, 1);
         }
         else if (opcode === OP_STOP_SOUND) {
             const nameLen = view.getUint16(offset, true); offset += 2;
             const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
             const active = activeSources[name];
-            if (active && audioCtx) {
+            if (effects && active && audioCtx) {
                 try {
                     const now = audioCtx.currentTime;
                     active.gain.gain.setValueAtTime(active.gain.gain.value, now);
                     active.gain.gain.linearRampToValueAtTime(0, now + 0.5);
-                    active.source.stop(now + 0.5); delete activeSources[name];
+                    active.source.stopped = true; active.source.stop(now + 0.5); delete activeSources[name];
                 } catch(e) {}
             }
         }
@@ -385,11 +1606,252 @@ function renderFrame(view) {
             const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
             const volume = view.getFloat32(offset, true); offset += 4;
             const active = activeSources[name];
-            if (active && audioCtx) {
+            if (effects && active && audioCtx) {
                 try { active.gain.gain.setTargetAtTime(volume, audioCtx.currentTime, 0.1); } catch(e) {}
             }
         }
+        else if (opcode === OP_LOAD_MUSIC) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const urlLen = view.getUint16(offset, true); offset += 2;
+            let url = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, urlLen)); offset += urlLen;
+            if (url.startsWith('/') && !url.startsWith('//')) {
+                const bp = getBasePath();
+                if (bp && !url.startsWith(bp)) url = bp + url;
+            }
+            if (effects && !music[name]) music[name] = { url, el: null, gain: null, playing: false, stopTimer: null };
+        }
+        else if (opcode === OP_PLAY_MUSIC) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const shouldLoop = view.getUint8(offset) === 1; offset += 1;
+            const volume = view.getFloat32(offset, true); offset += 4;
+            const fadeIn = view.getFloat32(offset, true); offset += 4;
+            if (effects) playMusic(name, shouldLoop, volume, fadeIn);
+        }
+        else if (opcode === OP_STOP_MUSIC) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const fadeOut = view.getFloat32(offset, true); offset += 4;
+            if (effects) stopMusic(name, fadeOut);
+        }
+        else if (opcode === OP_SET_PITCH) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const rate = view.getFloat32(offset, true); offset += 4;
+            const active = activeSources[name];
+            if (effects && active && audioCtx) {
+                try { active.source.playbackRate.setTargetAtTime(rate, audioCtx.currentTime, 0.05); } catch(e) {}
+            }
+        }
+        else if (opcode === OP_SET_BUS_VOLUME) {
+            const busLen = view.getUint16(offset, true); offset += 2;
+            const bus = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, busLen)); offset += busLen;
+            const volume = view.getFloat32(offset, true); offset += 4;
+            const fade = view.getFloat32(offset, true); offset += 4;
+            if (effects && audioCtx) {
+                try {
+                    const gain = audioBus(bus).gain, now = audioCtx.currentTime;
+                    gain.cancelScheduledValues(now);
+                    gain.setValueAtTime(gain.value, now);
+                    gain.linearRampToValueAtTime(volume, now + Math.max(fade, 0.02));
+                } catch(e) { console.error(e); }
+            }
+        }
+        else if (opcode === OP_SET_SOUND_BUS) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const busLen = view.getUint16(offset, true); offset += 2;
+            const bus = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, busLen)); offset += busLen;
+            if (effects) {
+                soundBuses[name] = bus;
+                // A music track already playing moves over
+                const track = music[name];
+                if (track && track.gain && audioCtx) { track.gain.disconnect(); track.gain.connect(audioBus(bus)); }
+            }
+        }
+        else if (opcode === OP_LOAD_IMAGE) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const urlLen = view.getUint16(offset, true); offset += 2;
+            const url = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, urlLen)); offset += urlLen;
+            if (effects) loadImage(name, url);
+        }
+        else if (opcode === OP_DRAW_SPRITE) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const w = view.getFloat32(offset, true); offset += 4;
+            const h = view.getFloat32(offset, true); offset += 4;
+            const rotation = view.getFloat32(offset, true); offset += 4;
+            const img = images[name];
+            if (draw && img && img.complete && img.naturalWidth > 0) {
+                if (rotation === 0) { ctx.drawImage(img, x, y, w, h); }
+                else {
+                    // Rotate around the sprite center
+                    ctx.save(); ctx.translate(x + w / 2, y + h / 2); ctx.rotate(rotation);
+                    ctx.drawImage(img, -w / 2, -h / 2, w, h); ctx.restore();
+                }
+            }
+        }
+        else if (opcode === OP_LOAD_SPRITESHEET) {
+            const id = view.getUint16(offset, true); offset += 2;
+            let url;
+            [url, offset] = readString(view, offset);
+            const fw = view.getUint16(offset, true); const fh = view.getUint16(offset + 2, true);
+            offset += 4;
+            if (url.startsWith('/') && !url.startsWith('//')) {
+                const bp = getBasePath();
+                if (bp && !url.startsWith(bp)) url = bp + url;
+            }
+            const sheet = sheets.get(id);
+            if (effects && (!sheet || sheet.url !== url)) {
+                const img = new Image();
+                img.onerror = e => console.error("Sprite sheet load failed:", url, e);
+                img.src = url;
+                sheets.set(id, { img, url, fw, fh });
+            }
+            else if (effects) { sheet.fw = fw; sheet.fh = fh; }
+        }
+        else if (opcode === OP_DRAW_FRAME) {
+            const id = view.getUint16(offset, true); const frame = view.getUint16(offset + 2, true);
+            offset += 4;
+            const x = view.getFloat32(offset, true); const y = view.getFloat32(offset + 4, true);
+            const w = view.getFloat32(offset + 8, true); const h = view.getFloat32(offset + 12, true);
+            const rotation = view.getFloat32(offset + 16, true);
+            offset += 20;
+            const sheet = sheets.get(id);
+            const img = sheet && sheet.img;
+            if (draw && img && img.complete && img.naturalWidth > 0) {
+                // Frames are numbered row by row; frames past the image are skipped
+                const columns = Math.floor(img.naturalWidth / sheet.fw);
+                const sx = (frame % columns) * sheet.fw, sy = Math.floor(frame / columns) * sheet.fh;
+                if (columns > 0 && sy + sheet.fh <= img.naturalHeight) {
+                    ctx.save(); ctx.translate(x + w / 2, y + h / 2); if (rotation !== 0) ctx.rotate(rotation);
+                    ctx.drawImage(img, sx, sy, sheet.fw, sheet.fh, -w / 2, -h / 2, w, h); ctx.restore();
+                }
+            }
+        }
+        else if (opcode === OP_DRAW_NINEPATCH) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const v = [];
+            for (let i = 0; i < 8; i++) { v.push(view.getFloat32(offset, true)); offset += 4; }
+            const img = images[name];
+            if (draw && img && img.complete && img.naturalWidth > 0) drawNinePatch(img, ...v);
+        }
+        else if (opcode === OP_CANVAS_CONTENT) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const w = view.getUint16(offset, true); offset += 2;
+            const h = view.getUint16(offset, true); offset += 2;
+            const contentLen = view.getUint32(offset, true); offset += 4;
+            if (effects) renderCanvas(name, w, h, new DataView(view.buffer, view.byteOffset + offset, contentLen));
+            offset += contentLen;
+        }
+        else if (opcode === OP_DRAW_CANVAS) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            const x = view.getFloat32(offset, true); offset += 4;
+            const y = view.getFloat32(offset, true); offset += 4;
+            const w = view.getFloat32(offset, true); offset += 4;
+            const h = view.getFloat32(offset, true); offset += 4;
+            const target = canvases.get(name);
+            if (draw && target) ctx.drawImage(target, x, y, w, h);
+        }
+        else if (opcode === OP_SET_CONTROL_SCHEME) {
+            const nameLen = view.getUint16(offset, true); offset += 2;
+            const name = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, nameLen)); offset += nameLen;
+            if (effects) setControlScheme(name);
+        }
+        else if (opcode === OP_PUSH_TRANSFORM) {
+            const tx = view.getFloat32(offset, true); offset += 4;
+            const ty = view.getFloat32(offset, true); offset += 4;
+            const rotation = view.getFloat32(offset, true); offset += 4;
+            const sx = view.getFloat32(offset, true); offset += 4;
+            const sy = view.getFloat32(offset, true); offset += 4;
+            transformStack.push(ctx.getTransform());
+            ctx.translate(tx, ty); ctx.rotate(rotation); ctx.scale(sx, sy);
+        }
+        else if (opcode === OP_POP_TRANSFORM) {
+            if (transformStack.length) ctx.setTransform(transformStack.pop());
+        }
+        else if (opcode === OP_SET_TEXT_SCALE) {
+            textScale = view.getFloat32(offset, true); offset += 4;
+        }
+        else if (opcode === OP_REQUEST_TEXT_INPUT) {
+            const textLen = view.getUint16(offset, true); offset += 2;
+            const placeholder = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, textLen)); offset += textLen;
+            const maxLen = view.getUint16(offset, true); offset += 2;
+            if (effects) showTextPrompt(placeholder, maxLen);
+        }
+        else if (opcode === OP_ACTION_KEYS) {
+            const count = view.getUint8(offset); offset += 1;
+            const bindings = new Map();
+            for (let i = 0; i < count; i++) {
+                let action;
+                [action, offset] = readString(view, offset);
+                const keyCount = view.getUint8(offset); offset += 1;
+                bindings.set(action, Array.from(new Uint8Array(view.buffer, view.byteOffset + offset, keyCount)));
+                offset += keyCount;
+            }
+            if (effects) { actionKeys.clear(); bindings.forEach((keys, action) => actionKeys.set(action, keys)); }
+        }
+        else if (opcode === OP_INPUT_CONFIG) {
+            const keyRepeat = view.getUint8(offset) !== 0;
+            const deadZone = view.getFloat32(offset + 1, true);
+            const joystickRadius = view.getFloat32(offset + 5, true);
+            offset += 9;
+            if (effects) applyInputConfig({ keyRepeat, deadZone, joystickRadius });
+        }
+        else if (opcode === OP_SETTINGS) {
+            const count = view.getUint8(offset); offset += 1;
+            const settings = [];
+            for (let i = 0; i < count; i++) {
+                let key, label;
+                [key, offset] = readString(view, offset);
+                [label, offset] = readString(view, offset);
+                const kind = view.getUint8(offset); offset += 1;
+                if (kind === 0) {
+                    const min = view.getFloat32(offset, true), max = view.getFloat32(offset + 4, true);
+                    const step = view.getFloat32(offset + 8, true), value = view.getFloat32(offset + 12, true);
+                    offset += 16;
+                    settings.push({ key, label, kind: 'slider', min, max, step, default: value });
+                } else if (kind === 1) {
+                    settings.push({ key, label, kind: 'toggle', default: view.getUint8(offset) !== 0 }); offset += 1;
+                } else {
+                    const options = [];
+                    const optionCount = view.getUint8(offset); offset += 1;
+                    for (let j = 0; j < optionCount; j++) { let option; [option, offset] = readString(view, offset); options.push(option); }
+                    const index = view.getUint8(offset); offset += 1;
+                    settings.push({ key, label, kind: 'choice', options, default: options[index] });
+                }
+            }
+            if (effects) showSettings(settings);
+        }
+        else if (opcode === OP_DEV_STATUS) {
+            const ok = view.getUint8(offset) === 0; offset += 1;
+            const textLen = view.getUint16(offset, true); offset += 2;
+            const message = new TextDecoder().decode(new Uint8Array(view.buffer, view.byteOffset + offset, textLen)); offset += textLen;
+            if (effects) showDevStatus(ok, message);
+        }
+        else if (opcode === OP_SET_LAYER) {
+            const layer = view.getInt8(offset); offset += 1;
+            layers.add(layer);
+            draw = layer === onlyLayer;
+        }
+        else if (opcode >= OP_PLUGIN_FIRST) {
+            const payloadLen = view.getUint16(offset, true); offset += 2;
+            const handler = pluginHandlers[opcode];
+            if (draw && handler) {
+                try { handler(ctx, new DataView(view.buffer, view.byteOffset + offset, payloadLen)); }
+                catch (e) { console.error("Plugin opcode failed:", opcode, e); }
+            }
+            offset += payloadLen; // Unknown plugin opcodes are skipped
+        }
         else { break; }
     }
+    return layers;
 }
 document.addEventListener('DOMContentLoaded', init);
@@ -0,0 +1,68 @@
+// SpatialDb / PhysicsWorld throughput at 10k+ entities, driven through the Lua API
+// exactly like a game would (step + range queries + churn from spawning/despawning).
+//
+//   cargo bench -p engine --bench physics
+use engine::GameState;
+use std::time::Instant;
+
+const SCRIPT: &str = r#"
+    local COUNT = 10000
+
+    function init()
+        db = api.new_spatial_db(64)
+        phys = api.new_physics_world(db)
+        ids = {}
+        for i = 1, COUNT do
+            ids[i] = spawn(i)
+        end
+        frame = 0
+    end
+
+    function spawn(i)
+        local id = db:add_circle((i * 37) % 6000, (i * 91) % 6000, 4, i % 2 == 0 and "a" or "b")
+        phys:add_body(id, { mass = 1.0, restitution = 0.8 })
+        phys:set_velocity(id, (i % 7) * 10 - 30, (i % 5) * 10 - 20)
+        return id
+    end
+
+    function bench_step(dt)
+        phys:step(dt)
+        phys:get_collision_events()
+    end
+
+    function bench_query()
+        local total = 0
+        for i = 1, COUNT, 10 do
+            local x, y = db:get_position(ids[i])
+            total = total + #db:query_range(x, y, 100, "a")
+        end
+        return total
+    end
+
+    function bench_churn()
+        frame = frame + 1
+        for i = 1, 500 do
+            local k = ((frame * 500 + i) % COUNT) + 1
+            db:remove(ids[k])
+            ids[k] = spawn(k)
+        end
+    end
+"#;
+
+fn bench(game: &GameState, name: &str, code: &str, iterations: u32) {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let out = game.eval(code);
+        assert!(!out.starts_with("Error"), "{}: {}", name, out);
+    }
+    let per_iter = start.elapsed() / iterations;
+    println!("{:<24} {:>6} iters  {:>10.3} ms/iter", name, iterations, per_iter.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init benchmark script");
+
+    bench(&game, "phys:step (10k)", "bench_step(1 / 30)", 100);
+    bench(&game, "query_range x1000", "bench_query()", 100);
+    bench(&game, "remove+add x500", "bench_churn()", 100);
+}
@@ -0,0 +1,98 @@
+// Interpreter throughput under the engine's typical workload:
+// a heavy update() loop over plain Lua tables + SpatialDb/Physics calls, and a per-client draw().
+//
+// Run once per backend and compare the numbers:
+//   cargo bench -p engine --bench scripting
+//   cargo bench -p engine --bench scripting --no-default-features --features luajit
+//   cargo bench -p engine --bench scripting --no-default-features --features luau
+use engine::GameState;
+use std::time::{Duration, Instant};
+
+const SCRIPT: &str = r#"
+    local ENTITIES = 5000
+
+    function init()
+        db = api.new_spatial_db(100)
+        phys = api.new_physics_world(db)
+        entities = {}
+        for i = 1, ENTITIES do
+            local x, y = (i * 37) % 4000, (i * 91) % 4000
+            local id = db:add_circle(x, y, 5, "unit")
+            phys:add_body(id, { mass = 1.0, restitution = 0.5, drag = 0.1 })
+            entities[i] = { id = id, x = x, y = y, vx = 0, vy = 0, hp = 100 }
+        end
+    end
+
+    function update(dt)
+        for i = 1, #entities do
+            local e = entities[i]
+            local a = (i * 0.1) + dt
+            e.vx = e.vx * 0.9 + math.cos(a) * 10
+            e.vy = e.vy * 0.9 + math.sin(a) * 10
+            e.hp = math.max(0, e.hp - 0.01)
+            phys:set_velocity(e.id, e.vx, e.vy)
+        end
+        phys:step(dt)
+        for i = 1, #entities, 50 do
+            local x, y = db:get_position(entities[i].id)
+            local near = db:query_range(x, y, 200, "unit")
+            entities[i].near = #near
+        end
+        phys:get_collision_events()
+    end
+
+    function draw(session_id)
+        api.clear_screen(0, 0, 0)
+        for _, id in ipairs(db:query_rect(0, 0, 800, 600)) do
+            local x, y = db:get_position(id)
+            api.set_color(255, 255, 255)
+            api.fill_rect(x - 5, y - 5, 10, 10)
+        end
+        api.draw_text("units: " .. #entities, 10, 10)
+    end
+"#;
+
+fn bench(name: &str, iterations: u32, mut f: impl FnMut()) {
+    // Warm-up (lets LuaJIT record traces before measuring)
+    for _ in 0..iterations / 10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let total = start.elapsed();
+    let per_iter = total / iterations;
+    println!("{:<24} {:>6} iters  {:>10.3} ms/iter", name, iterations, as_ms(per_iter));
+}
+
+fn as_ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn main() {
+    let backend = if cfg!(feature = "luajit") {
+        "luajit"
+    } else if cfg!(feature = "luau") {
+        "luau"
+    } else {
+        "lua54"
+    };
+    println!("Scripting backend: {}", backend);
+
+    let start = Instant::now();
+    let game = GameState::new(SCRIPT, None).expect("Failed to init benchmark script");
+    println!("{:<24} {:>10.3} ms", "init()", as_ms(start.elapsed()));
+
+    bench("update()", 200, || {
+        game.begin_frame();
+        game.update(1.0 / 30.0).expect("update failed");
+    });
+
+    bench("draw() x 8 clients", 200, || {
+        for i in 0..8 {
+            game.draw(&format!("bench_{}", i)).expect("draw failed");
+        }
+    });
+}
@@ -0,0 +1,90 @@
+// --- Accessibility ---
+// Per-session settings applied while encoding that session's draw() commands:
+// colors are daltonized for the chosen color-vision deficiency (the information
+// lost by the deficiency is shifted into channels the viewer can distinguish),
+// and text is scaled by the client.
+
+type Matrix = [[f32; 3]; 3];
+
+// Dichromat simulation matrices (RGB space)
+const PROTANOPIA: Matrix = [[0.567, 0.433, 0.0], [0.558, 0.442, 0.0], [0.0, 0.242, 0.758]];
+const DEUTERANOPIA: Matrix = [[0.625, 0.375, 0.0], [0.7, 0.3, 0.0], [0.0, 0.3, 0.7]];
+const TRITANOPIA: Matrix = [[0.95, 0.05, 0.0], [0.0, 0.433, 0.567], [0.0, 0.475, 0.525]];
+
+// Redistributes the simulation error into green/blue
+const ERROR_SHIFT: Matrix = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Palette {
+    Normal,
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl Palette {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" | "none" => Some(Palette::Normal),
+            "protanopia" => Some(Palette::Protanopia),
+            "deuteranopia" => Some(Palette::Deuteranopia),
+            "tritanopia" => Some(Palette::Tritanopia),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Normal => "normal",
+            Palette::Protanopia => "protanopia",
+            Palette::Deuteranopia => "deuteranopia",
+            Palette::Tritanopia => "tritanopia",
+        }
+    }
+
+    fn simulation(&self) -> Option<&'static Matrix> {
+        match self {
+            Palette::Normal => None,
+            Palette::Protanopia => Some(&PROTANOPIA),
+            Palette::Deuteranopia => Some(&DEUTERANOPIA),
+            Palette::Tritanopia => Some(&TRITANOPIA),
+        }
+    }
+}
+
+fn apply(m: &Matrix, c: [f32; 3]) -> [f32; 3] {
+    let mut out = [0.0; 3];
+    for (row, o) in m.iter().zip(out.iter_mut()) {
+        *o = row[0] * c[0] + row[1] * c[1] + row[2] * c[2];
+    }
+    out
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Accessibility {
+    pub palette: Palette,
+    pub text_scale: f32,
+}
+
+impl Default for Accessibility {
+    fn default() -> Self {
+        Self { palette: Palette::Normal, text_scale: 1.0 }
+    }
+}
+
+impl Accessibility {
+    /// Whether `remap` changes colors at all.
+    pub fn remaps_colors(&self) -> bool {
+        self.palette.simulation().is_some()
+    }
+
+    pub fn remap(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        let Some(sim) = self.palette.simulation() else { return (r, g, b) };
+        let c = [r as f32, g as f32, b as f32];
+        let seen = apply(sim, c);
+        let error = [c[0] - seen[0], c[1] - seen[1], c[2] - seen[2]];
+        let shift = apply(&ERROR_SHIFT, error);
+        let channel = |i: usize| (c[i] + shift[i]).round().clamp(0.0, 255.0) as u8;
+        (channel(0), channel(1), channel(2))
+    }
+}
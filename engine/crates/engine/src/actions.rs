@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::input::InputStates;
+
+// --- Input Actions ---
+// Logical actions ("jump", "fire") bound to keys and gamepad buttons, declared in
+// input_map.json next to the script and with api.bind_action. The script receives
+// on_action(session_id, action, is_down) when an action starts or stops being held,
+// whichever bound control caused it. A player can be given bindings of their own
+// (api.rebind_action); each client is sent the keys of every action, so mobile buttons
+// declared with an "action" in keys.json press whatever that action is bound to.
+//
+//   { "jump": {"keys": [32, 38], "pad_buttons": [0]}, "fire": {"keys": [90]} }
+
+pub const INPUT_MAP_FILE: &str = "input_map.json";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(default)]
+    pub keys: Vec<u8>,
+    #[serde(default)]
+    pub pad_buttons: Vec<u8>, // Standard layout indices (0 = "a")
+}
+
+#[derive(Default)]
+pub struct Actions {
+    bindings: BTreeMap<String, Binding>,
+    // Per-player replacements of some bindings
+    overrides: HashMap<String, BTreeMap<String, Binding>>,
+    held: HashMap<String, HashSet<String>>,
+    // Bumped when bindings change (the game's, a player's), to resend them to clients
+    version: u64,
+    session_versions: HashMap<String, u64>,
+    sent: HashMap<String, (u64, u64)>,
+}
+
+impl Actions {
+    pub fn bind(&mut self, action: String, binding: Binding) {
+        self.bindings.insert(action, binding);
+        self.version += 1;
+    }
+
+    /// Replaces a player's binding (None restores the game's). False for unknown actions.
+    pub fn rebind(&mut self, session_id: &str, action: &str, binding: Option<Binding>) -> bool {
+        if !self.bindings.contains_key(action) {
+            return false;
+        }
+        let overrides = self.overrides.entry(session_id.to_string()).or_default();
+        match binding {
+            Some(b) => { overrides.insert(action.to_string(), b); }
+            None => { overrides.remove(action); }
+        }
+        *self.session_versions.entry(session_id.to_string()).or_default() += 1;
+        true
+    }
+
+    /// Every action with the session's binding for it, by name.
+    pub fn bindings(&self, session_id: &str) -> BTreeMap<&str, &Binding> {
+        let overrides = self.overrides.get(session_id);
+        self.bindings.iter()
+            .map(|(action, b)| (action.as_str(), overrides.and_then(|o| o.get(action)).unwrap_or(b)))
+            .collect()
+    }
+
+    /// Actions that started (true) or stopped (false) being held since the session's last
+    /// input, by name.
+    pub fn update(&mut self, session_id: &str, input: &InputStates) -> Vec<(String, bool)> {
+        let now: HashSet<String> = self.bindings(session_id).into_iter()
+            .filter(|(_, b)| {
+                b.keys.iter().any(|&k| input.is_key_down(session_id, k))
+                    || b.pad_buttons.iter().any(|&i| input.is_pad_button_down(session_id, i))
+            })
+            .map(|(action, _)| action.to_string())
+            .collect();
+        let held = self.held.entry(session_id.to_string()).or_default();
+        let mut changes: Vec<(String, bool)> = held.difference(&now).map(|a| (a.clone(), false)).collect();
+        changes.extend(now.difference(held).map(|a| (a.clone(), true)));
+        changes.sort();
+        *held = now;
+        changes
+    }
+
+    pub fn is_held(&self, session_id: &str, action: &str) -> bool {
+        self.held.get(session_id).is_some_and(|h| h.contains(action))
+    }
+
+    /// The session's bindings if its client doesn't have them yet (marked as sent).
+    pub fn take_changed(&mut self, session_id: &str) -> Option<BTreeMap<&str, &Binding>> {
+        let version = (self.version, self.session_versions.get(session_id).copied().unwrap_or(0));
+        if self.bindings.is_empty() || self.sent.get(session_id) == Some(&version) {
+            return None;
+        }
+        self.sent.insert(session_id.to_string(), version);
+        Some(self.bindings(session_id))
+    }
+
+    /// The session's client starts over (it reconnected): its bindings are sent again and
+    /// the actions it held are released, by name.
+    pub fn reset_client(&mut self, session_id: &str) -> Vec<String> {
+        self.sent.remove(session_id);
+        let mut released: Vec<String> = self.held.remove(session_id).unwrap_or_default().into_iter().collect();
+        released.sort();
+        released
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.overrides.remove(session_id);
+        self.held.remove(session_id);
+        self.session_versions.remove(session_id);
+        self.sent.remove(session_id);
+    }
+}
@@ -0,0 +1,102 @@
+use bytes::{Bytes, BytesMut};
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+
+// --- Shared Drawing ---
+// api.draw_shared(fn), called in draw(session_id), marks the part of the frame that is the
+// same for every client (the world, typically, under each client's own camera). The first
+// session drawn in a frame runs `fn` and its commands are kept; for the others they are
+// copied in without calling `fn` again. draw_split() hands the frame over in three parts, so
+// hosts serialize and compress the shared one once per frame rather than once per client.
+//
+// The segment is drawn for no session in particular: no viewport culling, no congestion
+// detail cuts, no screen shake, and calls that need the session being drawn do nothing.
+// A camera it sets is undone after it. Clients that need frames downgraded
+// (compat.rs) get them whole.
+
+/// Commands api.draw_shared recorded this frame, the same for every session.
+pub struct SharedSegment {
+    pub bytes: Bytes,
+    encoded: OnceLock<Bytes>,
+}
+
+impl SharedSegment {
+    fn new(bytes: Bytes) -> Self {
+        Self { bytes, encoded: OnceLock::new() }
+    }
+
+    /// The segment passed through `encode` (compressed, say): only the first call encodes it.
+    pub fn encoded(&self, encode: impl FnOnce(&[u8]) -> Bytes) -> Bytes {
+        self.encoded.get_or_init(|| encode(&self.bytes)).clone()
+    }
+}
+
+/// A session's frame: `before`, then the shared segment if the script drew one, then `after`.
+#[derive(Clone)]
+pub struct DrawnFrame {
+    pub before: Bytes,
+    pub shared: Option<Arc<SharedSegment>>,
+    pub after: Bytes,
+}
+
+impl DrawnFrame {
+    /// The frame as one command stream.
+    pub fn to_bytes(&self) -> Bytes {
+        let shared = self.shared.as_ref().map_or(&[][..], |s| &s.bytes[..]);
+        if shared.is_empty() && self.after.is_empty() {
+            return self.before.clone();
+        }
+        let mut frame = BytesMut::with_capacity(self.before.len() + shared.len() + self.after.len());
+        frame.extend_from_slice(&self.before);
+        frame.extend_from_slice(shared);
+        frame.extend_from_slice(&self.after);
+        frame.freeze()
+    }
+}
+
+impl From<Bytes> for DrawnFrame {
+    fn from(frame: Bytes) -> Self {
+        Self { before: frame, shared: None, after: Bytes::new() }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct SharedDraw {
+    segment: Option<Arc<SharedSegment>>,
+    moves_camera: bool, // The segment sets a camera: each session's own is set again after it
+    recording: bool,
+    // Where the segment went in the command buffer of the session being drawn
+    placed: Option<Range<usize>>,
+}
+
+impl SharedDraw {
+    /// The segment recorded this frame, if any. Errors if the session being drawn already has one.
+    pub fn recorded(&self) -> mlua::Result<Option<(Arc<SharedSegment>, bool)>> {
+        if self.recording || self.placed.is_some() {
+            return Err(mlua::Error::RuntimeError("api.draw_shared can only be called once per draw()".to_string()));
+        }
+        Ok(self.segment.clone().map(|s| (s, self.moves_camera)))
+    }
+
+    pub fn start_recording(&mut self) {
+        self.recording = true;
+    }
+
+    pub fn finish_recording(&mut self, bytes: Option<Bytes>, moves_camera: bool) {
+        self.recording = false;
+        if let Some(bytes) = bytes {
+            self.segment = Some(Arc::new(SharedSegment::new(bytes)));
+            self.moves_camera = moves_camera;
+        }
+    }
+
+    pub fn place(&mut self, range: Range<usize>) {
+        self.placed = Some(range);
+    }
+
+    /// Where the segment went in the frame just drawn; forgotten for the next one.
+    pub fn take_placed(&mut self) -> Option<(Range<usize>, Arc<SharedSegment>)> {
+        let range = self.placed.take()?;
+        self.segment.clone().map(|s| (range, s))
+    }
+}
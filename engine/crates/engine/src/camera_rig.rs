@@ -0,0 +1,140 @@
+use crate::noise::{self, NoiseParams};
+use crate::spatial_db::SpatialDb;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// --- Camera Rigs ---
+// Engine-driven cameras. api.camera_follow eases a session's camera towards a spatial DB
+// entity every tick; api.camera_shake adds a decaying offset for one session or everyone.
+// draw() starts each session's frame with the resulting camera, and api.set_camera keeps
+// the shake, so scripts that place the camera themselves still shake.
+
+/// Target of api.camera_shake that means every client.
+pub const ALL_SESSIONS: &str = "all";
+
+// Shake wobbles per second (smooth noise rather than a new random offset every frame)
+const SHAKE_FREQUENCY: f64 = 25.0;
+
+struct Follow {
+    db: Arc<Mutex<SpatialDb>>,
+    id: u64,
+    lerp: f32,
+    zoom: f32,
+    pos: Option<(f32, f32)>, // None until the first tick: the camera starts on the entity
+}
+
+#[derive(Clone, Copy)]
+struct Shake {
+    intensity: f32,
+    duration: f32,
+    left: f32,
+}
+
+impl Shake {
+    fn amplitude(&self) -> f32 {
+        self.intensity * self.left / self.duration
+    }
+
+    // A new shake replaces the current one only if it is stronger right now
+    fn merge(current: &mut Option<Shake>, new: Shake) {
+        if current.is_none_or(|c| c.amplitude() <= new.amplitude()) {
+            *current = Some(new);
+        }
+    }
+
+    // False once it has died out
+    fn step(&mut self, dt: f32) -> bool {
+        self.left -= dt;
+        self.left > 0.0
+    }
+}
+
+#[derive(Default)]
+struct Rig {
+    follow: Option<Follow>,
+    shake: Option<Shake>,
+}
+
+#[derive(Default)]
+pub struct CameraRigs {
+    sessions: HashMap<String, Rig>,
+    shake_all: Option<Shake>,
+    time: f64,
+}
+
+impl CameraRigs {
+    /// `lerp`: fraction of the way to the entity covered per 1/60 s (1 = locked on).
+    pub fn follow(&mut self, session_id: &str, db: Arc<Mutex<SpatialDb>>, id: u64, lerp: f32, zoom: f32) {
+        let rig = self.sessions.entry(session_id.to_string()).or_default();
+        let pos = rig.follow.as_ref().and_then(|f| f.pos);
+        rig.follow = Some(Follow { db, id, lerp, zoom, pos });
+    }
+
+    pub fn unfollow(&mut self, session_id: &str) {
+        if let Some(rig) = self.sessions.get_mut(session_id) {
+            rig.follow = None;
+        }
+    }
+
+    pub fn shake(&mut self, target: &str, intensity: f32, duration: f32) {
+        let shake = Shake { intensity, duration, left: duration };
+        if target == ALL_SESSIONS {
+            Shake::merge(&mut self.shake_all, shake);
+        } else {
+            Shake::merge(&mut self.sessions.entry(target.to_string()).or_default().shake, shake);
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.time += dt as f64;
+        if let Some(shake) = &mut self.shake_all {
+            if !shake.step(dt) {
+                self.shake_all = None;
+            }
+        }
+        self.sessions.retain(|_, rig| {
+            if let Some(shake) = &mut rig.shake {
+                if !shake.step(dt) {
+                    rig.shake = None;
+                }
+            }
+            if let Some(follow) = &mut rig.follow {
+                let target = follow.db.lock().unwrap().get_position(follow.id);
+                if let Some(target) = target {
+                    let t = 1.0 - (1.0 - follow.lerp.clamp(0.0, 1.0)).powf(dt * 60.0);
+                    follow.pos = Some(match follow.pos {
+                        Some((x, y)) => (x + (target.0 - x) * t, y + (target.1 - y) * t),
+                        None => target,
+                    });
+                } else {
+                    // The entity was removed
+                    rig.follow = None;
+                }
+            }
+            rig.follow.is_some() || rig.shake.is_some()
+        });
+    }
+
+    /// Followed position and zoom, once the follow has had a tick.
+    pub fn view(&self, session_id: &str) -> Option<(f32, f32, f32)> {
+        let follow = self.sessions.get(session_id)?.follow.as_ref()?;
+        follow.pos.map(|(x, y)| (x, y, follow.zoom))
+    }
+
+    /// Shake offset in screen pixels for the current tick.
+    pub fn shake_offset(&self, session_id: &str) -> (f32, f32) {
+        let own = self.sessions.get(session_id).and_then(|rig| rig.shake);
+        let amplitude = own.map_or(0.0, |s| s.amplitude()) + self.shake_all.map_or(0.0, |s| s.amplitude());
+        if amplitude <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let t = self.time * SHAKE_FREQUENCY;
+        let x = NoiseParams { seed: 1, ..Default::default() };
+        let y = NoiseParams { seed: 2, ..Default::default() };
+        (noise::noise1d(t, &x) as f32 * amplitude, noise::noise1d(t, &y) as f32 * amplitude)
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
@@ -0,0 +1,85 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// --- Render Targets ---
+// A canvas is a named offscreen image kept by each client, filled with the commands
+// recorded by canvas:draw(fn). The recording (OP_CANVAS_CONTENT) only reaches a client
+// again when it changed, so blitting a cached background or minimap is a single
+// OP_DRAW_CANVAS per frame. Frames can be lost in transit: the recording is also resent
+// every REFRESH_BLITS blits until the client has it for sure.
+
+pub const MAX_CANVAS_SIZE: u16 = 4096;
+
+const REFRESH_BLITS: u32 = 120;
+
+struct SessionSync {
+    version: u32, // Recording version the client has
+    blits: u32,   // Blits since it was sent
+}
+
+pub struct Canvas {
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+    content: Bytes,
+    version: u32, // Bumped when the recording changes
+    sessions: HashMap<String, SessionSync>,
+}
+
+impl Canvas {
+    /// Replaces the recorded commands. Recording the same commands again changes nothing.
+    pub fn set_content(&mut self, content: Bytes) {
+        if content != self.content {
+            self.content = content;
+            self.version += 1;
+        }
+    }
+
+    /// The recorded commands when `session_id` should receive them with this blit.
+    pub fn sync(&mut self, session_id: &str) -> Option<Bytes> {
+        let version = self.version;
+        let sync = self.sessions.entry(session_id.to_string())
+            .or_insert(SessionSync { version: 0, blits: 0 });
+        sync.blits += 1;
+        if sync.version == version && sync.blits < REFRESH_BLITS {
+            return None;
+        }
+        *sync = SessionSync { version, blits: 0 };
+        Some(self.content.clone())
+    }
+
+    /// Drops what `session_id` is known to have: the recording is sent again.
+    pub fn forget(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+/// Canvases by name.
+#[derive(Clone, Default)]
+pub struct Canvases {
+    by_name: Arc<Mutex<HashMap<String, Arc<Mutex<Canvas>>>>>,
+}
+
+impl Canvases {
+    /// Creates `name`, replacing any canvas of that name (clients get the new one on its first blit).
+    pub fn create(&self, name: &str, width: u16, height: u16) -> Arc<Mutex<Canvas>> {
+        // Version 1: even an empty recording is sent once, clearing what the client had
+        let canvas = Arc::new(Mutex::new(Canvas {
+            name: name.to_string(),
+            width,
+            height,
+            content: Bytes::new(),
+            version: 1,
+            sessions: HashMap::new(),
+        }));
+        self.by_name.lock().unwrap().insert(name.to_string(), canvas.clone());
+        canvas
+    }
+
+    pub fn forget_session(&self, session_id: &str) {
+        for canvas in self.by_name.lock().unwrap().values() {
+            canvas.lock().unwrap().forget(session_id);
+        }
+    }
+}
@@ -0,0 +1,761 @@
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::f32::consts::TAU;
+
+use crate::{scene, CommandBuffer, SCREEN_H, SCREEN_W};
+use crate::{
+    OP_CLEAR, OP_SET_COLOR, OP_FILL_RECT, OP_DRAW_LINE, OP_DRAW_TEXT, OP_LOAD_SOUND, OP_PLAY_SOUND,
+    OP_STOP_SOUND, OP_SET_VOLUME, OP_LOAD_IMAGE, OP_DRAW_SPRITE, OP_FILL_CIRCLE, OP_DRAW_CIRCLE,
+    OP_FILL_ELLIPSE, OP_DRAW_ELLIPSE, OP_SET_CONTROL_SCHEME, OP_DRAW_POLY, OP_SET_TRANSFORM,
+    OP_REQUEST_TEXT_INPUT, OP_SET_TEXT_SCALE, OP_PUSH_TRANSFORM, OP_POP_TRANSFORM, OP_SET_LAYER,
+    OP_DEV_STATUS, OP_SET_GRADIENT, OP_SET_BLEND_MODE, OP_DRAW_PARTICLES, OP_FILL_ARC, OP_DRAW_ARC,
+    OP_TILEMAP_CHUNK, OP_DRAW_TILEMAP, OP_DRAW_CURVE, OP_NODE_CREATE, OP_NODE_UPDATE,
+    OP_NODE_DESTROY, OP_NODE_SYNC, OP_DRAW_SCENE, OP_DRAW_NINEPATCH, OP_CANVAS_CONTENT,
+    OP_DRAW_CANVAS, OP_SCENE_UPDATE, OP_SCREEN_SHAKE, OP_FLASH, OP_VIGNETTE,
+    OP_LOAD_SPRITESHEET, OP_DRAW_FRAME, OP_DEFINE_PALETTE, OP_PALETTE_COLOR,
+    OP_FILL_TRIANGLES, OP_DRAW_POLYLINE, OP_PLAY_SOUND_PANNED, OP_LOAD_MUSIC, OP_PLAY_MUSIC,
+    OP_STOP_MUSIC, OP_SET_POST, OP_SET_PITCH, OP_SET_BUS_VOLUME, OP_SET_SOUND_BUS, OP_LIGHTS,
+    OP_DRAW_TRAILS, OP_SETTINGS, OP_ACTION_KEYS, OP_INPUT_CONFIG, OP_PLUGIN_FIRST,
+};
+
+// --- Client Capabilities ---
+// Clients announce the opcode groups they understand as a bitfield when they connect
+// (`caps` on the WebSocket URL). Frames for a client missing some groups are rewritten
+// with the opcodes it does know: sprites, sheet frames and nine-patches become rectangles
+// in the current color, outlines and curves become line segments, filled circles and
+// ellipses their bounding rectangle, concave polygons separate triangles (or their
+// outline), gradients their first color, palette colors plain colors, and what has no
+// equivalent (particles, lights, trails, tilemaps, canvases, the retained scene, layers,
+// prompts, the settings menu...) is left out. Clients that predate capabilities announce nothing and get the
+// original command set (0x01-0x09) only.
+//
+// Transforms are applied here for clients without CAP_TRANSFORMS, so anything drawn under
+// one goes through the fallbacks even when the client knows its opcode.
+
+pub const CAP_IMAGES: u32 = 1 << 0; // OP_LOAD_IMAGE, OP_DRAW_SPRITE
+pub const CAP_ELLIPSES: u32 = 1 << 1; // Circles and ellipses
+pub const CAP_CONTROL_SCHEMES: u32 = 1 << 2; // OP_SET_CONTROL_SCHEME, OP_ACTION_KEYS, OP_INPUT_CONFIG
+pub const CAP_POLYGONS: u32 = 1 << 3;
+pub const CAP_TRANSFORMS: u32 = 1 << 4; // Camera, push/pop_transform
+pub const CAP_TEXT_INPUT: u32 = 1 << 5;
+pub const CAP_TEXT_SCALE: u32 = 1 << 6;
+pub const CAP_LAYERS: u32 = 1 << 7;
+pub const CAP_DEV_STATUS: u32 = 1 << 8;
+pub const CAP_GRADIENTS: u32 = 1 << 9; // Gradients and blend modes
+pub const CAP_PARTICLES: u32 = 1 << 10;
+pub const CAP_ARCS: u32 = 1 << 11;
+pub const CAP_TILEMAPS: u32 = 1 << 12;
+pub const CAP_CURVES: u32 = 1 << 13;
+pub const CAP_SCENE: u32 = 1 << 14;
+pub const CAP_PLUGINS: u32 = 1 << 15; // Opcodes 0xC0..0xFF
+pub const CAP_NINEPATCH: u32 = 1 << 16;
+pub const CAP_CANVASES: u32 = 1 << 17;
+pub const CAP_SCENE_UPDATES: u32 = 1 << 18; // OP_SCENE_UPDATE (batched node moves)
+pub const CAP_EFFECTS: u32 = 1 << 19; // Screen shake, flash, vignette
+pub const CAP_SPRITESHEETS: u32 = 1 << 20;
+pub const CAP_PALETTES: u32 = 1 << 21; // OP_DEFINE_PALETTE, OP_PALETTE_COLOR
+pub const CAP_TRIANGLES: u32 = 1 << 22; // Concave polygons, triangulated
+pub const CAP_POLYLINES: u32 = 1 << 23;
+pub const CAP_PANNING: u32 = 1 << 24; // OP_PLAY_SOUND_PANNED (positional sounds)
+pub const CAP_MUSIC: u32 = 1 << 25; // OP_LOAD_MUSIC, OP_PLAY_MUSIC, OP_STOP_MUSIC
+pub const CAP_POST: u32 = 1 << 26; // OP_SET_POST (post-processing)
+pub const CAP_PITCH: u32 = 1 << 27; // Pitch in OP_PLAY_SOUND, OP_SET_PITCH
+pub const CAP_BUSES: u32 = 1 << 28; // OP_SET_BUS_VOLUME, OP_SET_SOUND_BUS
+pub const CAP_LIGHTS: u32 = 1 << 29;
+pub const CAP_TRAILS: u32 = 1 << 30;
+pub const CAP_SETTINGS: u32 = 1 << 31;
+pub const CAPS_ALL: u32 = u32::MAX;
+/// Clients that don't announce capabilities.
+pub const CAPS_LEGACY: u32 = 0;
+
+// Line segments per full turn when flattening circles and arcs, and per bezier segment
+const CIRCLE_SEGMENTS: f32 = 32.0;
+const CURVE_STEPS: usize = 12;
+
+/// `frame` rewritten for a client that only understands `caps`. Commands that can't be
+/// decoded (unknown opcodes) end the frame, as they would on the client.
+pub fn downgrade(frame: &[u8], caps: u32) -> Bytes {
+    downgrade_with_palette(frame, caps, &[])
+}
+
+/// Like `downgrade`, with the palette defined in earlier frames (palette colors the frame
+/// uses before defining its own).
+pub fn downgrade_with_palette(frame: &[u8], caps: u32, palette: &[[u8; 4]]) -> Bytes {
+    if caps & CAPS_ALL == CAPS_ALL {
+        return Bytes::copy_from_slice(frame);
+    }
+    let mut fallback = Fallback { out: CommandBuffer::new(), caps, transform: Affine::IDENTITY, stack: Vec::new(), palette: palette.to_vec() };
+    let mut reader = Reader { data: frame, pos: 0 };
+    while let Some(opcode) = reader.u8() {
+        if fallback.rewrite(opcode, &mut reader).is_none() {
+            break;
+        }
+    }
+    fallback.out.get_bytes()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.take(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn point(&mut self) -> Option<(f32, f32)> {
+        Some((self.f32()?, self.f32()?))
+    }
+
+    // u16 length-prefixed string
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u16()? as usize;
+        std::str::from_utf8(self.take(len)?).ok()
+    }
+
+    fn since(&self, start: usize) -> &'a [u8] {
+        &self.data[start..self.pos]
+    }
+}
+
+// Canvas-style 2D matrix: x' = a*x + c*y + e, y' = b*x + d*y + f
+#[derive(Clone, Copy, PartialEq)]
+struct Affine([f32; 6]);
+
+impl Affine {
+    const IDENTITY: Affine = Affine([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn translate(x: f32, y: f32) -> Self {
+        Affine([1.0, 0.0, 0.0, 1.0, x, y])
+    }
+
+    fn rotate(angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Affine([cos, sin, -sin, cos, 0.0, 0.0])
+    }
+
+    fn scale(sx: f32, sy: f32) -> Self {
+        Affine([sx, 0.0, 0.0, sy, 0.0, 0.0])
+    }
+
+    // `other` applied first, like ctx.translate()/rotate()/scale()
+    fn then(self, other: Affine) -> Self {
+        let [a, b, c, d, e, f] = self.0;
+        let [oa, ob, oc, od, oe, of] = other.0;
+        Affine([
+            a * oa + c * ob,
+            b * oa + d * ob,
+            a * oc + c * od,
+            b * oc + d * od,
+            a * oe + c * of + e,
+            b * oe + d * of + f,
+        ])
+    }
+
+    fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d, e, f] = self.0;
+        (a * x + c * y + e, b * x + d * y + f)
+    }
+
+    // Average scale, for line widths
+    fn scale_factor(&self) -> f32 {
+        let [a, b, c, d, ..] = self.0;
+        (a * d - b * c).abs().sqrt()
+    }
+}
+
+// Fallback::outline: an edge's two ends as drawn, and how many triangles share it
+type OutlineEdge = ((f32, f32), (f32, f32), u32);
+
+struct Fallback {
+    out: CommandBuffer,
+    caps: u32,
+    transform: Affine, // Emulated transform (identity for clients with CAP_TRANSFORMS)
+    stack: Vec<Affine>,
+    palette: Vec<[u8; 4]>, // For OP_PALETTE_COLOR without CAP_PALETTES
+}
+
+impl Fallback {
+    // CAPS_LEGACY: always
+    fn has(&self, cap: u32) -> bool {
+        self.caps & cap == cap
+    }
+
+    // Drawing commands are copied as-is only when the client knows them and needs no
+    // emulated transform
+    fn native(&self, cap: u32) -> bool {
+        self.has(cap) && self.transform == Affine::IDENTITY
+    }
+
+    /// Copies or replaces one command; None when it can't be decoded.
+    fn rewrite(&mut self, opcode: u8, r: &mut Reader) -> Option<()> {
+        let start = r.pos - 1;
+        match opcode {
+            OP_CLEAR => { r.take(3)?; }
+            OP_SET_COLOR => { r.take(4)?; }
+            OP_DEFINE_PALETTE => {
+                let count = r.u16()? as usize;
+                let colors = r.take(count * 4)?;
+                if !self.has(CAP_PALETTES) {
+                    self.palette = colors.chunks_exact(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+                    return Some(());
+                }
+            }
+            OP_PALETTE_COLOR => {
+                let index = r.u8()? as usize;
+                if !self.has(CAP_PALETTES) {
+                    if let Some(&[red, green, blue, alpha]) = self.palette.get(index) {
+                        self.out.cmd_set_color(red, green, blue, alpha);
+                    }
+                    return Some(());
+                }
+            }
+            OP_LOAD_SOUND => { r.str()?; r.str()?; }
+            OP_PLAY_SOUND => {
+                let (name, looping, volume, pitch) = (r.str()?, r.u8()?, r.f32()?, r.f32()?);
+                if !self.has(CAP_PITCH) {
+                    self.play_sound(name, looping == 1, volume, pitch);
+                    return Some(());
+                }
+            }
+            OP_SET_PITCH => {
+                r.str()?;
+                r.f32()?;
+                if !self.has(CAP_PITCH) { return Some(()); }
+            }
+            OP_SET_BUS_VOLUME => {
+                r.str()?;
+                r.take(2 * 4)?;
+                if !self.has(CAP_BUSES) { return Some(()); }
+            }
+            OP_SET_SOUND_BUS => {
+                r.str()?;
+                r.str()?;
+                if !self.has(CAP_BUSES) { return Some(()); }
+            }
+            OP_PLAY_SOUND_PANNED => {
+                let (name, looping, volume) = (r.str()?, r.u8()?, r.f32()?);
+                r.f32()?;
+                if !self.has(CAP_PANNING) {
+                    self.play_sound(name, looping == 1, volume, 1.0);
+                    return Some(());
+                }
+            }
+            OP_STOP_SOUND => { r.str()?; }
+            // Without streaming, music is a looping sound
+            OP_LOAD_MUSIC => {
+                let (name, url) = (r.str()?, r.str()?);
+                if !self.has(CAP_MUSIC) {
+                    self.out.cmd_load_sound(name, url);
+                    return Some(());
+                }
+            }
+            OP_PLAY_MUSIC => {
+                let (name, looping, volume) = (r.str()?, r.u8()?, r.f32()?);
+                r.f32()?;
+                if !self.has(CAP_MUSIC) {
+                    self.play_sound(name, looping == 1, volume, 1.0);
+                    return Some(());
+                }
+            }
+            OP_STOP_MUSIC => {
+                let name = r.str()?;
+                r.f32()?;
+                if !self.has(CAP_MUSIC) {
+                    self.out.cmd_stop_sound(name);
+                    return Some(());
+                }
+            }
+            OP_SET_VOLUME => { r.str()?; r.f32()?; }
+            OP_FILL_RECT => {
+                let (x, y, w, h) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.native(CAPS_LEGACY) {
+                    self.rect(&[(x, y), (x + w, y), (x + w, y + h), (x, y + h)]);
+                    return Some(());
+                }
+            }
+            OP_DRAW_LINE => {
+                let (p1, p2, width) = (r.point()?, r.point()?, r.f32()?);
+                if !self.native(CAPS_LEGACY) {
+                    self.polyline(&[p1, p2], false, width);
+                    return Some(());
+                }
+            }
+            OP_DRAW_TEXT => {
+                let p = r.point()?;
+                let text = r.str()?;
+                if !self.native(CAPS_LEGACY) {
+                    let (x, y) = self.transform.apply(p);
+                    self.out.cmd_draw_text(text, x, y);
+                    return Some(());
+                }
+            }
+            OP_LOAD_IMAGE => {
+                r.str()?;
+                r.str()?;
+                if !self.has(CAP_IMAGES) { return Some(()); }
+            }
+            OP_DRAW_SPRITE => {
+                r.str()?;
+                let (x, y, w, h, rotation) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.native(CAP_IMAGES) {
+                    self.sprite_rect(x, y, w, h, rotation);
+                    return Some(());
+                }
+            }
+            OP_LOAD_SPRITESHEET => {
+                r.u16()?;
+                r.str()?;
+                r.take(2 * 2)?;
+                if !self.has(CAP_SPRITESHEETS) { return Some(()); }
+            }
+            OP_DRAW_FRAME => {
+                r.take(2 * 2)?;
+                let (x, y, w, h, rotation) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.native(CAP_SPRITESHEETS) {
+                    self.sprite_rect(x, y, w, h, rotation);
+                    return Some(());
+                }
+            }
+            OP_FILL_CIRCLE | OP_FILL_ELLIPSE => {
+                let (x, y, rx) = (r.f32()?, r.f32()?, r.f32()?);
+                let ry = if opcode == OP_FILL_ELLIPSE { r.f32()? } else { rx };
+                if !self.native(CAP_ELLIPSES) {
+                    let (rx, ry) = (rx.abs(), ry.abs());
+                    self.rect(&[(x - rx, y - ry), (x + rx, y - ry), (x + rx, y + ry), (x - rx, y + ry)]);
+                    return Some(());
+                }
+            }
+            OP_DRAW_CIRCLE | OP_DRAW_ELLIPSE => {
+                let (x, y, rx) = (r.f32()?, r.f32()?, r.f32()?);
+                let ry = if opcode == OP_DRAW_ELLIPSE { r.f32()? } else { rx };
+                let width = r.f32()?;
+                if !self.native(CAP_ELLIPSES) {
+                    self.polyline(&arc_points(x, y, rx.abs(), ry.abs(), 0.0, TAU), true, width);
+                    return Some(());
+                }
+            }
+            OP_FILL_ARC | OP_DRAW_ARC => {
+                let (x, y, radius, from, to, extra) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.native(CAP_ARCS) {
+                    let radius = radius.max(0.0);
+                    let mut points = arc_points(x, y, radius, radius, from, to);
+                    if opcode == OP_DRAW_ARC {
+                        self.polyline(&points, false, extra);
+                    } else {
+                        // Filled slices and ring segments are outlined
+                        if extra > 0.0 {
+                            points.extend(arc_points(x, y, extra, extra, to, from));
+                        } else {
+                            points.insert(0, (x, y));
+                        }
+                        self.polyline(&points, true, 1.0);
+                    }
+                    return Some(());
+                }
+            }
+            OP_SET_CONTROL_SCHEME => {
+                r.str()?;
+                if !self.has(CAP_CONTROL_SCHEMES) { return Some(()); }
+            }
+            OP_ACTION_KEYS => {
+                for _ in 0..r.u8()? {
+                    r.str()?;
+                    let keys = r.u8()? as usize;
+                    r.take(keys)?;
+                }
+                if !self.has(CAP_CONTROL_SCHEMES) { return Some(()); }
+            }
+            OP_INPUT_CONFIG => {
+                r.take(1 + 2 * 4)?;
+                if !self.has(CAP_CONTROL_SCHEMES) { return Some(()); }
+            }
+            OP_DRAW_POLY => {
+                let fill = r.u8()? == 0;
+                let width = r.f32()?;
+                let count = r.u16()?;
+                let points = (0..count).map(|_| r.point()).collect::<Option<Vec<_>>>()?;
+                if !self.native(CAP_POLYGONS) {
+                    self.polyline(&points, true, if fill { 1.0 } else { width });
+                    return Some(());
+                }
+            }
+            OP_DRAW_POLYLINE => {
+                let width = r.f32()?;
+                let count = r.u16()?;
+                let points = (0..count).map(|_| r.point()).collect::<Option<Vec<_>>>()?;
+                if !self.native(CAP_POLYLINES) {
+                    self.polyline(&points, false, width);
+                    return Some(());
+                }
+            }
+            OP_FILL_TRIANGLES => {
+                let count = r.u16()? as usize;
+                let points = (0..count * 3).map(|_| r.point()).collect::<Option<Vec<_>>>()?;
+                if !self.native(CAP_TRIANGLES) {
+                    if self.native(CAP_POLYGONS) {
+                        for triangle in points.chunks_exact(3) {
+                            self.out.cmd_draw_poly(triangle, true, 0.0);
+                        }
+                    } else {
+                        self.outline(&points);
+                    }
+                    return Some(());
+                }
+            }
+            OP_SET_TRANSFORM => {
+                let (x, y, zoom, rotation) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.has(CAP_TRANSFORMS) {
+                    // World (x, y) at the screen center, as the client does it
+                    self.transform = Affine::translate(SCREEN_W / 2.0, SCREEN_H / 2.0)
+                        .then(Affine::rotate(-rotation))
+                        .then(Affine::scale(zoom, zoom))
+                        .then(Affine::translate(-x, -y));
+                    return Some(());
+                }
+            }
+            OP_PUSH_TRANSFORM => {
+                let (tx, ty, rotation, sx, sy) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                if !self.has(CAP_TRANSFORMS) {
+                    self.stack.push(self.transform);
+                    self.transform = self.transform
+                        .then(Affine::translate(tx, ty))
+                        .then(Affine::rotate(rotation))
+                        .then(Affine::scale(sx, sy));
+                    return Some(());
+                }
+            }
+            OP_POP_TRANSFORM => {
+                if !self.has(CAP_TRANSFORMS) {
+                    if let Some(previous) = self.stack.pop() {
+                        self.transform = previous;
+                    }
+                    return Some(());
+                }
+            }
+            OP_REQUEST_TEXT_INPUT => {
+                r.str()?;
+                r.u16()?;
+                if !self.has(CAP_TEXT_INPUT) { return Some(()); }
+            }
+            OP_SET_TEXT_SCALE => {
+                r.f32()?;
+                if !self.has(CAP_TEXT_SCALE) { return Some(()); }
+            }
+            OP_SCREEN_SHAKE => {
+                r.take(2 * 4)?;
+                if !self.has(CAP_EFFECTS) { return Some(()); }
+            }
+            OP_FLASH => {
+                r.take(3 + 4)?;
+                if !self.has(CAP_EFFECTS) { return Some(()); }
+            }
+            OP_SET_POST => {
+                r.take(4 + 3 * 4 + 1)?;
+                if !self.has(CAP_POST) { return Some(()); }
+            }
+            OP_VIGNETTE => {
+                r.f32()?;
+                if !self.has(CAP_EFFECTS) { return Some(()); }
+            }
+            OP_SET_LAYER => {
+                r.u8()?;
+                if !self.has(CAP_LAYERS) { return Some(()); }
+            }
+            OP_DEV_STATUS => {
+                r.u8()?;
+                r.str()?;
+                if !self.has(CAP_DEV_STATUS) { return Some(()); }
+            }
+            OP_SET_GRADIENT => {
+                r.take(1 + 6 * 4)?;
+                let count = r.u8()? as usize;
+                let stops = r.take(count * 8)?;
+                if !self.native(CAP_GRADIENTS) {
+                    if let &[_, _, _, _, red, green, blue, alpha, ..] = stops {
+                        self.out.cmd_set_color(red, green, blue, alpha);
+                    }
+                    return Some(());
+                }
+            }
+            OP_SET_BLEND_MODE => {
+                r.u8()?;
+                if !self.has(CAP_GRADIENTS) { return Some(()); }
+            }
+            OP_DRAW_PARTICLES => {
+                r.u8()?;
+                let count = r.u16()? as usize;
+                r.take(count * 14)?;
+                if !self.native(CAP_PARTICLES) { return Some(()); }
+            }
+            OP_LIGHTS => {
+                r.take(3)?;
+                for _ in 0..r.u16()? {
+                    r.take(3 * 4 + 4)?;
+                    let points = r.u16()? as usize;
+                    r.take(points * 8)?;
+                }
+                if !self.native(CAP_LIGHTS) { return Some(()); }
+            }
+            OP_DRAW_TRAILS => {
+                for _ in 0..r.u16()? {
+                    r.take(4 + 4)?;
+                    let points = r.u16()? as usize;
+                    r.take(points * 8)?;
+                }
+                if !self.native(CAP_TRAILS) { return Some(()); }
+            }
+            OP_SETTINGS => {
+                for _ in 0..r.u8()? {
+                    r.str()?;
+                    r.str()?;
+                    match r.u8()? {
+                        0 => { r.take(4 * 4)?; }
+                        1 => { r.u8()?; }
+                        2 => {
+                            for _ in 0..r.u8()? {
+                                r.str()?;
+                            }
+                            r.u8()?;
+                        }
+                        _ => return None,
+                    }
+                }
+                if !self.has(CAP_SETTINGS) { return Some(()); }
+            }
+            OP_TILEMAP_CHUNK => {
+                r.take(10)?;
+                let (w, h) = (r.u8()? as usize, r.u8()? as usize);
+                r.take(w * h * 2)?;
+                if !self.has(CAP_TILEMAPS) { return Some(()); }
+            }
+            OP_DRAW_TILEMAP => {
+                r.u16()?;
+                r.str()?;
+                r.take(2 + 3 * 4)?;
+                if !self.native(CAP_TILEMAPS) { return Some(()); }
+            }
+            OP_DRAW_CURVE => {
+                let cubic = r.u8()? == 1;
+                let closed = r.u8()? == 1;
+                let width = r.f32()?;
+                let first = r.point()?;
+                let count = r.u16()? as usize;
+                let per_segment = if cubic { 3 } else { 2 };
+                let points = (0..count * per_segment).map(|_| r.point()).collect::<Option<Vec<_>>>()?;
+                if !self.native(CAP_CURVES) {
+                    self.polyline(&flatten_curve(first, &points, per_segment), closed, width);
+                    return Some(());
+                }
+            }
+            OP_NODE_CREATE | OP_NODE_UPDATE => {
+                r.u32()?;
+                skip_node_fields(r)?;
+                if !self.has(CAP_SCENE) { return Some(()); }
+            }
+            OP_NODE_DESTROY => {
+                r.u32()?;
+                if !self.has(CAP_SCENE) { return Some(()); }
+            }
+            OP_NODE_SYNC => {
+                let count = r.u32()? as usize;
+                r.take(count.checked_mul(4)?)?;
+                if !self.has(CAP_SCENE) { return Some(()); }
+            }
+            OP_SCENE_UPDATE => {
+                let count = r.u16()? as usize;
+                r.take(count * 16)?;
+                if !self.has(CAP_SCENE | CAP_SCENE_UPDATES) { return Some(()); }
+            }
+            OP_DRAW_NINEPATCH => {
+                r.str()?;
+                let (x, y, w, h) = (r.f32()?, r.f32()?, r.f32()?, r.f32()?);
+                r.take(4 * 4)?;
+                if !self.native(CAP_NINEPATCH) {
+                    self.rect(&[(x, y), (x + w, y), (x + w, y + h), (x, y + h)]);
+                    return Some(());
+                }
+            }
+            OP_CANVAS_CONTENT => {
+                let name = r.str()?;
+                let (w, h) = (r.u16()?, r.u16()?);
+                let len = r.u32()? as usize;
+                let content = r.take(len)?;
+                if self.has(CAP_CANVASES) {
+                    // The recording is drawn by the same client
+                    let content = downgrade_with_palette(content, self.caps, &self.palette);
+                    let mut header = Vec::with_capacity(name.len() + 11);
+                    header.push(OP_CANVAS_CONTENT);
+                    header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                    header.extend_from_slice(name.as_bytes());
+                    header.extend_from_slice(&w.to_le_bytes());
+                    header.extend_from_slice(&h.to_le_bytes());
+                    header.extend_from_slice(&(content.len() as u32).to_le_bytes());
+                    self.out.cmd_raw(&header);
+                    self.out.cmd_raw(&content);
+                }
+                return Some(());
+            }
+            OP_DRAW_CANVAS => {
+                r.str()?;
+                r.take(4 * 4)?;
+                if !self.native(CAP_CANVASES) { return Some(()); }
+            }
+            OP_DRAW_SCENE => {
+                if !self.native(CAP_SCENE) { return Some(()); }
+            }
+            op if op >= OP_PLUGIN_FIRST => {
+                let len = r.u16()? as usize;
+                r.take(len)?;
+                if !self.has(CAP_PLUGINS) { return Some(()); }
+            }
+            _ => return None,
+        }
+        self.out.cmd_raw(r.since(start));
+        Some(())
+    }
+
+    // Images drawn at x, y, w, h, rotated around their center
+    fn sprite_rect(&self, x: f32, y: f32, w: f32, h: f32, rotation: f32) {
+        let around = Affine::translate(x + w / 2.0, y + h / 2.0).then(Affine::rotate(rotation));
+        let (hw, hh) = (w / 2.0, h / 2.0);
+        let corners = [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)].map(|p| around.apply(p));
+        self.rect(&corners);
+    }
+
+    // Axis-aligned bounds of the transformed corners
+    fn rect(&self, corners: &[(f32, f32)]) {
+        let points: Vec<_> = corners.iter().map(|&p| self.transform.apply(p)).collect();
+        let (min_x, max_x) = points.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+        let (min_y, max_y) = points.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+        self.out.cmd_fill_rect(min_x, min_y, max_x - min_x, max_y - min_y);
+    }
+
+    // The edges of a triangle list that only one triangle has: the polygon's outline
+    fn outline(&self, points: &[(f32, f32)]) {
+        let key = |p: (f32, f32)| (p.0.to_bits(), p.1.to_bits());
+        let mut edges: BTreeMap<_, OutlineEdge> = BTreeMap::new();
+        for t in points.chunks_exact(3) {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                let (ka, kb) = (key(a), key(b));
+                edges.entry(if ka < kb { (ka, kb) } else { (kb, ka) }).or_insert((a, b, 0)).2 += 1;
+            }
+        }
+        for &(a, b, count) in edges.values() {
+            if count == 1 {
+                self.polyline(&[a, b], false, 1.0);
+            }
+        }
+    }
+
+    // OP_PLAY_SOUND without the pitch for clients that don't know it
+    fn play_sound(&self, name: &str, looping: bool, volume: f32, pitch: f32) {
+        if self.has(CAP_PITCH) {
+            self.out.cmd_play_sound(name, looping, volume, pitch);
+        } else {
+            let mut cmd = vec![OP_PLAY_SOUND];
+            cmd.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            cmd.extend_from_slice(name.as_bytes());
+            cmd.push(looping as u8);
+            cmd.extend_from_slice(&volume.to_le_bytes());
+            self.out.cmd_raw(&cmd);
+        }
+    }
+
+    fn polyline(&self, points: &[(f32, f32)], closed: bool, width: f32) {
+        let points: Vec<_> = points.iter().map(|&p| self.transform.apply(p)).collect();
+        let width = width * self.transform.scale_factor();
+        for pair in points.windows(2) {
+            self.out.cmd_draw_line(pair[0].0, pair[0].1, pair[1].0, pair[1].1, width);
+        }
+        if closed && points.len() > 2 {
+            let (first, last) = (points[0], points[points.len() - 1]);
+            self.out.cmd_draw_line(last.0, last.1, first.0, first.1, width);
+        }
+    }
+}
+
+// Points along an elliptical arc, both ends included
+fn arc_points(x: f32, y: f32, rx: f32, ry: f32, from: f32, to: f32) -> Vec<(f32, f32)> {
+    let steps = ((to - from).abs() / TAU * CIRCLE_SEGMENTS).ceil().clamp(1.0, CIRCLE_SEGMENTS) as usize;
+    (0..=steps).map(|i| {
+        let angle = from + (to - from) * i as f32 / steps as f32;
+        (x + rx * angle.cos(), y + ry * angle.sin())
+    }).collect()
+}
+
+// Bezier segments (control point(s) then end point) as a polyline
+fn flatten_curve(start: (f32, f32), points: &[(f32, f32)], per_segment: usize) -> Vec<(f32, f32)> {
+    let mut line = vec![start];
+    let mut from = start;
+    for segment in points.chunks_exact(per_segment) {
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let u = 1.0 - t;
+            let point = match *segment {
+                [c, end] => (
+                    u * u * from.0 + 2.0 * u * t * c.0 + t * t * end.0,
+                    u * u * from.1 + 2.0 * u * t * c.1 + t * t * end.1,
+                ),
+                [c1, c2, end] => (
+                    u * u * u * from.0 + 3.0 * u * u * t * c1.0 + 3.0 * u * t * t * c2.0 + t * t * t * end.0,
+                    u * u * u * from.1 + 3.0 * u * u * t * c1.1 + 3.0 * u * t * t * c2.1 + t * t * t * end.1,
+                ),
+                _ => unreachable!(),
+            };
+            line.push(point);
+        }
+        from = segment[per_segment - 1];
+    }
+    line
+}
+
+// Node fields in mask order (see CommandBuffer::cmd_node)
+fn skip_node_fields(r: &mut Reader) -> Option<()> {
+    let mask = r.u8()?;
+    if mask & scene::FIELD_TRANSFORM != 0 {
+        r.take(16)?;
+    }
+    if mask & scene::FIELD_SHAPE != 0 {
+        match r.u8()? {
+            0 => {}
+            1 => { r.take(8)?; }
+            2 => { r.take(4)?; }
+            3 => { r.str()?; r.take(8)?; }
+            4 => { r.str()?; }
+            _ => return None,
+        }
+    }
+    if mask & scene::FIELD_COLOR != 0 {
+        r.take(4)?;
+    }
+    if mask & scene::FIELD_Z != 0 {
+        r.take(1)?;
+    }
+    if mask & scene::FIELD_VISIBLE != 0 {
+        r.take(1)?;
+    }
+    if mask & scene::FIELD_PARENT != 0 {
+        r.take(4)?;
+    }
+    Some(())
+}
@@ -0,0 +1,47 @@
+use thiserror::Error;
+
+// --- Engine Errors ---
+// What GameState methods return, so embedders can tell a script bug (show it, keep
+// running) from a game that can't be loaded or restored (rebuild it) and from a client
+// sending what no client should (disconnect it).
+
+#[derive(Debug, Error)]
+pub enum EngineError {
+    /// The script (or a module, or a plugin) could not be loaded: syntax and type errors,
+    /// errors in top-level code or init(), missing files.
+    #[error("{0}")]
+    LuaInit(String),
+    /// A script callback failed. `traceback` is Lua's stack traceback, when there is one.
+    #[error("{message}{}", .traceback.as_ref().map(|t| format!("\n{}", t)).unwrap_or_default())]
+    LuaRuntime { message: String, traceback: Option<String> },
+    /// The script's state could not be saved or restored (snapshot()/restore(state)).
+    #[error("{0}")]
+    Snapshot(String),
+    /// Client input no client should send.
+    #[error("{0}")]
+    Protocol(String),
+}
+
+impl EngineError {
+    pub(crate) fn snapshot(e: impl std::fmt::Display) -> Self {
+        EngineError::Snapshot(e.to_string())
+    }
+
+    pub(crate) fn init(e: impl std::fmt::Display) -> Self {
+        EngineError::LuaInit(e.to_string())
+    }
+}
+
+impl From<mlua::Error> for EngineError {
+    fn from(e: mlua::Error) -> Self {
+        // mlua appends the traceback to the message
+        let text = e.to_string();
+        match text.split_once("\nstack traceback:") {
+            Some((message, traceback)) => EngineError::LuaRuntime {
+                message: message.to_string(),
+                traceback: Some(format!("stack traceback:{}", traceback)),
+            },
+            None => EngineError::LuaRuntime { message: text, traceback: None },
+        }
+    }
+}
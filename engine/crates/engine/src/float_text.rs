@@ -0,0 +1,75 @@
+// --- Floating Text ---
+// api.float_text(text, x, y, opts): short-lived labels in the world (damage numbers, "+50")
+// that rise and fade out over their duration. They age after every update(dt) and
+// api.draw_float_texts() draws those in the session's view with the plain text commands.
+
+/// Width of a character of the client's text font (14px monospace).
+pub const CHAR_WIDTH: f32 = 8.4;
+pub const LINE_HEIGHT: f32 = 14.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FloatTextConfig {
+    pub duration: f32, // Seconds
+    pub rise: f32,     // Distance travelled upwards over the duration
+    pub color: [u8; 4],
+}
+
+impl Default for FloatTextConfig {
+    fn default() -> Self {
+        Self { duration: 1.0, rise: 30.0, color: [255, 255, 255, 255] }
+    }
+}
+
+struct FloatText {
+    text: String,
+    x: f32,
+    y: f32,
+    config: FloatTextConfig,
+    age: f32,
+}
+
+/// A label ready to draw: left edge of the text, faded color.
+pub struct FloatTextSprite<'a> {
+    pub text: &'a str,
+    pub x: f32,
+    pub y: f32,
+    pub rgba: [u8; 4],
+}
+
+#[derive(Default)]
+pub struct FloatTexts {
+    texts: Vec<FloatText>,
+}
+
+impl FloatTexts {
+    pub fn add(&mut self, text: String, x: f32, y: f32, config: FloatTextConfig) {
+        self.texts.push(FloatText { text, x, y, config, age: 0.0 });
+    }
+
+    pub fn clear(&mut self) {
+        self.texts.clear();
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        for t in &mut self.texts {
+            t.age += dt;
+        }
+        self.texts.retain(|t| t.age < t.config.duration);
+    }
+
+    /// Labels centered on their position, easing upwards and fading out over their second
+    /// half, that `visible` keeps (given their bounds).
+    pub fn frame(&self, visible: impl Fn(f32, f32, f32, f32) -> bool) -> Vec<FloatTextSprite<'_>> {
+        self.texts.iter().filter_map(|t| {
+            let progress = (t.age / t.config.duration).clamp(0.0, 1.0);
+            let y = t.y - t.config.rise * (1.0 - (1.0 - progress) * (1.0 - progress));
+            let half_width = t.text.chars().count() as f32 * CHAR_WIDTH / 2.0;
+            if !visible(t.x - half_width, y - LINE_HEIGHT / 2.0, t.x + half_width, y + LINE_HEIGHT / 2.0) {
+                return None;
+            }
+            let mut rgba = t.config.color;
+            rgba[3] = (rgba[3] as f32 * (2.0 * (1.0 - progress)).min(1.0)).round() as u8;
+            Some(FloatTextSprite { text: &t.text, x: t.x - half_width, y, rgba })
+        }).collect()
+    }
+}
@@ -0,0 +1,74 @@
+// --- Geometry ---
+// Small 2D routines shared by the spatial DB, physics and api.geom, so scripts get the
+// same answers the engine uses for queries and collisions.
+
+pub type Vec2 = (f32, f32);
+
+pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+pub fn length(v: Vec2) -> f32 {
+    (v.0 * v.0 + v.1 * v.1).sqrt()
+}
+
+/// Unit vector in the direction of `v`; (0, 0) stays (0, 0).
+pub fn normalize(v: Vec2) -> Vec2 {
+    let len = length(v);
+    if len > 0.0 { (v.0 / len, v.1 / len) } else { (0.0, 0.0) }
+}
+
+pub fn dot(a: Vec2, b: Vec2) -> f32 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+/// z of the 3D cross product: > 0 when `b` turns counter-clockwise from `a` (in y-up coordinates).
+pub fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+pub fn rotate(v: Vec2, angle: f32) -> Vec2 {
+    let (sin, cos) = angle.sin_cos();
+    (v.0 * cos - v.1 * sin, v.0 * sin + v.1 * cos)
+}
+
+/// `v` bounced off a surface with normal `n` (any length).
+pub fn reflect(v: Vec2, n: Vec2) -> Vec2 {
+    let n = normalize(n);
+    let d = 2.0 * dot(v, n);
+    (v.0 - d * n.0, v.1 - d * n.1)
+}
+
+/// Point of segment a-b closest to `p`, and its position along the segment (0 at a, 1 at b).
+pub fn closest_point_on_segment(p: Vec2, a: Vec2, b: Vec2) -> (Vec2, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq > 0.0 { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+    ((a.0 + t * dx, a.1 + t * dy), t)
+}
+
+/// Where segments a1-a2 and b1-b2 cross, as fractions along each (t on a, u on b).
+/// None when they don't touch or are parallel.
+pub fn segment_intersection(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> Option<(f32, f32)> {
+    let den = (a1.0 - a2.0) * (b1.1 - b2.1) - (a1.1 - a2.1) * (b1.0 - b2.0);
+    if den == 0.0 {
+        return None;
+    }
+    let t = ((a1.0 - b1.0) * (b1.1 - b2.1) - (a1.1 - b1.1) * (b1.0 - b2.0)) / den;
+    let u = -((a1.0 - a2.0) * (a1.1 - b1.1) - (a1.1 - a2.1) * (a1.0 - b1.0)) / den;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some((t, u))
+}
+
+/// Even-odd rule: holes from self-intersections count as outside.
+pub fn point_in_polygon(p: Vec2, polygon: &[Vec2]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, &a) in polygon.iter().enumerate() {
+        let b = polygon[j];
+        if (a.1 > p.1) != (b.1 > p.1) && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0 {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
@@ -1,5 +1,16 @@
-use std::collections::{HashMap, BinaryHeap};
+use std::collections::{HashMap, HashSet, BinaryHeap, VecDeque};
 use std::cmp::Ordering;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GraphError {
+    #[error("nav node {0} does not exist")]
+    MissingNode(u64),
+    #[error("nav cost must be a positive number (got {0})")]
+    InvalidCost(f32),
+    #[error("nav edge {0} -> {1} leads to a missing node")]
+    DanglingEdge(u64, u64),
+}
 
 // --- Estruturas para A* ---
 
@@ -29,6 +40,7 @@ struct Node {
     x: f32,
     y: f32,
     edges: Vec<u64>, // IDs dos vizinhos
+    cost: f32, // Multiplicador para entrar no nó (1.0 = normal, >1 lama, <1 estrada)
 }
 
 pub struct Graph {
@@ -48,47 +60,133 @@ impl Graph {
             x,
             y,
             edges: Vec::new(),
+            cost: 1.0,
         });
     }
 
-    pub fn add_edge(&mut self, from: u64, to: u64) {
+    pub fn add_edge(&mut self, from: u64, to: u64) -> Result<(), GraphError> {
         // Grafo não-direcionado ou direcionado? O Lua manda A->B e B->A geralmente.
         // Vamos assumir direcionado conforme a chamada. O Lua chama add_edge(u, v) e add_edge(v, u).
-        if let Some(node) = self.nodes.get_mut(&from) {
-            // Evita duplicatas
-            if !node.edges.contains(&to) {
-                node.edges.push(to);
+        // Os dois nós precisam existir: uma aresta pendurada quebraria o A* depois.
+        if !self.nodes.contains_key(&to) {
+            return Err(GraphError::MissingNode(to));
+        }
+        let node = self.nodes.get_mut(&from).ok_or(GraphError::MissingNode(from))?;
+        // Evita duplicatas
+        if !node.edges.contains(&to) {
+            node.edges.push(to);
+        }
+        Ok(())
+    }
+
+    pub fn set_cost(&mut self, id: u64, cost: f32) -> Result<(), GraphError> {
+        if !(cost > 0.0 && cost.is_finite()) {
+            return Err(GraphError::InvalidCost(cost));
+        }
+        let node = self.nodes.get_mut(&id).ok_or(GraphError::MissingNode(id))?;
+        node.cost = cost;
+        Ok(())
+    }
+
+    pub fn get_cost(&self, id: u64) -> Option<f32> {
+        self.nodes.get(&id).map(|n| n.cost)
+    }
+
+    // --- Consulta / Debug ---
+
+    pub fn get_node(&self, id: u64) -> Option<(f32, f32)> {
+        self.nodes.get(&id).map(|n| (n.x, n.y))
+    }
+
+    pub fn neighbors(&self, id: u64) -> Option<&[u64]> {
+        self.nodes.get(&id).map(|n| n.edges.as_slice())
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // BFS com saída antecipada: só responde se existe caminho, sem custo de A*
+    pub fn has_path(&self, start: u64, goal: u64) -> bool {
+        if !self.nodes.contains_key(&start) || !self.nodes.contains_key(&goal) {
+            return false;
+        }
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(start);
+        queue.push_back(start);
+        while let Some(current) = queue.pop_front() {
+            if current == goal {
+                return true;
+            }
+            if let Some(node) = self.nodes.get(&current) {
+                for &next in &node.edges {
+                    if visited.insert(next) {
+                        queue.push_back(next);
+                    }
+                }
             }
         }
+        false
     }
 
-    fn heuristic(&self, a: u64, b: u64) -> f32 {
-        let n1 = &self.nodes[&a];
-        let n2 = &self.nodes[&b];
+    // Todas as arestas como segmentos (x1, y1, x2, y2), em ordem de id para desenho estável
+    pub fn edge_segments(&self) -> Vec<(f32, f32, f32, f32)> {
+        let mut ids: Vec<&u64> = self.nodes.keys().collect();
+        ids.sort();
+        let mut segs = Vec::new();
+        for id in ids {
+            let n = &self.nodes[id];
+            for to in &n.edges {
+                if let Some(m) = self.nodes.get(to) {
+                    segs.push((n.x, n.y, m.x, m.y));
+                }
+            }
+        }
+        segs
+    }
+
+    pub fn node_positions(&self) -> Vec<(f32, f32)> {
+        let mut ids: Vec<&u64> = self.nodes.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| (self.nodes[id].x, self.nodes[id].y)).collect()
+    }
+
+    // None se algum dos nós não existe (aresta pendurada)
+    fn heuristic(&self, a: u64, b: u64) -> Option<f32> {
+        let n1 = self.nodes.get(&a)?;
+        let n2 = self.nodes.get(&b)?;
         let dx = n1.x - n2.x;
         let dy = n1.y - n2.y;
-        (dx*dx + dy*dy).sqrt()
+        Some((dx*dx + dy*dy).sqrt())
     }
 
-    fn dist(&self, a: u64, b: u64) -> f32 {
-        // Custo real da aresta (Distância Euclidiana)
-        // Assumindo que se tem aresta, calcula direto.
-        self.heuristic(a, b)
+    fn dist(&self, a: u64, b: u64) -> Option<f32> {
+        // Custo real da aresta: distância Euclidiana x custo do nó de destino
+        Some(self.heuristic(a, b)? * self.nodes.get(&b)?.cost)
     }
 
-    pub fn find_path(&self, start: u64, goal: u64) -> Option<Vec<u64>> {
-        if !self.nodes.contains_key(&start) || !self.nodes.contains_key(&goal) {
-            return None;
+    // Err se start/goal não existem; Ok(None) se não há caminho
+    pub fn find_path(&self, start: u64, goal: u64) -> Result<Option<Vec<u64>>, GraphError> {
+        if !self.nodes.contains_key(&start) {
+            return Err(GraphError::MissingNode(start));
+        }
+        if !self.nodes.contains_key(&goal) {
+            return Err(GraphError::MissingNode(goal));
         }
 
         let mut open_set = BinaryHeap::new();
         let mut came_from: HashMap<u64, u64> = HashMap::new();
         let mut g_score: HashMap<u64, f32> = HashMap::new();
 
+        // A heurística escala pelo menor custo do grafo para continuar admissível
+        // (com estradas < 1.0 a distância pura superestimaria o custo restante)
+        let min_cost = self.nodes.values().map(|n| n.cost).fold(1.0f32, f32::min);
+
         // Init
         g_score.insert(start, 0.0);
         open_set.push(State {
-            f_score: self.heuristic(start, goal),
+            f_score: self.heuristic(start, goal).unwrap_or(0.0) * min_cost,
             node_id: start,
         });
 
@@ -103,18 +201,22 @@ impl Graph {
                     curr = prev;
                 }
                 path.reverse();
-                return Some(path);
+                return Ok(Some(path));
             }
 
             // Para cada vizinho
             if let Some(node) = self.nodes.get(&current) {
                 for &neighbor in &node.edges {
-                    let tentative_g = g_score[&current] + self.dist(current, neighbor);
+                    let (Some(edge_cost), Some(h)) = (self.dist(current, neighbor), self.heuristic(neighbor, goal)) else {
+                        return Err(GraphError::DanglingEdge(current, neighbor));
+                    };
+                    let h = h * min_cost;
+                    let tentative_g = g_score[&current] + edge_cost;
                     
                     if tentative_g < *g_score.get(&neighbor).unwrap_or(&f32::INFINITY) {
                         came_from.insert(neighbor, current);
                         g_score.insert(neighbor, tentative_g);
-                        let f = tentative_g + self.heuristic(neighbor, goal);
+                        let f = tentative_g + h;
                         open_set.push(State {
                             f_score: f,
                             node_id: neighbor,
@@ -124,6 +226,6 @@ impl Graph {
             }
         }
 
-        None // Caminho não encontrado
+        Ok(None) // Caminho não encontrado
     }
 }
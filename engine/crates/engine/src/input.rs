@@ -0,0 +1,378 @@
+use std::collections::{HashMap, HashSet};
+
+// --- Client Input ---
+// Binary input messages (DataChannel, or WebSocket fallback):
+//   2 bytes: key code u8, pressed u8 (0/1): the original format, still used for keys
+//   Longer messages start with their kind:
+//   INPUT_MOUSE: event u8 (MouseEvent), x f32, y f32 (screen coordinates, 800x600), button i8
+//   INPUT_TOUCH: phase u8 (TouchPhase), id u16, x f32, y f32 (screen coordinates)
+//   INPUT_GAMEPAD: pad u8, kind u8 (0 button, 1 axis), index u8 (standard mapping), value f32
+//   INPUT_SEQUENCED: seq u32, client time f64 (ms since the epoch), then any of the above.
+//     The client numbers every input it sends, so inputs lost or reordered by the unreliable
+//     DataChannel can be noticed, and lag compensation knows when the player acted.
+// Text travels as string messages instead: answers to api.request_text_input as they are,
+// pasted text (on_paste) after PASTE_PREFIX.
+
+pub const INPUT_MOUSE: u8 = 0x01;
+pub const INPUT_TOUCH: u8 = 0x02;
+pub const INPUT_GAMEPAD: u8 = 0x03;
+pub const INPUT_SEQUENCED: u8 = 0x10;
+/// Marks a string message as pasted text (the code of Ctrl+V).
+pub const PASTE_PREFIX: char = '\u{16}';
+
+// Browser "standard" gamepad layout (Xbox names)
+const GAMEPAD_BUTTONS: [&str; 17] = [
+    "a", "b", "x", "y", "lb", "rb", "lt", "rt", "back", "start", "ls", "rs",
+    "up", "down", "left", "right", "home",
+];
+const GAMEPAD_AXES: [&str; 4] = ["left_x", "left_y", "right_x", "right_y"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseEvent {
+    Move,
+    Down,
+    Up,
+    Wheel,
+}
+
+impl MouseEvent {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Move),
+            1 => Some(Self::Down),
+            2 => Some(Self::Up),
+            3 => Some(Self::Wheel),
+            _ => None,
+        }
+    }
+
+    /// As on_mouse receives it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Move => "move",
+            Self::Down => "down",
+            Self::Up => "up",
+            Self::Wheel => "wheel",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchPhase {
+    Start,
+    Move,
+    End,
+    Cancel,
+}
+
+impl TouchPhase {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Start),
+            1 => Some(Self::Move),
+            2 => Some(Self::End),
+            3 => Some(Self::Cancel),
+            _ => None,
+        }
+    }
+
+    /// As on_touch receives it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Move => "move",
+            Self::End => "end",
+            Self::Cancel => "cancel",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadControl {
+    Button(u8),
+    Axis(u8),
+}
+
+impl GamepadControl {
+    /// As on_gamepad receives it: "a", "lt", "left_x"... or "button_N"/"axis_N" beyond the standard layout.
+    pub fn name(self) -> std::borrow::Cow<'static, str> {
+        match self {
+            Self::Button(i) => GAMEPAD_BUTTONS.get(i as usize).map(|&n| n.into()).unwrap_or_else(|| format!("button_{}", i).into()),
+            Self::Axis(i) => GAMEPAD_AXES.get(i as usize).map(|&n| n.into()).unwrap_or_else(|| format!("axis_{}", i).into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputMessage {
+    Key(u8, bool),
+    /// `button`: 0 left, 1 middle, 2 right; the scroll direction for wheel events (1 down, -1 up).
+    Mouse { event: MouseEvent, x: f32, y: f32, button: i8 },
+    /// `id` tells simultaneous touches apart; it stays the same from start to end.
+    Touch { phase: TouchPhase, id: u16, x: f32, y: f32 },
+    /// `value`: 0..1 for buttons (analog triggers in between), -1..1 for axes.
+    Gamepad { pad: u8, control: GamepadControl, value: f32 },
+}
+
+/// Sequence number and client clock of an input message.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputMeta {
+    pub seq: u32,
+    pub time: f64,
+}
+
+/// Like `decode`, also unwrapping INPUT_SEQUENCED (None meta for messages sent without it).
+pub fn decode_sequenced(data: &[u8]) -> Option<(InputMessage, Option<InputMeta>)> {
+    // Two bytes are always a key, whatever the code
+    if data.len() > 2 && data[0] == INPUT_SEQUENCED {
+        let header = data.get(1..13)?;
+        let seq = u32::from_le_bytes(header[0..4].try_into().ok()?);
+        let time = f64::from_le_bytes(header[4..12].try_into().ok()?);
+        if !time.is_finite() {
+            return None;
+        }
+        return Some((decode(&data[13..])?, Some(InputMeta { seq, time })));
+    }
+    Some((decode(data)?, None))
+}
+
+/// None for messages that don't decode (unknown kinds, wrong sizes, non-finite coordinates).
+pub fn decode(data: &[u8]) -> Option<InputMessage> {
+    if let [code, active] = *data {
+        return Some(InputMessage::Key(code, active != 0));
+    }
+    match *data.first()? {
+        INPUT_MOUSE if data.len() == 11 => {
+            let event = MouseEvent::from_code(data[1])?;
+            let (x, y) = point(&data[2..10])?;
+            Some(InputMessage::Mouse { event, x, y, button: data[10] as i8 })
+        }
+        INPUT_TOUCH if data.len() == 12 => {
+            let phase = TouchPhase::from_code(data[1])?;
+            let id = u16::from_le_bytes([data[2], data[3]]);
+            let (x, y) = point(&data[4..12])?;
+            Some(InputMessage::Touch { phase, id, x, y })
+        }
+        INPUT_GAMEPAD if data.len() == 8 => {
+            let control = match data[2] {
+                0 => GamepadControl::Button(data[3]),
+                1 => GamepadControl::Axis(data[3]),
+                _ => return None,
+            };
+            let value = f32::from_le_bytes(data[4..8].try_into().ok()?);
+            if !value.is_finite() {
+                return None;
+            }
+            let value = match control {
+                GamepadControl::Button(_) => value.clamp(0.0, 1.0),
+                GamepadControl::Axis(_) => value.clamp(-1.0, 1.0),
+            };
+            Some(InputMessage::Gamepad { pad: data[1], control, value })
+        }
+        _ => None,
+    }
+}
+
+// Two f32 coordinates; None unless both are finite
+fn point(data: &[u8]) -> Option<(f32, f32)> {
+    let x = f32::from_le_bytes(data[0..4].try_into().ok()?);
+    let y = f32::from_le_bytes(data[4..8].try_into().ok()?);
+    (x.is_finite() && y.is_finite()).then_some((x, y))
+}
+
+// --- Input State ---
+// What each session is holding, kept by the engine as input arrives so scripts can poll it
+// (api.is_down, api.get_axis) instead of tracking on_input in tables of their own.
+
+// Key codes behind the built-in axes: (negative, positive)
+const KEYS_HORIZONTAL: [(u8, u8); 2] = [(37, 39), (65, 68)]; // Arrows, A/D
+const KEYS_VERTICAL: [(u8, u8); 2] = [(38, 40), (87, 83)]; // Arrows, W/S
+const DPAD_HORIZONTAL: (u8, u8) = (14, 15);
+const DPAD_VERTICAL: (u8, u8) = (12, 13);
+/// Gamepad buttons count as down from this value (analog triggers).
+const BUTTON_THRESHOLD: f32 = 0.5;
+
+#[derive(Default)]
+struct SessionInput {
+    keys: HashSet<u8>,
+    // Per (pad, control); a control reads as the value of largest magnitude over all pads
+    gamepad: HashMap<(u8, GamepadControl), f32>,
+    sequence: Option<SequenceStats>,
+}
+
+/// What the sequence numbers of a session's inputs tell about its connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    pub last_seq: u32, // Highest seen
+    pub received: u64,
+    /// Skipped numbers that never arrived (yet: a late one is taken back off).
+    pub dropped: u64,
+    /// Inputs that arrived after a later one.
+    pub reordered: u64,
+}
+
+impl SessionInput {
+    fn control(&self, control: GamepadControl) -> f32 {
+        self.gamepad.iter()
+            .filter(|((_, c), _)| *c == control)
+            .map(|(_, &v)| v)
+            .fold(0.0, |best: f32, v| if v.abs() > best.abs() { v } else { best })
+    }
+
+    fn button_down(&self, index: u8) -> bool {
+        self.control(GamepadControl::Button(index)) >= BUTTON_THRESHOLD
+    }
+
+    fn axis(&self, keys: [(u8, u8); 2], dpad: (u8, u8), stick: u8) -> f32 {
+        let key = |code: u8| if self.keys.contains(&code) { 1.0 } else { 0.0 };
+        let button = |index: u8| if self.button_down(index) { 1.0 } else { 0.0 };
+        let digital = keys.iter().map(|&(neg, pos)| key(pos) - key(neg)).sum::<f32>() + button(dpad.1) - button(dpad.0);
+        (digital + self.control(GamepadControl::Axis(stick))).clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Default)]
+pub struct InputStates {
+    sessions: HashMap<String, SessionInput>,
+}
+
+impl InputStates {
+    pub fn key(&mut self, session_id: &str, code: u8, down: bool) {
+        let keys = &mut self.sessions.entry(session_id.to_string()).or_default().keys;
+        if down {
+            keys.insert(code);
+        } else {
+            keys.remove(&code);
+        }
+    }
+
+    pub fn gamepad(&mut self, session_id: &str, pad: u8, control: GamepadControl, value: f32) {
+        let gamepad = &mut self.sessions.entry(session_id.to_string()).or_default().gamepad;
+        if value == 0.0 {
+            gamepad.remove(&(pad, control));
+        } else {
+            gamepad.insert((pad, control), value);
+        }
+    }
+
+    /// Records the sequence number of an input; false when it arrived late (out of order).
+    pub fn sequence(&mut self, session_id: &str, seq: u32) -> bool {
+        let session = self.sessions.entry(session_id.to_string()).or_default();
+        let Some(stats) = &mut session.sequence else {
+            session.sequence = Some(SequenceStats { last_seq: seq, received: 1, ..Default::default() });
+            return true;
+        };
+        stats.received += 1;
+        if seq > stats.last_seq {
+            stats.dropped += (seq - stats.last_seq - 1) as u64;
+            stats.last_seq = seq;
+            true
+        } else {
+            if seq < stats.last_seq {
+                stats.dropped = stats.dropped.saturating_sub(1);
+            }
+            stats.reordered += 1;
+            false
+        }
+    }
+
+    /// None until the session's client sends a sequenced input.
+    pub fn sequence_stats(&self, session_id: &str) -> Option<SequenceStats> {
+        self.sessions.get(session_id)?.sequence
+    }
+
+    pub fn is_key_down(&self, session_id: &str, code: u8) -> bool {
+        self.sessions.get(session_id).is_some_and(|s| s.keys.contains(&code))
+    }
+
+    /// A gamepad button by its on_gamepad name ("a", "rt"...); None for unknown names.
+    pub fn is_button_down(&self, session_id: &str, name: &str) -> Option<bool> {
+        let index = GAMEPAD_BUTTONS.iter().position(|&n| n == name)? as u8;
+        Some(self.is_pad_button_down(session_id, index))
+    }
+
+    /// A gamepad button by its index in the standard layout.
+    pub fn is_pad_button_down(&self, session_id: &str, index: u8) -> bool {
+        self.sessions.get(session_id).is_some_and(|s| s.button_down(index))
+    }
+
+    /// -1..1: "horizontal" and "vertical" combine the arrow keys, WASD, the d-pad and the
+    /// left stick; gamepad axis names ("right_x"...) read that axis alone. None for unknown names.
+    pub fn axis(&self, session_id: &str, name: &str) -> Option<f32> {
+        let session = self.sessions.get(session_id);
+        let value = match name {
+            "horizontal" => session.map(|s| s.axis(KEYS_HORIZONTAL, DPAD_HORIZONTAL, 0)),
+            "vertical" => session.map(|s| s.axis(KEYS_VERTICAL, DPAD_VERTICAL, 1)),
+            _ => {
+                let index = GAMEPAD_AXES.iter().position(|&n| n == name)? as u8;
+                session.map(|s| s.control(GamepadControl::Axis(index)))
+            }
+        };
+        Some(value.unwrap_or(0.0))
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+// --- Input Config ---
+// How the client turns the player's devices into input messages, set by the script
+// (api.set_input_config) so games can tune input feel without a custom client. Works like
+// post effects: shared settings, per-session overrides, sent to a client when they change.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputConfig {
+    /// Send the browser's auto-repeated key presses (a held key "presses" again and again).
+    pub key_repeat: bool,
+    /// Gamepad sticks and the touch joystick read 0 within this distance of the center (0..1).
+    pub dead_zone: f32,
+    /// Pixels the touch joystick's knob travels for a full deflection.
+    pub joystick_radius: f32,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self { key_repeat: false, dead_zone: 0.15, joystick_radius: 50.0 }
+    }
+}
+
+#[derive(Default)]
+pub struct InputConfigs {
+    all: InputConfig,
+    sessions: HashMap<String, InputConfig>,
+    // What each client was last sent
+    sent: HashMap<String, InputConfig>,
+}
+
+impl InputConfigs {
+    /// None resets `target`: to the defaults for ALL_SESSIONS, to the shared config for a session.
+    pub fn set(&mut self, target: &str, config: Option<InputConfig>) {
+        match (target, config) {
+            (crate::post::ALL_SESSIONS, config) => self.all = config.unwrap_or_default(),
+            (session_id, Some(config)) => { self.sessions.insert(session_id.to_string(), config); }
+            (session_id, None) => { self.sessions.remove(session_id); }
+        }
+    }
+
+    /// The session's config if its client doesn't have it yet (marked as sent).
+    pub fn take_changed(&mut self, session_id: &str) -> Option<InputConfig> {
+        let config = self.sessions.get(session_id).copied().unwrap_or(self.all);
+        let sent = self.sent.get(session_id).copied().unwrap_or_default();
+        if config == sent {
+            return None;
+        }
+        self.sent.insert(session_id.to_string(), config);
+        Some(config)
+    }
+
+    /// The session's client starts over (it reconnected): its config are sent again.
+    pub fn resend(&mut self, session_id: &str) {
+        self.sent.remove(session_id);
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+        self.sent.remove(session_id);
+    }
+}
@@ -1,14 +1,73 @@
 use bytes::{BufMut, Bytes, BytesMut};
 use mlua::{Lua, Function, LuaSerdeExt, StdLib, LuaOptions, UserData, AnyUserData};
+use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, Mutex};
 use serde_json::Value;
 
+// Re-exported so embedders writing native systems use the same mlua version
+pub use mlua;
+
+mod slab;
 mod spatial_db;
 use spatial_db::SpatialDb;
 mod physics;
-use physics::PhysicsWorld;
+use physics::{PhysicsWorld, ContactPhase, SensorPhase, BoundsMode, WorldBounds};
 mod graph_nav;
 use graph_nav::Graph;
+mod nav_grid;
+use nav_grid::NavGrid;
+mod path_follow;
+mod accessibility;
+mod particles;
+use particles::{Emitter, EmitterConfig, Emitters, ParticleSprite};
+mod tilemap;
+use tilemap::{TileChunk, Tilemap, Tilemaps, MAX_TILEMAP_SIZE};
+mod canvas;
+use canvas::{Canvas, Canvases, MAX_CANVAS_SIZE};
+mod scene;
+use scene::{NodeState, Scene, SceneOp, Shape};
+mod modules;
+use modules::ModuleGraph;
+mod spritesheet;
+mod triangulate;
+mod geom;
+mod post;
+mod camera_rig;
+use camera_rig::CameraRigs;
+mod lighting;
+use lighting::{LightSprite, Lights};
+mod float_text;
+use float_text::{FloatTextConfig, FloatTexts};
+mod trails;
+use trails::{TrailConfig, TrailSprite, Trails, MAX_TRAIL_LENGTH};
+mod settings;
+mod actions;
+use actions::{Actions, Binding, INPUT_MAP_FILE};
+use settings::{Setting, SettingKind, Settings, MAX_CHOICES, MAX_KEY_LEN};
+use post::{PostEffect, PostEffects};
+mod noise;
+use noise::NoiseParams;
+mod messages;
+use messages::{Outbox, MAX_MESSAGE_LEN};
+use spritesheet::{Animation, Spritesheets};
+use accessibility::{Accessibility, Palette};
+use path_follow::{PathFollow, PathFollowers};
+mod timers;
+use timers::{Timers, MIN_INTERVAL};
+#[cfg(feature = "luau")]
+pub mod typecheck;
+pub mod plugin;
+pub mod compat;
+pub mod input;
+use input::{GamepadControl, InputConfig, InputConfigs, InputMessage, InputMeta, InputStates, MouseEvent, TouchPhase};
+pub mod shared;
+use shared::SharedData;
+mod broadcast;
+pub use broadcast::{DrawnFrame, SharedSegment};
+use broadcast::SharedDraw;
+mod error;
+pub use error::EngineError;
+use plugin::{Plugin, PluginCommands, PluginContext};
 
 // OpCodes
 const OP_CLEAR: u8 = 0x01;
@@ -20,6 +79,87 @@ const OP_LOAD_SOUND: u8 = 0x06;
 const OP_PLAY_SOUND: u8 = 0x07;
 const OP_STOP_SOUND: u8 = 0x08;
 const OP_SET_VOLUME: u8 = 0x09;
+const OP_LOAD_IMAGE: u8 = 0x0A;
+const OP_DRAW_SPRITE: u8 = 0x0B;
+const OP_FILL_CIRCLE: u8 = 0x0C;
+const OP_DRAW_CIRCLE: u8 = 0x0D;
+const OP_FILL_ELLIPSE: u8 = 0x0E;
+const OP_DRAW_ELLIPSE: u8 = 0x0F;
+const OP_SET_CONTROL_SCHEME: u8 = 0x10;
+const OP_DRAW_POLY: u8 = 0x11;
+const OP_SET_TRANSFORM: u8 = 0x12;
+const OP_REQUEST_TEXT_INPUT: u8 = 0x13;
+const OP_SET_TEXT_SCALE: u8 = 0x14;
+const OP_PUSH_TRANSFORM: u8 = 0x15;
+const OP_POP_TRANSFORM: u8 = 0x16;
+const OP_SET_LAYER: u8 = 0x17;
+const OP_DEV_STATUS: u8 = 0x18;
+const OP_SET_GRADIENT: u8 = 0x19;
+const OP_SET_BLEND_MODE: u8 = 0x1A;
+const OP_DRAW_PARTICLES: u8 = 0x1B;
+const OP_FILL_ARC: u8 = 0x1C;
+const OP_DRAW_ARC: u8 = 0x1D;
+const OP_TILEMAP_CHUNK: u8 = 0x1E;
+const OP_DRAW_TILEMAP: u8 = 0x1F;
+const OP_DRAW_CURVE: u8 = 0x20;
+const OP_NODE_CREATE: u8 = 0x21;
+const OP_NODE_UPDATE: u8 = 0x22;
+const OP_NODE_DESTROY: u8 = 0x23;
+const OP_NODE_SYNC: u8 = 0x24;
+const OP_DRAW_SCENE: u8 = 0x25;
+const OP_DRAW_NINEPATCH: u8 = 0x26;
+const OP_CANVAS_CONTENT: u8 = 0x27;
+const OP_DRAW_CANVAS: u8 = 0x28;
+const OP_SCENE_UPDATE: u8 = 0x29;
+const OP_SCREEN_SHAKE: u8 = 0x2A;
+const OP_FLASH: u8 = 0x2B;
+const OP_VIGNETTE: u8 = 0x2C;
+const OP_LOAD_SPRITESHEET: u8 = 0x2D;
+const OP_DRAW_FRAME: u8 = 0x2E;
+const OP_DEFINE_PALETTE: u8 = 0x2F;
+const OP_PALETTE_COLOR: u8 = 0x30;
+const OP_FILL_TRIANGLES: u8 = 0x31;
+const OP_DRAW_POLYLINE: u8 = 0x32;
+const OP_PLAY_SOUND_PANNED: u8 = 0x33;
+const OP_LOAD_MUSIC: u8 = 0x34;
+const OP_PLAY_MUSIC: u8 = 0x35;
+const OP_STOP_MUSIC: u8 = 0x36;
+const OP_SET_POST: u8 = 0x37;
+const OP_SET_PITCH: u8 = 0x38;
+const OP_SET_BUS_VOLUME: u8 = 0x39;
+const OP_SET_SOUND_BUS: u8 = 0x3A;
+const OP_LIGHTS: u8 = 0x3B;
+const OP_DRAW_TRAILS: u8 = 0x3C;
+const OP_SETTINGS: u8 = 0x3D;
+const OP_ACTION_KEYS: u8 = 0x3E;
+const OP_INPUT_CONFIG: u8 = 0x3F;
+
+/// Upper bound for text submitted through api.request_text_input
+const MAX_TEXT_INPUT_LEN: usize = 1024;
+/// Dev overlay messages (Lua errors with tracebacks) are cut to this many bytes
+const MAX_DEV_STATUS_LEN: usize = 2048;
+/// First opcode available to plugins (0xC0..=0xFF)
+pub const OP_PLUGIN_FIRST: u8 = 0xC0;
+
+// --- Scripting Backend ---
+// Selected at compile time via the `lua54` (default), `luajit` or `luau` features.
+
+#[cfg(feature = "lua54")]
+fn sandbox_libs() -> StdLib {
+    StdLib::MATH | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::COROUTINE | StdLib::PACKAGE
+}
+
+// LuaJIT has no utf8 library. JIT must be loaded, otherwise the compiler stays off.
+#[cfg(feature = "luajit")]
+fn sandbox_libs() -> StdLib {
+    StdLib::MATH | StdLib::TABLE | StdLib::STRING | StdLib::BIT | StdLib::COROUTINE | StdLib::PACKAGE | StdLib::JIT
+}
+
+// Luau's `package` is emulated by mlua (require + package.path), so the same path setup applies.
+#[cfg(feature = "luau")]
+fn sandbox_libs() -> StdLib {
+    StdLib::MATH | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::BIT | StdLib::COROUTINE | StdLib::PACKAGE
+}
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 enum GameMode {
@@ -27,6 +167,128 @@ enum GameMode {
     Draw,
 }
 
+// --- Camera ---
+// Per-client view set from draw(): the world point (x, y) is shown at the screen center,
+// scaled by zoom and rotated by rotation (radians). Reset to the identity view for every draw().
+const SCREEN_W: f32 = 800.0;
+const SCREEN_H: f32 = 600.0;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Camera {
+    x: f32,
+    y: f32,
+    zoom: f32,
+    rotation: f32,
+}
+
+impl Camera {
+    const SCREEN: Camera = Camera { x: SCREEN_W / 2.0, y: SCREEN_H / 2.0, zoom: 1.0, rotation: 0.0 };
+
+    // World-space AABB covering the screen (for query_rect culling)
+    fn view_rect(&self) -> (f32, f32, f32, f32) {
+        let (hw, hh) = (SCREEN_W / 2.0 / self.zoom, SCREEN_H / 2.0 / self.zoom);
+        let (sin, cos) = self.rotation.sin_cos();
+        let ex = hw * cos.abs() + hh * sin.abs();
+        let ey = hw * sin.abs() + hh * cos.abs();
+        (self.x - ex, self.y - ey, self.x + ex, self.y + ey)
+    }
+}
+
+// --- Viewport Culling ---
+// With api.set_viewport(session_id, x, y, w, h), draw commands whose bounds fall entirely
+// outside that rect are dropped while drawing for the session. Only commands in the base
+// coordinate space are culled: never inside push_transform, nor after reset_camera (HUD).
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Viewport {
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+#[derive(Default)]
+struct Culling {
+    viewport: Option<Viewport>,
+    transform_depth: u32,
+    hud: bool,
+    // The session's connection is congested: cosmetic layers (particles, trails, floating
+    // text) are left out of its frames
+    reduced_detail: bool,
+}
+
+impl Culling {
+    fn visible(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> bool {
+        match self.viewport {
+            Some(v) if self.transform_depth == 0 && !self.hud => {
+                max_x >= v.min_x && min_x <= v.max_x && max_y >= v.min_y && min_y <= v.max_y
+            }
+            _ => true,
+        }
+    }
+
+    fn visible_rect(&self, x: f32, y: f32, w: f32, h: f32) -> bool {
+        self.visible(x.min(x + w), y.min(y + h), x.max(x + w), y.max(y + h))
+    }
+
+    fn visible_circle(&self, x: f32, y: f32, rx: f32, ry: f32) -> bool {
+        let (rx, ry) = (rx.abs(), ry.abs());
+        self.visible(x - rx, y - ry, x + rx, y + ry)
+    }
+}
+
+// --- Positional Audio ---
+// api.play_sound_at(name, x, y, opts) is mixed for each client on its own: volume falls off
+// linearly from `min_distance` (full volume) to `max_distance` (silent, not sent) around the
+// session's listener, and the sound pans with the horizontal offset. The listener is the
+// position from api.set_listener, else the center of the session's viewport; sessions with
+// neither hear the sound unattenuated. Mixing happens when the sound starts: a looping sound
+// keeps its volume and pan.
+#[derive(Clone)]
+struct PositionalSound {
+    name: String,
+    x: f32,
+    y: f32,
+    volume: f32,
+    pitch: f32,
+    looping: bool,
+    min_distance: f32,
+    max_distance: f32,
+}
+
+impl PositionalSound {
+    /// (volume, pan in [-1, 1]) heard from `listener`; None when out of range.
+    fn mix(&self, listener: Option<(f32, f32)>) -> Option<(f32, f32)> {
+        let Some((lx, ly)) = listener else { return Some((self.volume, 0.0)) };
+        let (dx, dy) = (self.x - lx, self.y - ly);
+        let distance = (dx * dx + dy * dy).sqrt();
+        if distance >= self.max_distance {
+            return None;
+        }
+        let falloff = if distance <= self.min_distance {
+            1.0
+        } else {
+            1.0 - (distance - self.min_distance) / (self.max_distance - self.min_distance)
+        };
+        let pan = (dx / self.max_distance).clamp(-1.0, 1.0);
+        Some((self.volume * falloff, pan))
+    }
+
+    fn write(&self, buffer: &CommandBuffer, listener: Option<(f32, f32)>) {
+        if let Some((volume, pan)) = self.mix(listener) {
+            buffer.cmd_play_sound_panned(&self.name, self.looping, volume, pan);
+            if self.pitch != 1.0 {
+                buffer.cmd_set_pitch(&self.name, self.pitch);
+            }
+        }
+    }
+}
+
+// Where a session hears positional sounds from
+fn listener_of(session_id: &str, listeners: &HashMap<String, (f32, f32)>, viewports: &HashMap<String, Viewport>) -> Option<(f32, f32)> {
+    listeners.get(session_id).copied()
+        .or_else(|| viewports.get(session_id).map(|v| ((v.min_x + v.max_x) / 2.0, (v.min_y + v.max_y) / 2.0)))
+}
+
 // Wrapper for SpatialDb to be exposed as UserData
 #[derive(Clone)]
 struct SpatialDbWrapper(Arc<Mutex<SpatialDb>>);
@@ -43,6 +305,17 @@ impl UserData for SpatialDbWrapper {
             Ok(db.add_segment(x1, y1, x2, y2, &tag))
         });
 
+        // One segment per edge of an outline (closed by default)
+        methods.add_method("add_polygon", |_, this, (points, tag, closed): (mlua::Table, String, Option<bool>)| {
+            let points = read_polygon(points)?;
+            let edges = if closed.unwrap_or(true) && points.len() > 2 { points.len() } else { points.len().saturating_sub(1) };
+            let mut db = this.0.lock().unwrap();
+            Ok((0..edges).map(|i| {
+                let ((x1, y1), (x2, y2)) = (points[i], points[(i + 1) % points.len()]);
+                db.add_segment(x1, y1, x2, y2, &tag)
+            }).collect::<Vec<u64>>())
+        });
+
         methods.add_method("update", |_, this, (id, x, y): (u64, f32, f32)| {
             let mut db = this.0.lock().unwrap();
             db.update_position(id, x, y);
@@ -64,16 +337,39 @@ impl UserData for SpatialDbWrapper {
             Ok(())
         });
 
-        methods.add_method("query_range", |_, this, (x, y, r, tag_filter): (f32, f32, f32, Option<String>)| {
+        methods.add_method("get_tag", |_, this, id: u64| {
+            let db = this.0.lock().unwrap();
+            Ok(db.get_tag(id).map(|t| t.to_string()))
+        });
+
+        methods.add_method("query_range", |_, this, (x, y, r, tag_filter, opts): (f32, f32, f32, Option<String>, Option<mlua::Table>)| {
             let db = this.0.lock().unwrap();
             let ids = db.query_range(x, y, r, tag_filter.as_deref());
-            Ok(ids)
+            let tags = collect_tags(&db, &ids, opts.as_ref())?;
+            Ok((ids, tags))
+        });
+
+        methods.add_method("query_range_ex", |lua, this, (x, y, r, tag_filter, max_results): (f32, f32, f32, Option<String>, Option<usize>)| {
+            let db = this.0.lock().unwrap();
+            let hits = db.query_range_ex(x, y, r, tag_filter.as_deref(), max_results);
+            let list = lua.create_table()?;
+            for (id, hx, hy, dist) in hits {
+                let hit = lua.create_table()?;
+                hit.set("id", id)?;
+                hit.set("tag", db.get_tag(id))?;
+                hit.set("x", hx)?;
+                hit.set("y", hy)?;
+                hit.set("dist", dist)?;
+                list.push(hit)?;
+            }
+            Ok(list)
         });
 
-        methods.add_method("query_rect", |_, this, (min_x, min_y, max_x, max_y, tag_filter): (f32, f32, f32, f32, Option<String>)| {
+        methods.add_method("query_rect", |_, this, (min_x, min_y, max_x, max_y, tag_filter, opts): (f32, f32, f32, f32, Option<String>, Option<mlua::Table>)| {
             let db = this.0.lock().unwrap();
             let ids = db.query_rect(min_x, min_y, max_x, max_y, tag_filter.as_deref());
-            Ok(ids)
+            let tags = collect_tags(&db, &ids, opts.as_ref())?;
+            Ok((ids, tags))
         });
 
         methods.add_method("cast_ray", |_, this, (x, y, angle, dist, tag_filter): (f32, f32, f32, f32, Option<String>)| {
@@ -87,6 +383,18 @@ impl UserData for SpatialDbWrapper {
     }
 }
 
+// Query option `{ return_tags = true }`: second return value with the tag of each id (same order)
+fn collect_tags(db: &SpatialDb, ids: &[u64], opts: Option<&mlua::Table>) -> mlua::Result<Option<Vec<String>>> {
+    let wanted = match opts {
+        Some(o) => o.get::<_, Option<bool>>("return_tags")?.unwrap_or(false),
+        None => false,
+    };
+    if !wanted {
+        return Ok(None);
+    }
+    Ok(Some(ids.iter().map(|&id| db.get_tag(id).unwrap_or_default().to_string()).collect()))
+}
+
 // Wrapper for PhysicsWorld
 #[derive(Clone)]
 struct PhysicsWrapper(Arc<Mutex<PhysicsWorld>>);
@@ -97,9 +405,10 @@ impl UserData for PhysicsWrapper {
             let mass: f32 = props.get("mass").unwrap_or(1.0);
             let restitution: f32 = props.get("restitution").unwrap_or(0.5);
             let drag: f32 = props.get("drag").unwrap_or(0.0);
+            let sensor: bool = props.get("sensor").unwrap_or(false);
             
             let mut phys = this.0.lock().unwrap();
-            phys.add_body(id, mass, restitution, drag);
+            phys.add_body(id, mass, restitution, drag, sensor);
             Ok(())
         });
 
@@ -109,6 +418,60 @@ impl UserData for PhysicsWrapper {
             Ok(())
         });
 
+        methods.add_method("get_gravity", |_, this, ()| {
+            let phys = this.0.lock().unwrap();
+            Ok(phys.get_gravity())
+        });
+
+        methods.add_method("set_damping", |_, this, damping: f32| {
+            let mut phys = this.0.lock().unwrap();
+            phys.set_damping(damping);
+            Ok(())
+        });
+
+        methods.add_method("get_damping", |_, this, ()| {
+            let phys = this.0.lock().unwrap();
+            Ok(phys.get_damping())
+        });
+
+        methods.add_method("set_bounds", |_, this, (min_x, min_y, max_x, max_y, mode): (f32, f32, f32, f32, Option<String>)| {
+            let mode = match mode.as_deref().unwrap_or("clamp") {
+                "clamp" => BoundsMode::Clamp,
+                "wrap" => BoundsMode::Wrap,
+                "destroy" => BoundsMode::Destroy,
+                other => return Err(mlua::Error::RuntimeError(format!("Unknown bounds mode '{}' (expected clamp, wrap or destroy)", other))),
+            };
+            if [min_x, min_y, max_x, max_y].iter().any(|v| v.is_nan()) || min_x > max_x || min_y > max_y {
+                return Err(mlua::Error::RuntimeError(format!("Invalid bounds ({}, {}) - ({}, {}): expected x1 <= x2 and y1 <= y2", min_x, min_y, max_x, max_y)));
+            }
+            let mut phys = this.0.lock().unwrap();
+            phys.set_bounds(Some(WorldBounds { min_x, min_y, max_x, max_y, mode }));
+            Ok(())
+        });
+
+        methods.add_method("clear_bounds", |_, this, ()| {
+            let mut phys = this.0.lock().unwrap();
+            phys.set_bounds(None);
+            Ok(())
+        });
+
+        methods.add_method("get_bounds_events", |lua, this, ()| {
+            let mut phys = this.0.lock().unwrap();
+            let events = phys.get_bounds_events();
+            let list = lua.create_table()?;
+            for (id, mode) in events {
+                let ev = lua.create_table()?;
+                ev.set("id", id)?;
+                ev.set("action", match mode {
+                    BoundsMode::Clamp => "clamp",
+                    BoundsMode::Wrap => "wrap",
+                    BoundsMode::Destroy => "destroy",
+                })?;
+                list.push(ev)?;
+            }
+            Ok(list)
+        });
+
         methods.add_method("set_velocity", |_, this, (id, vx, vy): (u64, f32, f32)| {
             let mut phys = this.0.lock().unwrap();
             phys.set_velocity(id, vx, vy);
@@ -136,10 +499,243 @@ impl UserData for PhysicsWrapper {
             let lua_events: Vec<Vec<u64>> = events.into_iter().map(|(a, b)| vec![a, b]).collect();
             Ok(lua_events)
         });
+
+        methods.add_method("get_contact_events", |lua, this, ()| {
+            let mut phys = this.0.lock().unwrap();
+            let events = phys.get_contact_events();
+            let list = lua.create_table()?;
+            for ((a, b), phase) in events {
+                let ev = lua.create_table()?;
+                ev.set("a", a)?;
+                ev.set("b", b)?;
+                ev.set("phase", match phase {
+                    ContactPhase::Begin => "begin",
+                    ContactPhase::Stay => "stay",
+                    ContactPhase::End => "end",
+                })?;
+                list.push(ev)?;
+            }
+            Ok(list)
+        });
+
+        methods.add_method("get_sensor_events", |lua, this, ()| {
+            let mut phys = this.0.lock().unwrap();
+            let events = phys.get_sensor_events();
+            let list = lua.create_table()?;
+            for ((sensor, other), phase) in events {
+                let ev = lua.create_table()?;
+                ev.set("sensor", sensor)?;
+                ev.set("other", other)?;
+                ev.set("phase", match phase {
+                    SensorPhase::Enter => "enter",
+                    SensorPhase::Exit => "exit",
+                })?;
+                list.push(ev)?;
+            }
+            Ok(list)
+        });
     }
 }
 
 // Wrapper for Graph
+// Polygon vertices: flat {x1, y1, x2, y2, ...} or nested {{x1, y1}, {x2, y2}, ...}
+fn read_polygon(points: mlua::Table) -> mlua::Result<Vec<(f32, f32)>> {
+    let values: Vec<mlua::Value> = points.sequence_values().collect::<mlua::Result<_>>()?;
+    let vertices: Vec<(f32, f32)> = match values.first() {
+        Some(mlua::Value::Table(_)) => values.into_iter().map(|v| match v {
+            mlua::Value::Table(t) => Ok((t.get(1)?, t.get(2)?)),
+            other => Err(mlua::Error::RuntimeError(format!("Invalid polygon vertex: {}", other.type_name()))),
+        }).collect::<mlua::Result<_>>()?,
+        _ => {
+            if values.len() % 2 != 0 {
+                return Err(mlua::Error::RuntimeError("Polygon needs an even number of coordinates".into()));
+            }
+            let coords: Vec<f32> = values.into_iter().map(|v| match v {
+                mlua::Value::Integer(i) => Ok(i as f32),
+                mlua::Value::Number(n) => Ok(n as f32),
+                other => Err(mlua::Error::RuntimeError(format!("Invalid polygon coordinate: {}", other.type_name()))),
+            }).collect::<mlua::Result<_>>()?;
+            coords.chunks(2).map(|c| (c[0], c[1])).collect()
+        }
+    };
+    if vertices.len() > u16::MAX as usize {
+        return Err(mlua::Error::RuntimeError(format!("Polygon has too many vertices ({})", vertices.len())));
+    }
+    Ok(vertices)
+}
+
+// Sound playback rates: what browsers can play
+const MIN_PITCH: f32 = 0.0625;
+const MAX_PITCH: f32 = 16.0;
+
+fn read_pitch(rate: f32) -> mlua::Result<f32> {
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err(mlua::Error::RuntimeError(format!("Pitch must be a positive number (got {})", rate)));
+    }
+    Ok(rate.clamp(MIN_PITCH, MAX_PITCH))
+}
+
+// api.set_camera and api.camera_follow zoom, 1 when left out
+fn read_zoom(zoom: Option<f32>) -> mlua::Result<f32> {
+    let zoom = zoom.unwrap_or(1.0);
+    if zoom.is_nan() || zoom <= 0.0 {
+        return Err(mlua::Error::RuntimeError(format!("Camera zoom must be positive (got {})", zoom)));
+    }
+    Ok(zoom)
+}
+
+// api.set_post_effect settings; fields left out take their defaults
+// api.set_input_config settings: {key_repeat, dead_zone, joystick_radius}
+fn read_input_config(settings: mlua::Table) -> mlua::Result<InputConfig> {
+    let mut config = InputConfig::default();
+    if let Some(v) = settings.get::<_, Option<bool>>("key_repeat")? {
+        config.key_repeat = v;
+    }
+    if let Some(v) = settings.get::<_, Option<f32>>("dead_zone")? {
+        if !(0.0..1.0).contains(&v) {
+            return Err(mlua::Error::RuntimeError("Input dead_zone must be at least 0 and below 1".into()));
+        }
+        config.dead_zone = v;
+    }
+    if let Some(v) = settings.get::<_, Option<f32>>("joystick_radius")? {
+        if !(v > 0.0 && v.is_finite()) {
+            return Err(mlua::Error::RuntimeError("Input joystick_radius must be a positive number".into()));
+        }
+        config.joystick_radius = v;
+    }
+    Ok(config)
+}
+
+fn read_post_effect(settings: mlua::Table) -> mlua::Result<PostEffect> {
+    let mut effect = PostEffect::default();
+    if let Some(tint) = settings.get::<_, Option<mlua::Table>>("tint")? {
+        let a: Option<u8> = tint.get(4)?;
+        effect.tint = [tint.get(1)?, tint.get(2)?, tint.get(3)?, a.unwrap_or(128)];
+    }
+    if let Some(v) = settings.get::<_, Option<f32>>("vignette")? {
+        effect.vignette = if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) };
+    }
+    for (key, field) in [("brightness", &mut effect.brightness), ("contrast", &mut effect.contrast)] {
+        if let Some(v) = settings.get::<_, Option<f32>>(key)? {
+            if !(v >= 0.0 && v.is_finite()) {
+                return Err(mlua::Error::RuntimeError(format!("Post effect {} must be a non-negative number", key)));
+            }
+            *field = v;
+        }
+    }
+    effect.scanlines = settings.get::<_, Option<bool>>("scanlines")?.unwrap_or(false);
+    Ok(effect)
+}
+
+// api.noise1d/noise2d/noise_map options: {octaves, frequency, seed, persistence, lacunarity}
+fn read_noise_params(opts: Option<mlua::Table>) -> mlua::Result<NoiseParams> {
+    let mut p = NoiseParams::default();
+    let Some(opts) = opts else { return Ok(p) };
+    if let Some(octaves) = opts.get::<_, Option<u32>>("octaves")? {
+        if octaves == 0 || octaves > noise::MAX_OCTAVES {
+            return Err(mlua::Error::RuntimeError(format!("Noise octaves must be 1 to {} (got {})", noise::MAX_OCTAVES, octaves)));
+        }
+        p.octaves = octaves;
+    }
+    p.frequency = opts.get::<_, Option<f64>>("frequency")?.unwrap_or(p.frequency);
+    p.seed = opts.get::<_, Option<i64>>("seed")?.map_or(p.seed, |s| s as u32);
+    p.persistence = opts.get::<_, Option<f64>>("persistence")?.unwrap_or(p.persistence);
+    p.lacunarity = opts.get::<_, Option<f64>>("lacunarity")?.unwrap_or(p.lacunarity);
+    if ![p.frequency, p.persistence, p.lacunarity].iter().all(|v| v.is_finite()) {
+        return Err(mlua::Error::RuntimeError("Noise options must be finite numbers".into()));
+    }
+    Ok(p)
+}
+
+/// Upper bound for api.noise_map cells
+const MAX_NOISE_MAP_CELLS: usize = 1 << 20;
+
+struct GradientStop {
+    offset: f32,
+    rgba: [u8; 4],
+}
+
+// Stops are {offset, r, g, b, [a]} with offset in 0..1; colors go through the session's palette
+fn read_gradient_stops(stops: mlua::Table, a11y: &Accessibility) -> mlua::Result<Vec<GradientStop>> {
+    let stops: Vec<mlua::Table> = stops.sequence_values().collect::<mlua::Result<_>>()?;
+    if stops.len() < 2 || stops.len() > u8::MAX as usize {
+        return Err(mlua::Error::RuntimeError(format!("A gradient needs 2 to 255 stops (got {})", stops.len())));
+    }
+    stops.into_iter().map(|t| {
+        let offset: f32 = t.get(1)?;
+        let (r, g, b) = a11y.remap(t.get(2)?, t.get(3)?, t.get(4)?);
+        let a: Option<u8> = t.get(5)?;
+        Ok(GradientStop { offset: offset.clamp(0.0, 1.0), rgba: [r, g, b, a.unwrap_or(255)] })
+    }).collect()
+}
+
+// Bounding box of the vertices, grown by `pad` (half the stroke width)
+fn polygon_visible(cull: &Culling, points: &[(f32, f32)], pad: f32) -> bool {
+    let Some(&(x, y)) = points.first() else { return false };
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+    for &(x, y) in &points[1..] {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    cull.visible(min_x - pad, min_y - pad, max_x + pad, max_y + pad)
+}
+
+// A path of bezier segments starting at `start`: each segment is its control point(s)
+// followed by its end point (quadratic: 2 points, cubic: 3)
+struct Curve {
+    cubic: bool,
+    closed: bool,
+    start: (f32, f32),
+    segments: Vec<Vec<(f32, f32)>>,
+}
+
+impl Curve {
+    // Every point, controls included: the curve stays inside their bounding box
+    fn points(&self) -> Vec<(f32, f32)> {
+        std::iter::once(self.start).chain(self.segments.iter().flatten().copied()).collect()
+    }
+}
+
+// Catmull-Rom spline through `points`, as cubic beziers (the ends repeat their point)
+fn spline_through(points: &[(f32, f32)], closed: bool) -> Curve {
+    let n = points.len();
+    let at = |i: isize| -> (f32, f32) {
+        if closed { points[i.rem_euclid(n as isize) as usize] } else { points[i.clamp(0, n as isize - 1) as usize] }
+    };
+    let count = if closed { n } else { n - 1 };
+    let segments = (0..count as isize).map(|i| {
+        let (p0, p1, p2, p3) = (at(i - 1), at(i), at(i + 1), at(i + 2));
+        vec![
+            (p1.0 + (p2.0 - p0.0) / 6.0, p1.1 + (p2.1 - p0.1) / 6.0),
+            (p2.0 - (p3.0 - p1.0) / 6.0, p2.1 - (p3.1 - p1.1) / 6.0),
+            p2,
+        ]
+    }).collect();
+    Curve { cubic: true, closed, start: points[0], segments }
+}
+
+// Waypoints are {x=, y=} / {x, y} points, or node ids when a graph is given
+fn read_waypoints(path: &mlua::Table, graph: Option<&Graph>) -> mlua::Result<Vec<(f32, f32)>> {
+    let mut points = Vec::new();
+    for entry in path.clone().sequence_values::<mlua::Value>() {
+        let point = match entry? {
+            mlua::Value::Table(t) => match t.get::<_, Option<f32>>("x")? {
+                Some(x) => (x, t.get("y")?),
+                None => (t.get(1)?, t.get(2)?),
+            },
+            mlua::Value::Integer(id) => {
+                let g = graph.ok_or_else(|| mlua::Error::RuntimeError("Path of node ids requires opts.graph".into()))?;
+                g.get_node(id as u64).ok_or_else(|| mlua::Error::RuntimeError(format!("nav node {} does not exist", id)))?
+            }
+            other => return Err(mlua::Error::RuntimeError(format!("Invalid waypoint: {}", other.type_name()))),
+        };
+        points.push(point);
+    }
+    Ok(points)
+}
+
 #[derive(Clone)]
 struct GraphWrapper(Arc<Mutex<Graph>>);
 
@@ -153,276 +749,3048 @@ impl UserData for GraphWrapper {
 
         methods.add_method("add_edge", |_, this, (u, v): (u64, u64)| {
             let mut g = this.0.lock().unwrap();
-            g.add_edge(u, v);
-            Ok(())
+            g.add_edge(u, v).map_err(mlua::Error::external)
         });
 
         methods.add_method("find_path", |_, this, (start, goal): (u64, u64)| {
             let g = this.0.lock().unwrap();
-            let path = g.find_path(start, goal);
-            Ok(path)
+            g.find_path(start, goal).map_err(mlua::Error::external)
+        });
+
+        methods.add_method("set_cost", |_, this, (id, cost): (u64, f32)| {
+            let mut g = this.0.lock().unwrap();
+            g.set_cost(id, cost).map_err(mlua::Error::external)
+        });
+
+        methods.add_method("get_cost", |_, this, id: u64| {
+            let g = this.0.lock().unwrap();
+            Ok(g.get_cost(id))
+        });
+
+        methods.add_method("get_node", |_, this, id: u64| {
+            let g = this.0.lock().unwrap();
+            match g.get_node(id) {
+                Some((x, y)) => Ok((Some(x), Some(y))),
+                None => Ok((None, None))
+            }
+        });
+
+        methods.add_method("neighbors", |_, this, id: u64| {
+            let g = this.0.lock().unwrap();
+            Ok(g.neighbors(id).map(|n| n.to_vec()))
+        });
+
+        methods.add_method("node_count", |_, this, ()| {
+            let g = this.0.lock().unwrap();
+            Ok(g.node_count())
+        });
+
+        methods.add_method("has_path", |_, this, (start, goal): (u64, u64)| {
+            let g = this.0.lock().unwrap();
+            Ok(g.has_path(start, goal))
         });
     }
 }
 
 #[derive(Clone)]
-pub struct CommandBuffer {
-    data: Arc<Mutex<BytesMut>>,
-}
+struct NavGridWrapper(Arc<Mutex<NavGrid>>);
 
-impl CommandBuffer {
-    pub fn new() -> Self {
-        Self {
-            data: Arc::new(Mutex::new(BytesMut::with_capacity(1024))),
-        }
-    }
+impl UserData for NavGridWrapper {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("set_walkable", |_, this, (x, y, walkable): (usize, usize, bool)| {
+            let mut g = this.0.lock().unwrap();
+            g.set_walkable(x, y, walkable).map_err(mlua::Error::external)
+        });
 
-    pub fn clear(&self) {
-        let mut data = self.data.lock().unwrap();
-        data.clear();
-    }
+        methods.add_method("is_walkable", |_, this, (x, y): (usize, usize)| {
+            let g = this.0.lock().unwrap();
+            Ok(g.is_walkable(x, y))
+        });
 
-    pub fn get_bytes(&self) -> Bytes {
-        let data = self.data.lock().unwrap();
-        data.clone().freeze()
-    }
+        methods.add_method("set_cost", |_, this, (x, y, cost): (usize, usize, f32)| {
+            let mut g = this.0.lock().unwrap();
+            g.set_cost(x, y, cost).map_err(mlua::Error::external)
+        });
 
-    // --- Primitive Writers ---
+        methods.add_method("get_cost", |_, this, (x, y): (usize, usize)| {
+            let g = this.0.lock().unwrap();
+            Ok(g.get_cost(x, y))
+        });
 
-    fn cmd_clear_screen(&self, r: u8, g: u8, b: u8) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_CLEAR);
-        data.put_u8(r);
-        data.put_u8(g);
-        data.put_u8(b);
-    }
+        methods.add_method("size", |_, this, ()| {
+            let g = this.0.lock().unwrap();
+            Ok(g.size())
+        });
 
-    fn cmd_set_color(&self, r: u8, g: u8, b: u8, a: u8) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_SET_COLOR);
-        data.put_u8(r);
-        data.put_u8(g);
-        data.put_u8(b);
-        data.put_u8(a);
+        // {{x=, y=}, ...} cells from start to goal, or nil
+        methods.add_method("find_path", |lua, this, (x1, y1, x2, y2): (usize, usize, usize, usize)| {
+            let g = this.0.lock().unwrap();
+            let Some(path) = g.find_path((x1, y1), (x2, y2)).map_err(mlua::Error::external)? else { return Ok(None) };
+            let list = lua.create_table()?;
+            for (x, y) in path {
+                let cell = lua.create_table()?;
+                cell.set("x", x)?;
+                cell.set("y", y)?;
+                list.push(cell)?;
+            }
+            Ok(Some(list))
+        });
     }
+}
 
-    fn cmd_fill_rect(&self, x: f32, y: f32, w: f32, h: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_FILL_RECT);
-        data.put_f32_le(x);
-        data.put_f32_le(y);
-        data.put_f32_le(w);
-        data.put_f32_le(h);
-    }
+// Particle emitter handle; drawing needs the frame's buffer, palette and culling
+struct EmitterWrapper {
+    emitter: Arc<Mutex<Emitter>>,
+    commands: CommandBuffer,
+    a11y: Arc<Mutex<Accessibility>>,
+    culling: Arc<Mutex<Culling>>,
+}
 
-    fn cmd_draw_line(&self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_DRAW_LINE);
-        data.put_f32_le(x1);
-        data.put_f32_le(y1);
-        data.put_f32_le(x2);
-        data.put_f32_le(y2);
-        data.put_f32_le(width);
-    }
+impl UserData for EmitterWrapper {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("set_position", |_, this, (x, y): (f32, f32)| {
+            let mut e = this.emitter.lock().unwrap();
+            e.config.x = x;
+            e.config.y = y;
+            Ok(())
+        });
 
-    fn cmd_draw_text(&self, text: &str, x: f32, y: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_DRAW_TEXT);
-        data.put_f32_le(x);
-        data.put_f32_le(y);
-        let bytes = text.as_bytes();
-        data.put_u16_le(bytes.len() as u16);
-        data.put_slice(bytes);
-    }
+        methods.add_method("set_rate", |_, this, rate: f32| {
+            this.emitter.lock().unwrap().config.rate = rate.max(0.0);
+            Ok(())
+        });
 
-    fn cmd_load_sound(&self, name: &str, url: &str) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_LOAD_SOUND);
-        
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
+        methods.add_method("burst", |_, this, count: usize| {
+            this.emitter.lock().unwrap().burst(count);
+            Ok(())
+        });
 
-        let url_bytes = url.as_bytes();
-        data.put_u16_le(url_bytes.len() as u16);
-        data.put_slice(url_bytes);
-    }
+        methods.add_method("count", |_, this, ()| {
+            Ok(this.emitter.lock().unwrap().count())
+        });
 
-    fn cmd_play_sound(&self, name: &str, loop_sound: bool, volume: f32) {
-        let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_PLAY_SOUND);
-        
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-        
-        data.put_u8(if loop_sound { 1 } else { 0 });
-        data.put_f32_le(volume);
+        methods.add_method("clear", |_, this, ()| {
+            this.emitter.lock().unwrap().clear();
+            Ok(())
+        });
+
+        // One OP_DRAW_PARTICLES batch with every visible particle
+        methods.add_method("draw", |_, this, ()| {
+            if this.culling.lock().unwrap().reduced_detail {
+                return Ok(());
+            }
+            let e = this.emitter.lock().unwrap();
+            let a11y = *this.a11y.lock().unwrap();
+            let remap = |[r, g, b, a]: [u8; 4]| {
+                let (r, g, b) = a11y.remap(r, g, b);
+                [r, g, b, a]
+            };
+            let cull = this.culling.lock().unwrap();
+            let sprites: Vec<ParticleSprite> = e.sprites(remap(e.config.color), remap(e.config.color_end))
+                .filter(|p| {
+                    let half = p.size / 2.0;
+                    cull.visible(p.x - half, p.y - half, p.x + half, p.y + half)
+                })
+                .collect();
+            if !sprites.is_empty() {
+                this.commands.cmd_draw_particles(&sprites, e.config.circles);
+            }
+            Ok(())
+        });
     }
+}
 
-    fn cmd_stop_sound(&self, name: &str) {
+// Tilemap handle; drawing syncs chunks to the session being drawn
+struct TilemapWrapper {
+    tilemap: Arc<Mutex<Tilemap>>,
+    commands: CommandBuffer,
+    drawing_session: Arc<Mutex<Option<String>>>,
+    culling: Arc<Mutex<Culling>>,
+}
+
+impl UserData for TilemapWrapper {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("set", |_, this, (x, y, tile): (usize, usize, u16)| {
+            let mut t = this.tilemap.lock().unwrap();
+            if !t.set(x, y, tile) {
+                return Err(mlua::Error::RuntimeError(format!("Tile ({}, {}) is outside the {}x{} tilemap", x, y, t.width(), t.height())));
+            }
+            Ok(())
+        });
+
+        methods.add_method("get", |_, this, (x, y): (usize, usize)| {
+            Ok(this.tilemap.lock().unwrap().get(x, y))
+        });
+
+        methods.add_method("fill", |_, this, (x, y, w, h, tile): (usize, usize, usize, usize, u16)| {
+            this.tilemap.lock().unwrap().fill(x, y, w, h, tile);
+            Ok(())
+        });
+
+        methods.add_method("set_tileset", |_, this, (name, src_size): (String, Option<u16>)| {
+            let mut t = this.tilemap.lock().unwrap();
+            let src_size = src_size.unwrap_or(t.tile_size.round() as u16).max(1);
+            t.tileset = Some((name, src_size));
+            Ok(())
+        });
+
+        methods.add_method("size", |_, this, ()| {
+            let t = this.tilemap.lock().unwrap();
+            Ok((t.width(), t.height()))
+        });
+
+        // Changed chunks for this client, then one OP_DRAW_TILEMAP. Only inside draw().
+        methods.add_method("draw", |_, this, (x, y): (Option<f32>, Option<f32>)| {
+            let Some(session_id) = this.drawing_session.lock().unwrap().clone() else { return Ok(()) };
+            let mut t = this.tilemap.lock().unwrap();
+            let Some((tileset, src_size)) = t.tileset.clone() else {
+                return Err(mlua::Error::RuntimeError("tilemap:draw() needs a tileset (tilemap:set_tileset)".to_string()));
+            };
+            let (w, h) = (t.width() as u16, t.height() as u16);
+            for chunk in t.sync(&session_id) {
+                this.commands.cmd_tilemap_chunk(t.id, w, h, &chunk);
+            }
+            let (x, y) = (x.unwrap_or(0.0), y.unwrap_or(0.0));
+            let (right, bottom) = (x + t.width() as f32 * t.tile_size, y + t.height() as f32 * t.tile_size);
+            if this.culling.lock().unwrap().visible(x, y, right, bottom) {
+                this.commands.cmd_draw_tilemap(t.id, &tileset, src_size, x, y, t.tile_size);
+            }
+            Ok(())
+        });
+    }
+}
+
+// Canvas handle: draw() records into it, blit() shows it in the frame of the session being drawn
+struct CanvasWrapper {
+    canvas: Arc<Mutex<Canvas>>,
+    commands: CommandBuffer,
+    drawing_session: Arc<Mutex<Option<String>>>,
+    culling: Arc<Mutex<Culling>>,
+}
+
+impl UserData for CanvasWrapper {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Replaces the canvas contents with whatever `f` draws, in canvas pixels from (0, 0)
+        methods.add_method("draw", |_, this, f: Function| {
+            // Viewport culling is for the screen, not the canvas
+            let culling = std::mem::take(&mut *this.culling.lock().unwrap());
+            let (result, content) = this.commands.capture(|| f.call::<_, ()>(()));
+            *this.culling.lock().unwrap() = culling;
+            result?;
+            this.canvas.lock().unwrap().set_content(content);
+            Ok(())
+        });
+
+        // Only inside draw(); w, h default to the canvas size
+        methods.add_method("blit", |_, this, (x, y, w, h): (f32, f32, Option<f32>, Option<f32>)| {
+            let Some(session_id) = this.drawing_session.lock().unwrap().clone() else { return Ok(()) };
+            let mut canvas = this.canvas.lock().unwrap();
+            if let Some(content) = canvas.sync(&session_id) {
+                this.commands.cmd_canvas_content(&canvas, &content);
+            }
+            let (w, h) = (w.unwrap_or(canvas.width as f32), h.unwrap_or(canvas.height as f32));
+            if this.culling.lock().unwrap().visible_rect(x, y, w, h) {
+                this.commands.cmd_draw_canvas(&canvas.name, x, y, w, h);
+            }
+            Ok(())
+        });
+
+        methods.add_method("size", |_, this, ()| {
+            let canvas = this.canvas.lock().unwrap();
+            Ok((canvas.width, canvas.height))
+        });
+    }
+}
+
+// Scene node handle. The node stays in the scene until destroy(), even if the handle is dropped.
+struct NodeWrapper {
+    id: u32,
+    scene: Arc<Mutex<Scene>>,
+}
+
+impl NodeWrapper {
+    fn set(&self, opts: &mlua::Table) -> mlua::Result<()> {
+        let mut scene = self.scene.lock().unwrap();
+        let parent = match opts.get::<_, mlua::Value>("parent")? {
+            mlua::Value::Nil => None,
+            mlua::Value::Boolean(false) => Some(0),
+            mlua::Value::UserData(ud) => {
+                let parent = ud.borrow::<NodeWrapper>()?.id;
+                // Walking up from the new parent must not come back to this node
+                let mut p = parent;
+                while p != 0 {
+                    if p == self.id {
+                        return Err(mlua::Error::RuntimeError("A node cannot be its own ancestor".into()));
+                    }
+                    p = scene.get(p).map(|n| n.parent).unwrap_or(0);
+                }
+                Some(parent)
+            }
+            other => return Err(mlua::Error::RuntimeError(format!("Invalid node parent: {}", other.type_name()))),
+        };
+        let node = scene.get_mut(self.id)
+            .ok_or_else(|| mlua::Error::RuntimeError("Node was destroyed".into()))?;
+        apply_node_opts(opts, node)?;
+        if let Some(parent) = parent {
+            node.parent = parent;
+        }
+        Ok(())
+    }
+}
+
+impl UserData for NodeWrapper {
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("set", |_, this, opts: mlua::Table| this.set(&opts));
+
+        methods.add_method("position", |_, this, ()| {
+            let scene = this.scene.lock().unwrap();
+            let node = scene.get(this.id).ok_or_else(|| mlua::Error::RuntimeError("Node was destroyed".into()))?;
+            Ok((node.x, node.y))
+        });
+
+        methods.add_method("id", |_, this, ()| Ok(this.id));
+
+        // Also destroys the node's children
+        methods.add_method("destroy", |_, this, ()| {
+            this.scene.lock().unwrap().destroy(this.id);
+            Ok(())
+        });
+    }
+}
+
+// api.load_spritesheet animations: { walk = { frames = {1, 2, 3}, fps = 10, loop = true } }
+fn read_animations(table: Option<mlua::Table>) -> mlua::Result<HashMap<String, Animation>> {
+    let mut animations = HashMap::new();
+    let Some(table) = table else { return Ok(animations) };
+    for pair in table.pairs::<String, mlua::Table>() {
+        let (name, anim) = pair?;
+        let frames = anim.get::<_, Vec<u32>>("frames")?.into_iter()
+            .map(|f| f.checked_sub(1).and_then(|f| u16::try_from(f).ok()))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(|| mlua::Error::RuntimeError(format!("Animation '{}': frames are numbered from 1", name)))?;
+        let fps = anim.get::<_, Option<f32>>("fps")?.unwrap_or(10.0);
+        if frames.is_empty() || !(fps > 0.0 && fps.is_finite()) {
+            return Err(mlua::Error::RuntimeError(format!("Animation '{}' needs frames and a positive fps", name)));
+        }
+        let looping = anim.get::<_, Option<bool>>("loop")?.unwrap_or(true);
+        animations.insert(name, Animation { frames, fps, looping });
+    }
+    Ok(animations)
+}
+
+// Culled like draw_sprite
+#[allow(clippy::too_many_arguments)]
+fn draw_frame(buf: &CommandBuffer, culling: &Mutex<Culling>, id: u16, frame: u16, x: f32, y: f32, w: f32, h: f32, rotation: f32) {
+    let visible = {
+        let cull = culling.lock().unwrap();
+        if rotation == 0.0 {
+            cull.visible_rect(x, y, w, h)
+        } else {
+            let r = w.hypot(h) / 2.0;
+            cull.visible_circle(x + w / 2.0, y + h / 2.0, r, r)
+        }
+    };
+    if visible {
+        buf.cmd_draw_frame(id, frame, x, y, w, h, rotation);
+    }
+}
+
+// Functions come from the new version, data keeps its current value (module state survives)
+fn patch_module(current: &mlua::Table, fresh: &mlua::Table) -> mlua::Result<()> {
+    let mut removed = Vec::new();
+    for pair in current.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        if matches!(value, mlua::Value::Function(_)) && !fresh.contains_key(key.clone())? {
+            removed.push(key);
+        }
+    }
+    for key in removed {
+        current.set(key, mlua::Value::Nil)?;
+    }
+    for pair in fresh.clone().pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair?;
+        if matches!(value, mlua::Value::Function(_)) || !current.contains_key(key.clone())? {
+            current.set(key, value)?;
+        }
+    }
+    Ok(())
+}
+
+// Script ids are strings or numbers: 7 and "7" name the same entity
+fn scene_key(key: &mlua::Value) -> mlua::Result<String> {
+    match key {
+        mlua::Value::String(s) => Ok(s.to_str()?.to_string()),
+        mlua::Value::Integer(i) => Ok(i.to_string()),
+        mlua::Value::Number(n) if n.fract() == 0.0 => Ok((*n as i64).to_string()),
+        mlua::Value::Number(n) => Ok(n.to_string()),
+        other => Err(mlua::Error::RuntimeError(format!("Invalid scene entity id: {}", other.type_name()))),
+    }
+}
+
+// api.new_node{...} / node:set{...}: omitted fields keep their value
+fn apply_node_opts(opts: &mlua::Table, node: &mut NodeState) -> mlua::Result<()> {
+    let f = |key: &str, current: f32| -> mlua::Result<f32> { Ok(opts.get::<_, Option<f32>>(key)?.unwrap_or(current)) };
+    node.x = f("x", node.x)?;
+    node.y = f("y", node.y)?;
+    node.rotation = f("rotation", node.rotation)?;
+    node.scale = f("scale", node.scale)?;
+    if let Some(color) = opts.get::<_, Option<mlua::Table>>("color")? {
+        node.color = read_rgba(&color)?;
+    }
+    if let Some(z) = opts.get::<_, Option<i8>>("z")? {
+        node.z = z;
+    }
+    if let Some(visible) = opts.get::<_, Option<bool>>("visible")? {
+        node.visible = visible;
+    }
+    if opts.get::<_, Option<bool>>("snap")? == Some(true) {
+        node.snap = node.snap.wrapping_add(1);
+    }
+
+    // Shape fields update the current shape unless `shape` switches to another kind
+    let (w, h, r, image, text) = match &node.shape {
+        Shape::None => (0.0, 0.0, 0.0, None, None),
+        Shape::Rect { w, h } => (*w, *h, 0.0, None, None),
+        Shape::Circle { r } => (0.0, 0.0, *r, None, None),
+        Shape::Sprite { image, w, h } => (*w, *h, 0.0, Some(image.clone()), None),
+        Shape::Text { text } => (0.0, 0.0, 0.0, None, Some(text.clone())),
+    };
+    let kind = match opts.get::<_, Option<String>>("shape")? {
+        Some(kind) => kind,
+        None => match node.shape {
+            Shape::None => return Ok(()),
+            Shape::Rect { .. } => "rect".into(),
+            Shape::Circle { .. } => "circle".into(),
+            Shape::Sprite { .. } => "sprite".into(),
+            Shape::Text { .. } => "text".into(),
+        },
+    };
+    node.shape = match kind.as_str() {
+        "none" => Shape::None,
+        "rect" => Shape::Rect { w: f("w", w)?, h: f("h", h)? },
+        "circle" => Shape::Circle { r: f("r", r)? },
+        "sprite" => Shape::Sprite {
+            image: opts.get::<_, Option<String>>("image")?.or(image)
+                .ok_or_else(|| mlua::Error::RuntimeError("Sprite nodes need an image".into()))?,
+            w: f("w", w)?,
+            h: f("h", h)?,
+        },
+        "text" => Shape::Text { text: opts.get::<_, Option<String>>("text")?.or(text).unwrap_or_default() },
+        other => return Err(mlua::Error::RuntimeError(format!("Unknown node shape '{}' (expected none, rect, circle, sprite or text)", other))),
+    };
+    Ok(())
+}
+
+fn read_rgba(t: &mlua::Table) -> mlua::Result<[u8; 4]> {
+    Ok([t.get(1)?, t.get(2)?, t.get(3)?, t.get::<_, Option<u8>>(4)?.unwrap_or(255)])
+}
+
+// api.new_emitter{...}: omitted fields keep the EmitterConfig defaults
+fn read_emitter_config(opts: &mlua::Table) -> mlua::Result<EmitterConfig> {
+    let d = EmitterConfig::default();
+    let f = |key: &str, default: f32| -> mlua::Result<f32> { Ok(opts.get::<_, Option<f32>>(key)?.unwrap_or(default)) };
+    let size = f("size", d.size)?;
+    let color = match opts.get::<_, Option<mlua::Table>>("color")? {
+        Some(t) => read_rgba(&t)?,
+        None => d.color,
+    };
+    // Without color_end, particles fade out in their start color
+    let color_end = match opts.get::<_, Option<mlua::Table>>("color_end")? {
+        Some(t) => read_rgba(&t)?,
+        None => [color[0], color[1], color[2], 0],
+    };
+    let circles = match opts.get::<_, Option<String>>("shape")?.as_deref() {
+        None | Some("square") => false,
+        Some("circle") => true,
+        Some(other) => return Err(mlua::Error::RuntimeError(format!("Unknown particle shape '{}' (expected square or circle)", other))),
+    };
+    Ok(EmitterConfig {
+        x: f("x", d.x)?,
+        y: f("y", d.y)?,
+        rate: f("rate", d.rate)?.max(0.0),
+        lifetime: f("lifetime", d.lifetime)?.max(0.0),
+        speed: f("speed", d.speed)?,
+        speed_spread: f("speed_spread", d.speed_spread)?,
+        angle: f("angle", d.angle)?,
+        spread: f("spread", d.spread)?,
+        gravity: (f("gravity_x", d.gravity.0)?, f("gravity_y", d.gravity.1)?),
+        size,
+        size_end: f("size_end", size)?,
+        color,
+        color_end,
+        max: opts.get::<_, Option<usize>>("max")?.unwrap_or(d.max).min(u16::MAX as usize),
+        circles,
+    })
+}
+
+// api.register_setting(key, {type="slider"|"toggle"|"choice", label=key, default, min=0, max=1, step=0, options})
+fn read_setting(key: String, opts: Option<&mlua::Table>) -> mlua::Result<Setting> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(mlua::Error::RuntimeError(format!("Setting keys must be 1 to {} bytes (got {})", MAX_KEY_LEN, key.len())));
+    }
+    let get_f64 = |name: &str, default: f64| -> mlua::Result<f64> {
+        Ok(match opts {
+            Some(o) => o.get::<_, Option<f64>>(name)?.unwrap_or(default),
+            None => default,
+        })
+    };
+    let (label, kind_name) = match opts {
+        Some(o) => (o.get::<_, Option<String>>("label")?, o.get::<_, Option<String>>("type")?),
+        None => (None, None),
+    };
+    let (kind, default) = match kind_name.as_deref().unwrap_or("slider") {
+        "slider" => {
+            let (min, max, step) = (get_f64("min", 0.0)?, get_f64("max", 1.0)?, get_f64("step", 0.0)?);
+            if !(min.is_finite() && max.is_finite() && min < max && step >= 0.0) {
+                return Err(mlua::Error::RuntimeError(format!("Slider '{}' needs min < max and step >= 0", key)));
+            }
+            (SettingKind::Slider { min, max, step }, Value::from(get_f64("default", min)?))
+        }
+        "toggle" => {
+            let default = match opts {
+                Some(o) => o.get::<_, Option<bool>>("default")?.unwrap_or(false),
+                None => false,
+            };
+            (SettingKind::Toggle, Value::Bool(default))
+        }
+        "choice" => {
+            let options = match opts {
+                Some(o) => o.get::<_, Option<Vec<String>>>("options")?.unwrap_or_default(),
+                None => Vec::new(),
+            };
+            if options.is_empty() || options.len() > MAX_CHOICES {
+                return Err(mlua::Error::RuntimeError(format!("Choice '{}' needs 1 to {} options", key, MAX_CHOICES)));
+            }
+            let default = match opts {
+                Some(o) => o.get::<_, Option<String>>("default")?,
+                None => None,
+            };
+            let default = default.unwrap_or_else(|| options[0].clone());
+            (SettingKind::Choice(options), Value::from(default))
+        }
+        other => return Err(mlua::Error::RuntimeError(format!("Unknown setting type '{}' (expected slider, toggle or choice)", other))),
+    };
+    let mut setting = Setting { label: label.unwrap_or_else(|| key.clone()), key, kind, default: Value::Null };
+    setting.default = setting.accept(&default)
+        .ok_or_else(|| mlua::Error::RuntimeError(format!("Default {} is not a value of setting '{}'", default, setting.key)))?;
+    Ok(setting)
+}
+
+#[derive(Clone)]
+pub struct CommandBuffer {
+    data: Arc<Mutex<BytesMut>>,
+}
+
+impl CommandBuffer {
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(BytesMut::with_capacity(1024))),
+        }
+    }
+
+    pub fn clear(&self) {
         let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_STOP_SOUND);
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
+        data.clear();
     }
 
-    fn cmd_set_volume(&self, name: &str, volume: f32) {
+    pub fn get_bytes(&self) -> Bytes {
+        let data = self.data.lock().unwrap();
+        data.clone().freeze()
+    }
+
+    // --- Primitive Writers ---
+
+    fn cmd_clear_screen(&self, r: u8, g: u8, b: u8) {
         let mut data = self.data.lock().unwrap();
-        data.put_u8(OP_SET_VOLUME);
-        let name_bytes = name.as_bytes();
-        data.put_u16_le(name_bytes.len() as u16);
-        data.put_slice(name_bytes);
-        data.put_f32_le(volume);
+        data.put_u8(OP_CLEAR);
+        data.put_u8(r);
+        data.put_u8(g);
+        data.put_u8(b);
     }
 
-    pub fn append(&self, other: &CommandBuffer) {
+    fn cmd_set_color(&self, r: u8, g: u8, b: u8, a: u8) {
         let mut data = self.data.lock().unwrap();
-        let other_data = other.data.lock().unwrap();
-        data.extend_from_slice(&other_data);
+        data.put_u8(OP_SET_COLOR);
+        data.put_u8(r);
+        data.put_u8(g);
+        data.put_u8(b);
+        data.put_u8(a);
     }
-}
 
-pub struct GameState {
-    lua: Lua,
-    command_buffer: CommandBuffer,
-    event_buffer: CommandBuffer,
-    current_mode: Arc<Mutex<GameMode>>,
-}
+    fn cmd_define_palette(&self, colors: &[[u8; 4]]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DEFINE_PALETTE);
+        data.put_u16_le(colors.len() as u16);
+        for rgba in colors {
+            data.put_slice(rgba);
+        }
+    }
 
-impl GameState {
-    pub fn new(script_content: &str, script_path: Option<&std::path::Path>) -> anyhow::Result<Self> {
-        // SANDBOX SECURITY:
-        // 1. Only load safe standard libraries. NO IO, NO OS, NO DEBUG.
-        let libs = StdLib::MATH | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::COROUTINE | StdLib::PACKAGE;
-        let lua = Lua::new_with(libs, LuaOptions::default())?;
-        
-        // 2. Set Memory Limit (128 MB) to prevent RAM exhaustion DoS
-        lua.set_memory_limit(128 * 1024 * 1024)?;
+    // Sets the fill and stroke color like OP_SET_COLOR, from the palette (index from 0)
+    fn cmd_palette_color(&self, index: u8) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_PALETTE_COLOR);
+        data.put_u8(index);
+    }
 
-        // 3. Configure package.path to allow requiring local modules
-        // We set it to "./?.lua" so scripts can require files relative to the working directory (which is set to the game dir by the server)
-        {
-            let globals = lua.globals();
-            let package: mlua::Table = globals.get("package")?;
-            
-            let mut path_str = "./?.lua".to_string();
-            if let Some(p) = script_path {
-                if let Some(parent) = p.parent() {
-                    if let Some(parent_str) = parent.to_str() {
-                        path_str.push_str(";");
-                        path_str.push_str(parent_str);
-                        path_str.push_str("/?.lua");
+    // kind 0 = linear (x1, y1) -> (x2, y2), 1 = radial from circle (x1, y1, r1) to (x2, y2, r2)
+    fn cmd_set_gradient(&self, kind: u8, from: (f32, f32, f32), to: (f32, f32, f32), stops: &[GradientStop]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_GRADIENT);
+        data.put_u8(kind);
+        for v in [from.0, from.1, from.2, to.0, to.1, to.2] {
+            data.put_f32_le(v);
+        }
+        data.put_u8(stops.len() as u8);
+        for stop in stops {
+            data.put_f32_le(stop.offset);
+            data.put_slice(&stop.rgba);
+        }
+    }
+
+    // shape u8 (0 square, 1 circle), u16 count, then per particle: x f32, y f32,
+    // size u16 (1/16 px), rgba
+    fn cmd_draw_particles(&self, particles: &[ParticleSprite], circles: bool) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_PARTICLES);
+        data.put_u8(if circles { 1 } else { 0 });
+        data.put_u16_le(particles.len() as u16);
+        for p in particles {
+            data.put_f32_le(p.x);
+            data.put_f32_le(p.y);
+            data.put_u16_le((p.size.max(0.0) * 16.0).round().min(u16::MAX as f32) as u16);
+            data.put_slice(&p.rgba);
+        }
+    }
+
+    // Per trail: rgba, head width f32, u16 count, then points (head first)
+    fn cmd_draw_trails(&self, trails: &[TrailSprite]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_TRAILS);
+        data.put_u16_le(trails.len().min(u16::MAX as usize) as u16);
+        for trail in trails.iter().take(u16::MAX as usize) {
+            data.put_slice(&trail.rgba);
+            data.put_f32_le(trail.width);
+            data.put_u16_le(trail.points.len() as u16);
+            for &(x, y) in trail.points {
+                data.put_f32_le(x);
+                data.put_f32_le(y);
+            }
+        }
+    }
+
+    // u8 count, then per action: name, u8 count, key codes (first = what mobile buttons send)
+    fn cmd_action_keys(&self, bindings: &std::collections::BTreeMap<&str, &Binding>) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_ACTION_KEYS);
+        data.put_u8(bindings.len().min(u8::MAX as usize) as u8);
+        for (action, binding) in bindings.iter().take(u8::MAX as usize) {
+            data.put_u16_le(action.len() as u16);
+            data.put_slice(action.as_bytes());
+            data.put_u8(binding.keys.len().min(u8::MAX as usize) as u8);
+            data.put_slice(&binding.keys[..binding.keys.len().min(u8::MAX as usize)]);
+        }
+    }
+
+    // u8 count, then per setting: key, label, kind u8 (0 slider, 1 toggle, 2 choice) and
+    // slider: min, max, step, default f32 / toggle: default u8 / choice: u8 count, options, default index u8
+    fn cmd_settings(&self, settings: &[Setting]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SETTINGS);
+        data.put_u8(settings.len() as u8);
+        for setting in settings {
+            for text in [&setting.key, &setting.label] {
+                data.put_u16_le(text.len() as u16);
+                data.put_slice(text.as_bytes());
+            }
+            match &setting.kind {
+                SettingKind::Slider { min, max, step } => {
+                    data.put_u8(0);
+                    data.put_f32_le(*min as f32);
+                    data.put_f32_le(*max as f32);
+                    data.put_f32_le(*step as f32);
+                    data.put_f32_le(setting.default.as_f64().unwrap_or(*min) as f32);
+                }
+                SettingKind::Toggle => {
+                    data.put_u8(1);
+                    data.put_u8(setting.default.as_bool().unwrap_or(false) as u8);
+                }
+                SettingKind::Choice(options) => {
+                    data.put_u8(2);
+                    data.put_u8(options.len() as u8);
+                    for option in options {
+                        data.put_u16_le(option.len() as u16);
+                        data.put_slice(option.as_bytes());
                     }
+                    let default = options.iter().position(|o| setting.default.as_str() == Some(o.as_str()));
+                    data.put_u8(default.unwrap_or(0) as u8);
                 }
             }
-            package.set("path", path_str)?;
         }
+    }
 
-        let command_buffer = CommandBuffer::new();
-        let event_buffer = CommandBuffer::new();
-        let current_mode = Arc::new(Mutex::new(GameMode::Update));
-        
-        // Expose API to Lua
-        {
-            let globals = lua.globals();
-            let api = lua.create_table()?;
-            
-            let buf_clone = command_buffer.clone();
-            api.set("clear_screen", lua.create_function(move |_, (r, g, b): (u8, u8, u8)| {
-                buf_clone.cmd_clear_screen(r, g, b);
-                Ok(())
+    // id u16, map width u16, map height u16, chunk x u16, y u16, w u8, h u8, then w * h u16 tiles
+    fn cmd_tilemap_chunk(&self, id: u16, map_w: u16, map_h: u16, chunk: &TileChunk) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_TILEMAP_CHUNK);
+        data.put_u16_le(id);
+        data.put_u16_le(map_w);
+        data.put_u16_le(map_h);
+        data.put_u16_le(chunk.x);
+        data.put_u16_le(chunk.y);
+        data.put_u8(chunk.w);
+        data.put_u8(chunk.h);
+        for &tile in &chunk.tiles {
+            data.put_u16_le(tile);
+        }
+    }
+
+    fn cmd_draw_tilemap(&self, id: u16, tileset: &str, src_size: u16, x: f32, y: f32, tile_size: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_TILEMAP);
+        data.put_u16_le(id);
+        let name_bytes = tileset.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_u16_le(src_size);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(tile_size);
+    }
+
+    // id u32, field mask u8, then the fields in the mask (see scene.rs), in bit order
+    fn cmd_node(&self, opcode: u8, id: u32, mask: u8, node: &NodeState) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(opcode);
+        data.put_u32_le(id);
+        data.put_u8(mask);
+        if mask & scene::FIELD_TRANSFORM != 0 {
+            data.put_f32_le(node.x);
+            data.put_f32_le(node.y);
+            data.put_f32_le(node.rotation);
+            data.put_f32_le(node.scale);
+        }
+        if mask & scene::FIELD_SHAPE != 0 {
+            let put_str = |data: &mut BytesMut, s: &str| {
+                data.put_u16_le(s.len() as u16);
+                data.put_slice(s.as_bytes());
+            };
+            match &node.shape {
+                Shape::None => data.put_u8(0),
+                Shape::Rect { w, h } => {
+                    data.put_u8(1);
+                    data.put_f32_le(*w);
+                    data.put_f32_le(*h);
+                }
+                Shape::Circle { r } => {
+                    data.put_u8(2);
+                    data.put_f32_le(*r);
+                }
+                Shape::Sprite { image, w, h } => {
+                    data.put_u8(3);
+                    put_str(&mut data, image);
+                    data.put_f32_le(*w);
+                    data.put_f32_le(*h);
+                }
+                Shape::Text { text } => {
+                    data.put_u8(4);
+                    put_str(&mut data, text);
+                }
+            }
+        }
+        if mask & scene::FIELD_COLOR != 0 {
+            data.put_slice(&node.color);
+        }
+        if mask & scene::FIELD_Z != 0 {
+            data.put_i8(node.z);
+        }
+        if mask & scene::FIELD_VISIBLE != 0 {
+            data.put_u8(node.visible as u8);
+        }
+        if mask & scene::FIELD_PARENT != 0 {
+            data.put_u32_le(node.parent);
+        }
+    }
+
+    fn cmd_node_destroy(&self, id: u32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_NODE_DESTROY);
+        data.put_u32_le(id);
+    }
+
+    // u32 count, then every live node id: the client drops any other node
+    fn cmd_node_sync(&self, ids: &[u32]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_NODE_SYNC);
+        data.put_u32_le(ids.len() as u32);
+        for &id in ids {
+            data.put_u32_le(id);
+        }
+    }
+
+    // u16 count, then per node: id u32, x, y, rotation
+    fn cmd_scene_moves(&self, moves: &[(u32, f32, f32, f32)]) {
+        let mut data = self.data.lock().unwrap();
+        for chunk in moves.chunks(u16::MAX as usize) {
+            data.put_u8(OP_SCENE_UPDATE);
+            data.put_u16_le(chunk.len() as u16);
+            for &(id, x, y, rotation) in chunk {
+                data.put_u32_le(id);
+                data.put_f32_le(x);
+                data.put_f32_le(y);
+                data.put_f32_le(rotation);
+            }
+        }
+    }
+
+    fn cmd_draw_scene(&self) {
+        self.data.lock().unwrap().put_u8(OP_DRAW_SCENE);
+    }
+
+    fn cmd_set_blend_mode(&self, mode: u8) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_BLEND_MODE);
+        data.put_u8(mode);
+    }
+
+    fn cmd_fill_rect(&self, x: f32, y: f32, w: f32, h: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FILL_RECT);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(w);
+        data.put_f32_le(h);
+    }
+
+    fn cmd_draw_line(&self, x1: f32, y1: f32, x2: f32, y2: f32, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_LINE);
+        data.put_f32_le(x1);
+        data.put_f32_le(y1);
+        data.put_f32_le(x2);
+        data.put_f32_le(y2);
+        data.put_f32_le(width);
+    }
+
+    fn cmd_fill_circle(&self, x: f32, y: f32, r: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FILL_CIRCLE);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(r);
+    }
+
+    fn cmd_draw_circle(&self, x: f32, y: f32, r: f32, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_CIRCLE);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(r);
+        data.put_f32_le(width);
+    }
+
+    // Angles in radians, clockwise from +x. inner_r 0 = pie slice, otherwise a ring segment
+    fn cmd_fill_arc(&self, x: f32, y: f32, r: f32, start: f32, end: f32, inner_r: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FILL_ARC);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(r);
+        data.put_f32_le(start);
+        data.put_f32_le(end);
+        data.put_f32_le(inner_r);
+    }
+
+    fn cmd_draw_arc(&self, x: f32, y: f32, r: f32, start: f32, end: f32, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_ARC);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(r);
+        data.put_f32_le(start);
+        data.put_f32_le(end);
+        data.put_f32_le(width);
+    }
+
+    fn cmd_fill_ellipse(&self, x: f32, y: f32, rx: f32, ry: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FILL_ELLIPSE);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(rx);
+        data.put_f32_le(ry);
+    }
+
+    fn cmd_draw_ellipse(&self, x: f32, y: f32, rx: f32, ry: f32, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_ELLIPSE);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(rx);
+        data.put_f32_le(ry);
+        data.put_f32_le(width);
+    }
+
+    // Layout: mode (0 = fill, 1 = stroke), width, vertex count, then x/y pairs
+    fn cmd_draw_poly(&self, points: &[(f32, f32)], fill: bool, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_POLY);
+        data.put_u8(if fill { 0 } else { 1 });
+        data.put_f32_le(width);
+        data.put_u16_le(points.len() as u16);
+        for &(x, y) in points {
+            data.put_f32_le(x);
+            data.put_f32_le(y);
+        }
+    }
+
+    // Open line strip: width, vertex count, then x/y pairs
+    fn cmd_draw_polyline(&self, points: &[(f32, f32)], width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_POLYLINE);
+        data.put_f32_le(width);
+        data.put_u16_le(points.len() as u16);
+        for &(x, y) in points {
+            data.put_f32_le(x);
+            data.put_f32_le(y);
+        }
+    }
+
+    // u16 triangle count, then 3 x/y pairs per triangle
+    fn cmd_fill_triangles(&self, triangles: &[[(f32, f32); 3]]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FILL_TRIANGLES);
+        data.put_u16_le(triangles.len() as u16);
+        for &(x, y) in triangles.iter().flatten() {
+            data.put_f32_le(x);
+            data.put_f32_le(y);
+        }
+    }
+
+    // kind u8 (0 quadratic, 1 cubic), closed u8, width f32, start x/y, u16 segment count,
+    // then per segment the control point(s) and end point
+    fn cmd_draw_curve(&self, curve: &Curve, width: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_CURVE);
+        data.put_u8(if curve.cubic { 1 } else { 0 });
+        data.put_u8(if curve.closed { 1 } else { 0 });
+        data.put_f32_le(width);
+        data.put_f32_le(curve.start.0);
+        data.put_f32_le(curve.start.1);
+        data.put_u16_le(curve.segments.len() as u16);
+        for &(x, y) in curve.segments.iter().flatten() {
+            data.put_f32_le(x);
+            data.put_f32_le(y);
+        }
+    }
+
+    fn cmd_draw_text(&self, text: &str, x: f32, y: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_TEXT);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        let bytes = text.as_bytes();
+        data.put_u16_le(bytes.len() as u16);
+        data.put_slice(bytes);
+    }
+
+    fn cmd_load_sound(&self, name: &str, url: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_LOAD_SOUND);
+        
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+
+        let url_bytes = url.as_bytes();
+        data.put_u16_le(url_bytes.len() as u16);
+        data.put_slice(url_bytes);
+    }
+
+    fn cmd_play_sound(&self, name: &str, loop_sound: bool, volume: f32, pitch: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_PLAY_SOUND);
+        
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        
+        data.put_u8(if loop_sound { 1 } else { 0 });
+        data.put_f32_le(volume);
+        data.put_f32_le(pitch);
+    }
+
+    fn cmd_play_sound_panned(&self, name: &str, loop_sound: bool, volume: f32, pan: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_PLAY_SOUND_PANNED);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_u8(if loop_sound { 1 } else { 0 });
+        data.put_f32_le(volume);
+        data.put_f32_le(pan);
+    }
+
+    fn cmd_load_music(&self, name: &str, url: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_LOAD_MUSIC);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        let url_bytes = url.as_bytes();
+        data.put_u16_le(url_bytes.len() as u16);
+        data.put_slice(url_bytes);
+    }
+
+    fn cmd_play_music(&self, name: &str, loop_music: bool, volume: f32, fade_in: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_PLAY_MUSIC);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_u8(if loop_music { 1 } else { 0 });
+        data.put_f32_le(volume);
+        data.put_f32_le(fade_in);
+    }
+
+    fn cmd_stop_music(&self, name: &str, fade_out: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_STOP_MUSIC);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_f32_le(fade_out);
+    }
+
+    fn cmd_stop_sound(&self, name: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_STOP_SOUND);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+    }
+
+    fn cmd_set_volume(&self, name: &str, volume: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_VOLUME);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_f32_le(volume);
+    }
+
+    fn cmd_set_pitch(&self, name: &str, rate: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_PITCH);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_f32_le(rate);
+    }
+
+    fn cmd_set_bus_volume(&self, bus: &str, volume: f32, fade: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_BUS_VOLUME);
+        let bus_bytes = bus.as_bytes();
+        data.put_u16_le(bus_bytes.len() as u16);
+        data.put_slice(bus_bytes);
+        data.put_f32_le(volume);
+        data.put_f32_le(fade);
+    }
+
+    fn cmd_set_sound_bus(&self, name: &str, bus: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_SOUND_BUS);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        let bus_bytes = bus.as_bytes();
+        data.put_u16_le(bus_bytes.len() as u16);
+        data.put_slice(bus_bytes);
+    }
+
+    fn cmd_screen_shake(&self, intensity: f32, duration: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SCREEN_SHAKE);
+        data.put_f32_le(intensity);
+        data.put_f32_le(duration);
+    }
+
+    fn cmd_flash(&self, r: u8, g: u8, b: u8, duration: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_FLASH);
+        data.put_u8(r);
+        data.put_u8(g);
+        data.put_u8(b);
+        data.put_f32_le(duration);
+    }
+
+    fn cmd_vignette(&self, strength: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_VIGNETTE);
+        data.put_f32_le(strength);
+    }
+
+    fn cmd_set_post(&self, effect: &PostEffect) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_POST);
+        data.put_slice(&effect.tint);
+        data.put_f32_le(effect.vignette);
+        data.put_f32_le(effect.brightness);
+        data.put_f32_le(effect.contrast);
+        data.put_u8(if effect.scanlines { 1 } else { 0 });
+    }
+
+    fn cmd_input_config(&self, config: &InputConfig) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_INPUT_CONFIG);
+        data.put_u8(if config.key_repeat { 1 } else { 0 });
+        data.put_f32_le(config.dead_zone);
+        data.put_f32_le(config.joystick_radius);
+    }
+
+    fn cmd_lights(&self, ambient: [u8; 3], lights: &[LightSprite]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_LIGHTS);
+        data.put_slice(&ambient);
+        data.put_u16_le(lights.len().min(u16::MAX as usize) as u16);
+        for light in lights.iter().take(u16::MAX as usize) {
+            data.put_f32_le(light.x);
+            data.put_f32_le(light.y);
+            data.put_f32_le(light.radius);
+            data.put_slice(&light.rgba);
+            let points = &light.shadow[..light.shadow.len().min(u16::MAX as usize)];
+            data.put_u16_le(points.len() as u16);
+            for &(x, y) in points {
+                data.put_f32_le(x);
+                data.put_f32_le(y);
+            }
+        }
+    }
+
+    fn cmd_load_image(&self, name: &str, url: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_LOAD_IMAGE);
+
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+
+        let url_bytes = url.as_bytes();
+        data.put_u16_le(url_bytes.len() as u16);
+        data.put_slice(url_bytes);
+    }
+
+    fn cmd_draw_sprite(&self, name: &str, x: f32, y: f32, w: f32, h: f32, rotation: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_SPRITE);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(w);
+        data.put_f32_le(h);
+        data.put_f32_le(rotation);
+    }
+
+    fn cmd_load_spritesheet(&self, id: u16, url: &str, frame_w: u16, frame_h: u16) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_LOAD_SPRITESHEET);
+        data.put_u16_le(id);
+        let url_bytes = url.as_bytes();
+        data.put_u16_le(url_bytes.len() as u16);
+        data.put_slice(url_bytes);
+        data.put_u16_le(frame_w);
+        data.put_u16_le(frame_h);
+    }
+
+    // Frames are numbered row by row from 0
+    #[allow(clippy::too_many_arguments)]
+    fn cmd_draw_frame(&self, id: u16, frame: u16, x: f32, y: f32, w: f32, h: f32, rotation: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_FRAME);
+        data.put_u16_le(id);
+        data.put_u16_le(frame);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(w);
+        data.put_f32_le(h);
+        data.put_f32_le(rotation);
+    }
+
+    // Margins (left, top, right, bottom) in source pixels, drawn unscaled
+    fn cmd_draw_ninepatch(&self, name: &str, x: f32, y: f32, w: f32, h: f32, margins: [f32; 4]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_NINEPATCH);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(w);
+        data.put_f32_le(h);
+        for m in margins {
+            data.put_f32_le(m);
+        }
+    }
+
+    // name, width u16, height u16, u32 length, then the recorded commands
+    fn cmd_canvas_content(&self, canvas: &Canvas, content: &[u8]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_CANVAS_CONTENT);
+        data.put_u16_le(canvas.name.len() as u16);
+        data.put_slice(canvas.name.as_bytes());
+        data.put_u16_le(canvas.width);
+        data.put_u16_le(canvas.height);
+        data.put_u32_le(content.len() as u32);
+        data.put_slice(content);
+    }
+
+    fn cmd_draw_canvas(&self, name: &str, x: f32, y: f32, w: f32, h: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DRAW_CANVAS);
+        data.put_u16_le(name.len() as u16);
+        data.put_slice(name.as_bytes());
+        data.put_f32_le(x);
+        data.put_f32_le(y);
+        data.put_f32_le(w);
+        data.put_f32_le(h);
+    }
+
+    fn cmd_set_control_scheme(&self, name: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_CONTROL_SCHEME);
+        let name_bytes = name.as_bytes();
+        data.put_u16_le(name_bytes.len() as u16);
+        data.put_slice(name_bytes);
+    }
+
+    fn cmd_set_transform(&self, camera: &Camera) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_TRANSFORM);
+        data.put_f32_le(camera.x);
+        data.put_f32_le(camera.y);
+        data.put_f32_le(camera.zoom);
+        data.put_f32_le(camera.rotation);
+    }
+
+    fn cmd_request_text_input(&self, placeholder: &str, max_len: u16) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_REQUEST_TEXT_INPUT);
+        let bytes = placeholder.as_bytes();
+        data.put_u16_le(bytes.len() as u16);
+        data.put_slice(bytes);
+        data.put_u16_le(max_len);
+    }
+
+    fn cmd_push_transform(&self, tx: f32, ty: f32, rotation: f32, sx: f32, sy: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_PUSH_TRANSFORM);
+        data.put_f32_le(tx);
+        data.put_f32_le(ty);
+        data.put_f32_le(rotation);
+        data.put_f32_le(sx);
+        data.put_f32_le(sy);
+    }
+
+    fn cmd_pop_transform(&self) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_POP_TRANSFORM);
+    }
+
+    fn cmd_set_layer(&self, layer: i8) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_LAYER);
+        data.put_i8(layer);
+    }
+
+    fn cmd_set_text_scale(&self, scale: f32) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_SET_TEXT_SCALE);
+        data.put_f32_le(scale);
+    }
+
+    fn cmd_dev_status(&self, ok: bool, message: &str) {
+        let mut end = message.len().min(MAX_DEV_STATUS_LEN);
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(OP_DEV_STATUS);
+        data.put_u8(if ok { 0 } else { 1 });
+        data.put_u16_le(end as u16);
+        data.put_slice(&message.as_bytes()[..end]);
+    }
+
+    fn cmd_plugin(&self, opcode: u8, payload: &[u8]) {
+        let mut data = self.data.lock().unwrap();
+        data.put_u8(opcode);
+        data.put_u16_le(payload.len() as u16);
+        data.put_slice(payload);
+    }
+
+    // Runs `f` with an empty buffer and returns what it wrote; earlier commands are kept
+    fn capture<T>(&self, f: impl FnOnce() -> T) -> (T, Bytes) {
+        let outer = std::mem::take(&mut *self.data.lock().unwrap());
+        let result = f();
+        let captured = std::mem::replace(&mut *self.data.lock().unwrap(), outer);
+        (result, captured.freeze())
+    }
+
+    // Already-encoded commands (frames rewritten by compat::downgrade)
+    fn cmd_raw(&self, bytes: &[u8]) {
+        self.data.lock().unwrap().put_slice(bytes);
+    }
+
+    fn len(&self) -> usize {
+        self.data.lock().unwrap().len()
+    }
+
+    fn extend_from_slice(&self, bytes: &[u8]) {
+        self.data.lock().unwrap().extend_from_slice(bytes);
+    }
+
+    // What was written since `start`
+    fn bytes_from(&self, start: usize) -> Bytes {
+        Bytes::copy_from_slice(&self.data.lock().unwrap()[start..])
+    }
+
+    pub fn append(&self, other: &CommandBuffer) {
+        let mut data = self.data.lock().unwrap();
+        let other_data = other.data.lock().unwrap();
+        data.extend_from_slice(&other_data);
+    }
+}
+
+/// Standalone frame carrying a development status banner for the browser client
+/// (hot reload result or the current script error). Only sent by hosts in dev mode.
+pub fn dev_status_frame(ok: bool, message: &str) -> Bytes {
+    let buf = CommandBuffer::new();
+    buf.cmd_dev_status(ok, message);
+    buf.get_bytes()
+}
+
+/// Standalone frame for a client the host keeps waiting (a full room, or the script's
+/// on_before_connect queued it): a blank screen with `message`.
+pub fn waiting_frame(message: &str) -> Bytes {
+    let buf = CommandBuffer::new();
+    buf.cmd_clear_screen(16, 16, 24);
+    buf.cmd_set_color(255, 255, 255, 255);
+    buf.cmd_draw_text(message, 40.0, 300.0);
+    buf.get_bytes()
+}
+
+// --- Native Systems ---
+// Rust hooks ticked around the Lua update(), the extension point for embedders and
+// built-in subsystems (timers, tweens...) that need to run every tick.
+
+/// A native tick hook. Receives the Lua state and the frame `dt`.
+pub type System = Box<dyn FnMut(&Lua, f32) -> mlua::Result<()> + Send>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SystemPhase {
+    PreUpdate,  // Before the Lua update(dt)
+    PostUpdate, // After the Lua update(dt)
+}
+
+/// Highest rate api.set_tick_rate accepts (and hosts should run at), in Hz.
+pub const MAX_TICK_RATE: u32 = 240;
+
+/// Rates the script asked for with `api.set_tick_rate(hz, [send_hz])`: how often the host
+/// steps the simulation (update) and, if set, how often it sends each client a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TickRate {
+    pub tick: u32,
+    pub send: Option<u32>,
+}
+
+/// What the script's `on_before_connect(session_id, auth_info)` decided for a new client:
+/// true (or no hook) lets it in, false turns it away, "queue" keeps it waiting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Accept,
+    Reject,
+    Queue,
+}
+
+/// How a session's connection is doing, as measured by the host (`set_client_stats`) and
+/// returned by api.get_client_stats.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClientStats {
+    pub queue: u32,          // Frames waiting to go out
+    pub queue_max: u32,      // Frames that can wait before new ones are dropped
+    pub rtt_ms: Option<f32>, // None until measured
+    pub dropped: u32,        // Frames dropped so far (queue full)
+    pub frame_rate: f32,     // Frames per second the client is being sent
+    pub congested: bool,     // Sent fewer frames: its draw() leaves out cosmetic layers
+}
+
+/// An event recorded by `api.track(name, properties)`, drained by the host (see `take_tracked_events`).
+#[derive(Debug, Clone)]
+pub struct TrackedEvent {
+    pub name: String,
+    pub properties: Value,
+}
+
+pub struct GameState {
+    lua: Lua,
+    command_buffer: CommandBuffer,
+    event_buffer: CommandBuffer,
+    current_mode: Arc<Mutex<GameMode>>,
+    systems: Mutex<Vec<(SystemPhase, System)>>,
+    path_followers: PathFollowers,
+    timers: Timers,
+    emitters: Emitters,
+    tilemaps: Tilemaps,
+    canvases: Canvases,
+    scene: Arc<Mutex<Scene>>,
+    // Session whose frame draw() is building (None outside draw())
+    drawing_session: Arc<Mutex<Option<String>>>,
+    tracked_events: Arc<Mutex<Vec<TrackedEvent>>>,
+    // api.set_tick_rate; None: the host's rates
+    tick_rate: Arc<Mutex<Option<TickRate>>>,
+    // Reported by the host for each connected session
+    client_stats: Arc<Mutex<HashMap<String, ClientStats>>>,
+    // Sessions watching without playing (on_spectate), in join order
+    spectators: Arc<Mutex<Vec<String>>>,
+    // Commands addressed to one client, delivered with its next frame
+    session_commands: Arc<Mutex<HashMap<String, CommandBuffer>>>,
+    // Open text prompts: session -> max length (unsolicited text is ignored)
+    text_prompts: Arc<Mutex<HashMap<String, usize>>>,
+    accessibility: Arc<Mutex<HashMap<String, Accessibility>>>,
+    // Settings of the session being drawn (default outside draw())
+    active_accessibility: Arc<Mutex<Accessibility>>,
+    camera: Arc<Mutex<Camera>>,
+    // Camera and HUD flag to restore at api.ui_end() (None outside a UI block)
+    ui_camera: Arc<Mutex<Option<(Camera, bool)>>>,
+    viewports: Arc<Mutex<HashMap<String, Viewport>>>,
+    listeners: Arc<Mutex<HashMap<String, (f32, f32)>>>,
+    // api.play_sound_at calls made in update(), mixed for each client in draw()
+    positional_sounds: Arc<Mutex<Vec<PositionalSound>>>,
+    // Every URL passed to load_sound/load_image (for startup asset validation)
+    requested_assets: Arc<Mutex<BTreeSet<String>>>,
+    // Culling state of the session being drawn (no viewport outside draw())
+    culling: Arc<Mutex<Culling>>,
+    // Opcode groups per client (compat::CAP_*); frames for other sessions are sent as-is
+    capabilities: Arc<Mutex<HashMap<String, u32>>>,
+    // Identity the host verified for each session (set_auth_info)
+    auth_info: Arc<Mutex<HashMap<String, Value>>>,
+    post_effects: Arc<Mutex<PostEffects>>,
+    camera_rigs: Arc<Mutex<CameraRigs>>,
+    lights: Arc<Mutex<Lights>>,
+    trails: Arc<Mutex<Trails>>,
+    float_texts: Arc<Mutex<FloatTexts>>,
+    settings: Arc<Mutex<Settings>>,
+    input_states: Arc<Mutex<InputStates>>,
+    input_configs: Arc<Mutex<InputConfigs>>,
+    actions: Arc<Mutex<Actions>>,
+    // api.send_message: JSON messages waiting for their session's host to deliver them
+    outbox: Arc<Mutex<Outbox>>,
+    // Camera shake of the session being drawn, in screen pixels ((0, 0) outside draw())
+    shake_offset: Arc<Mutex<(f32, f32)>>,
+    // api.draw_shared segment of this frame (broadcast.rs)
+    shared_draw: Arc<Mutex<SharedDraw>>,
+    // api.define_palette colors, for clients without CAP_PALETTES
+    color_palette: Arc<Mutex<Vec<[u8; 4]>>>,
+    // Where required modules come from and who requires them
+    modules: Arc<Mutex<ModuleGraph>>,
+}
+
+impl GameState {
+    pub fn new(script_content: &str, script_path: Option<&std::path::Path>) -> Result<Self, EngineError> {
+        Self::with_plugins(script_content, script_path, &mut [])
+    }
+
+    /// Like `new`, but lets each plugin register its Lua functions and systems before the script runs.
+    pub fn with_plugins(script_content: &str, script_path: Option<&std::path::Path>, plugins: &mut [Box<dyn Plugin>]) -> Result<Self, EngineError> {
+        Self::with_shared_data(script_content, script_path, plugins, SharedData::default())
+    }
+
+    /// Like `with_plugins`, with read-only data shared with other games (`api.shared_get`).
+    pub fn with_shared_data(script_content: &str, script_path: Option<&std::path::Path>, plugins: &mut [Box<dyn Plugin>], shared: SharedData) -> Result<Self, EngineError> {
+        Self::load(script_content, script_path, plugins, shared).map_err(EngineError::init)
+    }
+
+    fn load(script_content: &str, script_path: Option<&std::path::Path>, plugins: &mut [Box<dyn Plugin>], shared: SharedData) -> anyhow::Result<Self> {
+        // SANDBOX SECURITY:
+        // 1. Only load safe standard libraries. NO IO, NO OS, NO DEBUG.
+        let lua = Lua::new_with(sandbox_libs(), LuaOptions::default())?;
+
+        // 2. Set Memory Limit (128 MB) to prevent RAM exhaustion DoS
+        // LuaJIT uses its own allocator, so the limit is not available there (the server warns
+        // on startup, MANUAL: Sandbox).
+        #[cfg(not(feature = "luajit"))]
+        lua.set_memory_limit(128 * 1024 * 1024)?;
+
+        // 3. Configure package.path to allow requiring local modules
+        // We set it to "./?.lua" so scripts can require files relative to the working directory (which is set to the game dir by the server)
+        {
+            let globals = lua.globals();
+            let package: mlua::Table = globals.get("package")?;
+            
+            let mut path_str = "./?.lua".to_string();
+            if let Some(p) = script_path {
+                if let Some(parent) = p.parent() {
+                    if let Some(parent_str) = parent.to_str() {
+                        path_str.push_str(";");
+                        path_str.push_str(parent_str);
+                        path_str.push_str("/?.lua");
+                    }
+                }
+            }
+            package.set("path", path_str)?;
+        }
+
+        // 4. Record the module graph for partial reloads (see modules.rs)
+        let modules: Arc<Mutex<ModuleGraph>> = Arc::new(Mutex::new(ModuleGraph::default()));
+        {
+            let globals = lua.globals();
+            let original = lua.create_registry_value(globals.get::<_, Function>("require")?)?;
+            let graph = modules.clone();
+            globals.set("require", lua.create_function(move |lua, name: String| {
+                let package_path: String = lua.globals().get::<_, mlua::Table>("package")?.get("path")?;
+                graph.lock().unwrap().enter(&name, &package_path);
+                let require: Function = lua.registry_value(&original)?;
+                let result = require.call::<_, mlua::MultiValue>(name);
+                graph.lock().unwrap().done();
+                result
+            })?)?;
+        }
+
+        let command_buffer = CommandBuffer::new();
+        let event_buffer = CommandBuffer::new();
+        let current_mode = Arc::new(Mutex::new(GameMode::Update));
+        let mut plugin_systems: Vec<(SystemPhase, System)> = Vec::new();
+        let path_followers = PathFollowers::default();
+        let timers = Timers::default();
+        let emitters = Emitters::default();
+        let tracked_events = Arc::new(Mutex::new(Vec::new()));
+        let tick_rate: Arc<Mutex<Option<TickRate>>> = Arc::new(Mutex::new(None));
+        let client_stats: Arc<Mutex<HashMap<String, ClientStats>>> = Arc::new(Mutex::new(HashMap::new()));
+        let spectators: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let session_commands: Arc<Mutex<HashMap<String, CommandBuffer>>> = Arc::new(Mutex::new(HashMap::new()));
+        let text_prompts: Arc<Mutex<HashMap<String, usize>>> = Arc::new(Mutex::new(HashMap::new()));
+        let accessibility: Arc<Mutex<HashMap<String, Accessibility>>> = Arc::new(Mutex::new(HashMap::new()));
+        let active_accessibility = Arc::new(Mutex::new(Accessibility::default()));
+        let camera = Arc::new(Mutex::new(Camera::SCREEN));
+        let ui_camera: Arc<Mutex<Option<(Camera, bool)>>> = Arc::new(Mutex::new(None));
+        let viewports: Arc<Mutex<HashMap<String, Viewport>>> = Arc::new(Mutex::new(HashMap::new()));
+        let listeners: Arc<Mutex<HashMap<String, (f32, f32)>>> = Arc::new(Mutex::new(HashMap::new()));
+        let positional_sounds: Arc<Mutex<Vec<PositionalSound>>> = Arc::new(Mutex::new(Vec::new()));
+        let culling = Arc::new(Mutex::new(Culling::default()));
+        let tilemaps = Tilemaps::default();
+        let canvases = Canvases::default();
+        let spritesheets = Spritesheets::default();
+        let scene: Arc<Mutex<Scene>> = Arc::new(Mutex::new(Scene::default()));
+        let drawing_session: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let capabilities: Arc<Mutex<HashMap<String, u32>>> = Arc::new(Mutex::new(HashMap::new()));
+        let auth_info: Arc<Mutex<HashMap<String, Value>>> = Arc::new(Mutex::new(HashMap::new()));
+        let color_palette: Arc<Mutex<Vec<[u8; 4]>>> = Arc::new(Mutex::new(Vec::new()));
+        let post_effects: Arc<Mutex<PostEffects>> = Arc::new(Mutex::new(PostEffects::default()));
+        let camera_rigs: Arc<Mutex<CameraRigs>> = Arc::new(Mutex::new(CameraRigs::default()));
+        let lights: Arc<Mutex<Lights>> = Arc::new(Mutex::new(Lights::default()));
+        let trails: Arc<Mutex<Trails>> = Arc::new(Mutex::new(Trails::default()));
+        let float_texts: Arc<Mutex<FloatTexts>> = Arc::new(Mutex::new(FloatTexts::default()));
+        let settings_registry: Arc<Mutex<Settings>> = Arc::new(Mutex::new(Settings::default()));
+        let input_states: Arc<Mutex<InputStates>> = Arc::new(Mutex::new(InputStates::default()));
+        let input_configs: Arc<Mutex<InputConfigs>> = Arc::new(Mutex::new(InputConfigs::default()));
+        let actions: Arc<Mutex<Actions>> = Arc::new(Mutex::new(Actions::default()));
+        let outbox: Arc<Mutex<Outbox>> = Arc::new(Mutex::new(Outbox::default()));
+        // input_map.json next to the script: the game's actions before the script adds its own
+        if let Some(dir) = script_path.and_then(|p| p.parent()) {
+            let path = dir.join(INPUT_MAP_FILE);
+            if let Ok(text) = std::fs::read_to_string(&path) {
+                let map: std::collections::BTreeMap<String, Binding> = serde_json::from_str(&text)
+                    .map_err(|e| anyhow::anyhow!("Invalid {}: {}", path.display(), e))?;
+                let mut actions = actions.lock().unwrap();
+                for (action, binding) in map {
+                    actions.bind(action, binding);
+                }
+            }
+        }
+        let shake_offset = Arc::new(Mutex::new((0.0f32, 0.0f32)));
+        let shared_draw = Arc::new(Mutex::new(SharedDraw::default()));
+        let requested_assets: Arc<Mutex<BTreeSet<String>>> = Arc::new(Mutex::new(BTreeSet::new()));
+        
+        // Expose API to Lua
+        {
+            let globals = lua.globals();
+            let api = lua.create_table()?;
+            
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            api.set("clear_screen", lua.create_function(move |_, (r, g, b): (u8, u8, u8)| {
+                let (r, g, b) = a11y.lock().unwrap().remap(r, g, b);
+                buf_clone.cmd_clear_screen(r, g, b);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            api.set("set_color", lua.create_function(move |_, (r, g, b, a): (u8, u8, u8, Option<u8>)| {
+                let (r, g, b) = a11y.lock().unwrap().remap(r, g, b);
+                buf_clone.cmd_set_color(r, g, b, a.unwrap_or(255));
+                Ok(())
+            })?)?;
+
+            // Palettes: colors sent once, then set by index (2 bytes instead of 5 per change)
+            let buf_clone = command_buffer.clone();
+            let colors = color_palette.clone();
+            api.set("define_palette", lua.create_function(move |_, palette: mlua::Table| {
+                let entries: Vec<mlua::Table> = palette.sequence_values().collect::<mlua::Result<_>>()?;
+                if entries.is_empty() || entries.len() > 256 {
+                    return Err(mlua::Error::RuntimeError(format!("A palette needs 1 to 256 colors (got {})", entries.len())));
+                }
+                let rgba = entries.into_iter().map(|c| {
+                    let a: Option<u8> = c.get(4)?;
+                    Ok([c.get(1)?, c.get(2)?, c.get(3)?, a.unwrap_or(255)])
+                }).collect::<mlua::Result<Vec<[u8; 4]>>>()?;
+                buf_clone.cmd_define_palette(&rgba);
+                *colors.lock().unwrap() = rgba;
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            let colors = color_palette.clone();
+            api.set("set_palette_color", lua.create_function(move |_, index: usize| {
+                let [r, g, b, a] = index.checked_sub(1).and_then(|i| colors.lock().unwrap().get(i).copied())
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("No palette color {}", index)))?;
+                let a11y = a11y.lock().unwrap();
+                if a11y.remaps_colors() {
+                    // The client's palette holds the original colors
+                    let (r, g, b) = a11y.remap(r, g, b);
+                    buf_clone.cmd_set_color(r, g, b, a);
+                } else {
+                    buf_clone.cmd_palette_color((index - 1) as u8);
+                }
+                Ok(())
+            })?)?;
+
+            // Gradients replace the fill color until the next set_color
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            api.set("set_linear_gradient", lua.create_function(move |_, (x1, y1, x2, y2, stops): (f32, f32, f32, f32, mlua::Table)| {
+                let stops = read_gradient_stops(stops, &a11y.lock().unwrap())?;
+                buf_clone.cmd_set_gradient(0, (x1, y1, 0.0), (x2, y2, 0.0), &stops);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            api.set("set_radial_gradient", lua.create_function(move |_, (x, y, r, stops, inner): (f32, f32, f32, mlua::Table, Option<f32>)| {
+                let stops = read_gradient_stops(stops, &a11y.lock().unwrap())?;
+                buf_clone.cmd_set_gradient(1, (x, y, inner.unwrap_or(0.0).max(0.0)), (x, y, r.max(0.0)), &stops);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            api.set("set_blend_mode", lua.create_function(move |_, mode: String| {
+                let code = match mode.as_str() {
+                    "alpha" => 0,
+                    "add" => 1,
+                    "multiply" => 2,
+                    other => return Err(mlua::Error::RuntimeError(format!("Unknown blend mode '{}' (expected alpha, add or multiply)", other))),
+                };
+                buf_clone.cmd_set_blend_mode(code);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("fill_rect", lua.create_function(move |_, (x, y, w, h): (f32, f32, f32, f32)| {
+                if cull.lock().unwrap().visible_rect(x, y, w, h) {
+                    buf_clone.cmd_fill_rect(x, y, w, h);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_line", lua.create_function(move |_, (x1, y1, x2, y2, w): (f32, f32, f32, f32, Option<f32>)| {
+                let w = w.unwrap_or(1.0);
+                let pad = w.abs() / 2.0;
+                if cull.lock().unwrap().visible(x1.min(x2) - pad, y1.min(y2) - pad, x1.max(x2) + pad, y1.max(y2) + pad) {
+                    buf_clone.cmd_draw_line(x1, y1, x2, y2, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("fill_circle", lua.create_function(move |_, (x, y, r): (f32, f32, f32)| {
+                if cull.lock().unwrap().visible_circle(x, y, r, r) {
+                    buf_clone.cmd_fill_circle(x, y, r);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_circle", lua.create_function(move |_, (x, y, r, w): (f32, f32, f32, Option<f32>)| {
+                let w = w.unwrap_or(1.0);
+                let outer = r.abs() + w.abs() / 2.0;
+                if cull.lock().unwrap().visible_circle(x, y, outer, outer) {
+                    buf_clone.cmd_draw_circle(x, y, r, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("fill_arc", lua.create_function(move |_, (x, y, r, start, end, inner): (f32, f32, f32, f32, f32, Option<f32>)| {
+                if cull.lock().unwrap().visible_circle(x, y, r.abs(), r.abs()) {
+                    buf_clone.cmd_fill_arc(x, y, r, start, end, inner.unwrap_or(0.0));
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_arc", lua.create_function(move |_, (x, y, r, start, end, w): (f32, f32, f32, f32, f32, Option<f32>)| {
+                let w = w.unwrap_or(1.0);
+                let outer = r.abs() + w.abs() / 2.0;
+                if cull.lock().unwrap().visible_circle(x, y, outer, outer) {
+                    buf_clone.cmd_draw_arc(x, y, r, start, end, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("fill_polygon", lua.create_function(move |_, points: mlua::Table| {
+                let points = read_polygon(points)?;
+                if !polygon_visible(&cull.lock().unwrap(), &points, 0.0) {
+                    return Ok(());
+                }
+                // Concave outlines go out as triangles, so every client fills the same shape
+                if points.len() > 3 && !triangulate::is_convex(&points) {
+                    let triangles: Vec<[(f32, f32); 3]> = triangulate::triangulate(&points).into_iter()
+                        .map(|t| t.map(|i| points[i]))
+                        .collect();
+                    buf_clone.cmd_fill_triangles(&triangles);
+                } else {
+                    buf_clone.cmd_draw_poly(&points, true, 0.0);
+                }
+                Ok(())
+            })?)?;
+
+            // One command for a whole strip of connected lines (trails, lightning)
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_polyline", lua.create_function(move |_, (points, w): (mlua::Table, Option<f32>)| {
+                let points = read_polygon(points)?;
+                let w = w.unwrap_or(1.0);
+                if points.len() >= 2 && polygon_visible(&cull.lock().unwrap(), &points, w.abs() / 2.0) {
+                    buf_clone.cmd_draw_polyline(&points, w);
+                }
+                Ok(())
+            })?)?;
+
+            // Triangles covering an outline (concave allowed), each as {x1, y1, x2, y2, x3, y3}
+            api.set("triangulate", lua.create_function(|lua, points: mlua::Table| {
+                let points = read_polygon(points)?;
+                let triangles = lua.create_table()?;
+                for t in triangulate::triangulate(&points) {
+                    triangles.push(t.iter().flat_map(|&i| [points[i].0, points[i].1]).collect::<Vec<f32>>())?;
+                }
+                Ok(triangles)
+            })?)?;
+
+            // api.geom: the engine's own geometry routines (geom.rs); vectors are passed and
+            // returned as x, y pairs
+            let geom_api = lua.create_table()?;
+            geom_api.set("lerp", lua.create_function(|_, (a, b, t): (f32, f32, f32)| Ok(geom::lerp(a, b, t)))?)?;
+            geom_api.set("lerp_vec", lua.create_function(|_, (x1, y1, x2, y2, t): (f32, f32, f32, f32, f32)| {
+                Ok((geom::lerp(x1, x2, t), geom::lerp(y1, y2, t)))
+            })?)?;
+            geom_api.set("length", lua.create_function(|_, (x, y): (f32, f32)| Ok(geom::length((x, y))))?)?;
+            geom_api.set("distance", lua.create_function(|_, (x1, y1, x2, y2): (f32, f32, f32, f32)| {
+                Ok(geom::length((x2 - x1, y2 - y1)))
+            })?)?;
+            geom_api.set("normalize", lua.create_function(|_, (x, y): (f32, f32)| Ok(geom::normalize((x, y))))?)?;
+            geom_api.set("dot", lua.create_function(|_, (ax, ay, bx, by): (f32, f32, f32, f32)| Ok(geom::dot((ax, ay), (bx, by))))?)?;
+            geom_api.set("cross", lua.create_function(|_, (ax, ay, bx, by): (f32, f32, f32, f32)| Ok(geom::cross((ax, ay), (bx, by))))?)?;
+            geom_api.set("rotate", lua.create_function(|_, (x, y, angle): (f32, f32, f32)| Ok(geom::rotate((x, y), angle)))?)?;
+            geom_api.set("reflect", lua.create_function(|_, (vx, vy, nx, ny): (f32, f32, f32, f32)| Ok(geom::reflect((vx, vy), (nx, ny))))?)?;
+
+            // x, y, t, u where the segments cross (t, u: fractions along each), or nil
+            geom_api.set("segment_intersection", lua.create_function(|_, (x1, y1, x2, y2, x3, y3, x4, y4): (f32, f32, f32, f32, f32, f32, f32, f32)| {
+                Ok(match geom::segment_intersection((x1, y1), (x2, y2), (x3, y3), (x4, y4)) {
+                    Some((t, u)) => (Some(geom::lerp(x1, x2, t)), Some(geom::lerp(y1, y2, t)), Some(t), Some(u)),
+                    None => (None, None, None, None),
+                })
+            })?)?;
+
+            // Distance from a point to a segment, then the closest point: dist, x, y
+            geom_api.set("segment_distance", lua.create_function(|_, (px, py, x1, y1, x2, y2): (f32, f32, f32, f32, f32, f32)| {
+                let ((cx, cy), _) = geom::closest_point_on_segment((px, py), (x1, y1), (x2, y2));
+                Ok((geom::length((px - cx, py - cy)), cx, cy))
+            })?)?;
+
+            // Gap between a circle and a segment (negative when they overlap), then the
+            // closest point on the segment: gap, x, y
+            geom_api.set("circle_segment_distance", lua.create_function(|_, (cx, cy, r, x1, y1, x2, y2): (f32, f32, f32, f32, f32, f32, f32)| {
+                let ((px, py), _) = geom::closest_point_on_segment((cx, cy), (x1, y1), (x2, y2));
+                Ok((geom::length((cx - px, cy - py)) - r, px, py))
+            })?)?;
+
+            geom_api.set("point_in_polygon", lua.create_function(|_, (x, y, points): (f32, f32, mlua::Table)| {
+                Ok(geom::point_in_polygon((x, y), &read_polygon(points)?))
+            })?)?;
+            api.set("geom", geom_api)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_polygon", lua.create_function(move |_, (points, w): (mlua::Table, Option<f32>)| {
+                let points = read_polygon(points)?;
+                let w = w.unwrap_or(1.0);
+                if polygon_visible(&cull.lock().unwrap(), &points, w.abs() / 2.0) {
+                    buf_clone.cmd_draw_poly(&points, false, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_bezier", lua.create_function(move |_, (x1, y1, cx, cy, x2, y2, w): (f32, f32, f32, f32, f32, f32, Option<f32>)| {
+                let curve = Curve { cubic: false, closed: false, start: (x1, y1), segments: vec![vec![(cx, cy), (x2, y2)]] };
+                let w = w.unwrap_or(1.0);
+                if polygon_visible(&cull.lock().unwrap(), &curve.points(), w.abs() / 2.0) {
+                    buf_clone.cmd_draw_curve(&curve, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_cubic_bezier", lua.create_function(move |_, args: mlua::Variadic<f32>| {
+                if args.len() != 8 && args.len() != 9 {
+                    return Err(mlua::Error::RuntimeError("draw_cubic_bezier expects x1, y1, c1x, c1y, c2x, c2y, x2, y2, [width]".into()));
+                }
+                let a = &args;
+                let curve = Curve { cubic: true, closed: false, start: (a[0], a[1]), segments: vec![vec![(a[2], a[3]), (a[4], a[5]), (a[6], a[7])]] };
+                let w = a.get(8).copied().unwrap_or(1.0);
+                if polygon_visible(&cull.lock().unwrap(), &curve.points(), w.abs() / 2.0) {
+                    buf_clone.cmd_draw_curve(&curve, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_spline", lua.create_function(move |_, (points, w, closed): (mlua::Table, Option<f32>, Option<bool>)| {
+                let points = read_polygon(points)?;
+                if points.len() < 2 {
+                    return Ok(());
+                }
+                let curve = spline_through(&points, closed.unwrap_or(false));
+                let w = w.unwrap_or(1.0);
+                if polygon_visible(&cull.lock().unwrap(), &curve.points(), w.abs() / 2.0) {
+                    buf_clone.cmd_draw_curve(&curve, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("fill_ellipse", lua.create_function(move |_, (x, y, rx, ry): (f32, f32, f32, f32)| {
+                if cull.lock().unwrap().visible_circle(x, y, rx, ry) {
+                    buf_clone.cmd_fill_ellipse(x, y, rx, ry);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_ellipse", lua.create_function(move |_, (x, y, rx, ry, w): (f32, f32, f32, f32, Option<f32>)| {
+                let w = w.unwrap_or(1.0);
+                let pad = w.abs() / 2.0;
+                if cull.lock().unwrap().visible_circle(x, y, rx.abs() + pad, ry.abs() + pad) {
+                    buf_clone.cmd_draw_ellipse(x, y, rx, ry, w);
+                }
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            api.set("draw_text", lua.create_function(move |_, (text, x, y): (String, f32, f32)| {
+                buf_clone.cmd_draw_text(&text, x, y);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let assets = requested_assets.clone();
+            api.set("load_sound", lua.create_function(move |_, (name, url, bus): (String, String, Option<String>)| {
+                buf_clone.cmd_load_sound(&name, &url);
+                if let Some(bus) = bus {
+                    buf_clone.cmd_set_sound_bus(&name, &bus);
+                }
+                assets.lock().unwrap().insert(url);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let assets = requested_assets.clone();
+            api.set("load_image", lua.create_function(move |_, (name, url): (String, String)| {
+                buf_clone.cmd_load_image(&name, &url);
+                assets.lock().unwrap().insert(url);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_sprite", lua.create_function(move |_, (name, x, y, w, h, rotation): (String, f32, f32, f32, f32, Option<f32>)| {
+                let rotation = rotation.unwrap_or(0.0);
+                let visible = {
+                    let cull = cull.lock().unwrap();
+                    if rotation == 0.0 {
+                        cull.visible_rect(x, y, w, h)
+                    } else {
+                        // Rotated around its center: any angle fits in the half-diagonal circle
+                        let r = w.hypot(h) / 2.0;
+                        cull.visible_circle(x + w / 2.0, y + h / 2.0, r, r)
+                    }
+                };
+                if visible {
+                    buf_clone.cmd_draw_sprite(&name, x, y, w, h, rotation);
+                }
+                Ok(())
+            })?)?;
+
+            // Sprite sheets: the engine picks the frame, clients get sheet id + frame index
+            let buf_clone = command_buffer.clone();
+            let assets = requested_assets.clone();
+            let sheets = spritesheets.clone();
+            api.set("load_spritesheet", lua.create_function(move |_, (name, url, frame_w, frame_h, animations): (String, String, u16, u16, Option<mlua::Table>)| {
+                if frame_w == 0 || frame_h == 0 {
+                    return Err(mlua::Error::RuntimeError("Sprite sheet frames need a size".into()));
+                }
+                let id = sheets.load(&name, frame_w, frame_h, read_animations(animations)?)
+                    .ok_or_else(|| mlua::Error::RuntimeError("Too many sprite sheets".into()))?;
+                buf_clone.cmd_load_spritesheet(id, &url, frame_w, frame_h);
+                assets.lock().unwrap().insert(url);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            let sheets = spritesheets.clone();
+            api.set("draw_animation", lua.create_function(move |_, (name, anim, x, y, t, w, h, rotation): (String, String, f32, f32, f32, Option<f32>, Option<f32>, Option<f32>)| {
+                let (id, frame, fw, fh) = sheets.with(&name, |sheet| {
+                    let animation = sheet.animations.get(&anim)
+                        .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown animation '{}' in sprite sheet '{}'", anim, name)))?;
+                    Ok::<_, mlua::Error>((sheet.id, animation.frame_at(t), sheet.frame_w, sheet.frame_h))
+                }).ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown sprite sheet '{}'", name)))??;
+                draw_frame(&buf_clone, &cull, id, frame, x, y, w.unwrap_or(fw as f32), h.unwrap_or(fh as f32), rotation.unwrap_or(0.0));
+                Ok(())
+            })?)?;
+
+            // A single frame (from 1), without an animation
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            let sheets = spritesheets.clone();
+            api.set("draw_frame", lua.create_function(move |_, (name, frame, x, y, w, h, rotation): (String, u32, f32, f32, Option<f32>, Option<f32>, Option<f32>)| {
+                let frame = frame.checked_sub(1).and_then(|f| u16::try_from(f).ok())
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("Invalid frame {}", frame)))?;
+                let (id, fw, fh) = sheets.with(&name, |sheet| (sheet.id, sheet.frame_w, sheet.frame_h))
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown sprite sheet '{}'", name)))?;
+                draw_frame(&buf_clone, &cull, id, frame, x, y, w.unwrap_or(fw as f32), h.unwrap_or(fh as f32), rotation.unwrap_or(0.0));
+                Ok(())
+            })?)?;
+
+            let sheets = spritesheets.clone();
+            api.set("animation_duration", lua.create_function(move |_, (name, anim): (String, String)| {
+                sheets.with(&name, |sheet| sheet.animations.get(&anim).map(Animation::duration))
+                    .flatten()
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown animation '{}' in sprite sheet '{}'", anim, name)))
+            })?)?;
+
+            // Nine-patch: corners keep their size, edges and center stretch to fill w x h
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_ninepatch", lua.create_function(move |_, (name, x, y, w, h, margins): (String, f32, f32, f32, f32, mlua::Value)| {
+                let margins = match margins {
+                    mlua::Value::Integer(m) => [m as f32; 4],
+                    mlua::Value::Number(m) => [m as f32; 4],
+                    mlua::Value::Table(t) if t.raw_len() == 4 => {
+                        let mut m = [0.0; 4];
+                        for (i, v) in m.iter_mut().enumerate() {
+                            *v = t.raw_get(i + 1)?;
+                        }
+                        m
+                    }
+                    _ => return Err(mlua::Error::RuntimeError("Nine-patch margins must be a number or {left, top, right, bottom}".to_string())),
+                };
+                if margins.iter().any(|m| m.is_nan() || *m < 0.0) {
+                    return Err(mlua::Error::RuntimeError("Nine-patch margins can't be negative".to_string()));
+                }
+                if cull.lock().unwrap().visible_rect(x, y, w, h) {
+                    buf_clone.cmd_draw_ninepatch(&name, x, y, w, h, margins);
+                }
+                Ok(())
+            })?)?;
+
+            // Camera: applied by the client to every following draw command of this frame
+            let buf_clone = command_buffer.clone();
+            let cam = camera.clone();
+            let cull = culling.clone();
+            let shake = shake_offset.clone();
+            api.set("set_camera", lua.create_function(move |_, (x, y, zoom, rotation): (f32, f32, Option<f32>, Option<f32>)| {
+                let zoom = read_zoom(zoom)?;
+                let (ox, oy) = *shake.lock().unwrap();
+                let c = Camera { x: x + ox / zoom, y: y + oy / zoom, zoom, rotation: rotation.unwrap_or(0.0) };
+                *cam.lock().unwrap() = c;
+                cull.lock().unwrap().hud = false;
+                buf_clone.cmd_set_transform(&c);
+                Ok(())
+            })?)?;
+
+            // The part of the frame that is the same for every client (broadcast.rs): recorded
+            // for the first session drawn each frame, copied for the others
+            let buf_clone = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            let shared_draws = shared_draw.clone();
+            let (cam, cull, session, shake) = (camera.clone(), culling.clone(), drawing_session.clone(), shake_offset.clone());
+            api.set("draw_shared", lua.create_function(move |_, f: Function| {
+                let recorded = shared_draws.lock().unwrap().recorded()?;
+                if *mode_ref.lock().unwrap() != GameMode::Draw || session.lock().unwrap().is_none() {
+                    return Err(mlua::Error::RuntimeError("api.draw_shared can only be called in draw()".to_string()));
+                }
+                let camera = *cam.lock().unwrap();
+                let start = buf_clone.len();
+                let moves_camera = match recorded {
+                    Some((segment, moves_camera)) => {
+                        buf_clone.extend_from_slice(&segment.bytes);
+                        moves_camera
+                    }
+                    None => {
+                        shared_draws.lock().unwrap().start_recording();
+                        // Drawn for no session in particular
+                        let culling = std::mem::take(&mut *cull.lock().unwrap());
+                        let session_id = session.lock().unwrap().take();
+                        let offset = std::mem::take(&mut *shake.lock().unwrap());
+                        let result = f.call::<_, ()>(());
+                        let moves_camera = *cam.lock().unwrap() != camera;
+                        *cull.lock().unwrap() = culling;
+                        *session.lock().unwrap() = session_id;
+                        *shake.lock().unwrap() = offset;
+                        let bytes = result.is_ok().then(|| buf_clone.bytes_from(start));
+                        shared_draws.lock().unwrap().finish_recording(bytes, moves_camera);
+                        result?;
+                        moves_camera
+                    }
+                };
+                shared_draws.lock().unwrap().place(start..buf_clone.len());
+                if moves_camera {
+                    *cam.lock().unwrap() = camera;
+                    buf_clone.cmd_set_transform(&camera);
+                }
+                Ok(())
+            })?)?;
+
+            // Local transform stack: translate, then rotate (radians), then scale
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("push_transform", lua.create_function(move |_, (tx, ty, rotation, sx, sy): (f32, f32, Option<f32>, Option<f32>, Option<f32>)| {
+                let sx = sx.unwrap_or(1.0);
+                cull.lock().unwrap().transform_depth += 1;
+                buf_clone.cmd_push_transform(tx, ty, rotation.unwrap_or(0.0), sx, sy.unwrap_or(sx));
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("pop_transform", lua.create_function(move |_, ()| {
+                let mut c = cull.lock().unwrap();
+                c.transform_depth = c.transform_depth.saturating_sub(1);
+                buf_clone.cmd_pop_transform();
+                Ok(())
+            })?)?;
+
+            // Layers: the client rasterizes lower layers first, emission order within a layer
+            let buf_clone = command_buffer.clone();
+            api.set("set_layer", lua.create_function(move |_, layer: i64| {
+                let layer = i8::try_from(layer).map_err(|_| mlua::Error::RuntimeError(format!("Layer must be between -128 and 127 (got {})", layer)))?;
+                buf_clone.cmd_set_layer(layer);
+                Ok(())
+            })?)?;
+
+            // Back to screen coordinates (HUD)
+            let buf_clone = command_buffer.clone();
+            let cam = camera.clone();
+            let cull = culling.clone();
+            api.set("reset_camera", lua.create_function(move |_, ()| {
+                *cam.lock().unwrap() = Camera::SCREEN;
+                cull.lock().unwrap().hud = true;
+                buf_clone.cmd_set_transform(&Camera::SCREEN);
+                Ok(())
+            })?)?;
+
+            // Screen-space UI block: HUD drawn between ui_begin() and ui_end() ignores the
+            // camera, which is restored afterwards
+            let buf_clone = command_buffer.clone();
+            let cam = camera.clone();
+            let cull = culling.clone();
+            let saved = ui_camera.clone();
+            api.set("ui_begin", lua.create_function(move |_, ()| {
+                let mut saved = saved.lock().unwrap();
+                if saved.is_some() {
+                    return Err(mlua::Error::RuntimeError("api.ui_begin() called inside a UI block".to_string()));
+                }
+                let mut cull = cull.lock().unwrap();
+                *saved = Some((*cam.lock().unwrap(), cull.hud));
+                *cam.lock().unwrap() = Camera::SCREEN;
+                cull.hud = true;
+                buf_clone.cmd_set_transform(&Camera::SCREEN);
+                Ok(())
+            })?)?;
+
+            let buf_clone = command_buffer.clone();
+            let cam = camera.clone();
+            let cull = culling.clone();
+            let saved = ui_camera.clone();
+            api.set("ui_end", lua.create_function(move |_, ()| {
+                let Some((camera, hud)) = saved.lock().unwrap().take() else {
+                    return Err(mlua::Error::RuntimeError("api.ui_end() called without api.ui_begin()".to_string()));
+                };
+                *cam.lock().unwrap() = camera;
+                cull.lock().unwrap().hud = hud;
+                buf_clone.cmd_set_transform(&camera);
+                Ok(())
+            })?)?;
+
+            // Viewport culling: the area of the world this session can see
+            let views = viewports.clone();
+            api.set("set_viewport", lua.create_function(move |_, (session_id, x, y, w, h): (String, f32, f32, f32, f32)| {
+                let v = Viewport { min_x: x.min(x + w), min_y: y.min(y + h), max_x: x.max(x + w), max_y: y.max(y + h) };
+                views.lock().unwrap().insert(session_id, v);
+                Ok(())
+            })?)?;
+
+            let views = viewports.clone();
+            api.set("clear_viewport", lua.create_function(move |_, session_id: String| {
+                views.lock().unwrap().remove(&session_id);
+                Ok(())
+            })?)?;
+
+            // Engine-driven cameras (camera_rig.rs), applied at the start of the session's draw()
+            let rigs = camera_rigs.clone();
+            api.set("camera_follow", lua.create_function(move |_, (session_id, db, id, lerp, zoom): (String, AnyUserData, u64, Option<f32>, Option<f32>)| {
+                let db = db.borrow::<SpatialDbWrapper>()?.0.clone();
+                let zoom = read_zoom(zoom)?;
+                rigs.lock().unwrap().follow(&session_id, db, id, lerp.unwrap_or(0.15), zoom);
+                Ok(())
+            })?)?;
+
+            let rigs = camera_rigs.clone();
+            api.set("camera_unfollow", lua.create_function(move |_, session_id: String| {
+                rigs.lock().unwrap().unfollow(&session_id);
+                Ok(())
+            })?)?;
+
+            let rigs = camera_rigs.clone();
+            api.set("camera_shake", lua.create_function(move |_, (target, intensity, duration): (String, f32, f32)| {
+                if !(intensity >= 0.0 && intensity.is_finite() && duration > 0.0 && duration.is_finite()) {
+                    return Err(mlua::Error::RuntimeError("camera_shake needs a non-negative intensity and a positive duration".into()));
+                }
+                rigs.lock().unwrap().shake(&target, intensity, duration);
+                Ok(())
+            })?)?;
+
+            let cam = camera.clone();
+            api.set("get_camera", lua.create_function(move |_, ()| {
+                let c = *cam.lock().unwrap();
+                Ok((c.x, c.y, c.zoom, c.rotation))
+            })?)?;
+
+            let cam = camera.clone();
+            api.set("get_view_rect", lua.create_function(move |_, ()| {
+                Ok(cam.lock().unwrap().view_rect())
+            })?)?;
+
+            // Mobile controls: switch the named keys.json layout for one client
+            let per_session = session_commands.clone();
+            api.set("set_control_scheme", lua.create_function(move |_, (session_id, name): (String, String)| {
+                per_session.lock().unwrap().entry(session_id).or_insert_with(CommandBuffer::new).cmd_set_control_scheme(&name);
+                Ok(())
+            })?)?;
+
+            // Accessibility, applied to the session's draw() output
+            let settings = accessibility.clone();
+            api.set("set_accessibility", lua.create_function(move |_, (session_id, opts): (String, mlua::Table)| {
+                let mut map = settings.lock().unwrap();
+                let mut a = map.get(&session_id).copied().unwrap_or_default();
+                if let Some(name) = opts.get::<_, Option<String>>("palette")? {
+                    a.palette = Palette::parse(&name).ok_or_else(|| mlua::Error::RuntimeError(format!(
+                        "Unknown palette '{}' (expected normal, protanopia, deuteranopia or tritanopia)", name
+                    )))?;
+                }
+                if let Some(scale) = opts.get::<_, Option<f32>>("text_scale")? {
+                    if !(0.5..=4.0).contains(&scale) {
+                        return Err(mlua::Error::RuntimeError(format!("text_scale must be between 0.5 and 4 (got {})", scale)));
+                    }
+                    a.text_scale = scale;
+                }
+                map.insert(session_id, a);
+                Ok(())
+            })?)?;
+
+            let settings = accessibility.clone();
+            api.set("get_accessibility", lua.create_function(move |lua, session_id: String| {
+                let a = settings.lock().unwrap().get(&session_id).copied().unwrap_or_default();
+                let t = lua.create_table()?;
+                t.set("palette", a.palette.name())?;
+                t.set("text_scale", a.text_scale)?;
+                Ok(t)
+            })?)?;
+
+            // Text prompt (opens the mobile keyboard); the answer arrives in on_text_input(session_id, text)
+            let per_session = session_commands.clone();
+            let prompts = text_prompts.clone();
+            api.set("request_text_input", lua.create_function(move |_, (session_id, opts): (String, Option<mlua::Table>)| {
+                let (placeholder, max_len) = match &opts {
+                    Some(o) => (o.get::<_, Option<String>>("placeholder")?.unwrap_or_default(), o.get::<_, Option<usize>>("max_len")?),
+                    None => (String::new(), None),
+                };
+                let max_len = max_len.unwrap_or(64).clamp(1, MAX_TEXT_INPUT_LEN);
+                prompts.lock().unwrap().insert(session_id.clone(), max_len);
+                per_session.lock().unwrap().entry(session_id).or_insert_with(CommandBuffer::new).cmd_request_text_input(&placeholder, max_len as u16);
+                Ok(())
+            })?)?;
+
+            // Input polling (input.rs): what each player is holding right now
+            let states = input_states.clone();
+            api.set("is_down", lua.create_function(move |_, (session_id, control): (String, mlua::Value)| {
+                let states = states.lock().unwrap();
+                let code = match control {
+                    mlua::Value::Integer(i) => i as f64,
+                    mlua::Value::Number(n) => n,
+                    mlua::Value::String(name) => return states.is_button_down(&session_id, name.to_str()?)
+                        .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown gamepad button '{}'", name.to_string_lossy()))),
+                    other => return Err(mlua::Error::RuntimeError(format!("is_down expects a key code or a gamepad button name (got {})", other.type_name()))),
+                };
+                Ok((0.0..=255.0).contains(&code) && code.fract() == 0.0 && states.is_key_down(&session_id, code as u8))
+            })?)?;
+
+            let states = input_states.clone();
+            api.set("get_axis", lua.create_function(move |_, (session_id, name): (String, String)| {
+                states.lock().unwrap().axis(&session_id, &name)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown axis '{}' (expected horizontal, vertical or a gamepad axis)", name)))
+            })?)?;
+
+            let states = input_states.clone();
+            api.set("input_stats", lua.create_function(move |lua, session_id: String| {
+                let Some(stats) = states.lock().unwrap().sequence_stats(&session_id) else { return Ok(None) };
+                let t = lua.create_table()?;
+                t.set("last_seq", stats.last_seq)?;
+                t.set("received", stats.received)?;
+                t.set("dropped", stats.dropped)?;
+                t.set("reordered", stats.reordered)?;
+                Ok(Some(t))
+            })?)?;
+
+            let configs = input_configs.clone();
+            api.set("set_input_config", lua.create_function(move |_, (target, settings): (String, Option<mlua::Table>)| {
+                let config = settings.map(read_input_config).transpose()?;
+                configs.lock().unwrap().set(&target, config);
+                Ok(())
+            })?)?;
+
+            // Input actions (actions.rs): on_action(session_id, action, is_down) from any bound control
+            let registry = actions.clone();
+            api.set("bind_action", lua.create_function(move |lua, (action, binding): (String, mlua::Value)| {
+                let binding: Binding = lua.from_value(binding)?;
+                registry.lock().unwrap().bind(action, binding);
+                Ok(())
+            })?)?;
+
+            let registry = actions.clone();
+            api.set("rebind_action", lua.create_function(move |lua, (session_id, action, binding): (String, String, mlua::Value)| {
+                let binding = match binding {
+                    mlua::Value::Nil => None,
+                    v => Some(lua.from_value::<Binding>(v)?),
+                };
+                if !registry.lock().unwrap().rebind(&session_id, &action, binding) {
+                    return Err(mlua::Error::RuntimeError(format!("Unknown action '{}'", action)));
+                }
+                Ok(())
+            })?)?;
+
+            let registry = actions.clone();
+            api.set("is_action_down", lua.create_function(move |_, (session_id, action): (String, String)| {
+                Ok(registry.lock().unwrap().is_held(&session_id, &action))
+            })?)?;
+
+            let registry = actions.clone();
+            api.set("get_bindings", lua.create_function(move |lua, session_id: String| {
+                lua.to_value(&registry.lock().unwrap().bindings(&session_id))
+            })?)?;
+
+            // Settings menu (settings.rs), drawn by the client; changes arrive in on_setting_changed
+            let registry = settings_registry.clone();
+            api.set("register_setting", lua.create_function(move |_, (key, opts): (String, Option<mlua::Table>)| {
+                let setting = read_setting(key, opts.as_ref())?;
+                registry.lock().unwrap().register(setting).map_err(mlua::Error::RuntimeError)
+            })?)?;
+
+            let registry = settings_registry.clone();
+            api.set("get_setting", lua.create_function(move |lua, (session_id, key): (String, String)| {
+                let value = registry.lock().unwrap().get(&session_id, &key);
+                match value {
+                    Some(v) => lua.to_value(&v),
+                    None => Err(mlua::Error::RuntimeError(format!("Unknown setting '{}'", key))),
+                }
+            })?)?;
+
+            // Context-Aware Play Sound
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            
+            api.set("play_sound", lua.create_function(move |_, (name, loop_val, volume, pitch): (String, Option<bool>, Option<f32>, Option<f32>)| {
+                let mode = *mode_ref.lock().unwrap();
+                let vol = volume.unwrap_or(1.0);
+                let lp = loop_val.unwrap_or(false);
+                let rate = read_pitch(pitch.unwrap_or(1.0))?;
+                
+                match mode {
+                    GameMode::Update => event_buf.cmd_play_sound(&name, lp, vol, rate),
+                    GameMode::Draw => cmd_buf.cmd_play_sound(&name, lp, vol, rate),
+                }
+                Ok(())
+            })?)?;
+
+            // One player only, from update() or draw(): delivered with that session's next frame
+            let per_session = session_commands.clone();
+            api.set("play_sound_for", lua.create_function(move |_, (session_id, name, loop_val, volume, pitch): (String, String, Option<bool>, Option<f32>, Option<f32>)| {
+                let rate = read_pitch(pitch.unwrap_or(1.0))?;
+                per_session.lock().unwrap().entry(session_id).or_insert_with(CommandBuffer::new)
+                    .cmd_play_sound(&name, loop_val.unwrap_or(false), volume.unwrap_or(1.0), rate);
+                Ok(())
+            })?)?;
+
+            // Positional sounds: started in update() they are mixed per client in draw();
+            // in draw() they are mixed for the session being drawn
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            let pending = positional_sounds.clone();
+            let session = drawing_session.clone();
+            let (listen, views) = (listeners.clone(), viewports.clone());
+            api.set("play_sound_at", lua.create_function(move |_, (name, x, y, opts): (String, f32, f32, Option<mlua::Table>)| {
+                let (mut volume, mut looping, mut min_distance, mut max_distance) = (1.0, false, 100.0, 1000.0);
+                let mut pitch = 1.0;
+                if let Some(opts) = opts {
+                    volume = opts.get::<_, Option<f32>>("volume")?.unwrap_or(volume);
+                    pitch = read_pitch(opts.get::<_, Option<f32>>("pitch")?.unwrap_or(pitch))?;
+                    looping = opts.get::<_, Option<bool>>("loop")?.unwrap_or(looping);
+                    min_distance = opts.get::<_, Option<f32>>("min_distance")?.unwrap_or(min_distance);
+                    max_distance = opts.get::<_, Option<f32>>("max_distance")?.unwrap_or(max_distance);
+                }
+                if !(min_distance >= 0.0 && max_distance > min_distance) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "play_sound_at: need 0 <= min_distance < max_distance (got {} and {})", min_distance, max_distance
+                    )));
+                }
+                let sound = PositionalSound { name, x, y, volume, pitch, looping, min_distance, max_distance };
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => pending.lock().unwrap().push(sound),
+                    GameMode::Draw => {
+                        let listener = session.lock().unwrap().as_deref()
+                            .and_then(|id| listener_of(id, &listen.lock().unwrap(), &views.lock().unwrap()));
+                        sound.write(&cmd_buf, listener);
+                    }
+                }
+                Ok(())
+            })?)?;
+
+            let listen = listeners.clone();
+            api.set("set_listener", lua.create_function(move |_, (session_id, x, y): (String, f32, f32)| {
+                listen.lock().unwrap().insert(session_id, (x, y));
+                Ok(())
+            })?)?;
+
+            let listen = listeners.clone();
+            api.set("clear_listener", lua.create_function(move |_, session_id: String| {
+                listen.lock().unwrap().remove(&session_id);
+                Ok(())
+            })?)?;
+
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("stop_sound", lua.create_function(move |_, name: String| {
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_stop_sound(&name),
+                    GameMode::Draw => cmd_buf.cmd_stop_sound(&name),
+                }
+                Ok(())
+            })?)?;
+
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("set_volume", lua.create_function(move |_, (name, vol): (String, f32)| {
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_set_volume(&name, vol),
+                    GameMode::Draw => cmd_buf.cmd_set_volume(&name, vol),
+                }
+                Ok(())
+            })?)?;
+
+            // Playback rate of a playing sound (1 = normal; 2 = an octave up, twice as fast)
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("set_pitch", lua.create_function(move |_, (name, rate): (String, f32)| {
+                let rate = read_pitch(rate)?;
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_set_pitch(&name, rate),
+                    GameMode::Draw => cmd_buf.cmd_set_pitch(&name, rate),
+                }
+                Ok(())
+            })?)?;
+
+            // Music: streamed by the client instead of downloaded and decoded up front. Playing
+            // the track that is already playing only changes its volume, so a hot reload
+            // (on_connect running again) doesn't restart it.
+            let buf_clone = command_buffer.clone();
+            let assets = requested_assets.clone();
+            api.set("load_music", lua.create_function(move |_, (name, url, bus): (String, String, Option<String>)| {
+                buf_clone.cmd_load_music(&name, &url);
+                if let Some(bus) = bus {
+                    buf_clone.cmd_set_sound_bus(&name, &bus);
+                }
+                assets.lock().unwrap().insert(url);
+                Ok(())
+            })?)?;
+
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("play_music", lua.create_function(move |_, (name, opts): (String, Option<mlua::Table>)| {
+                let (mut looping, mut volume, mut fade_in) = (true, 1.0, 0.0);
+                if let Some(opts) = opts {
+                    looping = opts.get::<_, Option<bool>>("loop")?.unwrap_or(looping);
+                    volume = opts.get::<_, Option<f32>>("volume")?.unwrap_or(volume);
+                    fade_in = opts.get::<_, Option<f32>>("fade_in")?.unwrap_or(fade_in).max(0.0);
+                }
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_play_music(&name, looping, volume, fade_in),
+                    GameMode::Draw => cmd_buf.cmd_play_music(&name, looping, volume, fade_in),
+                }
+                Ok(())
+            })?)?;
+
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("stop_music", lua.create_function(move |_, (name, fade_out): (String, Option<f32>)| {
+                let fade_out = fade_out.unwrap_or(0.0).max(0.0);
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_stop_music(&name, fade_out),
+                    GameMode::Draw => cmd_buf.cmd_stop_music(&name, fade_out),
+                }
+                Ok(())
+            })?)?;
+
+            // One track fades out while the other fades in (looping, full volume)
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("crossfade_music", lua.create_function(move |_, (from, to, seconds): (String, String, f32)| {
+                let seconds = seconds.max(0.0);
+                let mode = *mode_ref.lock().unwrap();
+                let buf = match mode {
+                    GameMode::Update => &event_buf,
+                    GameMode::Draw => &cmd_buf,
+                };
+                buf.cmd_stop_music(&from, seconds);
+                buf.cmd_play_music(&to, true, 1.0, seconds);
+                Ok(())
+            })?)?;
+
+            // Audio buses: sounds play on "sfx" and music on "music" unless loaded onto another
+            // bus. Like sounds, a volume set from update() reaches everyone and from draw() one
+            // player; opts.session targets one player from anywhere (a settings menu).
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            let per_session = session_commands.clone();
+            api.set("set_bus_volume", lua.create_function(move |_, (bus, volume, opts): (String, f32, Option<mlua::Table>)| {
+                if !(volume >= 0.0 && volume.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Bus volume must be a non-negative number (got {})", volume)));
+                }
+                let (mut fade, mut session) = (0.0, None);
+                if let Some(opts) = opts {
+                    fade = opts.get::<_, Option<f32>>("fade")?.unwrap_or(fade).max(0.0);
+                    session = opts.get::<_, Option<String>>("session")?;
+                }
+                if let Some(session_id) = session {
+                    per_session.lock().unwrap().entry(session_id).or_insert_with(CommandBuffer::new).cmd_set_bus_volume(&bus, volume, fade);
+                    return Ok(());
+                }
+                let mode = *mode_ref.lock().unwrap();
+                match mode {
+                    GameMode::Update => event_buf.cmd_set_bus_volume(&bus, volume, fade),
+                    GameMode::Draw => cmd_buf.cmd_set_bus_volume(&bus, volume, fade),
+                }
+                Ok(())
+            })?)?;
+
+            // Screen effects: like sounds, everyone gets them from update(), one player from draw()
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("screen_shake", lua.create_function(move |_, (intensity, duration): (f32, f32)| {
+                if !(intensity >= 0.0 && duration >= 0.0 && intensity.is_finite() && duration.is_finite()) {
+                    return Err(mlua::Error::RuntimeError("screen_shake needs a non-negative intensity and duration".into()));
+                }
+                match *mode_ref.lock().unwrap() {
+                    GameMode::Update => event_buf.cmd_screen_shake(intensity, duration),
+                    GameMode::Draw => cmd_buf.cmd_screen_shake(intensity, duration),
+                }
+                Ok(())
+            })?)?;
+
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("flash", lua.create_function(move |_, (r, g, b, duration): (u8, u8, u8, f32)| {
+                if !(duration >= 0.0 && duration.is_finite()) {
+                    return Err(mlua::Error::RuntimeError("flash needs a non-negative duration".into()));
+                }
+                match *mode_ref.lock().unwrap() {
+                    GameMode::Update => event_buf.cmd_flash(r, g, b, duration),
+                    GameMode::Draw => cmd_buf.cmd_flash(r, g, b, duration),
+                }
+                Ok(())
+            })?)?;
+
+            // Stays on the client until changed; 0 removes it
+            let event_buf = event_buffer.clone();
+            let cmd_buf = command_buffer.clone();
+            let mode_ref = current_mode.clone();
+            api.set("vignette", lua.create_function(move |_, strength: f32| {
+                let strength = if strength.is_nan() { 0.0 } else { strength.clamp(0.0, 1.0) };
+                match *mode_ref.lock().unwrap() {
+                    GameMode::Update => event_buf.cmd_vignette(strength),
+                    GameMode::Draw => cmd_buf.cmd_vignette(strength),
+                }
+                Ok(())
+            })?)?;
+
+            // Post-processing for "all" or one session; nil settings reset it (post.rs)
+            let post = post_effects.clone();
+            api.set("set_post_effect", lua.create_function(move |_, (target, settings): (String, Option<mlua::Table>)| {
+                let effect = settings.map(read_post_effect).transpose()?;
+                post.lock().unwrap().set(&target, effect);
+                Ok(())
+            })?)?;
+
+            api.set("new_spatial_db", lua.create_function(move |_, (cell_size, opts): (f32, Option<mlua::Table>)| {
+                // Optional toroidal world: { wrap_width = w, wrap_height = h }
+                let wrap = match &opts {
+                    Some(o) => (o.get::<_, Option<f32>>("wrap_width")?, o.get::<_, Option<f32>>("wrap_height")?),
+                    None => (None, None),
+                };
+                let db = match wrap {
+                    (Some(w), Some(h)) => SpatialDb::new_toroidal(cell_size, w, h),
+                    _ => SpatialDb::new(cell_size),
+                };
+                Ok(SpatialDbWrapper(Arc::new(Mutex::new(db))))
+            })?)?;
+
+            api.set("new_physics_world", lua.create_function(move |_, userdata: AnyUserData| {
+                let db_wrapper = userdata.borrow::<SpatialDbWrapper>()?;
+                let phys = PhysicsWorld::new(db_wrapper.0.clone());
+                Ok(PhysicsWrapper(Arc::new(Mutex::new(phys))))
+            })?)?;
+
+            api.set("new_graph", lua.create_function(move |_, ()| {
+                let graph = Graph::new();
+                Ok(GraphWrapper(Arc::new(Mutex::new(graph))))
+            })?)?;
+
+            api.set("new_nav_grid", lua.create_function(move |_, (width, height): (usize, usize)| {
+                Ok(NavGridWrapper(Arc::new(Mutex::new(NavGrid::new(width, height)))))
+            })?)?;
+
+            // Draws every edge as a line and every node as a small square, in the current color
+            let buf_clone = command_buffer.clone();
+            api.set("debug_draw_graph", lua.create_function(move |_, (userdata, node_size): (AnyUserData, Option<f32>)| {
+                let graph = userdata.borrow::<GraphWrapper>()?;
+                let g = graph.0.lock().unwrap();
+                for (x1, y1, x2, y2) in g.edge_segments() {
+                    buf_clone.cmd_draw_line(x1, y1, x2, y2, 1.0);
+                }
+                let size = node_size.unwrap_or(4.0);
+                for (x, y) in g.node_positions() {
+                    buf_clone.cmd_fill_rect(x - size / 2.0, y - size / 2.0, size, size);
+                }
+                Ok(())
+            })?)?;
+
+            // Path following, advanced by the engine before every update(dt)
+            let followers = path_followers.clone();
+            api.set("follow_path", lua.create_function(move |lua, (phys, id, path, speed, opts): (AnyUserData, u64, mlua::Table, f32, Option<mlua::Table>)| {
+                let phys = phys.borrow::<PhysicsWrapper>()?.0.clone();
+                let (arrive_radius, on_complete, graph) = match &opts {
+                    Some(o) => (
+                        o.get::<_, Option<f32>>("arrive_radius")?.unwrap_or(4.0),
+                        o.get::<_, Option<Function>>("on_complete")?,
+                        o.get::<_, Option<AnyUserData>>("graph")?,
+                    ),
+                    None => (4.0, None, None),
+                };
+                let waypoints = match graph {
+                    Some(g) => {
+                        let graph = g.borrow::<GraphWrapper>()?;
+                        let g = graph.0.lock().unwrap();
+                        read_waypoints(&path, Some(&*g))?
+                    }
+                    None => read_waypoints(&path, None)?,
+                };
+                let on_complete = on_complete.map(|f| lua.create_registry_value(f)).transpose()?;
+                followers.start(id, PathFollow::new(phys, waypoints, speed, arrive_radius, on_complete));
+                Ok(())
+            })?)?;
+
+            let followers = path_followers.clone();
+            api.set("stop_path", lua.create_function(move |_, id: u64| {
+                Ok(followers.stop(id))
+            })?)?;
+
+            let followers = path_followers.clone();
+            api.set("is_following", lua.create_function(move |_, id: u64| {
+                Ok(followers.is_following(id))
+            })?)?;
+
+            // Timers (timers.rs), run by the engine before every update(dt)
+            let scheduled = timers.clone();
+            api.set("after", lua.create_function(move |lua, (seconds, callback): (f64, Function)| {
+                if seconds.is_nan() || seconds < 0.0 {
+                    return Err(mlua::Error::RuntimeError(format!("api.after: delay must be >= 0, got {}", seconds)));
+                }
+                Ok(scheduled.add(seconds, None, lua.create_registry_value(callback)?))
+            })?)?;
+
+            let scheduled = timers.clone();
+            api.set("every", lua.create_function(move |lua, (seconds, callback): (f64, Function)| {
+                if !seconds.is_finite() || seconds < MIN_INTERVAL {
+                    return Err(mlua::Error::RuntimeError(format!("api.every: interval must be at least {}, got {}", MIN_INTERVAL, seconds)));
+                }
+                Ok(scheduled.add(seconds, Some(seconds), lua.create_registry_value(callback)?))
+            })?)?;
+
+            let scheduled = timers.clone();
+            api.set("cancel", lua.create_function(move |lua, handle: u64| {
+                scheduled.cancel(lua, handle)
+            })?)?;
+
+            // Lighting (lighting.rs): lights persist until removed, draw_lights sends those in view
+            let registry = lights.clone();
+            api.set("add_light", lua.create_function(move |_, (x, y, radius, color, flicker): (f32, f32, f32, Option<mlua::Table>, Option<f32>)| {
+                if !(radius > 0.0 && radius.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Light radius must be positive (got {})", radius)));
+                }
+                let color = match color {
+                    Some(t) => read_rgba(&t)?,
+                    None => [255, 255, 255, 255],
+                };
+                Ok(registry.lock().unwrap().add(x, y, radius, color, flicker.unwrap_or(0.0).clamp(0.0, 1.0)))
+            })?)?;
+
+            let registry = lights.clone();
+            api.set("move_light", lua.create_function(move |_, (id, x, y): (u64, f32, f32)| {
+                Ok(registry.lock().unwrap().move_to(id, x, y))
+            })?)?;
+
+            let registry = lights.clone();
+            api.set("remove_light", lua.create_function(move |_, id: u64| {
+                Ok(registry.lock().unwrap().remove(id))
+            })?)?;
+
+            let registry = lights.clone();
+            api.set("clear_lights", lua.create_function(move |_, ()| {
+                registry.lock().unwrap().clear();
+                Ok(())
+            })?)?;
+
+            let registry = lights.clone();
+            api.set("set_ambient_light", lua.create_function(move |_, (r, g, b): (u8, u8, u8)| {
+                registry.lock().unwrap().ambient = [r, g, b];
+                Ok(())
+            })?)?;
+
+            let registry = lights.clone();
+            api.set("set_occluders", lua.create_function(move |_, polygons: mlua::Table| {
+                let polygons: Vec<Vec<(f32, f32)>> = polygons.sequence_values::<mlua::Table>()
+                    .map(|p| read_polygon(p?))
+                    .collect::<mlua::Result<_>>()?;
+                registry.lock().unwrap().set_occluders(&polygons);
+                Ok(())
+            })?)?;
+
+            let registry = lights.clone();
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_lights", lua.create_function(move |_, ()| {
+                let cull = cull.lock().unwrap();
+                let mut registry = registry.lock().unwrap();
+                let ambient = registry.ambient;
+                let frame = registry.frame(|l| cull.visible_circle(l.x, l.y, l.radius, l.radius));
+                buf_clone.cmd_lights(ambient, &frame);
+                Ok(())
+            })?)?;
+
+            // Trails (trails.rs): positions recorded after every update(dt), drawn in one batch
+            let registry = trails.clone();
+            api.set("trail_attach", lua.create_function(move |_, (db, id, opts): (AnyUserData, u64, Option<mlua::Table>)| {
+                let db = db.borrow::<SpatialDbWrapper>()?.0.clone();
+                let mut config = TrailConfig::default();
+                if let Some(opts) = opts {
+                    config.length = opts.get::<_, Option<usize>>("length")?.unwrap_or(config.length);
+                    config.width = opts.get::<_, Option<f32>>("width")?.unwrap_or(config.width);
+                    if let Some(color) = opts.get::<_, Option<mlua::Table>>("color")? {
+                        config.color = read_rgba(&color)?;
+                    }
+                }
+                if !(2..=MAX_TRAIL_LENGTH).contains(&config.length) {
+                    return Err(mlua::Error::RuntimeError(format!("Trail length must be 2 to {} points (got {})", MAX_TRAIL_LENGTH, config.length)));
+                }
+                if !(config.width >= 0.0 && config.width.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Trail width must be a non-negative number (got {})", config.width)));
+                }
+                registry.lock().unwrap().attach(id, db, config);
+                Ok(())
+            })?)?;
+
+            let registry = trails.clone();
+            api.set("trail_detach", lua.create_function(move |_, id: u64| {
+                Ok(registry.lock().unwrap().detach(id))
+            })?)?;
+
+            let registry = trails.clone();
+            api.set("clear_trails", lua.create_function(move |_, ()| {
+                registry.lock().unwrap().clear();
+                Ok(())
+            })?)?;
+
+            let registry = trails.clone();
+            let buf_clone = command_buffer.clone();
+            let cull = culling.clone();
+            api.set("draw_trails", lua.create_function(move |_, ()| {
+                let cull = cull.lock().unwrap();
+                if cull.reduced_detail {
+                    return Ok(());
+                }
+                let registry = registry.lock().unwrap();
+                let frame = registry.frame(|min_x, min_y, max_x, max_y| cull.visible(min_x, min_y, max_x, max_y));
+                if !frame.is_empty() {
+                    buf_clone.cmd_draw_trails(&frame);
+                }
+                Ok(())
+            })?)?;
+
+            // Floating text (float_text.rs): labels that rise and fade, aged after every update(dt)
+            let registry = float_texts.clone();
+            api.set("float_text", lua.create_function(move |_, (text, x, y, opts): (String, f32, f32, Option<mlua::Table>)| {
+                let mut config = FloatTextConfig::default();
+                if let Some(opts) = opts {
+                    config.duration = opts.get::<_, Option<f32>>("duration")?.unwrap_or(config.duration);
+                    config.rise = opts.get::<_, Option<f32>>("rise")?.unwrap_or(config.rise);
+                    if let Some(color) = opts.get::<_, Option<mlua::Table>>("color")? {
+                        config.color = read_rgba(&color)?;
+                    }
+                }
+                if !(config.duration > 0.0 && config.duration.is_finite()) {
+                    return Err(mlua::Error::RuntimeError(format!("Floating text duration must be positive (got {})", config.duration)));
+                }
+                registry.lock().unwrap().add(text, x, y, config);
+                Ok(())
+            })?)?;
+
+            let registry = float_texts.clone();
+            api.set("clear_float_texts", lua.create_function(move |_, ()| {
+                registry.lock().unwrap().clear();
+                Ok(())
+            })?)?;
+
+            let registry = float_texts.clone();
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            let cull = culling.clone();
+            api.set("draw_float_texts", lua.create_function(move |_, ()| {
+                let cull = cull.lock().unwrap();
+                if cull.reduced_detail {
+                    return Ok(());
+                }
+                let a11y = a11y.lock().unwrap();
+                let registry = registry.lock().unwrap();
+                for t in registry.frame(|min_x, min_y, max_x, max_y| cull.visible(min_x, min_y, max_x, max_y)) {
+                    let (r, g, b) = a11y.remap(t.rgba[0], t.rgba[1], t.rgba[2]);
+                    buf_clone.cmd_set_color(r, g, b, t.rgba[3]);
+                    buf_clone.cmd_draw_text(t.text, t.x, t.y);
+                }
+                Ok(())
+            })?)?;
+
+            // Particle emitters, simulated by the engine before every update(dt)
+            let registry = emitters.clone();
+            let buf_clone = command_buffer.clone();
+            let a11y = active_accessibility.clone();
+            let cull = culling.clone();
+            api.set("new_emitter", lua.create_function(move |_, opts: Option<mlua::Table>| {
+                let config = match &opts {
+                    Some(o) => read_emitter_config(o)?,
+                    None => EmitterConfig::default(),
+                };
+                Ok(EmitterWrapper {
+                    emitter: registry.create(config),
+                    commands: buf_clone.clone(),
+                    a11y: a11y.clone(),
+                    culling: cull.clone(),
+                })
+            })?)?;
+
+            // Tilemaps: tiles cached by the client, only changed chunks are resent
+            let registry = tilemaps.clone();
+            let buf_clone = command_buffer.clone();
+            let session = drawing_session.clone();
+            let cull = culling.clone();
+            api.set("new_tilemap", lua.create_function(move |_, (w, h, tile_size): (usize, usize, f32)| {
+                if !(1..=MAX_TILEMAP_SIZE).contains(&w) || !(1..=MAX_TILEMAP_SIZE).contains(&h) {
+                    return Err(mlua::Error::RuntimeError(format!("Tilemap size {}x{} out of range (1 to {} tiles per side)", w, h, MAX_TILEMAP_SIZE)));
+                }
+                Ok(TilemapWrapper {
+                    tilemap: registry.create(w, h, tile_size),
+                    commands: buf_clone.clone(),
+                    drawing_session: session.clone(),
+                    culling: cull.clone(),
+                })
+            })?)?;
+
+            // Render targets: recorded once, cached by clients, blitted any number of times
+            let registry = canvases.clone();
+            let buf_clone = command_buffer.clone();
+            let session = drawing_session.clone();
+            let cull = culling.clone();
+            api.set("new_canvas", lua.create_function(move |_, (name, w, h): (String, u16, u16)| {
+                if !(1..=MAX_CANVAS_SIZE).contains(&w) || !(1..=MAX_CANVAS_SIZE).contains(&h) {
+                    return Err(mlua::Error::RuntimeError(format!("Canvas size {}x{} out of range (1 to {} pixels per side)", w, h, MAX_CANVAS_SIZE)));
+                }
+                Ok(CanvasWrapper {
+                    canvas: registry.create(&name, w, h),
+                    commands: buf_clone.clone(),
+                    drawing_session: session.clone(),
+                    culling: cull.clone(),
+                })
+            })?)?;
+
+            // Retained scene: nodes persist across frames, clients receive only the changes
+            let scene_ref = scene.clone();
+            api.set("new_node", lua.create_function(move |_, opts: Option<mlua::Table>| {
+                let id = scene_ref.lock().unwrap().create(NodeState::default());
+                let node = NodeWrapper { id, scene: scene_ref.clone() };
+                if let Some(opts) = opts {
+                    if let Err(e) = node.set(&opts) {
+                        scene_ref.lock().unwrap().destroy(id);
+                        return Err(e);
+                    }
+                }
+                Ok(node)
+            })?)?;
+
+            // Entities by script id: api.scene_add(id, kind, props) / scene_update / scene_remove
+            let scene_ref = scene.clone();
+            api.set("scene_add", lua.create_function(move |lua, (key, kind, props): (mlua::Value, String, Option<mlua::Table>)| {
+                let name = scene_key(&key)?;
+                if scene_ref.lock().unwrap().named(&name).is_some() {
+                    return Err(mlua::Error::RuntimeError(format!("Scene entity '{}' already exists", name)));
+                }
+                let opts = lua.create_table()?;
+                if let Some(props) = props {
+                    for pair in props.pairs::<mlua::Value, mlua::Value>() {
+                        let (k, v) = pair?;
+                        opts.set(k, v)?;
+                    }
+                }
+                opts.set("shape", kind)?;
+                let id = scene_ref.lock().unwrap().create(NodeState::default());
+                let node = NodeWrapper { id, scene: scene_ref.clone() };
+                if let Err(e) = node.set(&opts) {
+                    scene_ref.lock().unwrap().destroy(id);
+                    return Err(e);
+                }
+                scene_ref.lock().unwrap().set_name(name, id);
+                Ok(node)
             })?)?;
 
-            let buf_clone = command_buffer.clone();
-            api.set("set_color", lua.create_function(move |_, (r, g, b, a): (u8, u8, u8, Option<u8>)| {
-                buf_clone.cmd_set_color(r, g, b, a.unwrap_or(255));
-                Ok(())
+            let scene_ref = scene.clone();
+            api.set("scene_update", lua.create_function(move |_, (key, props): (mlua::Value, mlua::Table)| {
+                let name = scene_key(&key)?;
+                let id = scene_ref.lock().unwrap().named(&name)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("Unknown scene entity '{}'", name)))?;
+                NodeWrapper { id, scene: scene_ref.clone() }.set(&props)
             })?)?;
 
-            let buf_clone = command_buffer.clone();
-            api.set("fill_rect", lua.create_function(move |_, (x, y, w, h): (f32, f32, f32, f32)| {
-                buf_clone.cmd_fill_rect(x, y, w, h);
+            // Removing an unknown id is a no-op, like destroying a destroyed node
+            let scene_ref = scene.clone();
+            api.set("scene_remove", lua.create_function(move |_, key: mlua::Value| {
+                let name = scene_key(&key)?;
+                let mut scene = scene_ref.lock().unwrap();
+                if let Some(id) = scene.named(&name) {
+                    scene.destroy(id);
+                }
                 Ok(())
             })?)?;
 
+            let scene_ref = scene.clone();
             let buf_clone = command_buffer.clone();
-            api.set("draw_line", lua.create_function(move |_, (x1, y1, x2, y2, w): (f32, f32, f32, f32, Option<f32>)| {
-                buf_clone.cmd_draw_line(x1, y1, x2, y2, w.unwrap_or(1.0));
+            let session = drawing_session.clone();
+            let a11y = active_accessibility.clone();
+            let caps = capabilities.clone();
+            api.set("draw_scene", lua.create_function(move |_, ()| {
+                let Some(session_id) = session.lock().unwrap().clone() else { return Ok(()) };
+                let a11y = *a11y.lock().unwrap();
+                // Sessions that never announced capabilities get everything
+                let batch_moves = caps.lock().unwrap().get(&session_id)
+                    .is_none_or(|&c| c & compat::CAP_SCENE_UPDATES != 0);
+                let ops = scene_ref.lock().unwrap().sync(&session_id, |[r, g, b, a]| {
+                    let (r, g, b) = a11y.remap(r, g, b);
+                    [r, g, b, a]
+                }, batch_moves);
+                for op in ops {
+                    match op {
+                        SceneOp::Create(id, node) => buf_clone.cmd_node(OP_NODE_CREATE, id, scene::FIELDS_ALL, &node),
+                        SceneOp::Update(id, mask, node) => buf_clone.cmd_node(OP_NODE_UPDATE, id, mask, &node),
+                        SceneOp::Destroy(id) => buf_clone.cmd_node_destroy(id),
+                        SceneOp::Sync(ids) => buf_clone.cmd_node_sync(&ids),
+                        SceneOp::Moves(moves) => buf_clone.cmd_scene_moves(&moves),
+                    }
+                }
+                buf_clone.cmd_draw_scene();
                 Ok(())
             })?)?;
 
-            let buf_clone = command_buffer.clone();
-            api.set("draw_text", lua.create_function(move |_, (text, x, y): (String, f32, f32)| {
-                buf_clone.cmd_draw_text(&text, x, y);
-                Ok(())
+            // Shared game data (shared.rs): nil for unknown names
+            api.set("shared_get", lua.create_function(move |_, name: String| Ok(shared.view(&name)))?)?;
+
+            // Noise: deterministic for a given seed, values in [-1, 1]
+            api.set("noise1d", lua.create_function(|_, (x, opts): (f64, Option<mlua::Table>)| {
+                Ok(noise::noise1d(x, &read_noise_params(opts)?))
             })?)?;
 
-            let buf_clone = command_buffer.clone();
-            api.set("load_sound", lua.create_function(move |_, (name, url): (String, String)| {
-                buf_clone.cmd_load_sound(&name, &url);
-                Ok(())
+            api.set("noise2d", lua.create_function(|_, (x, y, opts): (f64, f64, Option<mlua::Table>)| {
+                Ok(noise::noise2d(x, y, &read_noise_params(opts)?))
             })?)?;
 
-            // Context-Aware Play Sound
-            let event_buf = event_buffer.clone();
-            let cmd_buf = command_buffer.clone();
-            let mode_ref = current_mode.clone();
-            
-            api.set("play_sound", lua.create_function(move |_, (name, loop_val, volume): (String, Option<bool>, Option<f32>)| {
-                let mode = *mode_ref.lock().unwrap();
-                let vol = volume.unwrap_or(1.0);
-                let lp = loop_val.unwrap_or(false);
-                
-                match mode {
-                    GameMode::Update => event_buf.cmd_play_sound(&name, lp, vol),
-                    GameMode::Draw => cmd_buf.cmd_play_sound(&name, lp, vol),
+            // A whole grid in one call (terrain, tilemaps): rows[y][x] = noise2d(x - 1, y - 1)
+            api.set("noise_map", lua.create_function(|lua, (w, h, opts): (usize, usize, Option<mlua::Table>)| {
+                if w.saturating_mul(h) > MAX_NOISE_MAP_CELLS {
+                    return Err(mlua::Error::RuntimeError(format!("Noise map too large ({}x{}, at most {} cells)", w, h, MAX_NOISE_MAP_CELLS)));
                 }
-                Ok(())
+                let p = read_noise_params(opts)?;
+                let rows = lua.create_table_with_capacity(h, 0)?;
+                for y in 0..h {
+                    let row = lua.create_table_with_capacity(w, 0)?;
+                    for x in 0..w {
+                        row.raw_set(x + 1, noise::noise2d(x as f64, y as f64, &p))?;
+                    }
+                    rows.raw_set(y + 1, row)?;
+                }
+                Ok(rows)
             })?)?;
 
-            let event_buf = event_buffer.clone();
-            let cmd_buf = command_buffer.clone();
-            let mode_ref = current_mode.clone();
-            api.set("stop_sound", lua.create_function(move |_, name: String| {
-                let mode = *mode_ref.lock().unwrap();
-                match mode {
-                    GameMode::Update => event_buf.cmd_stop_sound(&name),
-                    GameMode::Draw => cmd_buf.cmd_stop_sound(&name),
-                }
+            // Analytics: buffered here, the host decides where they go
+            let events = tracked_events.clone();
+            api.set("track", lua.create_function(move |lua, (name, props): (String, Option<mlua::Value>)| {
+                let properties: Value = match props {
+                    Some(v) => lua.from_value(v)?,
+                    None => Value::Null,
+                };
+                events.lock().unwrap().push(TrackedEvent { name, properties });
                 Ok(())
             })?)?;
 
-            let event_buf = event_buffer.clone();
-            let cmd_buf = command_buffer.clone();
-            let mode_ref = current_mode.clone();
-            api.set("set_volume", lua.create_function(move |_, (name, vol): (String, f32)| {
-                let mode = *mode_ref.lock().unwrap();
-                match mode {
-                    GameMode::Update => event_buf.cmd_set_volume(&name, vol),
-                    GameMode::Draw => cmd_buf.cmd_set_volume(&name, vol),
+            // api.set_tick_rate(hz, [send_hz]); nil: back to the host's rates
+            let rate = tick_rate.clone();
+            api.set("set_tick_rate", lua.create_function(move |_, (tick, send): (Option<u32>, Option<u32>)| {
+                for hz in tick.iter().chain(send.iter()) {
+                    if !(1..=MAX_TICK_RATE).contains(hz) {
+                        return Err(mlua::Error::RuntimeError(format!("Rate of {} Hz (1 to {})", hz, MAX_TICK_RATE)));
+                    }
                 }
+                *rate.lock().unwrap() = tick.map(|tick| TickRate { tick, send });
                 Ok(())
             })?)?;
 
-            api.set("new_spatial_db", lua.create_function(move |_, cell_size: f32| {
-                let db = SpatialDb::new(cell_size);
-                Ok(SpatialDbWrapper(Arc::new(Mutex::new(db))))
+            // api.get_client_stats(session_id) -> {queue=, queue_max=, rtt=, dropped=, frame_rate=,
+            // congested=}, nil before the host reports any
+            let stats = client_stats.clone();
+            api.set("get_client_stats", lua.create_function(move |lua, session_id: String| {
+                let Some(s) = stats.lock().unwrap().get(&session_id).copied() else { return Ok(None) };
+                let t = lua.create_table()?;
+                t.set("queue", s.queue)?;
+                t.set("queue_max", s.queue_max)?;
+                t.set("rtt", s.rtt_ms)?;
+                t.set("dropped", s.dropped)?;
+                t.set("frame_rate", s.frame_rate)?;
+                t.set("congested", s.congested)?;
+                Ok(Some(t))
             })?)?;
 
-            api.set("new_physics_world", lua.create_function(move |_, userdata: AnyUserData| {
-                let db_wrapper = userdata.borrow::<SpatialDbWrapper>()?;
-                let phys = PhysicsWorld::new(db_wrapper.0.clone());
-                Ok(PhysicsWrapper(Arc::new(Mutex::new(phys))))
+            // api.get_ping(session_id) -> round-trip time in milliseconds, nil until measured
+            let stats = client_stats.clone();
+            api.set("get_ping", lua.create_function(move |_, session_id: String| {
+                Ok(stats.lock().unwrap().get(&session_id).and_then(|s| s.rtt_ms))
             })?)?;
 
-            api.set("new_graph", lua.create_function(move |_, ()| {
-                let graph = Graph::new();
-                Ok(GraphWrapper(Arc::new(Mutex::new(graph))))
+            // api.get_auth_info(session_id) -> what the host verified the player as, nil for
+            // anonymous players
+            let auth = auth_info.clone();
+            api.set("get_auth_info", lua.create_function(move |lua, session_id: String| {
+                match auth.lock().unwrap().get(&session_id) {
+                    Some(info) => lua.to_value(info),
+                    None => Ok(mlua::Value::Nil),
+                }
+            })?)?;
+
+            // api.get_spectators() -> {session_id, ...}: sessions watching, in join order
+            let watching = spectators.clone();
+            api.set("get_spectators", lua.create_function(move |_, ()| {
+                Ok(watching.lock().unwrap().clone())
+            })?)?;
+
+            // api.send_message(session_id, table) -> false when the session has too many
+            // messages waiting (messages.rs)
+            let out = outbox.clone();
+            api.set("send_message", lua.create_function(move |lua, (session_id, message): (String, mlua::Value)| {
+                let message: Value = lua.from_value(message)?;
+                let len = message.to_string().len();
+                if len > MAX_MESSAGE_LEN {
+                    return Err(mlua::Error::RuntimeError(format!("Message of {} bytes as JSON (at most {})", len, MAX_MESSAGE_LEN)));
+                }
+                Ok(out.lock().unwrap().push(&session_id, message))
             })?)?;
 
-            globals.set("api", api)?;
+            globals.set("api", api.clone())?;
+
+            // Native plugins
+            let commands = PluginCommands::new(command_buffer.clone(), event_buffer.clone(), current_mode.clone());
+            for plugin in plugins.iter_mut() {
+                let mut ctx = PluginContext::new(&lua, api.clone(), commands.clone());
+                plugin.register(&mut ctx)
+                    .map_err(|e| anyhow::anyhow!("Plugin '{}' failed to register: {}", plugin.name(), e))?;
+                plugin_systems.append(&mut ctx.systems);
+            }
 
             // Load the game script
             lua.load(script_content).exec()?;
@@ -438,68 +3806,611 @@ impl GameState {
             command_buffer,
             event_buffer,
             current_mode,
+            systems: Mutex::new(plugin_systems),
+            path_followers,
+            timers,
+            emitters,
+            tilemaps,
+            canvases,
+            scene,
+            drawing_session,
+            tracked_events,
+            tick_rate,
+            client_stats,
+            spectators,
+            session_commands,
+            text_prompts,
+            accessibility,
+            active_accessibility,
+            camera,
+            ui_camera,
+            viewports,
+            listeners,
+            positional_sounds,
+            requested_assets,
+            culling,
+            capabilities,
+            auth_info,
+            post_effects,
+            camera_rigs,
+            lights,
+            trails,
+            float_texts,
+            settings: settings_registry,
+            input_states,
+            input_configs,
+            actions,
+            outbox,
+            shake_offset,
+            shared_draw,
+            color_palette,
+            modules,
         })
     }
 
+    /// Registers a native system, run every `update()` in registration order within its phase.
+    /// Systems belong to this GameState: after a hot reload they must be registered again.
+    pub fn add_system(&self, phase: SystemPhase, system: System) {
+        self.systems.lock().unwrap().push((phase, system));
+    }
+
+    fn run_systems(&self, phase: SystemPhase, dt: f32) -> mlua::Result<()> {
+        let mut systems = self.systems.lock().unwrap();
+        for (p, system) in systems.iter_mut() {
+            if *p == phase {
+                system(&self.lua, dt)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// URLs the script has passed to `load_sound` / `load_image` so far, sorted and deduplicated.
+    pub fn requested_assets(&self) -> Vec<String> {
+        self.requested_assets.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Drains the events recorded by `api.track` since the last call.
+    /// The rates the script asked for (api.set_tick_rate), None for the host's own. Hosts
+    /// check it every tick: a reloaded script that no longer asks goes back to theirs.
+    pub fn tick_rate(&self) -> Option<TickRate> {
+        *self.tick_rate.lock().unwrap()
+    }
+
+    /// Records how the session's connection is doing, for api.get_client_stats; frames of
+    /// a congested session leave out cosmetic layers (particles, trails, floating text).
+    pub fn set_client_stats(&self, session_id: &str, stats: ClientStats) {
+        self.client_stats.lock().unwrap().insert(session_id.to_string(), stats);
+    }
+
+    pub fn take_tracked_events(&self) -> Vec<TrackedEvent> {
+        std::mem::take(&mut *self.tracked_events.lock().unwrap())
+    }
+
+    /// Messages for `session_id` queued by api.send_message, oldest first; the host sends
+    /// each as JSON over a reliable channel.
+    pub fn take_messages(&self, session_id: &str) -> Vec<Value> {
+        self.outbox.lock().unwrap().take(session_id)
+    }
+
     pub fn begin_frame(&self) {
         self.event_buffer.clear();
+        *self.shared_draw.lock().unwrap() = SharedDraw::default();
+        self.positional_sounds.lock().unwrap().clear();
     }
 
-    pub fn update(&self, dt: f32) -> anyhow::Result<()> {
+    pub fn update(&self, dt: f32) -> Result<(), EngineError> {
         *self.current_mode.lock().unwrap() = GameMode::Update;
+        self.path_followers.step(&self.lua)?;
+        self.timers.step(&self.lua, dt as f64)?;
+        self.emitters.step(dt);
+        self.run_systems(SystemPhase::PreUpdate, dt)?;
         let globals = self.lua.globals();
         if let Ok(update) = globals.get::<_, Function>("update") {
             update.call::<_, ()>(dt)?;
         }
+        self.run_systems(SystemPhase::PostUpdate, dt)?;
+        self.camera_rigs.lock().unwrap().step(dt);
+        self.lights.lock().unwrap().step(dt);
+        self.trails.lock().unwrap().step();
+        self.float_texts.lock().unwrap().step(dt);
         Ok(())
     }
 
     // Now accepts session_id so Lua knows WHO to draw for
-    pub fn draw(&self, session_id: &str) -> anyhow::Result<Bytes> {
+    pub fn draw(&self, session_id: &str) -> Result<Bytes, EngineError> {
+        Ok(self.draw_split(session_id)?.to_bytes())
+    }
+
+    /// draw(), with the api.draw_shared segment apart: it is the same Arc for every session
+    /// drawn until the next begin_frame(), so hosts can encode it once for all of them.
+    pub fn draw_split(&self, session_id: &str) -> Result<DrawnFrame, EngineError> {
         *self.current_mode.lock().unwrap() = GameMode::Draw;
         
         // Clear previous buffer
         self.command_buffer.clear();
+        *self.camera.lock().unwrap() = Camera::SCREEN;
+        *self.ui_camera.lock().unwrap() = None;
         
         // Include events from update (sounds)
         self.command_buffer.append(&self.event_buffer);
+        if let Some(effect) = self.post_effects.lock().unwrap().take_changed(session_id) {
+            self.command_buffer.cmd_set_post(&effect);
+        }
+        if let Some(bindings) = self.actions.lock().unwrap().take_changed(session_id) {
+            self.command_buffer.cmd_action_keys(&bindings);
+        }
+        if let Some(config) = self.input_configs.lock().unwrap().take_changed(session_id) {
+            self.command_buffer.cmd_input_config(&config);
+        }
+        let sounds = self.positional_sounds.lock().unwrap().clone();
+        if !sounds.is_empty() {
+            let listener = listener_of(session_id, &self.listeners.lock().unwrap(), &self.viewports.lock().unwrap());
+            for sound in &sounds {
+                sound.write(&self.command_buffer, listener);
+            }
+        }
 
-        let globals = self.lua.globals();
-        if let Ok(draw) = globals.get::<_, Function>("draw") {
-            draw.call::<_, ()>(session_id)?;
+        let a11y = self.accessibility.lock().unwrap().get(session_id).copied().unwrap_or_default();
+        if a11y.text_scale != 1.0 {
+            self.command_buffer.cmd_set_text_scale(a11y.text_scale);
+        }
+        *self.active_accessibility.lock().unwrap() = a11y;
+        let (view, (ox, oy)) = {
+            let rigs = self.camera_rigs.lock().unwrap();
+            (rigs.view(session_id), rigs.shake_offset(session_id))
+        };
+        *self.shake_offset.lock().unwrap() = (ox, oy);
+        if view.is_some() || (ox, oy) != (0.0, 0.0) {
+            let (x, y, zoom) = view.unwrap_or((Camera::SCREEN.x, Camera::SCREEN.y, 1.0));
+            let c = Camera { x: x + ox / zoom, y: y + oy / zoom, zoom, rotation: 0.0 };
+            *self.camera.lock().unwrap() = c;
+            self.command_buffer.cmd_set_transform(&c);
         }
+        let viewport = self.viewports.lock().unwrap().get(session_id).copied();
+        let reduced_detail = self.client_stats.lock().unwrap().get(session_id).is_some_and(|s| s.congested);
+        *self.culling.lock().unwrap() = Culling { viewport, reduced_detail, ..Default::default() };
+        *self.drawing_session.lock().unwrap() = Some(session_id.to_string());
+
+        let globals = self.lua.globals();
+        let result = match globals.get::<_, Function>("draw") {
+            Ok(draw) => draw.call::<_, ()>(session_id),
+            Err(_) => Ok(()),
+        };
+        *self.active_accessibility.lock().unwrap() = Accessibility::default();
+        *self.culling.lock().unwrap() = Culling::default();
+        *self.drawing_session.lock().unwrap() = None;
+        *self.shake_offset.lock().unwrap() = (0.0, 0.0);
+        let placed = self.shared_draw.lock().unwrap().take_placed();
+        result?;
+        self.flush_session_commands(session_id);
         
-        Ok(self.command_buffer.get_bytes())
+        let frame = self.command_buffer.get_bytes();
+        let downgraded = self.capabilities.lock().unwrap().get(session_id).is_some_and(|&caps| caps & compat::CAPS_ALL != compat::CAPS_ALL);
+        match placed {
+            Some((range, segment)) if !downgraded => Ok(DrawnFrame {
+                before: frame.slice(..range.start),
+                shared: Some(segment),
+                after: frame.slice(range.end..),
+            }),
+            _ => Ok(self.for_client(session_id, frame).into()),
+        }
     }
     
-    pub fn handle_input(&self, session_id: &str, input_code: u8, active: bool) -> anyhow::Result<()> {
-         let globals = self.lua.globals();
-         if let Ok(on_input) = globals.get::<_, Function>("on_input") {
-             on_input.call::<_, ()>((session_id, input_code, active))?;
-         }
-         Ok(())
+    /// A decoded input message, with the sequence number and client clock it was sent with.
+    /// Late ones (reordered by the DataChannel) still reach the callbacks, but keys and
+    /// gamepad changes don't update what api.is_down and actions see: a later input has.
+    pub fn handle_message(&self, session_id: &str, message: InputMessage, meta: Option<InputMeta>) -> Result<(), EngineError> {
+        let current = meta.is_none_or(|m| self.input_states.lock().unwrap().sequence(session_id, m.seq));
+        match message {
+            InputMessage::Key(code, active) => self.key_input(session_id, code, active, meta, current),
+            InputMessage::Mouse { event, x, y, button } => self.handle_mouse(session_id, event, x, y, button),
+            InputMessage::Touch { phase, id, x, y } => self.handle_touch(session_id, id, phase, x, y),
+            InputMessage::Gamepad { pad, control, value } => self.gamepad_input(session_id, pad, control, value, current),
+        }
+    }
+
+    pub fn handle_input(&self, session_id: &str, input_code: u8, active: bool) -> Result<(), EngineError> {
+        self.key_input(session_id, input_code, active, None, true)
+    }
+
+    // on_input(session_id, key_code, is_down, seq, client_time); seq and time are nil for
+    // inputs sent without them
+    fn key_input(&self, session_id: &str, input_code: u8, active: bool, meta: Option<InputMeta>, current: bool) -> Result<(), EngineError> {
+        if self.spectating(session_id) {
+            return self.spectator_input(session_id, input_code, active);
+        }
+        if current {
+            self.input_states.lock().unwrap().key(session_id, input_code, active);
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_input) = globals.get::<_, Function>("on_input") {
+            on_input.call::<_, ()>((session_id, input_code, active, meta.map(|m| m.seq), meta.map(|m| m.time)))?;
+        }
+        if current { self.dispatch_actions(session_id) } else { Ok(()) }
+    }
+
+    /// Pointer events: on_mouse(session_id, event, x, y, button) in screen coordinates.
+    pub fn handle_mouse(&self, session_id: &str, event: MouseEvent, x: f32, y: f32, button: i8) -> Result<(), EngineError> {
+        if self.spectating(session_id) {
+            return Ok(());
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_mouse) = globals.get::<_, Function>("on_mouse") {
+            on_mouse.call::<_, ()>((session_id, event.name(), x, y, button))?;
+        }
+        Ok(())
+    }
+
+    /// Touches: on_touch(session_id, id, phase, x, y) in screen coordinates.
+    pub fn handle_touch(&self, session_id: &str, id: u16, phase: TouchPhase, x: f32, y: f32) -> Result<(), EngineError> {
+        if self.spectating(session_id) {
+            return Ok(());
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_touch) = globals.get::<_, Function>("on_touch") {
+            on_touch.call::<_, ()>((session_id, id, phase.name(), x, y))?;
+        }
+        Ok(())
+    }
+
+    /// Gamepads: on_gamepad(session_id, control, value, pad) when a button or axis changes.
+    pub fn handle_gamepad(&self, session_id: &str, pad: u8, control: GamepadControl, value: f32) -> Result<(), EngineError> {
+        self.gamepad_input(session_id, pad, control, value, true)
+    }
+
+    fn gamepad_input(&self, session_id: &str, pad: u8, control: GamepadControl, value: f32, current: bool) -> Result<(), EngineError> {
+        if self.spectating(session_id) {
+            return Ok(());
+        }
+        if current {
+            self.input_states.lock().unwrap().gamepad(session_id, pad, control, value);
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_gamepad) = globals.get::<_, Function>("on_gamepad") {
+            on_gamepad.call::<_, ()>((session_id, &*control.name(), value, pad))?;
+        }
+        if current { self.dispatch_actions(session_id) } else { Ok(()) }
+    }
+
+    // on_action(session_id, action, is_down) for actions the session's last input started or stopped
+    fn dispatch_actions(&self, session_id: &str) -> Result<(), EngineError> {
+        let changes = {
+            let input = self.input_states.lock().unwrap();
+            self.actions.lock().unwrap().update(session_id, &input)
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_action) = globals.get::<_, Function>("on_action") {
+            for (action, down) in changes {
+                on_action.call::<_, ()>((session_id, action, down))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the script's optional `on_before_connect(session_id, auth_info)` whether a new
+    /// client may join, before on_connect. Hosts ask again for queued clients. Nothing is
+    /// kept for the session: set_auth_info once it is let in.
+    pub fn before_connect(&self, session_id: &str, auth_info: Option<&Value>) -> Result<Admission, EngineError> {
+        let globals = self.lua.globals();
+        let Ok(cb) = globals.get::<_, Function>("on_before_connect") else { return Ok(Admission::Accept) };
+        let auth_info = match auth_info {
+            Some(info) => self.lua.to_value(info)?,
+            None => mlua::Value::Nil,
+        };
+        match cb.call::<_, mlua::Value>((session_id, auth_info))? {
+            mlua::Value::Nil | mlua::Value::Boolean(true) => Ok(Admission::Accept),
+            mlua::Value::Boolean(false) => Ok(Admission::Reject),
+            mlua::Value::String(s) if s.to_str().ok() == Some("queue") => Ok(Admission::Queue),
+            other => Err(mlua::Error::RuntimeError(format!(
+                "on_before_connect must return true, false or \"queue\", not {}", other.type_name()
+            )).into()),
+        }
     }
 
-    pub fn on_connect(&self, session_id: &str) -> anyhow::Result<Bytes> {
+    pub fn on_connect(&self, session_id: &str) -> Result<Bytes, EngineError> {
         self.command_buffer.clear();
+        {
+            let settings = self.settings.lock().unwrap();
+            if !settings.all().is_empty() {
+                self.command_buffer.cmd_settings(settings.all());
+            }
+        }
+        if let Some(bindings) = self.actions.lock().unwrap().take_changed(session_id) {
+            self.command_buffer.cmd_action_keys(&bindings);
+        }
+        let auth_info = match self.auth_info.lock().unwrap().get(session_id) {
+            Some(info) => self.lua.to_value(info)?,
+            None => mlua::Value::Nil,
+        };
         let globals = self.lua.globals();
         if let Ok(cb) = globals.get::<_, Function>("on_connect") {
+            cb.call::<_, ()>((session_id, auth_info))?;
+        }
+        self.flush_session_commands(session_id);
+        Ok(self.for_client(session_id, self.command_buffer.get_bytes()))
+    }
+
+    /// A client joining to watch: it is drawn like a player, but on_connect doesn't run, so
+    /// the script's players stay as they are. Calls the script's optional
+    /// `on_spectator_connect(session_id)`; api.get_spectators lists the session until
+    /// on_disconnect. Its key presses go to `on_spectator_input(session_id, key_code,
+    /// is_down)`; its other inputs, text and messages are ignored.
+    pub fn on_spectate(&self, session_id: &str) -> Result<Bytes, EngineError> {
+        self.command_buffer.clear();
+        {
+            let settings = self.settings.lock().unwrap();
+            if !settings.all().is_empty() {
+                self.command_buffer.cmd_settings(settings.all());
+            }
+        }
+        {
+            let mut spectators = self.spectators.lock().unwrap();
+            if !spectators.iter().any(|s| s == session_id) {
+                spectators.push(session_id.to_string());
+            }
+        }
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_spectator_connect") {
+            cb.call::<_, ()>(session_id)?;
+        }
+        self.flush_session_commands(session_id);
+        Ok(self.for_client(session_id, self.command_buffer.get_bytes()))
+    }
+
+    /// Whether `session_id` joined with on_spectate.
+    pub fn spectating(&self, session_id: &str) -> bool {
+        self.spectators.lock().unwrap().iter().any(|s| s == session_id)
+    }
+
+    fn spectator_input(&self, session_id: &str, input_code: u8, active: bool) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_spectator_input") {
+            cb.call::<_, ()>((session_id, input_code, active))?;
+        }
+        Ok(())
+    }
+
+    /// A new client took over `session_id` after the old one's connection dropped (the
+    /// host kept the session meanwhile, without on_disconnect). The player's state stays;
+    /// what the new client doesn't have yet is sent again: the settings menu, action keys,
+    /// post effects, input config and cached state (tilemaps, canvases, scene). Keys and
+    /// actions held when the connection dropped are released (on_action is told). Calls
+    /// the script's `on_reconnect(session_id)`; commands addressed to the session while it
+    /// was away follow.
+    pub fn on_reconnect(&self, session_id: &str) -> Result<Bytes, EngineError> {
+        self.command_buffer.clear();
+        {
+            let settings = self.settings.lock().unwrap();
+            if !settings.all().is_empty() {
+                self.command_buffer.cmd_settings(settings.all());
+            }
+        }
+        self.post_effects.lock().unwrap().resend(session_id);
+        self.input_configs.lock().unwrap().resend(session_id);
+        self.frame_dropped(session_id);
+        self.input_states.lock().unwrap().forget_session(session_id);
+        let released = self.actions.lock().unwrap().reset_client(session_id);
+        if let Some(bindings) = self.actions.lock().unwrap().take_changed(session_id) {
+            self.command_buffer.cmd_action_keys(&bindings);
+        }
+        let globals = self.lua.globals();
+        if let Ok(on_action) = globals.get::<_, Function>("on_action") {
+            for action in released {
+                on_action.call::<_, ()>((session_id, action, false))?;
+            }
+        }
+        if let Ok(cb) = globals.get::<_, Function>("on_reconnect") {
+            cb.call::<_, ()>(session_id)?;
+        }
+        self.flush_session_commands(session_id);
+        Ok(self.for_client(session_id, self.command_buffer.get_bytes()))
+    }
+
+    /// Opcode groups (compat::CAP_*) the client of `session_id` understands: its frames
+    /// only use those, with fallbacks for the rest.
+    pub fn set_capabilities(&self, session_id: &str, caps: u32) {
+        self.capabilities.lock().unwrap().insert(session_id.to_string(), caps);
+    }
+
+    /// The identity the host verified for `session_id` (token claims, an auth service's
+    /// answer), set before on_connect: the script gets it as on_connect's second argument
+    /// and from api.get_auth_info. Kept until on_disconnect.
+    pub fn set_auth_info(&self, session_id: &str, info: Value) {
+        self.auth_info.lock().unwrap().insert(session_id.to_string(), info);
+    }
+
+    fn for_client(&self, session_id: &str, frame: Bytes) -> Bytes {
+        match self.capabilities.lock().unwrap().get(session_id) {
+            Some(&caps) if caps & compat::CAPS_ALL != compat::CAPS_ALL => {
+                compat::downgrade_with_palette(&frame, caps, &self.color_palette.lock().unwrap())
+            }
+            _ => frame,
+        }
+    }
+
+    // Appends commands addressed to one client (control schemes, text prompts) to its frame
+    fn flush_session_commands(&self, session_id: &str) {
+        if let Some(commands) = self.session_commands.lock().unwrap().remove(session_id) {
+            self.command_buffer.append(&commands);
+        }
+    }
+
+    /// Text typed in answer to api.request_text_input, passed to the script's
+    /// `on_text_input(session_id, text)`. Ignored if no prompt is open for the session.
+    /// Text longer than any prompt accepts is a protocol error.
+    pub fn handle_text_input(&self, session_id: &str, text: &str) -> Result<(), EngineError> {
+        if text.len() > MAX_TEXT_INPUT_LEN * 4 {
+            return Err(EngineError::Protocol(format!("Text input of {} bytes (at most {} characters)", text.len(), MAX_TEXT_INPUT_LEN)));
+        }
+        if self.spectating(session_id) {
+            return Ok(());
+        }
+        let Some(max_len) = self.text_prompts.lock().unwrap().remove(session_id) else { return Ok(()) };
+        let text: String = text.chars().filter(|c| !c.is_control()).take(max_len).collect();
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_text_input") {
+            cb.call::<_, ()>((session_id, text))?;
+        }
+        Ok(())
+    }
+
+    /// Text the player pasted outside any text prompt (word games, level codes), passed to
+    /// the script's `on_paste(session_id, text)`. Control characters other than line breaks
+    /// are dropped; text longer than a prompt could take is a protocol error.
+    pub fn handle_paste(&self, session_id: &str, text: &str) -> Result<(), EngineError> {
+        if text.len() > MAX_TEXT_INPUT_LEN * 4 {
+            return Err(EngineError::Protocol(format!("Pasted text of {} bytes (at most {} characters)", text.len(), MAX_TEXT_INPUT_LEN)));
+        }
+        let text: String = text.chars().filter(|&c| c == '\n' || !c.is_control()).take(MAX_TEXT_INPUT_LEN).collect();
+        if text.is_empty() || self.spectating(session_id) {
+            return Ok(());
+        }
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_paste") {
+            cb.call::<_, ()>((session_id, text))?;
+        }
+        Ok(())
+    }
+
+    /// A message from the client, passed to the script's `on_message(session_id, message)`
+    /// (a table for JSON objects and arrays). Larger than any message may be is a protocol error.
+    pub fn handle_script_message(&self, session_id: &str, message: &Value) -> Result<(), EngineError> {
+        let len = message.to_string().len();
+        if len > MAX_MESSAGE_LEN {
+            return Err(EngineError::Protocol(format!("Message of {} bytes (at most {})", len, MAX_MESSAGE_LEN)));
+        }
+        if self.spectating(session_id) {
+            return Ok(());
+        }
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_message") {
+            cb.call::<_, ()>((session_id, self.lua.to_value(message)?))?;
+        }
+        Ok(())
+    }
+
+    /// The client of `session_id` finished preloading the asset manifest (or has none to load).
+    pub fn handle_assets_ready(&self, session_id: &str) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_assets_ready") {
             cb.call::<_, ()>(session_id)?;
         }
-        Ok(self.command_buffer.get_bytes())
+        Ok(())
+    }
+
+    /// A sound or music track ran to its end on the client of `session_id` (stopped and
+    /// looping ones are not reported).
+    pub fn handle_sound_finished(&self, session_id: &str, name: &str) -> Result<(), EngineError> {
+        if name.len() > u16::MAX as usize {
+            return Err(EngineError::Protocol(format!("Sound name of {} bytes", name.len())));
+        }
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_sound_finished") {
+            cb.call::<_, ()>((name, session_id))?;
+        }
+        Ok(())
+    }
+
+    /// A player changed `key` in the settings menu. Passed to the script's
+    /// `on_setting_changed(session_id, key, value)` unless the key is unknown, the value
+    /// doesn't fit the setting or nothing changed.
+    pub fn handle_setting(&self, session_id: &str, key: &str, value: &Value) -> Result<(), EngineError> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(EngineError::Protocol(format!("Setting key of {} bytes", key.len())));
+        }
+        let Some(value) = self.settings.lock().unwrap().change(session_id, key, value) else { return Ok(()) };
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_setting_changed") {
+            cb.call::<_, ()>((session_id, key, self.lua.to_value(&value)?))?;
+        }
+        Ok(())
     }
 
-    pub fn on_disconnect(&self, session_id: &str) -> anyhow::Result<()> {
+    /// The client of `session_id` left: what the engine kept for the session is dropped and
+    /// the script's `on_disconnect(session_id)` runs (`on_spectator_disconnect` for spectators).
+    pub fn on_disconnect(&self, session_id: &str) -> Result<(), EngineError> {
+        self.session_commands.lock().unwrap().remove(session_id);
+        self.text_prompts.lock().unwrap().remove(session_id);
+        self.settings.lock().unwrap().forget_session(session_id);
+        self.input_states.lock().unwrap().forget_session(session_id);
+        self.input_configs.lock().unwrap().forget_session(session_id);
+        self.actions.lock().unwrap().forget_session(session_id);
+        self.outbox.lock().unwrap().forget_session(session_id);
+        self.client_stats.lock().unwrap().remove(session_id);
+        self.accessibility.lock().unwrap().remove(session_id);
+        self.viewports.lock().unwrap().remove(session_id);
+        self.listeners.lock().unwrap().remove(session_id);
+        self.post_effects.lock().unwrap().forget_session(session_id);
+        self.camera_rigs.lock().unwrap().forget_session(session_id);
+        self.capabilities.lock().unwrap().remove(session_id);
+        self.auth_info.lock().unwrap().remove(session_id);
+        self.tilemaps.forget_session(session_id);
+        self.canvases.forget_session(session_id);
+        self.scene.lock().unwrap().forget(session_id);
+        let hook = {
+            let mut spectators = self.spectators.lock().unwrap();
+            let before = spectators.len();
+            spectators.retain(|s| s != session_id);
+            if spectators.len() < before { "on_spectator_disconnect" } else { "on_disconnect" }
+        };
         let globals = self.lua.globals();
-        if let Ok(cb) = globals.get::<_, Function>("on_disconnect") {
+        if let Ok(cb) = globals.get::<_, Function>(hook) {
             cb.call::<_, ()>(session_id)?;
         }
         Ok(())
     }
 
+    /// The host could not deliver a frame to `session_id`: state the client caches
+    /// (tilemap chunks, canvases, scene nodes) is sent again in full with its next frame.
+    pub fn frame_dropped(&self, session_id: &str) {
+        self.tilemaps.forget_session(session_id);
+        self.canvases.forget_session(session_id);
+        self.scene.lock().unwrap().forget(session_id);
+    }
+
+    /// Calls the script's optional `on_room_idle()` hook (the last player just left).
+    pub fn on_room_idle(&self) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_room_idle") {
+            cb.call::<_, ()>(())?;
+        }
+        Ok(())
+    }
+
+    /// Calls the script's optional `on_room_message(from_room, event, payload)` hook with a
+    /// message another room's script sent (api.room_send, api.rooms_broadcast on servers).
+    pub fn on_room_message(&self, from: &str, event: &str, payload: &Value) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_room_message") {
+            cb.call::<_, ()>((from, event, self.lua.to_value(payload)?))?;
+        }
+        Ok(())
+    }
+
+    /// Calls the script's optional `on_shutdown()` hook (the server is stopping). Messages
+    /// it sends with api.send_message still go out.
+    pub fn on_shutdown(&self) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        if let Ok(cb) = globals.get::<_, Function>("on_shutdown") {
+            cb.call::<_, ()>(())?;
+        }
+        Ok(())
+    }
+
     // --- State Persistence for Hot Reload ---
 
-    pub fn snapshot_state(&self) -> anyhow::Result<String> {
+    pub fn snapshot_state(&self) -> Result<String, EngineError> {
+        self.asteroids_snapshot().map_err(EngineError::snapshot)
+    }
+
+    pub fn restore_state(&self, json_state: &str) -> Result<(), EngineError> {
+        self.asteroids_restore(json_state).map_err(EngineError::snapshot)
+    }
+
+    fn asteroids_snapshot(&self) -> anyhow::Result<String> {
         let globals = self.lua.globals();
         
         // Get as generic Lua Value first
@@ -521,7 +4432,7 @@ impl GameState {
         Ok(state.to_string())
     }
 
-    pub fn restore_state(&self, json_state: &str) -> anyhow::Result<()> {
+    fn asteroids_restore(&self, json_state: &str) -> anyhow::Result<()> {
         let globals = self.lua.globals();
         let state: Value = serde_json::from_str(json_state)?;
 
@@ -542,6 +4453,71 @@ impl GameState {
         Ok(())
     }
 
+    /// Applies a change to the module file at `path` without a full reload: the module and
+    /// every module requiring it run again and are patched into their package.loaded tables
+    /// (functions replaced, data kept). Returns the patched modules. On error (not a
+    /// required module, a module not returning a table, a script error) the caller should
+    /// fall back to a full reload: modules patched before the error stay patched.
+    pub fn reload_module(&self, path: &std::path::Path) -> Result<Vec<String>, EngineError> {
+        self.patch_modules(path).map_err(EngineError::init)
+    }
+
+    fn patch_modules(&self, path: &std::path::Path) -> anyhow::Result<Vec<String>> {
+        let files = {
+            let graph = self.modules.lock().unwrap();
+            let name = graph.module_at(path)
+                .ok_or_else(|| anyhow::anyhow!("{} is not a required module", path.display()))?;
+            graph.reload_order(name).into_iter()
+                .map(|name| {
+                    let file = graph.path_of(&name).map(|p| p.to_path_buf());
+                    file.map(|f| (name.clone(), f)).ok_or_else(|| anyhow::anyhow!("Module '{}' has no file", name))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        };
+
+        // Compile everything first, so syntax errors patch nothing. Each module then runs
+        // after the modules it requires were patched, seeing their new functions.
+        let loaded: mlua::Table = self.lua.globals().get::<_, mlua::Table>("package")?.get("loaded")?;
+        let mut chunks = Vec::new();
+        for (name, file) in &files {
+            let mlua::Value::Table(current) = loaded.get::<_, mlua::Value>(name.as_str())? else {
+                anyhow::bail!("Module '{}' does not return a table", name);
+            };
+            let file_name = file.to_string_lossy().to_string();
+            let chunk = self.lua.load(std::fs::read_to_string(file)?)
+                .set_name(format!("@{}", file_name))
+                .into_function()?;
+            chunks.push((name.as_str(), file_name, chunk, current));
+        }
+        for (name, file_name, chunk, current) in chunks {
+            let mlua::Value::Table(fresh) = chunk.call::<_, mlua::Value>((name, file_name.as_str()))? else {
+                anyhow::bail!("Module '{}' does not return a table", name);
+            };
+            patch_module(&current, &fresh)?;
+        }
+        Ok(files.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Generic snapshot: the value returned by the script's `snapshot()` hook, as JSON.
+    /// None if the script does not define one.
+    pub fn snapshot(&self) -> Result<Option<Value>, EngineError> {
+        let globals = self.lua.globals();
+        let Ok(hook) = globals.get::<_, Function>("snapshot") else { return Ok(None) };
+        let state: mlua::Value = hook.call(()).map_err(EngineError::snapshot)?;
+        Ok(Some(self.lua.from_value(state).map_err(EngineError::snapshot)?))
+    }
+
+    /// Hands a value produced by `snapshot()` back to the script's `restore(state)` hook,
+    /// which is responsible for rebuilding native objects (spatial db, physics bodies...).
+    pub fn restore(&self, state: &Value) -> Result<(), EngineError> {
+        let globals = self.lua.globals();
+        let hook: Function = globals.get("restore")
+            .map_err(|_| EngineError::Snapshot("Script defines snapshot() but no restore(state)".into()))?;
+        let state = self.lua.to_value(state).map_err(EngineError::snapshot)?;
+        hook.call::<_, ()>(state).map_err(EngineError::snapshot)?;
+        Ok(())
+    }
+
     pub fn eval(&self, code: &str) -> String {
         match self.lua.load(code).eval::<mlua::Value>() {
             Ok(v) => format!("{:?}", v),
@@ -0,0 +1,146 @@
+use crate::geom::{self, Vec2};
+use crate::noise::{self, NoiseParams};
+use crate::slab::Slab;
+use std::f32::consts::TAU;
+
+// --- Lighting ---
+// Lights and shadow-casting occluders kept by the engine. api.draw_lights() sends the lights
+// in view as one OP_LIGHTS command: the client darkens what was drawn so far to the ambient
+// color and adds each light as a radial glow. A light with occluders in range is clipped to
+// its visibility polygon, cached until the light moves or the occluders change.
+
+// Flicker wobbles per second
+const FLICKER_FREQUENCY: f64 = 8.0;
+// Rays cast around a shadowed light, besides the ones aimed at occluder corners
+const SHADOW_RAYS: usize = 32;
+// Rays either side of a corner (radians) graze past it onto what lies behind
+const CORNER_OFFSET: f32 = 1e-4;
+
+pub struct Light {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub color: [u8; 4], // Alpha is the intensity
+    pub flicker: f32,   // 0 = steady, 1 = dims down to nothing
+    shadow: Option<Vec<Vec2>>, // None until computed; empty when nothing is in range
+}
+
+/// A light ready to encode: flicker applied.
+pub struct LightSprite<'a> {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub rgba: [u8; 4],
+    pub shadow: &'a [Vec2], // Visibility polygon; empty = the whole circle is lit
+}
+
+pub struct Lights {
+    lights: Slab<Light>,
+    occluders: Vec<(Vec2, Vec2)>,
+    pub ambient: [u8; 3],
+    time: f64,
+}
+
+impl Default for Lights {
+    fn default() -> Self {
+        Self { lights: Slab::new(), occluders: Vec::new(), ambient: [0, 0, 0], time: 0.0 }
+    }
+}
+
+impl Lights {
+    pub fn add(&mut self, x: f32, y: f32, radius: f32, color: [u8; 4], flicker: f32) -> u64 {
+        self.lights.insert(Light { x, y, radius, color, flicker, shadow: None })
+    }
+
+    pub fn move_to(&mut self, id: u64, x: f32, y: f32) -> bool {
+        match self.lights.get_mut(id) {
+            Some(light) => {
+                if (light.x, light.y) != (x, y) {
+                    light.x = x;
+                    light.y = y;
+                    light.shadow = None;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        self.lights.remove(id).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.lights = Slab::new();
+    }
+
+    /// Replaces the occluders with the edges of `polygons` (closed outlines).
+    pub fn set_occluders(&mut self, polygons: &[Vec<Vec2>]) {
+        self.occluders.clear();
+        for polygon in polygons {
+            for (i, &a) in polygon.iter().enumerate() {
+                let b = polygon[(i + 1) % polygon.len()];
+                if a != b {
+                    self.occluders.push((a, b));
+                }
+            }
+        }
+        for (_, light) in self.lights.iter_mut() {
+            light.shadow = None;
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.time += dt as f64;
+    }
+
+    /// The lights `visible` keeps, with their shadows (computed if stale) and flicker applied.
+    pub fn frame(&mut self, visible: impl Fn(&Light) -> bool) -> Vec<LightSprite<'_>> {
+        let t = self.time * FLICKER_FREQUENCY;
+        let occluders = &self.occluders;
+        self.lights.iter_mut()
+            .filter(|(_, light)| visible(light))
+            .map(|(id, light)| {
+                if light.shadow.is_none() {
+                    light.shadow = Some(visibility_polygon((light.x, light.y), light.radius, occluders));
+                }
+                let mut rgba = light.color;
+                if light.flicker > 0.0 {
+                    let params = NoiseParams { seed: id as u32, ..Default::default() };
+                    let dim = light.flicker * (noise::noise1d(t, &params) as f32 + 1.0) / 2.0;
+                    rgba[3] = (rgba[3] as f32 * (1.0 - dim)).round() as u8;
+                }
+                LightSprite { x: light.x, y: light.y, radius: light.radius, rgba, shadow: light.shadow.as_deref().unwrap_or(&[]) }
+            })
+            .collect()
+    }
+}
+
+// The part of the circle around `origin` that sees past the occluders, as an outline
+// (empty when no occluder is in range). Rays are cast towards every corner in range and
+// just past it, plus evenly around the circle so the unblocked parts stay round.
+fn visibility_polygon(origin: Vec2, radius: f32, occluders: &[(Vec2, Vec2)]) -> Vec<Vec2> {
+    let near: Vec<(Vec2, Vec2)> = occluders.iter().copied().filter(|&(a, b)| {
+        let (closest, _) = geom::closest_point_on_segment(origin, a, b);
+        geom::length((closest.0 - origin.0, closest.1 - origin.1)) < radius
+    }).collect();
+    if near.is_empty() {
+        return Vec::new();
+    }
+    let mut angles: Vec<f32> = (0..SHADOW_RAYS).map(|i| i as f32 * TAU / SHADOW_RAYS as f32).collect();
+    for &(a, b) in &near {
+        for corner in [a, b] {
+            let angle = (corner.1 - origin.1).atan2(corner.0 - origin.0);
+            angles.extend([angle - CORNER_OFFSET, angle, angle + CORNER_OFFSET].map(|x| x.rem_euclid(TAU)));
+        }
+    }
+    angles.sort_by(|a, b| a.total_cmp(b));
+    angles.dedup();
+    angles.into_iter().map(|angle| {
+        let end = (origin.0 + angle.cos() * radius, origin.1 + angle.sin() * radius);
+        let t = near.iter()
+            .filter_map(|&(a, b)| geom::segment_intersection(origin, end, a, b))
+            .fold(1.0f32, |nearest, (t, _)| nearest.min(t));
+        (geom::lerp(origin.0, end.0, t), geom::lerp(origin.1, end.1, t))
+    }).collect()
+}
@@ -0,0 +1,40 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+// --- Script Messages ---
+// Structured data (inventories, chat, scores) exchanged between the script and a client
+// outside the frame stream. api.send_message(session_id, table) queues a message for the
+// session; the host delivers it as JSON over a reliable channel, in order. Messages from
+// the client reach the script as on_message(session_id, table). Messages to a session
+// that is away (see the server's reconnect grace) wait for its next client.
+
+/// Largest message either way, as JSON.
+pub const MAX_MESSAGE_LEN: usize = 64 * 1024;
+/// Messages waiting for one session; api.send_message refuses more.
+pub const MAX_QUEUED_MESSAGES: usize = 256;
+
+#[derive(Default)]
+pub struct Outbox {
+    queued: HashMap<String, Vec<Value>>,
+}
+
+impl Outbox {
+    /// False when the session already has MAX_QUEUED_MESSAGES waiting.
+    pub fn push(&mut self, session_id: &str, message: Value) -> bool {
+        let queue = self.queued.entry(session_id.to_string()).or_default();
+        if queue.len() >= MAX_QUEUED_MESSAGES {
+            return false;
+        }
+        queue.push(message);
+        true
+    }
+
+    /// The session's messages, oldest first.
+    pub fn take(&mut self, session_id: &str) -> Vec<Value> {
+        self.queued.remove(session_id).unwrap_or_default()
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.queued.remove(session_id);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// --- Module Graph ---
+// require() is wrapped to record the file each module was loaded from and which modules
+// required it. When one module file changes, it and the modules requiring it (directly
+// or not) can be run again and patched into their existing package.loaded tables instead
+// of reloading the whole game: functions are replaced, data fields keep their current
+// values. The main script is never run again; it sees the patched functions through the
+// same tables. Modules that don't return a table can't be patched this way.
+
+#[derive(Default)]
+pub struct ModuleGraph {
+    files: HashMap<PathBuf, String>,              // Canonical path -> module name
+    paths: HashMap<String, PathBuf>,              // Module name -> canonical path
+    dependents: HashMap<String, BTreeSet<String>>, // Module -> modules that required it
+    loading: Vec<String>,                         // Modules whose chunk is running
+}
+
+impl ModuleGraph {
+    /// Records that `name` is being required (searched in `package_path`) until `done`.
+    pub fn enter(&mut self, name: &str, package_path: &str) {
+        if let Some(parent) = self.loading.last() {
+            self.dependents.entry(name.to_string()).or_default().insert(parent.clone());
+        }
+        if let Some(path) = resolve(package_path, name) {
+            self.files.insert(path.clone(), name.to_string());
+            self.paths.insert(name.to_string(), path);
+        }
+        self.loading.push(name.to_string());
+    }
+
+    pub fn done(&mut self) {
+        self.loading.pop();
+    }
+
+    pub fn module_at(&self, path: &Path) -> Option<&str> {
+        let path = path.canonicalize().ok()?;
+        self.files.get(&path).map(String::as_str)
+    }
+
+    pub fn path_of(&self, name: &str) -> Option<&Path> {
+        self.paths.get(name).map(PathBuf::as_path)
+    }
+
+    /// `name` and every module requiring it, directly or not, each after the modules it requires.
+    pub fn reload_order(&self, name: &str) -> Vec<String> {
+        fn visit(graph: &ModuleGraph, name: &str, seen: &mut HashSet<String>, out: &mut Vec<String>) {
+            if !seen.insert(name.to_string()) {
+                return;
+            }
+            for dependent in graph.dependents.get(name).into_iter().flatten() {
+                visit(graph, dependent, seen, out);
+            }
+            out.push(name.to_string());
+        }
+        let mut out = Vec::new();
+        visit(self, name, &mut HashSet::new(), &mut out);
+        out.reverse();
+        out
+    }
+}
+
+// Same search as require: every `?` template of package.path, dots in the name as separators
+fn resolve(package_path: &str, name: &str) -> Option<PathBuf> {
+    let file = name.replace('.', "/");
+    package_path.split(';')
+        .map(|template| PathBuf::from(template.replace('?', &file)))
+        .find(|path| path.is_file())
+        .and_then(|path| path.canonicalize().ok())
+}
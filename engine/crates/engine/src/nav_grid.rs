@@ -0,0 +1,162 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use thiserror::Error;
+
+// --- Nav Grids ---
+// Pathfinding over a grid of cells (tile maps, mazes): every cell is walkable or blocked
+// and has a movement cost multiplier (1.0 normal, >1 mud or hazards, <1 roads). find_path
+// runs A* over the 8 neighbors of each cell, never cutting a corner past a blocked cell;
+// a step costs its length (1 or √2) times the cost of the cell it enters.
+
+pub const MAX_NAV_GRID_SIZE: usize = 4096; // Cells per side
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NavGridError {
+    #[error("nav grid cell ({0}, {1}) is outside the grid")]
+    OutOfBounds(usize, usize),
+    #[error("nav cost must be a positive number (got {0})")]
+    InvalidCost(f32),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct State {
+    f_score: f32,
+    cell: usize,
+}
+
+impl Eq for State {}
+
+impl Ord for State {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed: BinaryHeap pops the lowest f_score first
+        other.f_score.partial_cmp(&self.f_score).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+pub struct NavGrid {
+    width: usize,
+    height: usize,
+    walkable: Vec<bool>,
+    costs: Vec<f32>,
+}
+
+impl NavGrid {
+    /// Every cell walkable at cost 1.0. Sides are clamped to 1..=MAX_NAV_GRID_SIZE.
+    pub fn new(width: usize, height: usize) -> Self {
+        let (width, height) = (width.clamp(1, MAX_NAV_GRID_SIZE), height.clamp(1, MAX_NAV_GRID_SIZE));
+        Self { width, height, walkable: vec![true; width * height], costs: vec![1.0; width * height] }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, x: usize, y: usize) -> Result<usize, NavGridError> {
+        if x >= self.width || y >= self.height {
+            return Err(NavGridError::OutOfBounds(x, y));
+        }
+        Ok(y * self.width + x)
+    }
+
+    pub fn set_walkable(&mut self, x: usize, y: usize, walkable: bool) -> Result<(), NavGridError> {
+        let i = self.index(x, y)?;
+        self.walkable[i] = walkable;
+        Ok(())
+    }
+
+    /// False outside the grid.
+    pub fn is_walkable(&self, x: usize, y: usize) -> bool {
+        self.index(x, y).is_ok_and(|i| self.walkable[i])
+    }
+
+    pub fn set_cost(&mut self, x: usize, y: usize, cost: f32) -> Result<(), NavGridError> {
+        if !(cost > 0.0 && cost.is_finite()) {
+            return Err(NavGridError::InvalidCost(cost));
+        }
+        let i = self.index(x, y)?;
+        self.costs[i] = cost;
+        Ok(())
+    }
+
+    pub fn get_cost(&self, x: usize, y: usize) -> Option<f32> {
+        self.index(x, y).ok().map(|i| self.costs[i])
+    }
+
+    /// The cheapest path from `start` to `goal`, both ends included; None when the goal
+    /// can't be reached (or either end is blocked).
+    pub fn find_path(&self, start: (usize, usize), goal: (usize, usize)) -> Result<Option<Vec<(usize, usize)>>, NavGridError> {
+        let start = self.index(start.0, start.1)?;
+        let goal = self.index(goal.0, goal.1)?;
+        if !self.walkable[start] || !self.walkable[goal] {
+            return Ok(None);
+        }
+
+        // The heuristic is scaled by the cheapest cell so it never overestimates
+        let min_cost = self.costs.iter().zip(&self.walkable)
+            .filter(|(_, &w)| w)
+            .map(|(&c, _)| c)
+            .fold(f32::INFINITY, f32::min);
+        let (gx, gy) = (goal % self.width, goal / self.width);
+        let heuristic = |cell: usize| {
+            // Octile distance
+            let dx = (cell % self.width).abs_diff(gx) as f32;
+            let dy = (cell / self.width).abs_diff(gy) as f32;
+            (dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy)) * min_cost
+        };
+
+        let mut g_score = vec![f32::INFINITY; self.walkable.len()];
+        let mut came_from = vec![usize::MAX; self.walkable.len()];
+        let mut open_set = BinaryHeap::new();
+        g_score[start] = 0.0;
+        open_set.push(State { f_score: heuristic(start), cell: start });
+
+        while let Some(State { f_score, cell: current }) = open_set.pop() {
+            if current == goal {
+                let mut path = vec![(goal % self.width, goal / self.width)];
+                let mut cell = goal;
+                while came_from[cell] != usize::MAX {
+                    cell = came_from[cell];
+                    path.push((cell % self.width, cell / self.width));
+                }
+                path.reverse();
+                return Ok(Some(path));
+            }
+            if f_score > g_score[current] + heuristic(current) {
+                continue; // Stale entry: a cheaper way here was found since
+            }
+
+            let (x, y) = ((current % self.width) as isize, (current / self.width) as isize);
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if !self.open(nx, ny) {
+                    continue;
+                }
+                // Diagonals only between two open cells
+                if dx != 0 && dy != 0 && (!self.open(x + dx, y) || !self.open(x, y + dy)) {
+                    continue;
+                }
+                let neighbor = ny as usize * self.width + nx as usize;
+                let step = if dx != 0 && dy != 0 { std::f32::consts::SQRT_2 } else { 1.0 };
+                let tentative_g = g_score[current] + step * self.costs[neighbor];
+                if tentative_g < g_score[neighbor] {
+                    came_from[neighbor] = current;
+                    g_score[neighbor] = tentative_g;
+                    open_set.push(State { f_score: tentative_g + heuristic(neighbor), cell: neighbor });
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Inside the grid and walkable
+    fn open(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && self.is_walkable(x as usize, y as usize)
+    }
+}
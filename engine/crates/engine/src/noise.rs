@@ -0,0 +1,97 @@
+// --- Gradient Noise ---
+// Perlin-style gradient noise in 1D and 2D, summed over octaves (fractal noise). Lattice
+// gradients come from hashing the cell coordinates with the seed, so the same seed and
+// inputs give the same values on every server, with no tables to build or share.
+
+#[derive(Clone, Copy)]
+pub struct NoiseParams {
+    pub octaves: u32,
+    pub frequency: f64,
+    pub seed: u32,
+    pub persistence: f64, // Amplitude kept from one octave to the next
+    pub lacunarity: f64,  // Frequency multiplier from one octave to the next
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        Self { octaves: 1, frequency: 1.0, seed: 0, persistence: 0.5, lacunarity: 2.0 }
+    }
+}
+
+pub const MAX_OCTAVES: u32 = 16;
+
+// Integer hash (murmur3 finalizer) of a lattice point
+fn hash(x: i64, y: i64, seed: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841) ^ seed.wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Slope in [-1, 1] at a 1D lattice point
+fn gradient1(x: i64, seed: u32) -> f64 {
+    hash(x, 0, seed) as f64 / u32::MAX as f64 * 2.0 - 1.0
+}
+
+// One of 8 unit-ish directions at a 2D lattice point, dotted with the offset
+fn gradient2(x: i64, y: i64, seed: u32, dx: f64, dy: f64) -> f64 {
+    match hash(x, y, seed) & 7 {
+        0 => dx + dy,
+        1 => dx - dy,
+        2 => -dx + dy,
+        3 => -dx - dy,
+        4 => dx,
+        5 => -dx,
+        6 => dy,
+        _ => -dy,
+    }
+}
+
+// Single octave, roughly in [-1, 1]
+fn perlin1(x: f64, seed: u32) -> f64 {
+    let x0 = x.floor();
+    let t = x - x0;
+    let i = x0 as i64;
+    let a = gradient1(i, seed) * t;
+    let b = gradient1(i + 1, seed) * (t - 1.0);
+    lerp(a, b, fade(t)) * 2.0
+}
+
+fn perlin2(x: f64, y: f64, seed: u32) -> f64 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (i, j) = (x0 as i64, y0 as i64);
+    let top = lerp(gradient2(i, j, seed, tx, ty), gradient2(i + 1, j, seed, tx - 1.0, ty), fade(tx));
+    let bottom = lerp(gradient2(i, j + 1, seed, tx, ty - 1.0), gradient2(i + 1, j + 1, seed, tx - 1.0, ty - 1.0), fade(tx));
+    lerp(top, bottom, fade(ty)).clamp(-1.0, 1.0)
+}
+
+// Octaves summed and normalized back to [-1, 1]; each octave gets its own seed
+fn fractal(p: &NoiseParams, sample: impl Fn(f64, u32) -> f64) -> f64 {
+    let (mut total, mut norm, mut amplitude, mut frequency) = (0.0, 0.0, 1.0, p.frequency);
+    for octave in 0..p.octaves.clamp(1, MAX_OCTAVES) {
+        total += sample(frequency, p.seed.wrapping_add(octave.wrapping_mul(0x9e37_79b9))) * amplitude;
+        norm += amplitude;
+        amplitude *= p.persistence;
+        frequency *= p.lacunarity;
+    }
+    if norm > 0.0 { (total / norm).clamp(-1.0, 1.0) } else { 0.0 }
+}
+
+pub fn noise1d(x: f64, p: &NoiseParams) -> f64 {
+    fractal(p, |f, seed| perlin1(x * f, seed))
+}
+
+pub fn noise2d(x: f64, y: f64, p: &NoiseParams) -> f64 {
+    fractal(p, |f, seed| perlin2(x * f, y * f, seed))
+}
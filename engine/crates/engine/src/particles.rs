@@ -0,0 +1,171 @@
+use std::f32::consts::TAU;
+use std::sync::{Arc, Mutex, Weak};
+
+// --- Particles ---
+// Emitters are simulated natively before every update(dt) and drawn as a single
+// OP_DRAW_PARTICLES batch. Lua only holds a handle: once it is garbage collected the
+// emitter and its particles are dropped.
+
+#[derive(Clone, Copy, Debug)]
+pub struct EmitterConfig {
+    pub x: f32,
+    pub y: f32,
+    pub rate: f32,         // Particles per second
+    pub lifetime: f32,     // Seconds
+    pub speed: f32,
+    pub speed_spread: f32, // Speed is speed +/- speed_spread
+    pub angle: f32,        // Emission direction (radians)
+    pub spread: f32,       // Cone width around `angle` (radians, TAU = all directions)
+    pub gravity: (f32, f32),
+    pub size: f32,
+    pub size_end: f32,
+    pub color: [u8; 4],
+    pub color_end: [u8; 4],
+    pub max: usize,
+    pub circles: bool,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            rate: 20.0,
+            lifetime: 1.0,
+            speed: 50.0,
+            speed_spread: 0.0,
+            angle: 0.0,
+            spread: TAU,
+            gravity: (0.0, 0.0),
+            size: 3.0,
+            size_end: 3.0,
+            color: [255, 255, 255, 255],
+            color_end: [255, 255, 255, 0],
+            max: 500,
+            circles: false,
+        }
+    }
+}
+
+struct Particle {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    age: f32,
+}
+
+/// A particle ready to encode: position, size and color already interpolated.
+pub struct ParticleSprite {
+    pub x: f32,
+    pub y: f32,
+    pub size: f32,
+    pub rgba: [u8; 4],
+}
+
+pub struct Emitter {
+    pub config: EmitterConfig,
+    particles: Vec<Particle>,
+    pending: f32, // Fractional particles carried over between ticks
+    rng: u64,
+}
+
+impl Emitter {
+    pub fn new(config: EmitterConfig, seed: u64) -> Self {
+        Self { config, particles: Vec::new(), pending: 0.0, rng: seed | 1 }
+    }
+
+    // xorshift64, mapped to [0, 1)
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    pub fn burst(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.particles.len() >= self.config.max {
+                break;
+            }
+            let c = &self.config;
+            let (angle, speed, spread, speed_spread) = (c.angle, c.speed, c.spread, c.speed_spread);
+            let angle = angle + (self.random() - 0.5) * spread;
+            let speed = speed + (self.random() * 2.0 - 1.0) * speed_spread;
+            let (sin, cos) = angle.sin_cos();
+            self.particles.push(Particle { x: self.config.x, y: self.config.y, vx: cos * speed, vy: sin * speed, age: 0.0 });
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        let lifetime = self.config.lifetime;
+        let (gx, gy) = self.config.gravity;
+        self.particles.retain_mut(|p| {
+            p.age += dt;
+            p.vx += gx * dt;
+            p.vy += gy * dt;
+            p.x += p.vx * dt;
+            p.y += p.vy * dt;
+            p.age < lifetime
+        });
+
+        self.pending += self.config.rate.max(0.0) * dt;
+        let spawn = self.pending.floor();
+        self.pending -= spawn;
+        self.burst(spawn as usize);
+    }
+
+    pub fn count(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending = 0.0;
+    }
+
+    /// Current particles with size and color interpolated over their lifetime.
+    /// `color` and `color_end` are passed in so the caller can remap them (accessibility).
+    pub fn sprites(&self, color: [u8; 4], color_end: [u8; 4]) -> impl Iterator<Item = ParticleSprite> + '_ {
+        let c = &self.config;
+        self.particles.iter().map(move |p| {
+            let t = if c.lifetime > 0.0 { (p.age / c.lifetime).clamp(0.0, 1.0) } else { 1.0 };
+            let mut rgba = [0u8; 4];
+            for i in 0..4 {
+                rgba[i] = (color[i] as f32 + (color_end[i] as f32 - color[i] as f32) * t).round() as u8;
+            }
+            ParticleSprite { x: p.x, y: p.y, size: c.size + (c.size_end - c.size) * t, rgba }
+        })
+    }
+}
+
+/// Every live emitter, stepped by the engine before each update(dt).
+#[derive(Clone, Default)]
+pub struct Emitters {
+    live: Arc<Mutex<Vec<Weak<Mutex<Emitter>>>>>,
+    created: Arc<Mutex<u64>>,
+}
+
+impl Emitters {
+    pub fn create(&self, config: EmitterConfig) -> Arc<Mutex<Emitter>> {
+        // Seeded from the creation order, so runs are reproducible
+        let seed = {
+            let mut created = self.created.lock().unwrap();
+            *created += 1;
+            created.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        };
+        let emitter = Arc::new(Mutex::new(Emitter::new(config, seed)));
+        self.live.lock().unwrap().push(Arc::downgrade(&emitter));
+        emitter
+    }
+
+    pub fn step(&self, dt: f32) {
+        self.live.lock().unwrap().retain(|weak| match weak.upgrade() {
+            Some(emitter) => {
+                emitter.lock().unwrap().step(dt);
+                true
+            }
+            None => false, // Handle collected by Lua
+        });
+    }
+}
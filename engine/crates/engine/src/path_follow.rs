@@ -0,0 +1,90 @@
+use crate::physics::PhysicsWorld;
+use mlua::{Function, Lua, RegistryKey};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// --- Path Following ---
+// Engine-managed waypoint steering. Each tick (before the Lua update) a follower
+// advances past every waypoint within `arrive_radius`, then points the body's
+// velocity at the next one. When the last waypoint is reached the body is stopped
+// and the completion callback runs.
+
+pub struct PathFollow {
+    phys: Arc<Mutex<PhysicsWorld>>,
+    waypoints: Vec<(f32, f32)>,
+    next: usize,
+    speed: f32,
+    arrive_radius: f32,
+    on_complete: Option<RegistryKey>,
+}
+
+enum Progress {
+    Moving,
+    Arrived,
+    Lost, // Body or entity removed
+}
+
+impl PathFollow {
+    pub fn new(phys: Arc<Mutex<PhysicsWorld>>, waypoints: Vec<(f32, f32)>, speed: f32, arrive_radius: f32, on_complete: Option<RegistryKey>) -> Self {
+        Self { phys, waypoints, next: 0, speed, arrive_radius, on_complete }
+    }
+
+    fn advance(&mut self, id: u64) -> Progress {
+        let mut phys = self.phys.lock().unwrap();
+        while let Some(&(x, y)) = self.waypoints.get(self.next) {
+            let Some((dx, dy)) = phys.delta_to(id, x, y) else { return Progress::Lost };
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist > self.arrive_radius {
+                phys.set_velocity(id, dx / dist * self.speed, dy / dist * self.speed);
+                return Progress::Moving;
+            }
+            self.next += 1;
+        }
+        phys.set_velocity(id, 0.0, 0.0);
+        Progress::Arrived
+    }
+}
+
+/// Active followers keyed by entity id (one path per entity; BTreeMap for a deterministic callback order).
+#[derive(Clone, Default)]
+pub struct PathFollowers(Arc<Mutex<BTreeMap<u64, PathFollow>>>);
+
+impl PathFollowers {
+    /// Starts following, replacing any path the entity was already on.
+    pub fn start(&self, id: u64, follow: PathFollow) {
+        self.0.lock().unwrap().insert(id, follow);
+    }
+
+    pub fn stop(&self, id: u64) -> bool {
+        self.0.lock().unwrap().remove(&id).is_some()
+    }
+
+    pub fn is_following(&self, id: u64) -> bool {
+        self.0.lock().unwrap().contains_key(&id)
+    }
+
+    pub fn step(&self, lua: &Lua) -> mlua::Result<()> {
+        let mut finished = Vec::new();
+        {
+            let mut followers = self.0.lock().unwrap();
+            followers.retain(|&id, follow| match follow.advance(id) {
+                Progress::Moving => true,
+                Progress::Arrived => {
+                    finished.push((id, follow.on_complete.take()));
+                    false
+                }
+                Progress::Lost => false,
+            });
+        }
+
+        // Callbacks run unlocked so they can start a new path
+        for (id, key) in finished {
+            if let Some(key) = key {
+                let callback: Function = lua.registry_value(&key)?;
+                lua.remove_registry_value(key)?;
+                callback.call::<_, ()>(id)?;
+            }
+        }
+        Ok(())
+    }
+}
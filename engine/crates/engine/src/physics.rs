@@ -1,6 +1,8 @@
 use crate::spatial_db::{SpatialDb, EntityKind};
+use crate::geom;
+use crate::slab::Slab;
 use std::sync::{Arc, Mutex};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
 #[derive(Clone, Debug)]
 pub struct RigidBody {
@@ -11,6 +13,7 @@ pub struct RigidBody {
     pub restitution: f32, // 0.0 to 1.0 (bounciness)
     pub drag: f32,        // Air resistance
     pub is_static: bool,
+    pub is_sensor: bool,  // Detects overlaps (enter/exit) without collision response
 }
 
 impl RigidBody {
@@ -25,31 +28,123 @@ impl RigidBody {
             restitution,
             drag,
             is_static,
+            is_sensor: false,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ContactPhase {
+    Begin, // First step the pair touches
+    Stay,  // Still touching since the previous step
+    End,   // Touched in the previous step, not anymore
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SensorPhase {
+    Enter,
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundsMode {
+    Clamp,   // Stop at the edge (velocity into the edge is zeroed)
+    Wrap,    // Reappear at the opposite edge
+    Destroy, // Remove entity + body
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WorldBounds {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+    pub mode: BoundsMode,
+}
+
+// Contact, sensor and bounds events wait for the script to read them; one that never does
+// keeps at most the latest this many of each
+const MAX_PENDING_EVENTS: usize = 4096;
+
+// Appends a step's events. Trimmed back to the latest MAX_PENDING_EVENTS only once twice
+// that many are pending, so a script that never reads them doesn't pay a copy every step.
+fn push_events<T>(pending: &mut Vec<T>, events: impl IntoIterator<Item = T>) {
+    pending.extend(events);
+    if pending.len() > 2 * MAX_PENDING_EVENTS {
+        pending.drain(..pending.len() - MAX_PENDING_EVENTS);
+    }
+}
+
+// What a getter hands out: the latest MAX_PENDING_EVENTS
+fn take_events<T>(pending: &mut Vec<T>) -> Vec<T> {
+    let mut events = std::mem::take(pending);
+    if events.len() > MAX_PENDING_EVENTS {
+        events.drain(..events.len() - MAX_PENDING_EVENTS);
+    }
+    events
+}
+
 pub struct PhysicsWorld {
     db: Arc<Mutex<SpatialDb>>,
-    bodies: HashMap<u64, RigidBody>,
+    bodies: Slab<RigidBody>, // Keyed by SpatialDb handle
     gravity_x: f32,
     gravity_y: f32,
+    damping: f32, // Global linear damping, applied on top of per-body drag
+    bounds: Option<WorldBounds>,
+    bounds_events: Vec<(u64, BoundsMode)>,
     collisions: HashSet<(u64, u64)>, // Unique pairs per step
+    // Pair persistence across steps (for begin/stay/end)
+    step_contacts: HashSet<(u64, u64)>,
+    prev_contacts: HashSet<(u64, u64)>,
+    contact_events: Vec<((u64, u64), ContactPhase)>,
+    // Sensor overlaps, keyed (sensor, other)
+    step_overlaps: HashSet<(u64, u64)>,
+    prev_overlaps: HashSet<(u64, u64)>,
+    sensor_events: Vec<((u64, u64), SensorPhase)>,
 }
 
 impl PhysicsWorld {
     pub fn new(db: Arc<Mutex<SpatialDb>>) -> Self {
         Self {
             db,
-            bodies: HashMap::new(),
+            bodies: Slab::new(),
             gravity_x: 0.0,
             gravity_y: 0.0,
+            damping: 0.0,
+            bounds: None,
+            bounds_events: Vec::new(),
             collisions: HashSet::new(),
+            step_contacts: HashSet::new(),
+            prev_contacts: HashSet::new(),
+            contact_events: Vec::new(),
+            step_overlaps: HashSet::new(),
+            prev_overlaps: HashSet::new(),
+            sensor_events: Vec::new(),
         }
     }
 
     pub fn get_collision_events(&mut self) -> Vec<(u64, u64)> {
-        self.collisions.drain().collect()
+        let mut events: Vec<(u64, u64)> = self.collisions.drain().collect();
+        events.sort_unstable(); // Deterministic order for Lua
+        events
+    }
+
+    /// Contact events (begin/stay/end) accumulated since the last call, the latest
+    /// MAX_PENDING_EVENTS at most.
+    pub fn get_contact_events(&mut self) -> Vec<((u64, u64), ContactPhase)> {
+        take_events(&mut self.contact_events)
+    }
+
+    /// Sensor enter/exit events accumulated since the last call, keyed (sensor, other);
+    /// the latest MAX_PENDING_EVENTS at most.
+    pub fn get_sensor_events(&mut self) -> Vec<((u64, u64), SensorPhase)> {
+        take_events(&mut self.sensor_events)
+    }
+
+    /// Bodies that hit the world bounds since the last call, with the action taken; the
+    /// latest MAX_PENDING_EVENTS at most.
+    pub fn get_bounds_events(&mut self) -> Vec<(u64, BoundsMode)> {
+        take_events(&mut self.bounds_events)
     }
 
     pub fn set_gravity(&mut self, x: f32, y: f32) {
@@ -57,23 +152,52 @@ impl PhysicsWorld {
         self.gravity_y = y;
     }
 
-    pub fn add_body(&mut self, id: u64, mass: f32, restitution: f32, drag: f32) {
-        self.bodies.insert(id, RigidBody::new(mass, restitution, drag));
+    pub fn get_gravity(&self) -> (f32, f32) {
+        (self.gravity_x, self.gravity_y)
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.max(0.0);
+    }
+
+    pub fn get_damping(&self) -> f32 {
+        self.damping
+    }
+
+    pub fn set_bounds(&mut self, bounds: Option<WorldBounds>) {
+        self.bounds = bounds;
+    }
+
+    pub fn add_body(&mut self, id: u64, mass: f32, restitution: f32, drag: f32, is_sensor: bool) {
+        // Bodies are components of SpatialDb entities; unknown handles are ignored
+        if !self.db.lock().unwrap().contains(id) { return; }
+        let mut body = RigidBody::new(mass, restitution, drag);
+        body.is_sensor = is_sensor;
+        self.bodies.insert_at(id, body);
     }
 
     pub fn remove_body(&mut self, id: u64) {
-        self.bodies.remove(&id);
+        self.bodies.remove(id);
     }
 
     pub fn set_velocity(&mut self, id: u64, vx: f32, vy: f32) {
-        if let Some(body) = self.bodies.get_mut(&id) {
+        if let Some(body) = self.bodies.get_mut(id) {
             body.vx = vx;
             body.vy = vy;
         }
     }
 
     pub fn get_velocity(&self, id: u64) -> Option<(f32, f32)> {
-        self.bodies.get(&id).map(|b| (b.vx, b.vy))
+        self.bodies.get(id).map(|b| (b.vx, b.vy))
+    }
+
+    /// Offset from a body to the point (x, y), taking the shortest way around in toroidal worlds.
+    /// None if `id` has no body (or its entity was removed).
+    pub fn delta_to(&self, id: u64, x: f32, y: f32) -> Option<(f32, f32)> {
+        if !self.bodies.contains(id) { return None; }
+        let db = self.db.lock().unwrap();
+        let (px, py) = db.get_position(id)?;
+        Some(db.wrap_delta(x - px, y - py))
     }
 
     pub fn step(&mut self, dt: f32) {
@@ -83,6 +207,12 @@ impl PhysicsWorld {
         // Lock DB once to read positions for all bodies
         let mut db = self.db.lock().unwrap();
 
+        // Drop bodies whose entity was removed from the DB
+        let stale: Vec<u64> = self.bodies.handles().filter(|id| !db.contains(*id)).collect();
+        for id in stale {
+            self.bodies.remove(id);
+        }
+
         for (id, body) in self.bodies.iter_mut() {
             if body.is_static { continue; }
 
@@ -96,12 +226,19 @@ impl PhysicsWorld {
                 body.vy *= 1.0 - body.drag * dt;
             }
 
+            // Apply Global Damping
+            if self.damping > 0.0 {
+                let factor = (1.0 - self.damping * dt).max(0.0);
+                body.vx *= factor;
+                body.vy *= factor;
+            }
+
             // Get current pos from DB
-            if let Some(pos) = db.get_position(*id) {
+            if let Some(pos) = db.get_position(id) {
                 let new_x = pos.0 + body.vx * dt;
                 let new_y = pos.1 + body.vy * dt;
                 
-                updates.push((*id, new_x, new_y));
+                updates.push((id, new_x, new_y));
             }
         }
 
@@ -112,7 +249,7 @@ impl PhysicsWorld {
 
         // 2. Collision Detection & Resolution
         
-        let dynamic_ids: Vec<u64> = self.bodies.keys().cloned().filter(|id| !self.bodies[id].is_static).collect();
+        let dynamic_ids: Vec<u64> = self.bodies.iter().filter(|(_, b)| !b.is_static).map(|(id, _)| id).collect();
         
         for id_a in dynamic_ids {
             let (pos_a, radius_a) = match db.get_entity_info(id_a) {
@@ -120,7 +257,10 @@ impl PhysicsWorld {
                 _ => continue, 
             };
 
-            let body_a = self.bodies[&id_a].clone(); 
+            let body_a = match self.bodies.get(id_a) {
+                Some(b) => b.clone(),
+                None => continue,
+            };
 
             // Query potential colliders
             let nearby = db.query_range(pos_a.0, pos_a.1, radius_a + 50.0, None); 
@@ -136,6 +276,15 @@ impl PhysicsWorld {
                 if info_b.is_none() { continue; }
                 let (x_b, y_b, kind_b) = info_b.unwrap();
 
+                // Toroidal world: use the image of B nearest to A (no-op otherwise)
+                let (rel_x, rel_y) = db.wrap_delta(x_b - pos_a.0, y_b - pos_a.1);
+                let (shift_x, shift_y) = (pos_a.0 + rel_x - x_b, pos_a.1 + rel_y - y_b);
+                let (x_b, y_b) = (x_b + shift_x, y_b + shift_y);
+                let kind_b = match kind_b {
+                    EntityKind::Segment { x2, y2 } => EntityKind::Segment { x2: x2 + shift_x, y2: y2 + shift_y },
+                    k => k,
+                };
+
                 // Check collision
                 let collision = match kind_b {
                     EntityKind::Circle { radius: radius_b } => {
@@ -155,17 +304,8 @@ impl PhysicsWorld {
                         }
                     },
                     EntityKind::Segment { x2, y2 } => {
-                        let dx_seg = x2 - x_b;
-                        let dy_seg = y2 - y_b;
-                        let len_sq = dx_seg*dx_seg + dy_seg*dy_seg;
-                        let mut t: f32 = 0.0;
-                        if len_sq > 0.0 {
-                            t = ((pos_a.0 - x_b) * dx_seg + (pos_a.1 - y_b) * dy_seg) / len_sq;
-                            t = t.max(0.0).min(1.0);
-                        }
-                        let closest_x = x_b + t * dx_seg;
-                        let closest_y = y_b + t * dy_seg;
-                        
+                        let ((closest_x, closest_y), _) = geom::closest_point_on_segment(pos_a, (x_b, y_b), (x2, y2));
+
                         let dx = pos_a.0 - closest_x;
                         let dy = pos_a.1 - closest_y;
                         let dist_sq = dx*dx + dy*dy;
@@ -182,13 +322,22 @@ impl PhysicsWorld {
                 };
 
                 if let Some((nx, ny, penetration)) = collision {
+                    // Sensors only record the overlap, no response
+                    let sensor_b = self.bodies.get(id_b).is_some_and(|b| b.is_sensor);
+                    if body_a.is_sensor || sensor_b {
+                        if body_a.is_sensor { self.step_overlaps.insert((id_a, id_b)); }
+                        if sensor_b { self.step_overlaps.insert((id_b, id_a)); }
+                        continue;
+                    }
+
                     // Store Collision Event
                     // Normalize order to avoid duplicates (A,B) and (B,A)
                     let pair = if id_a < id_b { (id_a, id_b) } else { (id_b, id_a) };
                     self.collisions.insert(pair);
+                    self.step_contacts.insert(pair);
 
                     // RESOLVE
-                    let body_b_opt = self.bodies.get(&id_b).cloned();
+                    let body_b_opt = self.bodies.get(id_b).cloned();
                     
                     let (inv_mass_b, vel_bx, vel_by, restitution_b) = if let Some(bb) = body_b_opt {
                         (bb.inv_mass, bb.vx, bb.vy, bb.restitution)
@@ -216,7 +365,7 @@ impl PhysicsWorld {
                     }
 
                     // Velocity Impulse
-                    let ba = self.bodies.get_mut(&id_a).unwrap();
+                    let ba = self.bodies.get_mut(id_a).unwrap();
                     let vax = ba.vx;
                     let vay = ba.vy;
                     
@@ -234,11 +383,11 @@ impl PhysicsWorld {
                     let impulse_x = j * nx;
                     let impulse_y = j * ny;
                     
-                    self.bodies.get_mut(&id_a).unwrap().vx -= impulse_x * body_a.inv_mass;
-                    self.bodies.get_mut(&id_a).unwrap().vy -= impulse_y * body_a.inv_mass;
+                    self.bodies.get_mut(id_a).unwrap().vx -= impulse_x * body_a.inv_mass;
+                    self.bodies.get_mut(id_a).unwrap().vy -= impulse_y * body_a.inv_mass;
                     
                     if inv_mass_b > 0.0 {
-                        if let Some(bb) = self.bodies.get_mut(&id_b) {
+                        if let Some(bb) = self.bodies.get_mut(id_b) {
                             bb.vx += impulse_x * inv_mass_b;
                             bb.vy += impulse_y * inv_mass_b;
                         }
@@ -246,5 +395,70 @@ impl PhysicsWorld {
                 }
             }
         }
+
+        // 3. World Bounds
+        if let Some(bounds) = self.bounds {
+            let dynamic_ids: Vec<u64> = self.bodies.iter().filter(|(_, b)| !b.is_static).map(|(id, _)| id).collect();
+            let mut events = Vec::new();
+            for id in dynamic_ids {
+                let Some((x, y)) = db.get_position(id) else { continue };
+                let outside = x < bounds.min_x || x > bounds.max_x || y < bounds.min_y || y > bounds.max_y;
+                if !outside { continue; }
+
+                match bounds.mode {
+                    BoundsMode::Clamp => {
+                        db.update_position(id, x.clamp(bounds.min_x, bounds.max_x), y.clamp(bounds.min_y, bounds.max_y));
+                        if let Some(b) = self.bodies.get_mut(id) {
+                            if (x < bounds.min_x && b.vx < 0.0) || (x > bounds.max_x && b.vx > 0.0) { b.vx = 0.0; }
+                            if (y < bounds.min_y && b.vy < 0.0) || (y > bounds.max_y && b.vy > 0.0) { b.vy = 0.0; }
+                        }
+                    },
+                    BoundsMode::Wrap => {
+                        let w = bounds.max_x - bounds.min_x;
+                        let h = bounds.max_y - bounds.min_y;
+                        let nx = if w > 0.0 { bounds.min_x + (x - bounds.min_x).rem_euclid(w) } else { x };
+                        let ny = if h > 0.0 { bounds.min_y + (y - bounds.min_y).rem_euclid(h) } else { y };
+                        db.update_position(id, nx, ny);
+                    },
+                    BoundsMode::Destroy => {
+                        db.remove(id);
+                        self.bodies.remove(id);
+                    }
+                }
+                events.push((id, bounds.mode));
+            }
+            push_events(&mut self.bounds_events, events);
+        }
+
+        drop(db);
+        self.track_phases();
+    }
+
+    // Diff this step's pairs against the previous step's
+    fn track_phases(&mut self) {
+        let curr = std::mem::take(&mut self.step_contacts);
+        let mut events: Vec<((u64, u64), ContactPhase)> = Vec::new();
+        for &pair in &curr {
+            let phase = if self.prev_contacts.contains(&pair) { ContactPhase::Stay } else { ContactPhase::Begin };
+            events.push((pair, phase));
+        }
+        for &pair in self.prev_contacts.difference(&curr) {
+            events.push((pair, ContactPhase::End));
+        }
+        events.sort_unstable();
+        push_events(&mut self.contact_events, events);
+        self.prev_contacts = curr;
+
+        let curr = std::mem::take(&mut self.step_overlaps);
+        let mut events: Vec<((u64, u64), SensorPhase)> = Vec::new();
+        for &pair in curr.difference(&self.prev_overlaps) {
+            events.push((pair, SensorPhase::Enter));
+        }
+        for &pair in self.prev_overlaps.difference(&curr) {
+            events.push((pair, SensorPhase::Exit));
+        }
+        events.sort_unstable();
+        push_events(&mut self.sensor_events, events);
+        self.prev_overlaps = curr;
     }
 }
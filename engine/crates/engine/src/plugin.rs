@@ -0,0 +1,87 @@
+use crate::{CommandBuffer, GameMode, System, SystemPhase, OP_PLUGIN_FIRST};
+use mlua::Lua;
+use std::sync::{Arc, Mutex};
+
+// --- Native Plugins ---
+// A plugin adds native capabilities without forking the engine: Lua functions,
+// custom opcodes (0xC0..=0xFF, framed as opcode + u16 len + payload so clients
+// without a handler can skip them) and tick systems.
+//
+// Plugins outlive GameState instances: `register` is called again on every hot reload.
+
+pub trait Plugin: Send {
+    fn name(&self) -> &str;
+
+    fn register(&mut self, ctx: &mut PluginContext) -> mlua::Result<()>;
+}
+
+/// Builds a plugin from its settings table (the `[plugin.settings]` section of cleoselene.toml).
+pub type PluginFactory = fn(&serde_json::Value) -> Box<dyn Plugin>;
+
+/// Symbol exported by dynamic library plugins:
+/// `#[no_mangle] pub fn cleoselene_plugin_create(settings: &serde_json::Value) -> Box<dyn Plugin>`.
+/// The library must be built with the same compiler and engine version as the server.
+pub const PLUGIN_CREATE_SYMBOL: &[u8] = b"cleoselene_plugin_create";
+
+pub struct PluginContext<'lua> {
+    lua: &'lua Lua,
+    api: mlua::Table<'lua>,
+    commands: PluginCommands,
+    pub(crate) systems: Vec<(SystemPhase, System)>,
+}
+
+impl<'lua> PluginContext<'lua> {
+    pub(crate) fn new(lua: &'lua Lua, api: mlua::Table<'lua>, commands: PluginCommands) -> Self {
+        Self { lua, api, commands, systems: Vec::new() }
+    }
+
+    pub fn lua(&self) -> &'lua Lua {
+        self.lua
+    }
+
+    /// The global `api` table, for registering functions next to the built-in ones.
+    pub fn api(&self) -> &mlua::Table<'lua> {
+        &self.api
+    }
+
+    /// Writer for the plugin's custom opcodes. Cheap to clone into Lua closures.
+    pub fn commands(&self) -> PluginCommands {
+        self.commands.clone()
+    }
+
+    pub fn add_system(&mut self, phase: SystemPhase, system: System) {
+        self.systems.push((phase, system));
+    }
+}
+
+#[derive(Clone)]
+pub struct PluginCommands {
+    command_buffer: CommandBuffer,
+    event_buffer: CommandBuffer,
+    current_mode: Arc<Mutex<GameMode>>,
+}
+
+impl PluginCommands {
+    pub(crate) fn new(command_buffer: CommandBuffer, event_buffer: CommandBuffer, current_mode: Arc<Mutex<GameMode>>) -> Self {
+        Self { command_buffer, event_buffer, current_mode }
+    }
+
+    /// Emits a custom command. Like sounds, commands written during update() are
+    /// broadcast to every client; during draw() they only go to the client being drawn.
+    pub fn write(&self, opcode: u8, payload: &[u8]) -> mlua::Result<()> {
+        if opcode < OP_PLUGIN_FIRST {
+            return Err(mlua::Error::RuntimeError(format!(
+                "Plugin opcode 0x{:02X} is reserved (plugins use 0x{:02X}..=0xFF)", opcode, OP_PLUGIN_FIRST
+            )));
+        }
+        if payload.len() > u16::MAX as usize {
+            return Err(mlua::Error::RuntimeError(format!("Plugin payload too large ({} bytes)", payload.len())));
+        }
+        let mode = *self.current_mode.lock().unwrap();
+        match mode {
+            GameMode::Update => self.event_buffer.cmd_plugin(opcode, payload),
+            GameMode::Draw => self.command_buffer.cmd_plugin(opcode, payload),
+        }
+        Ok(())
+    }
+}
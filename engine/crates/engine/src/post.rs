@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+// --- Post-processing ---
+// Whole-screen settings the client applies after drawing each frame (tint, vignette,
+// brightness/contrast, scanlines). Set for every client or for one session, which overrides
+// the shared settings. Sent in a session's frame only when they change for it, and to new
+// clients when they differ from the defaults.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PostEffect {
+    pub tint: [u8; 4], // Drawn over the frame; alpha 0 = none
+    pub vignette: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    pub scanlines: bool,
+}
+
+impl Default for PostEffect {
+    fn default() -> Self {
+        Self { tint: [0, 0, 0, 0], vignette: 0.0, brightness: 1.0, contrast: 1.0, scanlines: false }
+    }
+}
+
+/// Target of api.set_post_effect that means every client.
+pub const ALL_SESSIONS: &str = "all";
+
+#[derive(Default)]
+pub struct PostEffects {
+    all: PostEffect,
+    sessions: HashMap<String, PostEffect>,
+    // What each client was last sent
+    sent: HashMap<String, PostEffect>,
+}
+
+impl PostEffects {
+    /// None resets `target`: to the defaults for ALL_SESSIONS, to the shared settings for a session.
+    pub fn set(&mut self, target: &str, effect: Option<PostEffect>) {
+        match (target, effect) {
+            (ALL_SESSIONS, effect) => self.all = effect.unwrap_or_default(),
+            (session_id, Some(effect)) => { self.sessions.insert(session_id.to_string(), effect); }
+            (session_id, None) => { self.sessions.remove(session_id); }
+        }
+    }
+
+    /// The session's settings if its client doesn't have them yet (marked as sent).
+    pub fn take_changed(&mut self, session_id: &str) -> Option<PostEffect> {
+        let effect = self.sessions.get(session_id).copied().unwrap_or(self.all);
+        let sent = self.sent.get(session_id).copied().unwrap_or_default();
+        if effect == sent {
+            return None;
+        }
+        self.sent.insert(session_id.to_string(), effect);
+        Some(effect)
+    }
+
+    /// The session's client starts over (it reconnected): its settings are sent again.
+    pub fn resend(&mut self, session_id: &str) {
+        self.sent.remove(session_id);
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+        self.sent.remove(session_id);
+    }
+}
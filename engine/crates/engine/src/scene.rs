@@ -0,0 +1,209 @@
+use std::collections::{BTreeMap, HashMap};
+
+// --- Retained Scene ---
+// Nodes live in the engine across frames; clients keep a copy and draw it where the
+// script calls api.draw_scene(). Each draw_scene() only carries what changed since the
+// client's previous one: OP_NODE_CREATE for nodes it hasn't seen, OP_NODE_UPDATE with
+// just the changed fields, OP_NODE_DESTROY for removed nodes. Clients interpolate
+// transforms between frames.
+//
+// Frames can be lost in transit: every sync also resends one node in full (round-robin)
+// and every RESYNC_FRAMES syncs the list of live ids (OP_NODE_SYNC), so clients heal.
+//
+// Moving entities are the bulk of the traffic: updates that only change position and
+// rotation are batched into one OP_SCENE_UPDATE for clients that understand it.
+
+const RESYNC_FRAMES: u32 = 90;
+
+// Fields of OP_NODE_CREATE/OP_NODE_UPDATE, in encoding order
+pub const FIELD_TRANSFORM: u8 = 0x01; // x, y, rotation, scale
+pub const FIELD_SHAPE: u8 = 0x02;
+pub const FIELD_COLOR: u8 = 0x04;
+pub const FIELD_Z: u8 = 0x08;
+pub const FIELD_VISIBLE: u8 = 0x10;
+pub const FIELD_PARENT: u8 = 0x20;
+pub const FIELD_SNAP: u8 = 0x40; // No payload: jump to the new transform instead of interpolating
+pub const FIELDS_ALL: u8 = 0x3F;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Shape {
+    None,
+    Rect { w: f32, h: f32 },
+    Circle { r: f32 },
+    Sprite { image: String, w: f32, h: f32 },
+    Text { text: String },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NodeState {
+    pub x: f32,
+    pub y: f32,
+    pub rotation: f32,
+    pub scale: f32,
+    pub shape: Shape, // Centered on the node's origin
+    pub color: [u8; 4],
+    pub z: i8,        // Draw order among nodes (ties: creation order)
+    pub visible: bool,
+    pub parent: u32,  // 0 = none; transforms are relative to the parent
+    pub snap: u32,    // Bumped by teleports
+}
+
+impl Default for NodeState {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            rotation: 0.0,
+            scale: 1.0,
+            shape: Shape::None,
+            color: [255, 255, 255, 255],
+            z: 0,
+            visible: true,
+            parent: 0,
+            snap: 0,
+        }
+    }
+}
+
+impl NodeState {
+    /// Fields that differ from `sent`.
+    fn diff(&self, sent: &NodeState) -> u8 {
+        let mut mask = 0;
+        if (self.x, self.y, self.rotation, self.scale) != (sent.x, sent.y, sent.rotation, sent.scale) {
+            mask |= FIELD_TRANSFORM;
+        }
+        if self.shape != sent.shape { mask |= FIELD_SHAPE; }
+        if self.color != sent.color { mask |= FIELD_COLOR; }
+        if self.z != sent.z { mask |= FIELD_Z; }
+        if self.visible != sent.visible { mask |= FIELD_VISIBLE; }
+        if self.parent != sent.parent { mask |= FIELD_PARENT; }
+        if self.snap != sent.snap { mask |= FIELD_SNAP | FIELD_TRANSFORM; }
+        mask
+    }
+}
+
+pub enum SceneOp {
+    Create(u32, NodeState),
+    Update(u32, u8, NodeState),
+    Destroy(u32),
+    Sync(Vec<u32>),
+    Moves(Vec<(u32, f32, f32, f32)>), // id, x, y, rotation (scale unchanged)
+}
+
+#[derive(Default)]
+struct SessionScene {
+    sent: HashMap<u32, NodeState>,
+    frames: u32,
+    refresh_cursor: u32,
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: BTreeMap<u32, NodeState>,
+    next_id: u32,
+    sessions: HashMap<String, SessionScene>,
+    names: HashMap<String, u32>, // Script ids given to api.scene_add
+}
+
+impl Scene {
+    pub fn create(&mut self, state: NodeState) -> u32 {
+        self.next_id += 1;
+        self.nodes.insert(self.next_id, state);
+        self.next_id
+    }
+
+    pub fn get(&self, id: u32) -> Option<&NodeState> {
+        self.nodes.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut NodeState> {
+        self.nodes.get_mut(&id)
+    }
+
+    /// Removes the node and its descendants.
+    pub fn destroy(&mut self, id: u32) {
+        let mut doomed = vec![id];
+        while let Some(id) = doomed.pop() {
+            if self.nodes.remove(&id).is_some() {
+                doomed.extend(self.nodes.iter().filter(|(_, n)| n.parent == id).map(|(&child, _)| child));
+            }
+        }
+        self.names.retain(|_, id| self.nodes.contains_key(id));
+    }
+
+    pub fn set_name(&mut self, name: String, id: u32) {
+        self.names.insert(name, id);
+    }
+
+    /// The live node registered under `name`.
+    pub fn named(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    /// What `session_id` needs to match the scene, recorded as sent. `remap` adjusts colors
+    /// for the session (accessibility palettes); `batch_moves` when the client understands
+    /// SceneOp::Moves.
+    pub fn sync(&mut self, session_id: &str, remap: impl Fn([u8; 4]) -> [u8; 4], batch_moves: bool) -> Vec<SceneOp> {
+        let session = self.sessions.entry(session_id.to_string()).or_default();
+        let mut ops = Vec::new();
+
+        // Also on the first frame: clears nodes the client kept from before a hot reload
+        if session.frames % RESYNC_FRAMES == 0 {
+            ops.push(SceneOp::Sync(self.nodes.keys().copied().collect()));
+        }
+        session.frames = session.frames.wrapping_add(1);
+
+        let mut gone: Vec<u32> = session.sent.keys().filter(|id| !self.nodes.contains_key(id)).copied().collect();
+        gone.sort_unstable();
+        for id in gone {
+            session.sent.remove(&id);
+            ops.push(SceneOp::Destroy(id));
+        }
+
+        let mut touched = Vec::new();
+        let mut moves = Vec::new();
+        for (&id, node) in &self.nodes {
+            let mut state = node.clone();
+            state.color = remap(state.color);
+            match session.sent.get(&id) {
+                None => {
+                    ops.push(SceneOp::Create(id, state.clone()));
+                    touched.push(id);
+                }
+                Some(sent) => {
+                    let mask = state.diff(sent);
+                    if mask == 0 {
+                        continue;
+                    }
+                    if batch_moves && mask == FIELD_TRANSFORM && state.scale == sent.scale {
+                        moves.push((id, state.x, state.y, state.rotation));
+                    } else {
+                        ops.push(SceneOp::Update(id, mask, state.clone()));
+                    }
+                    touched.push(id);
+                }
+            }
+            session.sent.insert(id, state);
+        }
+        if !moves.is_empty() {
+            ops.push(SceneOp::Moves(moves));
+        }
+
+        // Round-robin full refresh of one node the frame doesn't already carry
+        let next = self.nodes.range(session.refresh_cursor + 1..).next()
+            .or_else(|| self.nodes.iter().next())
+            .map(|(&id, _)| id);
+        if let Some(id) = next {
+            session.refresh_cursor = id;
+            if !touched.contains(&id) {
+                ops.push(SceneOp::Create(id, session.sent[&id].clone()));
+            }
+        }
+        ops
+    }
+
+    /// Drops what `session_id` is known to have: the whole scene is sent again.
+    pub fn forget(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
@@ -0,0 +1,99 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+// --- Settings Menu ---
+// api.register_setting(key, opts) declares an entry of the settings menu the client draws
+// itself (volume sliders, control toggles...). The menu is sent to each client when it
+// connects; a player's changes come back as SETTING messages, are checked against the
+// declaration and reach the script as on_setting_changed(session_id, key, value).
+// The client keeps the player's values (localStorage) and sends them again whenever it
+// receives the menu, so they survive reconnects, new visits and hot reloads.
+
+/// Entries a menu can hold.
+pub const MAX_SETTINGS: usize = 64;
+/// Options of a "choice" setting.
+pub const MAX_CHOICES: usize = 32;
+pub const MAX_KEY_LEN: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingKind {
+    Slider { min: f64, max: f64, step: f64 }, // step 0 = continuous
+    Toggle,
+    Choice(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: String,
+    pub label: String,
+    pub kind: SettingKind,
+    pub default: Value,
+}
+
+impl Setting {
+    /// `value` as the setting holds it (sliders snapped to the step and clamped), None if
+    /// it doesn't fit the setting.
+    pub fn accept(&self, value: &Value) -> Option<Value> {
+        match &self.kind {
+            SettingKind::Slider { min, max, step } => {
+                let v = value.as_f64().filter(|v| v.is_finite())?;
+                let v = if *step > 0.0 { min + ((v - min) / step).round() * step } else { v };
+                Some(Value::from(v.clamp(*min, *max)))
+            }
+            SettingKind::Toggle => value.as_bool().map(Value::Bool),
+            SettingKind::Choice(options) => {
+                let v = value.as_str()?;
+                options.iter().any(|o| o == v).then(|| Value::from(v))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Settings {
+    // In registration order, the order of the menu
+    settings: Vec<Setting>,
+    // Values players changed from the default
+    values: HashMap<String, HashMap<String, Value>>,
+}
+
+impl Settings {
+    /// Adds a setting, or replaces the one with the same key in place.
+    pub fn register(&mut self, setting: Setting) -> Result<(), String> {
+        match self.settings.iter().position(|s| s.key == setting.key) {
+            Some(i) => self.settings[i] = setting,
+            None if self.settings.len() >= MAX_SETTINGS => return Err(format!("At most {} settings", MAX_SETTINGS)),
+            None => self.settings.push(setting),
+        }
+        Ok(())
+    }
+
+    pub fn all(&self) -> &[Setting] {
+        &self.settings
+    }
+
+    /// The session's value for `key` (the default until the player changes it); None for
+    /// unknown keys.
+    pub fn get(&self, session_id: &str, key: &str) -> Option<Value> {
+        let setting = self.settings.iter().find(|s| s.key == key)?;
+        let value = self.values.get(session_id).and_then(|v| v.get(key));
+        Some(value.unwrap_or(&setting.default).clone())
+    }
+
+    /// Records a player's change. Returns the stored value, or None when nothing changed:
+    /// unknown key (e.g. removed by a reload), a value that doesn't fit, or the same value.
+    pub fn change(&mut self, session_id: &str, key: &str, value: &Value) -> Option<Value> {
+        let setting = self.settings.iter().find(|s| s.key == key)?;
+        let value = setting.accept(value)?;
+        let values = self.values.entry(session_id.to_string()).or_default();
+        if values.get(key).unwrap_or(&setting.default) == &value {
+            return None;
+        }
+        values.insert(key.to_string(), value.clone());
+        Some(value)
+    }
+
+    pub fn forget_session(&mut self, session_id: &str) {
+        self.values.remove(session_id);
+    }
+}
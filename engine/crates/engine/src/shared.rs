@@ -0,0 +1,144 @@
+use mlua::{Lua, LuaSerdeExt, MetaMethod, SerializeOptions, UserData, UserDataMethods};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// --- Shared Game Data ---
+// Read-only data (levels, tile layouts, nav graphs) loaded once per process from the
+// game's data/*.json files and shared by every GameState created with it, across hot
+// reloads. Scripts read it through views (api.shared_get): nested objects and arrays are
+// views too, and only the values actually read are converted to Lua.
+
+#[derive(Clone, Default)]
+pub struct SharedData {
+    values: Arc<HashMap<String, Arc<Value>>>,
+}
+
+impl SharedData {
+    pub fn new(values: HashMap<String, Value>) -> Self {
+        Self { values: Arc::new(values.into_iter().map(|(k, v)| (k, Arc::new(v))).collect()) }
+    }
+
+    /// Every `<dir>/<name>.json`, by name. A missing directory is empty data.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let mut values = HashMap::new();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            let value = serde_json::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+            values.insert(name.to_string(), value);
+        }
+        Ok(Self::new(values))
+    }
+
+    pub fn view(&self, name: &str) -> Option<SharedView> {
+        self.values.get(name).map(|root| SharedView { root: root.clone(), path: Vec::new() })
+    }
+}
+
+#[derive(Clone)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Clone)]
+pub struct SharedView {
+    root: Arc<Value>,
+    path: Vec<Step>,
+}
+
+impl SharedView {
+    fn node(&self) -> &Value {
+        let mut node = &*self.root;
+        for step in &self.path {
+            node = child(node, step).unwrap_or(&Value::Null);
+        }
+        node
+    }
+
+    // Scalars are converted, objects and arrays stay views
+    fn get<'lua>(&self, lua: &'lua Lua, step: Step) -> mlua::Result<mlua::Value<'lua>> {
+        Ok(match child(self.node(), &step) {
+            None | Some(Value::Null) => mlua::Value::Nil,
+            Some(Value::Bool(b)) => mlua::Value::Boolean(*b),
+            Some(Value::Number(n)) => match n.as_i64() {
+                Some(i) => mlua::Value::Integer(i as mlua::Integer),
+                None => mlua::Value::Number(n.as_f64().unwrap_or(0.0)),
+            },
+            Some(Value::String(s)) => mlua::Value::String(lua.create_string(s)?),
+            Some(_) => {
+                let mut path = self.path.clone();
+                path.push(step);
+                mlua::Value::UserData(lua.create_userdata(SharedView { root: self.root.clone(), path })?)
+            }
+        })
+    }
+}
+
+fn child<'a>(node: &'a Value, step: &Step) -> Option<&'a Value> {
+    match (node, step) {
+        (Value::Object(map), Step::Key(key)) => map.get(key),
+        (Value::Array(items), Step::Index(i)) => items.get(*i),
+        _ => None,
+    }
+}
+
+impl UserData for SharedView {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        // Object keys, or array indices (from 1)
+        methods.add_method("keys", |lua, this, ()| {
+            let keys = lua.create_table()?;
+            match this.node() {
+                Value::Object(map) => {
+                    for (i, key) in map.keys().enumerate() {
+                        keys.raw_set(i + 1, key.as_str())?;
+                    }
+                }
+                Value::Array(items) => {
+                    for i in 1..=items.len() {
+                        keys.raw_set(i, i)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok(keys)
+        });
+
+        // A plain (mutable) table copy
+        methods.add_method("copy", |lua, this, ()| {
+            lua.to_value_with(this.node(), SerializeOptions::new().serialize_none_to_null(false).serialize_unit_to_null(false))
+        });
+
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: mlua::Value| {
+            let step = match key {
+                mlua::Value::Integer(i) if i >= 1 => Step::Index(i as usize - 1),
+                mlua::Value::Number(n) if n >= 1.0 && n.fract() == 0.0 => Step::Index(n as usize - 1),
+                mlua::Value::String(s) => Step::Key(s.to_str()?.to_string()),
+                _ => return Ok(mlua::Value::Nil),
+            };
+            this.get(lua, step)
+        });
+
+        methods.add_meta_method(MetaMethod::NewIndex, |_, _, (_, _): (mlua::Value, mlua::Value)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError("Shared data is read-only (use :copy() for a mutable table)".into()))
+        });
+
+        methods.add_meta_method(MetaMethod::Len, |_, this, ()| {
+            Ok(match this.node() {
+                Value::Array(items) => items.len(),
+                _ => 0,
+            })
+        });
+    }
+}
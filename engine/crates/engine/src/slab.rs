@@ -0,0 +1,143 @@
+// --- Generational Slab ---
+// Dense storage for entities/bodies addressed by u64 handles (the ids Lua sees).
+//
+// Handle layout: [generation: 20 bits][index + 1: 32 bits]
+// - Generation 0 yields the classic 1, 2, 3... ids.
+// - Stays below 2^53, so handles survive backends where Lua numbers are doubles (LuaJIT).
+// - A removed handle never aliases the entity that later reuses its slot.
+//
+// Values live contiguously in `dense` (iteration = linear scan, deterministic order);
+// `slots` maps the handle index to the dense position.
+
+const INDEX_BITS: u32 = 32;
+const GENERATION_MASK: u32 = (1 << 20) - 1;
+
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    generation: u32,
+    dense: Option<u32>,
+}
+
+pub struct Slab<T> {
+    dense: Vec<(u64, T)>,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+fn make_handle(index: u32, generation: u32) -> u64 {
+    ((generation as u64) << INDEX_BITS) | (index as u64 + 1)
+}
+
+fn split_handle(handle: u64) -> Option<(u32, u32)> {
+    let low = (handle & ((1u64 << INDEX_BITS) - 1)) as u32;
+    if low == 0 {
+        return None; // 0 is never a valid handle
+    }
+    let generation = (handle >> INDEX_BITS) as u32;
+    if generation > GENERATION_MASK {
+        return None;
+    }
+    Some((low - 1, generation))
+}
+
+impl<T> Default for Slab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slab<T> {
+    pub fn new() -> Self {
+        Self {
+            dense: Vec::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Allocates a new handle and stores `value` under it.
+    pub fn insert(&mut self, value: T) -> u64 {
+        let index = match self.free.pop() {
+            Some(i) => i,
+            None => {
+                self.slots.push(Slot { generation: 0, dense: None });
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        let handle = make_handle(index, slot.generation);
+        slot.dense = Some(self.dense.len() as u32);
+        self.dense.push((handle, value));
+        handle
+    }
+
+    /// Stores `value` under a handle allocated by another slab (component storage,
+    /// e.g. physics bodies keyed by SpatialDb ids). Replaces any previous value in that slot.
+    pub fn insert_at(&mut self, handle: u64, value: T) {
+        let Some((index, generation)) = split_handle(handle) else { return };
+        if self.slots.len() <= index as usize {
+            self.slots.resize(index as usize + 1, Slot { generation: 0, dense: None });
+        }
+        let slot = &mut self.slots[index as usize];
+        slot.generation = generation;
+        match slot.dense {
+            Some(d) => self.dense[d as usize] = (handle, value),
+            None => {
+                slot.dense = Some(self.dense.len() as u32);
+                self.dense.push((handle, value));
+            }
+        }
+    }
+
+    fn dense_index(&self, handle: u64) -> Option<usize> {
+        let (index, generation) = split_handle(handle)?;
+        let slot = self.slots.get(index as usize)?;
+        if slot.generation != generation {
+            return None;
+        }
+        slot.dense.map(|d| d as usize)
+    }
+
+    pub fn contains(&self, handle: u64) -> bool {
+        self.dense_index(handle).is_some()
+    }
+
+    pub fn get(&self, handle: u64) -> Option<&T> {
+        self.dense_index(handle).map(|d| &self.dense[d].1)
+    }
+
+    pub fn get_mut(&mut self, handle: u64) -> Option<&mut T> {
+        self.dense_index(handle).map(move |d| &mut self.dense[d].1)
+    }
+
+    pub fn remove(&mut self, handle: u64) -> Option<T> {
+        let d = self.dense_index(handle)?;
+        let (index, _) = split_handle(handle)?;
+
+        let (_, value) = self.dense.swap_remove(d);
+        // Patch the slot of the element that was moved into the hole
+        if let Some(&(moved, _)) = self.dense.get(d) {
+            if let Some((moved_index, _)) = split_handle(moved) {
+                self.slots[moved_index as usize].dense = Some(d as u32);
+            }
+        }
+
+        let slot = &mut self.slots[index as usize];
+        slot.dense = None;
+        slot.generation = (slot.generation + 1) & GENERATION_MASK;
+        self.free.push(index);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &T)> {
+        self.dense.iter().map(|(h, v)| (*h, v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u64, &mut T)> {
+        self.dense.iter_mut().map(|(h, v)| (*h, v))
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = u64> + '_ {
+        self.dense.iter().map(|(h, _)| *h)
+    }
+}
@@ -1,6 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::collections::hash_map::DefaultHasher;
+use crate::geom;
+use crate::slab::Slab;
 
 // --- Tipos Geométricos ---
 
@@ -30,19 +32,67 @@ pub enum EntityKind {
 // --- Spatial DB ---
 
 pub struct SpatialDb {
-    next_id: u64,
     cell_size: f32,
-    entities: HashMap<u64, Entity>,
+    entities: Slab<Entity>, // Handle (id exposto ao Lua) -> Entity, armazenamento denso
     grid: HashMap<(i32, i32), Vec<u64>>, // Cell Coordinate -> List of Entity IDs
+    wrap: Option<(f32, f32)>, // Mundo toroidal: (largura, altura), posições em [0, w) x [0, h)
+    tags: HashMap<u64, String>, // Registro de tags: hash -> string (lookup reverso)
 }
 
 impl SpatialDb {
     pub fn new(cell_size: f32) -> Self {
         Self {
-            next_id: 1,
             cell_size,
-            entities: HashMap::new(),
+            entities: Slab::new(),
             grid: HashMap::new(),
+            wrap: None,
+            tags: HashMap::new(),
+        }
+    }
+
+    // Mundo "Asteroids": sair por uma borda volta pela oposta.
+    // Consultas, raycasts e a física enxergam vizinhos através da emenda.
+    pub fn new_toroidal(cell_size: f32, width: f32, height: f32) -> Self {
+        let mut db = Self::new(cell_size);
+        if width > 0.0 && height > 0.0 {
+            db.wrap = Some((width, height));
+        }
+        db
+    }
+
+    // --- Helpers Toroidais ---
+
+    fn wrap_point(&self, x: f32, y: f32) -> (f32, f32) {
+        match self.wrap {
+            Some((w, h)) => (x.rem_euclid(w), y.rem_euclid(h)),
+            None => (x, y),
+        }
+    }
+
+    /// Menor deslocamento equivalente (atravessando a emenda se for mais curto).
+    pub fn wrap_delta(&self, dx: f32, dy: f32) -> (f32, f32) {
+        match self.wrap {
+            Some((w, h)) => (
+                dx - w * (dx / w).round(),
+                dy - h * (dy / h).round(),
+            ),
+            None => (dx, dy),
+        }
+    }
+
+    // Deslocamentos das cópias da consulta: só a original, ou as 9 cópias vizinhas no modo toroidal
+    fn images(&self) -> Vec<(f32, f32)> {
+        match self.wrap {
+            Some((w, h)) => {
+                let mut out = Vec::with_capacity(9);
+                for ox in [-w, 0.0, w] {
+                    for oy in [-h, 0.0, h] {
+                        out.push((ox, oy));
+                    }
+                }
+                out
+            },
+            None => vec![(0.0, 0.0)],
         }
     }
 
@@ -97,7 +147,7 @@ impl SpatialDb {
     }
 
     fn add_to_grid(&mut self, id: u64) {
-        if let Some(e) = self.entities.get(&id) {
+        if let Some(e) = self.entities.get(id) {
             let cells = self.get_cells_for_entity(e);
             for cell in cells {
                 self.grid.entry(cell).or_insert_with(Vec::new).push(id);
@@ -106,7 +156,7 @@ impl SpatialDb {
     }
 
     fn remove_from_grid(&mut self, id: u64) {
-        if let Some(e) = self.entities.get(&id) {
+        if let Some(e) = self.entities.get(id) {
             let cells = self.get_cells_for_entity(e);
             for cell in cells {
                 if let Some(list) = self.grid.get_mut(&cell) {
@@ -121,49 +171,66 @@ impl SpatialDb {
     // --- Public API ---
 
     pub fn add_circle(&mut self, x: f32, y: f32, radius: f32, tag: &str) -> u64 {
-        let id = self.next_id;
-        self.next_id += 1;
-        let e = Entity {
-            id,
-            x,
-            y,
-            kind: EntityKind::Circle { radius },
-            tag_hash: Self::calculate_hash(tag),
-        };
-        self.entities.insert(id, e);
-        self.add_to_grid(id);
-        id
+        self.insert(x, y, EntityKind::Circle { radius }, tag)
     }
 
     pub fn add_segment(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, tag: &str) -> u64 {
-        let id = self.next_id;
-        self.next_id += 1;
-        let e = Entity {
-            id,
-            x: x1,
-            y: y1,
-            kind: EntityKind::Segment { x2, y2 },
-            tag_hash: Self::calculate_hash(tag),
+        self.insert(x1, y1, EntityKind::Segment { x2, y2 }, tag)
+    }
+
+    // Interna a string da tag; entidades guardam só o hash
+    fn intern_tag(&mut self, tag: &str) -> u64 {
+        let hash = Self::calculate_hash(tag);
+        self.tags.entry(hash).or_insert_with(|| tag.to_string());
+        hash
+    }
+
+    fn insert(&mut self, x: f32, y: f32, kind: EntityKind, tag: &str) -> u64 {
+        let (wx, wy) = self.wrap_point(x, y);
+        let kind = match kind {
+            EntityKind::Segment { x2, y2 } => EntityKind::Segment { x2: x2 + (wx - x), y2: y2 + (wy - y) },
+            k => k,
         };
-        self.entities.insert(id, e);
+        let (x, y) = (wx, wy);
+        let tag_hash = self.intern_tag(tag);
+        let id = self.entities.insert(Entity {
+            id: 0,
+            x,
+            y,
+            kind,
+            tag_hash,
+        });
+        if let Some(e) = self.entities.get_mut(id) {
+            e.id = id;
+        }
         self.add_to_grid(id);
         id
     }
 
+    pub fn contains(&self, id: u64) -> bool {
+        self.entities.contains(id)
+    }
+
     pub fn get_position(&self, id: u64) -> Option<(f32, f32)> {
-        self.entities.get(&id).map(|e| (e.x, e.y))
+        self.entities.get(id).map(|e| (e.x, e.y))
+    }
+
+    pub fn get_tag(&self, id: u64) -> Option<&str> {
+        let e = self.entities.get(id)?;
+        self.tags.get(&e.tag_hash).map(|t| t.as_str())
     }
 
     pub fn get_entity_info(&self, id: u64) -> Option<(f32, f32, EntityKind)> {
-        self.entities.get(&id).map(|e| (e.x, e.y, e.kind.clone()))
+        self.entities.get(id).map(|e| (e.x, e.y, e.kind.clone()))
     }
 
     pub fn update_position(&mut self, id: u64, x: f32, y: f32) {
         // Remove old position from grid, update, add new
         // Optimization: Check if cell changed? For now, brute force safety.
-        if self.entities.contains_key(&id) {
+        let (x, y) = self.wrap_point(x, y);
+        if self.entities.contains(id) {
             self.remove_from_grid(id);
-            if let Some(e) = self.entities.get_mut(&id) {
+            if let Some(e) = self.entities.get_mut(id) {
                 // Only valid for moving entities (usually Circles in this game context)
                 // If moving a segment, we'd need to update x2/y2 too (offset). 
                 // Assuming simple translation for circles mostly.
@@ -183,16 +250,85 @@ impl SpatialDb {
     }
 
     pub fn remove(&mut self, id: u64) {
-        if self.entities.contains_key(&id) {
+        if self.entities.contains(id) {
             self.remove_from_grid(id);
-            self.entities.remove(&id);
+            self.entities.remove(id);
         }
     }
 
     // --- Queries ---
 
+    // Entidades em várias células aparecem repetidas; ordenar por id também deixa o resultado determinístico
+    fn dedup_ids(mut ids: Vec<u64>) -> Vec<u64> {
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
     pub fn query_rect(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32, tag_filter: Option<&str>) -> Vec<u64> {
-        let mut result = HashSet::new();
+        let mut result = Vec::new();
+        for (ox, oy) in self.images() {
+            result.extend(self.query_rect_plain(min_x + ox, min_y + oy, max_x + ox, max_y + oy, tag_filter));
+        }
+        Self::dedup_ids(result)
+    }
+
+    pub fn query_range(&self, x: f32, y: f32, range: f32, tag_filter: Option<&str>) -> Vec<u64> {
+        let mut result = Vec::new();
+        for (ox, oy) in self.images() {
+            result.extend(self.query_range_plain(x + ox, y + oy, range, tag_filter));
+        }
+        Self::dedup_ids(result)
+    }
+
+    // Como query_range, mas retorna (id, x, y, dist) ordenado pela distância (mais perto primeiro),
+    // opcionalmente limitado a `max_results`. dist = distância até o centro (círculo) ou ponto mais próximo (segmento).
+    pub fn query_range_ex(&self, x: f32, y: f32, range: f32, tag_filter: Option<&str>, max_results: Option<usize>) -> Vec<(u64, f32, f32, f32)> {
+        let mut result: Vec<(u64, f32, f32, f32)> = self.query_range(x, y, range, tag_filter)
+            .into_iter()
+            .filter_map(|id| {
+                let e = self.entities.get(id)?;
+                let (dx, dy) = match e.kind {
+                    EntityKind::Circle { .. } => self.wrap_delta(e.x - x, e.y - y),
+                    EntityKind::Segment { x2, y2 } => {
+                        let (sx, sy) = self.wrap_delta(e.x - x, e.y - y);
+                        let (ex, ey) = (sx + (x2 - e.x), sy + (y2 - e.y));
+                        let len2 = (ex - sx).powi(2) + (ey - sy).powi(2);
+                        let t = if len2 > 0.0 { (-(sx * (ex - sx) + sy * (ey - sy)) / len2).clamp(0.0, 1.0) } else { 0.0 };
+                        (sx + t * (ex - sx), sy + t * (ey - sy))
+                    }
+                };
+                Some((id, e.x, e.y, (dx * dx + dy * dy).sqrt()))
+            })
+            .collect();
+
+        result.sort_by(|a, b| a.3.total_cmp(&b.3).then(a.0.cmp(&b.0)));
+        if let Some(max) = max_results {
+            result.truncate(max);
+        }
+        result
+    }
+
+    // Retorna (id, dist_fraction, x, y). No modo toroidal o raio atravessa a emenda.
+    pub fn cast_ray(&self, x1: f32, y1: f32, angle_deg: f32, max_dist: f32, tag_filter: Option<&str>) -> Option<(u64, f32, f32, f32)> {
+        let mut closest: Option<(u64, f32, f32, f32)> = None;
+        for (ox, oy) in self.images() {
+            if let Some(hit) = self.cast_ray_plain(x1 + ox, y1 + oy, angle_deg, max_dist, tag_filter) {
+                if closest.map_or(true, |(_, cd, _, _)| hit.1 < cd) {
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest.map(|(id, frac, hx, hy)| {
+            let (hx, hy) = self.wrap_point(hx, hy);
+            (id, frac, hx, hy)
+        })
+    }
+
+    // --- Consultas em uma única cópia do mundo ---
+
+    fn query_rect_plain(&self, min_x: f32, min_y: f32, max_x: f32, max_y: f32, tag_filter: Option<&str>) -> Vec<u64> {
+        let mut result = Vec::new();
         let target_hash = tag_filter.map(Self::calculate_hash);
 
         let min_c = self.get_cell(min_x, min_y);
@@ -202,9 +338,7 @@ impl SpatialDb {
             for cy in min_c.1..=max_c.1 {
                 if let Some(list) = self.grid.get(&(cx, cy)) {
                     for &id in list {
-                        if result.contains(&id) { continue; }
-                        
-                        if let Some(e) = self.entities.get(&id) {
+                        if let Some(e) = self.entities.get(id) {
                             if let Some(th) = target_hash {
                                 if e.tag_hash != th { continue; }
                             }
@@ -216,18 +350,18 @@ impl SpatialDb {
                             };
 
                             if e_max_x >= min_x && e_min_x <= max_x && e_max_y >= min_y && e_min_y <= max_y {
-                                result.insert(id);
+                                result.push(id);
                             }
                         }
                     }
                 }
             }
         }
-        result.into_iter().collect()
+        Self::dedup_ids(result)
     }
 
-    pub fn query_range(&self, x: f32, y: f32, range: f32, tag_filter: Option<&str>) -> Vec<u64> {
-        let mut result = HashSet::new();
+    fn query_range_plain(&self, x: f32, y: f32, range: f32, tag_filter: Option<&str>) -> Vec<u64> {
+        let mut result = Vec::new();
         let target_hash = tag_filter.map(Self::calculate_hash);
 
         // AABB check first
@@ -238,9 +372,7 @@ impl SpatialDb {
             for cy in min_c.1..=max_c.1 {
                 if let Some(list) = self.grid.get(&(cx, cy)) {
                     for &id in list {
-                        if result.contains(&id) { continue; }
-                        
-                        if let Some(e) = self.entities.get(&id) {
+                        if let Some(e) = self.entities.get(id) {
                             // Filter Tag
                             if let Some(th) = target_hash {
                                 if e.tag_hash != th { continue; }
@@ -258,18 +390,14 @@ impl SpatialDb {
                                 },
                                 EntityKind::Segment { x2, y2 } => {
                                     // Point to Segment distance
-                                    let seg_len2 = (x2-e.x).powi(2) + (y2-e.y).powi(2);
-                                    let mut t = ((x - e.x) * (x2 - e.x) + (y - e.y) * (y2 - e.y)) / seg_len2;
-                                    t = t.max(0.0).min(1.0);
-                                    let closest_x = e.x + t * (x2 - e.x);
-                                    let closest_y = e.y + t * (y2 - e.y);
+                                    let ((closest_x, closest_y), _) = geom::closest_point_on_segment((x, y), (e.x, e.y), (x2, y2));
                                     let dist2 = (x - closest_x).powi(2) + (y - closest_y).powi(2);
                                     if dist2 <= range * range { Some(dist2) } else { None }
                                 }
                             };
 
                             if dist_sq.is_some() {
-                                result.insert(id);
+                                result.push(id);
                             }
                         }
                     }
@@ -277,12 +405,12 @@ impl SpatialDb {
             }
         }
 
-        result.into_iter().collect()
+        Self::dedup_ids(result)
     }
 
     // Raycast simples (Naive traversal, optimizing via Grid cells is harder but doable)
     // Retorna (id, dist_fraction, x, y)
-    pub fn cast_ray(&self, x1: f32, y1: f32, angle_deg: f32, max_dist: f32, tag_filter: Option<&str>) -> Option<(u64, f32, f32, f32)> {
+    fn cast_ray_plain(&self, x1: f32, y1: f32, angle_deg: f32, max_dist: f32, tag_filter: Option<&str>) -> Option<(u64, f32, f32, f32)> {
         let rad = angle_deg.to_radians();
         let dx = rad.cos();
         let dy = rad.sin();
@@ -293,7 +421,7 @@ impl SpatialDb {
 
         // Ray Traversal (DDA-like or simple stepping)
         // Para simplificar e garantir robustez, vamos coletar candidatos via células atravessadas.
-        let mut candidates = HashSet::new();
+        let mut candidates = Vec::new();
         
         // Passo de amostragem na grid (grosseiro mas funcional para 2D top down)
         let steps = (max_dist / self.cell_size).ceil() as i32;
@@ -309,9 +437,7 @@ impl SpatialDb {
              for ox in -1..=1 {
                  for oy in -1..=1 {
                      if let Some(list) = self.grid.get(&(cell.0+ox, cell.1+oy)) {
-                         for &id in list {
-                             candidates.insert(id);
-                         }
+                         candidates.extend_from_slice(list);
                      }
                  }
              }
@@ -319,8 +445,8 @@ impl SpatialDb {
 
         let mut closest: Option<(u64, f32, f32, f32)> = None;
 
-        for id in candidates {
-            if let Some(e) = self.entities.get(&id) {
+        for id in Self::dedup_ids(candidates) {
+            if let Some(e) = self.entities.get(id) {
                  if let Some(th) = target_hash {
                     if e.tag_hash != th { continue; }
                 }
@@ -347,16 +473,10 @@ impl SpatialDb {
                     EntityKind::Segment { x2: wx2, y2: wy2 } => {
                         // Ray vs Segment
                         // Line-Line Intersection
-                        let den = (x1 - x2) * (e.y - wy2) - (y1 - y2) * (e.x - wx2);
-                        if den != 0.0 {
-                            let t = ((x1 - e.x) * (e.y - wy2) - (y1 - e.y) * (e.x - wx2)) / den;
-                            let u = -((x1 - x2) * (y1 - e.y) - (y1 - y2) * (x1 - e.x)) / den;
-                            
-                            if t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0 {
-                                // t é fração do raio
-                                if closest.map_or(true, |(_, cd, _, _)| t < cd) {
-                                     closest = Some((id, t, x1 + t*(x2-x1), y1 + t*(y2-y1)));
-                                }
+                        if let Some((t, _)) = geom::segment_intersection((x1, y1), (x2, y2), (e.x, e.y), (wx2, wy2)) {
+                            // t é fração do raio
+                            if closest.map_or(true, |(_, cd, _, _)| t < cd) {
+                                 closest = Some((id, t, x1 + t*(x2-x1), y1 + t*(y2-y1)));
                             }
                         }
                     }
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// --- Sprite Sheets ---
+// A sheet is an image cut into equal frames, numbered row by row from 0. Animations are
+// frame lists played at a fixed rate: the engine turns the time an animation has been
+// playing into a frame, and clients only receive the sheet id and frame index
+// (OP_DRAW_FRAME).
+
+pub struct Animation {
+    pub frames: Vec<u16>,
+    pub fps: f32,
+    pub looping: bool,
+}
+
+impl Animation {
+    /// The frame shown `t` seconds after the animation started. One-shot animations hold
+    /// their last frame.
+    pub fn frame_at(&self, t: f32) -> u16 {
+        let step = (t.max(0.0) * self.fps) as usize; // Saturates for huge t
+        let i = if self.looping { step % self.frames.len() } else { step.min(self.frames.len() - 1) };
+        self.frames[i]
+    }
+
+    /// Seconds for one play through.
+    pub fn duration(&self) -> f32 {
+        self.frames.len() as f32 / self.fps
+    }
+}
+
+pub struct Sheet {
+    pub id: u16,
+    pub frame_w: u16,
+    pub frame_h: u16,
+    pub animations: HashMap<String, Animation>,
+}
+
+/// Sheets by name. Loading a name again replaces the sheet but keeps its id.
+#[derive(Clone, Default)]
+pub struct Spritesheets {
+    by_name: Arc<Mutex<HashMap<String, Sheet>>>,
+}
+
+impl Spritesheets {
+    /// The sheet id, None when all ids are taken.
+    pub fn load(&self, name: &str, frame_w: u16, frame_h: u16, animations: HashMap<String, Animation>) -> Option<u16> {
+        let mut sheets = self.by_name.lock().unwrap();
+        let id = match sheets.get(name) {
+            Some(sheet) => sheet.id,
+            None => u16::try_from(sheets.len()).ok()?,
+        };
+        sheets.insert(name.to_string(), Sheet { id, frame_w, frame_h, animations });
+        Some(id)
+    }
+
+    pub fn with<T>(&self, name: &str, f: impl FnOnce(&Sheet) -> T) -> Option<T> {
+        self.by_name.lock().unwrap().get(name).map(f)
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+// --- Tilemaps ---
+// Tiles live natively and reach each client as chunks (OP_TILEMAP_CHUNK) that the client
+// caches, so drawing a map is a single OP_DRAW_TILEMAP per frame whatever its size. A
+// chunk is only resent to a client when it changed since that client last received it.
+// Frames can be lost in transit, so one unchanged chunk per frame is also resent,
+// round-robin, until every client has healed.
+
+pub const CHUNK_SIZE: usize = 16;
+pub const MAX_TILEMAP_SIZE: usize = 4096;
+
+// Unchanged chunks resent per session and frame
+const REFRESH_PER_FRAME: usize = 1;
+
+/// A rectangle of tiles, row-major. 0 is an empty tile.
+pub struct TileChunk {
+    pub x: u16,
+    pub y: u16,
+    pub w: u8,
+    pub h: u8,
+    pub tiles: Vec<u16>,
+}
+
+struct SessionSync {
+    versions: Vec<u32>, // Chunk versions the client has
+    refresh_cursor: usize,
+}
+
+pub struct Tilemap {
+    pub id: u16,
+    width: usize,
+    height: usize,
+    pub tile_size: f32,
+    pub tileset: Option<(String, u16)>, // Image name, source tile size in pixels
+    tiles: Vec<u16>,
+    versions: Vec<u32>, // Per chunk, bumped by every change
+    sessions: HashMap<String, SessionSync>,
+}
+
+impl Tilemap {
+    pub fn new(id: u16, width: usize, height: usize, tile_size: f32) -> Self {
+        let chunks = width.div_ceil(CHUNK_SIZE) * height.div_ceil(CHUNK_SIZE);
+        // Version 1: even an empty map is sent once, replacing whatever the client cached
+        // under this id before a hot reload
+        Self {
+            id,
+            width,
+            height,
+            tile_size,
+            tileset: None,
+            tiles: vec![0; width * height],
+            versions: vec![1; chunks],
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn chunk_index(&self, x: usize, y: usize) -> usize {
+        (y / CHUNK_SIZE) * self.width.div_ceil(CHUNK_SIZE) + x / CHUNK_SIZE
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<u16> {
+        (x < self.width && y < self.height).then(|| self.tiles[y * self.width + x])
+    }
+
+    /// Returns false when (x, y) is outside the map.
+    pub fn set(&mut self, x: usize, y: usize, tile: u16) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let i = y * self.width + x;
+        if self.tiles[i] != tile {
+            self.tiles[i] = tile;
+            let chunk = self.chunk_index(x, y);
+            self.versions[chunk] += 1;
+        }
+        true
+    }
+
+    /// Sets every tile of the rectangle, clipped to the map.
+    pub fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, tile: u16) {
+        for ty in y..(y + h).min(self.height) {
+            for tx in x..(x + w).min(self.width) {
+                self.set(tx, ty, tile);
+            }
+        }
+    }
+
+    /// Chunks to send to `session_id` with this frame, marked as received.
+    pub fn sync(&mut self, session_id: &str) -> Vec<TileChunk> {
+        let count = self.versions.len();
+        let sync = self.sessions.entry(session_id.to_string())
+            .or_insert_with(|| SessionSync { versions: vec![0; count], refresh_cursor: 0 });
+
+        let mut send = Vec::new();
+        for (i, (&version, seen)) in self.versions.iter().zip(sync.versions.iter_mut()).enumerate() {
+            if *seen != version {
+                *seen = version;
+                send.push(i);
+            }
+        }
+        for _ in 0..REFRESH_PER_FRAME.min(count) {
+            let i = sync.refresh_cursor;
+            sync.refresh_cursor = (i + 1) % count;
+            if !send.contains(&i) {
+                send.push(i);
+            }
+        }
+        send.into_iter().map(|i| self.chunk(i)).collect()
+    }
+
+    fn chunk(&self, index: usize) -> TileChunk {
+        let columns = self.width.div_ceil(CHUNK_SIZE);
+        let (x0, y0) = ((index % columns) * CHUNK_SIZE, (index / columns) * CHUNK_SIZE);
+        let (w, h) = (CHUNK_SIZE.min(self.width - x0), CHUNK_SIZE.min(self.height - y0));
+        let tiles = (y0..y0 + h)
+            .flat_map(|y| self.tiles[y * self.width + x0..y * self.width + x0 + w].iter().copied())
+            .collect();
+        TileChunk { x: x0 as u16, y: y0 as u16, w: w as u8, h: h as u8, tiles }
+    }
+
+    /// Drops what `session_id` is known to have: everything is sent again.
+    pub fn forget(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+    }
+}
+
+/// Every live tilemap, so sessions can be forgotten across all of them.
+#[derive(Clone, Default)]
+pub struct Tilemaps {
+    live: Arc<Mutex<Vec<Weak<Mutex<Tilemap>>>>>,
+    next_id: Arc<Mutex<u16>>,
+}
+
+impl Tilemaps {
+    pub fn create(&self, width: usize, height: usize, tile_size: f32) -> Arc<Mutex<Tilemap>> {
+        let id = {
+            let mut next = self.next_id.lock().unwrap();
+            *next = next.wrapping_add(1);
+            *next
+        };
+        let tilemap = Arc::new(Mutex::new(Tilemap::new(id, width, height, tile_size)));
+        let mut live = self.live.lock().unwrap();
+        live.retain(|weak| weak.strong_count() > 0); // Handles collected by Lua
+        live.push(Arc::downgrade(&tilemap));
+        tilemap
+    }
+
+    pub fn forget_session(&self, session_id: &str) {
+        for tilemap in self.live.lock().unwrap().iter().filter_map(Weak::upgrade) {
+            tilemap.lock().unwrap().forget(session_id);
+        }
+    }
+}
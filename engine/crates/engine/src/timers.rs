@@ -0,0 +1,91 @@
+use mlua::{Function, Lua, RegistryKey};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+// --- Timers ---
+// api.after / api.every callbacks, counted down in game time: each update(dt) advances
+// the clock by dt (before the Lua update) and runs the timers that came due, earliest
+// first, ties in the order they were made. A repeating timer runs at most once per
+// update; if it fell behind it skips the missed runs rather than bunching them up.
+// Timers made by a callback run from the next update on, even with a delay of 0.
+
+/// Shortest api.every interval, in seconds.
+pub const MIN_INTERVAL: f64 = 0.001;
+
+struct Timer {
+    due: f64,
+    interval: Option<f64>, // api.every
+    callback: RegistryKey,
+}
+
+#[derive(Default)]
+struct TimerState {
+    clock: f64,
+    next_id: u64,
+    timers: BTreeMap<u64, Timer>,
+}
+
+/// Pending timers keyed by handle (ids are never reused, so a stale handle cancels nothing).
+#[derive(Clone, Default)]
+pub struct Timers(Arc<Mutex<TimerState>>);
+
+impl Timers {
+    /// Schedules `callback` in `delay` seconds, then every `interval` seconds if given.
+    pub fn add(&self, delay: f64, interval: Option<f64>, callback: RegistryKey) -> u64 {
+        let mut state = self.0.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        let due = state.clock + delay;
+        state.timers.insert(id, Timer { due, interval, callback });
+        id
+    }
+
+    pub fn cancel(&self, lua: &Lua, id: u64) -> mlua::Result<bool> {
+        let timer = self.0.lock().unwrap().timers.remove(&id);
+        match timer {
+            Some(timer) => {
+                lua.remove_registry_value(timer.callback)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn step(&self, lua: &Lua, dt: f64) -> mlua::Result<()> {
+        let mut due: Vec<(f64, u64)> = {
+            let mut state = self.0.lock().unwrap();
+            state.clock += dt;
+            let clock = state.clock;
+            state.timers.iter()
+                .filter(|(_, timer)| timer.due <= clock)
+                .map(|(&id, timer)| (timer.due, id))
+                .collect()
+        };
+        due.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        // Callbacks run unlocked so they can add and cancel timers; one cancelled by an
+        // earlier callback this update doesn't run
+        for (_, id) in due {
+            let callback: Function = {
+                let mut state = self.0.lock().unwrap();
+                let clock = state.clock;
+                let Some(timer) = state.timers.get_mut(&id) else { continue };
+                match timer.interval {
+                    Some(interval) => {
+                        // The first run after the clock, skipping any missed in one step
+                        timer.due += ((clock - timer.due) / interval).floor() * interval + interval;
+                        lua.registry_value(&timer.callback)?
+                    }
+                    None => {
+                        let timer = state.timers.remove(&id).unwrap();
+                        let callback = lua.registry_value(&timer.callback)?;
+                        lua.remove_registry_value(timer.callback)?;
+                        callback
+                    }
+                }
+            };
+            callback.call::<_, ()>(id)?;
+        }
+        Ok(())
+    }
+}
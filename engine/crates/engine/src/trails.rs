@@ -0,0 +1,101 @@
+use crate::geom::Vec2;
+use crate::spatial_db::SpatialDb;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// --- Trails ---
+// api.trail_attach(db, id, opts) records the entity's position after every update(dt) and
+// api.draw_trails() sends every trail as one OP_DRAW_TRAILS batch: the client draws each as
+// a ribbon narrowing and fading towards its tail. Once detached (or the entity is removed)
+// a trail shrinks away by one point per tick.
+
+/// Longest trail, in points.
+pub const MAX_TRAIL_LENGTH: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrailConfig {
+    pub length: usize, // Points kept (one per tick)
+    pub width: f32,    // At the head
+    pub color: [u8; 4],
+}
+
+impl Default for TrailConfig {
+    fn default() -> Self {
+        Self { length: 20, width: 4.0, color: [255, 255, 255, 255] }
+    }
+}
+
+struct Trail {
+    db: Arc<Mutex<SpatialDb>>,
+    config: TrailConfig,
+    points: VecDeque<Vec2>, // Newest first
+    attached: bool,
+}
+
+/// A trail ready to encode.
+pub struct TrailSprite<'a> {
+    pub width: f32,
+    pub rgba: [u8; 4],
+    pub points: &'a VecDeque<Vec2>,
+}
+
+/// Trails keyed by entity id (BTreeMap for a deterministic draw order).
+#[derive(Default)]
+pub struct Trails {
+    trails: BTreeMap<u64, Trail>,
+}
+
+impl Trails {
+    /// Starts recording, replacing the entity's trail.
+    pub fn attach(&mut self, id: u64, db: Arc<Mutex<SpatialDb>>, config: TrailConfig) {
+        self.trails.insert(id, Trail { db, config, points: VecDeque::new(), attached: true });
+    }
+
+    /// Stops recording; the trail shrinks away. False if the entity had no trail.
+    pub fn detach(&mut self, id: u64) -> bool {
+        match self.trails.get_mut(&id) {
+            Some(trail) if trail.attached => {
+                trail.attached = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.trails.clear();
+    }
+
+    pub fn step(&mut self) {
+        self.trails.retain(|&id, trail| {
+            let position = if trail.attached { trail.db.lock().unwrap().get_position(id) } else { None };
+            match position {
+                Some(p) => {
+                    trail.points.push_front(p);
+                    trail.points.truncate(trail.config.length);
+                }
+                None => {
+                    trail.attached = false;
+                    trail.points.pop_back();
+                }
+            }
+            trail.attached || !trail.points.is_empty()
+        });
+    }
+
+    /// Trails of at least two points whose bounds (points, width) `visible` keeps.
+    pub fn frame(&self, visible: impl Fn(f32, f32, f32, f32) -> bool) -> Vec<TrailSprite<'_>> {
+        self.trails.values()
+            .filter(|t| t.points.len() >= 2)
+            .filter(|t| {
+                let pad = t.config.width / 2.0;
+                let (min_x, min_y, max_x, max_y) = t.points.iter().fold(
+                    (f32::MAX, f32::MAX, f32::MIN, f32::MIN),
+                    |(a, b, c, d), &(x, y)| (a.min(x), b.min(y), c.max(x), d.max(y)),
+                );
+                visible(min_x - pad, min_y - pad, max_x + pad, max_y + pad)
+            })
+            .map(|t| TrailSprite { width: t.config.width, rgba: t.config.color, points: &t.points })
+            .collect()
+    }
+}
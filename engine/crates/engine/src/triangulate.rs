@@ -0,0 +1,80 @@
+// --- Polygon Triangulation ---
+// Ear clipping for simple polygons (concave allowed, no holes), in either winding. Runs in
+// O(n²): fine for level outlines and UI shapes, which are triangulated once.
+
+/// Twice the signed area: positive for counter-clockwise (in y-up coordinates).
+fn signed_area(points: &[(f32, f32)]) -> f32 {
+    let n = points.len();
+    (0..n).map(|i| {
+        let (a, b) = (points[i], points[(i + 1) % n]);
+        a.0 * b.1 - b.0 * a.1
+    }).sum()
+}
+
+fn cross(o: (f32, f32), a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// Inside or on the edges of triangle abc (counter-clockwise)
+fn in_triangle(p: (f32, f32), a: (f32, f32), b: (f32, f32), c: (f32, f32)) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// Whether every turn goes the same way (collinear vertices allowed).
+pub fn is_convex(points: &[(f32, f32)]) -> bool {
+    let n = points.len();
+    let (mut left, mut right) = (false, false);
+    for i in 0..n {
+        let turn = cross(points[i], points[(i + 1) % n], points[(i + 2) % n]);
+        left |= turn > 0.0;
+        right |= turn < 0.0;
+    }
+    !(left && right)
+}
+
+/// Triangles covering the polygon, as indices into `points`, wound like the polygon.
+/// Self-intersecting outlines still produce triangles, but they may overlap.
+pub fn triangulate(points: &[(f32, f32)]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    // Work counter-clockwise, flip back at the end
+    let ccw = signed_area(points) >= 0.0;
+    let mut remaining: Vec<usize> = if ccw { (0..n).collect() } else { (0..n).rev().collect() };
+    let mut triangles = Vec::with_capacity(n - 2);
+
+    let mut i = 0;
+    let mut misses = 0;
+    while remaining.len() > 3 {
+        let len = remaining.len();
+        let (prev, cur, next) = (remaining[(i + len - 1) % len], remaining[i % len], remaining[(i + 1) % len]);
+        let (a, b, c) = (points[prev], points[cur], points[next]);
+        let convex = cross(a, b, c) > 0.0;
+        let ear = convex && !remaining.iter().any(|&j| {
+            j != prev && j != cur && j != next && in_triangle(points[j], a, b, c)
+        });
+        // A full lap without ears (degenerate or self-intersecting): clip anyway
+        if ear || misses >= len {
+            if cross(a, b, c) != 0.0 {
+                triangles.push([prev, cur, next]);
+            }
+            remaining.remove(i % len);
+            misses = 0;
+        } else {
+            i += 1;
+            misses += 1;
+        }
+        i %= remaining.len();
+    }
+    if cross(points[remaining[0]], points[remaining[1]], points[remaining[2]]) != 0.0 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+
+    if !ccw {
+        for t in &mut triangles {
+            t.swap(1, 2);
+        }
+    }
+    triangles
+}
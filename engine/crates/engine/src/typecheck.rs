@@ -0,0 +1,67 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// --- Luau Type Checking ---
+// mlua bundles the Luau compiler and VM, but not the analyzer (Luau.Analysis).
+// We shell out to `luau-analyze` instead (override with CLEOSELENE_LUAU_ANALYZE), handing
+// it the source on stdin: what is checked is what gets loaded, not the file as it is on
+// disk by then. It runs from the script's directory, where it finds the .luaurc and
+// resolves the script's requires. The analyzer is a blocking subprocess, so hosts call
+// check() before GameState::new, off any loop that must keep ticking. If the analyzer is
+// not installed the check is skipped, the script still loads.
+
+const DEFAULT_ANALYZER: &str = "luau-analyze";
+
+/// A script without type errors.
+#[derive(Debug, PartialEq)]
+pub enum TypeCheck {
+    Passed,
+    /// Not checked: the analyzer couldn't be run (why).
+    Skipped(String),
+}
+
+/// Type checks `source`, loaded from `path`, which labels the errors. Type errors come back
+/// as the Err, one `path(line,col): Kind: message` per line.
+pub fn check(source: &str, path: &Path) -> anyhow::Result<TypeCheck> {
+    let analyzer = std::env::var("CLEOSELENE_LUAU_ANALYZE").unwrap_or_else(|_| DEFAULT_ANALYZER.to_string());
+    // A relative analyzer path means relative to here, not to the script's directory
+    let program = match std::fs::canonicalize(&analyzer) {
+        Ok(p) if analyzer.contains(std::path::MAIN_SEPARATOR) => p,
+        _ => analyzer.clone().into(),
+    };
+
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let child = Command::new(program)
+        .arg("-")
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => return Ok(TypeCheck::Skipped(format!("{} unavailable: {}", analyzer, e))),
+    };
+
+    // Written from another thread: a long report could fill the output pipe first
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(source.as_bytes()));
+        child.wait_with_output()
+    })?;
+
+    if output.status.success() {
+        return Ok(TypeCheck::Passed);
+    }
+
+    let mut report = String::from_utf8_lossy(&output.stdout).into_owned();
+    report.push_str(&String::from_utf8_lossy(&output.stderr));
+    let report: Vec<String> = report.trim_end().lines()
+        .map(|line| match line.strip_prefix("-(") {
+            Some(rest) => format!("{}({}", path.display(), rest),
+            None => line.to_string(),
+        })
+        .collect();
+    anyhow::bail!("Type check failed:\n{}", report.join("\n"))
+}
@@ -0,0 +1,31 @@
+use engine::GameState;
+
+#[test]
+fn test_palette_and_text_scale_apply_per_session() {
+    let script = r#"
+        function init()
+            api.set_accessibility("s1", {palette = "deuteranopia", text_scale = 1.5})
+        end
+        function draw(session_id)
+            api.set_color(255, 0, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    // Unaffected session: plain OP_SET_COLOR
+    assert_eq!(game.draw("s2").unwrap().as_ref(), &[0x02, 255, 0, 0, 255]);
+
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(bytes[0], 0x14, "OP_SET_TEXT_SCALE");
+    assert_eq!(f32::from_le_bytes(bytes[1..5].try_into().unwrap()), 1.5);
+    // Red loses its green-distinguishable component and gains blue
+    assert_eq!(&bytes[5..], &[0x02, 255, 0, 67, 255]);
+
+    let result = game.eval(r#"
+        local a = api.get_accessibility("s1")
+        assert(a.palette == "deuteranopia" and a.text_scale == 1.5)
+        assert(not pcall(api.set_accessibility, "s1", {palette = "sepia"}))
+        assert(not pcall(api.set_accessibility, "s1", {text_scale = 10}))
+    "#);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
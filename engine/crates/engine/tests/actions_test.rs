@@ -0,0 +1,91 @@
+use bytes::Buf;
+use engine::input::GamepadControl;
+use engine::GameState;
+use std::fs;
+
+const OP_ACTION_KEYS: u8 = 0x3E;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+// (action, keys) of the OP_ACTION_KEYS command a frame starts with
+fn action_keys(frame: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut cursor = frame;
+    assert_eq!(cursor.get_u8(), OP_ACTION_KEYS);
+    (0..cursor.get_u8()).map(|_| {
+        let len = cursor.get_u16_le() as usize;
+        let action = String::from_utf8(cursor[..len].to_vec()).unwrap();
+        cursor.advance(len);
+        let count = cursor.get_u8() as usize;
+        let keys = cursor[..count].to_vec();
+        cursor.advance(count);
+        (action, keys)
+    }).collect()
+}
+
+const SCRIPT: &str = r#"
+    events = {}
+    api.bind_action("jump", {keys = {32, 38}, pad_buttons = {0}})
+    api.bind_action("fire", {keys = {90}})
+    function on_action(session_id, action, is_down)
+        table.insert(events, action .. ":" .. tostring(is_down))
+    end
+"#;
+
+#[test]
+fn test_actions_from_keys_and_buttons() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+
+    game.handle_input("s1", 32, true).unwrap();
+    eval_ok(&game, "assert(api.is_action_down('s1', 'jump') and not api.is_action_down('s1', 'fire'))");
+    // Still held while any bound control is
+    game.handle_gamepad("s1", 0, GamepadControl::Button(0), 1.0).unwrap();
+    game.handle_input("s1", 32, false).unwrap();
+    eval_ok(&game, "assert(api.is_action_down('s1', 'jump'))");
+    game.handle_gamepad("s1", 0, GamepadControl::Button(0), 0.0).unwrap();
+    game.handle_input("s1", 90, true).unwrap();
+    game.handle_input("s1", 65, true).unwrap(); // Unbound
+    eval_ok(&game, r#"assert(table.concat(events, ",") == "jump:true,jump:false,fire:true", table.concat(events, ","))"#);
+}
+
+#[test]
+fn test_rebinding_is_per_player_and_sent_to_clients() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    assert_eq!(action_keys(&game.on_connect("s1").unwrap()), vec![
+        ("fire".to_string(), vec![90]),
+        ("jump".to_string(), vec![32, 38]),
+    ]);
+    game.on_connect("s2").unwrap();
+    // Sent once, until the bindings change
+    assert!(game.draw("s1").unwrap().is_empty());
+
+    eval_ok(&game, "api.rebind_action('s1', 'fire', {keys = {88}})");
+    assert_eq!(action_keys(&game.draw("s1").unwrap())[0], ("fire".to_string(), vec![88]));
+    assert!(game.draw("s2").unwrap().is_empty());
+
+    game.handle_input("s1", 90, true).unwrap();
+    game.handle_input("s2", 90, true).unwrap();
+    eval_ok(&game, "assert(not api.is_action_down('s1', 'fire') and api.is_action_down('s2', 'fire'))");
+    eval_ok(&game, "assert(api.get_bindings('s1').fire.keys[1] == 88 and api.get_bindings('s2').fire.keys[1] == 90)");
+
+    eval_ok(&game, "api.rebind_action('s1', 'fire', nil)");
+    eval_ok(&game, "assert(api.get_bindings('s1').fire.keys[1] == 90)");
+    assert!(game.eval("api.rebind_action('s1', 'dance', {keys = {1}})").starts_with("Error"));
+}
+
+#[test]
+fn test_input_map_file() {
+    let dir = std::env::temp_dir().join(format!("cleoselene-input-map-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("input_map.json"), r#"{"jump": {"keys": [32]}, "pause": {"pad_buttons": [9]}}"#).unwrap();
+
+    let game = GameState::new("api.bind_action('fire', {keys = {90}})", Some(&dir.join("main.lua"))).expect("Failed to init game");
+    eval_ok(&game, "local b = api.get_bindings('s1'); assert(b.jump.keys[1] == 32 and b.pause.pad_buttons[1] == 9 and b.fire)");
+
+    fs::write(dir.join("input_map.json"), r#"{"jump": {"keys": [999]}}"#).unwrap();
+    assert!(GameState::new("", Some(&dir.join("main.lua"))).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,36 @@
+use engine::{Admission, GameState};
+use serde_json::json;
+
+const SCRIPT: &str = r#"
+    banned = {troll = true}
+    match_running = false
+    function on_before_connect(id, auth)
+        if auth and banned[auth.name] then return false end
+        if match_running then return "queue" end
+        return true
+    end
+"#;
+
+#[test]
+fn test_on_before_connect_decides_admission() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    assert_eq!(game.before_connect("s1", None).unwrap(), Admission::Accept);
+    assert_eq!(game.before_connect("s2", Some(&json!({"name": "troll"}))).unwrap(), Admission::Reject);
+
+    game.eval("match_running = true");
+    assert_eq!(game.before_connect("s3", None).unwrap(), Admission::Queue);
+}
+
+#[test]
+fn test_before_connect_without_hook_or_with_bad_answer() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    assert_eq!(game.before_connect("s1", None).unwrap(), Admission::Accept);
+
+    let game = GameState::new("function on_before_connect(id) return 42 end", None).expect("Failed to init game");
+    assert!(game.before_connect("s1", None).is_err());
+}
+
+#[test]
+fn test_waiting_frame_is_not_empty() {
+    assert!(!engine::waiting_frame("Waiting for a free slot").is_empty());
+}
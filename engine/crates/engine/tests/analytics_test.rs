@@ -0,0 +1,26 @@
+use engine::GameState;
+
+#[test]
+fn test_track_buffers_events_until_taken() {
+    let script = r#"
+        function init()
+            api.track("boot")
+        end
+
+        function update(dt)
+            api.track("level_complete", {level = 3, tags = {"fast"}})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.update(0.1).unwrap();
+
+    let events = game.take_tracked_events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].name, "boot");
+    assert!(events[0].properties.is_null());
+    assert_eq!(events[1].name, "level_complete");
+    assert_eq!(events[1].properties["level"], 3);
+    assert_eq!(events[1].properties["tags"][0], "fast");
+
+    assert!(game.take_tracked_events().is_empty(), "events are drained");
+}
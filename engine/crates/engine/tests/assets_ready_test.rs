@@ -0,0 +1,20 @@
+use engine::GameState;
+
+#[test]
+fn test_on_assets_ready() {
+    let script = r#"
+        ready = {}
+        function on_assets_ready(session_id)
+            table.insert(ready, session_id)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+    game.handle_assets_ready("s1").unwrap();
+    let result = game.eval("assert(#ready == 1 and ready[1] == 's1')");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    // Optional callback
+    let game = GameState::new("", None).expect("Failed to init game");
+    game.handle_assets_ready("s1").unwrap();
+}
@@ -1,9 +1,13 @@
+use engine::compat::{CAPS_ALL, CAP_BUSES, CAP_PITCH};
 use engine::GameState;
 use bytes::Buf;
 use std::io::Cursor;
 
 // Replicate OpCodes from lib.rs for testing
 const OP_PLAY_SOUND: u8 = 0x07;
+const OP_SET_PITCH: u8 = 0x38;
+const OP_SET_BUS_VOLUME: u8 = 0x39;
+const OP_SET_SOUND_BUS: u8 = 0x3A;
 
 #[test]
 fn test_audio_context_separation() {
@@ -19,7 +23,7 @@ fn test_audio_context_separation() {
         end
     "#;
 
-    let game = GameState::new(script).expect("Failed to init game");
+    let game = GameState::new(script, None).expect("Failed to init game");
 
     // 1. Run Update
     // This should write "global_boom" to event_buffer
@@ -48,6 +52,7 @@ fn test_audio_context_separation() {
     
     let vol1 = cursor.get_f32_le();
     assert_eq!(vol1, 1.0);
+    assert_eq!(cursor.get_f32_le(), 1.0, "pitch");
 
     // --- Verify Second Sound (Local) ---
     assert_eq!(cursor.get_u8(), OP_PLAY_SOUND, "Expected OP_PLAY_SOUND (Local)");
@@ -63,6 +68,7 @@ fn test_audio_context_separation() {
     
     let vol2 = cursor.get_f32_le();
     assert_eq!(vol2, 0.5);
+    assert_eq!(cursor.get_f32_le(), 1.0, "pitch");
 
     // Ensure no extra data
     assert!(!cursor.has_remaining(), "Buffer should be empty");
@@ -81,7 +87,7 @@ fn test_draw_clears_local_buffer() {
         end
     "#;
 
-    let game = GameState::new(script).expect("Failed to init");
+    let game = GameState::new(script, None).expect("Failed to init");
 
     // Frame 1
     game.update(0.16).unwrap();
@@ -108,7 +114,136 @@ fn test_draw_clears_local_buffer() {
             cursor.advance(len); // Name
             cursor.advance(1); // Loop
             cursor.advance(4); // Volume
+            cursor.advance(4); // Pitch
         }
     }
     assert_eq!(count, 1, "Should only have 1 sound per frame");
 }
+
+#[test]
+fn test_sound_pitch() {
+    let script = r#"
+        function draw(session_id)
+            api.play_sound("engine", true, 0.5, 1.5)
+            api.set_pitch("engine", 2)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_PITCH);
+
+    let bytes = game.draw("s1").unwrap();
+    let mut cursor = Cursor::new(bytes);
+    assert_eq!(cursor.get_u8(), OP_PLAY_SOUND);
+    cursor.advance(2 + "engine".len() + 1);
+    assert_eq!((cursor.get_f32_le(), cursor.get_f32_le()), (0.5, 1.5), "volume, pitch");
+    assert_eq!(cursor.get_u8(), OP_SET_PITCH);
+    cursor.advance(2 + "engine".len());
+    assert_eq!(cursor.get_f32_le(), 2.0);
+    assert!(!cursor.has_remaining());
+
+    // Clients without pitch get the sound at normal speed
+    let old = game.draw("old").unwrap();
+    assert_eq!((old[0], old.len()), (OP_PLAY_SOUND, 1 + 2 + "engine".len() + 1 + 4));
+
+    assert!(game.eval("api.set_pitch('engine', 0)").starts_with("Error"));
+    assert!(game.eval("api.play_sound('engine', false, 1, -1)").starts_with("Error"));
+}
+
+#[test]
+fn test_play_sound_for_reaches_one_session() {
+    let script = r#"
+        function update(dt)
+            api.play_sound_for("hit_player", "ouch", false, 0.7)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.begin_frame();
+    game.update(0.016).unwrap();
+
+    assert!(game.draw("bystander").unwrap().is_empty(), "not broadcast");
+    let bytes = game.draw("hit_player").unwrap();
+    let mut cursor = Cursor::new(bytes);
+    assert_eq!(cursor.get_u8(), OP_PLAY_SOUND);
+    let len = cursor.get_u16_le() as usize;
+    let pos = cursor.position() as usize;
+    assert_eq!(&cursor.get_ref()[pos..pos + len], b"ouch");
+    cursor.advance(len + 1);
+    assert_eq!((cursor.get_f32_le(), cursor.get_f32_le()), (0.7, 1.0), "volume, pitch");
+    assert!(!cursor.has_remaining());
+
+    // Played once
+    assert!(game.draw("hit_player").unwrap().is_empty());
+}
+
+#[test]
+fn test_bus_volumes() {
+    let script = r#"
+        function update(dt)
+            api.set_bus_volume("music", 0.25, {fade = 2})
+            api.set_bus_volume("ui", 0, {session = "muted"})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_BUSES);
+    game.begin_frame();
+    game.update(0.016).unwrap();
+
+    let bytes = game.draw("s1").unwrap();
+    let mut cursor = Cursor::new(bytes);
+    assert_eq!(cursor.get_u8(), OP_SET_BUS_VOLUME);
+    let len = cursor.get_u16_le() as usize;
+    let pos = cursor.position() as usize;
+    assert_eq!(&cursor.get_ref()[pos..pos + len], b"music");
+    cursor.advance(len);
+    assert_eq!((cursor.get_f32_le(), cursor.get_f32_le()), (0.25, 2.0), "volume, fade");
+    assert!(!cursor.has_remaining(), "the session-only volume isn't broadcast");
+
+    let muted = game.draw("muted").unwrap();
+    assert_eq!(muted.iter().filter(|&&b| b == OP_SET_BUS_VOLUME).count(), 2);
+    assert!(muted.windows(2).any(|w| w == b"ui"));
+
+    // Clients without buses play everything at the sounds' own volumes
+    assert!(game.draw("old").unwrap().is_empty());
+
+    assert!(game.eval("api.set_bus_volume('sfx', -1)").starts_with("Error"));
+}
+
+#[test]
+fn test_load_sound_onto_bus() {
+    let script = r#"
+        function on_connect(session_id)
+            api.load_sound("click", "click.wav", "ui")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.on_connect("s1").unwrap();
+    let at = bytes.iter().position(|&b| b == OP_SET_SOUND_BUS).expect("sound bus sent");
+    let mut cursor = Cursor::new(&bytes[at + 1..]);
+    let len = cursor.get_u16_le() as usize;
+    cursor.advance(len);
+    assert_eq!(len, "click".len());
+    let len = cursor.get_u16_le() as usize;
+    let pos = cursor.position() as usize;
+    assert_eq!(&cursor.get_ref()[pos..pos + len], b"ui");
+}
+
+#[test]
+fn test_sound_finished_callback() {
+    let script = r#"
+        finished = {}
+        function on_sound_finished(name, session_id)
+            table.insert(finished, session_id .. ":" .. name)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.handle_sound_finished("s1", "line_1").unwrap();
+    game.handle_sound_finished("s2", "theme").unwrap();
+    let result = game.eval("assert(table.concat(finished, ',') == 's1:line_1,s2:theme')");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    assert!(game.handle_sound_finished("s1", &"x".repeat(70_000)).is_err());
+
+    // Scripts without the callback ignore the reports
+    let game = GameState::new("", None).expect("Failed to init game");
+    game.handle_sound_finished("s1", "line_1").unwrap();
+}
@@ -0,0 +1,29 @@
+use engine::GameState;
+use serde_json::json;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    players = {}
+    function on_connect(id, auth)
+        players[id] = {name = auth and auth.name or "guest"}
+    end
+"#;
+
+#[test]
+fn test_auth_info_reaches_on_connect() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.set_auth_info("s1", json!({"sub": "42", "name": "ada"}));
+    game.on_connect("s1").unwrap();
+    game.on_connect("s2").unwrap();
+    eval_ok(&game, r#"
+        assert(players.s1.name == "ada" and players.s2.name == "guest")
+        assert(api.get_auth_info("s1").sub == "42" and api.get_auth_info("s2") == nil)
+    "#);
+
+    game.on_disconnect("s1").unwrap();
+    eval_ok(&game, r#"assert(api.get_auth_info("s1") == nil)"#);
+}
@@ -0,0 +1,79 @@
+use engine::GameState;
+
+// Opcodes of a frame made of canvas contents (0x27) and blits (0x28), with the recorded
+// bytes of each content command
+fn parse_canvas_frame(bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]) as usize;
+    let mut ops = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opcode = bytes[offset];
+        let name_end = offset + 3 + u16_at(offset + 1);
+        match opcode {
+            0x27 => {
+                let len = u32::from_le_bytes(bytes[name_end + 4..name_end + 8].try_into().unwrap()) as usize;
+                let start = name_end + 8;
+                ops.push((opcode, bytes[start..start + len].to_vec()));
+                offset = start + len;
+            }
+            0x28 => {
+                ops.push((opcode, Vec::new()));
+                offset = name_end + 16;
+            }
+            other => panic!("unexpected opcode 0x{:02X}", other),
+        }
+    }
+    ops
+}
+
+#[test]
+fn test_canvas_contents_sent_once() {
+    let script = r#"
+        bg = api.new_canvas("bg", 200, 100)
+        color = 1
+        function repaint()
+            bg:draw(function() api.clear_screen(color, 0, 0) end)
+        end
+        repaint()
+        function draw(session_id) bg:blit(0, 0) end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let ops = parse_canvas_frame(&game.draw("s1").unwrap());
+    assert_eq!(ops, vec![(0x27, vec![0x01, 1, 0, 0]), (0x28, vec![])]);
+    // Recorded commands don't leak into the frame that recorded them
+    assert_eq!(parse_canvas_frame(&game.draw("s1").unwrap()), vec![(0x28, vec![])]);
+
+    // Same drawing again: nothing to resend
+    game.eval("repaint()");
+    assert_eq!(parse_canvas_frame(&game.draw("s1").unwrap()), vec![(0x28, vec![])]);
+
+    game.eval("color = 2; repaint()");
+    let ops = parse_canvas_frame(&game.draw("s1").unwrap());
+    assert_eq!(ops[0], (0x27, vec![0x01, 2, 0, 0]));
+
+    // Other clients get the contents on their first blit; lost frames resend them
+    assert_eq!(parse_canvas_frame(&game.draw("s2").unwrap())[0].0, 0x27);
+    game.frame_dropped("s1");
+    assert_eq!(parse_canvas_frame(&game.draw("s1").unwrap())[0].0, 0x27);
+}
+
+#[test]
+fn test_canvas_blit_layout_and_size_limits() {
+    let script = r#"
+        map = api.new_canvas("map", 64, 32)
+        function draw(session_id) map:blit(10, 20, 128) end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // Empty recording first, then the blit: name, x, y, w, h (h defaults to the canvas height)
+    let blit = &bytes[1 + 2 + 3 + 4 + 4..];
+    assert_eq!(blit[0], 0x28);
+    assert_eq!(&blit[3..6], b"map");
+    let floats: Vec<f32> = blit[6..22].chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+    assert_eq!(floats, vec![10.0, 20.0, 128.0, 32.0]);
+
+    assert!(game.eval("api.new_canvas('big', 5000, 10)").starts_with("Error"));
+    assert!(game.eval("api.new_canvas('none', 0, 10)").starts_with("Error"));
+}
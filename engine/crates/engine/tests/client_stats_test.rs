@@ -0,0 +1,55 @@
+use engine::{ClientStats, GameState};
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    function init()
+        sparks = api.new_emitter{ rate = 0, lifetime = 5, speed = 0 }
+        sparks:burst(20)
+        api.float_text("+10", 100, 100)
+    end
+    function draw(session_id)
+        sparks:draw()
+        api.draw_float_texts()
+    end
+"#;
+
+#[test]
+fn test_client_stats_reach_the_script() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    eval_ok(&game, "assert(api.get_client_stats('s1') == nil)");
+
+    game.set_client_stats("s1", ClientStats { queue: 3, queue_max: 30, rtt_ms: Some(42.0), dropped: 1, frame_rate: 15.0, congested: true });
+    eval_ok(&game, r#"
+        local s = api.get_client_stats('s1')
+        assert(s.queue == 3 and s.queue_max == 30 and s.rtt == 42 and s.dropped == 1)
+        assert(s.frame_rate == 15 and s.congested)
+    "#);
+
+    game.on_disconnect("s1").unwrap();
+    eval_ok(&game, "assert(api.get_client_stats('s1') == nil)");
+}
+
+#[test]
+fn test_get_ping() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.set_client_stats("s1", ClientStats::default());
+    eval_ok(&game, "assert(api.get_ping('s1') == nil and api.get_ping('s2') == nil)");
+
+    game.set_client_stats("s1", ClientStats { rtt_ms: Some(80.0), ..Default::default() });
+    eval_ok(&game, "assert(api.get_ping('s1') == 80)");
+}
+
+#[test]
+fn test_congested_clients_skip_cosmetic_layers() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.update(0.01).unwrap();
+    game.set_client_stats("s1", ClientStats { congested: true, ..Default::default() });
+    game.set_client_stats("s2", ClientStats::default());
+
+    assert!(game.draw("s1").unwrap().is_empty());
+    assert!(!game.draw("s2").unwrap().is_empty());
+}
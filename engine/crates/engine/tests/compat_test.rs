@@ -0,0 +1,168 @@
+use engine::compat::{self, CAPS_ALL, CAPS_LEGACY, CAP_ELLIPSES, CAP_IMAGES, CAP_MUSIC};
+use engine::GameState;
+
+fn f32_at(bytes: &[u8], i: usize) -> f32 {
+    f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+// Opcodes of a frame made of fill_rect (0x03), draw_line (0x04) and set_color (0x02) only
+fn legacy_opcodes(bytes: &[u8]) -> Vec<u8> {
+    let mut ops = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opcode = bytes[offset];
+        ops.push(opcode);
+        offset += 1 + match opcode {
+            0x02 => 4,
+            0x03 => 16,
+            0x04 => 20,
+            other => panic!("unexpected opcode 0x{:02X}", other),
+        };
+    }
+    ops
+}
+
+#[test]
+fn test_sprite_falls_back_to_rect() {
+    let script = r#"
+        function draw(session_id)
+            api.set_color(255, 0, 0)
+            api.draw_sprite("ship", 10, 20, 30, 40)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_LEGACY);
+    game.set_capabilities("new", CAPS_ALL);
+
+    let old = game.draw("old").unwrap();
+    assert_eq!(legacy_opcodes(&old), vec![0x02, 0x03]);
+    let rect: Vec<f32> = (0..4).map(|i| f32_at(&old, 6 + i * 4)).collect();
+    assert_eq!(rect, vec![10.0, 20.0, 30.0, 40.0]);
+
+    // Capable clients, and sessions that never announced anything, get the sprite
+    assert_eq!(game.draw("new").unwrap()[5], 0x0B);
+    assert_eq!(game.draw("unknown").unwrap()[5], 0x0B);
+}
+
+#[test]
+fn test_fallbacks_follow_capabilities() {
+    let script = r#"
+        function draw(session_id)
+            api.fill_circle(50, 50, 10)
+            api.draw_sprite("ship", 0, 0, 8, 8)
+            api.set_layer(2)
+            api.draw_bezier(0, 0, 50, 100, 100, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("s1", CAP_ELLIPSES | CAP_IMAGES);
+
+    let bytes = game.draw("s1").unwrap();
+    // Circle (13 bytes) and sprite (1 + 2 + 4 + 20 bytes) kept, layer dropped, curve as lines
+    assert_eq!(bytes[0], 0x0C);
+    assert_eq!(bytes[13], 0x0B);
+    let rest = &bytes[13 + 27..];
+    let ops = legacy_opcodes(rest);
+    assert!(ops.len() > 1 && ops.iter().all(|&op| op == 0x04), "{:?}", ops);
+    // The polyline ends where the curve does
+    let last = rest.len() - 21;
+    assert_eq!((f32_at(rest, last + 9), f32_at(rest, last + 13)), (100.0, 0.0));
+}
+
+#[test]
+fn test_camera_applied_for_legacy_clients() {
+    let script = r#"
+        function draw(session_id)
+            api.set_camera(500, 400, 2)
+            api.fill_rect(500, 400, 10, 10)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("s1", CAPS_LEGACY);
+
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(legacy_opcodes(&bytes), vec![0x03]);
+    // The camera center lands on the screen center, scaled by the zoom
+    let rect: Vec<f32> = (0..4).map(|i| f32_at(&bytes, 1 + i * 4)).collect();
+    assert_eq!(rect, vec![400.0, 300.0, 20.0, 20.0]);
+}
+
+#[test]
+fn test_unsupported_frames_are_emptied() {
+    let status = engine::dev_status_frame(false, "boom");
+    assert!(compat::downgrade(&status, CAPS_LEGACY).is_empty());
+    assert_eq!(compat::downgrade(&status, CAPS_ALL), status);
+}
+
+#[test]
+fn test_palette_colors_fall_back_to_set_color() {
+    let script = r#"
+        api.define_palette({ {255, 0, 0}, {0, 128, 255, 100} })
+        function draw(session_id)
+            api.set_palette_color(2)
+            api.fill_rect(0, 0, 10, 10)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_LEGACY);
+    game.set_capabilities("new", CAPS_ALL);
+
+    // Palette defined in an earlier frame
+    let old = game.draw("old").unwrap();
+    assert_eq!(legacy_opcodes(&old), vec![0x02, 0x03]);
+    assert_eq!(&old[1..5], &[0, 128, 255, 100]);
+
+    // OP_PALETTE_COLOR: index from 0
+    assert_eq!(&game.draw("new").unwrap()[..2], &[0x30, 1]);
+
+    assert!(game.eval("api.set_palette_color(3)").starts_with("Error"));
+    assert!(game.eval("api.define_palette({})").starts_with("Error"));
+}
+
+#[test]
+fn test_polyline_falls_back_to_lines() {
+    let script = r#"
+        function draw(session_id)
+            api.draw_polyline({0, 0, 10, 0, 10, 10, 20, 10}, 3)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_LEGACY);
+    game.set_capabilities("new", CAPS_ALL);
+
+    // OP_DRAW_POLYLINE: width, u16 count, x/y pairs
+    let new = game.draw("new").unwrap();
+    assert_eq!((new[0], f32_at(&new, 1), u16::from_le_bytes([new[5], new[6]])), (0x32, 3.0, 4));
+    assert_eq!(new.len(), 7 + 4 * 8);
+
+    // One line per segment, not closed
+    let old = game.draw("old").unwrap();
+    assert_eq!(legacy_opcodes(&old), vec![0x04; 3]);
+    assert_eq!((f32_at(&old, 42 + 9), f32_at(&old, 42 + 13), f32_at(&old, 42 + 17)), (20.0, 10.0, 3.0));
+}
+
+#[test]
+fn test_music_falls_back_to_sounds() {
+    let script = r#"
+        function draw(session_id)
+            api.crossfade_music("calm", "battle", 2)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_MUSIC);
+    game.set_capabilities("new", CAPS_ALL);
+
+    // OP_STOP_MUSIC (name, fade out) then OP_PLAY_MUSIC (name, loop, volume, fade in)
+    let new = game.draw("new").unwrap();
+    assert_eq!((new[0], &new[3..7], f32_at(&new, 7)), (0x36, &b"calm"[..], 2.0));
+    assert_eq!((new[11], &new[14..20], new[20]), (0x35, &b"battle"[..], 1));
+    assert_eq!((f32_at(&new, 21), f32_at(&new, 25)), (1.0, 2.0));
+    assert_eq!(new.len(), 29);
+
+    // A stopped sound and a looping sound (volume, pitch), without fades
+    let old = game.draw("old").unwrap();
+    assert_eq!((old[0], &old[3..7]), (0x08, &b"calm"[..]));
+    assert_eq!((old[7], &old[10..16], old[16]), (0x07, &b"battle"[..], 1));
+    assert_eq!((f32_at(&old, 17), f32_at(&old, 21)), (1.0, 1.0));
+    assert_eq!(old.len(), 25);
+}
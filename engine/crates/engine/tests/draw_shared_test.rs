@@ -0,0 +1,99 @@
+use engine::{compat, GameState};
+use std::sync::Arc;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    world_draws = 0
+
+    function draw(session_id)
+        api.set_color(session_id == "a" and 255 or 0, 0, 0)
+        api.draw_shared(function()
+            world_draws = world_draws + 1
+            api.fill_rect(0, 0, 10, 10)
+        end)
+        api.fill_rect(20, 20, 5, 5)
+    end
+"#;
+
+#[test]
+fn test_shared_segment_recorded_once_per_frame() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    game.begin_frame();
+    let a = game.draw_split("a").unwrap();
+    let b = game.draw_split("b").unwrap();
+    eval_ok(&game, "assert(world_draws == 1)");
+
+    let (sa, sb) = (a.shared.clone().unwrap(), b.shared.clone().unwrap());
+    assert!(Arc::ptr_eq(&sa, &sb), "one segment for every session");
+    assert_eq!(sa.bytes[0], 0x03, "OP_FILL_RECT");
+    assert_ne!(a.before, b.before, "the color is each session's own");
+    assert_eq!(a.after, b.after);
+    assert_eq!(a.after[0], 0x03);
+
+    // The whole frame is the three parts in order
+    let whole = a.to_bytes();
+    assert_eq!(whole.len(), a.before.len() + sa.bytes.len() + a.after.len());
+    assert_eq!(&whole[a.before.len()..a.before.len() + sa.bytes.len()], &sa.bytes[..]);
+
+    // Encoded once, whoever asks
+    let mut encodes = 0;
+    for segment in [&sa, &sb] {
+        segment.encoded(|raw| {
+            encodes += 1;
+            bytes::Bytes::copy_from_slice(raw)
+        });
+    }
+    assert_eq!(encodes, 1);
+
+    game.begin_frame();
+    let c = game.draw_split("a").unwrap();
+    eval_ok(&game, "assert(world_draws == 2)");
+    assert!(!Arc::ptr_eq(&sa, c.shared.as_ref().unwrap()), "recorded again next frame");
+}
+
+#[test]
+fn test_camera_set_in_segment_is_undone() {
+    let script = r#"
+        function draw(session_id)
+            api.draw_shared(function()
+                api.set_camera(100, 100, 2)
+                api.fill_rect(0, 0, 10, 10)
+            end)
+        end
+    "#;
+    let game = GameState::new(script, None).unwrap();
+    game.begin_frame();
+    for session in ["a", "b"] {
+        let frame = game.draw_split(session).unwrap();
+        assert_eq!(frame.shared.unwrap().bytes[0], 0x12, "OP_SET_TRANSFORM");
+        assert_eq!(frame.after[0], 0x12, "the session's camera again, for {}", session);
+    }
+}
+
+#[test]
+fn test_downgraded_clients_get_whole_frames() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    game.set_capabilities("old", compat::CAPS_LEGACY);
+    game.begin_frame();
+    let frame = game.draw_split("old").unwrap();
+    assert!(frame.shared.is_none());
+    assert!(frame.after.is_empty());
+    assert_eq!(game.draw("old").unwrap(), frame.before);
+}
+
+#[test]
+fn test_draw_shared_misuse() {
+    let script = r#"
+        function draw(session_id)
+            api.draw_shared(function() end)
+            api.draw_shared(function() end)
+        end
+    "#;
+    let game = GameState::new(script, None).unwrap();
+    assert!(game.draw("a").is_err(), "only once per draw()");
+    assert!(game.eval("api.draw_shared(function() end)").starts_with("Error"), "only in draw()");
+}
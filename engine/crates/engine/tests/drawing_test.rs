@@ -0,0 +1,508 @@
+use engine::GameState;
+
+#[test]
+fn test_load_image_and_draw_sprite_opcodes() {
+    let script = r#"
+        function on_connect(session_id)
+            api.load_image("ship", "/assets/ship.png")
+        end
+
+        function draw(session_id)
+            api.draw_sprite("ship", 10, 20, 32, 16, 1.5)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let bytes = game.on_connect("s1").unwrap();
+    assert_eq!(bytes[0], 0x0A, "OP_LOAD_IMAGE");
+    assert_eq!(bytes.len(), 1 + 2 + 4 + 2 + "/assets/ship.png".len());
+
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(bytes[0], 0x0B, "OP_DRAW_SPRITE");
+    assert_eq!(u16::from_le_bytes([bytes[1], bytes[2]]), 4);
+    assert_eq!(&bytes[3..7], b"ship");
+    let floats: Vec<f32> = bytes[7..27].chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+    assert_eq!(floats, vec![10.0, 20.0, 32.0, 16.0, 1.5]);
+    assert_eq!(bytes.len(), 27);
+}
+
+#[test]
+fn test_ninepatch_opcode_layout() {
+    let script = r#"
+        function draw(session_id)
+            api.draw_ninepatch("panel", 10, 20, 200, 100, 8)
+            api.draw_ninepatch("panel", 0, 0, 50, 50, {1, 2, 3, 4})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    let size = 1 + 2 + 5 + 8 * 4;
+    assert_eq!(bytes.len(), 2 * size);
+    assert_eq!(bytes[0], 0x26, "OP_DRAW_NINEPATCH");
+    assert_eq!(&bytes[3..8], b"panel");
+    let floats = |at: usize| -> Vec<f32> {
+        bytes[at + 8..at + size].chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+    };
+    assert_eq!(floats(0), vec![10.0, 20.0, 200.0, 100.0, 8.0, 8.0, 8.0, 8.0], "one number for every margin");
+    assert_eq!(floats(size)[4..], [1.0, 2.0, 3.0, 4.0]);
+
+    assert!(game.eval("api.draw_ninepatch('panel', 0, 0, 10, 10, {1, 2})").starts_with("Error"));
+    assert!(game.eval("api.draw_ninepatch('panel', 0, 0, 10, 10, -1)").starts_with("Error"));
+}
+
+#[test]
+fn test_circle_and_ellipse_opcodes() {
+    let script = r#"
+        function draw(session_id)
+            api.fill_circle(1, 2, 3)
+            api.draw_circle(1, 2, 3)
+            api.fill_ellipse(1, 2, 3, 4)
+            api.draw_ellipse(1, 2, 3, 4, 2)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // One opcode byte + f32 args each: 3, 4 (default width), 4, 5
+    let sizes = [(0x0C, 3), (0x0D, 4), (0x0E, 4), (0x0F, 5)];
+    let mut offset = 0;
+    for (opcode, args) in sizes {
+        assert_eq!(bytes[offset], opcode);
+        offset += 1 + 4 * args;
+    }
+    assert_eq!(bytes.len(), offset);
+
+    let default_width = f32::from_le_bytes([bytes[26], bytes[27], bytes[28], bytes[29]]);
+    assert_eq!(default_width, 1.0);
+}
+
+#[test]
+fn test_arc_opcodes() {
+    let script = r#"
+        function draw(session_id)
+            api.fill_arc(10, 20, 5, 0, 1.5)
+            api.fill_arc(10, 20, 5, 0, 1.5, 3)
+            api.draw_arc(10, 20, 5, 1.5, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // One opcode byte + 6 f32 args each; inner_r and width have defaults
+    let floats = |offset: usize| -> Vec<f32> {
+        bytes[offset + 1..offset + 25].chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+    };
+    assert_eq!(bytes.len(), 3 * 25);
+    assert_eq!(bytes[0], 0x1C, "OP_FILL_ARC");
+    assert_eq!(floats(0), vec![10.0, 20.0, 5.0, 0.0, 1.5, 0.0]);
+    assert_eq!(bytes[25], 0x1C);
+    assert_eq!(floats(25)[5], 3.0);
+    assert_eq!(bytes[50], 0x1D, "OP_DRAW_ARC");
+    assert_eq!(floats(50), vec![10.0, 20.0, 5.0, 1.5, 0.0, 1.0]);
+}
+
+#[test]
+fn test_curve_opcode_layout() {
+    let script = r#"
+        function draw(session_id)
+            api.draw_bezier(0, 0, 5, 10, 10, 0, 2)
+            api.draw_cubic_bezier(0, 0, 1, 1, 2, 2, 3, 3)
+            api.draw_spline({{0, 0}, {10, 0}, {10, 10}, {0, 10}}, 3, true)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+    let f32_at = |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+
+    // opcode, kind, closed, width, start x/y, count: 17 bytes, then the segments
+    assert_eq!(&bytes[0..3], &[0x20, 0, 0], "quadratic, open");
+    assert_eq!(f32_at(3), 2.0);
+    assert_eq!(u16_at(15), 1);
+    assert_eq!((f32_at(17), f32_at(21), f32_at(25), f32_at(29)), (5.0, 10.0, 10.0, 0.0));
+
+    let cubic = 17 + 4 * 4;
+    assert_eq!(&bytes[cubic..cubic + 3], &[0x20, 1, 0]);
+    assert_eq!(f32_at(cubic + 3), 1.0, "default width");
+
+    // Closed spline through 4 points: 4 cubic segments ending back at the start
+    let spline = cubic + 17 + 6 * 4;
+    assert_eq!(&bytes[spline..spline + 3], &[0x20, 1, 1]);
+    assert_eq!(u16_at(spline + 15), 4);
+    let end = spline + 17 + 4 * 6 * 4;
+    assert_eq!((f32_at(end - 8), f32_at(end - 4)), (0.0, 0.0));
+    assert_eq!(bytes.len(), end);
+}
+
+#[test]
+fn test_control_scheme_goes_only_to_its_session() {
+    let script = r#"
+        function update(dt)
+            api.set_control_scheme("s1", "vehicle")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.update(0.1).unwrap();
+
+    assert!(game.draw("s2").unwrap().is_empty(), "other clients are unaffected");
+
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(bytes[0], 0x10, "OP_SET_CONTROL_SCHEME");
+    assert_eq!(u16::from_le_bytes([bytes[1], bytes[2]]), 7);
+    assert_eq!(&bytes[3..], b"vehicle");
+
+    assert!(game.draw("s1").unwrap().is_empty(), "sent once");
+}
+
+#[test]
+fn test_polygon_opcode_layout() {
+    let script = r#"
+        function draw(session_id)
+            api.fill_polygon({0, 0, 10, 0, 5, 8})
+            api.draw_polygon({{0, 0}, {10, 0}, {5, 8}}, 2)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // opcode, mode, width, u16 count, 3 vertices
+    let size = 1 + 1 + 4 + 2 + 3 * 8;
+    assert_eq!(bytes.len(), 2 * size);
+    for (start, mode, width) in [(0, 0u8, 0.0f32), (size, 1, 2.0)] {
+        assert_eq!(bytes[start], 0x11, "OP_DRAW_POLY");
+        assert_eq!(bytes[start + 1], mode);
+        assert_eq!(f32::from_le_bytes(bytes[start + 2..start + 6].try_into().unwrap()), width);
+        assert_eq!(u16::from_le_bytes([bytes[start + 6], bytes[start + 7]]), 3);
+        let last_y = f32::from_le_bytes(bytes[start + size - 4..start + size].try_into().unwrap());
+        assert_eq!(last_y, 8.0);
+    }
+
+    let result = game.eval("api.fill_polygon({1, 2, 3})");
+    assert!(result.starts_with("Error"), "odd coordinate count must be rejected");
+}
+
+#[test]
+fn test_concave_polygon_is_triangulated() {
+    // An L: 6 vertices, 4 triangles
+    let script = r#"
+        L = {0, 0, 20, 0, 20, 10, 10, 10, 10, 30, 0, 30}
+        function draw(session_id)
+            api.fill_polygon(L)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // OP_FILL_TRIANGLES: u16 count, 3 vertices per triangle
+    assert_eq!(bytes[0], 0x31);
+    assert_eq!(u16::from_le_bytes([bytes[1], bytes[2]]), 4);
+    assert_eq!(bytes.len(), 3 + 4 * 24);
+
+    // The triangles cover the L exactly (area 20*10 + 10*20 = 400), in either winding
+    let result = game.eval(r#"
+        local function area(t) return math.abs((t[3] - t[1]) * (t[6] - t[2]) - (t[5] - t[1]) * (t[4] - t[2])) / 2 end
+        for _, outline in ipairs({L, {0, 30, 10, 30, 10, 10, 20, 10, 20, 0, 0, 0}}) do
+            local total = 0
+            local triangles = api.triangulate(outline)
+            for _, t in ipairs(triangles) do total = total + area(t) end
+            assert(#triangles == 4 and total == 400, total)
+        end
+    "#);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_camera_transform_and_view_rect() {
+    let script = r#"
+        function draw(session_id)
+            local x, y, zoom = api.get_camera()
+            assert(x == 400 and y == 300 and zoom == 1, "each draw starts with the screen camera")
+
+            api.set_camera(1000, 500, 2)
+            local x1, y1, x2, y2 = api.get_view_rect()
+            assert(x1 == 800 and y1 == 350 and x2 == 1200 and y2 == 650, x1 .. "," .. y1 .. "," .. x2 .. "," .. y2)
+            api.reset_camera()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+    let bytes2 = game.draw("s2").unwrap();
+    assert_eq!(bytes, bytes2);
+
+    // Two OP_SET_TRANSFORM commands: the camera, then the screen view
+    assert_eq!(bytes.len(), 2 * 17);
+    assert_eq!(bytes[0], 0x12);
+    let floats: Vec<f32> = bytes[1..17].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(floats, vec![1000.0, 500.0, 2.0, 0.0]);
+    assert_eq!(bytes[17], 0x12);
+
+    assert!(game.eval("api.set_camera(0, 0, 0)").starts_with("Error"), "zoom must be positive");
+}
+
+#[test]
+fn test_transform_stack_opcodes() {
+    let script = r#"
+        function draw(session_id)
+            api.push_transform(100, 50, 0.5, 2)
+            api.pop_transform()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    assert_eq!(bytes[0], 0x15, "OP_PUSH_TRANSFORM");
+    let floats: Vec<f32> = bytes[1..21].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(floats, vec![100.0, 50.0, 0.5, 2.0, 2.0], "sy defaults to sx");
+    assert_eq!(&bytes[21..], &[0x16], "OP_POP_TRANSFORM");
+}
+
+#[test]
+fn test_ui_block_restores_camera() {
+    let script = r#"
+        function draw(session_id)
+            api.set_camera(1000, 500, 2)
+            api.ui_begin()
+            local x, y, zoom = api.get_camera()
+            assert(x == 400 and y == 300 and zoom == 1, "UI is drawn in screen space")
+            api.ui_end()
+            local x, y, zoom = api.get_camera()
+            assert(x == 1000 and y == 500 and zoom == 2, "the world camera is back")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    // Camera, screen view, camera again
+    assert_eq!(bytes.len(), 3 * 17);
+    let camera = |i: usize| -> Vec<f32> {
+        bytes[i * 17 + 1..(i + 1) * 17].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect()
+    };
+    assert_eq!(camera(1), vec![400.0, 300.0, 1.0, 0.0]);
+    assert_eq!(camera(2), camera(0));
+
+    assert!(game.eval("api.ui_end()").starts_with("Error"), "ui_end needs a ui_begin");
+}
+
+#[test]
+fn test_viewport_culls_offscreen_commands() {
+    let script = r#"
+        function draw(session_id)
+            api.fill_rect(50, 50, 10, 10)      -- inside
+            api.fill_rect(500, 500, 10, 10)    -- outside
+            api.fill_circle(105, 50, 10)       -- overlaps the edge
+            api.push_transform(500, 500)
+            api.fill_rect(0, 0, 10, 10)        -- local coordinates: never culled
+            api.pop_transform()
+            api.reset_camera()
+            api.fill_rect(700, 10, 10, 10)     -- HUD: never culled
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let result = game.eval("api.set_viewport('s1', 0, 0, 100, 100)");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    let culled = game.draw("s1").unwrap();
+    let full = game.draw("s2").unwrap();
+    assert_eq!(full.len() - culled.len(), 17, "only the outside rect is dropped");
+    assert!(!culled.windows(5).any(|w| w[0] == 0x03 && w[1..5] == 500.0f32.to_le_bytes()));
+
+    game.eval("api.clear_viewport('s1')");
+    assert_eq!(game.draw("s1").unwrap(), full);
+}
+
+#[test]
+fn test_set_layer_opcode() {
+    let script = r#"
+        function draw(session_id)
+            api.set_layer(-3)
+            api.fill_rect(0, 0, 1, 1)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(&bytes[..2], &[0x17, (-3i8) as u8], "OP_SET_LAYER carries a signed byte");
+    assert_eq!(bytes[2], 0x03);
+
+    assert!(game.eval("api.set_layer(200)").starts_with("Error"), "layer out of range");
+}
+
+#[test]
+fn test_requested_assets_are_recorded() {
+    let script = r#"
+        function init() api.load_sound("laser", "/assets/laser.wav") end
+        function on_connect(session_id)
+            api.load_image("ship", "/assets/ship.png")
+            api.load_sound("laser", "/assets/laser.wav")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+    assert_eq!(game.requested_assets(), vec!["/assets/laser.wav", "/assets/ship.png"]);
+}
+
+#[test]
+fn test_dev_status_frame_layout() {
+    let frame = engine::dev_status_frame(false, "update error: boom");
+    assert_eq!(frame[0], 0x18, "OP_DEV_STATUS");
+    assert_eq!(frame[1], 1, "1 = error");
+    assert_eq!(u16::from_le_bytes([frame[2], frame[3]]), 18);
+    assert_eq!(&frame[4..], b"update error: boom");
+
+    let long = "é".repeat(5000);
+    let frame = engine::dev_status_frame(true, &long);
+    let len = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+    assert!(len <= 2048 && std::str::from_utf8(&frame[4..4 + len]).is_ok(), "cut on a char boundary");
+}
+
+#[test]
+fn test_gradient_and_blend_mode_opcodes() {
+    let script = r#"
+        function draw(session_id)
+            api.set_blend_mode("add")
+            api.set_radial_gradient(10, 20, 30, {{0, 255, 0, 0}, {1, 0, 0, 255, 0}})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+
+    assert_eq!(&bytes[..2], &[0x1A, 1], "OP_SET_BLEND_MODE add");
+    assert_eq!(&bytes[2..4], &[0x19, 1], "OP_SET_GRADIENT radial");
+    let floats: Vec<f32> = bytes[4..28].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(floats, vec![10.0, 20.0, 0.0, 10.0, 20.0, 30.0]);
+    assert_eq!(bytes[28], 2, "stop count");
+    assert_eq!(&bytes[33..37], &[255, 0, 0, 255], "alpha defaults to 255");
+    assert_eq!(&bytes[41..45], &[0, 0, 255, 0]);
+    assert_eq!(bytes.len(), 45);
+
+    assert!(game.eval("api.set_blend_mode('screen')").starts_with("Error"));
+    assert!(game.eval("api.set_linear_gradient(0, 0, 1, 1, {{0, 1, 2, 3}})").starts_with("Error"), "needs 2 stops");
+}
+
+#[test]
+fn test_screen_effect_opcodes() {
+    let script = r#"
+        function update(dt) api.screen_shake(6, 0.5) end
+        function draw(session_id)
+            if session_id == "hurt" then api.flash(255, 0, 0, 0.25) end
+            api.vignette(3)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.update(0.016).unwrap();
+
+    // Shake from update() reaches everyone, the flash only the player it was drawn for
+    let bytes = game.draw("hurt").unwrap();
+    assert_eq!(bytes[0], 0x2A, "OP_SCREEN_SHAKE");
+    assert_eq!((f32::from_le_bytes(bytes[1..5].try_into().unwrap()), f32::from_le_bytes(bytes[5..9].try_into().unwrap())), (6.0, 0.5));
+    assert_eq!(&bytes[9..13], &[0x2B, 255, 0, 0], "OP_FLASH");
+    assert_eq!(f32::from_le_bytes(bytes[13..17].try_into().unwrap()), 0.25);
+    assert_eq!(bytes[17], 0x2C, "OP_VIGNETTE");
+    assert_eq!(f32::from_le_bytes(bytes[18..22].try_into().unwrap()), 1.0, "strength is clamped");
+
+    let other = game.draw("other").unwrap();
+    assert_eq!((other[0], other[9], other.len()), (0x2A, 0x2C, 14));
+
+    assert!(game.eval("api.screen_shake(-1, 1)").starts_with("Error"));
+    assert!(game.eval("api.flash(0, 0, 0, -1)").starts_with("Error"));
+}
+
+#[test]
+fn test_spritesheet_animation_frames() {
+    let script = r#"
+        api.load_spritesheet("hero", "/assets/hero.png", 32, 16, {
+            walk = { frames = {1, 2, 3}, fps = 10 },
+            die = { frames = {7, 8}, fps = 4, loop = false },
+        })
+        api.load_spritesheet("coin", "/assets/coin.png", 8, 8)
+        function draw(session_id)
+            api.draw_animation("hero", "walk", 10, 20, 0.45)
+            api.draw_animation("hero", "die", 0, 0, 5, 64, 32)
+            api.draw_frame("coin", 2, 0, 0)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").unwrap();
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let f32_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+
+    // OP_DRAW_FRAME: sheet id, frame (from 0), x, y, w, h, rotation
+    assert_eq!((bytes[0], u16_at(1), u16_at(3)), (0x2E, 0, 1), "4th walk step loops to the 2nd frame");
+    assert_eq!((f32_at(5), f32_at(9), f32_at(13), f32_at(17)), (10.0, 20.0, 32.0, 16.0), "frame size by default");
+    assert_eq!((u16_at(26), u16_at(28), f32_at(38)), (0, 7, 64.0), "one-shot animations hold their last frame");
+    assert_eq!((u16_at(51), u16_at(53)), (1, 1));
+    assert_eq!(bytes.len(), 75);
+
+    let result = game.eval("assert(api.animation_duration('hero', 'die') == 0.5)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(game.eval("api.draw_animation('hero', 'fly', 0, 0, 0)").starts_with("Error"));
+    assert!(game.eval("api.draw_frame('nope', 1, 0, 0)").starts_with("Error"));
+    assert!(game.eval("api.load_spritesheet('x', 'x.png', 8, 8, { a = { frames = {0} } })").starts_with("Error"));
+}
+
+#[test]
+fn test_post_effects_are_sent_when_they_change() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    assert!(game.draw("s1").unwrap().is_empty(), "defaults are not sent");
+
+    game.eval(r#"api.set_post_effect("all", {tint = {20, 30, 90}, brightness = 0.5, scanlines = true})"#);
+    // OP_SET_POST: tint RGBA, vignette, brightness, contrast, scanlines
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!((bytes[0], &bytes[1..5]), (0x37, &[20, 30, 90, 128][..]), "tint alpha defaults to 128");
+    let floats: Vec<f32> = bytes[5..17].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(floats, vec![0.0, 0.5, 1.0]);
+    assert_eq!((bytes[17], bytes.len()), (1, 18));
+    assert!(game.draw("s1").unwrap().is_empty(), "unchanged");
+    assert_eq!(game.draw("s2").unwrap().len(), 18, "new clients get the shared settings");
+
+    // A session's own settings, then back to the shared ones
+    game.eval(r#"api.set_post_effect("s1", {vignette = 2})"#);
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!((&bytes[1..5], f32::from_le_bytes(bytes[5..9].try_into().unwrap())), (&[0, 0, 0, 0][..], 1.0));
+    assert!(game.draw("s2").unwrap().is_empty());
+    game.eval(r#"api.set_post_effect("s1", nil)"#);
+    assert_eq!(&game.draw("s1").unwrap()[1..5], &[20, 30, 90, 128]);
+
+    assert!(game.eval(r#"api.set_post_effect("all", {contrast = -1})"#).starts_with("Error"));
+}
+
+// OP_SET_TRANSFORM (0x12): x, y, zoom, rotation
+fn transform_at(bytes: &[u8], i: usize) -> Vec<f32> {
+    assert_eq!(bytes[i], 0x12);
+    bytes[i + 1..i + 17].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect()
+}
+
+#[test]
+fn test_camera_follow_and_shake() {
+    let script = r#"
+        db = api.new_spatial_db(64)
+        target = db:add_circle(100, 50, 5, "player")
+        api.camera_follow("s1", db, target, 0.5, 2)
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    assert!(game.draw("s1").unwrap().is_empty(), "no tick yet");
+
+    // Starts on the entity, then eases half way per 1/60 s
+    game.update(1.0 / 60.0).unwrap();
+    assert_eq!(transform_at(&game.draw("s1").unwrap(), 0), vec![100.0, 50.0, 2.0, 0.0]);
+    game.eval("db:update(target, 200, 50)");
+    game.update(1.0 / 60.0).unwrap();
+    let view = transform_at(&game.draw("s1").unwrap(), 0);
+    assert!((view[0] - 150.0).abs() < 1e-3, "{:?}", view);
+    assert!(game.draw("s2").unwrap().is_empty(), "only the followed session");
+
+    // Shakes everyone around their camera, then dies out
+    game.eval("api.camera_shake('all', 10, 0.5)");
+    game.update(0.1).unwrap();
+    let view = transform_at(&game.draw("s2").unwrap(), 0);
+    assert!((view[0] - 400.0).abs() <= 10.0 && (view[1] - 300.0).abs() <= 10.0, "{:?}", view);
+    game.update(0.5).unwrap();
+    assert!(game.draw("s2").unwrap().is_empty());
+
+    // Following stops with the entity
+    game.eval("db:remove(target)");
+    game.update(1.0 / 60.0).unwrap();
+    assert!(game.draw("s1").unwrap().is_empty());
+
+    assert!(game.eval("api.camera_shake('s1', 5, 0)").starts_with("Error"));
+}
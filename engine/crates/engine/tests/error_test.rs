@@ -0,0 +1,23 @@
+use engine::{EngineError, GameState};
+
+#[test]
+fn test_error_kinds() {
+    let script = r#"
+        function on_input(session_id, code, active)
+            error("boom")
+        end
+        function snapshot() return {} end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    match game.handle_input("s1", 32, true) {
+        Err(EngineError::LuaRuntime { message, traceback }) => {
+            assert!(message.contains("boom"), "{}", message);
+            assert!(traceback.is_some());
+        }
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert!(matches!(game.restore(&serde_json::json!({})), Err(EngineError::Snapshot(_))));
+    assert!(matches!(game.handle_text_input("s1", &"x".repeat(100_000)), Err(EngineError::Protocol(_))));
+    assert!(matches!(GameState::new("function (", None), Err(EngineError::LuaInit(_))));
+}
@@ -0,0 +1,79 @@
+use bytes::Buf;
+use engine::GameState;
+use std::io::Cursor;
+
+const OP_SET_COLOR: u8 = 0x02;
+const OP_DRAW_TEXT: u8 = 0x05;
+
+// (text, x, y, alpha) of every label; the frame holds only set_color + draw_text pairs
+fn labels(bytes: &[u8]) -> Vec<(String, f32, f32, u8)> {
+    let mut cursor = Cursor::new(bytes);
+    let mut out = Vec::new();
+    while cursor.has_remaining() {
+        assert_eq!(cursor.get_u8(), OP_SET_COLOR);
+        cursor.advance(3);
+        let alpha = cursor.get_u8();
+        assert_eq!(cursor.get_u8(), OP_DRAW_TEXT);
+        let (x, y) = (cursor.get_f32_le(), cursor.get_f32_le());
+        let len = cursor.get_u16_le() as usize;
+        let pos = cursor.position() as usize;
+        let text = String::from_utf8(bytes[pos..pos + len].to_vec()).unwrap();
+        cursor.advance(len);
+        out.push((text, x, y, alpha));
+    }
+    out
+}
+
+#[test]
+fn test_float_text_rises_and_fades() {
+    let script = r#"
+        function update(dt)
+            if not shown then
+                api.float_text("12", 100, 200, {duration = 1, rise = 40})
+                shown = true
+            end
+        end
+        function draw(session_id)
+            api.draw_float_texts()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.update(0.25).unwrap();
+    let start = labels(&game.draw("s1").unwrap());
+    assert_eq!(start.len(), 1);
+    let (text, x, y, alpha) = start[0].clone();
+    assert_eq!((text.as_str(), alpha), ("12", 255));
+    assert!(x < 100.0, "centered: {}", x);
+    assert!(y < 200.0, "aged by the update it was added in: {}", y);
+
+    game.update(0.25).unwrap();
+    let (_, _, y, alpha) = labels(&game.draw("s1").unwrap())[0].clone();
+    assert_eq!(y, 200.0 - 40.0 * 0.75, "eased: 3/4 of the rise at half time");
+    assert_eq!(alpha, 255);
+
+    game.update(0.25).unwrap();
+    let (_, _, _, alpha) = labels(&game.draw("s1").unwrap())[0].clone();
+    assert_eq!(alpha, 128, "fading over the second half");
+
+    game.update(0.3).unwrap();
+    assert!(labels(&game.draw("s1").unwrap()).is_empty(), "gone after its duration");
+}
+
+#[test]
+fn test_float_texts_are_culled() {
+    let script = r#"
+        api.float_text("near", 100, 100)
+        api.float_text("far", 5000, 100)
+        function draw(session_id)
+            api.draw_float_texts()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.eval("api.set_viewport('s1', 0, 0, 800, 600)");
+    let frame = labels(&game.draw("s1").unwrap());
+    assert_eq!(frame.len(), 1);
+    assert_eq!(frame[0].0, "near");
+    assert_eq!(labels(&game.draw("s2").unwrap()).len(), 2, "no viewport, no culling");
+
+    assert!(game.eval("api.float_text('x', 0, 0, {duration = 0})").starts_with("Error"));
+}
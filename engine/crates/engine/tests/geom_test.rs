@@ -0,0 +1,59 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_vector_ops() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    eval_ok(&game, r#"
+        local g = api.geom
+        local function near(a, b) return math.abs(a - b) < 1e-5 end
+        assert(g.length(3, 4) == 5)
+        assert(g.distance(1, 1, 4, 5) == 5)
+        local x, y = g.normalize(0, 10)
+        assert(x == 0 and y == 1)
+        x, y = g.normalize(0, 0)
+        assert(x == 0 and y == 0)
+        assert(g.dot(1, 2, 3, 4) == 11)
+        assert(g.cross(1, 0, 0, 1) == 1 and g.cross(0, 1, 1, 0) == -1)
+        x, y = g.rotate(1, 0, math.pi / 2)
+        assert(near(x, 0) and near(y, 1))
+        -- Falling onto a floor (normal up, any length) bounces back up
+        x, y = g.reflect(3, 4, 0, -10)
+        assert(near(x, 3) and near(y, -4))
+        assert(g.lerp(10, 20, 0.25) == 12.5)
+        x, y = g.lerp_vec(0, 0, 10, 20, 0.5)
+        assert(x == 5 and y == 10)
+    "#);
+}
+
+#[test]
+fn test_segments_and_polygons() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    eval_ok(&game, r#"
+        local g = api.geom
+        local x, y, t, u = g.segment_intersection(0, 0, 10, 10, 0, 10, 10, 0)
+        assert(x == 5 and y == 5 and t == 0.5 and u == 0.5)
+        assert(g.segment_intersection(0, 0, 1, 1, 0, 10, 10, 0) == nil, "too short to cross")
+        assert(g.segment_intersection(0, 0, 10, 0, 0, 1, 10, 1) == nil, "parallel")
+
+        local d, cx, cy = g.segment_distance(5, 3, 0, 0, 10, 0)
+        assert(d == 3 and cx == 5 and cy == 0)
+        d, cx, cy = g.segment_distance(-4, 3, 0, 0, 10, 0)
+        assert(d == 5 and cx == 0 and cy == 0, "past the end: closest is the endpoint")
+
+        local gap = g.circle_segment_distance(5, 3, 2, 0, 0, 10, 0)
+        assert(gap == 1)
+        assert(g.circle_segment_distance(5, 1, 2, 0, 0, 10, 0) == -1, "overlapping")
+
+        -- Concave "L", flat and nested points
+        local l = {0, 0, 10, 0, 10, 4, 4, 4, 4, 10, 0, 10}
+        assert(g.point_in_polygon(2, 8, l))
+        assert(not g.point_in_polygon(8, 8, l), "in the notch")
+        assert(g.point_in_polygon(1, 1, {{0, 0}, {4, 0}, {0, 4}}))
+        assert(not g.point_in_polygon(3, 3, {{0, 0}, {4, 0}, {0, 4}}))
+    "#);
+}
@@ -0,0 +1,145 @@
+use engine::GameState;
+
+// Scenarios are written in Lua and assert from inside init():
+// a failed assert surfaces as an Err from GameState::new.
+fn run_lua(script: &str) {
+    if let Err(e) = GameState::new(script, None) {
+        panic!("Lua assertion failed: {}", e);
+    }
+}
+
+#[test]
+fn test_graph_query_api() {
+    run_lua(r#"
+        function init()
+            local g = api.new_graph()
+            g:add_node(1, 0, 0)
+            g:add_node(2, 10, 0)
+            g:add_node(3, 20, 0)
+            g:add_node(4, 99, 99)
+            g:add_edge(1, 2)
+            g:add_edge(2, 3)
+
+            assert(g:node_count() == 4)
+            local x, y = g:get_node(2)
+            assert(x == 10 and y == 0)
+            assert(g:get_node(42) == nil)
+            assert(#g:neighbors(2) == 1 and g:neighbors(2)[1] == 3)
+            assert(g:has_path(1, 3))
+            assert(not g:has_path(3, 1), "edges are directed")
+            assert(not g:has_path(1, 4))
+        end
+    "#);
+}
+
+#[test]
+fn test_debug_draw_graph_emits_commands() {
+    let script = r#"
+        function init()
+            g = api.new_graph()
+            g:add_node(1, 0, 0)
+            g:add_node(2, 10, 0)
+            g:add_edge(1, 2)
+        end
+
+        function draw(session_id)
+            api.debug_draw_graph(g)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let bytes = game.draw("s1").expect("Draw failed");
+
+    // 1 line (1 + 5 * 4 bytes) + 2 node squares (1 + 4 * 4 bytes each)
+    assert_eq!(bytes.len(), 21 + 2 * 17);
+    assert_eq!(bytes[0], 0x04, "Expected OP_DRAW_LINE first");
+}
+
+#[test]
+fn test_missing_nodes_raise_lua_errors() {
+    run_lua(r#"
+        function init()
+            local g = api.new_graph()
+            g:add_node(1, 0, 0)
+            g:add_node(2, 10, 0)
+            g:add_edge(1, 2)
+
+            assert(not pcall(g.add_edge, g, 1, 99), "edge to a missing node must be rejected")
+            assert(not pcall(g.add_edge, g, 99, 1), "edge from a missing node must be rejected")
+            assert(#g:neighbors(1) == 1)
+
+            assert(not pcall(g.find_path, g, 1, 99))
+            assert(not pcall(g.find_path, g, 99, 1))
+            assert(g:find_path(2, 1) == nil, "unreachable goal is nil, not an error")
+            local path = g:find_path(1, 2)
+            assert(#path == 2 and path[1] == 1 and path[2] == 2)
+        end
+    "#);
+}
+
+#[test]
+fn test_find_path_honors_node_costs() {
+    run_lua(r#"
+        function init()
+            -- Two routes from 1 to 4: straight through 2 (short) or around via 3 (longer)
+            local g = api.new_graph()
+            g:add_node(1, 0, 0)
+            g:add_node(2, 10, 0)
+            g:add_node(3, 10, 10)
+            g:add_node(4, 20, 0)
+            for _, e in ipairs({{1, 2}, {2, 4}, {1, 3}, {3, 4}}) do g:add_edge(e[1], e[2]) end
+
+            assert(g:find_path(1, 4)[2] == 2, "shortest route by default")
+            assert(g:get_cost(2) == 1.0)
+
+            g:set_cost(2, 2.5)
+            assert(g:get_cost(2) == 2.5)
+            assert(g:find_path(1, 4)[2] == 3, "mud on 2 should push the path around it")
+
+            g:set_cost(2, 1.0)
+            g:set_cost(3, 0.25)
+            assert(g:find_path(1, 4)[2] == 3, "road on 3 should attract the path")
+
+            assert(not pcall(g.set_cost, g, 2, 0))
+            assert(not pcall(g.set_cost, g, 99, 1.5))
+        end
+    "#);
+}
+
+#[test]
+fn test_nav_grid_costs() {
+    run_lua(r#"
+        function init()
+            local grid = api.new_nav_grid(5, 3)
+            local path = grid:find_path(0, 1, 4, 1)
+            assert(#path == 5, "straight across")
+
+            -- Mud along the middle row: around it is cheaper
+            for x = 1, 3 do grid:set_cost(x, 1, 10) end
+            assert(grid:get_cost(2, 1) == 10 and grid:get_cost(0, 0) == 1)
+            path = grid:find_path(0, 1, 4, 1)
+            assert(path[1].x == 0 and path[1].y == 1 and path[#path].x == 4)
+            for i = 2, #path - 1 do assert(path[i].y ~= 1, "walked through mud") end
+
+            -- A wall with no gap
+            for y = 0, 2 do grid:set_walkable(2, y, false) end
+            assert(grid:find_path(0, 1, 4, 1) == nil)
+            assert(not grid:is_walkable(2, 0) and not grid:is_walkable(9, 9))
+
+            assert(not pcall(grid.set_cost, grid, 0, 0, 0), "cost must be positive")
+            assert(not pcall(grid.set_cost, grid, 5, 0, 2), "outside the grid")
+            assert(not pcall(grid.find_path, grid, 0, 0, 0, 3), "outside the grid")
+        end
+    "#);
+}
+
+#[test]
+fn test_nav_grid_no_corner_cutting() {
+    run_lua(r#"
+        function init()
+            local grid = api.new_nav_grid(2, 2)
+            grid:set_walkable(1, 0, false)
+            grid:set_walkable(0, 1, false)
+            assert(grid:find_path(0, 0, 1, 1) == nil)
+        end
+    "#);
+}
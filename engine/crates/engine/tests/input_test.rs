@@ -0,0 +1,264 @@
+use engine::input::{self, GamepadControl, InputMessage, InputMeta, MouseEvent, TouchPhase, INPUT_GAMEPAD, INPUT_MOUSE, INPUT_SEQUENCED, INPUT_TOUCH};
+use engine::GameState;
+
+fn mouse_message(event: u8, x: f32, y: f32, button: i8) -> Vec<u8> {
+    let mut data = vec![INPUT_MOUSE, event];
+    data.extend_from_slice(&x.to_le_bytes());
+    data.extend_from_slice(&y.to_le_bytes());
+    data.push(button as u8);
+    data
+}
+
+fn touch_message(phase: u8, id: u16, x: f32, y: f32) -> Vec<u8> {
+    let mut data = vec![INPUT_TOUCH, phase];
+    data.extend_from_slice(&id.to_le_bytes());
+    data.extend_from_slice(&x.to_le_bytes());
+    data.extend_from_slice(&y.to_le_bytes());
+    data
+}
+
+fn gamepad_message(pad: u8, kind: u8, index: u8, value: f32) -> Vec<u8> {
+    let mut data = vec![INPUT_GAMEPAD, pad, kind, index];
+    data.extend_from_slice(&value.to_le_bytes());
+    data
+}
+
+fn sequenced(seq: u32, time: f64, message: &[u8]) -> Vec<u8> {
+    let mut data = vec![INPUT_SEQUENCED];
+    data.extend_from_slice(&seq.to_le_bytes());
+    data.extend_from_slice(&time.to_le_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+#[test]
+fn test_decode_input_messages() {
+    // The original 2-byte key format is unchanged
+    assert_eq!(input::decode(&[37, 1]), Some(InputMessage::Key(37, true)));
+    assert_eq!(input::decode(&[37, 0]), Some(InputMessage::Key(37, false)));
+
+    assert_eq!(
+        input::decode(&mouse_message(1, 120.5, 300.0, 2)),
+        Some(InputMessage::Mouse { event: MouseEvent::Down, x: 120.5, y: 300.0, button: 2 })
+    );
+    assert_eq!(
+        input::decode(&mouse_message(3, 0.0, 0.0, -1)),
+        Some(InputMessage::Mouse { event: MouseEvent::Wheel, x: 0.0, y: 0.0, button: -1 })
+    );
+
+    // Unknown kinds and events, truncated messages and NaN coordinates are dropped
+    assert_eq!(input::decode(&[]), None);
+    assert_eq!(input::decode(&[0x7f, 0, 0]), None);
+    assert_eq!(input::decode(&mouse_message(9, 1.0, 1.0, 0)), None);
+    assert_eq!(input::decode(&mouse_message(0, 1.0, 1.0, 0)[..10]), None);
+    assert_eq!(input::decode(&mouse_message(0, f32::NAN, 1.0, 0)), None);
+}
+
+#[test]
+fn test_on_mouse_callback() {
+    let script = r#"
+        clicks = {}
+        function on_mouse(session_id, event, x, y, button)
+            table.insert(clicks, session_id .. ":" .. event .. ":" .. x .. ":" .. y .. ":" .. button)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_mouse("s1", MouseEvent::Down, 10.0, 20.0, 0).unwrap();
+    game.handle_mouse("s1", MouseEvent::Wheel, 10.0, 20.0, -1).unwrap();
+    let result = game.eval("return table.concat(clicks, ',')");
+    assert!(result.contains("s1:down:10"), "{}", result);
+    assert!(result.contains("s1:wheel:10"), "{}", result);
+    assert!(result.contains(":-1"), "{}", result);
+
+    // Scripts without on_mouse ignore pointer input
+    let game = GameState::new("function update() end", None).expect("Failed to init game");
+    game.handle_mouse("s1", MouseEvent::Move, 1.0, 2.0, 0).unwrap();
+}
+
+#[test]
+fn test_decode_touch_messages() {
+    assert_eq!(
+        input::decode(&touch_message(0, 7, 50.0, 60.0)),
+        Some(InputMessage::Touch { phase: TouchPhase::Start, id: 7, x: 50.0, y: 60.0 })
+    );
+    assert_eq!(
+        input::decode(&touch_message(3, 513, 0.0, 0.0)),
+        Some(InputMessage::Touch { phase: TouchPhase::Cancel, id: 513, x: 0.0, y: 0.0 })
+    );
+    assert_eq!(input::decode(&touch_message(4, 1, 0.0, 0.0)), None);
+    assert_eq!(input::decode(&touch_message(0, 1, 0.0, f32::INFINITY)), None);
+    assert_eq!(input::decode(&touch_message(0, 1, 0.0, 0.0)[..11]), None);
+}
+
+#[test]
+fn test_on_touch_callback() {
+    let script = r#"
+        touches = {}
+        function on_touch(session_id, id, phase, x, y)
+            table.insert(touches, session_id .. ":" .. id .. ":" .. phase .. ":" .. x .. ":" .. y)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_touch("s1", 0, TouchPhase::Start, 10.0, 20.0).unwrap();
+    game.handle_touch("s1", 1, TouchPhase::Start, 700.0, 20.0).unwrap();
+    game.handle_touch("s1", 0, TouchPhase::End, 15.0, 25.0).unwrap();
+    eval_touches(&game, "s1:0:start:10.0:20.0,s1:1:start:700.0:20.0,s1:0:end:15.0:25.0");
+}
+
+fn eval_touches(game: &GameState, expected: &str) {
+    let result = game.eval(&format!("assert(table.concat(touches, ',') == '{}', table.concat(touches, ','))", expected));
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_decode_gamepad_messages() {
+    assert_eq!(
+        input::decode(&gamepad_message(0, 1, 2, -0.5)),
+        Some(InputMessage::Gamepad { pad: 0, control: GamepadControl::Axis(2), value: -0.5 })
+    );
+    // Values are clamped to the control's range
+    assert_eq!(
+        input::decode(&gamepad_message(1, 0, 7, 3.0)),
+        Some(InputMessage::Gamepad { pad: 1, control: GamepadControl::Button(7), value: 1.0 })
+    );
+    assert_eq!(input::decode(&gamepad_message(0, 2, 0, 0.0)), None);
+    assert_eq!(input::decode(&gamepad_message(0, 1, 0, f32::NAN)), None);
+
+    assert_eq!(GamepadControl::Button(0).name(), "a");
+    assert_eq!(GamepadControl::Button(6).name(), "lt");
+    assert_eq!(GamepadControl::Axis(1).name(), "left_y");
+    assert_eq!(GamepadControl::Button(20).name(), "button_20");
+    assert_eq!(GamepadControl::Axis(5).name(), "axis_5");
+}
+
+#[test]
+fn test_on_gamepad_callback() {
+    let script = r#"
+        pads = {}
+        function on_gamepad(session_id, control, value, pad)
+            pads[control] = {value, pad}
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.handle_gamepad("s1", 0, GamepadControl::Axis(0), 0.75).unwrap();
+    game.handle_gamepad("s1", 1, GamepadControl::Button(7), 0.5).unwrap();
+    let result = game.eval("assert(pads.left_x[1] == 0.75 and pads.left_x[2] == 0); assert(pads.rt[1] == 0.5 and pads.rt[2] == 1)");
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_input_polling() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    let check = |code: &str| {
+        let result = game.eval(code);
+        assert!(!result.starts_with("Error"), "{}", result);
+    };
+
+    check("assert(not api.is_down('s1', 37) and api.get_axis('s1', 'horizontal') == 0)");
+    game.handle_input("s1", 37, true).unwrap();
+    check("assert(api.is_down('s1', 37) and not api.is_down('s2', 37))");
+    check("assert(api.get_axis('s1', 'horizontal') == -1 and api.get_axis('s1', 'vertical') == 0)");
+
+    // Opposite keys cancel out; the stick adds up, clamped to -1..1
+    game.handle_input("s1", 68, true).unwrap();
+    check("assert(api.get_axis('s1', 'horizontal') == 0)");
+    game.handle_gamepad("s1", 0, GamepadControl::Axis(0), 0.5).unwrap();
+    check("assert(api.get_axis('s1', 'horizontal') == 0.5)");
+    game.handle_input("s1", 37, false).unwrap();
+    check("assert(api.get_axis('s1', 'horizontal') == 1)");
+
+    game.handle_gamepad("s1", 0, GamepadControl::Axis(3), -0.25).unwrap();
+    check("assert(api.get_axis('s1', 'right_y') == -0.25)");
+    game.handle_gamepad("s1", 0, GamepadControl::Button(7), 0.3).unwrap();
+    check("assert(not api.is_down('s1', 'rt'))");
+    game.handle_gamepad("s1", 1, GamepadControl::Button(7), 0.9).unwrap();
+    check("assert(api.is_down('s1', 'rt'))");
+
+    assert!(game.eval("return api.get_axis('s1', 'diagonal')").starts_with("Error"));
+    assert!(game.eval("return api.is_down('s1', 'turbo')").starts_with("Error"));
+
+    game.on_disconnect("s1").unwrap();
+    check("assert(not api.is_down('s1', 68) and api.get_axis('s1', 'horizontal') == 0)");
+}
+
+#[test]
+fn test_decode_sequenced_messages() {
+    let meta = InputMeta { seq: 7, time: 1_700_000_000_123.5 };
+    assert_eq!(input::decode_sequenced(&sequenced(7, meta.time, &[32, 1])), Some((InputMessage::Key(32, true), Some(meta))));
+    assert_eq!(
+        input::decode_sequenced(&sequenced(7, meta.time, &mouse_message(0, 10.0, 20.0, 0))),
+        Some((InputMessage::Mouse { event: MouseEvent::Move, x: 10.0, y: 20.0, button: 0 }, Some(meta)))
+    );
+    // Unwrapped messages still decode; a 2-byte message is a key even with code 0x10 (Shift)
+    assert_eq!(input::decode_sequenced(&[32, 0]), Some((InputMessage::Key(32, false), None)));
+    assert_eq!(input::decode_sequenced(&[INPUT_SEQUENCED, 1]), Some((InputMessage::Key(16, true), None)));
+
+    assert_eq!(input::decode_sequenced(&sequenced(7, f64::NAN, &[32, 1])), None);
+    assert_eq!(input::decode_sequenced(&sequenced(7, 0.0, &[])), None);
+    assert_eq!(input::decode_sequenced(&sequenced(7, 0.0, &[32, 1])[..10]), None);
+}
+
+#[test]
+fn test_input_sequence_numbers() {
+    let script = r#"
+        inputs = {}
+        function on_input(session_id, key_code, is_down, seq, time)
+            table.insert(inputs, {key_code, is_down, seq, time})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let check = |code: &str| {
+        let result = game.eval(code);
+        assert!(!result.starts_with("Error"), "{}", result);
+    };
+    let key = |code: u8, down: bool, seq: u32| {
+        game.handle_message("s1", InputMessage::Key(code, down), Some(InputMeta { seq, time: 1000.0 + seq as f64 })).unwrap();
+    };
+
+    check("assert(api.input_stats('s1') == nil)");
+    key(32, true, 10);
+    check("assert(inputs[1][3] == 10 and inputs[1][4] == 1010)");
+
+    // 11 and 12 go missing, then 12 shows up late: it's reported, not applied
+    key(32, false, 13);
+    check("assert(not api.is_down('s1', 32))");
+    key(32, true, 12);
+    check("assert(inputs[3][3] == 12 and not api.is_down('s1', 32))");
+    check([
+        "local s = api.input_stats('s1')",
+        "assert(s.last_seq == 13 and s.received == 3 and s.dropped == 1 and s.reordered == 1)",
+    ].join("\n").as_str());
+
+    // Inputs without a sequence number (older clients) don't touch the stats
+    game.handle_input("s1", 40, true).unwrap();
+    check("assert(inputs[4][3] == nil and api.is_down('s1', 40) and api.input_stats('s1').received == 3)");
+
+    game.on_disconnect("s1").unwrap();
+    check("assert(api.input_stats('s1') == nil)");
+}
+
+#[test]
+fn test_input_config_is_sent_when_it_changes() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    assert!(game.draw("s1").unwrap().is_empty(), "defaults are not sent");
+
+    game.eval(r#"api.set_input_config("all", {key_repeat = true, joystick_radius = 80})"#);
+    // OP_INPUT_CONFIG: key_repeat u8, dead_zone, joystick_radius
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!((bytes[0], bytes[1], bytes.len()), (0x3F, 1, 10));
+    let floats: Vec<f32> = bytes[2..10].chunks(4).map(|c| f32::from_le_bytes(c.try_into().unwrap())).collect();
+    assert_eq!(floats, vec![0.15, 80.0], "dead zone keeps its default");
+    assert!(game.draw("s1").unwrap().is_empty(), "unchanged");
+
+    // A player's own config, then back to the shared one
+    game.eval(r#"api.set_input_config("s2", {dead_zone = 0.3})"#);
+    assert_eq!(&game.draw("s2").unwrap()[1..6], &[0, 0x9A, 0x99, 0x99, 0x3E], "key_repeat off, dead zone 0.3");
+    game.eval(r#"api.set_input_config("s2", nil)"#);
+    assert_eq!(game.draw("s2").unwrap()[1], 1);
+
+    assert!(game.eval(r#"api.set_input_config("all", {dead_zone = 1})"#).starts_with("Error"));
+    assert!(game.eval(r#"api.set_input_config("all", {joystick_radius = 0})"#).starts_with("Error"));
+}
@@ -0,0 +1,124 @@
+use bytes::Buf;
+use engine::compat::{CAPS_ALL, CAP_LIGHTS};
+use engine::GameState;
+use std::io::Cursor;
+
+const OP_LIGHTS: u8 = 0x3B;
+
+struct Light {
+    x: f32,
+    radius: f32,
+    rgba: [u8; 4],
+    shadow: Vec<(f32, f32)>,
+}
+
+// Ambient color and lights of a frame holding only OP_LIGHTS
+fn lights(bytes: &[u8]) -> ([u8; 3], Vec<Light>) {
+    let mut cursor = Cursor::new(bytes);
+    assert_eq!(cursor.get_u8(), OP_LIGHTS);
+    let ambient = [cursor.get_u8(), cursor.get_u8(), cursor.get_u8()];
+    let count = cursor.get_u16_le();
+    let mut out = Vec::new();
+    for _ in 0..count {
+        let x = cursor.get_f32_le();
+        cursor.get_f32_le(); // y
+        let radius = cursor.get_f32_le();
+        let rgba = [cursor.get_u8(), cursor.get_u8(), cursor.get_u8(), cursor.get_u8()];
+        let points = cursor.get_u16_le();
+        let shadow = (0..points).map(|_| (cursor.get_f32_le(), cursor.get_f32_le())).collect();
+        out.push(Light { x, radius, rgba, shadow });
+    }
+    assert!(!cursor.has_remaining());
+    (ambient, out)
+}
+
+#[test]
+fn test_lights_are_sent_in_view() {
+    let script = r#"
+        api.set_ambient_light(10, 20, 30)
+        lamp = api.add_light(100, 100, 50, {255, 200, 100, 200})
+        far = api.add_light(5000, 100, 50)
+        function draw(session_id)
+            api.draw_lights()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let (ambient, all) = lights(&game.draw("s1").unwrap());
+    assert_eq!(ambient, [10, 20, 30]);
+    assert_eq!(all.len(), 2);
+    assert_eq!((all[0].radius, all[0].rgba), (50.0, [255, 200, 100, 200]));
+    assert!(all[0].shadow.is_empty(), "no occluders: the whole circle is lit");
+
+    game.eval("api.set_viewport('s1', 0, 0, 800, 600)");
+    let (_, culled) = lights(&game.draw("s1").unwrap());
+    assert_eq!(culled.len(), 1);
+
+    game.eval("api.move_light(lamp, 300, 100)");
+    let (_, moved) = lights(&game.draw("s1").unwrap());
+    assert_eq!(moved[0].x, 300.0);
+
+    let result = game.eval("assert(api.remove_light(lamp)); assert(not api.remove_light(lamp))");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(lights(&game.draw("s1").unwrap()).1.is_empty());
+
+    assert!(game.eval("api.add_light(0, 0, 0)").starts_with("Error"));
+}
+
+#[test]
+fn test_occluders_cast_shadows() {
+    let script = r#"
+        api.add_light(0, 0, 100)
+        api.add_light(500, 500, 100)
+        -- A wall 40 to the right of the first light
+        api.set_occluders({{40, -20, 60, -20, 60, 20, 40, 20}})
+        function draw(session_id)
+            api.draw_lights()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let (_, all) = lights(&game.draw("s1").unwrap());
+    assert!(all[1].shadow.is_empty(), "out of range of the wall");
+
+    let shadow = &all[0].shadow;
+    assert!(shadow.len() > 32);
+    assert!(shadow.iter().all(|&(x, y)| (x * x + y * y).sqrt() <= 100.0 + 1e-3));
+    // Straight right stops at the wall, straight left reaches the edge
+    let reach = |dx: f32| shadow.iter().filter(|p| p.1.abs() < 1e-3 && p.0 * dx > 0.0).map(|p| p.0.abs()).fold(0.0f32, f32::max);
+    assert!((reach(1.0) - 40.0).abs() < 1e-3, "{}", reach(1.0));
+    assert!((reach(-1.0) - 100.0).abs() < 1e-3, "{}", reach(-1.0));
+
+    game.eval("api.set_occluders({})");
+    assert!(lights(&game.draw("s1").unwrap()).1[0].shadow.is_empty());
+}
+
+#[test]
+fn test_flicker_only_dims() {
+    let script = r#"
+        api.add_light(0, 0, 100, {255, 255, 255, 200}, 1)
+        function draw(session_id)
+            api.draw_lights()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let mut seen = Vec::new();
+    for _ in 0..30 {
+        game.update(0.05).unwrap();
+        seen.push(lights(&game.draw("s1").unwrap()).1[0].rgba[3]);
+    }
+    assert!(seen.iter().all(|&a| a <= 200));
+    assert!(seen.iter().any(|&a| a != seen[0]), "{:?}", seen);
+}
+
+#[test]
+fn test_clients_without_lighting_draw_unlit() {
+    let script = r#"
+        api.add_light(0, 0, 100)
+        api.set_occluders({{40, -20, 60, -20, 60, 20, 40, 20}})
+        function draw(session_id)
+            api.draw_lights()
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_LIGHTS);
+    assert!(game.draw("old").unwrap().is_empty());
+}
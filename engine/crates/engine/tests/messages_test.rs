@@ -0,0 +1,51 @@
+use engine::GameState;
+use serde_json::json;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    received = {}
+    function on_message(session_id, msg)
+        table.insert(received, session_id .. ":" .. msg.type .. ":" .. #msg.items)
+    end
+"#;
+
+#[test]
+fn test_messages_queue_per_session_in_order() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    eval_ok(&game, r#"api.send_message("s1", {type = "score", value = 10})"#);
+    eval_ok(&game, r#"api.send_message("s2", {type = "chat", text = "hi"})"#);
+    eval_ok(&game, r#"api.send_message("s1", {type = "score", value = 12})"#);
+
+    assert_eq!(game.take_messages("s1"), vec![
+        json!({"type": "score", "value": 10}),
+        json!({"type": "score", "value": 12}),
+    ]);
+    assert!(game.take_messages("s1").is_empty());
+    assert_eq!(game.take_messages("s2"), vec![json!({"type": "chat", "text": "hi"})]);
+}
+
+#[test]
+fn test_message_limits() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    assert!(game.eval(r#"api.send_message("s1", {text = string.rep("x", 70000)})"#).starts_with("Error"));
+    eval_ok(&game, r#"
+        for i = 1, 256 do assert(api.send_message("s1", {n = i})) end
+        assert(api.send_message("s1", {n = 257}) == false)
+    "#);
+    game.on_disconnect("s1").unwrap();
+    assert!(game.take_messages("s1").is_empty());
+}
+
+#[test]
+fn test_on_message() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.handle_script_message("s1", &json!({"type": "buy", "items": ["sword", "potion"]})).unwrap();
+    eval_ok(&game, r#"assert(received[1] == "s1:buy:2", received[1])"#);
+
+    let huge = json!({"text": "x".repeat(70000)});
+    assert!(matches!(game.handle_script_message("s1", &huge), Err(engine::EngineError::Protocol(_))));
+}
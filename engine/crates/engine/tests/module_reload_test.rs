@@ -0,0 +1,56 @@
+use engine::GameState;
+use std::fs;
+use std::path::PathBuf;
+
+// A game directory with main.lua and the given modules
+fn game_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cleoselene-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        fs::write(dir.join(file), source).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_changed_module_is_patched_in_place() {
+    let dir = game_dir("patch", &[
+        ("util.lua", "local M = { hits = 0 }\nfunction M.damage() return 1 end\nreturn M"),
+        ("weapons.lua", "local util = require('util')\nlocal W = {}\nW.base = util.damage\nreturn W"),
+    ]);
+    let script = "util = require('util')\nweapons = require('weapons')\nutil.hits = 5";
+    let game = GameState::new(script, Some(&dir.join("main.lua"))).expect("Failed to init game");
+
+    fs::write(dir.join("util.lua"), "local M = { hits = 0 }\nfunction M.damage() return 2 end\nreturn M").unwrap();
+    let patched = game.reload_module(&dir.join("util.lua")).unwrap();
+    // The module first, then the module requiring it
+    assert_eq!(patched, vec!["util".to_string(), "weapons".to_string()]);
+
+    // Same tables, new functions, data kept
+    let result = game.eval("assert(util.damage() == 2 and util.hits == 5 and weapons.base() == 2 and require('util') == util)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_unsafe_changes_need_full_reload() {
+    let dir = game_dir("unsafe", &[
+        ("util.lua", "return { damage = function() return 1 end }"),
+        ("counter.lua", "return function() return 1 end"),
+    ]);
+    let script = "util = require('util')\ncounter = require('counter')";
+    let main = dir.join("main.lua");
+    fs::write(&main, script).unwrap();
+    let game = GameState::new(script, Some(&main)).expect("Failed to init game");
+
+    assert!(game.reload_module(&main).is_err(), "the main script is not a module");
+    assert!(game.reload_module(&dir.join("counter.lua")).is_err(), "not a table");
+
+    // A broken module leaves the running one untouched
+    fs::write(dir.join("util.lua"), "return { damage = function() return 2 end").unwrap();
+    assert!(game.reload_module(&dir.join("util.lua")).is_err());
+    let result = game.eval("assert(util.damage() == 1)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,42 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_noise_is_deterministic_and_bounded() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    eval_ok(&game, r#"
+        local opts = {octaves = 4, frequency = 0.13, seed = 42}
+        local other = {octaves = 4, frequency = 0.13, seed = 43}
+        local differs = false
+        for i = 0, 200 do
+            local x, y = i * 0.7, i * 1.3
+            local v = api.noise2d(x, y, opts)
+            assert(v >= -1 and v <= 1)
+            assert(v == api.noise2d(x, y, opts), "same seed, same value")
+            differs = differs or v ~= api.noise2d(x, y, other)
+            local w = api.noise1d(x, opts)
+            assert(w >= -1 and w <= 1 and w == api.noise1d(x, opts))
+        end
+        assert(differs, "seeds change the noise")
+    "#);
+
+    // Smooth: close inputs, close values
+    eval_ok(&game, "assert(math.abs(api.noise2d(3.5, 7.25) - api.noise2d(3.501, 7.25)) < 0.01)");
+}
+
+#[test]
+fn test_noise_map_matches_noise2d() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    eval_ok(&game, r#"
+        local opts = {octaves = 3, frequency = 0.1, seed = 9}
+        local rows = api.noise_map(8, 5, opts)
+        assert(#rows == 5 and #rows[1] == 8)
+        assert(rows[3][6] == api.noise2d(5, 2, opts))
+    "#);
+    assert!(game.eval("api.noise2d(0, 0, {octaves = 0})").starts_with("Error"));
+    assert!(game.eval("api.noise_map(4096, 4096)").starts_with("Error"));
+}
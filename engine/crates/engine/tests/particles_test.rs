@@ -0,0 +1,42 @@
+use engine::GameState;
+
+#[test]
+fn test_emitter_spawns_ages_and_draws_one_batch() {
+    let script = r#"
+        sparks = api.new_emitter{ rate = 10, lifetime = 1, speed = 0, size = 2, color = {255, 0, 0} }
+        function draw(session_id) sparks:draw() end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.update(0.5).unwrap();
+    let result = game.eval("assert(sparks:count() == 5, sparks:count())");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    let bytes = game.draw("s1").unwrap();
+    assert_eq!(bytes[0], 0x1B, "OP_DRAW_PARTICLES");
+    assert_eq!(bytes[1], 0, "squares by default");
+    assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]), 5);
+    assert_eq!(bytes.len(), 4 + 5 * 14);
+    assert_eq!(u16::from_le_bytes([bytes[12], bytes[13]]), 32, "size in 1/16 px");
+    assert_eq!(&bytes[14..17], &[255, 0, 0]);
+
+    // Particles die after their lifetime; with no rate, none replace them
+    game.eval("sparks:set_rate(0)");
+    game.update(1.0).unwrap();
+    let result = game.eval("assert(sparks:count() == 0, sparks:count())");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(game.draw("s1").unwrap().is_empty(), "empty emitters draw nothing");
+}
+
+#[test]
+fn test_emitter_burst_respects_max() {
+    let script = r#"
+        e = api.new_emitter{ rate = 0, max = 50 }
+        e:burst(80)
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    let result = game.eval("assert(e:count() == 50, e:count())");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    assert!(game.eval("api.new_emitter{ shape = 'star' }").starts_with("Error"));
+}
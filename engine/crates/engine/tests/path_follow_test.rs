@@ -0,0 +1,65 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_follow_path_reaches_goal_and_fires_callback() {
+    let script = r#"
+        function init()
+            db = api.new_spatial_db(64)
+            phys = api.new_physics_world(db)
+            id = db:add_circle(0, 0, 2, "unit")
+            phys:add_body(id, {})
+            done = nil
+            api.follow_path(phys, id, {{x = 50, y = 0}, {50, 50}}, 100, {
+                arrive_radius = 2,
+                on_complete = function(who) done = who end,
+            })
+        end
+
+        function update(dt)
+            phys:step(dt)
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.update(0.1).unwrap();
+    eval_ok(&game, "local vx, vy = phys:get_velocity(id); assert(vx == 100 and vy == 0, vx .. ',' .. vy)");
+    eval_ok(&game, "assert(api.is_following(id))");
+
+    for _ in 0..30 {
+        game.update(0.05).unwrap();
+    }
+    eval_ok(&game, "assert(done == id, tostring(done))");
+    eval_ok(&game, "assert(not api.is_following(id))");
+    eval_ok(&game, "local x, y = db:get_position(id); assert(math.abs(x - 50) <= 2 and math.abs(y - 50) <= 2, x .. ',' .. y)");
+    eval_ok(&game, "local vx, vy = phys:get_velocity(id); assert(vx == 0 and vy == 0)");
+}
+
+#[test]
+fn test_follow_path_with_graph_nodes_and_stop() {
+    let script = r#"
+        function init()
+            nav = api.new_graph()
+            nav:add_node(1, 0, 0)
+            nav:add_node(2, 0, 100)
+            nav:add_edge(1, 2)
+
+            db = api.new_spatial_db(64)
+            phys = api.new_physics_world(db)
+            id = db:add_circle(0, 0, 2, "unit")
+            phys:add_body(id, {})
+
+            api.follow_path(phys, id, nav:find_path(1, 2), 10, {graph = nav})
+            assert(not pcall(api.follow_path, phys, id, {1, 2}, 10), "node ids need opts.graph")
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.update(0.1).unwrap();
+    eval_ok(&game, "local vx, vy = phys:get_velocity(id); assert(vx == 0 and vy == 10)");
+    eval_ok(&game, "assert(api.stop_path(id) and not api.is_following(id))");
+}
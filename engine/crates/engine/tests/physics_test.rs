@@ -0,0 +1,123 @@
+use engine::GameState;
+
+// Scenarios are written in Lua and assert from inside init():
+// a failed assert surfaces as an Err from GameState::new.
+fn run_lua(script: &str) {
+    if let Err(e) = GameState::new(script, None) {
+        panic!("Lua assertion failed: {}", e);
+    }
+}
+
+#[test]
+fn test_contact_phases() {
+    run_lua(r#"
+        local function phases(events)
+            local out = {}
+            for _, ev in ipairs(events) do table.insert(out, ev.phase) end
+            return table.concat(out, ",")
+        end
+
+        function init()
+            local db = api.new_spatial_db(50)
+            local phys = api.new_physics_world(db)
+            local a = db:add_circle(0, 0, 10, "ball")
+            local b = db:add_circle(15, 0, 10, "ball")
+            phys:add_body(a, { mass = 0 })
+            phys:add_body(b, { mass = 1.0, restitution = 0 })
+
+            phys:step(0.01)
+            assert(phases(phys:get_contact_events()) == "begin", "expected begin")
+
+            db:update(b, 15, 0)
+            phys:step(0.01)
+            assert(phases(phys:get_contact_events()) == "stay", "expected stay")
+
+            db:update(b, 200, 0)
+            phys:step(0.01)
+            assert(phases(phys:get_contact_events()) == "end", "expected end")
+        end
+    "#);
+}
+
+#[test]
+fn test_contact_events_bounded_when_never_read() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local phys = api.new_physics_world(db)
+            local a = db:add_circle(0, 0, 10, "ball")
+            local b = db:add_circle(15, 0, 10, "ball")
+            phys:add_body(a, { mass = 0 })
+            phys:add_body(b, { mass = 1.0, restitution = 0 })
+
+            -- A resting pair: one "stay" per step, never read
+            for _ = 1, 10000 do
+                db:update(b, 15, 0)
+                phys:step(0.01)
+            end
+            local events = phys:get_contact_events()
+            assert(#events == 4096, "expected the latest 4096 events, got " .. #events)
+            assert(events[#events].phase == "stay")
+            assert(#phys:get_contact_events() == 0)
+        end
+    "#);
+}
+
+#[test]
+fn test_sensor_enter_exit() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local phys = api.new_physics_world(db)
+            local zone = db:add_circle(0, 0, 20, "zone")
+            local ball = db:add_circle(10, 0, 5, "ball")
+            phys:add_body(zone, { mass = 0, sensor = true })
+            phys:add_body(ball, { mass = 1.0 })
+
+            phys:step(0.01)
+            local ev = phys:get_sensor_events()
+            assert(#ev == 1 and ev[1].phase == "enter" and ev[1].sensor == zone and ev[1].other == ball)
+            assert(#phys:get_contact_events() == 0, "sensor overlap must not be a contact")
+            local x = db:get_position(ball)
+            assert(x == 10, "sensor pushed the ball")
+
+            db:update(ball, 100, 0)
+            phys:step(0.01)
+            ev = phys:get_sensor_events()
+            assert(#ev == 1 and ev[1].phase == "exit")
+        end
+    "#);
+}
+
+#[test]
+fn test_bounds_events_bounded_when_never_read() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local phys = api.new_physics_world(db)
+            phys:set_bounds(0, 0, 100, 100, "clamp")
+            local ball = db:add_circle(50, 50, 5, "ball")
+            phys:add_body(ball, { mass = 1.0 })
+
+            for _ = 1, 10000 do
+                db:update(ball, 150, 50)
+                phys:step(0.01)
+            end
+            local events = phys:get_bounds_events()
+            assert(#events == 4096, "expected the latest 4096 events, got " .. #events)
+            assert(events[#events].id == ball and events[#events].action == "clamp")
+        end
+    "#);
+}
+
+#[test]
+fn test_invalid_bounds() {
+    run_lua(r#"
+        function init()
+            local phys = api.new_physics_world(api.new_spatial_db(50))
+            assert(not pcall(phys.set_bounds, phys, 100, 0, 0, 100), "x1 > x2")
+            assert(not pcall(phys.set_bounds, phys, 0, 0, 100, 0/0), "NaN")
+            phys:set_bounds(0, 0, 100, 100)
+        end
+    "#);
+}
@@ -0,0 +1,60 @@
+use engine::GameState;
+
+const OP_ACTION_KEYS: u8 = 0x3E;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    events = {}
+    players = {}
+    api.bind_action("jump", {keys = {32}})
+    function on_connect(id)
+        players[id] = {score = 0}
+        table.insert(events, "connect")
+    end
+    function on_disconnect(id)
+        players[id] = nil
+        table.insert(events, "disconnect")
+    end
+    function on_reconnect(id)
+        table.insert(events, "reconnect:" .. players[id].score)
+    end
+    function on_action(id, action, is_down)
+        table.insert(events, action .. ":" .. tostring(is_down))
+    end
+"#;
+
+#[test]
+fn test_reconnect_keeps_the_player_and_releases_held_input() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+    eval_ok(&game, "players.s1.score = 7");
+    game.handle_input("s1", 32, true).unwrap();
+    game.draw("s1").unwrap();
+
+    // The connection dropped with space held; a new client takes the session over
+    let bytes = game.on_reconnect("s1").unwrap();
+    assert_eq!(bytes[0], OP_ACTION_KEYS, "bindings are sent again");
+    eval_ok(&game, r#"assert(table.concat(events, ",") == "connect,jump:true,jump:false,reconnect:7", table.concat(events, ","))"#);
+    eval_ok(&game, "assert(not api.is_down('s1', 32) and not api.is_action_down('s1', 'jump'))");
+
+    // Pressed again from the new client
+    game.handle_input("s1", 32, true).unwrap();
+    eval_ok(&game, "assert(api.is_action_down('s1', 'jump'))");
+}
+
+#[test]
+fn test_commands_addressed_while_away_follow_the_reconnect() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+    game.draw("s1").unwrap();
+
+    // Not drawn while away: the prompt waits for the new client
+    eval_ok(&game, "api.request_text_input('s1', {placeholder = 'Name?'})");
+    let bytes = game.on_reconnect("s1").unwrap();
+    assert!(bytes.windows(5).any(|w| w == b"Name?"));
+    assert!(!game.draw("s1").unwrap().windows(5).any(|w| w == b"Name?"));
+}
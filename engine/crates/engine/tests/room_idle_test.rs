@@ -0,0 +1,19 @@
+use engine::GameState;
+
+#[test]
+fn test_on_room_idle_hook() {
+    let script = r#"
+        resets = 0
+        function on_room_idle() resets = resets + 1 end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.on_room_idle().unwrap();
+    let result = game.eval("assert(resets == 1, resets)");
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_on_room_idle_is_optional() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    assert!(game.on_room_idle().is_ok());
+}
@@ -0,0 +1,29 @@
+use engine::GameState;
+use serde_json::json;
+
+#[test]
+fn test_on_room_message_hook() {
+    let script = r#"
+        received = {}
+        function on_room_message(from, event, payload)
+            table.insert(received, {from = from, event = event, payload = payload})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.on_room_message("lobby", "announce", &json!({"text": "Tournament at 8", "round": 2})).unwrap();
+    game.on_room_message("match-1", "ping", &json!([1, 2, 3])).unwrap();
+    let result = game.eval(r#"
+        assert(#received == 2)
+        local a = received[1]
+        assert(a.from == "lobby" and a.event == "announce")
+        assert(a.payload.text == "Tournament at 8" and a.payload.round == 2)
+        assert(received[2].event == "ping" and received[2].payload[3] == 3)
+    "#);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_on_room_message_is_optional() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    assert!(game.on_room_message("lobby", "announce", &json!(null)).is_ok());
+}
@@ -0,0 +1,154 @@
+use engine::compat::{CAPS_ALL, CAP_SCENE_UPDATES};
+use engine::GameState;
+
+// (opcode, node id, field mask) of every node command in a frame; OP_NODE_SYNC has its count
+// as id, OP_SCENE_UPDATE one entry per moved node
+fn parse_scene_frame(bytes: &[u8]) -> Vec<(u8, u32, u8)> {
+    let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let mut ops = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let opcode = bytes[offset];
+        match opcode {
+            0x21 | 0x22 => {
+                let (id, mask) = (u32_at(offset + 1), bytes[offset + 5]);
+                ops.push((opcode, id, mask));
+                offset += 6;
+                if mask & 0x01 != 0 { offset += 16; }
+                if mask & 0x02 != 0 {
+                    let kind = bytes[offset];
+                    offset += 1 + match kind {
+                        1 => 8,
+                        2 => 4,
+                        3 => 2 + u16::from_le_bytes([bytes[offset + 1], bytes[offset + 2]]) as usize + 8,
+                        4 => 2 + u16::from_le_bytes([bytes[offset + 1], bytes[offset + 2]]) as usize,
+                        _ => 0,
+                    };
+                }
+                if mask & 0x04 != 0 { offset += 4; }
+                if mask & 0x08 != 0 { offset += 1; }
+                if mask & 0x10 != 0 { offset += 1; }
+                if mask & 0x20 != 0 { offset += 4; }
+            }
+            0x23 => {
+                ops.push((opcode, u32_at(offset + 1), 0));
+                offset += 5;
+            }
+            0x24 => {
+                let count = u32_at(offset + 1);
+                ops.push((opcode, count, 0));
+                offset += 5 + count as usize * 4;
+            }
+            0x29 => {
+                let count = u16::from_le_bytes([bytes[offset + 1], bytes[offset + 2]]) as usize;
+                for i in 0..count {
+                    ops.push((opcode, u32_at(offset + 3 + i * 16), 0));
+                }
+                offset += 3 + count * 16;
+            }
+            0x25 => {
+                ops.push((opcode, 0, 0));
+                offset += 1;
+            }
+            other => panic!("unexpected opcode 0x{:02X}", other),
+        }
+    }
+    ops
+}
+
+#[test]
+fn test_scene_sends_only_changes() {
+    let script = r#"
+        a = api.new_node{ x = 10, y = 20, shape = "rect", w = 8, h = 8, color = {255, 0, 0} }
+        b = api.new_node{ shape = "circle", r = 4, parent = a }
+        function draw(session_id) api.draw_scene() end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    // First frame: live ids, both nodes in full, then the draw
+    let ops = parse_scene_frame(&game.draw("s1").unwrap());
+    assert_eq!(ops, vec![(0x24, 2, 0), (0x21, 1, 0x3F), (0x21, 2, 0x3F), (0x25, 0, 0)]);
+
+    // Only the moved node's position (plus the round-robin refresh of the other one)
+    game.eval("a:set{ x = 15 }");
+    let ops = parse_scene_frame(&game.draw("s1").unwrap());
+    assert!(ops.contains(&(0x29, 1, 0)), "{:?}", ops);
+    assert_eq!(ops.len(), 3);
+
+    // Teleports are flagged so the client doesn't interpolate
+    game.eval("a:set{ x = 0, snap = true }");
+    let ops = parse_scene_frame(&game.draw("s1").unwrap());
+    assert!(ops.contains(&(0x22, 1, 0x41)), "{:?}", ops);
+
+    // Destroying a parent destroys its children
+    game.eval("a:destroy()");
+    let ops = parse_scene_frame(&game.draw("s1").unwrap());
+    assert_eq!(ops, vec![(0x23, 1, 0), (0x23, 2, 0), (0x25, 0, 0)]);
+}
+
+#[test]
+fn test_scene_node_errors() {
+    let script = r#"
+        a = api.new_node{ shape = "text", text = "hi" }
+        b = api.new_node{ parent = a }
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    assert!(game.eval("a:set{ parent = b }").starts_with("Error"), "cycles are rejected");
+    assert!(game.eval("api.new_node{ shape = 'hexagon' }").starts_with("Error"));
+    assert!(game.eval("api.new_node{ shape = 'sprite' }").starts_with("Error"), "sprites need an image");
+    let result = game.eval("a:set{ x = 3, y = 4 }; local x, y = a:position(); assert(x == 3 and y == 4)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    game.eval("a:destroy()");
+    assert!(game.eval("a:set{ x = 1 }").starts_with("Error"), "destroyed");
+}
+
+#[test]
+fn test_scene_moves_batched() {
+    let script = r#"
+        for i = 1, 3 do api.scene_add(i, "circle", { r = 2 }) end
+        function draw(session_id) api.draw_scene() end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_SCENE_UPDATES);
+    game.draw("new").unwrap();
+    game.draw("old").unwrap();
+
+    game.eval("for i = 1, 3 do api.scene_update(i, { x = i * 10, rotation = 1 }) end");
+    let bytes = game.draw("new").unwrap();
+    let ops = parse_scene_frame(&bytes);
+    assert_eq!(ops.iter().filter(|op| op.0 == 0x29).count(), 3, "{:?}", ops);
+    // One batch: opcode, count, then id and x, y, rotation per node
+    assert_eq!((bytes[0], u16::from_le_bytes([bytes[1], bytes[2]])), (0x29, 3));
+    let floats: Vec<f32> = bytes[7..19].chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect();
+    assert_eq!(floats, vec![10.0, 0.0, 1.0]);
+
+    // Clients without the batch get node updates; scale changes always do
+    let ops = parse_scene_frame(&game.draw("old").unwrap());
+    assert_eq!(ops.iter().filter(|op| *op == &(0x22, 1, 0x01)).count(), 1, "{:?}", ops);
+    game.eval("api.scene_update(1, { scale = 2 })");
+    assert!(parse_scene_frame(&game.draw("new").unwrap()).contains(&(0x22, 1, 0x01)));
+}
+
+#[test]
+fn test_scene_entities_by_id() {
+    let script = r#"
+        ship = api.scene_add("ship", "rect", { w = 8, h = 8, x = 5 })
+        api.scene_add(7, "text", { text = "seven" })
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let result = game.eval("api.scene_update('ship', { y = 9 }); local x, y = ship:position(); assert(x == 5 and y == 9)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(game.eval("api.scene_add('ship', 'circle')").starts_with("Error"), "ids are unique");
+    assert!(game.eval("api.scene_add('blob', 'hexagon')").starts_with("Error"));
+    assert!(game.eval("api.scene_update('blob', { x = 1 })").starts_with("Error"), "failed adds leave nothing");
+
+    // Numbers and their string form name the same entity; removed ids can be reused
+    assert!(!game.eval("api.scene_update('7', { x = 1 })").starts_with("Error"));
+    game.eval("api.scene_remove(7); api.scene_remove(7)");
+    assert!(game.eval("api.scene_update(7, { x = 1 })").starts_with("Error"));
+    assert!(!game.eval("api.scene_add(7, 'circle', { r = 1 })").starts_with("Error"));
+    game.eval("ship:destroy()");
+    assert!(game.eval("api.scene_update('ship', { x = 1 })").starts_with("Error"));
+}
@@ -0,0 +1,104 @@
+use bytes::Buf;
+use engine::compat::{CAPS_ALL, CAP_SETTINGS};
+use engine::GameState;
+use serde_json::json;
+
+const OP_SETTINGS: u8 = 0x3D;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+fn read_str(cursor: &mut &[u8]) -> String {
+    let len = cursor.get_u16_le() as usize;
+    let text = String::from_utf8(cursor[..len].to_vec()).unwrap();
+    cursor.advance(len);
+    text
+}
+
+const SCRIPT: &str = r#"
+    changes = {}
+    api.register_setting("music_volume", {type = "slider", label = "Music", default = 0.8})
+    api.register_setting("sensitivity", {min = 1, max = 10, step = 1, default = 5})
+    api.register_setting("show_fps", {type = "toggle"})
+    api.register_setting("difficulty", {type = "choice", options = {"easy", "normal", "hard"}, default = "normal"})
+    function on_setting_changed(session_id, key, value)
+        table.insert(changes, session_id .. ":" .. key .. "=" .. tostring(value))
+    end
+"#;
+
+#[test]
+fn test_settings_menu_sent_on_connect() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    let bytes = game.on_connect("s1").unwrap();
+    let mut cursor = &bytes[..];
+
+    assert_eq!(cursor.get_u8(), OP_SETTINGS);
+    assert_eq!(cursor.get_u8(), 4);
+
+    assert_eq!(read_str(&mut cursor), "music_volume");
+    assert_eq!(read_str(&mut cursor), "Music");
+    assert_eq!(cursor.get_u8(), 0, "slider");
+    assert_eq!((cursor.get_f32_le(), cursor.get_f32_le(), cursor.get_f32_le(), cursor.get_f32_le()), (0.0, 1.0, 0.0, 0.8));
+
+    assert_eq!(read_str(&mut cursor), "sensitivity");
+    assert_eq!(read_str(&mut cursor), "sensitivity", "label defaults to the key");
+    assert_eq!(cursor.get_u8(), 0);
+    assert_eq!((cursor.get_f32_le(), cursor.get_f32_le(), cursor.get_f32_le(), cursor.get_f32_le()), (1.0, 10.0, 1.0, 5.0));
+
+    assert_eq!(read_str(&mut cursor), "show_fps");
+    read_str(&mut cursor);
+    assert_eq!(cursor.get_u8(), 1, "toggle");
+    assert_eq!(cursor.get_u8(), 0);
+
+    assert_eq!(read_str(&mut cursor), "difficulty");
+    read_str(&mut cursor);
+    assert_eq!(cursor.get_u8(), 2, "choice");
+    assert_eq!(cursor.get_u8(), 3);
+    assert_eq!([read_str(&mut cursor), read_str(&mut cursor), read_str(&mut cursor)], ["easy", "normal", "hard"]);
+    assert_eq!(cursor.get_u8(), 1, "default index");
+    assert!(cursor.is_empty());
+
+    // Clients without the settings menu don't get it
+    game.set_capabilities("old", CAPS_ALL & !CAP_SETTINGS);
+    assert!(game.on_connect("old").unwrap().is_empty());
+}
+
+#[test]
+fn test_setting_changes_are_validated() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+
+    eval_ok(&game, "assert(api.get_setting('s1', 'music_volume') == 0.8)");
+    game.handle_setting("s1", "music_volume", &json!(0.5)).unwrap();
+    game.handle_setting("s1", "sensitivity", &json!(42.4)).unwrap(); // Clamped
+    game.handle_setting("s1", "show_fps", &json!(true)).unwrap();
+    game.handle_setting("s1", "difficulty", &json!("hard")).unwrap();
+    eval_ok(&game, r#"assert(table.concat(changes, ",") == "s1:music_volume=0.5,s1:sensitivity=10.0,s1:show_fps=true,s1:difficulty=hard", table.concat(changes, ","))"#);
+
+    // Unchanged values, wrong types, unknown options and keys are dropped
+    game.handle_setting("s1", "difficulty", &json!("hard")).unwrap();
+    game.handle_setting("s1", "show_fps", &json!(1)).unwrap();
+    game.handle_setting("s1", "difficulty", &json!("nightmare")).unwrap();
+    game.handle_setting("s1", "removed", &json!(1)).unwrap();
+    eval_ok(&game, "assert(#changes == 4)");
+
+    // Values are per player, and forgotten when they leave
+    eval_ok(&game, "assert(api.get_setting('s1', 'difficulty') == 'hard')");
+    eval_ok(&game, "assert(api.get_setting('s2', 'difficulty') == 'normal')");
+    game.on_disconnect("s1").unwrap();
+    eval_ok(&game, "assert(api.get_setting('s1', 'difficulty') == 'normal')");
+
+    assert!(game.handle_setting("s1", &"k".repeat(1000), &json!(1)).is_err());
+}
+
+#[test]
+fn test_register_setting_errors() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    assert!(game.eval("api.register_setting('a', {type = 'dial'})").starts_with("Error"));
+    assert!(game.eval("api.register_setting('a', {min = 2, max = 1})").starts_with("Error"));
+    assert!(game.eval("api.register_setting('a', {type = 'choice'})").starts_with("Error"));
+    assert!(game.eval("api.register_setting('a', {type = 'choice', options = {'x'}, default = 'y'})").starts_with("Error"));
+    assert!(game.eval("api.get_setting('s1', 'missing')").starts_with("Error"));
+}
@@ -0,0 +1,55 @@
+use engine::shared::SharedData;
+use engine::GameState;
+use std::collections::HashMap;
+
+fn levels() -> SharedData {
+    let levels = serde_json::json!([
+        { "name": "cave", "enemies": [3, 4], "spawn": { "x": 1.5, "y": 2 }, "boss": null },
+        { "name": "tower", "enemies": [] }
+    ]);
+    SharedData::new(HashMap::from([("levels".to_string(), levels)]))
+}
+
+#[test]
+fn test_shared_data_views() {
+    let script = r#"
+        levels = api.shared_get("levels")
+        function check()
+            assert(#levels == 2 and levels[3] == nil)
+            local cave = levels[1]
+            assert(cave.name == "cave" and #cave.enemies == 2 and cave.enemies[2] == 4)
+            assert(cave.spawn.x == 1.5 and cave.boss == nil and cave.missing == nil)
+            local spawn = cave.spawn:copy()
+            spawn.x = 10
+            assert(cave.spawn.x == 1.5, "copies are independent")
+            assert(#cave:keys() == 4 and levels[2].enemies:keys()[1] == nil)
+            assert(api.shared_get("nothing") == nil)
+        end
+    "#;
+    let game = GameState::with_shared_data(script, None, &mut [], levels()).expect("Failed to init game");
+    let result = game.eval("check()");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(game.eval("levels[1].name = 'x'").starts_with("Error"), "read-only");
+}
+
+#[test]
+fn test_shared_data_shared_between_games() {
+    let shared = levels();
+    let a = GameState::with_shared_data("", None, &mut [], shared.clone()).unwrap();
+    let b = GameState::with_shared_data("", None, &mut [], shared).unwrap();
+    for game in [&a, &b] {
+        let result = game.eval("assert(api.shared_get('levels')[2].name == 'tower')");
+        assert!(!result.starts_with("Error"), "{}", result);
+    }
+
+    let dir = std::env::temp_dir().join(format!("cleoselene-shared-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("items.json"), r#"{"sword": 3}"#).unwrap();
+    std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+    let loaded = SharedData::load(&dir).unwrap();
+    assert!(loaded.view("items").is_some() && loaded.view("notes").is_none());
+    std::fs::write(dir.join("broken.json"), "{").unwrap();
+    assert!(SharedData::load(&dir).is_err());
+    assert!(SharedData::load(&dir.join("missing")).unwrap().view("items").is_none());
+    let _ = std::fs::remove_dir_all(&dir);
+}
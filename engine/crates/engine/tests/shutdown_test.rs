@@ -0,0 +1,21 @@
+use engine::GameState;
+use serde_json::json;
+
+#[test]
+fn test_on_shutdown_hook_can_message_players() {
+    let script = r#"
+        function on_shutdown()
+            api.send_message("s1", {type = "bye"})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.on_connect("s1").unwrap();
+    game.on_shutdown().unwrap();
+    assert_eq!(game.take_messages("s1"), vec![json!({"type": "bye"})]);
+}
+
+#[test]
+fn test_on_shutdown_is_optional() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    assert!(game.on_shutdown().is_ok());
+}
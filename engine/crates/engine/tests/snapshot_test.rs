@@ -0,0 +1,30 @@
+use engine::GameState;
+
+#[test]
+fn test_snapshot_hooks_round_trip() {
+    let script = r#"
+        score = 0
+        function update(dt) score = score + 1 end
+        function snapshot() return {score = score, name = "run"} end
+        function restore(state) score = state.score end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.update(0.1).unwrap();
+
+    let snap = game.snapshot().unwrap().expect("script defines snapshot()");
+    assert_eq!(snap["score"], 1);
+    assert_eq!(snap["name"], "run");
+
+    game.update(0.1).unwrap();
+    game.update(0.1).unwrap();
+    game.restore(&snap).unwrap();
+    let result = game.eval("assert(score == 1, score)");
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_snapshot_is_optional() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    assert!(game.snapshot().unwrap().is_none());
+    assert!(game.restore(&serde_json::json!({})).is_err(), "restore needs a restore(state) hook");
+}
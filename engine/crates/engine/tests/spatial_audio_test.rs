@@ -0,0 +1,106 @@
+use bytes::Buf;
+use engine::compat::{CAPS_ALL, CAP_PANNING};
+use engine::GameState;
+use std::io::Cursor;
+
+const OP_PLAY_SOUND: u8 = 0x07;
+const OP_PLAY_SOUND_PANNED: u8 = 0x33;
+
+// (opcode, name, volume, pan) of every sound in the frame; the frame holds only sounds
+// (OP_PLAY_SOUND with its pitch)
+fn sounds(bytes: &[u8]) -> Vec<(u8, String, f32, f32)> {
+    let mut cursor = Cursor::new(bytes);
+    let mut out = Vec::new();
+    while cursor.has_remaining() {
+        let opcode = cursor.get_u8();
+        let len = cursor.get_u16_le() as usize;
+        let pos = cursor.position() as usize;
+        let name = String::from_utf8(bytes[pos..pos + len].to_vec()).unwrap();
+        cursor.advance(len);
+        cursor.get_u8(); // Loop
+        let volume = cursor.get_f32_le();
+        let pan = if opcode == OP_PLAY_SOUND_PANNED { cursor.get_f32_le() } else { 0.0 };
+        if opcode == OP_PLAY_SOUND {
+            cursor.get_f32_le(); // Pitch
+        }
+        out.push((opcode, name, volume, pan));
+    }
+    out
+}
+
+#[test]
+fn test_positional_sound_is_mixed_per_listener() {
+    let script = r#"
+        api.set_listener("left", 0, 0)
+        api.set_listener("right", 600, 0)
+        api.set_listener("far", 2000, 0)
+
+        function update(dt)
+            api.play_sound_at("boom", 300, 0, {min_distance = 100, max_distance = 1000})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.begin_frame();
+    game.update(0.016).unwrap();
+
+    let left = sounds(&game.draw("left").unwrap());
+    assert_eq!(left.len(), 1);
+    let (opcode, name, volume, pan) = left[0].clone();
+    assert_eq!((opcode, name.as_str()), (OP_PLAY_SOUND_PANNED, "boom"));
+    assert!((volume - (1.0 - 200.0 / 900.0)).abs() < 1e-5, "{}", volume);
+    assert!((pan - 0.3).abs() < 1e-5, "louder on the right: {}", pan);
+
+    let right = sounds(&game.draw("right").unwrap());
+    assert!((right[0].2 - volume).abs() < 1e-5);
+    assert!((right[0].3 + 0.3).abs() < 1e-5, "louder on the left: {}", right[0].3);
+
+    assert!(sounds(&game.draw("far").unwrap()).is_empty(), "out of range");
+
+    // No listener, no viewport: unattenuated
+    let nobody = sounds(&game.draw("nobody").unwrap());
+    assert_eq!((nobody[0].2, nobody[0].3), (1.0, 0.0));
+
+    // Sounds are played once, in the frame they were started
+    game.begin_frame();
+    assert!(sounds(&game.draw("left").unwrap()).is_empty());
+}
+
+#[test]
+fn test_listener_defaults_to_viewport_center() {
+    let script = r#"
+        function draw(session_id)
+            api.play_sound_at("step", 500, 300, {volume = 0.5})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.eval("api.set_viewport('s1', 100, 0, 800, 600)");
+    let frame = sounds(&game.draw("s1").unwrap());
+    assert_eq!((frame[0].2, frame[0].3), (0.5, 0.0));
+
+    game.eval("api.set_listener('s1', 500, 1300)");
+    assert!(sounds(&game.draw("s1").unwrap()).is_empty());
+    game.eval("api.clear_listener('s1')");
+    assert_eq!(sounds(&game.draw("s1").unwrap()).len(), 1);
+
+    let result = game.eval("api.play_sound_at('step', 0, 0, {min_distance = 50, max_distance = 10})");
+    assert!(result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_clients_without_panning_get_plain_sounds() {
+    let script = r#"
+        function update(dt)
+            api.play_sound_at("boom", 1000, 0, {max_distance = 2000})
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_PANNING);
+    game.eval("api.set_listener('old', 0, 0)");
+    game.begin_frame();
+    game.update(0.016).unwrap();
+
+    let frame = sounds(&game.draw("old").unwrap());
+    assert_eq!(frame.len(), 1);
+    assert_eq!(frame[0].0, OP_PLAY_SOUND);
+    assert!((frame[0].2 - (1.0 - 900.0 / 1900.0)).abs() < 1e-5);
+}
@@ -0,0 +1,139 @@
+use engine::GameState;
+
+// Scenarios are written in Lua and assert from inside init():
+// a failed assert surfaces as an Err from GameState::new.
+fn run_lua(script: &str) {
+    if let Err(e) = GameState::new(script, None) {
+        panic!("Lua assertion failed: {}", e);
+    }
+}
+
+#[test]
+fn test_removed_ids_are_not_reused() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local a = db:add_circle(10, 10, 5, "unit")
+            db:remove(a)
+            local b = db:add_circle(10, 10, 5, "unit")
+
+            assert(a ~= b, "handle was reused after remove")
+            assert(db:get_position(a) == nil, "stale handle still resolves")
+            local x, y = db:get_position(b)
+            assert(x == 10 and y == 10)
+        end
+    "#);
+}
+
+#[test]
+fn test_query_results_are_ordered() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(10)
+            local ids = {}
+            for i = 1, 50 do
+                ids[i] = db:add_circle(i * 3, 0, 4, "unit")
+            end
+
+            local res = db:query_range(75, 0, 200)
+            assert(#res == 50, "expected all entities, got " .. #res)
+            for i = 2, #res do
+                assert(res[i - 1] < res[i], "query_range results not sorted/deduplicated")
+            end
+        end
+    "#);
+}
+
+#[test]
+fn test_body_removed_with_entity() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local phys = api.new_physics_world(db)
+            local id = db:add_circle(0, 0, 5, "ball")
+            phys:add_body(id, { mass = 1.0 })
+            phys:set_velocity(id, 10, 0)
+            db:remove(id)
+            phys:step(0.1)
+            assert(phys:get_velocity(id) == nil, "body outlived its entity")
+        end
+    "#);
+}
+
+#[test]
+fn test_toroidal_queries_cross_seam() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50, { wrap_width = 1000, wrap_height = 1000 })
+            local a = db:add_circle(990, 500, 5, "rock")
+
+            local x, y = db:get_position(a)
+            db:update(a, 1010, 500)
+            x, y = db:get_position(a)
+            assert(math.abs(x - 10) < 0.001, "position not wrapped: " .. x)
+
+            db:update(a, 990, 500)
+            local hits = db:query_range(5, 500, 20)
+            assert(#hits == 1 and hits[1] == a, "query did not see across the seam")
+
+            local id, frac, hx, hy = db:cast_ray(20, 500, 180, 40)
+            assert(id == a, "ray did not cross the seam")
+            assert(hx > 900, "hit point not wrapped: " .. hx)
+        end
+    "#);
+}
+
+#[test]
+fn test_query_range_ex_sorted_and_capped() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local far = db:add_circle(90, 0, 2, "enemy")
+            local near = db:add_circle(10, 0, 2, "enemy")
+            local mid = db:add_circle(50, 0, 2, "enemy")
+            db:add_circle(20, 0, 2, "ally")
+
+            local res = db:query_range_ex(0, 0, 100, "enemy")
+            assert(#res == 3)
+            assert(res[1].id == near and res[2].id == mid and res[3].id == far, "not sorted by distance")
+            assert(res[1].dist == 10 and res[1].x == 10 and res[1].y == 0)
+
+            local capped = db:query_range_ex(0, 0, 100, "enemy", 2)
+            assert(#capped == 2 and capped[2].id == mid)
+        end
+    "#);
+}
+
+#[test]
+fn test_tag_reverse_lookup() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local p = db:add_circle(0, 0, 5, "player")
+            local w = db:add_segment(-10, 10, 10, 10, "wall")
+            assert(db:get_tag(p) == "player" and db:get_tag(w) == "wall")
+
+            local ids, tags = db:query_range(0, 0, 50, nil, { return_tags = true })
+            assert(#ids == 2 and #tags == 2)
+            for i, id in ipairs(ids) do
+                assert(tags[i] == db:get_tag(id))
+            end
+
+            local only_ids, none = db:query_rect(-50, -50, 50, 50)
+            assert(#only_ids == 2 and none == nil)
+        end
+    "#);
+}
+
+#[test]
+fn test_polygon_outline_becomes_segments() {
+    run_lua(r#"
+        function init()
+            local db = api.new_spatial_db(50)
+            local square = {{0, 0}, {100, 0}, {100, 100}, {0, 100}}
+            assert(#db:add_polygon(square, "wall") == 4)
+            assert(#db:add_polygon(square, "ledge", false) == 3)
+            assert(#db:query_rect(-10, -10, 110, 110, "wall") == 4)
+        end
+    "#);
+}
@@ -0,0 +1,60 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    players = {}
+    watched = {}
+    events = {}
+    function on_connect(id) players[id] = {x = 0} end
+    function on_disconnect(id) players[id] = nil end
+    function on_input(id, key, down) players[id].x = players[id].x + 1 end
+    function on_spectator_connect(id) table.insert(events, "watch:" .. id) end
+    function on_spectator_disconnect(id) table.insert(events, "left:" .. id) end
+    function on_spectator_input(id, key, down)
+        if down then watched[id] = key end
+    end
+    function draw(id)
+        api.clear_screen(0, 0, 0)
+    end
+"#;
+
+#[test]
+fn test_spectators_are_drawn_but_not_players() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.on_connect("p1").unwrap();
+    game.on_spectate("v1").unwrap();
+    game.on_spectate("v2").unwrap();
+
+    assert!(game.spectating("v1") && !game.spectating("p1"));
+    assert!(!game.draw("v1").unwrap().is_empty());
+    eval_ok(&game, r#"
+        assert(players.p1 and not players.v1)
+        local s = api.get_spectators()
+        assert(#s == 2 and s[1] == "v1" and s[2] == "v2")
+        assert(events[1] == "watch:v1")
+    "#);
+
+    game.on_disconnect("v1").unwrap();
+    eval_ok(&game, r#"
+        assert(players.p1 and events[3] == "left:v1")
+        local s = api.get_spectators()
+        assert(#s == 1 and s[1] == "v2")
+    "#);
+}
+
+#[test]
+fn test_spectator_input_goes_to_its_own_hook() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.on_spectate("v1").unwrap();
+    // on_input would fail: there is no player entry for v1
+    game.handle_input("v1", 39, true).unwrap();
+    eval_ok(&game, "assert(watched.v1 == 39 and not api.is_down('v1', 39))");
+
+    game.handle_text_input("v1", "hello").unwrap();
+    game.handle_paste("v1", "hello").unwrap();
+    game.handle_script_message("v1", &serde_json::json!({"type": "chat"})).unwrap();
+}
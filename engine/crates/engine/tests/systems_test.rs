@@ -0,0 +1,93 @@
+use engine::{GameState, SystemPhase};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn test_systems_run_around_update() {
+    let script = r#"
+        log = {}
+        function update(dt)
+            table.insert(log, "lua")
+        end
+    "#;
+
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    game.add_system(SystemPhase::PostUpdate, Box::new(|lua, _dt| {
+        let log: engine::mlua::Table = lua.globals().get("log")?;
+        log.push("post")
+    }));
+    game.add_system(SystemPhase::PreUpdate, Box::new(|lua, dt| {
+        let log: engine::mlua::Table = lua.globals().get("log")?;
+        log.push(format!("pre {}", dt))
+    }));
+
+    game.update(0.5).expect("Update failed");
+
+    let result = game.eval("assert(table.concat(log, ',') == 'pre 0.5,lua,post', table.concat(log, ','))");
+    assert!(!result.starts_with("Error"), "Unexpected order: {}", result);
+}
+
+#[test]
+fn test_system_error_propagates() {
+    let game = GameState::new("function update(dt) end", None).expect("Failed to init game");
+    let ticks = Arc::new(Mutex::new(0));
+
+    let counter = ticks.clone();
+    game.add_system(SystemPhase::PreUpdate, Box::new(move |_, _| {
+        *counter.lock().unwrap() += 1;
+        Err(engine::mlua::Error::RuntimeError("boom".into()))
+    }));
+
+    assert!(game.update(0.1).is_err());
+    assert_eq!(*ticks.lock().unwrap(), 1);
+}
+
+struct Echo;
+
+impl engine::plugin::Plugin for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn register(&mut self, ctx: &mut engine::plugin::PluginContext) -> engine::mlua::Result<()> {
+        let cmds = ctx.commands();
+        let f = ctx.lua().create_function(move |_, text: String| cmds.write(0xC0, text.as_bytes()))?;
+        ctx.api().set("echo", f)?;
+        ctx.add_system(SystemPhase::PostUpdate, Box::new(|lua, _| lua.globals().set("echo_ticked", true)));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_plugin_registers_functions_opcodes_and_systems() {
+    let script = r#"
+        function draw(session_id)
+            api.echo("hi")
+        end
+    "#;
+    let mut plugins: Vec<Box<dyn engine::plugin::Plugin>> = vec![Box::new(Echo)];
+    let game = GameState::with_plugins(script, None, &mut plugins).expect("Failed to init game");
+
+    game.update(0.1).expect("Update failed");
+    let result = game.eval("assert(echo_ticked == true)");
+    assert!(!result.starts_with("Error"), "Plugin system did not run: {}", result);
+
+    let bytes = game.draw("s1").expect("Draw failed");
+    assert_eq!(&bytes[..], &[0xC0, 2, 0, b'h', b'i']);
+}
+
+#[test]
+fn test_plugin_cannot_use_builtin_opcodes() {
+    struct Bad;
+    impl engine::plugin::Plugin for Bad {
+        fn name(&self) -> &str {
+            "bad"
+        }
+        fn register(&mut self, ctx: &mut engine::plugin::PluginContext) -> engine::mlua::Result<()> {
+            ctx.commands().write(0x01, &[])
+        }
+    }
+
+    let mut plugins: Vec<Box<dyn engine::plugin::Plugin>> = vec![Box::new(Bad)];
+    assert!(GameState::with_plugins("", None, &mut plugins).is_err());
+}
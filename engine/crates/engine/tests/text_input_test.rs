@@ -0,0 +1,56 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+#[test]
+fn test_text_prompt_round_trip() {
+    let script = r#"
+        names = {}
+        function on_connect(session_id)
+            api.request_text_input(session_id, {placeholder = "Name", max_len = 5})
+        end
+        function on_text_input(session_id, text)
+            names[session_id] = text
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let bytes = game.on_connect("s1").unwrap();
+    assert_eq!(bytes[0], 0x13, "OP_REQUEST_TEXT_INPUT");
+    assert_eq!(u16::from_le_bytes([bytes[1], bytes[2]]), 4);
+    assert_eq!(&bytes[3..7], b"Name");
+    assert_eq!(u16::from_le_bytes([bytes[7], bytes[8]]), 5);
+
+    game.handle_text_input("s1", "Alexander\n").unwrap();
+    eval_ok(&game, "assert(names.s1 == 'Alexa', names.s1)");
+
+    // The prompt is answered: further text is unsolicited
+    game.handle_text_input("s1", "again").unwrap();
+    eval_ok(&game, "assert(names.s1 == 'Alexa')");
+    game.handle_text_input("s2", "intruder").unwrap();
+    eval_ok(&game, "assert(names.s2 == nil)");
+}
+
+#[test]
+fn test_paste_reaches_on_paste() {
+    let script = r#"
+        pasted, answers = {}, 0
+        function on_paste(session_id, text)
+            table.insert(pasted, session_id .. ":" .. text)
+        end
+        function on_text_input(session_id, text)
+            answers = answers + 1
+        end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    // No prompt needed; line breaks survive, other control characters don't
+    game.handle_paste("s1", "LEVEL-42\nB\x07").unwrap();
+    game.handle_paste("s2", "\x1b").unwrap();
+    eval_ok(&game, r#"assert(#pasted == 1 and pasted[1] == "s1:LEVEL-42\nB" and answers == 0, pasted[1])"#);
+
+    assert!(game.handle_paste("s1", &"x".repeat(8192)).is_err(), "too long");
+}
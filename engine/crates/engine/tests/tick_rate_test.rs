@@ -0,0 +1,19 @@
+use engine::{GameState, TickRate};
+
+#[test]
+fn test_set_tick_rate() {
+    let game = GameState::new("", None).expect("Failed to init game");
+    assert_eq!(game.tick_rate(), None);
+
+    game.eval("api.set_tick_rate(60)");
+    assert_eq!(game.tick_rate(), Some(TickRate { tick: 60, send: None }));
+    game.eval("api.set_tick_rate(10, 5)");
+    assert_eq!(game.tick_rate(), Some(TickRate { tick: 10, send: Some(5) }));
+
+    assert!(game.eval("api.set_tick_rate(0)").starts_with("Error"));
+    assert!(game.eval("api.set_tick_rate(30, 1000)").starts_with("Error"));
+    assert_eq!(game.tick_rate(), Some(TickRate { tick: 10, send: Some(5) }));
+
+    game.eval("api.set_tick_rate(nil)");
+    assert_eq!(game.tick_rate(), None);
+}
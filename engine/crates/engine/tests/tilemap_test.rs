@@ -0,0 +1,69 @@
+use engine::GameState;
+
+// (x, y, w, h, tiles) of every OP_TILEMAP_CHUNK in a frame, and the number of OP_DRAW_TILEMAP
+fn parse_tilemap_frame(bytes: &[u8]) -> (Vec<(u16, u16, u8, u8, Vec<u16>)>, usize) {
+    let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+    let (mut chunks, mut draws, mut offset) = (Vec::new(), 0, 0);
+    while offset < bytes.len() {
+        match bytes[offset] {
+            0x1E => {
+                let (w, h) = (bytes[offset + 11], bytes[offset + 12]);
+                let tiles = (0..w as usize * h as usize).map(|i| u16_at(offset + 13 + i * 2)).collect();
+                chunks.push((u16_at(offset + 7), u16_at(offset + 9), w, h, tiles));
+                offset += 13 + w as usize * h as usize * 2;
+            }
+            0x1F => {
+                draws += 1;
+                offset += 1 + 2 + 2 + u16_at(offset + 3) as usize + 2 + 12;
+            }
+            other => panic!("unexpected opcode 0x{:02X}", other),
+        }
+    }
+    (chunks, draws)
+}
+
+#[test]
+fn test_tilemap_sends_changed_chunks_only() {
+    let script = r#"
+        map = api.new_tilemap(20, 20, 8)
+        map:set_tileset("tiles", 16)
+        function draw(session_id) map:draw() end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    // First frame: every chunk (16x16 chunks, clipped at the map edge)
+    let (chunks, draws) = parse_tilemap_frame(&game.draw("s1").unwrap());
+    let rects: Vec<_> = chunks.iter().map(|c| (c.0, c.1, c.2, c.3)).collect();
+    assert_eq!(rects, vec![(0, 0, 16, 16), (16, 0, 4, 16), (0, 16, 16, 4), (16, 16, 4, 4)]);
+    assert_eq!(draws, 1);
+
+    // Nothing changed: only the round-robin refresh chunk
+    let (chunks, _) = parse_tilemap_frame(&game.draw("s1").unwrap());
+    assert_eq!(chunks.len(), 1);
+
+    game.eval("map:set(17, 18, 7)");
+    let (chunks, _) = parse_tilemap_frame(&game.draw("s1").unwrap());
+    let changed = chunks.iter().find(|c| (c.0, c.1) == (16, 16)).expect("changed chunk resent");
+    assert_eq!(changed.4[2 * 4 + 1], 7);
+    assert!(chunks.len() <= 2);
+
+    // Other clients and clients that lost a frame get everything
+    assert_eq!(parse_tilemap_frame(&game.draw("s2").unwrap()).0.len(), 4);
+    game.frame_dropped("s1");
+    assert_eq!(parse_tilemap_frame(&game.draw("s1").unwrap()).0.len(), 4);
+}
+
+#[test]
+fn test_tilemap_api_errors() {
+    let script = r#"
+        map = api.new_tilemap(4, 4, 8)
+        function draw(session_id) map:draw() end
+    "#;
+    let game = GameState::new(script, None).expect("Failed to init game");
+
+    let result = game.eval("map:fill(0, 0, 10, 10, 3); assert(map:get(3, 3) == 3 and map:get(4, 0) == nil)");
+    assert!(!result.starts_with("Error"), "{}", result);
+    assert!(game.eval("map:set(4, 0, 1)").starts_with("Error"), "outside the map");
+    assert!(game.eval("api.new_tilemap(0, 10, 8)").starts_with("Error"));
+    assert!(game.draw("s1").is_err(), "drawing needs a tileset");
+}
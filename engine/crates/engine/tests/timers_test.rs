@@ -0,0 +1,76 @@
+use engine::GameState;
+
+fn eval_ok(game: &GameState, code: &str) {
+    let result = game.eval(code);
+    assert!(!result.starts_with("Error"), "{}", result);
+}
+
+const SCRIPT: &str = r#"
+    fired = {}
+    function log(name) return function(handle) table.insert(fired, name) end end
+    function update(dt) table.insert(fired, "update") end
+"#;
+
+#[test]
+fn test_after_fires_once_before_update() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    eval_ok(&game, "handle = api.after(0.25, log('ding'))");
+    game.update(0.1).unwrap();
+    game.update(0.1).unwrap();
+    eval_ok(&game, "assert(#fired == 2 and fired[2] == 'update')");
+    game.update(0.1).unwrap();
+    eval_ok(&game, "assert(fired[3] == 'ding' and fired[4] == 'update', table.concat(fired, ','))");
+    game.update(1.0).unwrap();
+    eval_ok(&game, "assert(#fired == 5)");
+    eval_ok(&game, "assert(api.cancel(handle) == false)");
+}
+
+#[test]
+fn test_every_repeats_until_cancelled() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    eval_ok(&game, "ticks = 0; handle = api.every(0.5, function(h) assert(h == handle); ticks = ticks + 1 end)");
+    for _ in 0..10 {
+        game.update(0.25).unwrap();
+    }
+    eval_ok(&game, "assert(ticks == 5, ticks)");
+
+    // Fell behind: one run, not four
+    game.update(2.0).unwrap();
+    eval_ok(&game, "assert(ticks == 6, ticks)");
+    game.update(0.5).unwrap();
+    eval_ok(&game, "assert(ticks == 7, ticks)");
+
+    // Far behind, and on schedule after
+    game.update(1e9).unwrap();
+    eval_ok(&game, "assert(ticks == 8, ticks)");
+    game.update(0.25).unwrap();
+    eval_ok(&game, "assert(ticks == 8, ticks)");
+
+    eval_ok(&game, "assert(api.cancel(handle) == true)");
+    game.update(5.0).unwrap();
+    eval_ok(&game, "assert(ticks == 8, ticks)");
+}
+
+#[test]
+fn test_order_and_cancel_from_callback() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    eval_ok(&game, r#"
+        api.after(0.3, log("late"))
+        api.after(0.1, function() table.insert(fired, "early"); api.cancel(doomed) end)
+        doomed = api.after(0.2, log("doomed"))
+        api.after(0.1, function() api.after(0, log("next")) end)
+    "#);
+    game.update(0.5).unwrap();
+    eval_ok(&game, "assert(table.concat(fired, ',') == 'early,late,update', table.concat(fired, ','))");
+    game.update(0.0).unwrap();
+    eval_ok(&game, "assert(fired[4] == 'next', table.concat(fired, ','))");
+}
+
+#[test]
+fn test_invalid_timers() {
+    let game = GameState::new(SCRIPT, None).unwrap();
+    assert!(game.eval("api.after(-1, function() end)").starts_with("Error"));
+    assert!(game.eval("api.every(0, function() end)").starts_with("Error"));
+    assert!(game.eval("api.every(1e-300, function() end)").starts_with("Error"));
+    eval_ok(&game, "assert(api.cancel(12345) == false)");
+}
@@ -0,0 +1,94 @@
+use bytes::Buf;
+use engine::compat::{CAPS_ALL, CAP_TRAILS};
+use engine::GameState;
+use std::io::Cursor;
+
+const OP_DRAW_TRAILS: u8 = 0x3C;
+
+// (rgba, width, points) of every trail; the frame holds only OP_DRAW_TRAILS (or nothing)
+fn trails(bytes: &[u8]) -> Vec<([u8; 4], f32, Vec<(f32, f32)>)> {
+    if bytes.is_empty() {
+        return Vec::new();
+    }
+    let mut cursor = Cursor::new(bytes);
+    assert_eq!(cursor.get_u8(), OP_DRAW_TRAILS);
+    let out = (0..cursor.get_u16_le()).map(|_| {
+        let rgba = [cursor.get_u8(), cursor.get_u8(), cursor.get_u8(), cursor.get_u8()];
+        let width = cursor.get_f32_le();
+        let points = (0..cursor.get_u16_le()).map(|_| (cursor.get_f32_le(), cursor.get_f32_le())).collect();
+        (rgba, width, points)
+    }).collect();
+    assert!(!cursor.has_remaining());
+    out
+}
+
+const SCRIPT: &str = r#"
+    db = api.new_spatial_db(100)
+    comet = db:add_circle(0, 0, 4, "comet")
+    api.trail_attach(db, comet, {length = 3, width = 5, color = {255, 100, 0}})
+    x = 0
+
+    function update(dt)
+        x = x + 10
+        db:update(comet, x, 0)
+    end
+
+    function draw(session_id)
+        api.draw_trails()
+    end
+"#;
+
+#[test]
+fn test_trail_follows_entity() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.update(0.016).unwrap();
+    assert!(trails(&game.draw("s1").unwrap()).is_empty(), "one point is not a trail yet");
+
+    for _ in 0..4 {
+        game.update(0.016).unwrap();
+    }
+    let frame = trails(&game.draw("s1").unwrap());
+    assert_eq!(frame.len(), 1);
+    let (rgba, width, points) = &frame[0];
+    assert_eq!((*rgba, *width), ([255, 100, 0, 255], 5.0));
+    assert_eq!(points, &vec![(50.0, 0.0), (40.0, 0.0), (30.0, 0.0)], "newest first, capped to length");
+
+    game.eval("api.set_viewport('s1', 1000, 1000, 800, 600)");
+    assert!(trails(&game.draw("s1").unwrap()).is_empty(), "out of view");
+}
+
+#[test]
+fn test_detached_trail_shrinks_away() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    for _ in 0..3 {
+        game.update(0.016).unwrap();
+    }
+    let result = game.eval("assert(api.trail_detach(comet)); assert(not api.trail_detach(comet))");
+    assert!(!result.starts_with("Error"), "{}", result);
+
+    game.update(0.016).unwrap();
+    assert_eq!(trails(&game.draw("s1").unwrap())[0].2, vec![(30.0, 0.0), (20.0, 0.0)]);
+    game.update(0.016).unwrap();
+    assert!(trails(&game.draw("s1").unwrap()).is_empty());
+
+    // Same when the entity is removed
+    game.eval("comet = db:add_circle(0, 0, 4, 'comet'); api.trail_attach(db, comet, {length = 3})");
+    for _ in 0..3 {
+        game.update(0.016).unwrap();
+    }
+    game.eval("db:remove(comet)");
+    game.update(0.016).unwrap();
+    assert_eq!(trails(&game.draw("s1").unwrap())[0].2.len(), 2);
+
+    assert!(game.eval("api.trail_attach(db, comet, {length = 1})").starts_with("Error"));
+}
+
+#[test]
+fn test_clients_without_trails() {
+    let game = GameState::new(SCRIPT, None).expect("Failed to init game");
+    game.set_capabilities("old", CAPS_ALL & !CAP_TRAILS);
+    for _ in 0..3 {
+        game.update(0.016).unwrap();
+    }
+    assert!(game.draw("old").unwrap().is_empty());
+}
@@ -0,0 +1,188 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::plugins::CONFIG_FILE;
+
+// --- Analytics ---
+// Events from `api.track` plus built-in ones (session_start, session_end, error) are
+// handed to a writer thread, which batches them and flushes to the configured sink.
+// The queue is bounded: when the sink can't keep up, new events are dropped (and counted)
+// rather than piling up in memory or blocking a game loop. On shutdown the writer flushes
+// what is left and stops (Analytics::shut_down).
+// Disabled unless cleoselene.toml has an [analytics] section:
+//
+//   [analytics]
+//   sink = "jsonl"                  # or "http"
+//   path = "analytics.jsonl"        # jsonl: relative to the state directory (--state-dir)
+//   url = "https://example.com/ev"  # http: POSTed as a JSON array per batch
+//   flush_interval_secs = 5
+//   batch_size = 100
+
+#[derive(Deserialize, Default)]
+struct Config {
+    analytics: Option<AnalyticsConfig>,
+}
+
+#[derive(Deserialize)]
+struct AnalyticsConfig {
+    #[serde(default = "default_sink")]
+    sink: String,
+    path: Option<String>,
+    url: Option<String>,
+    #[serde(default = "default_flush_interval")]
+    flush_interval_secs: u64,
+    #[serde(default = "default_batch_size")]
+    batch_size: usize,
+}
+
+fn default_sink() -> String { "jsonl".to_string() }
+fn default_flush_interval() -> u64 { 5 }
+fn default_batch_size() -> usize { 100 }
+
+/// Events waiting for the writer thread before new ones are dropped
+const MAX_QUEUED_EVENTS: usize = 10_000;
+// A slow or unreachable endpoint delays the next flush by at most these
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Sink {
+    Jsonl(PathBuf),
+    Http(ureq::Agent, String),
+}
+
+impl Sink {
+    fn flush(&self, batch: &[Value]) -> anyhow::Result<()> {
+        match self {
+            Sink::Jsonl(path) => {
+                let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+                for event in batch {
+                    writeln!(file, "{}", event)?;
+                }
+            }
+            Sink::Http(agent, url) => {
+                agent.post(url).send_json(Value::Array(batch.to_vec()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cheap handle for recording events; a no-op when analytics is disabled.
+#[derive(Clone, Default)]
+pub struct Analytics {
+    tx: Option<SyncSender<Message>>,
+    dropped: Arc<AtomicUsize>, // Events refused by a full queue since the last flush
+    writer: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+enum Message {
+    Event(Value),
+    Stop, // Flush and exit
+}
+
+impl Analytics {
+    /// Configured by the game's cleoselene.toml; the jsonl file goes to `state_dir`.
+    pub fn load(game_dir: &Path, state_dir: &Path) -> anyhow::Result<Self> {
+        let config_path = game_dir.join(CONFIG_FILE);
+        let config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+            Err(_) => Config::default(),
+        };
+        let Some(cfg) = config.analytics else { return Ok(Self::default()) };
+
+        let sink = match cfg.sink.as_str() {
+            "jsonl" => {
+                let path = state_dir.join(cfg.path.as_deref().unwrap_or("analytics.jsonl"));
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                Sink::Jsonl(path)
+            }
+            "http" => {
+                let url = cfg.url.ok_or_else(|| anyhow::anyhow!("[analytics] sink = \"http\" needs a `url`"))?;
+                let agent = ureq::AgentBuilder::new()
+                    .timeout_connect(HTTP_CONNECT_TIMEOUT)
+                    .timeout(HTTP_TIMEOUT)
+                    .build();
+                Sink::Http(agent, url)
+            }
+            other => anyhow::bail!("Unknown analytics sink '{}' (expected jsonl or http)", other),
+        };
+        let interval = Duration::from_secs(cfg.flush_interval_secs.max(1));
+        let batch_size = cfg.batch_size.max(1);
+
+        let (tx, rx) = sync_channel::<Message>(MAX_QUEUED_EVENTS);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let writer_dropped = dropped.clone();
+        let writer = thread::spawn(move || {
+            let mut batch = Vec::new();
+            let mut last_flush = Instant::now();
+            loop {
+                let closed = match rx.recv_timeout(interval) {
+                    Ok(Message::Event(event)) => { batch.push(event); false }
+                    Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => true,
+                    Err(RecvTimeoutError::Timeout) => false,
+                };
+                if !batch.is_empty() && (closed || batch.len() >= batch_size || last_flush.elapsed() >= interval) {
+                    if let Err(e) = sink.flush(&batch) {
+                        tracing::error!("Analytics flush failed ({} events dropped): {}", batch.len(), e);
+                    }
+                    batch.clear();
+                    last_flush = Instant::now();
+                    let lost = writer_dropped.swap(0, Ordering::Relaxed);
+                    if lost > 0 {
+                        tracing::warn!("Analytics queue full, {} events dropped", lost);
+                    }
+                }
+                if closed { break; }
+            }
+        });
+
+        tracing::info!("Analytics enabled");
+        Ok(Self { tx: Some(tx), dropped, writer: Arc::new(Mutex::new(Some(writer))) })
+    }
+
+    /// Flushes the queued events and waits for the writer to finish. Events tracked after
+    /// this are dropped.
+    pub fn shut_down(&self) {
+        let Some(tx) = &self.tx else { return };
+        let _ = tx.send(Message::Stop); // Behind every queued event
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            let _ = writer.join();
+        }
+    }
+
+    pub fn track(&self, name: &str, session_id: Option<&str>, properties: Value) {
+        let Some(tx) = &self.tx else { return };
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let event = json!({
+            "ts": ts,
+            "event": name,
+            "session": session_id,
+            "properties": properties,
+        });
+        if let Err(TrySendError::Full(_)) = tx.try_send(Message::Event(event)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn session_start(&self, session_id: &str) {
+        self.track("session_start", Some(session_id), Value::Null);
+    }
+
+    pub fn session_end(&self, session_id: &str, length: Duration) {
+        self.track("session_end", Some(session_id), json!({ "length_secs": length.as_secs_f64() }));
+    }
+
+    pub fn error(&self, source: &str, session_id: Option<&str>, message: &str) {
+        self.track("error", session_id, json!({ "source": source, "message": message }));
+    }
+}
@@ -0,0 +1,73 @@
+use engine::plugin::Plugin;
+use engine::shared::SharedData;
+use engine::GameState;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::AssetManifest;
+
+// --- Asset Validation ---
+// Runs the script's init() and one on_connect() on a throwaway GameState, then checks
+// that every load_sound/load_image URL served from /assets exists in the game directory,
+// as well as those of assets.json. Assets only requested later (e.g. from update()) are not
+// seen by this pass.
+
+const PROBE_SESSION: &str = "__asset_check__";
+
+pub struct MissingAsset {
+    pub url: String,
+    pub path: PathBuf,
+}
+
+/// Maps a URL as the client would fetch it to a file under the game directory, or None when
+/// it is not served from /assets (absolute URLs, other routes).
+fn resolve(url: &str, assets_dir: &Path) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let rest = path.strip_prefix("/assets/").or_else(|| path.strip_prefix("assets/"))?;
+    Some(assets_dir.join(rest))
+}
+
+pub fn check(script_path: &Path, plugins: &mut [Box<dyn Plugin>], shared: &SharedData) -> anyhow::Result<Vec<MissingAsset>> {
+    let assets_dir = script_path.parent().unwrap_or(Path::new("."));
+    let script = std::fs::read_to_string(script_path)?;
+    let game = GameState::with_shared_data(&script, Some(script_path), plugins, shared.clone())?;
+    // A script may not expect a player out of nowhere: init() assets are still checked
+    if let Err(e) = game.on_connect(PROBE_SESSION).and_then(|_| game.on_disconnect(PROBE_SESSION)) {
+        tracing::error!("Asset check: on_connect failed ({}), only assets loaded by init() were checked", e);
+    }
+
+    let mut urls: BTreeSet<String> = game.requested_assets().into_iter().collect();
+    if let Some(manifest) = AssetManifest::load(assets_dir) {
+        urls.extend(manifest.urls().cloned());
+    }
+    let missing = urls.into_iter()
+        .filter_map(|url| {
+            let path = resolve(&url, assets_dir)?;
+            // ServeDir refuses to leave the directory, so `..` never resolves either
+            let escapes = path.components().any(|c| matches!(c, std::path::Component::ParentDir));
+            (escapes || !path.is_file()).then_some(MissingAsset { url, path })
+        })
+        .collect();
+    Ok(missing)
+}
+
+/// Logs the result; returns false when assets are missing or the script failed to run.
+pub fn report(result: &anyhow::Result<Vec<MissingAsset>>) -> bool {
+    match result {
+        Ok(missing) if missing.is_empty() => {
+            tracing::info!("Assets OK");
+            true
+        }
+        Ok(missing) => {
+            tracing::error!("Missing assets ({}):", missing.len());
+            for m in missing {
+                tracing::error!("Missing asset: {} -> {}", m.url, m.path.display());
+            }
+            false
+        }
+        Err(e) => {
+            tracing::error!("Asset check failed: {}", e);
+            false
+        }
+    }
+}
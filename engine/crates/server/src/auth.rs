@@ -0,0 +1,51 @@
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+// --- Player Authentication ---
+// With --auth-jwt-secret or --auth-url, a WebSocket upgrade needs a token: `?token=` on
+// the URL (the browser client passes on the page's) or an `Authorization: Bearer` header.
+// Missing or invalid tokens get 401 Unauthorized. The verified identity reaches the
+// script as on_connect(session_id, auth_info) and api.get_auth_info(session_id).
+//
+//   --auth-jwt-secret  HS256 JSON Web Tokens signed with this secret; they must carry an
+//                      `exp` claim. auth_info is the token's claims.
+//   --auth-url         An HTTP service of the game's own: the server POSTs
+//                      {"token": "..."} and a 2xx answer's JSON body is auth_info;
+//                      any other answer refuses the player.
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Authenticator {
+    Jwt(DecodingKey),
+    Endpoint { url: String, agent: ureq::Agent },
+}
+
+impl Authenticator {
+    /// None when neither is set: players join anonymously.
+    pub fn from_args(jwt_secret: Option<&str>, url: Option<&str>) -> Option<Self> {
+        if let Some(secret) = jwt_secret {
+            return Some(Authenticator::Jwt(DecodingKey::from_secret(secret.as_bytes())));
+        }
+        let agent = ureq::AgentBuilder::new().timeout(AUTH_TIMEOUT).build();
+        url.map(|url| Authenticator::Endpoint { url: url.to_string(), agent })
+    }
+
+    /// The identity behind `token`, or why it was refused.
+    pub async fn verify(&self, token: &str) -> anyhow::Result<Value> {
+        match self {
+            Authenticator::Jwt(key) => {
+                let data = jsonwebtoken::decode::<Value>(token, key, &Validation::new(Algorithm::HS256))?;
+                Ok(data.claims)
+            }
+            Authenticator::Endpoint { url, agent } => {
+                let (url, agent, token) = (url.clone(), agent.clone(), token.to_string());
+                // ureq blocks: off the async workers
+                tokio::task::spawn_blocking(move || -> anyhow::Result<Value> {
+                    let response = agent.post(&url).send_json(json!({ "token": token }))?;
+                    Ok(response.into_json::<Value>()?)
+                }).await?
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use engine::DrawnFrame;
+use std::io;
+
+// --- Frame Compression ---
+// Clients list the codecs they decode when connecting, in order of preference:
+// ?codecs=zstd,lz4,none on the /ws URL (native clients: "codecs" in HELLO). Their frames
+// then start with a codec byte: CODEC_NONE (the commands as they are), CODEC_ZSTD (zstd
+// frames, one after the other) or CODEC_LZ4 (an LZ4 block after its uncompressed size,
+// u32 little-endian). Frames are compressed with the first codec in the list the server
+// knows; frames smaller than --compress-min-bytes go uncompressed to clients that list
+// "none", since a 60-byte frame only grows with zstd's headers. Clients that don't send
+// `codecs` get every frame as bare zstd, like before codecs were negotiated.
+//
+// A shared segment (api.draw_shared) is compressed once for all zstd clients; LZ4 frames
+// are compressed whole.
+
+pub const CODEC_NONE: u8 = 0;
+pub const CODEC_ZSTD: u8 = 1;
+pub const CODEC_LZ4: u8 = 2;
+
+/// --compress-min-bytes default
+pub const COMPRESS_MIN_BYTES: usize = 128;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameEncoding {
+    codec: u8,
+    tagged: bool,          // The client sent `codecs`: frames start with the codec byte
+    uncompressed_ok: bool, // It listed "none"
+    min_size: usize,
+}
+
+impl FrameEncoding {
+    /// From the client's `codecs` list. A list of codecs the server doesn't know gets zstd,
+    /// which every client decodes.
+    pub fn negotiate(codecs: Option<&str>, min_size: usize) -> Self {
+        let Some(codecs) = codecs else {
+            return Self { codec: CODEC_ZSTD, tagged: false, uncompressed_ok: false, min_size };
+        };
+        let names: Vec<&str> = codecs.split(',').map(str::trim).collect();
+        let codec = names.iter()
+            .find_map(|name| match *name {
+                "zstd" => Some(CODEC_ZSTD),
+                "lz4" => Some(CODEC_LZ4),
+                "none" => Some(CODEC_NONE),
+                _ => None,
+            })
+            .unwrap_or(CODEC_ZSTD);
+        Self { codec, tagged: true, uncompressed_ok: names.contains(&"none"), min_size }
+    }
+
+    /// The frame as the client gets it in a binary message.
+    pub fn encode(&self, frame: &DrawnFrame) -> io::Result<Vec<u8>> {
+        if !self.tagged {
+            return zstd_frame(frame);
+        }
+        let size = frame.before.len() + frame.shared.as_ref().map_or(0, |s| s.bytes.len()) + frame.after.len();
+        let codec = if self.uncompressed_ok && size < self.min_size { CODEC_NONE } else { self.codec };
+        let mut data = vec![codec];
+        match codec {
+            CODEC_ZSTD => data.extend(zstd_frame(frame)?),
+            CODEC_LZ4 => data.extend(lz4_flex::compress_prepend_size(&frame.to_bytes())),
+            _ => data.extend_from_slice(&frame.to_bytes()),
+        }
+        Ok(data)
+    }
+}
+
+// Zstd (standard, level 0). A shared segment goes out as a zstd frame of its own between the
+// client's parts, compressed once for every client: decoders return consecutive frames as one.
+fn zstd_frame(frame: &DrawnFrame) -> io::Result<Vec<u8>> {
+    let Some(shared) = &frame.shared else { return zstd::encode_all(&frame.to_bytes()[..], 0) };
+    let mut data = zstd::encode_all(&frame.before[..], 0)?;
+    data.extend_from_slice(&shared.encoded(|raw| zstd::encode_all(raw, 0).map(Into::into).unwrap_or_default()));
+    if !frame.after.is_empty() {
+        data.extend(zstd::encode_all(&frame.after[..], 0)?);
+    }
+    Ok(data)
+}
@@ -0,0 +1,241 @@
+use engine::mlua;
+use engine::plugin::{Plugin, PluginContext};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::plugins::CONFIG_FILE;
+
+// --- Content Hygiene ---
+// Always-on built-in plugin providing api.generate_name() and api.filter_text(text).
+// Default wordlists ship with the server (wordlists/*.txt); games can replace or
+// extend them in cleoselene.toml:
+//
+//   [content]
+//   adjectives = "words/adjectives.txt"   # relative to the game directory
+//   nouns = "words/nouns.txt"
+//   blocklist = "words/blocked.txt"        # replaces the default list
+//   extra_blocked = ["darn"]               # added to the list in use
+//   allowed = ["damn"]                     # never flagged
+//   names_per_minute = 10                  # per session, for api.generate_name(session_id)
+
+const DEFAULT_ADJECTIVES: &str = include_str!("../wordlists/adjectives.txt");
+const DEFAULT_NOUNS: &str = include_str!("../wordlists/nouns.txt");
+const DEFAULT_BLOCKLIST: &str = include_str!("../wordlists/blocklist.txt");
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize, Default)]
+struct Config {
+    #[serde(default)]
+    content: ContentConfig,
+}
+
+#[derive(Deserialize)]
+struct ContentConfig {
+    adjectives: Option<String>,
+    nouns: Option<String>,
+    blocklist: Option<String>,
+    #[serde(default)]
+    extra_blocked: Vec<String>,
+    #[serde(default)]
+    allowed: Vec<String>,
+    #[serde(default = "default_names_per_minute")]
+    names_per_minute: usize,
+}
+
+fn default_names_per_minute() -> usize { 10 }
+
+impl Default for ContentConfig {
+    fn default() -> Self {
+        Self {
+            adjectives: None,
+            nouns: None,
+            blocklist: None,
+            extra_blocked: Vec::new(),
+            allowed: Vec::new(),
+            names_per_minute: default_names_per_minute(),
+        }
+    }
+}
+
+// One entry per line; blank lines and # comments are skipped
+fn parse_wordlist(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+fn load_wordlist(game_dir: &Path, path: Option<&str>, default: &str) -> anyhow::Result<Vec<String>> {
+    let words = match path {
+        Some(p) => {
+            let full = game_dir.join(p);
+            let text = std::fs::read_to_string(&full)
+                .map_err(|e| anyhow::anyhow!("Cannot read wordlist {}: {}", full.display(), e))?;
+            parse_wordlist(&text)
+        }
+        None => parse_wordlist(default),
+    };
+    if words.is_empty() {
+        anyhow::bail!("Wordlist {} is empty", path.unwrap_or("(default)"));
+    }
+    Ok(words)
+}
+
+// Undo common letter substitutions so "sh1t" and "$hit" match "shit"
+fn normalize(word: &str) -> String {
+    word.chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+pub struct TextFilter {
+    blocked: HashSet<String>,
+}
+
+impl TextFilter {
+    /// Returns the text with blocked words masked by `*`, and whether anything was masked.
+    pub fn filter(&self, text: &str) -> (String, bool) {
+        let mut out = String::with_capacity(text.len());
+        let mut flagged = false;
+        let mut word = String::new();
+        let mut flush = |word: &mut String, out: &mut String| {
+            if !word.is_empty() {
+                if self.blocked.contains(&normalize(word)) {
+                    flagged = true;
+                    out.extend(std::iter::repeat('*').take(word.chars().count()));
+                } else {
+                    out.push_str(word);
+                }
+                word.clear();
+            }
+        };
+        for c in text.chars() {
+            // Substitution characters count as part of a word
+            if c.is_alphanumeric() || matches!(c, '@' | '$' | '!') {
+                word.push(c);
+            } else {
+                flush(&mut word, &mut out);
+                out.push(c);
+            }
+        }
+        flush(&mut word, &mut out);
+        (out, flagged)
+    }
+}
+
+struct NameGenerator {
+    adjectives: Vec<String>,
+    nouns: Vec<String>,
+    state: u64,
+}
+
+impl NameGenerator {
+    // xorshift64: names only need to look random
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn generate(&mut self) -> String {
+        let a = self.next() as usize % self.adjectives.len();
+        let n = self.next() as usize % self.nouns.len();
+        format!("{}{}", self.adjectives[a], self.nouns[n])
+    }
+}
+
+pub struct ContentPlugin {
+    filter: Arc<TextFilter>,
+    names: Arc<Mutex<NameGenerator>>,
+    names_per_minute: usize,
+    // Recent generate_name calls per session (survives hot reloads)
+    recent: Arc<Mutex<HashMap<String, Vec<Instant>>>>,
+}
+
+impl ContentPlugin {
+    pub fn load(game_dir: &Path) -> anyhow::Result<Self> {
+        let config_path = game_dir.join(CONFIG_FILE);
+        let config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+            Err(_) => Config::default(),
+        };
+        let cfg = config.content;
+
+        let allowed: HashSet<String> = cfg.allowed.iter().map(|w| normalize(w)).collect();
+        let blocked = load_wordlist(game_dir, cfg.blocklist.as_deref(), DEFAULT_BLOCKLIST)?
+            .into_iter()
+            .chain(cfg.extra_blocked)
+            .map(|w| normalize(&w))
+            .filter(|w| !allowed.contains(w))
+            .collect();
+
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        let names = NameGenerator {
+            adjectives: load_wordlist(game_dir, cfg.adjectives.as_deref(), DEFAULT_ADJECTIVES)?,
+            nouns: load_wordlist(game_dir, cfg.nouns.as_deref(), DEFAULT_NOUNS)?,
+            state: seed | 1, // xorshift must not start at 0
+        };
+
+        Ok(Self {
+            filter: Arc::new(TextFilter { blocked }),
+            names: Arc::new(Mutex::new(names)),
+            names_per_minute: cfg.names_per_minute,
+            recent: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+}
+
+impl Plugin for ContentPlugin {
+    fn name(&self) -> &str {
+        "content"
+    }
+
+    fn register(&mut self, ctx: &mut PluginContext) -> mlua::Result<()> {
+        let lua = ctx.lua();
+
+        // api.generate_name([session_id]) -> name, or nil when the session is over its rate limit
+        let names = self.names.clone();
+        let recent = self.recent.clone();
+        let limit = self.names_per_minute;
+        ctx.api().set("generate_name", lua.create_function(move |_, session_id: Option<String>| {
+            if let Some(session) = session_id {
+                let mut recent = recent.lock().unwrap();
+                let now = Instant::now();
+                recent.retain(|_, calls| {
+                    calls.retain(|t| now.duration_since(*t) < RATE_WINDOW);
+                    !calls.is_empty()
+                });
+                let calls = recent.entry(session).or_default();
+                if calls.len() >= limit {
+                    return Ok(None);
+                }
+                calls.push(now);
+            }
+            Ok(Some(names.lock().unwrap().generate()))
+        })?)?;
+
+        // api.filter_text(text) -> cleaned, flagged
+        let filter = self.filter.clone();
+        ctx.api().set("filter_text", lua.create_function(move |_, text: String| {
+            Ok(filter.filter(&text))
+        })?)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use bytes::Bytes;
+
+// --- Dev Overlay ---
+// With --dev, every client gets a banner showing the last hot reload result or the
+// current script error (OP_DEV_STATUS). A status is broadcast once when it changes and
+// sent again to clients joining later; errors stay up until the next successful reload.
+
+pub struct DevOverlay {
+    enabled: bool,
+    status: Option<(bool, String)>,
+    pending: bool,
+}
+
+impl DevOverlay {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, status: None, pending: false }
+    }
+
+    pub fn reload_ok(&mut self) {
+        self.set(true, "Reload OK".to_string());
+    }
+
+    pub fn error(&mut self, source: &str, message: &str) {
+        self.set(false, format!("{} error: {}", source, message));
+    }
+
+    fn set(&mut self, ok: bool, message: String) {
+        if !self.enabled {
+            return;
+        }
+        // The same runtime error repeats every tick: only changes are sent
+        if self.status.as_ref() != Some(&(ok, message.clone())) {
+            self.status = Some((ok, message));
+            self.pending = true;
+        }
+    }
+
+    /// The current status, for a client that just joined.
+    pub fn current(&self) -> Option<Bytes> {
+        self.status.as_ref().map(|(ok, message)| engine::dev_status_frame(*ok, message))
+    }
+
+    /// The status to broadcast, once after each change.
+    pub fn take_pending(&mut self) -> Option<Bytes> {
+        if !std::mem::take(&mut self.pending) {
+            return None;
+        }
+        self.current()
+    }
+}
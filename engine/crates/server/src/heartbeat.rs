@@ -0,0 +1,63 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::throttle::Rtt;
+
+// --- Heartbeat ---
+// Every HEARTBEAT_INTERVAL the network task sends the client a PING (over the reliable
+// DataChannel once open, the WebSocket until then); the client answers with a PONG on the
+// same channel. The answers give the round-trip time (api.get_ping, api.get_client_stats);
+// a client missing MISSED_HEARTBEATS in a row is disconnected, instead of lingering as a
+// ghost player until the TCP stack gives up on it.
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+pub const MISSED_HEARTBEATS: u32 = 5;
+/// Marks a PONG on the DataChannel, where other text is text input: "\x06<id>".
+pub const PONG_PREFIX: char = '\u{6}';
+
+pub type SharedHeartbeat = Arc<Mutex<Heartbeat>>;
+
+pub struct Heartbeat {
+    next_id: u32,
+    waiting: Option<(u32, Instant)>, // The PING not answered yet
+    missed: u32,
+    rtt: Rtt,
+}
+
+impl Heartbeat {
+    pub fn new(rtt: Rtt) -> SharedHeartbeat {
+        Arc::new(Mutex::new(Self { next_id: 0, waiting: None, missed: 0, rtt }))
+    }
+
+    /// The id of the next PING. The previous one counts as missed if it is still waiting.
+    pub fn ping(&mut self) -> u32 {
+        if self.waiting.is_some() {
+            self.missed += 1;
+        }
+        self.next_id = self.next_id.wrapping_add(1);
+        self.waiting = Some((self.next_id, Instant::now()));
+        self.next_id
+    }
+
+    /// A PONG: the round-trip time is smoothed over the last few answers. Late answers
+    /// (to an earlier PING) are ignored.
+    pub fn pong(&mut self, id: u32) {
+        let Some((waiting, sent)) = self.waiting else { return };
+        if id != waiting {
+            return;
+        }
+        self.waiting = None;
+        self.missed = 0;
+        let sample = sent.elapsed().as_secs_f32() * 1000.0;
+        let mut rtt = self.rtt.lock().unwrap();
+        *rtt = Some(match *rtt {
+            Some(smoothed) => smoothed * 0.875 + sample * 0.125,
+            None => sample,
+        });
+    }
+
+    /// Whether the client stopped answering.
+    pub fn dead(&self) -> bool {
+        self.missed >= MISSED_HEARTBEATS
+    }
+}
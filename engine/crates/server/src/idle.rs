@@ -0,0 +1,103 @@
+use serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::plugins::CONFIG_FILE;
+
+// --- Empty-Room Policy ---
+// What the game loop does while no client is connected. New clients are still
+// accepted every frame, so the room resumes on the very next join. The script's
+// on_room_idle() hook runs whenever the last client leaves, whatever the policy.
+//
+//   [idle]
+//   policy = "pause"   # "run" (default): keep ticking update()
+//                      # "pause": skip update() until someone joins
+//                      # "slow": tick update() at `tick_rate` Hz
+//   tick_rate = 2
+
+#[derive(Deserialize, Default)]
+struct Config {
+    idle: Option<IdleConfig>,
+}
+
+#[derive(Deserialize)]
+struct IdleConfig {
+    #[serde(default = "default_policy")]
+    policy: String,
+    #[serde(default = "default_tick_rate")]
+    tick_rate: f32,
+}
+
+fn default_policy() -> String { "run".to_string() }
+fn default_tick_rate() -> f32 { 2.0 }
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdlePolicy {
+    Run,
+    Pause,
+    Slow(Duration),
+}
+
+pub struct RoomIdle {
+    policy: IdlePolicy,
+    empty: bool,
+}
+
+impl RoomIdle {
+    pub fn load(game_dir: &Path) -> anyhow::Result<Self> {
+        let config_path = game_dir.join(CONFIG_FILE);
+        let config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+            Err(_) => Config::default(),
+        };
+        let policy = match config.idle {
+            None => IdlePolicy::Run,
+            Some(cfg) => match cfg.policy.as_str() {
+                "run" => IdlePolicy::Run,
+                "pause" => IdlePolicy::Pause,
+                "slow" => {
+                    if !(cfg.tick_rate > 0.0) {
+                        anyhow::bail!("[idle] tick_rate must be greater than 0");
+                    }
+                    IdlePolicy::Slow(Duration::from_secs_f32(1.0 / cfg.tick_rate))
+                }
+                other => anyhow::bail!("Unknown idle policy '{}' (expected run, pause or slow)", other),
+            },
+        };
+        // The room starts empty; the hook only fires when it empties again
+        Ok(Self { policy, empty: true })
+    }
+
+    /// Records whether anyone is connected this frame. Returns true when the last
+    /// client just left (time to call on_room_idle).
+    pub fn set_occupied(&mut self, occupied: bool) -> bool {
+        let just_emptied = !occupied && !self.empty;
+        if occupied && self.empty && self.policy != IdlePolicy::Run {
+            tracing::info!("Room active, resuming normal tick rate");
+        }
+        if just_emptied && self.policy != IdlePolicy::Run {
+            tracing::info!("Room empty, idle policy: {:?}", self.policy);
+        }
+        self.empty = !occupied;
+        just_emptied
+    }
+
+    /// Whether update() should run this frame, given the time since the last update.
+    pub fn should_update(&self, since_last_update: Duration) -> bool {
+        if !self.empty {
+            return true;
+        }
+        match self.policy {
+            IdlePolicy::Run => true,
+            IdlePolicy::Pause => false,
+            IdlePolicy::Slow(interval) => since_last_update >= interval,
+        }
+    }
+
+    /// Whether the last update was skipped for being paused: the first tick after a
+    /// join then uses a normal frame dt instead of the whole paused span.
+    pub fn paused(&self) -> bool {
+        self.empty && self.policy == IdlePolicy::Pause
+    }
+}
@@ -0,0 +1,114 @@
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::logging;
+
+// --- Connection Limits ---
+// Middleware on /ws, per client IP address: at most `max_sessions` WebSocket sessions at
+// once (--max-sessions-per-ip) and new ones no faster than `joins_per_minute`
+// (--joins-per-minute, with bursts of up to that many). Refused upgrades get
+// 429 Too Many Requests, so a tab stuck in a reload loop can't flood the rooms' join
+// queues. Behind a reverse proxy every client has the proxy's address: --forwarded-for
+// takes it from the X-Forwarded-For header instead. 0 turns a limit off.
+
+// How long an address without sessions is remembered (its join budget refills meanwhile)
+const FORGET_AFTER: Duration = Duration::from_secs(60);
+
+struct Address {
+    sessions: usize,
+    tokens: f32, // Joins available now
+    refilled: Instant,
+}
+
+pub struct ConnectionLimits {
+    max_sessions: usize,
+    joins_per_minute: u32,
+    forwarded_for: bool,
+    addresses: Mutex<HashMap<IpAddr, Address>>,
+}
+
+/// Counts as one of the address's sessions until dropped (with the WebSocket).
+pub struct SessionPermit {
+    limits: Arc<ConnectionLimits>,
+    ip: IpAddr,
+}
+
+impl Drop for SessionPermit {
+    fn drop(&mut self) {
+        let mut addresses = self.limits.addresses.lock().unwrap();
+        if let Some(address) = addresses.get_mut(&self.ip) {
+            address.sessions = address.sessions.saturating_sub(1);
+        }
+    }
+}
+
+impl ConnectionLimits {
+    pub fn new(max_sessions: usize, joins_per_minute: u32, forwarded_for: bool) -> Arc<Self> {
+        Arc::new(Self { max_sessions, joins_per_minute, forwarded_for, addresses: Mutex::new(HashMap::new()) })
+    }
+
+    /// A session for `ip`, unless it has too many or joins too fast.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<SessionPermit, &'static str> {
+        let now = Instant::now();
+        let mut addresses = self.addresses.lock().unwrap();
+        addresses.retain(|_, a| a.sessions > 0 || now.duration_since(a.refilled) < FORGET_AFTER);
+        let budget = self.joins_per_minute as f32;
+        let address = addresses.entry(ip).or_insert(Address { sessions: 0, tokens: budget, refilled: now });
+
+        if self.max_sessions > 0 && address.sessions >= self.max_sessions {
+            return Err("Too many sessions from this address");
+        }
+        if self.joins_per_minute > 0 {
+            let refill = now.duration_since(address.refilled).as_secs_f32() * budget / 60.0;
+            address.tokens = (address.tokens + refill).min(budget);
+            address.refilled = now;
+            if address.tokens < 1.0 {
+                return Err("Joining too fast");
+            }
+            address.tokens -= 1.0;
+        }
+        address.sessions += 1;
+        Ok(SessionPermit { limits: self.clone(), ip })
+    }
+
+    fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        if self.forwarded_for {
+            // The proxy appends the address it saw: the last entry is the one it vouches for
+            let forwarded = headers.get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok());
+            if let Some(ip) = forwarded {
+                return ip;
+            }
+        }
+        peer.ip()
+    }
+}
+
+/// Admits the WebSocket upgrade or refuses it; the permit travels with the request
+/// (an extension) into the socket's task.
+pub async fn limit_sessions(
+    State(limits): State<Arc<ConnectionLimits>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let ip = limits.client_ip(peer, request.headers());
+    match limits.admit(ip) {
+        Ok(permit) => {
+            request.extensions_mut().insert(Arc::new(permit));
+            next.run(request).await
+        }
+        Err(reason) => {
+            logging::audit_connection_refused(&ip.to_string(), reason);
+            (StatusCode::TOO_MANY_REQUESTS, reason).into_response()
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::{TcpListener, UdpSocket};
+
+// --- Listen Addresses ---
+// --bind takes the addresses to listen on, repeated or comma-separated: an IP (on --port)
+// or an IP and port, IPv6 ones in brackets (--bind 0.0.0.0 --bind '[::]:8080'). By default
+// the server listens on every IPv4 interface. An IPv6 address only takes IPv6 connections,
+// whatever the system's dual-stack default: bind 0.0.0.0 and :: to serve both. WebRTC
+// gathers ICE candidates on the same interfaces (negotiation.rs) and --native-port
+// listens on the same IPs (native.rs).
+
+pub const DEFAULT_BIND: &str = "0.0.0.0";
+
+/// The --bind addresses, or why one isn't an address.
+pub fn parse(binds: &[String], port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    if binds.is_empty() {
+        return Ok(vec![SocketAddr::new(DEFAULT_BIND.parse().unwrap(), port)]);
+    }
+    binds.iter()
+        .map(|bind| {
+            let bind = bind.trim();
+            if let Ok(addr) = bind.parse::<SocketAddr>() {
+                return Ok(addr);
+            }
+            let ip = bind.trim_start_matches('[').trim_end_matches(']');
+            match ip.parse::<IpAddr>() {
+                Ok(ip) => Ok(SocketAddr::new(ip, port)),
+                Err(_) => anyhow::bail!("Invalid --bind address: {}", bind),
+            }
+        })
+        .collect()
+}
+
+/// The IPs of `addresses` on another port.
+pub fn with_port(addresses: &[SocketAddr], port: u16) -> Vec<SocketAddr> {
+    addresses.iter().map(|addr| SocketAddr::new(addr.ip(), port)).collect()
+}
+
+pub fn tcp_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = socket(addr, Type::STREAM)?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+pub fn udp_socket(addr: SocketAddr) -> io::Result<UdpSocket> {
+    UdpSocket::from_std(socket(addr, Type::DGRAM)?.into())
+}
+
+fn socket(addr: SocketAddr, ty: Type) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Restarts don't wait for the old connections' TIME_WAIT (as tokio's own bind)
+    #[cfg(unix)]
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket)
+}
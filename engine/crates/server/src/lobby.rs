@@ -0,0 +1,127 @@
+use engine::plugin::{Plugin, PluginContext};
+use engine::mlua::{self, LuaSerdeExt};
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+
+use crate::rooms::{RoomMessage, Rooms};
+
+// --- Lobby ---
+// Lets a script matchmake its players into rooms of their own (see rooms.rs):
+//   api.create_room(name, [opts])      starts a room, optionally running another script
+//                                      of the game: {script = "match.lua"}
+//   api.move_player(session_id, room)  sends a player there (starting the room if needed)
+//   api.list_rooms()                   {{name=, players=, script=}, ...}
+//   api.room_send(room, event, data)   a message for that room's script
+//   api.rooms_broadcast(event, data)   the same for every running room but this one
+// Moves are carried out by the game loop at the end of the tick: the player leaves this
+// room (on_disconnect) and joins the other one (on_connect) over the same connection.
+// Messages reach the other script's on_room_message(from_room, event, data) before its
+// next update(); data is anything JSON can hold, copied from one Lua state to the other.
+
+/// Players to move: (session_id, room).
+pub type Moves = Arc<Mutex<Vec<(String, String)>>>;
+
+pub struct LobbyPlugin {
+    room: String, // Where the script runs: the sender of its messages
+    rooms: Option<Arc<Rooms>>, // None outside a running server (--test, --check)
+    moves: Moves,
+}
+
+impl LobbyPlugin {
+    pub fn new(room: &str, rooms: Arc<Rooms>, moves: Moves) -> Self {
+        Self { room: room.to_string(), rooms: Some(rooms), moves }
+    }
+
+    /// No rooms to create, move players or send messages to: the functions exist but do nothing.
+    pub fn detached() -> Self {
+        Self { room: String::new(), rooms: None, moves: Moves::default() }
+    }
+}
+
+fn check_room_name(name: &str) -> mlua::Result<()> {
+    if !Rooms::valid_name(name) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "Invalid room name '{}' (letters, digits, '-' and '_', at most {} characters)", name, crate::rooms::MAX_ROOM_NAME_LEN
+        )));
+    }
+    Ok(())
+}
+
+// A script of the game: relative, inside the game directory
+fn check_script(game_dir: &Path, script: &str) -> mlua::Result<std::path::PathBuf> {
+    let relative = Path::new(script);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(mlua::Error::RuntimeError(format!("Room script '{}' must be a path inside the game directory", script)));
+    }
+    let path = game_dir.join(relative);
+    if !path.is_file() {
+        return Err(mlua::Error::RuntimeError(format!("Room script '{}' not found", script)));
+    }
+    Ok(path)
+}
+
+impl Plugin for LobbyPlugin {
+    fn name(&self) -> &str {
+        "lobby"
+    }
+
+    fn register(&mut self, ctx: &mut PluginContext) -> mlua::Result<()> {
+        let lua = ctx.lua();
+
+        // api.create_room(name, [opts]) -> true if started, false if already running
+        let rooms = self.rooms.clone();
+        ctx.api().set("create_room", lua.create_function(move |_, (name, opts): (String, Option<mlua::Table>)| {
+            check_room_name(&name)?;
+            let script: Option<String> = match &opts {
+                Some(o) => o.get("script")?,
+                None => None,
+            };
+            let Some(rooms) = &rooms else { return Ok(false) };
+            let script_path = script.map(|s| check_script(rooms.game_dir(), &s)).transpose()?;
+            Ok(rooms.open(&name, script_path))
+        })?)?;
+
+        // api.move_player(session_id, room)
+        let moves = self.moves.clone();
+        ctx.api().set("move_player", lua.create_function(move |_, (session_id, room): (String, String)| {
+            check_room_name(&room)?;
+            moves.lock().unwrap().push((session_id, room));
+            Ok(())
+        })?)?;
+
+        // api.list_rooms() -> {{name=, players=, script=}, ...} by name
+        let rooms = self.rooms.clone();
+        ctx.api().set("list_rooms", lua.create_function(move |lua, ()| {
+            let list = lua.create_table()?;
+            for (name, players, script) in rooms.iter().flat_map(|r| r.list()) {
+                let room = lua.create_table()?;
+                room.set("name", name)?;
+                room.set("players", players)?;
+                room.set("script", script.to_string_lossy().into_owned())?;
+                list.push(room)?;
+            }
+            Ok(list)
+        })?)?;
+
+        // api.room_send(room, event, [data]) -> false if the room isn't running or its inbox is full
+        let rooms = self.rooms.clone();
+        let from = self.room.clone();
+        ctx.api().set("room_send", lua.create_function(move |lua, (room, event, payload): (String, String, mlua::Value)| {
+            check_room_name(&room)?;
+            let payload: serde_json::Value = lua.from_value(payload)?;
+            let Some(rooms) = &rooms else { return Ok(false) };
+            Ok(rooms.send(&room, RoomMessage { from: from.clone(), event, payload }))
+        })?)?;
+
+        // api.rooms_broadcast(event, [data]) -> number of rooms reached
+        let rooms = self.rooms.clone();
+        let from = self.room.clone();
+        ctx.api().set("rooms_broadcast", lua.create_function(move |lua, (event, payload): (String, mlua::Value)| {
+            let payload: serde_json::Value = lua.from_value(payload)?;
+            let Some(rooms) = &rooms else { return Ok(0) };
+            Ok(rooms.broadcast(RoomMessage { from: from.clone(), event, payload }))
+        })?)?;
+
+        Ok(())
+    }
+}
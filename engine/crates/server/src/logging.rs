@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::prelude::*;
+
+// --- Logging ---
+// Everything the server reports goes through tracing (only the --test and --export-client
+// CLI modes print directly). The console gets every event; with --log-dir, two rotating
+// files are added:
+//   cleoselene.log.<date>  every tracing event
+//   audit.log.<date>       only the `audit` target: dropped frames, slow ticks,
+//                          removed clients, refused connections and hot reloads
+
+const AUDIT_TARGET: &str = "audit";
+
+// Dropped frames and slow ticks happen in bursts; they are summarized at most this often
+const AUDIT_SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sets up the global subscriber. Keep the returned guards alive for the whole process:
+/// dropping them flushes and stops the file writers.
+pub fn init(log_dir: Option<&Path>, rotation: &str) -> anyhow::Result<Vec<WorkerGuard>> {
+    let console = tracing_subscriber::fmt::layer();
+
+    let Some(dir) = log_dir else {
+        tracing_subscriber::registry().with(console).init();
+        return Ok(Vec::new());
+    };
+
+    let rotation_kind = match rotation {
+        "daily" => Rotation::DAILY,
+        "hourly" => Rotation::HOURLY,
+        "never" => Rotation::NEVER,
+        other => anyhow::bail!("Unknown log rotation '{}' (expected daily, hourly or never)", other),
+    };
+    std::fs::create_dir_all(dir)?;
+
+    let (server_writer, server_guard) = tracing_appender::non_blocking(RollingFileAppender::new(rotation_kind.clone(), dir, "cleoselene.log"));
+    let (audit_writer, audit_guard) = tracing_appender::non_blocking(RollingFileAppender::new(rotation_kind, dir, "audit.log"));
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(server_writer))
+        .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(audit_writer)
+            .with_filter(filter_fn(|meta| meta.target() == AUDIT_TARGET)))
+        .init();
+
+    tracing::info!("Logging to {:?} (rotation: {})", dir, rotation);
+    Ok(vec![server_guard, audit_guard])
+}
+
+pub fn audit_reload(ok: bool) {
+    if ok {
+        tracing::info!(target: AUDIT_TARGET, event = "reload", ok, "Hot reload applied");
+    } else {
+        tracing::warn!(target: AUDIT_TARGET, event = "reload", ok, "Hot reload failed, previous script kept");
+    }
+}
+
+pub fn audit_client_removed(session_id: &str, reason: &str) {
+    tracing::info!(target: AUDIT_TARGET, event = "client_removed", session = session_id, reason, "Client removed");
+}
+
+pub fn audit_connection_refused(ip: &str, reason: &str) {
+    tracing::warn!(target: AUDIT_TARGET, event = "connection_refused", ip, reason, "Connection refused");
+}
+
+/// Per-tick counters for the game loop, written to the audit stream as periodic summaries.
+pub struct FrameAudit {
+    dropped: BTreeMap<String, u32>,
+    slow_ticks: u32,
+    worst_tick: Duration,
+    last_flush: Instant,
+}
+
+impl FrameAudit {
+    pub fn new() -> Self {
+        Self { dropped: BTreeMap::new(), slow_ticks: 0, worst_tick: Duration::ZERO, last_flush: Instant::now() }
+    }
+
+    /// A frame could not be queued because the client's render channel was full.
+    pub fn dropped_frame(&mut self, session_id: &str) {
+        *self.dropped.entry(session_id.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn tick(&mut self, elapsed: Duration, budget: Duration) {
+        if elapsed > budget {
+            self.slow_ticks += 1;
+            self.worst_tick = self.worst_tick.max(elapsed);
+        }
+        if self.last_flush.elapsed() >= AUDIT_SUMMARY_INTERVAL {
+            self.flush(budget);
+        }
+    }
+
+    fn flush(&mut self, budget: Duration) {
+        for (session, count) in std::mem::take(&mut self.dropped) {
+            tracing::warn!(target: AUDIT_TARGET, event = "dropped_frames", session = session.as_str(), count, "Frames dropped (client lagging)");
+        }
+        if self.slow_ticks > 0 {
+            tracing::warn!(
+                target: AUDIT_TARGET, event = "slow_ticks", count = self.slow_ticks,
+                worst_ms = self.worst_tick.as_secs_f64() * 1000.0, budget_ms = budget.as_secs_f64() * 1000.0,
+                "Ticks over frame budget"
+            );
+        }
+        self.slow_ticks = 0;
+        self.worst_tick = Duration::ZERO;
+        self.last_flush = Instant::now();
+    }
+}
@@ -1,11 +1,15 @@
 use axum::{
-    extract::{Query, State, ws::{Message, WebSocket, WebSocketUpgrade}},
+    extract::{Extension, Query, State, ws::{Message, WebSocket, WebSocketUpgrade}},
+    middleware,
     response::IntoResponse,
     routing::{get, post},
     Router,
 };
-use engine::GameState;
-use futures::{sink::SinkExt, stream::StreamExt};
+use engine::{DrawnFrame, EngineError, GameState};
+use engine::input::{InputMessage, InputMeta, PASTE_PREFIX};
+use futures::{sink::SinkExt, stream::StreamExt, FutureExt};
+use std::future::IntoFuture;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -19,16 +23,59 @@ use std::sync::mpsc::channel;
 use std::path::{Path, PathBuf};
 use clap::Parser;
 use rust_embed::RustEmbed;
-use axum::http::{header, StatusCode, Uri};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+
+mod plugins;
+mod content;
+mod storage;
+use storage::StoragePlugin;
+mod save_queue;
+use engine::plugin::Plugin;
+use engine::shared::SharedData;
+mod analytics;
+use analytics::Analytics;
+mod logging;
+use logging::FrameAudit;
+mod rewind;
+use rewind::{InputRecord, RewindBuffer};
+mod idle;
+use idle::RoomIdle;
+mod asset_check;
+mod manifest;
+use manifest::AssetManifest;
+mod dev_overlay;
+use dev_overlay::DevOverlay;
+mod standby;
+use standby::Standby;
+mod negotiation;
+use negotiation::{Negotiator, Outcome};
+mod rooms;
+use rooms::{DebugRequests, RewindRequests, RoomConfig, Rooms, DEFAULT_ROOM, EMPTY_ROOM_TTL};
+mod lobby;
+use lobby::{LobbyPlugin, Moves};
+mod resume;
+use resume::{Away, Resume};
+mod throttle;
+use throttle::{Rtt, Throttle};
+mod shutdown;
+mod heartbeat;
+mod limits;
+mod auth;
+use auth::Authenticator;
+mod waiting;
+use waiting::WaitingRoom;
+mod native;
+mod codec;
+use codec::FrameEncoding;
+mod listen;
+mod tls;
+use limits::{ConnectionLimits, SessionPermit};
+use heartbeat::{Heartbeat, SharedHeartbeat, HEARTBEAT_INTERVAL, PONG_PREFIX};
 
 // WebRTC Imports
-use webrtc::api::interceptor_registry::register_default_interceptors;
-use webrtc::api::media_engine::MediaEngine;
-use webrtc::api::APIBuilder;
-use webrtc::ice_transport::ice_server::RTCIceServer;
-use webrtc::interceptor::registry::Registry;
-use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 
 // --- Architecture Types ---
@@ -52,6 +99,19 @@ struct Cli {
     #[arg(long, default_value_t = 3425)]
     port: u16,
 
+    /// Address to listen on: an IP (on --port) or IP:port, IPv6 in brackets, e.g. 0.0.0.0,
+    /// [::] or [::1]:8080 (repeatable; default 0.0.0.0). WebRTC uses the same interfaces
+    #[arg(long, value_delimiter = ',')]
+    bind: Vec<String>,
+
+    /// TLS certificate chain (PEM): serve HTTPS and WSS instead of HTTP (needs --tls-key)
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key (PEM) of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
     /// Base path for the application (e.g. /game)
     #[arg(long, default_value = "/")]
     base_path: String,
@@ -73,48 +133,218 @@ struct Cli {
     /// Initializes the engine, runs init() and one update() cycle, then exits.
     #[arg(long)]
     test: bool,
+
+    /// Check that every asset the script loads from /assets (load_sound/load_image during
+    /// init() and on_connect()) exists, print the missing ones, then exit
+    #[arg(long)]
+    check: bool,
+
+    /// Development mode: show hot reload results and script errors as a banner in the browser
+    #[arg(long)]
+    dev: bool,
+
+    /// Start even if assets referenced by the script are missing
+    #[arg(long)]
+    skip_asset_check: bool,
+
+    /// Write rotating log files (server log + audit log of dropped frames,
+    /// slow ticks, removed clients and reloads) to this directory
+    #[arg(long)]
+    log_dir: Option<PathBuf>,
+
+    /// Keep this many seconds of snapshot() history and enable POST /admin/rewind?seconds=N
+    /// (development servers only: the script must define snapshot() and restore(state))
+    #[arg(long)]
+    rewind: Option<f32>,
+
+    /// Directory for what the server writes while running (storage file, ...). Default:
+    /// `<game directory>.state` next to the game directory, outside the hot reload watch
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
+    /// Simulation steps (update) per second; scripts can ask for another rate with api.set_tick_rate
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=engine::MAX_TICK_RATE as i64))]
+    tick_rate: u32,
+
+    /// Frames sent to each client per second (default: one per tick, never more)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=engine::MAX_TICK_RATE as i64))]
+    send_rate: Option<u32>,
+
+    /// Rooms running at once, the default one included; clients asking for another room
+    /// are refused (0: no limit)
+    #[arg(long, default_value_t = 100)]
+    max_rooms: usize,
+
+    /// Players per room; more wait in line until a slot frees up (default: no limit)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    max_players: Option<u64>,
+
+    /// STUN server for WebRTC, e.g. stun:stun.l.google.com:19302 (repeatable)
+    #[arg(long, env = "CLEOSELENE_STUN", value_delimiter = ',')]
+    stun: Vec<String>,
+
+    /// TURN server relaying WebRTC for clients behind symmetric NAT, e.g.
+    /// turn:turn.example.com:3478?transport=udp (repeatable; needs --turn-user and --turn-pass)
+    #[arg(long, env = "CLEOSELENE_TURN", value_delimiter = ',')]
+    turn: Vec<String>,
+
+    /// TURN username
+    #[arg(long, env = "CLEOSELENE_TURN_USER")]
+    turn_user: Option<String>,
+
+    /// TURN password (sent to every client, like the username)
+    #[arg(long, env = "CLEOSELENE_TURN_PASS", hide_env_values = true)]
+    turn_pass: Option<String>,
+
+    /// WebSocket sessions one IP address may have open at once (0: no limit)
+    #[arg(long, default_value_t = 32)]
+    max_sessions_per_ip: usize,
+
+    /// New WebSocket sessions one IP address may open per minute, in bursts of up to as
+    /// many (0: no limit)
+    #[arg(long, default_value_t = 60)]
+    joins_per_minute: u32,
+
+    /// Behind a reverse proxy: count sessions by the client address in X-Forwarded-For
+    /// instead of the proxy's
+    #[arg(long)]
+    forwarded_for: bool,
+
+    /// Require players to log in with an HS256 JSON Web Token signed with this secret
+    /// (?token= on the page URL); its claims reach on_connect(session_id, auth_info)
+    #[arg(long, env = "CLEOSELENE_AUTH_JWT_SECRET", hide_env_values = true, conflicts_with = "auth_url")]
+    auth_jwt_secret: Option<String>,
+
+    /// Require players to log in with a token this HTTP endpoint verifies: it gets
+    /// POST {"token": ...} and answers 2xx with the player's identity as JSON
+    #[arg(long, env = "CLEOSELENE_AUTH_URL")]
+    auth_url: Option<String>,
+
+    /// Also listen for native clients (bots, load tests, custom frontends) on this TCP port:
+    /// the WebSocket protocol over plain length-prefixed messages
+    #[arg(long)]
+    native_port: Option<u16>,
+
+    /// With --native-port: send native clients their frames over UDP (same port) once they
+    /// ask for it
+    #[arg(long, requires = "native_port")]
+    native_udp: bool,
+
+    /// Frames smaller than this many bytes go uncompressed to clients that accept it
+    #[arg(long, default_value_t = codec::COMPRESS_MIN_BYTES)]
+    compress_min_bytes: usize,
+
+    /// Log file rotation: daily, hourly or never
+    #[arg(long, default_value = "daily")]
+    log_rotation: String,
+}
+
+// Input from a client: key, mouse, touch and gamepad events (binary messages, see engine::input), text answering
+// api.request_text_input and pasted text (string DataChannel message, or TEXT_INPUT over the WebSocket)
+// a sound that finished playing, the end of preloading, settings menu changes and script
+// messages (SOUND_FINISHED, ASSETS_READY, SETTING and MESSAGE over the WebSocket)
+enum ClientInput {
+    Message(InputMessage, Option<InputMeta>),
+    Text(String),
+    Paste(String),
+    SoundFinished(String),
+    AssetsReady,
+    Setting(String, serde_json::Value),
+    ScriptMessage(serde_json::Value), // on_message
 }
 
 struct ClientConnection {
     session_id: String,
-    tx_render: mpsc::Sender<bytes::Bytes>,
-    rx_input: mpsc::Receiver<(u8, bool)>,
+    capabilities: u32, // engine::compat::CAP_* announced by the client
+    tx_render: mpsc::Sender<DrawnFrame>,
+    // api.send_message, as MESSAGE signals: reliable and in order, unlike frames
+    tx_messages: mpsc::UnboundedSender<String>,
+    rx_input: mpsc::Receiver<ClientInput>,
+    rtt: Rtt, // Measured by the network task
+    spectator: bool, // ?spectate=1: drawn, but not a player (engine on_spectate)
+    auth: Option<serde_json::Value>, // The verified identity (auth.rs)
 }
 
-// Global state used by Axum to push new clients to the game loop
+impl ClientInput {
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (message, meta) = engine::input::decode_sequenced(data)?;
+        Some(ClientInput::Message(message, meta))
+    }
+
+    fn text(text: String, paste: bool) -> Self {
+        if paste { ClientInput::Paste(text) } else { ClientInput::Text(text) }
+    }
+}
+
+// Global state used by Axum to push new clients to the game loops
 struct AppState {
-    // Queue of new clients waiting to join the game loop
-    new_clients: Arc<Mutex<Vec<ClientConnection>>>,
+    rooms: Arc<Rooms>, // Game loops by room (see rooms.rs)
     base_path: String,
     assets_dir: PathBuf,
     instance_id: String,
-    tx_debug: Option<mpsc::Sender<(String, oneshot::Sender<String>)>>,
     custom_client_dir: Option<PathBuf>,  // Custom client directory (--client)
+    negotiator: Arc<Negotiator>,         // WebRTC setup pool (see negotiation.rs)
+    auth: Option<Authenticator>,         // --auth-jwt-secret / --auth-url (see auth.rs)
+    compress_min_bytes: usize,           // --compress-min-bytes (see codec.rs)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 enum SignalMessage {
-    WELCOME { session_id: String, server_instance_id: String },
+    WELCOME {
+        session_id: String,
+        server_instance_id: String,
+        #[serde(skip_serializing_if = "Vec::is_empty", skip_deserializing)]
+        ice_servers: Vec<negotiation::IceServer>, // --stun/--turn (none: the client's default)
+    },
     OFFER { sdp: String },
     ANSWER { sdp: String },
     CANDIDATE { candidate: String, sdp_mid: Option<String>, sdp_mline_index: Option<u16> },
+    TEXT_INPUT { text: String, #[serde(default)] paste: bool },
+    SOUND_FINISHED { name: String },
+    ASSET_MANIFEST(AssetManifest),
+    ASSETS_READY,
+    SETTING { key: String, value: serde_json::Value },
+    MESSAGE { data: serde_json::Value },
+    SHUTDOWN, // The server is stopping (see shutdown.rs)
+    UDP { port: u16, key: String }, // Native clients: where to ask for frames over UDP (native.rs)
+    REFUSED { reason: String },     // Native clients: why the connection closes (WebSockets get an HTTP status)
+    PING { id: u32 }, // Answered with PONG (see heartbeat.rs)
+    PONG { id: u32 },
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-
     let args = Cli::parse();
 
+    // Initialize logging (guards flush the log files on exit)
+    let _log_guards = match logging::init(args.log_dir.as_deref(), &args.log_rotation) {
+        Ok(g) => g,
+        Err(e) => {
+            eprintln!("Logging Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let state_dir = state_dir(&args.script_path, args.state_dir.as_deref());
+    // One storage backend for the whole process: every room (and check) gets a handle
+    let storage = match StoragePlugin::load(args.script_path.parent().unwrap_or(Path::new(".")), &state_dir) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Storage Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Test Mode
     if args.test {
         println!("Running in TEST mode: {:?}", args.script_path);
         let script_path_str = args.script_path.to_string_lossy().to_string();
+        let mut plugins = load_plugins_or_exit(&args.script_path, storage.for_room(), LobbyPlugin::detached());
+        let shared = load_shared_data_or_exit(&args.script_path);
         
-        match load_game(&script_path_str) {
-            Some(game) => {
+        match load_game(&script_path_str, &mut plugins, &shared) {
+            Ok(game) => {
                 println!("Script loaded successfully.");
                 // Try running one update step
                 if let Err(e) = game.update(0.1) {
@@ -124,13 +354,21 @@ async fn main() {
                 println!("Test Passed: init() and update() executed without errors.");
                 std::process::exit(0);
             }
-            None => {
+            Err(_) => {
                 eprintln!("Test Failed: Could not load script.");
                 std::process::exit(1);
             }
         }
     }
 
+    // Check Mode
+    if args.check {
+        let mut plugins = load_plugins_or_exit(&args.script_path, storage.for_room(), LobbyPlugin::detached());
+        let shared = load_shared_data_or_exit(&args.script_path);
+        let ok = asset_check::report(&asset_check::check(&args.script_path, &mut plugins, &shared));
+        std::process::exit(if ok { 0 } else { 1 });
+    }
+
     // Export Client Mode
     if let Some(target_dir) = args.export_client {
         println!("Exporting client assets to {:?}...", target_dir);
@@ -156,29 +394,68 @@ async fn main() {
         std::process::exit(0);
     }
     
-    println!("Starting Cleoselene Server...");
-    println!("Script: {:?}", args.script_path);
-    println!("Port: {}", args.port);
-    println!("Base Path: {}", args.base_path);
-
-    let new_clients_queue = Arc::new(Mutex::new(Vec::new()));
-    
-    // Debug Channel
-    let (tx_debug, rx_debug) = if args.debug {
-        let (tx, rx) = mpsc::channel(10);
-        println!("Debug endpoint enabled at /debug");
-        (Some(tx), Some(rx))
-    } else {
-        (None, None)
+    tracing::info!("Starting Cleoselene Server...");
+    tracing::info!("Script: {:?}", args.script_path);
+    #[cfg(feature = "luajit")]
+    tracing::warn!("LuaJIT build, scripts run without a memory limit (see MANUAL: Sandbox)");
+
+    // Fail fast instead of letting clients 404 mid-game
+    if !args.skip_asset_check {
+        let mut plugins = load_plugins_or_exit(&args.script_path, storage.for_room(), LobbyPlugin::detached());
+        let shared = load_shared_data_or_exit(&args.script_path);
+        if !asset_check::report(&asset_check::check(&args.script_path, &mut plugins, &shared)) {
+            tracing::error!("Refusing to start (use --skip-asset-check to start anyway)");
+            std::process::exit(1);
+        }
+    }
+    let addresses = match listen::parse(&args.bind, args.port) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            tracing::error!("Config Error: {}", e);
+            std::process::exit(1);
+        }
     };
+    tracing::info!("Port: {}", args.port);
+    tracing::info!("Base Path: {}", args.base_path);
 
-    // Start the Global Game Loop
-    let queue_clone = new_clients_queue.clone();
-    let script_path = args.script_path.clone();
-    
-    thread::spawn(move || {
-        game_loop(queue_clone, script_path, rx_debug);
+    if args.debug {
+        tracing::info!("Debug endpoint enabled at /debug");
+    }
+    let rewind = args.rewind.map(|seconds| match Duration::try_from_secs_f32(seconds) {
+        Ok(window) => window,
+        Err(_) => {
+            tracing::error!("Invalid --rewind {}: expected a number of seconds", seconds);
+            std::process::exit(1);
+        }
     });
+    if let Some(window) = rewind {
+        tracing::info!("Rewind buffer: {}s, endpoint enabled at /admin/rewind", window.as_secs_f32());
+    }
+    if args.dev {
+        tracing::info!("Dev overlay enabled");
+    }
+
+    let analytics = match Analytics::load(args.script_path.parent().unwrap_or(Path::new(".")), &state_dir) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Analytics Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Start the default room; the others start with their first client
+    let rooms = Rooms::new(RoomConfig {
+        script_path: args.script_path.clone(),
+        debug: args.debug,
+        rewind,
+        dev: args.dev,
+        tick_rate: args.tick_rate,
+        send_rate: args.send_rate,
+        max_players: args.max_players.map(|n| n as usize),
+        state_dir,
+        max_rooms: (args.max_rooms > 0).then_some(args.max_rooms),
+    }, analytics, storage);
+    rooms.open(DEFAULT_ROOM, None);
 
     // Determine assets dir (parent of script)
     let assets_dir = args.script_path.parent().unwrap_or(Path::new(".")).to_path_buf();
@@ -186,28 +463,60 @@ async fn main() {
     // Custom client directory handling
     let custom_client_dir = args.client.clone();
     if let Some(ref client_dir) = custom_client_dir {
-        println!("Using custom client: {:?}", client_dir);
+        tracing::info!("Using custom client: {:?}", client_dir);
     }
     
+    let ice_servers = match negotiation::ice_servers(&args.stun, &args.turn, args.turn_user.as_deref(), args.turn_pass.as_deref()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Config Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // ICE candidates only on the interfaces given to --bind
+    let ice_ips: Vec<_> = if args.bind.is_empty() { Vec::new() } else { addresses.iter().map(SocketAddr::ip).collect() };
+    let negotiator = match Negotiator::load(&assets_dir, ice_servers, &ice_ips) {
+        Ok(n) => n,
+        Err(e) => {
+            tracing::error!("Config Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Per-address session caps and join rate (limits.rs), in front of /ws
+    let connection_limits = ConnectionLimits::new(args.max_sessions_per_ip, args.joins_per_minute, args.forwarded_for);
+    let ws_route = get(ws_handler).route_layer(middleware::from_fn_with_state(connection_limits.clone(), limits::limit_sessions));
+
     // Generate unique ID for this server process run
     let instance_id = Uuid::new_v4().to_string();
-    println!("Server Instance ID: {}", instance_id);
+    tracing::info!("Server Instance ID: {}", instance_id);
 
     let app_state = Arc::new(AppState {
-        new_clients: new_clients_queue,
+        rooms: rooms.clone(),
         base_path: args.base_path.clone(),
         assets_dir: assets_dir.clone(),
         instance_id,
-        tx_debug,
         custom_client_dir: custom_client_dir.clone(),
+        negotiator,
+        auth: Authenticator::from_args(args.auth_jwt_secret.as_deref(), args.auth_url.as_deref()),
+        compress_min_bytes: args.compress_min_bytes,
     });
 
+    // Native clients (native.rs), under the same limits
+    if let Some(port) = args.native_port {
+        if let Err(e) = native::serve(&listen::with_port(&addresses, port), args.native_udp, app_state.clone(), connection_limits) {
+            tracing::error!("Native listener Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     // Build router based on whether custom client is used
     let app = if let Some(ref client_dir) = custom_client_dir {
         // Serve from custom client directory
         Router::new()
-            .route("/ws", get(ws_handler))
+            .route("/ws", ws_route)
             .route("/debug", post(debug_handler))
+            .route("/admin/rewind", post(rewind_handler))
             .nest_service("/assets", ServeDir::new(assets_dir))
             .fallback_service(ServeDir::new(client_dir).append_index_html_on_directories(true))
             .layer(TraceLayer::new_for_http())
@@ -215,8 +524,9 @@ async fn main() {
     } else {
         // Serve from embedded assets
         Router::new()
-            .route("/ws", get(ws_handler))
+            .route("/ws", ws_route)
             .route("/debug", post(debug_handler))
+            .route("/admin/rewind", post(rewind_handler))
             .route("/", get(serve_index))
             .route("/index.html", get(serve_index))
             .nest_service("/assets", ServeDir::new(assets_dir))
@@ -225,14 +535,71 @@ async fn main() {
             .with_state(app_state)
     };
 
-    let addr = format!("0.0.0.0:{}", args.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    println!("Listening on http://localhost:{}", args.port);
-    axum::serve(listener, app).await.unwrap();
+    // HTTPS (tls.rs)
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => match tls::load(cert, key).await {
+            Ok(config) => Some(config),
+            Err(e) => {
+                tracing::error!("TLS Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let mut listeners = Vec::new();
+    for addr in &addresses {
+        match listen::tcp_listener(*addr) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => {
+                tracing::error!("Failed to listen on {}: {}", addr, e);
+                std::process::exit(1);
+            }
+        }
+        tracing::info!("Listening on {}://{}", if tls.is_some() { "https" } else { "http" }, addr);
+    }
+
+    // SIGINT/SIGTERM: the rooms close first, then the listeners (see shutdown.rs)
+    let (tx_stop, rx_stop) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        tracing::info!("Shutting down...");
+        rooms.shut_down();
+        if !rooms.closed(shutdown::SHUTDOWN_TIMEOUT).await {
+            tracing::warn!("Rooms still running after {}s, exiting anyway", shutdown::SHUTDOWN_TIMEOUT.as_secs());
+        }
+        // The rooms' last events (session_end, ...) are queued by now
+        let analytics = rooms.analytics().clone();
+        let _ = tokio::task::spawn_blocking(move || analytics.shut_down()).await;
+        let _ = tx_stop.send(true);
+    });
+    let servers = listeners.into_iter().map(|listener| {
+        let mut rx_stop = rx_stop.clone();
+        let stop = async move {
+            let _ = rx_stop.wait_for(|stop| *stop).await;
+        };
+        match &tls {
+            Some(config) => tls::serve(listener, config.clone(), app.clone(), stop).boxed(),
+            None => axum::serve(listener, app.clone().into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(stop)
+                .into_future()
+                .boxed(),
+        }
+    });
+    for result in futures::future::join_all(servers).await {
+        result.unwrap();
+    }
 }
 
-async fn debug_handler(State(state): State<Arc<AppState>>, body: String) -> impl IntoResponse {
-    if let Some(tx) = &state.tx_debug {
+#[derive(Deserialize)]
+struct RoomParams {
+    room: Option<String>,
+}
+
+async fn debug_handler(State(state): State<Arc<AppState>>, Query(params): Query<RoomParams>, body: String) -> impl IntoResponse {
+    if !state.rooms.config().debug {
+        return "Debug disabled".to_string();
+    }
+    if let Some(tx) = state.rooms.tx_debug(params.room.as_deref().unwrap_or(DEFAULT_ROOM)) {
         let (reply_tx, reply_rx) = oneshot::channel();
         if tx.send((body, reply_tx)).await.is_ok() {
             if let Ok(result) = reply_rx.await {
@@ -241,7 +608,33 @@ async fn debug_handler(State(state): State<Arc<AppState>>, body: String) -> impl
         }
         "Error: Game loop unresponsive".to_string()
     } else {
-        "Debug disabled".to_string()
+        "Error: No such room".to_string()
+    }
+}
+
+#[derive(Deserialize)]
+struct RewindParams {
+    seconds: f32,
+    room: Option<String>,
+}
+
+async fn rewind_handler(State(state): State<Arc<AppState>>, Query(params): Query<RewindParams>) -> impl IntoResponse {
+    if state.rooms.config().rewind.is_none() {
+        return "Rewind disabled (start the server with --rewind <seconds>)".to_string();
+    }
+    if !params.seconds.is_finite() {
+        return "Error: seconds must be a finite number".to_string();
+    }
+    if let Some(tx) = state.rooms.tx_rewind(params.room.as_deref().unwrap_or(DEFAULT_ROOM)) {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if tx.send((params.seconds, reply_tx)).await.is_ok() {
+            if let Ok(result) = reply_rx.await {
+                return result;
+            }
+        }
+        "Error: Game loop unresponsive".to_string()
+    } else {
+        "Error: No such room".to_string()
     }
 }
 
@@ -273,35 +666,90 @@ async fn serve_index(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }
 }
 
+// A button presses `key`, or the first key bound to `action` (see engine input actions);
+// `"joystick": true` is a touch stick reporting as a gamepad's left stick instead
 #[derive(Deserialize)]
 struct KeyDef {
+    #[serde(default)]
     label: String,
-    key: u32,
+    key: Option<u32>,
+    action: Option<String>,
+    #[serde(default)]
+    joystick: bool,
+}
+
+type Layout = Vec<Vec<KeyDef>>;
+
+// keys.json is either a single layout (rows of buttons) or named schemes
+// switched at runtime with api.set_control_scheme(session_id, name):
+//   { "default": "gameplay", "schemes": { "menu": [[...]], "gameplay": [[...]] } }
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeysFile {
+    Schemes { default: Option<String>, schemes: std::collections::BTreeMap<String, Layout> },
+    Single(Layout),
 }
 
 fn generate_controls_html(assets_dir: &Path) -> String {
     let keys_path = assets_dir.join("keys.json");
-    if let Ok(file) = std::fs::File::open(keys_path) {
-        let layout: Vec<Vec<KeyDef>> = serde_json::from_reader(file).unwrap_or_default();
-        if layout.is_empty() { return String::new(); }
+    let Ok(file) = std::fs::File::open(keys_path) else { return String::new() };
+    let (default, schemes) = match serde_json::from_reader(file) {
+        Ok(KeysFile::Single(layout)) => ("default".to_string(), vec![("default".to_string(), layout)]),
+        Ok(KeysFile::Schemes { default, schemes }) => {
+            let default = default.or_else(|| schemes.keys().next().cloned()).unwrap_or_default();
+            (default, schemes.into_iter().collect())
+        }
+        Err(e) => {
+            tracing::error!("Invalid keys.json: {}", e);
+            return String::new();
+        }
+    };
+    if schemes.iter().all(|(_, layout)| layout.is_empty()) { return String::new(); }
 
-        let mut html = String::from("<div id='mobile-controls' class='touch-controls' style='display: none;'>");
+    let mut html = String::from("<div id='mobile-controls' class='touch-controls' style='display: none;'>");
+    for (name, layout) in schemes {
+        let hidden = if name == default { "" } else { " style='display: none;'" };
+        html.push_str(&format!("<div class='control-scheme' data-scheme='{}'{}>", escape_html(&name), hidden));
         for row in layout {
             let cols = row.len();
             html.push_str(&format!("<div class='control-row' style='display: grid; grid-template-columns: repeat({}, 1fr); gap: 10px;'>", cols));
             for btn in row {
-                html.push_str(&format!(
-                    "<div class='touch-btn' data-key='{}'>{}</div>",
-                    btn.key, btn.label
-                ));
+                if btn.joystick {
+                    html.push_str("<div class='touch-joystick'><div class='touch-knob'></div></div>");
+                    continue;
+                }
+                let target = match (&btn.action, btn.key) {
+                    (Some(action), _) => format!("data-action='{}'", escape_html(action)),
+                    (None, Some(key)) => format!("data-key='{}'", key),
+                    (None, None) => {
+                        tracing::warn!("keys.json: button '{}' needs a `key` or an `action`", btn.label);
+                        continue;
+                    }
+                };
+                html.push_str(&format!("<div class='touch-btn' {}>{}</div>", target, escape_html(&btn.label)));
             }
             html.push_str("</div>");
         }
         html.push_str("</div>");
-        html
-    } else {
-        String::new()
     }
+    html.push_str("</div>");
+    html
+}
+
+// keys.json strings end up in the page, as text and inside single-quoted attributes
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
 }
 
 // Serve other static files from Embedded Assets
@@ -324,12 +772,21 @@ async fn static_handler(uri: Uri) -> impl IntoResponse {
 
 struct ActiveClient {
     session_id: String,
-    tx_render: mpsc::Sender<bytes::Bytes>,
-    rx_input: mpsc::Receiver<(u8, bool)>,
+    capabilities: u32,
+    joined_at: Instant,
+    init: bytes::Bytes, // What on_connect sent, sent again when the player reconnects
+    auth: Option<serde_json::Value>,
+    tx_render: mpsc::Sender<DrawnFrame>,
+    tx_messages: mpsc::UnboundedSender<String>,
+    rx_input: mpsc::Receiver<ClientInput>,
+    rtt: Rtt,
+    throttle: Throttle, // Frames this client is sent (throttle.rs)
+    spectator: bool,
 }
 
-fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path: PathBuf, mut rx_debug: Option<mpsc::Receiver<(String, oneshot::Sender<String>)>>) {
-    println!("Global Game Loop Started");
+// One room's world (see rooms.rs); returns when the room closes
+fn game_loop(room: String, rooms: Arc<Rooms>, script_path: PathBuf, new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, mut rx_debug: Option<DebugRequests>, rewind: Option<(Duration, RewindRequests)>) {
+    tracing::info!("Game Loop Started (room '{}')", room);
     
     // Convert PathBuf to String for loading
     let script_path_str = script_path.to_string_lossy().to_string();
@@ -338,8 +795,13 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
     let (tx_notify, rx_notify) = channel();
     let mut watcher = notify::recommended_watcher(move |res: Result<Event, notify::Error>| {
         if let Ok(event) = res {
-            if event.kind.is_modify() {
-                let _ = tx_notify.send(());
+            // Only Lua sources: anything else written next to the script (a storage file,
+            // an editor's swap file) must not restart the game
+            let sources: Vec<PathBuf> = event.paths.into_iter()
+                .filter(|p| p.extension().is_some_and(|ext| ext == "lua"))
+                .collect();
+            if event.kind.is_modify() && !sources.is_empty() {
+                let _ = tx_notify.send(sources);
             }
         }
     }).expect("Failed to create watcher");
@@ -347,70 +809,227 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
     // Watch the parent directory of the script
     if let Some(parent) = script_path.parent() {
         if let Err(e) = watcher.watch(parent, RecursiveMode::Recursive) {
-             eprintln!("Failed to watch directory {:?}: {}", parent, e);
+             tracing::error!("Failed to watch directory {:?}: {}", parent, e);
         }
     } else {
          let _ = watcher.watch(Path::new("."), RecursiveMode::Recursive);
     }
 
+    // Plugins live for the whole process and re-register on every reload
+    // api.move_player: carried out below, once per tick
+    let moves = Moves::default();
+    let lobby = LobbyPlugin::new(&room, rooms.clone(), moves.clone());
+    // A broken game directory closes this room; the server and the other rooms keep running
+    let RoomSetup { mut plugins, shared, mut room_idle, mut resume } = match load_room_setup(&script_path, rooms.storage().for_room(), lobby) {
+        Ok(setup) => setup,
+        Err(e) => {
+            tracing::error!("{}: room '{}' closed", e, room);
+            rooms.close(&room);
+            return;
+        }
+    };
+
+    let analytics = rooms.analytics().clone();
+
     // Init Game
-    let mut game = load_game(&script_path_str).expect("Failed to load initial game script");
+    let Ok(mut game) = load_game(&script_path_str, &mut plugins, &shared) else {
+        tracing::error!("Failed to load initial game script: room '{}' closed", room);
+        rooms.close(&room);
+        return;
+    };
+    // Where the last shutdown left off
+    match shutdown::take_state(&rooms.config().state_dir, &room) {
+        Ok(Some(state)) => match game.restore_state(&state) {
+            Ok(()) => tracing::info!("Room '{}' restored from its shutdown state", room),
+            Err(e) => tracing::error!("Room '{}' shutdown state not restored: {}", room, e),
+        },
+        Ok(None) => {}
+        Err(e) => tracing::error!("Room '{}' shutdown state unreadable: {}", room, e),
+    }
+    let mut standby = Standby::new(script_path_str, plugins, shared);
+    let mut dev_overlay = DevOverlay::new(rooms.config().dev);
+    // New players waiting their turn (--max-players, on_before_connect)
+    let mut waiting = WaitingRoom::new(rooms.config().max_players);
     
     // Active Clients List
     let mut clients: Vec<ActiveClient> = Vec::new();
 
-    let target_fps = 30;
-    let frame_duration = Duration::from_micros(1_000_000 / target_fps);
+    // Simulation and frame rates: the CLI's, unless the script asks for others (api.set_tick_rate)
+    let (default_tick_rate, default_send_rate) = (rooms.config().tick_rate, rooms.config().send_rate);
     let mut last_time = Instant::now();
+    let mut last_send = Instant::now();
+    let mut frame_sent = true;
+    let mut frame_audit = FrameAudit::new();
+
+    let (mut rewind_buffer, mut rx_rewind) = match rewind {
+        Some((window, rx)) => (Some(RewindBuffer::new(window)), Some(rx)),
+        None => (None, None),
+    };
+    let mut tick_inputs: Vec<InputRecord> = Vec::new();
+    let mut warned_no_snapshot = false;
+    let mut reload_needed = false; // An engine error left the game unusable
+    let mut empty_since = Instant::now();
 
     loop {
-        // 1. Hot Reload
-        if rx_notify.try_recv().is_ok() {
-            while rx_notify.try_recv().is_ok() {} // Drain
-            thread::sleep(Duration::from_millis(50)); // Debounce
-            println!("Hot Reload Triggered!");
-            
-            // Load new game without state preservation
-            if let Some(new_game) = load_game(&script_path_str) {
-                game = new_game;
-                println!("Reload & Swap Successful!");
-                
-                // Re-register existing clients in the new Lua instance
-                for client in &clients {
-                    if let Ok(bytes) = game.on_connect(&client.session_id) {
-                        let _ = client.tx_render.try_send(bytes);
+        // The server is stopping: players are told, the state saved, the room closed
+        if rooms.shutting_down() {
+            shut_down_room(&room, &rooms.config().state_dir, &game, &analytics, &clients, &mut resume);
+            forward_tracked_events(&game, &analytics);
+            rooms.close(&room);
+            return;
+        }
+
+        // 1. Hot Reload: changed modules are patched in place when possible, otherwise the
+        // replacement is built in the background, then swapped in here
+        if let Ok(mut changed) = rx_notify.try_recv() {
+            while let Ok(paths) = rx_notify.try_recv() { changed.extend(paths); } // Drain
+            tracing::info!("Hot Reload Triggered!");
+            if standby.building() || !reload_modules(&game, &changed) {
+                standby.start(&game);
+            } else {
+                logging::audit_reload(true);
+                dev_overlay.reload_ok();
+            }
+        }
+        if std::mem::take(&mut reload_needed) && !standby.building() {
+            tracing::info!("Rebuilding the game from the script...");
+            standby.start(&game);
+        }
+        if let Some(result) = standby.poll(&game) {
+            match result {
+                Ok(new_game) => {
+                    forward_tracked_events(&game, &analytics);
+                    game = new_game;
+                    tracing::info!("Reload & Swap Successful!");
+                    logging::audit_reload(true);
+                    dev_overlay.reload_ok();
+
+                    // Re-register existing clients in the new Lua instance
+                    for client in &mut clients {
+                        game.set_capabilities(&client.session_id, client.capabilities);
+                        if let Some(auth) = &client.auth {
+                            game.set_auth_info(&client.session_id, auth.clone());
+                        }
+                        let joined = if client.spectator { game.on_spectate(&client.session_id) } else { game.on_connect(&client.session_id) };
+                        if let Ok(bytes) = joined {
+                            client.init = bytes.clone();
+                            let _ = client.tx_render.try_send(bytes.into());
+                        }
+                    }
+                    // Away players too, so they have a player entry to come back to
+                    for (session_id, away) in resume.iter_mut() {
+                        if let Some(auth) = &away.auth {
+                            game.set_auth_info(session_id, auth.clone());
+                        }
+                        if let Ok(bytes) = game.on_connect(session_id) {
+                            away.init = bytes;
+                        }
                     }
                 }
+                Err(e) => {
+                    logging::audit_reload(false);
+                    dev_overlay.error("Reload", &e);
+                }
             }
         }
 
         let now = Instant::now();
-        let dt = now.duration_since(last_time).as_secs_f32();
-        last_time = now;
 
-        // Reset frame state (events)
-        game.begin_frame();
+        let rate = game.tick_rate();
+        let tick_rate = rate.map_or(default_tick_rate, |r| r.tick);
+        let send_rate = rate.and_then(|r| r.send).or(default_send_rate).unwrap_or(tick_rate).min(tick_rate);
+        let frame_duration = Duration::from_secs(1) / tick_rate;
+        // Due within half a tick: sends land on ticks, so 20 Hz out of 60 is every third one
+        let send_due = now.duration_since(last_send) + frame_duration / 2 >= Duration::from_secs(1) / send_rate;
+
+        // Reset frame state (events), unless the last tick's haven't been sent yet
+        if std::mem::take(&mut frame_sent) {
+            game.begin_frame();
+        }
 
         // 2. Accept New Clients
         {
-            let mut queue = new_clients_queue.lock().unwrap();
-            while let Some(conn) = queue.pop() {
-                println!("New player joined game: {}", conn.session_id);
-                
-                // Init player and get initialization commands (e.g. load_sound)
-                match game.on_connect(&conn.session_id) {
+            // New players may have to wait their turn (waiting.rs)
+            let arrivals = std::mem::take(&mut *new_clients_queue.lock().unwrap());
+            let players = clients.iter().filter(|c| !c.spectator).count() + resume.len();
+            let returning = |id: &str| resume.holds(id) || clients.iter().any(|c| c.session_id == id);
+            for conn in waiting.admit(arrivals, &game, &analytics, players, returning) {
+                // A logged-in player's session only resumes for the same identity
+                let other_identity = !resume.matches(&conn.session_id, conn.auth.as_ref())
+                    || clients.iter().any(|c| c.session_id == conn.session_id && c.auth != conn.auth);
+                if other_identity {
+                    tracing::info!("Refused {}: the session belongs to another player", conn.session_id);
+                    logging::audit_client_removed(&conn.session_id, "session of another identity");
+                    continue; // Dropped: its connection closes
+                }
+                // A player coming back to a held session, or whose old connection
+                // hasn't been noticed as closed yet (it is dropped). Never a spectator: they
+                // get a new session id every time
+                let resumed = match resume.take(&conn.session_id) {
+                    Some(away) => Some((away.joined_at, away.init)),
+                    None => clients.iter().position(|c| c.session_id == conn.session_id)
+                        .map(|i| clients.remove(i))
+                        .map(|old| (old.joined_at, old.init)),
+                };
+                let resuming = resumed.is_some();
+                game.set_capabilities(&conn.session_id, conn.capabilities);
+                if let Some(auth) = &conn.auth {
+                    game.set_auth_info(&conn.session_id, auth.clone());
+                }
+                let (callback, joined_at, mut init, result) = match resumed {
+                    Some((joined_at, init)) => {
+                        tracing::info!("Player reconnected: {}", conn.session_id);
+                        // The new client starts from scratch: what on_connect sent goes first
+                        let _ = conn.tx_render.try_send(init.clone().into());
+                        ("on_reconnect", joined_at, init, game.on_reconnect(&conn.session_id))
+                    }
+                    None if conn.spectator => {
+                        tracing::info!("New spectator joined game: {}", conn.session_id);
+                        analytics.session_start(&conn.session_id);
+                        // Drawn like a player, without on_connect
+                        ("on_spectator_connect", Instant::now(), bytes::Bytes::new(), game.on_spectate(&conn.session_id))
+                    }
+                    None => {
+                        tracing::info!("New player joined game: {}", conn.session_id);
+                        analytics.session_start(&conn.session_id);
+                        // Init player and get initialization commands (e.g. load_sound)
+                        ("on_connect", Instant::now(), bytes::Bytes::new(), game.on_connect(&conn.session_id))
+                    }
+                };
+                match result {
                     Ok(bytes) => {
-                        let _ = conn.tx_render.try_send(bytes);
+                        if !resuming {
+                            init = bytes.clone();
+                        }
+                        let _ = conn.tx_render.try_send(bytes.into());
                     },
                     Err(e) => {
-                        eprintln!("Lua on_connect Error (Session {}): {}", conn.session_id, e);
+                        // The client never gets in: the script cleans up after the failed
+                        // callback, and the dropped connection closes
+                        recover(&e, callback, callback, Some(&conn.session_id), &analytics, &mut dev_overlay, &mut reload_needed);
+                        tracing::info!("Dropping {}: {} failed", conn.session_id, callback);
+                        let _ = game.on_disconnect(&conn.session_id);
+                        analytics.session_end(&conn.session_id, joined_at.elapsed());
+                        logging::audit_client_removed(&conn.session_id, &format!("{} failed", callback));
+                        continue;
                     }
                 }
+                if let Some(status) = dev_overlay.current() {
+                    let _ = conn.tx_render.try_send(engine::compat::downgrade(&status, conn.capabilities).into());
+                }
                 
                 clients.push(ActiveClient {
                     session_id: conn.session_id,
+                    capabilities: conn.capabilities,
+                    joined_at,
+                    init,
                     tx_render: conn.tx_render,
+                    tx_messages: conn.tx_messages,
                     rx_input: conn.rx_input,
+                    rtt: conn.rtt,
+                    throttle: Throttle::new(),
+                    spectator: conn.spectator,
+                    auth: conn.auth,
                 });
             }
         }
@@ -423,75 +1042,438 @@ fn game_loop(new_clients_queue: Arc<Mutex<Vec<ClientConnection>>>, script_path:
             }
         }
 
+        // Handle Rewind
+        if let (Some(rx), Some(buffer)) = (&mut rx_rewind, &mut rewind_buffer) {
+            if let Ok((seconds, tx)) = rx.try_recv() {
+                let result = match buffer.rewind(seconds) {
+                    Some((snapshot, report)) => match game.restore(&snapshot) {
+                        Ok(()) => {
+                            tracing::info!("Rewound {}s", report["rewound_secs"]);
+                            report.to_string()
+                        }
+                        Err(e) => {
+                            // A half-applied restore: start over from the script
+                            if recovery(&e) == Recovery::Reload {
+                                reload_needed = true;
+                            }
+                            format!("Error: restore failed: {}", e)
+                        }
+                    },
+                    None => "Error: no snapshots recorded (does the script define snapshot()?)".to_string(),
+                };
+                let _ = tx.send(result);
+            }
+        }
+
         // 3. Process Inputs & Prune Disconnected
         clients.retain_mut(|client| {
             // Read all pending inputs
             loop {
-                match client.rx_input.try_recv() {
-                    Ok((code, active)) => {
-                        if let Err(e) = game.handle_input(&client.session_id, code, active) {
-                            eprintln!("Input error {}: {}", client.session_id, e);
+                let (callback, result) = match client.rx_input.try_recv() {
+                    Ok(ClientInput::Message(message, meta)) => {
+                        if let (Some(_), InputMessage::Key(code, active)) = (&rewind_buffer, message) {
+                            tick_inputs.push(InputRecord { session_id: client.session_id.clone(), code, active });
                         }
+                        let callback = match message {
+                            InputMessage::Key(..) => "on_input",
+                            InputMessage::Mouse { .. } => "on_mouse",
+                            InputMessage::Touch { .. } => "on_touch",
+                            InputMessage::Gamepad { .. } => "on_gamepad",
+                        };
+                        (callback, game.handle_message(&client.session_id, message, meta))
                     },
+                    Ok(ClientInput::Text(text)) => ("on_text_input", game.handle_text_input(&client.session_id, &text)),
+                    Ok(ClientInput::Paste(text)) => ("on_paste", game.handle_paste(&client.session_id, &text)),
+                    Ok(ClientInput::SoundFinished(name)) => ("on_sound_finished", game.handle_sound_finished(&client.session_id, &name)),
+                    Ok(ClientInput::AssetsReady) => ("on_assets_ready", game.handle_assets_ready(&client.session_id)),
+                    Ok(ClientInput::Setting(key, value)) => ("on_setting_changed", game.handle_setting(&client.session_id, &key, &value)),
+                    Ok(ClientInput::ScriptMessage(data)) => ("on_message", game.handle_script_message(&client.session_id, &data)),
                     Err(mpsc::error::TryRecvError::Empty) => break, // No more inputs
                     Err(mpsc::error::TryRecvError::Disconnected) => {
-                        println!("Player disconnected: {}", client.session_id);
-                        let _ = game.on_disconnect(&client.session_id);
+                        connection_lost(&game, &analytics, &mut resume, client, "input channel closed");
                         return false; // Remove from list
                     }
+                };
+                if let Err(e) = result {
+                    if recover(&e, callback, "input", Some(&client.session_id), &analytics, &mut dev_overlay, &mut reload_needed) == Recovery::Kick {
+                        kick(&game, &analytics, client);
+                        return false;
+                    }
                 }
             }
             true
         });
 
-        // 4. Update World
-        if let Err(e) = game.update(dt) {
-            eprintln!("Update error: {}", e);
-        }
-
-        // 5. Render for Each Client
-        clients.retain(|client| {
-            match game.draw(&client.session_id) {
-                Ok(bytes) => {
-                    // Try to send. If receiver dropped (client closed connection), this fails.
-                    // If channel full, we drop the frame (lag), but don't disconnect.
-                    match client.tx_render.try_send(bytes) {
-                        Ok(_) => true,
-                        Err(mpsc::error::TrySendError::Full(_)) => true, // Lag
-                        Err(mpsc::error::TrySendError::Closed(_)) => {
-                             println!("Render channel closed for {}", client.session_id);
-                             let _ = game.on_disconnect(&client.session_id);
-                             false // Remove
+        // Players the script sent to other rooms keep their connection, handed to that room
+        for (session_id, target) in std::mem::take(&mut *moves.lock().unwrap()) {
+            if target == room { continue; }
+            if !rooms.accepts(&target) {
+                tracing::warn!("Not moving {}: room '{}' not available", session_id, target);
+                continue;
+            }
+            let Some(i) = clients.iter().position(|c| c.session_id == session_id) else { continue };
+            let client = clients.remove(i);
+            tracing::info!("Moving {} to room '{}'", client.session_id, target);
+            if let Err(e) = game.on_disconnect(&client.session_id) {
+                tracing::error!("Lua on_disconnect Error (Session {}): {}", client.session_id, e);
+            }
+            analytics.session_end(&client.session_id, client.joined_at.elapsed());
+            rooms.join(&target, ClientConnection {
+                session_id: client.session_id,
+                capabilities: client.capabilities,
+                tx_render: client.tx_render,
+                tx_messages: client.tx_messages,
+                rx_input: client.rx_input,
+                rtt: client.rtt,
+                spectator: client.spectator,
+                auth: client.auth,
+            });
+        }
+
+        // Away players who didn't come back in time
+        for (session_id, away) in resume.expired(now) {
+            tracing::info!("Player disconnected: {} (didn't reconnect)", session_id);
+            if let Err(e) = game.on_disconnect(&session_id) {
+                tracing::error!("Lua on_disconnect Error (Session {}): {}", session_id, e);
+            }
+            analytics.session_end(&session_id, away.joined_at.elapsed());
+            logging::audit_client_removed(&session_id, "reconnect grace period over");
+        }
+        rooms.set_players(&room, clients.iter().filter(|c| !c.spectator).count() + resume.len());
+
+        // Messages from other rooms' scripts (api.room_send, api.rooms_broadcast)
+        for message in rooms.take_messages(&room) {
+            if let Err(e) = game.on_room_message(&message.from, &message.event, &message.payload) {
+                recover(&e, "on_room_message", "on_room_message", None, &analytics, &mut dev_overlay, &mut reload_needed);
+            }
+        }
+
+        // Empty-room policy (away players, spectators and players waiting to join still count)
+        let occupied = !clients.is_empty() || !resume.is_empty() || !waiting.is_empty();
+        let was_paused = room_idle.paused();
+        if room_idle.set_occupied(occupied) {
+            if let Err(e) = game.on_room_idle() {
+                recover(&e, "on_room_idle", "on_room_idle", None, &analytics, &mut dev_overlay, &mut reload_needed);
+            }
+        }
+        if was_paused && !room_idle.paused() {
+            last_time = now - frame_duration; // Don't hand the paused span to update()
+        }
+        if occupied {
+            empty_since = now;
+        } else if room != DEFAULT_ROOM && now.duration_since(empty_since) >= EMPTY_ROOM_TTL && rooms.retire(&room) {
+            tracing::info!("Room '{}' closed (empty)", room);
+            forward_tracked_events(&game, &analytics);
+            return;
+        }
+
+        if room_idle.should_update(now.duration_since(last_time)) {
+            let dt = now.duration_since(last_time).as_secs_f32();
+            last_time = now;
+
+            // 4. Update World
+            if let Err(e) = game.update(dt) {
+                recover(&e, "update", "update", None, &analytics, &mut dev_overlay, &mut reload_needed);
+            }
+
+            // Record the post-update state for /admin/rewind
+            if let Some(buffer) = &mut rewind_buffer {
+                match game.snapshot() {
+                    Ok(Some(snapshot)) => buffer.push(snapshot, std::mem::take(&mut tick_inputs)),
+                    Ok(None) => {
+                        tick_inputs.clear();
+                        if !warned_no_snapshot {
+                            tracing::warn!("Rewind enabled but the script has no snapshot() function");
+                            warned_no_snapshot = true;
                         }
                     }
-                },
-                Err(e) => {
-                    eprintln!("Draw error {}: {}", client.session_id, e);
-                    true
+                    Err(e) => {
+                        tick_inputs.clear();
+                        tracing::warn!("Snapshot error: {}", e);
+                    }
                 }
             }
-        });
+        }
+
+        // 5. Render for Each Client (every tick unless --send-rate/api.set_tick_rate ask for fewer frames)
+        if send_due {
+            last_send = now;
+            frame_sent = true;
+            clients.retain_mut(|client| {
+                for data in game.take_messages(&client.session_id) {
+                    let signal = serde_json::to_string(&SignalMessage::MESSAGE { data }).unwrap();
+                    let _ = client.tx_messages.send(signal); // Closed: noticed below
+                }
+                // A congested client gets fewer frames, with less detail
+                if !client.throttle.due() {
+                    return true;
+                }
+                let capacity = client.tx_render.max_capacity();
+                game.set_client_stats(&client.session_id, client.throttle.stats(capacity, send_rate, &client.rtt));
+                // What api.draw_shared drew is the same Arc for everyone, compressed once
+                match game.draw_split(&client.session_id) {
+                    Ok(frame) => {
+                        // Try to send. If receiver dropped (client closed connection), this fails.
+                        // If channel full, we drop the frame (lag), but don't disconnect.
+                        match client.tx_render.try_send(frame) {
+                            Ok(_) => {
+                                client.throttle.record(capacity - client.tx_render.capacity(), capacity, false);
+                                true
+                            }
+                            Err(mpsc::error::TrySendError::Full(_)) => { // Lag
+                                frame_audit.dropped_frame(&client.session_id);
+                                game.frame_dropped(&client.session_id);
+                                client.throttle.record(capacity, capacity, true);
+                                true
+                            }
+                            Err(mpsc::error::TrySendError::Closed(_)) => {
+                                 connection_lost(&game, &analytics, &mut resume, client, "render channel closed");
+                                 false // Remove
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        if recover(&e, "draw", "draw", Some(&client.session_id), &analytics, &mut dev_overlay, &mut reload_needed) == Recovery::Kick {
+                            kick(&game, &analytics, client);
+                            return false;
+                        }
+                        true
+                    }
+                }
+            });
+        }
+
+        forward_tracked_events(&game, &analytics);
+
+        if let Some(status) = dev_overlay.take_pending() {
+            for client in &clients {
+                let _ = client.tx_render.try_send(engine::compat::downgrade(&status, client.capabilities).into());
+            }
+        }
 
         // Sleep
         let elapsed = now.elapsed();
+        frame_audit.tick(elapsed, frame_duration);
         if elapsed < frame_duration {
             thread::sleep(frame_duration - elapsed);
         }
     }
 }
 
-fn load_game(path: &str) -> Option<GameState> {
+// How the game loop answers an engine error
+#[derive(PartialEq)]
+enum Recovery {
+    Overlay, // A script bug: reported (dev overlay), the game keeps running
+    Reload,  // The game can't go on as it is: rebuilt from the script, like a hot reload
+    Kick,    // The client sent what no client should: disconnected
+}
+
+fn recovery(e: &EngineError) -> Recovery {
+    match e {
+        EngineError::LuaRuntime { .. } => Recovery::Overlay,
+        EngineError::LuaInit(_) | EngineError::Snapshot(_) => Recovery::Reload,
+        EngineError::Protocol(_) => Recovery::Kick,
+    }
+}
+
+// An engine error from `callback`: logged, recorded in analytics under `source`, then
+// answered per recovery(). Kicking is left to the caller, which owns the client; without a
+// client (update, room callbacks) a Kick is only reported on the dev overlay.
+fn recover(
+    e: &EngineError,
+    callback: &str,
+    source: &str,
+    session_id: Option<&str>,
+    analytics: &Analytics,
+    dev_overlay: &mut DevOverlay,
+    reload_needed: &mut bool,
+) -> Recovery {
+    match session_id {
+        Some(id) => tracing::error!("Lua {} Error (Session {}): {}", callback, id, e),
+        None => tracing::error!("Lua {} Error: {}", callback, e),
+    }
+    analytics.error(source, session_id, &e.to_string());
+    let answer = recovery(e);
+    match answer {
+        Recovery::Reload => *reload_needed = true,
+        Recovery::Kick if session_id.is_some() => {}
+        _ => dev_overlay.error(callback, &e.to_string()),
+    }
+    answer
+}
+
+// Removes a client from the game; dropping its channels closes the connection
+fn kick(game: &GameState, analytics: &Analytics, client: &ActiveClient) {
+    tracing::info!("Kicking {}", client.session_id);
+    let _ = game.on_disconnect(&client.session_id);
+    analytics.session_end(&client.session_id, client.joined_at.elapsed());
+    logging::audit_client_removed(&client.session_id, "protocol error");
+}
+
+// The client's connection dropped: its session is held for it to reconnect (resume.rs),
+// or it leaves the game (spectators always do)
+fn connection_lost(game: &GameState, analytics: &Analytics, resume: &mut Resume, client: &ActiveClient, reason: &str) {
+    let away = Away { since: Instant::now(), joined_at: client.joined_at, init: client.init.clone(), auth: client.auth.clone() };
+    if !client.spectator && resume.hold(&client.session_id, away) {
+        tracing::info!("Player away: {} ({}s to reconnect)", client.session_id, resume.grace().as_secs());
+        logging::audit_client_removed(&client.session_id, &format!("{}, held for reconnect", reason));
+        return;
+    }
+    tracing::info!("Player disconnected: {}", client.session_id);
+    let _ = game.on_disconnect(&client.session_id);
+    analytics.session_end(&client.session_id, client.joined_at.elapsed());
+    logging::audit_client_removed(&client.session_id, reason);
+}
+
+// on_shutdown(), then the script's last messages and SHUTDOWN to every player, then the
+// room's state to disk (see shutdown.rs). Players are not sent on_disconnect: the saved
+// state still has them.
+fn shut_down_room(room: &str, state_dir: &Path, game: &GameState, analytics: &Analytics, clients: &[ActiveClient], resume: &mut Resume) {
+    if let Err(e) = game.on_shutdown() {
+        tracing::error!("Lua on_shutdown Error: {}", e);
+        analytics.error("on_shutdown", None, &e.to_string());
+    }
+    let signal = serde_json::to_string(&SignalMessage::SHUTDOWN).unwrap();
+    for client in clients {
+        for data in game.take_messages(&client.session_id) {
+            let _ = client.tx_messages.send(serde_json::to_string(&SignalMessage::MESSAGE { data }).unwrap());
+        }
+        let _ = client.tx_messages.send(signal.clone());
+        analytics.session_end(&client.session_id, client.joined_at.elapsed());
+        logging::audit_client_removed(&client.session_id, "server shutdown");
+    }
+    for (session_id, away) in resume.iter_mut() {
+        analytics.session_end(session_id, away.joined_at.elapsed());
+    }
+    let saved = game.snapshot_state()
+        .map_err(anyhow::Error::from)
+        .and_then(|state| shutdown::save_state(state_dir, room, &state));
+    match saved {
+        Ok(path) => tracing::info!("Room '{}' closed (shutdown), state saved to {}", room, path.display()),
+        Err(e) => tracing::warn!("Room '{}' closed (shutdown), state not saved: {}", room, e),
+    }
+}
+
+// --state-dir, or `<game directory>.state` beside the game directory: files the server
+// writes there never reach the hot reload watcher, which sees the whole game directory
+fn state_dir(script_path: &Path, arg: Option<&Path>) -> PathBuf {
+    if let Some(dir) = arg {
+        return dir.to_path_buf();
+    }
+    let game_dir = script_path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let game_dir = std::fs::canonicalize(game_dir).unwrap_or_else(|_| game_dir.to_path_buf());
+    let name = game_dir.file_name().map_or_else(|| "game".into(), |n| n.to_string_lossy().into_owned());
+    game_dir.parent().unwrap_or(&game_dir).join(format!("{}.state", name))
+}
+
+// Hands api.track events over to the analytics writer (drained even when disabled)
+fn forward_tracked_events(game: &GameState, analytics: &Analytics) {
+    for event in game.take_tracked_events() {
+        analytics.track(&event.name, None, event.properties);
+    }
+}
+
+fn load_plugins_or_exit(script_path: &Path, storage: StoragePlugin, lobby: LobbyPlugin) -> Vec<Box<dyn Plugin>> {
+    let game_dir = script_path.parent().unwrap_or(Path::new("."));
+    match plugins::load_plugins(game_dir, storage, lobby) {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!("Plugin Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// What a room loads from the game directory besides the script
+struct RoomSetup {
+    plugins: Vec<Box<dyn Plugin>>,
+    shared: SharedData, // Loaded once: every reload shares the same data
+    room_idle: RoomIdle,
+    resume: Resume, // Players whose connection dropped, held for them to reconnect
+}
+
+fn load_room_setup(script_path: &Path, storage: StoragePlugin, lobby: LobbyPlugin) -> Result<RoomSetup, String> {
+    let game_dir = script_path.parent().unwrap_or(Path::new("."));
+    Ok(RoomSetup {
+        plugins: plugins::load_plugins(game_dir, storage, lobby).map_err(|e| format!("Plugin Error: {}", e))?,
+        shared: SharedData::load(&game_dir.join("data")).map_err(|e| format!("Shared Data Error: {}", e))?,
+        room_idle: RoomIdle::load(game_dir).map_err(|e| format!("Config Error: {}", e))?,
+        resume: Resume::load(game_dir).map_err(|e| format!("Config Error: {}", e))?,
+    })
+}
+
+// Partial reload of every changed file; false when a full reload is needed instead
+// (something else changed, or a module can't be patched)
+fn reload_modules(game: &GameState, changed: &[PathBuf]) -> bool {
+    let mut files: Vec<&PathBuf> = changed.iter().collect();
+    files.sort();
+    files.dedup();
+    for path in files {
+        let checked = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| type_check(&source, path));
+        if let Err(e) = checked {
+            tracing::info!("Full reload needed ({})", e);
+            return false;
+        }
+        match game.reload_module(path) {
+            Ok(modules) => tracing::info!("Module Reload Successful: {}", modules.join(", ")),
+            Err(e) => {
+                tracing::info!("Full reload needed ({})", e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// data/*.json next to the script, for api.shared_get
+fn load_shared_data_or_exit(script_path: &Path) -> SharedData {
+    let game_dir = script_path.parent().unwrap_or(Path::new("."));
+    match SharedData::load(&game_dir.join("data")) {
+        Ok(d) => d,
+        Err(e) => {
+            tracing::error!("Shared Data Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Luau builds: type errors refuse the (re)load like syntax errors do. The source checked
+// is the one about to be loaded, so the file changing in between doesn't matter.
+#[cfg(feature = "luau")]
+fn type_check(source: &str, path: &Path) -> Result<(), String> {
+    use engine::typecheck::{check, TypeCheck};
+    match check(source, path) {
+        Ok(TypeCheck::Passed) => Ok(()),
+        Ok(TypeCheck::Skipped(reason)) => {
+            tracing::warn!("Type check skipped ({})", reason);
+            Ok(())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(not(feature = "luau"))]
+fn type_check(_source: &str, _path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+// Errors are printed here and returned for the dev overlay
+fn load_game(path: &str, plugins: &mut [Box<dyn Plugin>], shared: &SharedData) -> Result<GameState, String> {
     match std::fs::read_to_string(path) {
-        Ok(script) => match GameState::new(&script, Some(std::path::Path::new(path))) {
-            Ok(g) => Some(g),
+        Ok(script) => match type_check(&script, Path::new(path))
+            .and_then(|()| GameState::with_shared_data(&script, Some(std::path::Path::new(path)), plugins, shared.clone()).map_err(|e| e.to_string()))
+        {
+            Ok(g) => Ok(g),
             Err(e) => {
-                eprintln!("Lua Init Error: {}", e);
-                None
+                tracing::error!("Lua Init Error: {}", e);
+                Err(e)
             }
         },
         Err(e) => {
-            eprintln!("File Read Error: {}", e);
-            None
+            tracing::error!("File Read Error: {}", e);
+            Err(e.to_string())
         }
     }
 }
@@ -501,100 +1483,345 @@ fn load_game(path: &str) -> Option<GameState> {
 #[derive(Deserialize)]
 struct WsParams {
     session: Option<String>,
+    room: Option<String>, // rooms.rs; DEFAULT_ROOM when absent
+    // Opcode groups the client understands (engine::compat::CAP_*); clients that predate
+    // capabilities don't send it
+    caps: Option<u32>,
+    spectate: Option<u8>, // 1: watch without playing
+    token: Option<String>, // auth.rs; or an Authorization: Bearer header
+    codecs: Option<String>, // codec.rs: "zstd,lz4,none", preferred first
 }
 
 async fn ws_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<WsParams>,
     State(state): State<Arc<AppState>>,
+    Extension(permit): Extension<Arc<SessionPermit>>, // limits.rs
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state, params.session))
+    let capabilities = params.caps.unwrap_or(engine::compat::CAPS_LEGACY);
+    let encoding = FrameEncoding::negotiate(params.codecs.as_deref(), state.compress_min_bytes);
+    let room = params.room.unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    if !Rooms::valid_name(&room) {
+        return (StatusCode::BAD_REQUEST, "Invalid room name").into_response();
+    }
+    if !state.rooms.accepts(&room) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Room not available").into_response();
+    }
+    let spectator = params.spectate.is_some_and(|s| s != 0);
+    // Log-in (auth.rs): refused before the upgrade
+    let bearer = headers.get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let auth_info = match verify_token(&state, params.token.or(bearer)).await {
+        Ok(info) => info,
+        Err(reason) => return (StatusCode::UNAUTHORIZED, reason).into_response(),
+    };
+    ws.on_upgrade(move |socket| async move {
+        handle_socket(socket, state, room, params.session, capabilities, encoding, spectator, auth_info).await;
+        drop(permit); // The address's session ends with the socket
+    }).into_response()
+}
+
+// The identity behind a client's token (auth.rs), None when players don't log in
+async fn verify_token(state: &AppState, token: Option<String>) -> Result<Option<serde_json::Value>, &'static str> {
+    let Some(auth) = &state.auth else { return Ok(None) };
+    let Some(token) = token else { return Err("Token required") };
+    match auth.verify(&token).await {
+        Ok(info) => Ok(Some(info)),
+        Err(e) => {
+            tracing::info!("Refused client: invalid token ({})", e);
+            Err("Invalid token")
+        }
+    }
 }
 
-async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_session: Option<String>) {
-    let session_id = requested_session.unwrap_or_else(|| Uuid::new_v4().to_string());
-    println!("Client {} connecting via WebSocket...", session_id);
+// Signals carrying the client's input go to the game loop; the others (WebRTC
+// negotiation) are handed back
+async fn client_signal(signal: SignalMessage, tx_input: &mpsc::Sender<ClientInput>, heartbeat: &SharedHeartbeat) -> Option<SignalMessage> {
+    let input = match signal {
+        SignalMessage::TEXT_INPUT { text, paste } => ClientInput::text(text, paste),
+        SignalMessage::SOUND_FINISHED { name } => ClientInput::SoundFinished(name),
+        SignalMessage::ASSETS_READY => ClientInput::AssetsReady,
+        SignalMessage::SETTING { key, value } => ClientInput::Setting(key, value),
+        SignalMessage::MESSAGE { data } => ClientInput::ScriptMessage(data),
+        SignalMessage::PONG { id } => {
+            heartbeat.lock().unwrap().pong(id);
+            return None;
+        }
+        other => return Some(other),
+    };
+    let _ = tx_input.send(input).await;
+    None
+}
+
+async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, room: String, requested_session: Option<String>, capabilities: u32, encoding: FrameEncoding, spectator: bool, auth_info: Option<serde_json::Value>) {
+    // Spectators always get a session of their own: a player's id doesn't let them take it over
+    let session_id = requested_session.filter(|_| !spectator).unwrap_or_else(|| Uuid::new_v4().to_string());
+    tracing::info!("Client {} connecting via WebSocket (room '{}')...", session_id, room);
 
     // 1. Send Handshake
     let handshake = SignalMessage::WELCOME { 
         session_id: session_id.clone(),
-        server_instance_id: state.instance_id.clone() 
+        server_instance_id: state.instance_id.clone(),
+        ice_servers: state.negotiator.ice_servers().to_vec(),
     };
     if let Err(e) = socket.send(Message::Text(serde_json::to_string(&handshake).unwrap().into())).await {
-        eprintln!("Handshake failed: {}", e);
+        tracing::error!("Handshake failed: {}", e);
         return;
     }
 
     // 2. Prepare Game Loop Channels
-    let (tx_render, mut rx_render) = mpsc::channel::<bytes::Bytes>(30); // From Game -> Network
-    let (tx_input, rx_input) = mpsc::channel::<(u8, bool)>(100);       // From Network -> Game
-
-    // Push to Game Loop
-    {
-        let mut queue = state.new_clients.lock().unwrap();
-        queue.push(ClientConnection {
-            session_id: session_id.clone(),
-            tx_render,
-            rx_input,
-        });
+    let (tx_render, mut rx_render) = mpsc::channel::<DrawnFrame>(30); // From Game -> Network
+    let (tx_input, rx_input) = mpsc::channel::<ClientInput>(100);      // From Network -> Game
+    let (tx_messages, mut rx_messages) = mpsc::unbounded_channel::<String>(); // api.send_message
+    let rtt = Rtt::default();
+    let heartbeat = Heartbeat::new(rtt.clone());
+
+    // Preloading (manifest.rs): the client reports ASSETS_READY once the manifest is loaded
+    match AssetManifest::load(&state.assets_dir) {
+        Some(manifest) => {
+            let signal = serde_json::to_string(&SignalMessage::ASSET_MANIFEST(manifest)).unwrap();
+            if let Err(e) = socket.send(Message::Text(signal.into())).await {
+                tracing::error!("Asset manifest failed: {}", e);
+                return;
+            }
+        }
+        None => {
+            let _ = tx_input.try_send(ClientInput::AssetsReady);
+        }
     }
 
-    // 3. Setup WebRTC API
-    let mut m = MediaEngine::default();
-    let registry = Registry::new();
-    let registry = match register_default_interceptors(registry, &mut m) {
-        Ok(r) => r,
-        Err(e) => {
-            eprintln!("WebRTC Registry error: {}", e);
-            return;
+    // Push to the room's Game Loop (refused if the server filled up since ws_handler)
+    let joined = state.rooms.join(&room, ClientConnection {
+        session_id: session_id.clone(),
+        capabilities,
+        tx_render,
+        tx_messages,
+        rx_input,
+        rtt,
+        spectator,
+        auth: auth_info,
+    });
+    if !joined {
+        tracing::info!("Client {} refused: room '{}' not available", session_id, room);
+        return;
+    }
+
+    // 3. WebRTC runs on the negotiation pool; the connection plays over the WebSocket
+    // fallback until its data channel opens
+    let active_dc: DataChannelSlot = Arc::new(tokio::sync::Mutex::new(None));
+    let events_dc: DataChannelSlot = Arc::new(tokio::sync::Mutex::new(None));
+    let peer: PeerSlot = Arc::new(tokio::sync::Mutex::new(None));
+    let session_id_rtc = session_id.clone();
+    // The sender stays here too: the negotiation task may end before the connection
+    let (tx_ws_sig, mut rx_ws_sig) = mpsc::channel::<Message>(100);
+    let (tx_signal, rx_signal) = mpsc::unbounded_channel::<SignalMessage>();
+    let negotiation_handle = state.negotiator.spawn(negotiate_peer(
+        state.negotiator.clone(),
+        session_id.clone(),
+        rx_signal,
+        tx_ws_sig.clone(),
+        tx_input.clone(),
+        active_dc.clone(),
+        events_dc.clone(),
+        peer.clone(),
+        heartbeat.clone(),
+    ));
+
+    // Liveness and round-trip time (heartbeat.rs)
+    let mut heartbeat_timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat_timer.tick().await; // The first tick is immediate
+
+    // 4. WebSocket Signaling & Coordinator Loop
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+
+    // Spawn Coordinator Task (consumes rx_render)
+    let active_dc_sender = active_dc.clone();
+    
+    let (tx_ws_frame, mut rx_ws_frame) = mpsc::channel::<Vec<u8>>(30);
+
+    let coordinator_handle = tokio::spawn(async move {
+        while let Some(frame) = rx_render.recv().await {
+            if let Ok(compressed) = encoding.encode(&frame) {
+                let data = bytes::Bytes::from(compressed);
+                
+                // Check DC
+                let dc_opt = active_dc_sender.lock().await.clone();
+                let mut sent_via_udp = false;
+                
+                if let Some(dc) = dc_opt {
+                     // Only try if actually Open
+                     if dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
+                         if let Err(_e) = dc.send(&data).await {
+                             // eprintln!("WebRTC Send Error: {}", _e);
+                         } else {
+                             sent_via_udp = true;
+                         }
+                     }
+                } 
+                
+                if !sent_via_udp {
+                     // Fallback TCP
+                     let _ = tx_ws_frame.send(data.to_vec()).await;
+                }
+            }
         }
-    };
-    let api = APIBuilder::new()
-        .with_media_engine(m)
-        .with_interceptor_registry(registry)
-        .build();
-
-    // STUN Servers (Google is reliable)
-    // Commented out to reduce FD usage on macOS dev environment and avoid DNS errors.
-    // Localhost / Fallback mode works without this.
-    let config = RTCConfiguration {
-        // ice_servers: vec![RTCIceServer {
-        //     urls: vec!["stun:stun.l.google.com:19302".to_owned()],
-        //     ..Default::default()
-        // }],
-        ..Default::default()
-    };
+        tracing::info!("Coordinator task finished for session {}", session_id_rtc);
+    });
+
+    // Main Loop: Select between Incoming WS messages, Outgoing WS Frames (Fallback), Outgoing Signals
+    loop {
+        tokio::select! {
+            // 1. Incoming WS Message
+            msg = ws_receiver.next() => {
+                match msg {
+                    Some(Ok(Message::Text(text))) => {
+                         // Handle Signaling
+                         if let Ok(signal) = serde_json::from_str::<SignalMessage>(&text) {
+                            if let Some(signal @ (SignalMessage::OFFER { .. } | SignalMessage::ANSWER { .. } | SignalMessage::CANDIDATE { .. })) = client_signal(signal, &tx_input, &heartbeat).await {
+                                let _ = tx_signal.send(signal);
+                            }
+                        }
+                    },
+                    Some(Ok(Message::Binary(data))) => {
+                        // Fallback Input
+                        if let Some(input) = ClientInput::decode(&data) {
+                            let _ = tx_input.send(input).await;
+                        }
+                    },
+                    Some(Err(_)) | None => break, // Disconnected
+                    _ => {}
+                }
+            },
+            // 2. Outgoing WS Frame (Fallback)
+            frame = rx_ws_frame.recv() => {
+                match frame {
+                    Some(data) => {
+                        if ws_sender.send(Message::Binary(data)).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => {
+                        // The game dropped this client (kicked, shutdown): its last messages first
+                        while let Ok(text) = rx_messages.try_recv() {
+                            let _ = ws_sender.send(Message::Text(text.into())).await;
+                        }
+                        break;
+                    }
+                }
+            },
+            // 3. Outgoing Signaling
+            sig = rx_ws_sig.recv() => {
+                if let Some(msg) = sig {
+                    if ws_sender.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            // 4. Outgoing Script Messages: the reliable DataChannel once open, so they don't
+            // queue up behind frames on the WebSocket
+            message = rx_messages.recv() => {
+                match message {
+                    Some(text) => {
+                        let dc_opt = events_dc.lock().await.clone();
+                        let open = dc_opt.as_ref().is_some_and(|dc| dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open);
+                        let sent_via_dc = match dc_opt {
+                            Some(dc) if open => dc.send_text(text.clone()).await.is_ok(),
+                            _ => false,
+                        };
+                        if !sent_via_dc && ws_sender.send(Message::Text(text.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    None => break, // The game dropped this client
+                }
+            },
+            // 5. Heartbeat: a PING on the channel the client's inputs use, or a client gone silent
+            _ = heartbeat_timer.tick() => {
+                let (id, dead) = {
+                    let mut heartbeat = heartbeat.lock().unwrap();
+                    (heartbeat.ping(), heartbeat.dead())
+                };
+                if dead {
+                    tracing::info!("Client {} missed {} heartbeats, disconnecting", session_id, heartbeat::MISSED_HEARTBEATS);
+                    break;
+                }
+                let ping = serde_json::to_string(&SignalMessage::PING { id }).unwrap();
+                let dc_opt = events_dc.lock().await.clone();
+                let open = dc_opt.as_ref().is_some_and(|dc| dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open);
+                let sent_via_dc = match dc_opt {
+                    Some(dc) if open => dc.send_text(ping.clone()).await.is_ok(),
+                    _ => false,
+                };
+                if !sent_via_dc && ws_sender.send(Message::Text(ping.into())).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    
+    tracing::info!("WS Handle Socket loop finished for {}", session_id);
+    // Cleanup
+    coordinator_handle.abort();
+    negotiation_handle.abort();
+    if let Some(pc) = peer.lock().await.take() {
+        state.negotiator.spawn(async move {
+            let _ = pc.close().await;
+        });
+    }
+}
 
-    let peer_connection = match api.new_peer_connection(config).await {
+type PeerSlot = Arc<tokio::sync::Mutex<Option<Arc<RTCPeerConnection>>>>;
+type DataChannelSlot = Arc<tokio::sync::Mutex<Option<Arc<webrtc::data_channel::RTCDataChannel>>>>;
+
+// The client opens two DataChannels: "game_data", unordered and unreliable, for frames (a
+// lost frame is replaced by the next one instead of holding it up), and EVENTS_CHANNEL,
+// reliable and ordered, for its inputs and the script's messages. Inputs are accepted on
+// either; any channel but EVENTS_CHANNEL carries frames (older clients only open one).
+const EVENTS_CHANNEL: &str = "game_events";
+
+// Runs on the negotiation pool: waits for a handshake slot, then answers the client's
+// signaling. Signals sent while queued wait in `rx_signal`.
+async fn negotiate_peer(
+    negotiator: Arc<Negotiator>,
+    session_id: String,
+    mut rx_signal: mpsc::UnboundedReceiver<SignalMessage>,
+    tx_ws_sig: mpsc::Sender<Message>,
+    tx_input: mpsc::Sender<ClientInput>,
+    active_dc: DataChannelSlot,
+    events_dc: DataChannelSlot,
+    peer: PeerSlot,
+    heartbeat: SharedHeartbeat,
+) {
+    let slot = negotiator.acquire().await;
+    let peer_connection = match negotiator.new_peer_connection().await {
         Ok(pc) => Arc::new(pc),
         Err(e) => {
-             eprintln!("Failed to create PeerConnection: {}", e);
-             return;
+            tracing::error!("Failed to create PeerConnection: {}", e);
+            slot.finish(Outcome::Failed);
+            return;
         }
     };
+    *peer.lock().await = Some(peer_connection.clone());
 
-    // 4. Shared State for DataChannel
-    // We need to pass the DataChannel from the callback to the sender task.
-    let active_dc: Arc<tokio::sync::Mutex<Option<Arc<webrtc::data_channel::RTCDataChannel>>>> = Arc::new(tokio::sync::Mutex::new(None));
-    let active_dc_clone = active_dc.clone();
-    let session_id_rtc = session_id.clone();
-
-    // 5. Handle Client-Initiated DataChannel
+    // Handle Client-Initiated DataChannel
     // The client will create the DataChannel, ensuring the SDP Offer is valid.
-    let tx_input_for_rtc = tx_input.clone();
-    let session_id_for_dc = session_id_rtc.clone();
+    let session_id_for_dc = session_id.clone();
     peer_connection.on_data_channel(Box::new(move |dc: Arc<webrtc::data_channel::RTCDataChannel>| {
         let dc_label = dc.label().to_owned();
         let dc_id = dc.id();
-        println!("New DataChannel {} Id: {} for session {}", dc_label, dc_id, session_id_for_dc);
+        tracing::info!("New DataChannel {} Id: {} for session {}", dc_label, dc_id, session_id_for_dc);
 
-        let active_dc_inner = active_dc_clone.clone();
-        let tx_input_rtc = tx_input_for_rtc.clone();
+        let active_dc_inner = if dc_label == EVENTS_CHANNEL { events_dc.clone() } else { active_dc.clone() };
+        let tx_input_rtc = tx_input.clone();
+        let heartbeat_rtc = heartbeat.clone();
 
         // Clone DC for use inside the on_open callback
         let dc_for_open = dc.clone();
         dc.on_open(Box::new(move || {
-            println!("DataChannel '{}' open", dc_label);
+            tracing::info!("DataChannel '{}' open", dc_label);
             let dc_clone = dc_for_open.clone();
             let active_dc_inner = active_dc_inner.clone();
             Box::pin(async move {
@@ -605,69 +1832,35 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
 
         dc.on_message(Box::new(move |msg: DataChannelMessage| {
             let tx = tx_input_rtc.clone();
+            let heartbeat = heartbeat_rtc.clone();
             Box::pin(async move {
                 let data = msg.data;
-                if data.len() == 2 {
-                    let code = data[0];
-                    let active = data[1] != 0;
-                    let _ = tx.send((code, active)).await;
+                if msg.is_string {
+                    let text = String::from_utf8_lossy(&data);
+                    if let Some(id) = text.strip_prefix(PONG_PREFIX) {
+                        if let Ok(id) = id.parse() {
+                            heartbeat.lock().unwrap().pong(id);
+                        }
+                        return;
+                    }
+                    let input = match text.strip_prefix(PASTE_PREFIX) {
+                        Some(pasted) => ClientInput::text(pasted.to_string(), true),
+                        None => ClientInput::text(text.into_owned(), false),
+                    };
+                    let _ = tx.send(input).await;
+                } else if let Some(input) = ClientInput::decode(&data) {
+                    let _ = tx.send(input).await;
                 }
             })
         }));
-        
+
         Box::pin(async {})
     }));
 
-    // 6. WebSocket Signaling & Coordinator Loop
-    let (mut ws_sender, mut ws_receiver) = socket.split();
-    let pc_clone = peer_connection.clone();
-
-    // Spawn Coordinator Task (consumes rx_render)
-    let active_dc_sender = active_dc.clone();
-    
-    let (tx_ws_frame, mut rx_ws_frame) = mpsc::channel::<Vec<u8>>(30);
-
-    let coordinator_handle = tokio::spawn(async move {
-        use std::io::Write;
-
-        while let Some(bytes) = rx_render.recv().await {
-            // Compress with Zstd (Standard, Level 0)
-            let mut encoder = zstd::stream::write::Encoder::new(Vec::new(), 0).unwrap();
-            
-            if encoder.write_all(&bytes).is_ok() {
-                if let Ok(compressed) = encoder.finish() {
-                    let data = bytes::Bytes::from(compressed);
-                    
-                    // Check DC
-                    let dc_opt = active_dc_sender.lock().await.clone();
-                    let mut sent_via_udp = false;
-                    
-                    if let Some(dc) = dc_opt {
-                         // Only try if actually Open
-                         if dc.ready_state() == webrtc::data_channel::data_channel_state::RTCDataChannelState::Open {
-                             if let Err(_e) = dc.send(&data).await {
-                                 // eprintln!("WebRTC Send Error: {}", _e);
-                             } else {
-                                 sent_via_udp = true;
-                             }
-                         }
-                    } 
-                    
-                    if !sent_via_udp {
-                         // Fallback TCP
-                         let _ = tx_ws_frame.send(data.to_vec()).await;
-                    }
-                }
-            }
-        }
-        println!("Coordinator task finished for session {}", session_id_rtc);
-    });
-
     // Handle ICE Candidates from Local (Server) -> Remote (Client) via WebSocket
-    let (tx_ws_sig, mut rx_ws_sig) = mpsc::channel::<Message>(100);
-    
+    let tx_candidates = tx_ws_sig.clone();
     peer_connection.on_ice_candidate(Box::new(move |c| {
-        let tx = tx_ws_sig.clone();
+        let tx = tx_candidates.clone();
         Box::pin(async move {
             if let Some(candidate) = c {
                 if let Ok(json_cand) = candidate.to_json() {
@@ -683,75 +1876,76 @@ async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, requested_se
         })
     }));
 
-    // Main Loop: Select between Incoming WS messages, Outgoing WS Frames (Fallback), Outgoing Signals
+    let (tx_state, mut rx_state) = mpsc::unbounded_channel();
+    peer_connection.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+        let _ = tx_state.send(s);
+        Box::pin(async {})
+    }));
+
+    // The slot is held until the handshake settles
+    let mut slot = Some(slot);
+    let deadline = tokio::time::sleep(negotiator.timeout());
+    tokio::pin!(deadline);
     loop {
         tokio::select! {
-            // 1. Incoming WS Message
-            msg = ws_receiver.next() => {
-                match msg {
-                    Some(Ok(Message::Text(text))) => {
-                         // Handle Signaling
-                         if let Ok(signal) = serde_json::from_str::<SignalMessage>(&text) {
-                            match signal {
-                                SignalMessage::OFFER { sdp } => {
-                                     let desc = RTCSessionDescription::offer(sdp).unwrap();
-                                     if pc_clone.set_remote_description(desc).await.is_ok() {
-                                         if let Ok(answer) = pc_clone.create_answer(None).await {
-                                             if pc_clone.set_local_description(answer.clone()).await.is_ok() {
-                                                 let resp = SignalMessage::ANSWER { sdp: answer.sdp };
-                                                 let _ = ws_sender.send(Message::Text(serde_json::to_string(&resp).unwrap().into())).await;
-                                             }
-                                         }
-                                     }
-                                },
-                                SignalMessage::ANSWER { sdp } => {
-                                    let desc = RTCSessionDescription::answer(sdp).unwrap();
-                                    let _ = pc_clone.set_remote_description(desc).await;
-                                },
-                                SignalMessage::CANDIDATE { candidate, sdp_mid, sdp_mline_index } => {
-                                    let cand = webrtc::ice_transport::ice_candidate::RTCIceCandidateInit {
-                                        candidate,
-                                        sdp_mid,
-                                        sdp_mline_index,
-                                        username_fragment: None,
-                                    };
-                                    let _ = pc_clone.add_ice_candidate(cand).await;
-                                },
-                                _ => {}
+            signal = rx_signal.recv() => {
+                match signal {
+                    Some(SignalMessage::OFFER { sdp }) => {
+                        let Ok(desc) = RTCSessionDescription::offer(sdp) else { continue };
+                        if peer_connection.set_remote_description(desc).await.is_ok() {
+                            if let Ok(answer) = peer_connection.create_answer(None).await {
+                                if peer_connection.set_local_description(answer.clone()).await.is_ok() {
+                                    let resp = SignalMessage::ANSWER { sdp: answer.sdp };
+                                    let _ = tx_ws_sig.send(Message::Text(serde_json::to_string(&resp).unwrap().into())).await;
+                                }
                             }
                         }
                     },
-                    Some(Ok(Message::Binary(data))) => {
-                        // Fallback Input
-                        if data.len() == 2 {
-                            let _ = tx_input.send((data[0], data[1] != 0)).await;
+                    Some(SignalMessage::ANSWER { sdp }) => {
+                        if let Ok(desc) = RTCSessionDescription::answer(sdp) {
+                            let _ = peer_connection.set_remote_description(desc).await;
                         }
                     },
-                    Some(Err(_)) | None => break, // Disconnected
-                    _ => {}
+                    Some(SignalMessage::CANDIDATE { candidate, sdp_mid, sdp_mline_index }) => {
+                        let cand = webrtc::ice_transport::ice_candidate::RTCIceCandidateInit {
+                            candidate,
+                            sdp_mid,
+                            sdp_mline_index,
+                            username_fragment: None,
+                        };
+                        let _ = peer_connection.add_ice_candidate(cand).await;
+                    },
+                    Some(_) => {}
+                    None => break, // WebSocket closed
                 }
             },
-            // 2. Outgoing WS Frame (Fallback)
-            frame = rx_ws_frame.recv() => {
-                if let Some(data) = frame {
-                    if ws_sender.send(Message::Binary(data)).await.is_err() {
-                        break;
-                    }
+            Some(s) = rx_state.recv() => {
+                match s {
+                    RTCPeerConnectionState::Connected => {
+                        if let Some(slot) = slot.take() {
+                            slot.finish(Outcome::Connected);
+                        }
+                    },
+                    RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed => {
+                        if let Some(slot) = slot.take() {
+                            slot.finish(Outcome::Failed);
+                        }
+                    },
+                    _ => {}
                 }
             },
-            // 3. Outgoing Signaling
-            sig = rx_ws_sig.recv() => {
-                if let Some(msg) = sig {
-                    if ws_sender.send(msg).await.is_err() {
-                        break;
-                    }
+            _ = &mut deadline, if slot.is_some() => {
+                // Give the slot to the next client; this one stays on the WebSocket fallback
+                tracing::info!("WebRTC negotiation timed out for session {}", session_id);
+                if let Some(slot) = slot.take() {
+                    slot.finish(Outcome::TimedOut);
                 }
+                break;
             }
         }
     }
-    
-    println!("WS Handle Socket loop finished for {}", session_id);
-    // Cleanup
-    coordinator_handle.abort();
-    let _ = peer_connection.close().await;
+
+    if let Some(pc) = peer.lock().await.take() {
+        let _ = pc.close().await;
+    }
 }
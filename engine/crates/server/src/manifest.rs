@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// --- Asset Manifest ---
+// assets.json next to the script lists the sounds and images every client preloads:
+//   { "sounds": { "jump": "assets/jump.wav" }, "images": { "player": "assets/player.png" } }
+// It is sent over the WebSocket right after the welcome; the client loads everything behind
+// its loading overlay and answers ASSETS_READY, which runs on_assets_ready(session_id).
+// Without a manifest the server reports the client ready itself.
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AssetManifest {
+    #[serde(default)]
+    pub sounds: BTreeMap<String, String>,
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+}
+
+impl AssetManifest {
+    /// Read on every connection, so edits apply without a restart. None when there is no
+    /// assets.json (or it is invalid, which is reported).
+    pub fn load(assets_dir: &Path) -> Option<Self> {
+        let file = std::fs::File::open(assets_dir.join("assets.json")).ok()?;
+        match serde_json::from_reader(file) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                tracing::error!("Invalid assets.json: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn urls(&self) -> impl Iterator<Item = &String> {
+        self.sounds.values().chain(self.images.values())
+    }
+}
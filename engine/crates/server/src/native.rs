@@ -0,0 +1,315 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::heartbeat::{self, Heartbeat, HEARTBEAT_INTERVAL};
+use crate::limits::ConnectionLimits;
+use crate::listen;
+use crate::manifest::AssetManifest;
+use crate::rooms::{Rooms, DEFAULT_ROOM};
+use crate::throttle::Rtt;
+use crate::codec::FrameEncoding;
+use crate::{client_signal, logging, verify_token};
+use crate::{AppState, ClientConnection, ClientInput, DrawnFrame, SignalMessage, WsParams};
+
+// --- Native Clients ---
+// --native-port opens plain TCP listeners (on the --bind IPs) for clients that aren't browsers: bots, load
+// tests, custom frontends. It speaks the /ws protocol without the WebSocket: every message
+// is a u32 length (little-endian, counting what follows), a kind byte and the payload.
+// KIND_TEXT carries the JSON signals, KIND_BINARY the frames (from the server, see
+// codec.rs) and the input messages (from the client, see engine::input).
+//
+// The client opens with a HELLO text message holding what browsers put on the /ws URL,
+// {"type": "HELLO", "room", "session", "caps", "spectate", "token", "codecs"} (all
+// optional), within HELLO_TIMEOUT. The server answers WELCOME and from then on it goes as over /ws, without
+// WebRTC: frames, messages and PINGs arrive on the connection. A refused client gets
+// REFUSED {reason} before the connection closes. --max-sessions-per-ip and
+// --joins-per-minute apply as to WebSockets.
+//
+// With --native-udp frames can go over UDP instead (on the same port): WELCOME is followed
+// by UDP {port, key} and once the client sends a datagram holding just the key, from the
+// address it connected from, its frames go there, unreliable like the browser's
+// game_data DataChannel. Frames larger than MAX_DATAGRAM still go over TCP.
+
+pub const KIND_TEXT: u8 = 1;
+pub const KIND_BINARY: u8 = 2;
+const MAX_MESSAGE: usize = 64 * 1024; // From clients: inputs and signals are small
+const MAX_DATAGRAM: usize = 60 * 1024; // IPv4 UDP payloads top out at 65507 bytes
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct Hello {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(flatten)]
+    params: WsParams,
+}
+
+// Where a session's frames go over UDP, once its client asked
+type UdpTarget = Arc<Mutex<Option<SocketAddr>>>;
+
+struct Udp {
+    socket: UdpSocket,
+    port: u16,
+    // Keys handed out in UDP signals: the address their datagram must come from, and the session's target
+    keys: Mutex<HashMap<String, (IpAddr, UdpTarget)>>,
+}
+
+impl Udp {
+    fn bind(addr: SocketAddr) -> io::Result<Arc<Self>> {
+        let socket = listen::udp_socket(addr)?;
+        let udp = Arc::new(Self { socket, port: addr.port(), keys: Mutex::new(HashMap::new()) });
+        tokio::spawn(udp.clone().receive());
+        Ok(udp)
+    }
+
+    // Datagrams from clients hold the key of their UDP signal, nothing else. Sending it
+    // again (the client's NAT moved it to another port) moves its frames there.
+    async fn receive(self: Arc<Self>) {
+        let mut buf = [0u8; 64];
+        loop {
+            let Ok((len, from)) = self.socket.recv_from(&mut buf).await else { continue };
+            let Ok(key) = std::str::from_utf8(&buf[..len]) else { continue };
+            if let Some((ip, target)) = self.keys.lock().unwrap().get(key) {
+                if *ip == from.ip() {
+                    *target.lock().unwrap() = Some(from);
+                }
+            }
+        }
+    }
+}
+
+/// Binds the listeners (and UDP sockets), then accepts native clients in the background.
+pub fn serve(addresses: &[SocketAddr], udp: bool, state: Arc<AppState>, limits: Arc<ConnectionLimits>) -> io::Result<()> {
+    for &addr in addresses {
+        let listener = listen::tcp_listener(addr)?;
+        let udp = if udp { Some(Udp::bind(addr)?) } else { None };
+        tracing::info!("Native clients on tcp://{}{}", addr, if udp.is_some() { " (frames over UDP too)" } else { "" });
+        accept(listener, udp, state.clone(), limits.clone());
+    }
+    Ok(())
+}
+
+fn accept(listener: TcpListener, udp: Option<Arc<Udp>>, state: Arc<AppState>, limits: Arc<ConnectionLimits>) {
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Native accept error: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let (state, limits, udp) = (state.clone(), limits.clone(), udp.clone());
+            tokio::spawn(async move {
+                let mut stream = stream;
+                match limits.admit(peer.ip()) {
+                    Ok(permit) => {
+                        handle_native(stream, peer, state, udp).await;
+                        drop(permit); // The address's session ends with the connection
+                    }
+                    Err(reason) => {
+                        logging::audit_connection_refused(&peer.ip().to_string(), reason);
+                        let _ = refuse(&mut stream, reason).await;
+                    }
+                }
+            });
+        }
+    });
+}
+
+async fn handle_native(stream: TcpStream, peer: SocketAddr, state: Arc<AppState>, udp: Option<Arc<Udp>>) {
+    let _ = stream.set_nodelay(true); // Frames and PINGs shouldn't wait for more to send
+    let (mut reader, mut writer) = stream.into_split();
+
+    // 1. HELLO: what browsers put on the /ws URL
+    let hello = match tokio::time::timeout(HELLO_TIMEOUT, read_message(&mut reader)).await {
+        Ok(Ok((KIND_TEXT, payload))) => serde_json::from_slice::<Hello>(&payload).ok().filter(|h| h.kind == "HELLO"),
+        _ => None,
+    };
+    let Some(Hello { params, .. }) = hello else {
+        let _ = refuse(&mut writer, "Expected HELLO").await;
+        return;
+    };
+    let room = params.room.unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    if !Rooms::valid_name(&room) {
+        let _ = refuse(&mut writer, "Invalid room name").await;
+        return;
+    }
+    let auth_info = match verify_token(&state, params.token).await {
+        Ok(info) => info,
+        Err(reason) => {
+            let _ = refuse(&mut writer, reason).await;
+            return;
+        }
+    };
+    let capabilities = params.caps.unwrap_or(engine::compat::CAPS_LEGACY);
+    let encoding = FrameEncoding::negotiate(params.codecs.as_deref(), state.compress_min_bytes);
+    let spectator = params.spectate.is_some_and(|s| s != 0);
+    // Spectators always get a session of their own, as over /ws
+    let session_id = params.session.filter(|_| !spectator).unwrap_or_else(|| Uuid::new_v4().to_string());
+    tracing::info!("Client {} connecting via TCP from {} (room '{}')...", session_id, peer, room);
+
+    // 2. Handshake, as over /ws
+    let welcome = SignalMessage::WELCOME {
+        session_id: session_id.clone(),
+        server_instance_id: state.instance_id.clone(),
+        ice_servers: Vec::new(), // No WebRTC here
+    };
+    if let Err(e) = send_signal(&mut writer, &welcome).await {
+        tracing::error!("Handshake failed: {}", e);
+        return;
+    }
+    let udp_target: UdpTarget = Arc::new(Mutex::new(None));
+    let udp_key = Uuid::new_v4().to_string();
+    if let Some(udp) = &udp {
+        udp.keys.lock().unwrap().insert(udp_key.clone(), (peer.ip(), udp_target.clone()));
+        if send_signal(&mut writer, &SignalMessage::UDP { port: udp.port, key: udp_key.clone() }).await.is_err() {
+            udp.keys.lock().unwrap().remove(&udp_key);
+            return;
+        }
+    }
+
+    let (tx_render, mut rx_render) = mpsc::channel::<DrawnFrame>(30); // From Game -> Network
+    let (tx_input, rx_input) = mpsc::channel::<ClientInput>(100);      // From Network -> Game
+    let (tx_messages, mut rx_messages) = mpsc::unbounded_channel::<String>(); // api.send_message
+    let rtt = Rtt::default();
+    let heartbeat = Heartbeat::new(rtt.clone());
+
+    match AssetManifest::load(&state.assets_dir) {
+        Some(manifest) => {
+            if send_signal(&mut writer, &SignalMessage::ASSET_MANIFEST(manifest)).await.is_err() {
+                forget_key(&udp, &udp_key);
+                return;
+            }
+        }
+        None => {
+            let _ = tx_input.try_send(ClientInput::AssetsReady);
+        }
+    }
+
+    state.rooms.join(&room, ClientConnection {
+        session_id: session_id.clone(),
+        capabilities,
+        tx_render,
+        tx_messages,
+        rx_input,
+        rtt,
+        spectator,
+        auth: auth_info,
+    });
+
+    // 3. Incoming messages, on a task of their own: a read can't be cut off halfway
+    let reader_heartbeat = heartbeat.clone();
+    let mut reader_task = tokio::spawn(async move {
+        loop {
+            match read_message(&mut reader).await {
+                Ok((KIND_TEXT, payload)) => {
+                    if let Ok(signal) = serde_json::from_slice::<SignalMessage>(&payload) {
+                        client_signal(signal, &tx_input, &reader_heartbeat).await;
+                    }
+                }
+                Ok((KIND_BINARY, payload)) => {
+                    if let Some(input) = ClientInput::decode(&payload) {
+                        let _ = tx_input.send(input).await;
+                    }
+                }
+                Ok(_) => {} // Kinds of later versions
+                Err(_) => break, // Disconnected, or a message too large
+            }
+        }
+    });
+
+    let mut heartbeat_timer = tokio::time::interval(HEARTBEAT_INTERVAL);
+    heartbeat_timer.tick().await; // The first tick is immediate
+
+    loop {
+        tokio::select! {
+            _ = &mut reader_task => break,
+            frame = rx_render.recv() => {
+                let Some(frame) = frame else {
+                    // The game dropped this client (kicked, shutdown): its last messages first
+                    while let Ok(text) = rx_messages.try_recv() {
+                        let _ = write_message(&mut writer, KIND_TEXT, text.as_bytes()).await;
+                    }
+                    break;
+                };
+                let Ok(data) = encoding.encode(&frame) else { continue };
+                let target = *udp_target.lock().unwrap();
+                let sent_via_udp = match (&udp, target) {
+                    (Some(udp), Some(addr)) if data.len() <= MAX_DATAGRAM => udp.socket.send_to(&data, addr).await.is_ok(),
+                    _ => false,
+                };
+                if !sent_via_udp && write_message(&mut writer, KIND_BINARY, &data).await.is_err() {
+                    break;
+                }
+            },
+            message = rx_messages.recv() => {
+                let Some(text) = message else { break }; // The game dropped this client
+                if write_message(&mut writer, KIND_TEXT, text.as_bytes()).await.is_err() {
+                    break;
+                }
+            },
+            _ = heartbeat_timer.tick() => {
+                let (id, dead) = {
+                    let mut heartbeat = heartbeat.lock().unwrap();
+                    (heartbeat.ping(), heartbeat.dead())
+                };
+                if dead {
+                    tracing::info!("Client {} missed {} heartbeats, disconnecting", session_id, heartbeat::MISSED_HEARTBEATS);
+                    break;
+                }
+                if send_signal(&mut writer, &SignalMessage::PING { id }).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    tracing::info!("TCP connection finished for {}", session_id);
+    reader_task.abort();
+    forget_key(&udp, &udp_key);
+}
+
+fn forget_key(udp: &Option<Arc<Udp>>, key: &str) {
+    if let Some(udp) = udp {
+        udp.keys.lock().unwrap().remove(key);
+    }
+}
+
+async fn read_message(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<(u8, Vec<u8>)> {
+    let len = reader.read_u32_le().await? as usize;
+    if len == 0 || len > MAX_MESSAGE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Message of {} bytes", len)));
+    }
+    let kind = reader.read_u8().await?;
+    let mut payload = vec![0; len - 1];
+    reader.read_exact(&mut payload).await?;
+    Ok((kind, payload))
+}
+
+async fn write_message(writer: &mut (impl AsyncWrite + Unpin), kind: u8, payload: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(5 + payload.len());
+    message.extend_from_slice(&(payload.len() as u32 + 1).to_le_bytes());
+    message.push(kind);
+    message.extend_from_slice(payload);
+    writer.write_all(&message).await
+}
+
+async fn send_signal(writer: &mut (impl AsyncWrite + Unpin), signal: &SignalMessage) -> io::Result<()> {
+    write_message(writer, KIND_TEXT, serde_json::to_string(signal).unwrap().as_bytes()).await
+}
+
+async fn refuse(writer: &mut (impl AsyncWrite + Unpin), reason: &str) -> io::Result<()> {
+    tracing::info!("Refused native client: {}", reason);
+    send_signal(writer, &SignalMessage::REFUSED { reason: reason.to_string() }).await?;
+    writer.shutdown().await
+}
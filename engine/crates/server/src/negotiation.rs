@@ -0,0 +1,363 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::{APIBuilder, API};
+use webrtc::ice::network_type::NetworkType;
+use webrtc::interceptor::registry::Registry;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::plugins::CONFIG_FILE;
+
+// --- WebRTC Negotiation Pool ---
+// Peer connections live on a runtime of their own (dedicated worker threads), so ICE
+// gathering, DTLS handshakes and data channel traffic don't compete with the WebSocket
+// tasks. At most `max_concurrent` handshakes run at once and they start at most
+// `joins_per_second` per second, so a burst of joins queues up instead of stalling the
+// server. Queued clients already play over the WebSocket fallback and switch to the data
+// channel when their turn comes. A handshake gives its slot back once connected, failed
+// or after `timeout` seconds.
+//
+//   [webrtc]
+//   threads = 2
+//   max_concurrent = 8
+//   joins_per_second = 20
+//   timeout = 10
+//
+// Queue wait and handshake times are logged every 5 seconds under the `metrics` target.
+//
+// STUN and TURN servers come from the command line (--stun, --turn, --turn-user,
+// --turn-pass): both the server's peer connections and the clients (in WELCOME) use them.
+// Without any, the server gathers only host candidates and the client uses its default STUN.
+// Host candidates come from the interfaces the server listens on (--bind): an unspecified
+// address stands for every interface of its family.
+
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+const METRICS_TARGET: &str = "metrics";
+
+#[derive(Deserialize, Default)]
+struct Config {
+    webrtc: Option<WebRtcConfig>,
+}
+
+#[derive(Deserialize)]
+struct WebRtcConfig {
+    #[serde(default = "default_threads")]
+    threads: usize,
+    #[serde(default = "default_max_concurrent")]
+    max_concurrent: usize,
+    #[serde(default = "default_joins_per_second")]
+    joins_per_second: f64,
+    #[serde(default = "default_timeout")]
+    timeout: f64,
+}
+
+fn default_threads() -> usize { 2 }
+fn default_max_concurrent() -> usize { 8 }
+fn default_joins_per_second() -> f64 { 20.0 }
+fn default_timeout() -> f64 { 10.0 }
+
+impl Default for WebRtcConfig {
+    fn default() -> Self {
+        Self {
+            threads: default_threads(),
+            max_concurrent: default_max_concurrent(),
+            joins_per_second: default_joins_per_second(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+// Token bucket: `rate` joins per second, bursts of up to `rate`
+struct JoinRate {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl JoinRate {
+    // None when a join may start now, otherwise how long until it may
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.refilled).as_secs_f64() * self.rate).min(self.rate.max(1.0));
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// A STUN or TURN server, as the browser's RTCIceServer takes it.
+#[derive(Serialize, Clone, Debug)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// The ICE servers of the command line. TURN servers need the credentials.
+pub fn ice_servers(stun: &[String], turn: &[String], user: Option<&str>, pass: Option<&str>) -> anyhow::Result<Vec<IceServer>> {
+    for url in stun {
+        if !(url.starts_with("stun:") || url.starts_with("stuns:")) {
+            anyhow::bail!("STUN server '{}' must start with stun: or stuns:", url);
+        }
+    }
+    for url in turn {
+        if !(url.starts_with("turn:") || url.starts_with("turns:")) {
+            anyhow::bail!("TURN server '{}' must start with turn: or turns:", url);
+        }
+    }
+    let mut servers = Vec::new();
+    if !stun.is_empty() {
+        servers.push(IceServer { urls: stun.to_vec(), username: None, credential: None });
+    }
+    if !turn.is_empty() {
+        let (Some(user), Some(pass)) = (user, pass) else {
+            anyhow::bail!("--turn needs --turn-user and --turn-pass");
+        };
+        servers.push(IceServer { urls: turn.to_vec(), username: Some(user.to_string()), credential: Some(pass.to_string()) });
+    }
+    Ok(servers)
+}
+
+#[derive(Default)]
+struct Stats {
+    connected: u32,
+    failed: u32,
+    timed_out: u32,
+    total_wait: Duration,
+    worst_wait: Duration,
+    total_handshake: Duration,
+    worst_handshake: Duration,
+}
+
+pub struct Negotiator {
+    pool: Handle,
+    api: API,
+    slots: Arc<Semaphore>,
+    rate: Mutex<JoinRate>,
+    timeout: Duration,
+    ice_servers: Vec<IceServer>,
+    queued: AtomicUsize,
+    stats: Mutex<Stats>,
+}
+
+/// A handshake slot. Report how the handshake ended; dropping it unreported counts as failed.
+pub struct Slot {
+    negotiator: Arc<Negotiator>,
+    started: Instant,
+    _permit: OwnedSemaphorePermit,
+    reported: bool,
+}
+
+pub enum Outcome {
+    Connected,
+    Failed,
+    TimedOut,
+}
+
+impl Negotiator {
+    /// `ips`: where ICE may gather candidates (empty: anywhere).
+    pub fn load(game_dir: &Path, ice_servers: Vec<IceServer>, ips: &[IpAddr]) -> anyhow::Result<Arc<Self>> {
+        let config_path = game_dir.join(CONFIG_FILE);
+        let config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+            Err(_) => Config::default(),
+        };
+        let cfg = config.webrtc.unwrap_or_default();
+        if cfg.threads == 0 || cfg.max_concurrent == 0 || !(cfg.joins_per_second > 0.0) || !(cfg.timeout > 0.0) {
+            anyhow::bail!("[webrtc] threads, max_concurrent, joins_per_second and timeout must be greater than 0");
+        }
+
+        // The runtime lives on its own thread for the whole process
+        let (tx, rx) = std::sync::mpsc::channel();
+        let threads = cfg.threads;
+        thread::Builder::new().name("webrtc-pool".into()).spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(threads)
+                .thread_name("webrtc")
+                .enable_all()
+                .build();
+            match runtime {
+                Ok(runtime) => {
+                    let _ = tx.send(Ok(runtime.handle().clone()));
+                    runtime.block_on(std::future::pending::<()>());
+                }
+                Err(e) => { let _ = tx.send(Err(e)); }
+            }
+        })?;
+        let pool = rx.recv()??;
+
+        // One API (media engine and interceptors) shared by every peer connection
+        let mut media = MediaEngine::default();
+        let registry = register_default_interceptors(Registry::new(), &mut media)?;
+        let mut settings = SettingEngine::default();
+        if !ips.is_empty() {
+            let mut network_types = Vec::new();
+            if ips.iter().any(IpAddr::is_ipv4) {
+                network_types.push(NetworkType::Udp4);
+            }
+            if ips.iter().any(IpAddr::is_ipv6) {
+                network_types.push(NetworkType::Udp6);
+            }
+            settings.set_network_types(network_types);
+            let ips = ips.to_vec();
+            settings.set_ip_filter(Box::new(move |ip: IpAddr| {
+                ips.iter().any(|bound| *bound == ip || (bound.is_unspecified() && bound.is_ipv4() == ip.is_ipv4()))
+            }));
+        }
+        let api = APIBuilder::new()
+            .with_setting_engine(settings)
+            .with_media_engine(media)
+            .with_interceptor_registry(registry)
+            .build();
+
+        let negotiator = Arc::new(Self {
+            pool,
+            api,
+            slots: Arc::new(Semaphore::new(cfg.max_concurrent)),
+            rate: Mutex::new(JoinRate { rate: cfg.joins_per_second, tokens: cfg.joins_per_second.max(1.0), refilled: Instant::now() }),
+            timeout: Duration::from_secs_f64(cfg.timeout),
+            ice_servers,
+            queued: AtomicUsize::new(0),
+            stats: Mutex::new(Stats::default()),
+        });
+        let reporter = negotiator.clone();
+        negotiator.pool.spawn(async move {
+            let mut interval = tokio::time::interval(METRICS_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                reporter.report();
+            }
+        });
+        Ok(negotiator)
+    }
+
+    /// Runs a connection's WebRTC side on the pool.
+    pub fn spawn<F: Future<Output = ()> + Send + 'static>(&self, task: F) -> JoinHandle<()> {
+        self.pool.spawn(task)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Waits for the join rate and a free slot. Safe to cancel.
+    pub async fn acquire(self: &Arc<Self>) -> Slot {
+        let queued_at = Instant::now();
+        let queued = Queued::new(&self.queued);
+        loop {
+            let wait = self.rate.lock().unwrap().take();
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => break,
+            }
+        }
+        let permit = self.slots.clone().acquire_owned().await.expect("slots are never closed");
+        drop(queued);
+
+        let wait = queued_at.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        stats.total_wait += wait;
+        stats.worst_wait = stats.worst_wait.max(wait);
+        Slot { negotiator: self.clone(), started: Instant::now(), _permit: permit, reported: false }
+    }
+
+    /// For clients: sent with WELCOME.
+    pub fn ice_servers(&self) -> &[IceServer] {
+        &self.ice_servers
+    }
+
+    pub async fn new_peer_connection(&self) -> webrtc::error::Result<RTCPeerConnection> {
+        // No servers configured: fewer FDs and no DNS lookups; localhost and LAN work without them
+        let ice_servers = self.ice_servers.iter()
+            .map(|s| RTCIceServer {
+                urls: s.urls.clone(),
+                username: s.username.clone().unwrap_or_default(),
+                credential: s.credential.clone().unwrap_or_default(),
+                ..Default::default()
+            })
+            .collect();
+        self.api.new_peer_connection(RTCConfiguration { ice_servers, ..Default::default() }).await
+    }
+
+    fn report(&self) {
+        let queued = self.queued.load(Ordering::Relaxed);
+        let mut stats = self.stats.lock().unwrap();
+        let handshakes = stats.connected + stats.failed + stats.timed_out;
+        if handshakes > 0 || queued > 0 {
+            let avg_ms = |total: Duration| if handshakes > 0 { total.as_secs_f64() * 1000.0 / handshakes as f64 } else { 0.0 };
+            tracing::info!(
+                target: METRICS_TARGET, event = "webrtc_negotiation", connected = stats.connected,
+                failed = stats.failed, timed_out = stats.timed_out, queued,
+                avg_wait_ms = avg_ms(stats.total_wait), worst_wait_ms = stats.worst_wait.as_secs_f64() * 1000.0,
+                avg_handshake_ms = avg_ms(stats.total_handshake), worst_handshake_ms = stats.worst_handshake.as_secs_f64() * 1000.0,
+                "WebRTC negotiation"
+            );
+        }
+        *stats = Stats::default();
+    }
+}
+
+// Counts a waiting join until dropped, including when the wait is cancelled
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Slot {
+    /// Records the handshake and frees the slot.
+    pub fn finish(mut self, outcome: Outcome) {
+        self.record(outcome);
+    }
+
+    fn record(&mut self, outcome: Outcome) {
+        self.reported = true;
+        let took = self.started.elapsed();
+        let mut stats = self.negotiator.stats.lock().unwrap();
+        match outcome {
+            Outcome::Connected => stats.connected += 1,
+            Outcome::Failed => stats.failed += 1,
+            Outcome::TimedOut => stats.timed_out += 1,
+        }
+        stats.total_handshake += took;
+        stats.worst_handshake = stats.worst_handshake.max(took);
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if !self.reported {
+            self.record(Outcome::Failed);
+        }
+    }
+}
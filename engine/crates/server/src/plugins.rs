@@ -0,0 +1,92 @@
+use engine::plugin::{Plugin, PluginFactory};
+use crate::content::ContentPlugin;
+use crate::lobby::LobbyPlugin;
+use crate::storage::StoragePlugin;
+use serde::Deserialize;
+use std::path::Path;
+
+// --- Plugin Loading ---
+// Plugins are enabled per game in `cleoselene.toml` (next to the script):
+//
+//   [[plugin]]
+//   name = "my_plugin"                  # compiled in (see BUILTIN_PLUGINS)
+//
+//   [[plugin]]
+//   path = "plugins/libmy_plugin.so"    # dynamic library (feature `dynamic-plugins`)
+//   [plugin.settings]
+//   any = "value"
+
+pub const CONFIG_FILE: &str = "cleoselene.toml";
+
+// Compile-time registry. Plugins shipped with the server register here behind their own cargo feature:
+//   #[cfg(feature = "plugin-foo")] ("foo", foo::create),
+const BUILTIN_PLUGINS: &[(&str, PluginFactory)] = &[];
+
+#[derive(Deserialize, Default)]
+struct Config {
+    #[serde(default)]
+    plugin: Vec<PluginEntry>,
+}
+
+#[derive(Deserialize)]
+struct PluginEntry {
+    name: Option<String>,
+    path: Option<String>,
+    #[serde(default)]
+    settings: Option<toml::Value>,
+}
+
+pub fn load_plugins(game_dir: &Path, storage: StoragePlugin, lobby: LobbyPlugin) -> anyhow::Result<Vec<Box<dyn Plugin>>> {
+    let config_path = game_dir.join(CONFIG_FILE);
+    let config: Config = match std::fs::read_to_string(&config_path) {
+        Ok(text) => toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+        Err(_) => Config::default(), // No config, built-in plugins only
+    };
+
+    // Built-in, always available: name generator and profanity filter, persistent storage, rooms
+    let mut plugins: Vec<Box<dyn Plugin>> = vec![
+        Box::new(ContentPlugin::load(game_dir)?),
+        Box::new(storage),
+        Box::new(lobby),
+    ];
+    for entry in config.plugin {
+        let settings = match entry.settings {
+            Some(v) => serde_json::to_value(v)?,
+            None => serde_json::Value::Null,
+        };
+
+        let plugin = match (&entry.name, &entry.path) {
+            (_, Some(path)) => load_dynamic(&game_dir.join(path), &settings)?,
+            (Some(name), None) => {
+                let factory = BUILTIN_PLUGINS.iter()
+                    .find(|(n, _)| *n == name.as_str())
+                    .map(|(_, f)| f)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown plugin '{}' (not compiled into this server)", name))?;
+                factory(&settings)
+            },
+            (None, None) => anyhow::bail!("[[plugin]] entry needs a `name` or a `path`"),
+        };
+        tracing::info!("Plugin loaded: {}", plugin.name());
+        plugins.push(plugin);
+    }
+    Ok(plugins)
+}
+
+#[cfg(feature = "dynamic-plugins")]
+fn load_dynamic(path: &Path, settings: &serde_json::Value) -> anyhow::Result<Box<dyn Plugin>> {
+    type CreateFn = fn(&serde_json::Value) -> Box<dyn Plugin>;
+
+    // SAFETY: the library must be built against the same engine crate and rustc version
+    // (trait objects cross the boundary). It stays loaded for the lifetime of the process.
+    unsafe {
+        let lib = Box::leak(Box::new(libloading::Library::new(path)?));
+        let create: libloading::Symbol<CreateFn> = lib.get(engine::plugin::PLUGIN_CREATE_SYMBOL)?;
+        Ok(create(settings))
+    }
+}
+
+#[cfg(not(feature = "dynamic-plugins"))]
+fn load_dynamic(path: &Path, _settings: &serde_json::Value) -> anyhow::Result<Box<dyn Plugin>> {
+    anyhow::bail!("Cannot load {}: server built without the `dynamic-plugins` feature", path.display())
+}
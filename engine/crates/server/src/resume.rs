@@ -0,0 +1,109 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::plugins::CONFIG_FILE;
+
+// --- Session Resume ---
+// A player whose connection drops (mobile networks, a backgrounded tab) keeps their place
+// for a grace period: on_disconnect is held back, the script's player entry stays and
+// commands addressed to the session pile up. A client connecting with the same session id
+// (the browser client retries with ?session=) resumes it: the script gets
+// on_reconnect(session_id) instead of on_connect, and the client is sent what the session
+// got on connect again, then what piled up. After the grace period, on_disconnect runs.
+//
+//   [reconnect]
+//   grace = 30   # seconds (default); 0 = on_disconnect as soon as the connection drops
+
+#[derive(Deserialize, Default)]
+struct Config {
+    reconnect: Option<ReconnectConfig>,
+}
+
+#[derive(Deserialize)]
+struct ReconnectConfig {
+    #[serde(default = "default_grace")]
+    grace: f32,
+}
+
+fn default_grace() -> f32 { 30.0 }
+
+/// A session held for its player to come back.
+pub struct Away {
+    pub since: Instant,
+    pub joined_at: Instant,
+    pub init: bytes::Bytes, // What on_connect sent the client
+    pub auth: Option<serde_json::Value>, // Who logged in (auth.rs)
+}
+
+pub struct Resume {
+    grace: Duration,
+    away: HashMap<String, Away>,
+}
+
+impl Resume {
+    pub fn load(game_dir: &Path) -> anyhow::Result<Self> {
+        let config_path = game_dir.join(CONFIG_FILE);
+        let config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+            Err(_) => Config::default(),
+        };
+        let grace = config.reconnect.map_or_else(default_grace, |r| r.grace);
+        if !(grace >= 0.0) || !grace.is_finite() {
+            anyhow::bail!("[reconnect] grace must be a number of seconds, 0 or more");
+        }
+        Ok(Self { grace: Duration::from_secs_f32(grace), away: HashMap::new() })
+    }
+
+    /// Holds the session of a dropped connection. False when sessions aren't held
+    /// (grace 0): the player is gone.
+    pub fn hold(&mut self, session_id: &str, away: Away) -> bool {
+        if self.grace.is_zero() {
+            return false;
+        }
+        self.away.insert(session_id.to_string(), away);
+        true
+    }
+
+    pub fn holds(&self, session_id: &str) -> bool {
+        self.away.contains_key(session_id)
+    }
+
+    /// The held session, if its player came back in time.
+    pub fn take(&mut self, session_id: &str) -> Option<Away> {
+        self.away.remove(session_id)
+    }
+
+    /// Whether a client logged in as `auth` may take the session over: it isn't held, or
+    /// was held for the same identity.
+    pub fn matches(&self, session_id: &str, auth: Option<&serde_json::Value>) -> bool {
+        self.away.get(session_id).map_or(true, |a| a.auth.as_ref() == auth)
+    }
+
+    /// Sessions whose grace period is over, removed.
+    pub fn expired(&mut self, now: Instant) -> Vec<(String, Away)> {
+        let gone: Vec<String> = self.away.iter()
+            .filter(|(_, a)| now.duration_since(a.since) >= self.grace)
+            .map(|(id, _)| id.clone())
+            .collect();
+        gone.into_iter().filter_map(|id| self.away.remove_entry(&id)).collect()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut Away)> {
+        self.away.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.away.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.away.is_empty()
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+}
@@ -0,0 +1,71 @@
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// --- Time-Travel Buffer ---
+// With --rewind <seconds>, the game loop stores the script's snapshot() after every
+// tick together with the inputs processed in that tick. POST /admin/rewind?seconds=N
+// restores the newest snapshot at least N seconds old and resumes from there; the
+// inputs recorded since that point are returned so the bug can be reproduced.
+
+pub struct InputRecord {
+    pub session_id: String,
+    pub code: u8,
+    pub active: bool,
+}
+
+struct Frame {
+    at: Instant,
+    tick: u64,
+    snapshot: Value,
+    inputs: Vec<InputRecord>,
+}
+
+pub struct RewindBuffer {
+    window: Duration,
+    frames: VecDeque<Frame>,
+    tick: u64,
+}
+
+impl RewindBuffer {
+    pub fn new(window: Duration) -> Self {
+        Self { window, frames: VecDeque::new(), tick: 0 }
+    }
+
+    pub fn push(&mut self, snapshot: Value, inputs: Vec<InputRecord>) {
+        let now = Instant::now();
+        self.tick += 1;
+        self.frames.push_back(Frame { at: now, tick: self.tick, snapshot, inputs });
+        while self.frames.front().is_some_and(|f| now.duration_since(f.at) > self.window) {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Drops every frame newer than the target and returns its snapshot, plus a JSON
+    /// report (age actually rewound, inputs that happened after the restored frame).
+    pub fn rewind(&mut self, seconds: f32) -> Option<(Value, Value)> {
+        let now = Instant::now();
+        // No further back than the window reaches (which NaN asks for too)
+        let target = Duration::try_from_secs_f32(seconds.clamp(0.0, self.window.as_secs_f32())).unwrap_or(self.window);
+        let index = self.frames.iter().rposition(|f| now.duration_since(f.at) >= target)
+            .or(if self.frames.is_empty() { None } else { Some(0) })?; // Not that much history: oldest frame
+
+        let discarded: Vec<Frame> = self.frames.drain(index + 1..).collect();
+        let frame = self.frames.back()?;
+        let inputs: Vec<Value> = discarded.iter()
+            .flat_map(|f| f.inputs.iter().map(move |i| json!({
+                "tick": f.tick,
+                "session": i.session_id,
+                "code": i.code,
+                "active": i.active,
+            })))
+            .collect();
+
+        let report = json!({
+            "rewound_secs": now.duration_since(frame.at).as_secs_f64(),
+            "tick": frame.tick,
+            "inputs_since": inputs,
+        });
+        Some((frame.snapshot.clone(), report))
+    }
+}
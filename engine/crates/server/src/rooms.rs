@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::analytics::Analytics;
+use crate::storage::StoragePlugin;
+use crate::{game_loop, ClientConnection};
+
+// --- Rooms ---
+// Each room is a world of its own: a GameState, a game loop thread and its clients.
+// Clients pick one with `?room=name` on the WebSocket URL (none: DEFAULT_ROOM). A room is
+// started by its first client and stops once it has been empty for EMPTY_ROOM_TTL, except
+// the default room, which runs for the whole process (idle policy permitting). Scripts
+// can start rooms running another script of the game, move players there and send
+// messages to each other's scripts (lobby.rs): a room's inbox is drained by its game loop,
+// which hands each message to on_room_message. At most max_rooms run at once (the default
+// room included): past that, clients asking for a room that isn't running are refused.
+// On shutdown every room closes and no new one starts (see shutdown.rs).
+
+pub const DEFAULT_ROOM: &str = "default";
+pub const MAX_ROOM_NAME_LEN: usize = 64;
+pub const EMPTY_ROOM_TTL: Duration = Duration::from_secs(30);
+/// Messages waiting for a room's script; more are refused.
+pub const MAX_ROOM_INBOX: usize = 256;
+
+pub type DebugRequests = mpsc::Receiver<(String, oneshot::Sender<String>)>;
+pub type RewindRequests = mpsc::Receiver<(f32, oneshot::Sender<String>)>;
+
+/// What every room's game loop starts with.
+pub struct RoomConfig {
+    pub script_path: PathBuf, // Unless the room was created with another
+    pub debug: bool,              // /debug
+    pub rewind: Option<Duration>, // /admin/rewind window
+    pub dev: bool,
+    pub tick_rate: u32,         // --tick-rate
+    pub send_rate: Option<u32>, // --send-rate
+    pub max_players: Option<usize>, // --max-players
+    pub state_dir: PathBuf,         // --state-dir
+    pub max_rooms: Option<usize>,   // --max-rooms
+}
+
+/// From one room's script to another's (api.room_send, api.rooms_broadcast).
+#[derive(Clone)]
+pub struct RoomMessage {
+    pub from: String, // The sending room
+    pub event: String,
+    pub payload: serde_json::Value,
+}
+
+struct Room {
+    script_path: PathBuf,
+    players: usize, // As of the room's last tick
+    inbox: Vec<RoomMessage>,
+    // Clients waiting to join the room's game loop
+    new_clients: Arc<Mutex<Vec<ClientConnection>>>,
+    tx_debug: Option<mpsc::Sender<(String, oneshot::Sender<String>)>>,
+    tx_rewind: Option<mpsc::Sender<(f32, oneshot::Sender<String>)>>,
+}
+
+pub struct Rooms {
+    config: RoomConfig,
+    analytics: Analytics, // Shared by every room
+    storage: StoragePlugin, // Every room registers a handle of its own (for_room)
+    rooms: Mutex<HashMap<String, Room>>,
+    shutting_down: AtomicBool,
+}
+
+impl Rooms {
+    pub fn new(config: RoomConfig, analytics: Analytics, storage: StoragePlugin) -> Arc<Self> {
+        Arc::new(Self { config, analytics, storage, rooms: Mutex::new(HashMap::new()), shutting_down: AtomicBool::new(false) })
+    }
+
+    /// Letters, digits, '-' and '_', at most MAX_ROOM_NAME_LEN bytes.
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_ROOM_NAME_LEN
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }
+
+    pub fn config(&self) -> &RoomConfig {
+        &self.config
+    }
+
+    pub fn analytics(&self) -> &Analytics {
+        &self.analytics
+    }
+
+    pub fn storage(&self) -> &StoragePlugin {
+        &self.storage
+    }
+
+    /// The directory scripts of the game live in.
+    pub fn game_dir(&self) -> &Path {
+        self.config.script_path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Whether a client could join the room now: it is running, or another one may start.
+    pub fn accepts(&self, name: &str) -> bool {
+        let rooms = self.rooms.lock().unwrap();
+        !self.shutting_down() && (rooms.contains_key(name) || !self.full(&rooms))
+    }
+
+    fn full(&self, rooms: &HashMap<String, Room>) -> bool {
+        self.config.max_rooms.is_some_and(|max| rooms.len() >= max)
+    }
+
+    /// Starts the room, running `script_path` (None: the main script), unless it is
+    /// running already. False when it was, or when max_rooms are running.
+    pub fn open(self: &Arc<Self>, name: &str, script_path: Option<PathBuf>) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.contains_key(name) || self.shutting_down() {
+            return false;
+        }
+        if self.full(&rooms) {
+            tracing::warn!("Room '{}' not started: {} rooms running (--max-rooms)", name, rooms.len());
+            return false;
+        }
+        let script_path = script_path.unwrap_or_else(|| self.config.script_path.clone());
+        rooms.insert(name.to_string(), self.start(name, script_path));
+        true
+    }
+
+    /// Hands a client to the room's game loop, starting the room if needed. While shutting
+    /// down, or when the room would be one more than max_rooms, the client is dropped,
+    /// which closes its connection, and the result is false.
+    pub fn join(self: &Arc<Self>, name: &str, conn: ClientConnection) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        if self.shutting_down() {
+            return false;
+        }
+        if !rooms.contains_key(name) && self.full(&rooms) {
+            tracing::warn!("Room '{}' not started: {} rooms running (--max-rooms)", name, rooms.len());
+            return false;
+        }
+        let room = rooms.entry(name.to_string()).or_insert_with(|| self.start(name, self.config.script_path.clone()));
+        room.new_clients.lock().unwrap().push(conn);
+        true
+    }
+
+    fn start(self: &Arc<Self>, name: &str, script_path: PathBuf) -> Room {
+        tracing::info!("Starting room '{}' ({:?})", name, script_path);
+        let new_clients = Arc::new(Mutex::new(Vec::new()));
+        let (tx_debug, rx_debug) = if self.config.debug {
+            let (tx, rx) = mpsc::channel(10);
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let (tx_rewind, rewind) = match self.config.rewind {
+            Some(window) => {
+                let (tx, rx) = mpsc::channel(4);
+                (Some(tx), Some((window, rx)))
+            }
+            None => (None, None),
+        };
+
+        let rooms = self.clone();
+        let queue = new_clients.clone();
+        let room = name.to_string();
+        let script = script_path.clone();
+        thread::spawn(move || {
+            game_loop(room, rooms, script, queue, rx_debug, rewind);
+        });
+        Room { script_path, players: 0, inbox: Vec::new(), new_clients, tx_debug, tx_rewind }
+    }
+
+    /// Reported by the room's game loop every tick.
+    pub fn set_players(&self, name: &str, players: usize) {
+        if let Some(room) = self.rooms.lock().unwrap().get_mut(name) {
+            room.players = players;
+        }
+    }
+
+    /// Running rooms by name: players and the script they run (relative to the game directory).
+    pub fn list(&self) -> Vec<(String, usize, PathBuf)> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut list: Vec<_> = rooms.iter()
+            .map(|(name, room)| {
+                let script = room.script_path.strip_prefix(self.game_dir()).unwrap_or(&room.script_path);
+                (name.clone(), room.players, script.to_path_buf())
+            })
+            .collect();
+        list.sort();
+        list
+    }
+
+    /// Queues a message for the room's script. False when the room isn't running or
+    /// already has MAX_ROOM_INBOX messages waiting.
+    pub fn send(&self, name: &str, message: RoomMessage) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(name) {
+            Some(room) if room.inbox.len() < MAX_ROOM_INBOX => {
+                room.inbox.push(message);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Queues a message for every running room but the sender's; the number of rooms
+    /// it reached.
+    pub fn broadcast(&self, message: RoomMessage) -> usize {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut reached = 0;
+        for (name, room) in rooms.iter_mut() {
+            if *name != message.from && room.inbox.len() < MAX_ROOM_INBOX {
+                room.inbox.push(message.clone());
+                reached += 1;
+            }
+        }
+        reached
+    }
+
+    /// The messages waiting for the room, oldest first. Called by its game loop every tick.
+    pub fn take_messages(&self, name: &str) -> Vec<RoomMessage> {
+        match self.rooms.lock().unwrap().get_mut(name) {
+            Some(room) => std::mem::take(&mut room.inbox),
+            None => Vec::new(),
+        }
+    }
+
+    /// Called by the game loop of an empty room: true when the room is gone, false when a
+    /// client is about to join after all.
+    pub fn retire(&self, name: &str) -> bool {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.get(name).is_some_and(|r| !r.new_clients.lock().unwrap().is_empty()) {
+            return false;
+        }
+        rooms.remove(name);
+        true
+    }
+
+    /// Removes a room whose game loop can't run; the clients waiting for it are dropped,
+    /// which closes their connections.
+    pub fn close(&self, name: &str) {
+        self.rooms.lock().unwrap().remove(name);
+    }
+
+    /// Asks every room to shut down; their game loops close them (see shutdown.rs).
+    pub fn shut_down(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Waits for every room to close, at most `timeout`. False if some are still running.
+    pub async fn closed(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !self.is_empty() {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.rooms.lock().unwrap().is_empty()
+    }
+
+    pub fn tx_debug(&self, name: &str) -> Option<mpsc::Sender<(String, oneshot::Sender<String>)>> {
+        self.rooms.lock().unwrap().get(name)?.tx_debug.clone()
+    }
+
+    pub fn tx_rewind(&self, name: &str) -> Option<mpsc::Sender<(f32, oneshot::Sender<String>)>> {
+        self.rooms.lock().unwrap().get(name)?.tx_rewind.clone()
+    }
+}
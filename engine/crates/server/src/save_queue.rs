@@ -0,0 +1,213 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::storage::{Staged, Storage};
+
+// --- Background Saves ---
+// Storage writes never run on the game loop: they go through a bounded queue to a saver
+// thread that owns every write to the backend. Queued values are visible to reads right
+// away (an overlay of pending writes), and each finished write is reported back so the
+// game loop can call `on_save_complete`. Transactions are queued too, behind earlier
+// writes, but their caller waits for the answer. One queue serves the whole process: each
+// room gets a handle (for_room) that shares the backend but collects its own results.
+
+// Save latency and queue depth are logged this often (when anything was saved)
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+const METRICS_TARGET: &str = "metrics";
+
+type Completed = Arc<Mutex<Vec<SaveResult>>>;
+
+enum Job {
+    Write { key: String, value: Option<Value>, seq: u64, completed: Completed },
+    Commit { reads: Staged, writes: Staged, reply: SyncSender<anyhow::Result<bool>> },
+}
+
+/// A finished write, delivered to Lua as `on_save_complete(ok, key, error)`.
+pub struct SaveResult {
+    pub ok: bool,
+    pub key: String,
+    pub error: Option<String>,
+}
+
+type Backend = Arc<Mutex<Box<dyn Storage>>>;
+// Latest queued value per key (None = delete), tagged with the write's sequence number
+type Pending = Arc<Mutex<HashMap<String, (u64, Option<Value>)>>>;
+
+/// Cheap to clone into Lua closures.
+#[derive(Clone)]
+pub struct SaveQueue {
+    backend: Backend,
+    jobs: SyncSender<Job>,
+    pending: Pending,
+    seq: Arc<AtomicU64>,
+    depth: Arc<AtomicUsize>,
+    completed: Completed, // This handle's finished writes
+}
+
+impl SaveQueue {
+    pub fn start(storage: Box<dyn Storage>, capacity: usize) -> Self {
+        let (jobs, rx) = sync_channel(capacity.max(1));
+        let queue = Self {
+            backend: Arc::new(Mutex::new(storage)),
+            jobs,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            seq: Arc::new(AtomicU64::new(0)),
+            depth: Arc::new(AtomicUsize::new(0)),
+            completed: Arc::new(Mutex::new(Vec::new())),
+        };
+        let worker = queue.clone();
+        thread::spawn(move || worker.run(rx));
+        queue
+    }
+
+    /// Another handle on the same queue and backend, with its own finished writes.
+    pub fn for_room(&self) -> Self {
+        Self { completed: Arc::new(Mutex::new(Vec::new())), ..self.clone() }
+    }
+
+    pub fn get(&self, key: &str) -> anyhow::Result<Option<Value>> {
+        if let Some((_, value)) = self.pending.lock().unwrap().get(key) {
+            return Ok(value.clone());
+        }
+        self.backend.lock().unwrap().get(key)
+    }
+
+    pub fn keys(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut keys = self.backend.lock().unwrap().keys(prefix)?;
+        let pending = self.pending.lock().unwrap();
+        keys.retain(|k| !matches!(pending.get(k), Some((_, None))));
+        keys.extend(pending.iter()
+            .filter(|(k, (_, value))| value.is_some() && k.starts_with(prefix))
+            .map(|(k, _)| k.clone()));
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    /// Queues a write (None deletes). False when the queue is full: nothing was queued.
+    pub fn write(&self, key: String, value: Option<Value>) -> bool {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        // Overlay first, so the saver never finishes a write that reads can't see yet
+        let previous = self.pending.lock().unwrap().insert(key.clone(), (seq, value.clone()));
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        match self.jobs.try_send(Job::Write { key: key.clone(), value, seq, completed: self.completed.clone() }) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                let mut pending = self.pending.lock().unwrap();
+                if pending.get(&key).is_some_and(|(s, _)| *s == seq) {
+                    match previous {
+                        Some(p) => pending.insert(key, p),
+                        None => pending.remove(&key),
+                    };
+                }
+                false
+            }
+        }
+    }
+
+    /// Runs `Storage::commit` on the saver thread after every write queued before it.
+    pub fn commit(&self, reads: Staged, writes: Staged) -> anyhow::Result<bool> {
+        let (reply, rx) = sync_channel(1);
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        if self.jobs.send(Job::Commit { reads, writes, reply }).is_err() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+            anyhow::bail!("saver thread stopped");
+        }
+        rx.recv().map_err(|_| anyhow::anyhow!("saver thread stopped"))?
+    }
+
+    pub fn take_completed(&self) -> Vec<SaveResult> {
+        std::mem::take(&mut *self.completed.lock().unwrap())
+    }
+
+    fn run(self, rx: Receiver<Job>) {
+        let mut stats = SaveStats::new();
+        loop {
+            let job = match rx.recv_timeout(METRICS_INTERVAL) {
+                Ok(job) => job,
+                Err(RecvTimeoutError::Timeout) => {
+                    stats.report(self.depth.load(Ordering::Relaxed));
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let started = Instant::now();
+            match job {
+                Job::Write { key, value, seq, completed } => {
+                    let result = {
+                        let mut backend = self.backend.lock().unwrap();
+                        match &value {
+                            Some(v) => backend.set(&key, v),
+                            None => backend.delete(&key),
+                        }
+                    };
+                    // A newer write to the same key may be queued behind this one: keep its entry
+                    {
+                        let mut pending = self.pending.lock().unwrap();
+                        if pending.get(&key).is_some_and(|(s, _)| *s == seq) {
+                            pending.remove(&key);
+                        }
+                    }
+                    if let Err(e) = &result {
+                        tracing::error!("Storage save failed for '{}': {}", key, e);
+                    }
+                    completed.lock().unwrap().push(SaveResult {
+                        ok: result.is_ok(),
+                        key,
+                        error: result.err().map(|e| e.to_string()),
+                    });
+                }
+                Job::Commit { reads, writes, reply } => {
+                    let _ = reply.send(self.backend.lock().unwrap().commit(&reads, &writes));
+                }
+            }
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+            stats.record(started.elapsed());
+            if stats.due() {
+                stats.report(self.depth.load(Ordering::Relaxed));
+            }
+        }
+    }
+}
+
+struct SaveStats {
+    saves: u32,
+    total: Duration,
+    worst: Duration,
+    last_report: Instant,
+}
+
+impl SaveStats {
+    fn new() -> Self {
+        Self { saves: 0, total: Duration::ZERO, worst: Duration::ZERO, last_report: Instant::now() }
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        self.saves += 1;
+        self.total += elapsed;
+        self.worst = self.worst.max(elapsed);
+    }
+
+    fn due(&self) -> bool {
+        self.last_report.elapsed() >= METRICS_INTERVAL
+    }
+
+    fn report(&mut self, queue_depth: usize) {
+        if self.saves > 0 || queue_depth > 0 {
+            let avg = if self.saves > 0 { self.total / self.saves } else { Duration::ZERO };
+            tracing::info!(
+                target: METRICS_TARGET, event = "storage_saves", saves = self.saves,
+                avg_ms = avg.as_secs_f64() * 1000.0, worst_ms = self.worst.as_secs_f64() * 1000.0,
+                queue_depth, "Storage save latency"
+            );
+        }
+        *self = Self::new();
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// --- Graceful Shutdown ---
+// On SIGINT (Ctrl+C) or SIGTERM every room stops between two ticks: the script gets
+// on_shutdown(), its last messages and a SHUTDOWN signal go out to the players, and the
+// room's snapshot_state() is written to SNAPSHOT_DIR/<room>.json in the state directory
+// (--state-dir). The listener closes once every room is done, or after SHUTDOWN_TIMEOUT.
+// When the room starts again, its game is restored from that file (take_state).
+
+pub const SNAPSHOT_DIR: &str = "shutdown";
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves on the first SIGINT or SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => { sigterm.recv().await; }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+fn state_path(state_dir: &Path, room: &str) -> PathBuf {
+    state_dir.join(SNAPSHOT_DIR).join(format!("{}.json", room)) // Room names are safe file names
+}
+
+/// Writes a room's state, replacing the one of the previous shutdown. Returns the file.
+pub fn save_state(state_dir: &Path, room: &str, state: &str) -> anyhow::Result<PathBuf> {
+    let path = state_path(state_dir, room);
+    std::fs::create_dir_all(state_dir.join(SNAPSHOT_DIR))?;
+    std::fs::write(&path, state)?;
+    Ok(path)
+}
+
+/// The state the room's last shutdown saved, if any. The file is removed: a state is
+/// restored once, never again after a later crash.
+pub fn take_state(state_dir: &Path, room: &str) -> anyhow::Result<Option<String>> {
+    let path = state_path(state_dir, room);
+    match std::fs::read_to_string(&path) {
+        Ok(state) => {
+            std::fs::remove_file(&path)?;
+            Ok(Some(state))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
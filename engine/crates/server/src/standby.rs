@@ -0,0 +1,92 @@
+use engine::plugin::Plugin;
+use engine::shared::SharedData;
+use engine::GameState;
+use serde_json::Value;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+// --- Warm Standby ---
+// Hot reloads build the replacement GameState on a thread of its own: reading and
+// compiling the script, init() and restore(state) with the running game's snapshot()
+// no longer stall the game loop, which swaps games between two ticks once the new one
+// is ready. Whatever the running game does while the replacement builds is not carried
+// over. Saving the script again during a build discards that build and starts another.
+
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+type Plugins = Vec<Box<dyn Plugin>>;
+type Build = (Plugins, Result<GameState, String>);
+
+pub struct Standby {
+    script_path: String,
+    shared: SharedData,
+    plugins: Option<Plugins>, // None while a build registers them
+    building: Option<Receiver<Build>>,
+    stale: bool, // The script changed since the running build started
+}
+
+impl Standby {
+    pub fn new(script_path: String, plugins: Plugins, shared: SharedData) -> Self {
+        Self { script_path, shared, plugins: Some(plugins), building: None, stale: false }
+    }
+
+    /// Starts building the script as it is now, restored from `game`'s snapshot.
+    pub fn start(&mut self, game: &GameState) {
+        let Some(mut plugins) = self.plugins.take() else {
+            self.stale = true;
+            return;
+        };
+        let snapshot = match game.snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                tracing::error!("Lua snapshot Error (reload continues without state): {}", e);
+                None
+            }
+        };
+        let script_path = self.script_path.clone();
+        let shared = self.shared.clone();
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            thread::sleep(DEBOUNCE);
+            // Plugins come back even if the build panics
+            let result = panic::catch_unwind(AssertUnwindSafe(|| build(&script_path, &mut plugins, &shared, snapshot)))
+                .unwrap_or_else(|_| Err("Reload panicked".to_string()));
+            let _ = tx.send((plugins, result));
+        });
+        self.building = Some(rx);
+    }
+
+    pub fn building(&self) -> bool {
+        self.building.is_some()
+    }
+
+    /// The finished replacement (or why it failed), once. Stale builds are replaced by a
+    /// new one from `game` instead.
+    pub fn poll(&mut self, game: &GameState) -> Option<Result<GameState, String>> {
+        let (plugins, result) = match self.building.as_ref()?.try_recv() {
+            Ok(build) => build,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => unreachable!("the build thread always reports back"),
+        };
+        self.building = None;
+        self.plugins = Some(plugins);
+        if std::mem::take(&mut self.stale) {
+            self.start(game);
+            return None;
+        }
+        Some(result)
+    }
+}
+
+fn build(script_path: &str, plugins: &mut [Box<dyn Plugin>], shared: &SharedData, snapshot: Option<Value>) -> Result<GameState, String> {
+    let game = crate::load_game(script_path, plugins, shared)?;
+    if let Some(state) = snapshot {
+        if let Err(e) = game.restore(&state) {
+            tracing::error!("Lua restore Error: {}", e);
+            return Err(format!("restore failed: {}", e));
+        }
+    }
+    Ok(game)
+}
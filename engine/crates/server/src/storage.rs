@@ -0,0 +1,490 @@
+use engine::mlua::{self, LuaSerdeExt};
+use engine::plugin::{Plugin, PluginContext};
+use engine::SystemPhase;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::plugins::CONFIG_FILE;
+use crate::save_queue::SaveQueue;
+
+// --- Persistent Storage ---
+// Key/value persistence for games (api.storage_*), behind the `Storage` trait so every
+// persistence feature shares one configurable backend. Values are JSON. Selected in
+// cleoselene.toml (default: a JSON file in the state directory, --state-dir, no extra
+// dependencies).
+// Writes go through the background saver (see save_queue.rs).
+//
+//   [storage]
+//   backend = "file"                # "file", "sqlite" (feature `storage-sqlite`)
+//                                   # or "redis" (feature `storage-redis`)
+//   path = "storage.json"           # file/sqlite: relative to the state directory
+//   url = "redis://127.0.0.1/"      # redis
+//   prefix = "mygame:"              # redis: key namespace shared servers don't collide in
+//   queue_size = 1024               # writes waiting for the saver before storage_set fails
+
+pub trait Storage: Send {
+    fn get(&mut self, key: &str) -> anyhow::Result<Option<Value>>;
+    fn set(&mut self, key: &str, value: &Value) -> anyhow::Result<()>;
+    fn delete(&mut self, key: &str) -> anyhow::Result<()>;
+    /// Keys starting with `prefix`, sorted.
+    fn keys(&mut self, prefix: &str) -> anyhow::Result<Vec<String>>;
+    /// Applies `writes` (None deletes) atomically if every key in `reads` still holds the
+    /// value the transaction saw. Returns false on conflict, with nothing written.
+    fn commit(&mut self, reads: &Staged, writes: &Staged) -> anyhow::Result<bool>;
+}
+
+/// Keys touched by a transaction and the value read or written (None = absent / delete).
+pub type Staged = BTreeMap<String, Option<Value>>;
+
+// A transaction is retried this many times before giving up
+const TRANSACTION_ATTEMPTS: usize = 5;
+
+#[derive(Deserialize, Default)]
+struct Config {
+    storage: Option<StorageConfig>,
+}
+
+#[derive(Deserialize)]
+struct StorageConfig {
+    #[serde(default = "default_backend")]
+    backend: String,
+    path: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default = "default_queue_size")]
+    queue_size: usize,
+}
+
+fn default_backend() -> String { "file".to_string() }
+fn default_queue_size() -> usize { 1024 }
+
+fn load_config(game_dir: &Path) -> anyhow::Result<StorageConfig> {
+    let config_path = game_dir.join(CONFIG_FILE);
+    let config: Config = match std::fs::read_to_string(&config_path) {
+        Ok(text) => toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid {}: {}", config_path.display(), e))?,
+        Err(_) => Config::default(),
+    };
+    Ok(config.storage.unwrap_or(StorageConfig {
+        backend: default_backend(),
+        path: None,
+        url: None,
+        prefix: String::new(),
+        queue_size: default_queue_size(),
+    }))
+}
+
+fn open(state_dir: &Path, cfg: StorageConfig) -> anyhow::Result<Box<dyn Storage>> {
+    // Relative paths are in the state directory, created on demand
+    let file = |default: &str| -> anyhow::Result<PathBuf> {
+        let path = state_dir.join(cfg.path.as_deref().unwrap_or(default));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(path)
+    };
+    match cfg.backend.as_str() {
+        "file" => Ok(Box::new(FileStorage::open(file("storage.json")?)?)),
+        #[cfg(feature = "storage-sqlite")]
+        "sqlite" => Ok(Box::new(sqlite::SqliteStorage::open(&file("storage.db")?)?)),
+        #[cfg(feature = "storage-redis")]
+        "redis" => {
+            let url = cfg.url.ok_or_else(|| anyhow::anyhow!("[storage] backend = \"redis\" needs a `url`"))?;
+            Ok(Box::new(redis_backend::RedisStorage::open(&url, cfg.prefix)?))
+        }
+        "sqlite" | "redis" => anyhow::bail!("Storage backend '{}' is not compiled into this server (enable the storage-{} feature)", cfg.backend, cfg.backend),
+        other => anyhow::bail!("Unknown storage backend '{}' (expected file, sqlite or redis)", other),
+    }
+}
+
+// --- File backend ---
+// The whole map is kept in memory and rewritten on every change (write + rename, so a
+// crash never leaves a half-written file). Fine for small games; use sqlite beyond that.
+
+pub struct FileStorage {
+    path: PathBuf,
+    data: BTreeMap<String, Value>,
+}
+
+impl FileStorage {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Corrupt storage file {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, data })
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&self.data)?)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&mut self, key: &str) -> anyhow::Result<Option<Value>> {
+        Ok(self.data.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> anyhow::Result<()> {
+        self.data.insert(key.to_string(), value.clone());
+        self.save()
+    }
+
+    fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        if self.data.remove(key).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn keys(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        Ok(self.data.range(prefix.to_string()..)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, _)| k.clone())
+            .collect())
+    }
+
+    fn commit(&mut self, reads: &Staged, writes: &Staged) -> anyhow::Result<bool> {
+        // Only this process writes the file, but reads may have been overtaken by api.storage_set
+        if reads.iter().any(|(key, seen)| self.data.get(key) != seen.as_ref()) {
+            return Ok(false);
+        }
+        for (key, value) in writes {
+            match value {
+                Some(v) => self.data.insert(key.clone(), v.clone()),
+                None => self.data.remove(key),
+            };
+        }
+        self.save()?;
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "storage-sqlite")]
+mod sqlite {
+    use super::{Staged, Storage};
+    use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+    use serde_json::Value;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub struct SqliteStorage {
+        conn: Connection,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: &Path) -> anyhow::Result<Self> {
+            let conn = Connection::open(path)?;
+            // Several servers may share the file: wait for their write locks instead of failing
+            conn.busy_timeout(Duration::from_secs(5))?;
+            conn.execute_batch("PRAGMA journal_mode = WAL; CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY, value TEXT NOT NULL);")?;
+            Ok(Self { conn })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn get(&mut self, key: &str) -> anyhow::Result<Option<Value>> {
+            read(&self.conn, key)
+        }
+
+        fn set(&mut self, key: &str, value: &Value) -> anyhow::Result<()> {
+            self.conn.execute(
+                "INSERT INTO kv (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value.to_string()],
+            )?;
+            Ok(())
+        }
+
+        fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+            self.conn.execute("DELETE FROM kv WHERE key = ?1", params![key])?;
+            Ok(())
+        }
+
+        fn keys(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+            let mut stmt = self.conn.prepare("SELECT key FROM kv WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")?;
+            let keys = stmt.query_map(params![prefix], |row| row.get(0))?.collect::<Result<_, _>>()?;
+            Ok(keys)
+        }
+
+        fn commit(&mut self, reads: &Staged, writes: &Staged) -> anyhow::Result<bool> {
+            // IMMEDIATE takes the write lock up front, so nothing changes between check and write
+            let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            for (key, seen) in reads {
+                if read(&tx, key)? != *seen {
+                    return Ok(false); // Dropping `tx` rolls back
+                }
+            }
+            for (key, value) in writes {
+                match value {
+                    Some(v) => tx.execute(
+                        "INSERT INTO kv (key, value) VALUES (?1, ?2) ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                        params![key, v.to_string()],
+                    )?,
+                    None => tx.execute("DELETE FROM kv WHERE key = ?1", params![key])?,
+                };
+            }
+            tx.commit()?;
+            Ok(true)
+        }
+    }
+
+    fn read(conn: &Connection, key: &str) -> anyhow::Result<Option<Value>> {
+        let text: Option<String> = conn
+            .query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| row.get(0))
+            .optional()?;
+        Ok(text.map(|t| serde_json::from_str(&t)).transpose()?)
+    }
+}
+
+#[cfg(feature = "storage-redis")]
+mod redis_backend {
+    use super::{Staged, Storage};
+    use redis::Commands;
+    use serde_json::Value;
+
+    pub struct RedisStorage {
+        conn: redis::Connection,
+        prefix: String,
+    }
+
+    impl RedisStorage {
+        pub fn open(url: &str, prefix: String) -> anyhow::Result<Self> {
+            let conn = redis::Client::open(url)?.get_connection()?;
+            Ok(Self { conn, prefix })
+        }
+
+        fn key(&self, key: &str) -> String {
+            format!("{}{}", self.prefix, key)
+        }
+    }
+
+    // SCAN patterns are globs: the literal prefix must not match more than itself
+    fn escape_glob(s: &str) -> String {
+        s.chars().flat_map(|c| match c {
+            '*' | '?' | '[' | ']' | '\\' => vec!['\\', c],
+            c => vec![c],
+        }).collect()
+    }
+
+    impl Storage for RedisStorage {
+        fn get(&mut self, key: &str) -> anyhow::Result<Option<Value>> {
+            let text: Option<String> = self.conn.get(self.key(key))?;
+            Ok(text.map(|t| serde_json::from_str(&t)).transpose()?)
+        }
+
+        fn set(&mut self, key: &str, value: &Value) -> anyhow::Result<()> {
+            self.conn.set::<_, _, ()>(self.key(key), value.to_string())?;
+            Ok(())
+        }
+
+        fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+            self.conn.del::<_, ()>(self.key(key))?;
+            Ok(())
+        }
+
+        fn keys(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+            let pattern = format!("{}*", escape_glob(&self.key(prefix)));
+            let mut keys: Vec<String> = self.conn.scan_match::<_, String>(pattern)?
+                .map(|k| k[self.prefix.len()..].to_string())
+                .collect();
+            keys.sort();
+            keys.dedup(); // SCAN may return a key more than once
+            Ok(keys)
+        }
+
+        fn commit(&mut self, reads: &Staged, writes: &Staged) -> anyhow::Result<bool> {
+            // Optimistic: WATCH the read keys, re-check them, then MULTI/EXEC. EXEC aborts
+            // (nil reply) if another client touched a watched key in between.
+            let watched: Vec<String> = reads.keys().map(|k| self.key(k)).collect();
+            if !watched.is_empty() {
+                redis::cmd("WATCH").arg(&watched).query::<()>(&mut self.conn)?;
+            }
+            for (key, seen) in reads {
+                if self.get(key)? != *seen {
+                    redis::cmd("UNWATCH").query::<()>(&mut self.conn)?;
+                    return Ok(false);
+                }
+            }
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (key, value) in writes {
+                match value {
+                    Some(v) => pipe.set(self.key(key), v.to_string()).ignore(),
+                    None => pipe.del(self.key(key)).ignore(),
+                };
+            }
+            let result: Option<()> = pipe.query(&mut self.conn)?;
+            Ok(result.is_some())
+        }
+    }
+}
+
+// --- Lua API ---
+// Built-in plugin: the backend and its saver thread outlive hot reloads like every plugin.
+// Loaded once per process; every room registers its own handle (for_room), so they all
+// read and write the same backend.
+
+pub struct StoragePlugin {
+    queue: SaveQueue,
+}
+
+impl StoragePlugin {
+    /// Configured by the game's cleoselene.toml; files go to `state_dir`.
+    pub fn load(game_dir: &Path, state_dir: &Path) -> anyhow::Result<Self> {
+        let cfg = load_config(game_dir)?;
+        let queue_size = cfg.queue_size;
+        Ok(Self { queue: SaveQueue::start(open(state_dir, cfg)?, queue_size) })
+    }
+
+    /// The plugin for one room: same backend, its own on_save_complete results.
+    pub fn for_room(&self) -> Self {
+        Self { queue: self.queue.for_room() }
+    }
+}
+
+fn storage_error(e: anyhow::Error) -> mlua::Error {
+    mlua::Error::RuntimeError(format!("storage: {}", e))
+}
+
+// Reads and writes staged by one attempt of api.storage_transaction
+#[derive(Default)]
+struct Transaction {
+    reads: Staged,
+    writes: Staged,
+    finished: bool,
+}
+
+impl Transaction {
+    fn check_open(&self) -> mlua::Result<()> {
+        if self.finished {
+            return Err(mlua::Error::RuntimeError("storage: transaction already finished".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// The `tx` table handed to the transaction function: get/set/delete against the staged view.
+fn transaction_table<'lua>(lua: &'lua mlua::Lua, queue: &SaveQueue, tx: &Arc<Mutex<Transaction>>) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+
+    let (queue, tx_get) = (queue.clone(), tx.clone());
+    table.set("get", lua.create_function(move |lua, key: String| {
+        let mut tx = tx_get.lock().unwrap();
+        tx.check_open()?;
+        let value = match tx.writes.get(&key).or_else(|| tx.reads.get(&key)) {
+            Some(staged) => staged.clone(),
+            None => {
+                let value = queue.get(&key).map_err(storage_error)?;
+                tx.reads.insert(key, value.clone());
+                value
+            }
+        };
+        match value {
+            Some(v) => lua.to_value(&v),
+            None => Ok(mlua::Value::Nil),
+        }
+    })?)?;
+
+    let tx_set = tx.clone();
+    table.set("set", lua.create_function(move |lua, (key, value): (String, mlua::Value)| {
+        let mut tx = tx_set.lock().unwrap();
+        tx.check_open()?;
+        let value = match value {
+            mlua::Value::Nil => None,
+            v => Some(lua.from_value::<Value>(v)?),
+        };
+        tx.writes.insert(key, value);
+        Ok(())
+    })?)?;
+
+    let tx_delete = tx.clone();
+    table.set("delete", lua.create_function(move |_, key: String| {
+        let mut tx = tx_delete.lock().unwrap();
+        tx.check_open()?;
+        tx.writes.insert(key, None);
+        Ok(())
+    })?)?;
+
+    Ok(table)
+}
+
+impl Plugin for StoragePlugin {
+    fn name(&self) -> &str {
+        "storage"
+    }
+
+    fn register(&mut self, ctx: &mut PluginContext) -> mlua::Result<()> {
+        let lua = ctx.lua();
+
+        // api.storage_get(key) -> value or nil. Sees writes still waiting in the queue.
+        let queue = self.queue.clone();
+        ctx.api().set("storage_get", lua.create_function(move |lua, key: String| {
+            let value = queue.get(&key).map_err(storage_error)?;
+            match value {
+                Some(v) => lua.to_value(&v),
+                None => Ok(mlua::Value::Nil),
+            }
+        })?)?;
+
+        // api.storage_set(key, value) -> queued: any JSON-serializable value; nil deletes the key.
+        // Returns false (nothing saved) when the save queue is full.
+        let queue = self.queue.clone();
+        ctx.api().set("storage_set", lua.create_function(move |lua, (key, value): (String, mlua::Value)| {
+            let value = match value {
+                mlua::Value::Nil => None,
+                v => Some(lua.from_value::<Value>(v)?),
+            };
+            Ok(queue.write(key, value))
+        })?)?;
+
+        // api.storage_keys([prefix]) -> sorted list of keys
+        let queue = self.queue.clone();
+        ctx.api().set("storage_keys", lua.create_function(move |_, prefix: Option<String>| {
+            queue.keys(prefix.as_deref().unwrap_or("")).map_err(storage_error)
+        })?)?;
+
+        // api.storage_transaction(fn(tx)): tx.get/tx.set/tx.delete are staged and committed
+        // together once fn returns; if a value read changed meanwhile, fn runs again.
+        // Returns fn's results. An error inside fn discards every staged write.
+        let queue = self.queue.clone();
+        ctx.api().set("storage_transaction", lua.create_function(move |lua, f: mlua::Function| {
+            for _ in 0..TRANSACTION_ATTEMPTS {
+                let tx = Arc::new(Mutex::new(Transaction::default()));
+                let result = f.call::<_, mlua::MultiValue>(transaction_table(lua, &queue, &tx)?);
+                let mut tx = tx.lock().unwrap();
+                tx.finished = true;
+                let result = result?;
+                let (reads, writes) = (std::mem::take(&mut tx.reads), std::mem::take(&mut tx.writes));
+                if queue.commit(reads, writes).map_err(storage_error)? {
+                    return Ok(result);
+                }
+            }
+            Err(mlua::Error::RuntimeError(format!("storage: transaction conflicted {} times in a row", TRANSACTION_ATTEMPTS)))
+        })?)?;
+
+        // on_save_complete(ok, key, error) for every write the saver finished since last tick
+        let queue = self.queue.clone();
+        ctx.add_system(SystemPhase::PreUpdate, Box::new(move |lua: &mlua::Lua, _dt: f32| {
+            let finished = queue.take_completed();
+            if finished.is_empty() {
+                return Ok(());
+            }
+            let Ok(callback) = lua.globals().get::<_, mlua::Function>("on_save_complete") else { return Ok(()) };
+            for save in finished {
+                callback.call::<_, ()>((save.ok, save.key, save.error))?;
+            }
+            Ok(())
+        }));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::{Arc, Mutex};
+
+// --- Adaptive Frame Rate ---
+// A client's render queue (frames waiting for its network task) shows whether its
+// connection keeps up. When frames pile up or have to be dropped, the client is sent only
+// every 2nd, 3rd... frame (up to MAX_FRAME_SKIP) and its draw() leaves out cosmetic layers
+// (engine ClientStats::congested). Once the queue has stayed short for RECOVERY_SENDS
+// frames, it steps back up. The measurements reach the script via api.get_client_stats.
+
+pub const MAX_FRAME_SKIP: u32 = 4;
+// Frames sent with an (almost) empty queue before stepping back up
+const RECOVERY_SENDS: u32 = 60;
+
+/// Round-trip time to the client in milliseconds, measured by its network task (heartbeat.rs).
+pub type Rtt = Arc<Mutex<Option<f32>>>;
+
+pub struct Throttle {
+    skip: u32,      // Send every skip-th frame
+    countdown: u32, // Frames to skip before the next send
+    calm: u32,      // Sends in a row with a short queue
+    queue: usize,
+    dropped: u32,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self { skip: 1, countdown: 0, calm: 0, queue: 0, dropped: 0 }
+    }
+
+    /// Whether the client gets the frame being rendered.
+    pub fn due(&mut self) -> bool {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = self.skip - 1;
+        true
+    }
+
+    /// After a frame was queued (or dropped, the queue being full): `queued` frames of at
+    /// most `capacity` are waiting.
+    pub fn record(&mut self, queued: usize, capacity: usize, dropped: bool) {
+        self.queue = queued;
+        if dropped {
+            self.dropped += 1;
+        }
+        if dropped || queued * 2 >= capacity {
+            self.skip = (self.skip + 1).min(MAX_FRAME_SKIP);
+            self.calm = 0;
+        } else if queued <= 1 {
+            self.calm += 1;
+            if self.calm >= RECOVERY_SENDS && self.skip > 1 {
+                self.skip -= 1;
+                self.calm = 0;
+            }
+        } else {
+            self.calm = 0;
+        }
+    }
+
+    /// As reported to the engine, given how many frames per second are rendered.
+    pub fn stats(&self, capacity: usize, send_rate: u32, rtt: &Rtt) -> engine::ClientStats {
+        engine::ClientStats {
+            queue: self.queue as u32,
+            queue_max: capacity as u32,
+            rtt_ms: *rtt.lock().unwrap(),
+            dropped: self.dropped,
+            frame_rate: send_rate as f32 / self.skip as f32,
+            congested: self.skip > 1,
+        }
+    }
+}
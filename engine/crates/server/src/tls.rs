@@ -0,0 +1,38 @@
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::net::TcpListener;
+
+// --- HTTPS ---
+// With --tls-cert and --tls-key (PEM files: the certificate chain and its private key) the
+// server speaks HTTPS on every --bind address, and the browser client connects over WSS:
+// no reverse proxy needed for the secure context browsers ask of gamepads, clipboard and
+// the like. Certificates are read at startup; restart the server to renew them. Native
+// clients (--native-port) stay on plain TCP.
+
+/// The certificate and key, or why they can't be used.
+pub async fn load(cert: &Path, key: &Path) -> anyhow::Result<RustlsConfig> {
+    // rustls wants a default crypto provider picked when it is built with several (ureq's)
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .map_err(|e| anyhow::anyhow!("Can't use {} and {}: {}", cert.display(), key.display(), e))
+}
+
+/// Serves `app` over HTTPS until `stop` resolves, then lets open connections finish.
+pub async fn serve(listener: TcpListener, config: RustlsConfig, app: Router, stop: impl Future<Output = ()> + Send + 'static) -> io::Result<()> {
+    let handle = Handle::new();
+    let stopping = handle.clone();
+    tokio::spawn(async move {
+        stop.await;
+        stopping.graceful_shutdown(None);
+    });
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
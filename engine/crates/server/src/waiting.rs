@@ -0,0 +1,119 @@
+use engine::{Admission, GameState};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::analytics::Analytics;
+use crate::{logging, ClientConnection};
+
+// --- Waiting Room ---
+// New players get in through here. The script's on_before_connect(session_id, auth_info)
+// may turn them away (false) or keep them waiting ("queue"); with --max-players, a full
+// room keeps everyone waiting. Waiting clients are shown a status frame and let in first
+// come, first served: the script is asked again for them every RETRY_INTERVAL while the
+// room has a free slot. Players coming back to their session and spectators skip the line.
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Waiting {
+    conn: ClientConnection,
+    asked: Option<Instant>, // Last time on_before_connect was asked
+    shown: Option<Instant>, // Last status frame
+}
+
+pub struct WaitingRoom {
+    max_players: Option<usize>,
+    queue: VecDeque<Waiting>,
+}
+
+impl WaitingRoom {
+    pub fn new(max_players: Option<usize>) -> Self {
+        Self { max_players, queue: VecDeque::new() }
+    }
+
+    /// Clients waiting to join.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Queues the new `arrivals` and returns everyone let in this tick, in order.
+    /// `players` is how many the room has; `returning` tells sessions the room holds.
+    pub fn admit(
+        &mut self,
+        arrivals: Vec<ClientConnection>,
+        game: &GameState,
+        analytics: &Analytics,
+        mut players: usize,
+        returning: impl Fn(&str) -> bool,
+    ) -> Vec<ClientConnection> {
+        let mut admitted = Vec::new();
+        for conn in arrivals {
+            if conn.spectator || returning(&conn.session_id) {
+                admitted.push(conn);
+            } else {
+                self.queue.push_back(Waiting { conn, asked: None, shown: None });
+            }
+        }
+
+        // Clients who left while waiting; their inputs are dropped so their network
+        // task doesn't block on a full channel
+        self.queue.retain_mut(|w| loop {
+            match w.conn.rx_input.try_recv() {
+                Ok(_) => {}
+                Err(mpsc::error::TryRecvError::Empty) => break !w.conn.tx_render.is_closed(),
+                Err(mpsc::error::TryRecvError::Disconnected) => break false,
+            }
+        });
+
+        let now = Instant::now();
+        let mut i = 0;
+        while i < self.queue.len() {
+            if self.max_players.is_some_and(|max| players >= max) {
+                break; // Full: everyone waits, in order
+            }
+            let waiting = &mut self.queue[i];
+            if waiting.asked.is_some_and(|at| now.duration_since(at) < RETRY_INTERVAL) {
+                i += 1;
+                continue;
+            }
+            waiting.asked = Some(now);
+            let session_id = waiting.conn.session_id.clone();
+            let admission = game.before_connect(&session_id, waiting.conn.auth.as_ref()).unwrap_or_else(|e| {
+                // A script bug doesn't lock players out
+                tracing::error!("Lua on_before_connect Error (Session {}): {}", session_id, e);
+                analytics.error("on_before_connect", Some(&session_id), &e.to_string());
+                Admission::Accept
+            });
+            match admission {
+                Admission::Accept => {
+                    admitted.extend(self.queue.remove(i).map(|w| w.conn));
+                    players += 1;
+                }
+                Admission::Reject => {
+                    tracing::info!("Player refused by on_before_connect: {}", session_id);
+                    logging::audit_client_removed(&session_id, "refused by on_before_connect");
+                    self.queue.remove(i); // Dropped: its connection closes
+                }
+                Admission::Queue => i += 1,
+            }
+        }
+
+        for (ahead, waiting) in self.queue.iter_mut().enumerate() {
+            if waiting.shown.is_some_and(|at| now.duration_since(at) < RETRY_INTERVAL) {
+                continue;
+            }
+            waiting.shown = Some(now);
+            let message = match ahead {
+                0 => "Waiting to join...".to_string(),
+                n => format!("Waiting to join... ({} ahead of you)", n),
+            };
+            let frame = engine::waiting_frame(&message);
+            let _ = waiting.conn.tx_render.try_send(engine::compat::downgrade(&frame, waiting.conn.capabilities).into());
+        }
+        admitted
+    }
+}